@@ -96,7 +96,7 @@ export default function OptimizationProgress({
       routes_count,
       route_iteration,
       iterations_per_route,
-      converged,
+      event,
       converged_route,
       converged_routes,
       optimize_attempts,
@@ -151,7 +151,7 @@ export default function OptimizationProgress({
             <span className="truncate max-w-[150px]">
               Route {current_route_index + 1}/{routes_count}: {current_route}
             </span>
-            {converged && converged_route === current_route ? (
+            {event === 'route_converged' && converged_route === current_route ? (
               <span className="ml-2 text-accent-2 font-semibold">
                 ✓ Converged
               </span>
@@ -165,8 +165,13 @@ function TransitMap({
 
   const fetchPopulationData = async () => {
     try {
-      const data = await fetchFromAPI('/grid');
-      setPopulationData(data);
+      const data = await fetchFromAPI('/zones?valid_only=true&zoom=10');
+      setPopulationData(
+        data.features.map((feature) => ({
+          COORDINATES: feature.properties.centroid,
+          POPULATION: feature.properties.population,
+        }))
+      );
     } catch (error) {
       console.error('Error fetching population data:', error);
       setPopulationData(null);
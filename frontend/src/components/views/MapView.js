@@ -4,7 +4,7 @@ import { useState, useRef, useEffect } from 'react';
 import dynamic from 'next/dynamic';
 import Sidebar from '../maps/Sidebar';
 import OptimizationProgress from '../maps/OptimizationProgress';
-import { fetchFromAPI, createWebSocket } from '@/utils/api';
+import { fetchFromAPI, createWebSocket, waitForJob } from '@/utils/api';
 import OptimizationResultsModal from '../maps/OptimizationResultsModal';
 
 // Dynamically import MapView with no SSR to ensure it runs only on the client
@@ -101,7 +101,8 @@ export default function MapView({ data, initialOptimizedRoutesData, initialOptim
       setOptimizationResults(null); // Reset results
       
       try {
-        const result = await fetchFromAPI('/optimize-routes', {
+        // The optimization runs as a background job, wait for its result
+        const job = await fetchFromAPI('/optimize-routes', {
           method: 'POST',
           headers: {
             'Content-Type': 'application/json'
@@ -110,6 +111,7 @@ export default function MapView({ data, initialOptimizedRoutesData, initialOptim
             routes: routesToOptimize,
           })
         });
+        const result = await waitForJob(job.job_id);
 
         if (result && result.geojson) {
           // Store the optimized route data
@@ -207,15 +209,32 @@ export default function MapView({ data, initialOptimizedRoutesData, initialOptim
           const data = JSON.parse(event.data);
           console.log('Parsed WebSocket message:', data);
           
+          // Generation events only update the score, the rest of the progress stays as is
+          if (data.event === "generation_completed") {
+            setCurrentEvaluation(data.best_score);
+            return;
+          }
+
+          if (data.event === "search_space") {
+            return;
+          }
+
+          if (data.event === "local_search_completed") {
+            setCurrentEvaluation(data.final_score);
+            return;
+          }
+
+          if (data.event === "walk_constraint_violated") {
+            console.warn(
+              `Route ${data.route_id} rejected: ${data.check.violations.length} zones exceed the ${data.check.max_increase_m}m walk increase`
+            );
+            return;
+          }
+
           // Store the complete websocket data for detailed UI rendering
           setWebsocketData(data);
           
-          // Handle connection confirmation message - check with more detailed logging
-          if (data.status) {
-            console.log(`Message has status field: ${data.status}`);
-          }
-          
-          if (data.status === "connected") {
+          if (data.event === "started") {
             console.log(`WebSocket connection confirmed: ${data.message}`);
             setOptimizationProgress(0.1);
             return; // return here to avoid processing this as an optimization message
@@ -233,7 +252,7 @@ export default function MapView({ data, initialOptimizedRoutesData, initialOptim
           }
           
           // Handle converged routes
-          if (data.converged && data.converged_route) {
+          if (data.event === "route_converged") {
             console.info(`Route ${data.converged_route} has converged to optimal solution`);
             setConvergedRoutes(prev => {
               const newSet = new Set(prev);
@@ -56,6 +56,22 @@ export const fetchFromAPI = async (endpoint, options = {}, city = null) => {
   }
 };
 
+/**
+ * Poll a background job until it finishes, returning its result
+ */
+export const waitForJob = async (jobId, city = null, intervalMs = 1000) => {
+  for (;;) {
+    const job = await fetchFromAPI(`/jobs/${jobId}`, {}, city);
+    if (job.status === 'succeeded') {
+      return job.result;
+    }
+    if (job.status === 'failed') {
+      throw new Error(job.error || `Job ${jobId} failed`);
+    }
+    await new Promise(resolve => setTimeout(resolve, intervalMs));
+  }
+};
+
 /**
  * Create WebSocket connection with city parameter
  */
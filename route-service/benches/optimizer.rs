@@ -0,0 +1,50 @@
+//! Benchmarks of the optimizer on a generated demo city
+//!
+//! Run with `cargo bench`, or `cargo bench -- evaluate_route` for a single benchmark. The
+//! city is written to a temporary directory and loaded without the city cache, so the numbers
+//! do not depend on what was cached by earlier runs.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use route_service::layers::demo_city::{load_demo_city, DemoCityConfig};
+use route_service::opt::aco2::{self, bench::SearchSpace, ACO};
+
+fn optimizer(c: &mut Criterion) {
+    let city = load_demo_city(
+        &format!("optimizer_bench_{}", std::process::id()),
+        &DemoCityConfig::default(),
+    );
+    let route = &city.transit.routes[0];
+    let space = SearchSpace::new(route, &city);
+    let stops = &route.outbound_stops;
+
+    c.bench_function("evaluate_route", |b| {
+        b.iter(|| space.evaluate_route(black_box(route), &city))
+    });
+    c.bench_function("compute_heuristic", |b| {
+        b.iter(|| {
+            // every candidate stop after the first two stops of the route, as an ant scores them
+            for to in &space.stops {
+                black_box(space.compute_heuristic(&stops[0], &stops[1], to, &city));
+            }
+        })
+    });
+    // paths found before are answered from the path cache, as they are during a search
+    c.bench_function("road_distance", |b| {
+        b.iter(|| {
+            for to in &stops[1..] {
+                black_box(stops[0].road_distance(to, &city.road));
+            }
+        })
+    });
+
+    let mut group = c.benchmark_group("run_aco");
+    group.sample_size(10);
+    group.bench_function("route", |b| {
+        b.iter(|| aco2::run_aco(ACO::init(), black_box(route), &city, &city.transit))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, optimizer);
+criterion_main!(benches);
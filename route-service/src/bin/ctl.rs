@@ -2,11 +2,21 @@ use std::time::Instant;
 
 use clap::Parser;
 
+use route_service::config::Config;
 use route_service::gtfs::geojson;
 use route_service::gtfs::gtfs::Gtfs;
 use route_service::layers::city::City;
+use route_service::layers::memory::MemoryMode;
+use route_service::layers::raster::Raster;
 use route_service::layers::{road_network::RoadNetwork, transit_network::TransitNetwork};
-use route_service::opt::aco2::{run_aco_batch, run_aco_network, ACO};
+use route_service::opt::aco2::{
+    run_aco, run_aco_batch, run_aco_network, BatchOptions, CoverageMode, ACO,
+};
+use route_service::opt::budget::{OperatingBudget, OperatingCost};
+use route_service::opt::checkpoint::Checkpointing;
+use route_service::opt::eval::{zone_metric, ZoneMetric};
+use route_service::opt::network_diff::{NetworkDiff, RunRecord};
+use route_service::opt::resources::{ResourceMeter, ResourceUsage};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -15,19 +25,21 @@ struct Args {
     #[arg(long)]
     city: String,
 
-    /// Path to GTFS data base directory
-    #[clap(
-        long,
-        default_value = "/Users/jeevanopel/workspace/transit-works/scripts/city_data"
-    )]
-    gtfs_base_path: String,
+    /// Config file, transit-works.toml in the working directory if it exists and none is given
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Path to GTFS data base directory, overrides the config file
+    #[arg(long)]
+    gtfs_base_path: Option<String>,
 
-    /// Path to database base directory
-    #[clap(
-        long,
-        default_value = "/Users/jeevanopel/workspace/transit-works/scripts/city_db"
-    )]
-    db_base_path: String,
+    /// Path to database base directory, overrides the config file
+    #[arg(long)]
+    db_base_path: Option<String>,
+
+    /// Directory the caches and logs of the city are written to, overrides the config file
+    #[arg(long)]
+    cache_dir: Option<String>,
 
     /// Output directory for results
     #[arg(long, default_value = "./ctl_output")]
@@ -56,6 +68,68 @@ struct Args {
     /// Fix evaluations in cached transit networks
     #[arg(long)]
     fix_evals: bool,
+
+    /// Coverage mode while optimizing several routes: live, frozen or refresh:K
+    #[arg(long, default_value = "live", value_parser = parse_coverage_mode)]
+    coverage_mode: CoverageMode,
+
+    /// Export a zone metric as a GeoTIFF raster: demand, coverage or transfers
+    #[arg(long)]
+    export_raster: Option<ZoneMetric>,
+
+    /// Refine the routes found by ACO with a greedy local search
+    #[arg(long)]
+    local_search: bool,
+
+    /// Compare the routes given by --routes optimized with and without inbound-only candidate stops
+    #[arg(long)]
+    compare_inbound_candidates: bool,
+
+    /// Pixel size of exported rasters in meters
+    #[arg(long, default_value_t = 100.0)]
+    raster_resolution: f64,
+
+    /// Keep the road and grid networks in memory (standard) or map the road network from disk
+    /// and read demand from the database as needed (low), overrides the config file
+    #[arg(long, value_enum)]
+    memory_mode: Option<MemoryMode>,
+
+    /// Routes optimized between two checkpoints of --optimize-network, 0 to save none
+    #[arg(long, default_value_t = 10)]
+    checkpoint_every: usize,
+
+    /// Resume --optimize-network from its last checkpoint instead of starting over
+    #[arg(long)]
+    resume: bool,
+
+    /// Most buses --optimize-network may keep in service at once
+    #[arg(long)]
+    max_fleet: Option<usize>,
+
+    /// Most daily revenue hours --optimize-network may run
+    #[arg(long)]
+    max_revenue_hours: Option<f64>,
+
+    /// Most route-km, both directions counted, --optimize-network may run
+    #[arg(long)]
+    max_route_km: Option<f64>,
+}
+
+/// Name of the checkpoint of --optimize-network in the city cache
+const NETWORK_CHECKPOINT: &str = "network";
+
+fn parse_coverage_mode(s: &str) -> Result<CoverageMode, String> {
+    match s {
+        "live" => Ok(CoverageMode::Live),
+        "frozen" => Ok(CoverageMode::Frozen),
+        _ => match s.strip_prefix("refresh:").map(str::parse::<usize>) {
+            Some(Ok(k)) => CoverageMode::refresh_every(k),
+            _ => Err(format!(
+                "invalid coverage mode '{}', expected live, frozen or refresh:K",
+                s
+            )),
+        },
+    }
 }
 
 // Fix evaluations for transit networks in the cache
@@ -72,7 +146,12 @@ fn fix_evals(city: &City) -> Result<(), Box<dyn std::error::Error>> {
         .routes
         .iter()
         .map(|route| {
-            route_service::opt::eval::TransitRouteEvals::for_route(&transit, route, &city.grid)
+            route_service::opt::eval::TransitRouteEvals::for_route(
+                &transit,
+                route,
+                &city.grid,
+                &city.search,
+            )
         })
         .collect();
 
@@ -90,7 +169,7 @@ fn fix_evals(city: &City) -> Result<(), Box<dyn std::error::Error>> {
     );
 
     // Save updated transit network
-    City::save_transit_to_cache(&city.name, &transit)?;
+    city.save_transit_to_cache(&transit)?;
     println!("  Saved updated transit network to cache");
 
     // Check for optimized transit network and fix if it exists
@@ -109,10 +188,14 @@ fn fix_evals(city: &City) -> Result<(), Box<dyn std::error::Error>> {
                         &opt_transit.network,
                         route,
                         &city.grid,
+                        &city.search,
                     )
                 } else {
                     route_service::opt::eval::TransitRouteEvals::for_route(
-                        &transit, route, &city.grid,
+                        &transit,
+                        route,
+                        &city.grid,
+                        &city.search,
                     )
                 }
             })
@@ -145,13 +228,55 @@ fn fix_evals(city: &City) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+// Rasterize a zone metric of the original transit network into a GeoTIFF
+fn export_raster(
+    city: &City,
+    metric: ZoneMetric,
+    resolution: f64,
+    path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "Exporting {} raster at {}m to {}",
+        metric.name(),
+        resolution,
+        path
+    );
+    let start = Instant::now();
+    let values = zone_metric(metric, &city.transit, &city.grid);
+    let raster = Raster::rasterize(&city.grid, &values, resolution)?;
+    println!(
+        "  Rasterized {}x{} pixels in {:?}",
+        raster.width,
+        raster.height,
+        start.elapsed()
+    );
+    std::fs::write(path, raster.to_geotiff())?;
+    println!("  Wrote GeoTIFF");
+    Ok(())
+}
+
 fn main() {
     env_logger::init();
     let args = Args::parse();
+    let mut config = Config::load(args.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Some(path) = &args.gtfs_base_path {
+        config.paths.gtfs_base_path = path.clone();
+    }
+    if let Some(path) = &args.db_base_path {
+        config.paths.db_base_path = path.clone();
+    }
+    if let Some(path) = &args.cache_dir {
+        config.paths.cache_dir = path.clone();
+    }
+    City::set_cache_dir(&config.paths.cache_dir);
+    let memory_mode = args.memory_mode.unwrap_or(config.server.memory_mode);
 
     // Construct the paths for GTFS and DB
-    let gtfs_path = format!("{}/{}/gtfs", args.gtfs_base_path, args.city);
-    let db_path = format!("{}/{}.db", args.db_base_path, args.city);
+    let gtfs_path = config.paths.gtfs_path(&args.city);
+    let db_path = config.paths.db_path(&args.city);
 
     // Create output directory if it doesn't exist
     std::fs::create_dir_all(&args.output_dir).unwrap_or_else(|e| {
@@ -162,11 +287,15 @@ fn main() {
         "Loading city: {} from {} and {}",
         args.city, gtfs_path, db_path
     );
-    let city = City::load_with_cached_transit(&args.city, &gtfs_path, &db_path, true, false)
-        .unwrap_or_else(|e| {
-            eprintln!("Failed to load city: {}", e);
-            std::process::exit(1);
-        });
+    let mut city =
+        City::load_with_cached_transit(&args.city, &gtfs_path, &db_path, true, false, memory_mode)
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to load city: {}", e);
+                std::process::exit(1);
+            });
+    let vehicles = config.route_vehicles(&args.city);
+    city.transit
+        .assign_vehicles(&vehicles, &city.grid, &city.search);
 
     // Handle fixing evaluations if requested
     if args.fix_evals {
@@ -177,18 +306,47 @@ fn main() {
         return;
     }
 
+    // Define file name suffix
+    let suffix = args.suffix.unwrap_or_else(|| "".to_string());
+
+    // Handle raster export if requested
+    if let Some(metric) = args.export_raster {
+        let path = format!("{}/{}{}.tif", args.output_dir, metric.name(), suffix);
+        if let Err(e) = export_raster(&city, metric, args.raster_resolution, &path) {
+            eprintln!("Failed to export raster: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Initialize ACO parameters
     println!("Initializing ACO");
-    let aco = ACO::init();
+    let mut aco = ACO::init();
+    aco.update_from_partial(config.aco.clone());
+    aco.local_search = aco.local_search || args.local_search;
     aco.print_stats();
 
-    // Define file name suffix
-    let suffix = args.suffix.unwrap_or_else(|| "".to_string());
+    if args.compare_inbound_candidates {
+        let route_ids: Vec<String> = args
+            .routes
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        compare_inbound_candidates(&mut city, &aco, &route_ids);
+        return;
+    }
 
     // Output GTFS as geojson if requested
     if args.output_geojson {
+        let full_gtfs = city.full_gtfs().unwrap_or_else(|e| {
+            eprintln!("Failed to load full GTFS: {}", e);
+            std::process::exit(1);
+        });
         output_geojson(
-            &city.gtfs,
+            full_gtfs,
             &format!("{}/gtfs{}.geojson", args.output_dir, suffix),
         );
         output_routes_geojson(
@@ -221,9 +379,35 @@ fn main() {
                 let start = Instant::now();
                 // Create a mutable copy of the transit network
                 let mut new_transit = city.transit.clone();
-                let optimized_route_ids =
-                    run_aco_batch(aco.clone(), &target_routes, &city, &mut new_transit);
+                let result = run_aco_batch(
+                    aco.clone(),
+                    &target_routes,
+                    &city,
+                    &mut new_transit,
+                    BatchOptions {
+                        coverage_mode: args.coverage_mode,
+                        ..BatchOptions::default()
+                    },
+                );
                 println!("  ACO finished in {:?}", start.elapsed());
+                println!(
+                    "  Coverage mode: {:?} ({} snapshots)",
+                    result.coverage_mode, result.coverage_snapshots
+                );
+                if let Some(share) = result.local_search_share {
+                    println!("  Local search share of improvement: {:.1}%", share * 100.0);
+                }
+                let optimized_route_ids = result.optimized_route_ids;
+                let before: Vec<_> = target_routes.into_iter().cloned().collect();
+                record_run(
+                    &city,
+                    "optimize-routes",
+                    route_ids.len(),
+                    &optimized_route_ids,
+                    start.elapsed(),
+                    NetworkDiff::new(&before, &new_transit),
+                    result.resources,
+                );
 
                 // Create the OptimizedTransitNetwork structure
                 let optimized_network = route_service::opt::aco2::OptimizedTransitNetwork {
@@ -259,13 +443,74 @@ fn main() {
     } else if args.optimize_network {
         println!("Optimizing entire network");
 
+        let budget = OperatingBudget {
+            max_fleet: args.max_fleet,
+            max_revenue_hours: args.max_revenue_hours,
+            max_route_km: args.max_route_km,
+        };
+        if let Err(e) = budget.validate() {
+            eprintln!("Invalid operating budget: {}", e);
+            std::process::exit(1);
+        }
+
+        let resume = if args.resume {
+            match City::load_checkpoint(&args.city, NETWORK_CHECKPOINT) {
+                Ok(checkpoint) => Some(checkpoint),
+                Err(e) => {
+                    eprintln!("Failed to load checkpoint: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            None
+        };
+        let checkpointing = Checkpointing {
+            every: args.checkpoint_every,
+            resume,
+            save: &mut |checkpoint| {
+                println!(
+                    "  Saving checkpoint after {}/{} routes",
+                    checkpoint.progress,
+                    checkpoint.route_ids.len()
+                );
+                if let Err(e) = City::save_checkpoint(&args.city, NETWORK_CHECKPOINT, checkpoint) {
+                    eprintln!("Failed to save checkpoint: {}", e);
+                }
+            },
+        };
+
         let start = Instant::now();
-        let optimized_network = run_aco_network(aco.clone(), &city, &city.transit);
+        let meter = ResourceMeter::start();
+        let optimized_network = run_aco_network(
+            aco.clone(),
+            &city,
+            &city.transit,
+            args.coverage_mode,
+            Some(&budget),
+            Some(checkpointing),
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to resume network optimization: {}", e);
+            std::process::exit(1);
+        });
+        let resources = meter.finish();
+        if let Err(e) = City::remove_checkpoint(&args.city, NETWORK_CHECKPOINT) {
+            eprintln!("Failed to remove checkpoint: {}", e);
+        }
         // for i in 2..6 {
         //     println!("Iteration {}/{}", i, 5);
         //     run_aco_network(aco.clone(), &city, &optimized_network.network);
         // }
         println!("  Network optimization finished in {:?}", start.elapsed());
+        record_run(
+            &city,
+            "optimize-network",
+            city.transit.routes.len(),
+            &optimized_network.optimized_routes,
+            start.elapsed(),
+            NetworkDiff::new(&city.transit.routes, &optimized_network.network),
+            resources,
+        );
 
         // Save to cache if requested
         if args.save_cache {
@@ -291,6 +536,94 @@ fn main() {
             "Optimized {} routes in the network",
             optimized_network.optimized_routes.len()
         );
+        if !budget.is_unlimited() {
+            let cost = OperatingCost::for_network(&optimized_network.network, &city.road);
+            println!("Operating budget: {:?}", budget.utilization(&cost));
+        }
+    }
+}
+
+// Optimize routes with and without the stops only served inbound as candidates and print the
+// scores, to measure the effect on routes whose directions stop at different places
+fn compare_inbound_candidates(city: &mut City, aco: &ACO, route_ids: &[String]) {
+    let routes: Vec<_> = city
+        .transit
+        .routes
+        .iter()
+        .filter(|r| route_ids.contains(&r.route_id))
+        .cloned()
+        .collect();
+    if routes.is_empty() {
+        println!("No matching routes found, pass the routes to compare with --routes");
+        return;
+    }
+
+    let include_inbound_stops = city.search.include_inbound_stops;
+    for route in &routes {
+        let inbound_only = route
+            .inbound_stops
+            .iter()
+            .filter(|s| !route.outbound_stops.iter().any(|o| o.stop_id == s.stop_id))
+            .count();
+        println!(
+            "Route {}: {} outbound stops, {} inbound stops not served outbound",
+            route.route_id,
+            route.outbound_stops.len(),
+            inbound_only
+        );
+        for include in [false, true] {
+            city.search.include_inbound_stops = include;
+            let start = Instant::now();
+            let result = run_aco(aco.clone(), route, city, &city.transit);
+            let candidates = if include {
+                "both directions"
+            } else {
+                "outbound only"
+            };
+            match result {
+                Some((optimized, score)) => println!(
+                    "  {}: score {:.4} with {} stops in {:?}",
+                    candidates,
+                    score,
+                    optimized.outbound_stops.len(),
+                    start.elapsed()
+                ),
+                None => println!("  {}: no improvement in {:?}", candidates, start.elapsed()),
+            }
+        }
+    }
+    city.search.include_inbound_stops = include_inbound_stops;
+}
+
+// Print the changes of an optimization run and add it to the city's run history
+fn record_run(
+    city: &City,
+    job: &str,
+    routes_requested: usize,
+    optimized_route_ids: &[String],
+    duration: std::time::Duration,
+    diff: NetworkDiff,
+    resources: ResourceUsage,
+) {
+    println!(
+        "  Network changes: {}",
+        serde_json::to_string_pretty(&diff).unwrap()
+    );
+    println!(
+        "  Resources used: {}",
+        serde_json::to_string_pretty(&resources).unwrap()
+    );
+    let record = RunRecord {
+        job: job.to_string(),
+        finished_at: chrono::Local::now().to_rfc3339(),
+        duration_ms: duration.as_millis(),
+        routes_requested,
+        optimized_route_ids: optimized_route_ids.to_vec(),
+        diff,
+        resources: Some(resources),
+    };
+    if let Err(e) = City::append_run_history(&city.name, &record) {
+        eprintln!("Failed to record optimization run: {}", e);
     }
 }
 
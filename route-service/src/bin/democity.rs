@@ -0,0 +1,102 @@
+use clap::Parser;
+
+use route_service::layers::demo_city::{DemoCity, DemoCityConfig};
+
+/// Generate a synthetic city to run the service without real data
+///
+/// Writes `{db-base-path}/{city}.db` and the GTFS feed to `{gtfs-base-path}/{city}/gtfs`, the
+/// layout the service and ctl load cities from.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Name of the city to write
+    #[arg(long, default_value = "democity")]
+    city: String,
+
+    /// Path to GTFS data base directory
+    #[arg(long, default_value = "city_data")]
+    gtfs_base_path: String,
+
+    /// Path to database base directory
+    #[arg(long, default_value = "city_db")]
+    db_base_path: String,
+
+    /// Zones from west to east
+    #[arg(long, default_value_t = 12)]
+    cols: usize,
+
+    /// Zones from south to north
+    #[arg(long, default_value_t = 12)]
+    rows: usize,
+
+    /// Side of a zone in meters
+    #[arg(long, default_value_t = 500.0)]
+    zone_size: f64,
+
+    /// Distance between two parallel streets in meters
+    #[arg(long, default_value_t = 250.0)]
+    block_size: f64,
+
+    /// Approximate distance between two stops of a route in meters
+    #[arg(long, default_value_t = 500.0)]
+    stop_spacing: f64,
+
+    /// Number of bus routes
+    #[arg(long, default_value_t = 6)]
+    routes: usize,
+
+    /// Latitude of the city center
+    #[arg(long, default_value_t = 43.6532)]
+    lat: f64,
+
+    /// Longitude of the city center
+    #[arg(long, default_value_t = -79.3832, allow_hyphen_values = true)]
+    lon: f64,
+
+    /// Time zone of the transit agency
+    #[arg(long, default_value = "America/Toronto")]
+    timezone: String,
+
+    /// Seed of the random variation between zones
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+
+    let config = DemoCityConfig {
+        cols: args.cols,
+        rows: args.rows,
+        zone_size_m: args.zone_size,
+        block_size_m: args.block_size,
+        stop_spacing_m: args.stop_spacing,
+        routes: args.routes,
+        center_lat: args.lat,
+        center_lon: args.lon,
+        timezone: args.timezone,
+        seed: args.seed,
+    };
+    let demo = match DemoCity::generate(&config) {
+        Ok(demo) => demo,
+        Err(e) => {
+            eprintln!("Failed to generate city: {}", e);
+            std::process::exit(1);
+        }
+    };
+    demo.print_stats();
+
+    let db_path = format!("{}/{}.db", args.db_base_path, args.city);
+    let gtfs_path = format!("{}/{}/gtfs", args.gtfs_base_path, args.city);
+    if let Err(e) = demo.write_db(&db_path) {
+        eprintln!("Failed to write database {}: {}", db_path, e);
+        std::process::exit(1);
+    }
+    if let Err(e) = demo.write_gtfs(&gtfs_path) {
+        eprintln!("Failed to write GTFS {}: {}", gtfs_path, e);
+        std::process::exit(1);
+    }
+    println!("Database written to {}", db_path);
+    println!("GTFS written to {}", gtfs_path);
+}
@@ -0,0 +1,353 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use thiserror::Error;
+
+use crate::layers::city::DEFAULT_CACHE_DIR;
+use crate::layers::memory::MemoryMode;
+use crate::layers::vehicle::{RouteVehicles, VehicleProfile};
+use crate::opt::aco2::PartialACO;
+
+/// Config file read when none is given on the command line, if it exists in the working
+/// directory
+pub const DEFAULT_CONFIG_PATH: &str = "transit-works.toml";
+
+/// Error reading a config file
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read config file {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("Failed to parse config file {0}: {1}")]
+    Parse(String, toml::de::Error),
+    #[error("Invalid config file {0}: {1}")]
+    Invalid(String, String),
+}
+
+/// Deployment settings of the service and of ctl, read from a TOML file
+///
+/// Every section and field of the file is optional, missing ones keep the values of
+/// `Config::default`. Command line flags override the values of the file.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub paths: PathsConfig,
+    pub server: ServerConfig,
+    pub proxy: ProxyConfig,
+    /// Cities that can be served, by name
+    pub cities: BTreeMap<String, CityEntry>,
+    /// ACO parameters the city servers and ctl start with, over the defaults of `ACO::init`
+    pub aco: PartialACO,
+    /// Vehicle profiles routes can be assigned, by name, besides those of
+    /// `VehicleProfile::builtin` which they replace
+    pub vehicles: BTreeMap<String, VehicleProfile>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let cities = [
+            ("toronto", 8081),
+            ("sanfrancisco", 8082),
+            ("vancouver", 8083),
+            ("austin", 8084),
+            ("democity", 8085),
+        ];
+        Config {
+            paths: PathsConfig::default(),
+            server: ServerConfig::default(),
+            proxy: ProxyConfig::default(),
+            cities: cities
+                .into_iter()
+                .map(|(name, port)| {
+                    let city = CityEntry {
+                        port,
+                        webhook_url: None,
+                        vehicles: BTreeMap::new(),
+                    };
+                    (name.to_string(), city)
+                })
+                .collect(),
+            aco: PartialACO::default(),
+            vehicles: BTreeMap::new(),
+        }
+    }
+}
+
+/// Where the data of the cities is found
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// Directory with the GTFS feed of each city in `<city>/gtfs`
+    pub gtfs_base_path: String,
+    /// Directory with the database of each city in `<city>.db`
+    pub db_base_path: String,
+    /// Directory the caches and logs of the cities are written to
+    pub cache_dir: String,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        PathsConfig {
+            gtfs_base_path: "../scripts/city_data".to_string(),
+            db_base_path: "../scripts/city_db".to_string(),
+            cache_dir: DEFAULT_CACHE_DIR.to_string(),
+        }
+    }
+}
+
+impl PathsConfig {
+    pub fn gtfs_path(&self, city: &str) -> String {
+        format!("{}/{}/gtfs", self.gtfs_base_path, city)
+    }
+
+    pub fn db_path(&self, city: &str) -> String {
+        format!("{}/{}.db", self.db_base_path, city)
+    }
+}
+
+/// How the city servers are run
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the city servers and the proxy listen on
+    pub host: String,
+    /// Cities whose servers are started at launch, each must be in `cities`
+    pub start: Vec<String>,
+    pub memory_mode: MemoryMode,
+    /// Wall time limit in seconds of a single optimization request
+    pub max_optimize_secs: Option<u64>,
+    /// Most routes a single optimization request may optimize
+    pub max_optimize_routes: Option<usize>,
+    /// Most ACO generations run per route, regardless of the ACO parameters
+    pub max_generations: Option<usize>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "127.0.0.1".to_string(),
+            start: vec!["toronto".to_string(), "sanfrancisco".to_string()],
+            memory_mode: MemoryMode::Standard,
+            max_optimize_secs: None,
+            max_optimize_routes: None,
+            max_generations: None,
+        }
+    }
+}
+
+/// How the proxy forwards requests to the city servers
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    pub port: u16,
+    /// City of the requests that name none, `None` to reject them
+    pub default_city: Option<String>,
+    /// Seconds the proxy waits for a city server to answer
+    pub timeout_secs: u64,
+    /// Token `POST /admin/cities` must be sent with as `Authorization: Bearer <token>`,
+    /// `None` to refuse registering cities at runtime
+    pub admin_token: Option<String>,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            port: 8080,
+            default_city: Some("toronto".to_string()),
+            timeout_secs: 60,
+            admin_token: None,
+        }
+    }
+}
+
+/// A city that can be served
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CityEntry {
+    /// Port of the city's server
+    pub port: u16,
+    /// URL notified when batch jobs of the city finish
+    pub webhook_url: Option<String>,
+    /// Name of the vehicle profile of each route that has one, by route id
+    #[serde(default)]
+    pub vehicles: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Read the config file at `path`, or `DEFAULT_CONFIG_PATH` if no path is given and
+    /// the file exists, or use the defaults otherwise
+    pub fn load(path: Option<&str>) -> Result<Config, ConfigError> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => DEFAULT_CONFIG_PATH,
+            None => return Ok(Config::default()),
+        };
+        let text =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_string(), e))?;
+        Config::parse(&text, path)
+    }
+
+    /// Parse and check a config file
+    ///
+    /// # Parameters
+    /// - `text`: Contents of the file
+    /// - `path`: Path of the file, for error messages
+    pub fn parse(text: &str, path: &str) -> Result<Config, ConfigError> {
+        let config: Config =
+            toml::from_str(text).map_err(|e| ConfigError::Parse(path.to_string(), e))?;
+        config
+            .validate()
+            .map_err(|e| ConfigError::Invalid(path.to_string(), e))?;
+        Ok(config)
+    }
+
+    /// Check that ports are not shared and that started cities are configured
+    pub fn validate(&self) -> Result<(), String> {
+        let mut ports: HashMap<u16, &str> = HashMap::new();
+        ports.insert(self.proxy.port, "the proxy");
+        for (name, city) in &self.cities {
+            if let Some(other) = ports.insert(city.port, name) {
+                return Err(format!(
+                    "Port {} of {} is already used by {}",
+                    city.port, name, other
+                ));
+            }
+        }
+        if let Some(city) = self
+            .server
+            .start
+            .iter()
+            .find(|c| !self.cities.contains_key(*c))
+        {
+            return Err(format!("City {} is started but not configured", city));
+        }
+        for (name, profile) in &self.vehicles {
+            profile
+                .validate()
+                .map_err(|e| format!("Vehicle profile {}: {}", name, e))?;
+        }
+        let profiles = self.vehicle_profiles();
+        for (name, city) in &self.cities {
+            if let Some((route_id, profile)) = city
+                .vehicles
+                .iter()
+                .find(|(_, profile)| !profiles.contains_key(*profile))
+            {
+                return Err(format!(
+                    "Route {} of {} has unknown vehicle profile {}",
+                    route_id, name, profile
+                ));
+            }
+        }
+        self.aco.validate()
+    }
+
+    /// Vehicle profiles by name, the built-in ones with those of the file
+    pub fn vehicle_profiles(&self) -> BTreeMap<String, VehicleProfile> {
+        let mut profiles = VehicleProfile::builtin();
+        profiles.extend(self.vehicles.clone());
+        profiles
+    }
+
+    /// Vehicle profile of each route of a city that is assigned one
+    pub fn route_vehicles(&self, city: &str) -> RouteVehicles {
+        let Some(entry) = self.cities.get(city) else {
+            return RouteVehicles::new();
+        };
+        let profiles = self.vehicle_profiles();
+        entry
+            .vehicles
+            .iter()
+            .filter_map(|(route_id, profile)| {
+                Some((route_id.clone(), profiles.get(profile)?.clone()))
+            })
+            .collect()
+    }
+
+    /// Port of each configured city
+    pub fn city_ports(&self) -> HashMap<String, u16> {
+        self.cities
+            .iter()
+            .map(|(name, city)| (name.clone(), city.port))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_override_the_defaults_they_set() {
+        let config = Config::parse(
+            r#"
+            [paths]
+            gtfs_base_path = "/data/gtfs"
+            cache_dir = "/var/cache/transit-works"
+
+            [server]
+            start = ["montreal"]
+            memory_mode = "low"
+
+            [cities.montreal]
+            port = 9001
+            webhook_url = "https://example.com/hook"
+            vehicles = { "24" = "articulated", "55" = "midibus" }
+
+            [vehicles.midibus]
+            capacity = 35
+            min_turn_angle = 10.0
+            max_grade = 0.12
+            stop_spacing = 300.0
+
+            [aco]
+            alpha = 1.5
+            "#,
+            "test.toml",
+        )
+        .unwrap();
+        assert_eq!(
+            config.paths.gtfs_path("montreal"),
+            "/data/gtfs/montreal/gtfs"
+        );
+        assert_eq!(
+            config.paths.db_base_path,
+            PathsConfig::default().db_base_path
+        );
+        assert_eq!(config.paths.cache_dir, "/var/cache/transit-works");
+        assert_eq!(config.server.memory_mode, MemoryMode::Low);
+        assert_eq!(config.proxy.port, 8080);
+        assert_eq!(
+            config.city_ports(),
+            HashMap::from([("montreal".to_string(), 9001)])
+        );
+        assert_eq!(config.aco.alpha, Some(1.5));
+        let vehicles = config.route_vehicles("montreal");
+        assert_eq!(vehicles["24"], VehicleProfile::builtin()["articulated"]);
+        assert_eq!(vehicles["55"].capacity, 35);
+        assert!(config.route_vehicles("toronto").is_empty());
+
+        let shared = "[cities.a]\nport = 8080\n[server]\nstart = []";
+        assert!(matches!(
+            Config::parse(shared, "test.toml"),
+            Err(ConfigError::Invalid(..))
+        ));
+        let unknown = "[server]\nstart = [\"nowhere\"]";
+        assert!(matches!(
+            Config::parse(unknown, "test.toml"),
+            Err(ConfigError::Invalid(..))
+        ));
+        let unknown_vehicle =
+            "[server]\nstart = []\n[cities.a]\nport = 9001\nvehicles = { \"1\" = \"tram\" }";
+        assert!(matches!(
+            Config::parse(unknown_vehicle, "test.toml"),
+            Err(ConfigError::Invalid(..))
+        ));
+        assert!(matches!(
+            Config::parse("[server]\nport = 1", "test.toml"),
+            Err(ConfigError::Parse(..))
+        ));
+        Config::default().validate().unwrap();
+        let example = Config::parse(include_str!("../config.example.toml"), "example").unwrap();
+        assert_eq!(example.city_ports(), Config::default().city_ports());
+    }
+}
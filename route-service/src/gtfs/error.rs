@@ -39,9 +39,15 @@ pub enum Error {
     /// Error when trying to unzip the GTFS archive
     #[error(transparent)]
     Zip(#[from] zip::result::ZipError),
+    /// The GTFS archive extracts to more bytes than allowed
+    #[error("{0}")]
+    ArchiveTooLarge(String),
     /// Error when querying sqlite
     #[error(transparent)]
     SqliteError(#[from] rusqlite::Error),
+    /// A GTFS-realtime feed could not be decoded
+    #[error(transparent)]
+    Protobuf(#[from] prost::DecodeError),
 }
 
 impl Serialize for Error {
@@ -0,0 +1,327 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use zip::result::ZipError;
+
+use crate::gtfs::{error::Error, gtfs::Gtfs, raw_gtfs::GtfsDataSet, structs::FeedInfo};
+use crate::layers::import_report::ImportReport;
+
+/// Largest GTFS archive accepted by an upload, in bytes
+pub const MAX_UPLOAD_BYTES: usize = 512 * 1024 * 1024;
+/// Largest file extracted from a GTFS archive, in bytes
+pub const MAX_ENTRY_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+/// Largest total size of the files extracted from a GTFS archive, in bytes
+pub const MAX_EXTRACTED_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Version name of the feed at the city's configured GTFS path
+pub const ORIGINAL_VERSION: &str = "original";
+
+/// Name of the directory next to the city's GTFS path that holds uploaded feed versions
+const VERSIONS_DIR: &str = "gtfs_versions";
+/// Metadata file stored in every version directory
+const METADATA_FILE: &str = "feed.json";
+/// File in the versions directory recording which versions were activated
+const ACTIVE_FILE: &str = "active.json";
+
+/// Counts describing a feed after it was validated
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FeedSummary {
+    pub routes: usize,
+    pub stops: usize,
+    pub trips: usize,
+    /// Stops removed by clipping the feed to the city boundary
+    pub dropped_stops: usize,
+    /// Trips removed by clipping the feed to the city boundary
+    pub dropped_trips: usize,
+    /// Routes removed by clipping the feed to the city boundary
+    pub dropped_routes: usize,
+}
+
+impl FeedSummary {
+    pub fn new(gtfs: &Gtfs, report: &ImportReport) -> FeedSummary {
+        let clipping = report.clipping.as_ref();
+        FeedSummary {
+            routes: gtfs.routes.len(),
+            stops: gtfs.stops.len(),
+            trips: gtfs.trips.values().map(|t| t.len()).sum(),
+            dropped_stops: clipping.map_or(0, |c| c.dropped_stop_ids.len()),
+            dropped_trips: clipping.map_or(0, |c| c.dropped_trip_ids.len()),
+            dropped_routes: clipping.map_or(0, |c| c.dropped_route_ids.len()),
+        }
+    }
+}
+
+/// Metadata of a stored feed version
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FeedVersion {
+    pub version: String,
+    /// Upload time in RFC 3339 format, `None` for the original feed
+    pub uploaded_at: Option<String>,
+    /// Contents of the feed's `feed_info.txt`
+    pub feed_info: Vec<FeedInfo>,
+    /// Validation summary, `None` for the original feed
+    pub summary: Option<FeedSummary>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct ActivationHistory {
+    /// Activated versions, oldest first. The last entry is the active version.
+    versions: Vec<String>,
+}
+
+/// Stored versions of a city's GTFS feed.
+///
+/// For a city whose feed is read from `<base>/<city>/gtfs` the versions are kept in
+/// `<base>/<city>/gtfs_versions/<version>`, one extracted feed per directory. The feed at
+/// the configured path itself is always available as the `original` version.
+pub struct FeedStore {
+    gtfs_path: String,
+    dir: PathBuf,
+}
+
+impl FeedStore {
+    /// # Parameters
+    /// - `gtfs_path`: The GTFS path the city is configured with
+    pub fn new(gtfs_path: &str) -> FeedStore {
+        FeedStore {
+            gtfs_path: gtfs_path.to_string(),
+            dir: Path::new(gtfs_path)
+                .parent()
+                .unwrap_or_else(|| Path::new("."))
+                .join(VERSIONS_DIR),
+        }
+    }
+
+    /// Directory holding the uploaded versions
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of a stored version's feed, `None` if the version does not exist
+    pub fn path(&self, version: &str) -> Option<PathBuf> {
+        if version == ORIGINAL_VERSION {
+            return Some(PathBuf::from(&self.gtfs_path));
+        }
+        if !is_valid_version(version) {
+            return None;
+        }
+        let path = self.dir.join(version);
+        path.join(METADATA_FILE).exists().then_some(path)
+    }
+
+    /// Store the metadata of an uploaded version, which makes it visible to the store
+    pub fn save(&self, feed: &FeedVersion) -> std::io::Result<()> {
+        let file = File::create(self.dir.join(&feed.version).join(METADATA_FILE))?;
+        serde_json::to_writer_pretty(file, feed)?;
+        Ok(())
+    }
+
+    /// List the original feed followed by the uploaded versions, oldest first
+    pub fn list(&self) -> Vec<FeedVersion> {
+        let mut uploaded: Vec<FeedVersion> = std::fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let file = File::open(entry.ok()?.path().join(METADATA_FILE)).ok()?;
+                serde_json::from_reader(file).ok()
+            })
+            .collect();
+        uploaded.sort_by(|a, b| {
+            a.uploaded_at
+                .cmp(&b.uploaded_at)
+                .then_with(|| a.version.cmp(&b.version))
+        });
+
+        let original = FeedVersion {
+            version: ORIGINAL_VERSION.to_string(),
+            uploaded_at: None,
+            feed_info: GtfsDataSet::optional_read_obj_from_path(
+                Path::new(&self.gtfs_path),
+                "feed_info.txt",
+            )
+            .and_then(Result::ok)
+            .unwrap_or_default(),
+            summary: None,
+        };
+        std::iter::once(original).chain(uploaded).collect()
+    }
+
+    /// The active version, `original` unless another version was activated
+    pub fn active(&self) -> String {
+        self.history()
+            .versions
+            .pop()
+            .unwrap_or_else(|| ORIGINAL_VERSION.to_string())
+    }
+
+    /// The version that was active before the current one, if any
+    pub fn previous(&self) -> Option<String> {
+        let mut history = self.history();
+        history.versions.pop()?;
+        Some(
+            history
+                .versions
+                .pop()
+                .unwrap_or_else(|| ORIGINAL_VERSION.to_string()),
+        )
+    }
+
+    /// Record a version as active
+    pub fn set_active(&self, version: &str) -> std::io::Result<()> {
+        let mut history = self.history();
+        history.versions.push(version.to_string());
+        self.save_history(&history)
+    }
+
+    /// Drop the active version from the history, making the previous version active
+    pub fn pop_active(&self) -> std::io::Result<()> {
+        let mut history = self.history();
+        history.versions.pop();
+        self.save_history(&history)
+    }
+
+    fn history(&self) -> ActivationHistory {
+        File::open(self.dir.join(ACTIVE_FILE))
+            .ok()
+            .and_then(|file| serde_json::from_reader(file).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_history(&self, history: &ActivationHistory) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let file = File::create(self.dir.join(ACTIVE_FILE))?;
+        serde_json::to_writer(file, history)?;
+        Ok(())
+    }
+}
+
+/// Whether a version tag can be used as a directory name for a stored feed.
+///
+/// Tags may only contain ASCII letters, digits, `-`, `_` and `.`, may not start with `.`
+/// and may not shadow the original feed
+pub fn is_valid_version(version: &str) -> bool {
+    !version.is_empty()
+        && version != ORIGINAL_VERSION
+        && version.len() <= 64
+        && !version.starts_with('.')
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Extract a GTFS zip archive into a directory.
+///
+/// Feeds zipped with an enclosing folder (e.g. `feed/stops.txt`) are flattened so that
+/// the text files end up directly in `dest`, where the directory reader expects them.
+/// Extraction stops with `Error::ArchiveTooLarge` once a file exceeds `MAX_ENTRY_BYTES` or
+/// the files together exceed `MAX_EXTRACTED_BYTES`, so a small archive cannot fill the disk.
+///
+/// # Parameters
+/// - `zip_path`: Path to the archive
+/// - `dest`: Directory to extract to, created if missing
+pub fn extract_zip(zip_path: &Path, dest: &Path) -> Result<(), Error> {
+    extract_zip_within(zip_path, dest, MAX_ENTRY_BYTES, MAX_EXTRACTED_BYTES)
+}
+
+/// `extract_zip` with the largest size of a file and of all files extracted, in bytes
+fn extract_zip_within(
+    zip_path: &Path,
+    dest: &Path,
+    max_entry_bytes: u64,
+    max_total_bytes: u64,
+) -> Result<(), Error> {
+    let mut archive = zip::ZipArchive::new(File::open(zip_path)?)?;
+    std::fs::create_dir_all(dest)?;
+    let mut extracted = 0;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let name = entry
+            .enclosed_name()
+            .ok_or(ZipError::InvalidArchive("File path outside of the archive"))?;
+        let path = dest.join(name);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let limit = max_entry_bytes.min(max_total_bytes - extracted);
+        let entry_name = entry.name().to_string();
+        let too_large = || match limit < max_entry_bytes {
+            true => Error::ArchiveTooLarge(format!(
+                "Archive extracts to more than {} bytes",
+                max_total_bytes
+            )),
+            false => Error::ArchiveTooLarge(format!(
+                "File {} extracts to more than {} bytes",
+                entry_name, max_entry_bytes
+            )),
+        };
+        if entry.size() > limit {
+            return Err(too_large());
+        }
+        // the sizes recorded in the archive can lie, so the bytes written are counted too
+        let mut file = File::create(&path)?;
+        let written = std::io::copy(&mut (&mut entry).take(limit + 1), &mut file)?;
+        if written > limit {
+            return Err(too_large());
+        }
+        extracted += written;
+    }
+
+    if dest.join("stops.txt").exists() {
+        return Ok(());
+    }
+    // A single enclosing folder holding the feed
+    let entries: Vec<PathBuf> = std::fs::read_dir(dest)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .collect();
+    if let [inner] = entries.as_slice() {
+        if inner.is_dir() && inner.join("stops.txt").exists() {
+            for entry in std::fs::read_dir(inner)? {
+                let entry = entry?;
+                std::fs::rename(entry.path(), dest.join(entry.file_name()))?;
+            }
+            std::fs::remove_dir_all(inner)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn extraction_stops_at_the_size_limits() {
+        let dir = std::env::temp_dir().join(format!("feeds_extract_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let zip_path = dir.join("feed.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for name in ["feed/stops.txt", "feed/stop_times.txt"] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(&[b'0'; 1000]).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let dest = dir.join("small_entries");
+        let result = extract_zip_within(&zip_path, &dest, 999, 10_000);
+        assert!(matches!(result, Err(Error::ArchiveTooLarge(_))));
+        let dest = dir.join("small_total");
+        let result = extract_zip_within(&zip_path, &dest, 1000, 1999);
+        assert!(matches!(result, Err(Error::ArchiveTooLarge(_))));
+
+        let dest = dir.join("extracted");
+        extract_zip_within(&zip_path, &dest, 1000, 2000).unwrap();
+        assert_eq!(
+            std::fs::metadata(dest.join("stops.txt")).unwrap().len(),
+            1000
+        );
+        assert!(dest.join("stop_times.txt").exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
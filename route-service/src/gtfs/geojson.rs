@@ -3,7 +3,9 @@ use crate::gtfs::{
     structs::{Route, Stop, Trip},
 };
 
-use serde_json::{json, Value};
+use geo::Simplify;
+use geo_types::{LineString, Polygon};
+use serde_json::{json, Map, Value};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
@@ -16,6 +18,59 @@ pub fn convert_to_geojson(features: &Vec<Value>) -> Value {
     return output;
 }
 
+/// Features serialized into each chunk of a streamed FeatureCollection
+const FEATURES_PER_CHUNK: usize = 256;
+
+/// A FeatureCollection serialized a few features at a time, so that a large collection is sent
+/// as it is written instead of being held as one JSON value and string
+///
+/// # Parameters
+/// - `features`: Features of the collection, dropped as they are serialized
+/// - `parent`: Fields of an object the collection is nested in and the key it is nested
+///   under, e.g. the message of a response; `None` to serialize the collection alone
+pub fn feature_collection_chunks(
+    features: Vec<Value>,
+    parent: Option<(Map<String, Value>, &str)>,
+) -> impl Iterator<Item = Result<Vec<u8>, serde_json::Error>> {
+    let mut tail = b"]}".to_vec();
+    let head = (|| {
+        let mut head = vec![];
+        if let Some((fields, key)) = parent {
+            head.push(b'{');
+            for (field, value) in fields.iter().filter(|(field, _)| *field != key) {
+                serde_json::to_writer(&mut head, field)?;
+                head.push(b':');
+                serde_json::to_writer(&mut head, value)?;
+                head.push(b',');
+            }
+            serde_json::to_writer(&mut head, key)?;
+            head.push(b':');
+            tail.push(b'}');
+        }
+        head.extend_from_slice(br#"{"type":"FeatureCollection","features":["#);
+        Ok(head)
+    })();
+
+    let mut features = features.into_iter();
+    let mut first = true;
+    let body = std::iter::from_fn(move || {
+        let mut chunk = vec![];
+        for feature in features.by_ref().take(FEATURES_PER_CHUNK) {
+            if !first {
+                chunk.push(b',');
+            }
+            first = false;
+            if let Err(e) = serde_json::to_writer(&mut chunk, &feature) {
+                return Some(Err(e));
+            }
+        }
+        (!chunk.is_empty()).then_some(Ok(chunk))
+    });
+    std::iter::once(head)
+        .chain(body)
+        .chain(std::iter::once(Ok(tail)))
+}
+
 pub fn get_all_features(gtfs_data: &Gtfs) -> Vec<Value> {
     let mut feature_set: Vec<Value> = vec![];
     feature_set.extend(get_route_features(&gtfs_data));
@@ -24,40 +79,147 @@ pub fn get_all_features(gtfs_data: &Gtfs) -> Vec<Value> {
     return feature_set;
 }
 
+/// Zoom level at and above which line geometry is returned at full detail
+pub const FULL_DETAIL_ZOOM: u8 = 16;
+
+/// Simplification tolerance in degrees for a web map zoom level, roughly the
+/// width of a 256px tile pixel at that zoom. Returns 0.0 (no simplification)
+/// at `FULL_DETAIL_ZOOM` and above.
+pub fn simplify_tolerance(zoom: u8) -> f64 {
+    if zoom >= FULL_DETAIL_ZOOM {
+        0.0
+    } else {
+        360.0 / (256.0 * 2f64.powi(zoom as i32))
+    }
+}
+
+/// GeoJSON coordinates of a polygon, exterior ring first
+///
+/// # Parameters
+/// - `polygon`: Polygon to convert
+/// - `tolerance`: Ramer-Douglas-Peucker tolerance in degrees, 0.0 keeps the geometry as is
+pub fn polygon_coordinates(polygon: &Polygon<f64>, tolerance: f64) -> Vec<Vec<[f64; 2]>> {
+    let simplified;
+    let polygon = if tolerance > 0.0 {
+        simplified = polygon.simplify(&tolerance);
+        &simplified
+    } else {
+        polygon
+    };
+    std::iter::once(polygon.exterior())
+        .chain(polygon.interiors())
+        .map(|ring| ring.coords().map(|c| [c.x, c.y]).collect())
+        .collect()
+}
+
+/// Tag features with a `variant` property and simplify their line geometry
+///
+/// # Parameters
+/// - `features`: GeoJSON features to modify in place
+/// - `variant`: Value of the `variant` property, e.g. `original` or `optimized`
+/// - `tolerance`: Ramer-Douglas-Peucker tolerance in degrees, 0.0 keeps the geometry as is
+pub fn tag_and_simplify_features(features: &mut [Value], variant: &str, tolerance: f64) {
+    for feature in features.iter_mut() {
+        if let Some(properties) = feature["properties"].as_object_mut() {
+            properties.insert("variant".to_string(), json!(variant));
+        }
+        if tolerance <= 0.0 || feature["geometry"]["type"] != "LineString" {
+            continue;
+        }
+        let coords: Vec<(f64, f64)> = feature["geometry"]["coordinates"]
+            .as_array()
+            .map(|coords| {
+                coords
+                    .iter()
+                    .filter_map(|c| Some((c[0].as_f64()?, c[1].as_f64()?)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let simplified = LineString::from(coords).simplify(&tolerance);
+        feature["geometry"]["coordinates"] =
+            json!(simplified.coords().map(|c| [c.x, c.y]).collect::<Vec<_>>());
+    }
+}
+
 // Build route features from gtfs data
 fn get_route_features(gtfs_data: &Gtfs) -> Vec<Value> {
     let route_to_shape = build_route_shape_mapping(&gtfs_data.trips);
     let route_to_stops = build_route_stop_mapping(&gtfs_data.trips);
-    let features = gtfs_data
-        .routes
-        .values()
-        .map(|route| {
-            json!({
-                "type": "Feature",
-                "geometry": {
-                    "type": "LineString",
-                    "coordinates": get_route_coords(&route, gtfs_data, &route_to_shape),
-                },
-                "properties": {
-                    "route_id": &route.route_id,
-                    "route_short_name": &route.route_short_name,
-                    "route_long_name": &route.route_long_name,
-                    "route_desc": &route.route_desc,
-                    "route_type": &route.route_type,
-                    "route_url": &route.route_url,
-                    "route_stops": &route_to_stops.get(&route.route_id).unwrap_or(&vec![]),
-                }
+    let mut routes: Vec<&Route> = gtfs_data.routes.values().collect();
+    routes.sort_by(|a, b| a.route_id.cmp(&b.route_id));
+    let features = routes
+        .into_iter()
+        .flat_map(|route| {
+            // routes without an inbound trip only have an outbound feature
+            let inbound = (route.route_id.clone(), Direction::Inbound);
+            let directions = if route_to_shape.contains_key(&inbound) {
+                vec![Direction::Outbound, Direction::Inbound]
+            } else {
+                vec![Direction::Outbound]
+            };
+            directions.into_iter().map(|direction| {
+                let key = (route.route_id.clone(), direction);
+                json!({
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "LineString",
+                        "coordinates": get_route_coords(&key, gtfs_data, &route_to_shape),
+                    },
+                    "properties": {
+                        "route_id": &route.route_id,
+                        "route_short_name": &route.route_short_name,
+                        "route_long_name": &route.route_long_name,
+                        "route_desc": &route.route_desc,
+                        "route_type": &route.route_type,
+                        "route_url": &route.route_url,
+                        "route_stops": &route_to_stops.get(&key).unwrap_or(&vec![]),
+                        "direction": direction.name(),
+                        "approximate_geometry": route_to_shape
+                            .get(&key)
+                            .is_some_and(|shape_id| gtfs_data.approximate_shapes.contains_key(shape_id)),
+                        "synthesized_geometry": route_to_shape
+                            .get(&key)
+                            .is_some_and(|shape_id| gtfs_data.synthesized_shapes.contains(shape_id)),
+                    }
+                })
             })
+            .collect::<Vec<Value>>()
         })
         .collect::<Vec<Value>>();
 
     return features;
 }
 
+/// Direction of the trips of a route, trips with `direction_id` 1 run inbound
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Direction {
+    Outbound,
+    Inbound,
+}
+
+impl Direction {
+    fn of(trip: &Trip) -> Direction {
+        if trip.direction_id == Some(1) {
+            Direction::Inbound
+        } else {
+            Direction::Outbound
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Direction::Outbound => "outbound",
+            Direction::Inbound => "inbound",
+        }
+    }
+}
+
 // Build stop features from gtfs data
 fn get_stop_features(stops: &HashMap<String, Arc<Stop>>) -> Vec<Value> {
+    let mut stops: Vec<&Arc<Stop>> = stops.values().collect();
+    stops.sort_by(|a, b| a.stop_id.cmp(&b.stop_id));
     let features = stops
-        .values()
+        .into_iter()
         .map(|stop| {
             let stop = stop.as_ref();
 
@@ -88,23 +250,27 @@ fn get_stop_features(stops: &HashMap<String, Arc<Stop>>) -> Vec<Value> {
     return features;
 }
 
-// Map route_id to shape_id
-fn build_route_shape_mapping(trips: &HashMap<String, Vec<Trip>>) -> HashMap<String, String> {
-    let mut mapping: HashMap<String, String> = HashMap::new();
+// Map route_id and direction to shape_id
+fn build_route_shape_mapping(
+    trips: &HashMap<String, Vec<Trip>>,
+) -> HashMap<(String, Direction), String> {
+    let mut mapping: HashMap<(String, Direction), String> = HashMap::new();
 
     for trip_list in trips.values() {
         for trip in trip_list {
             let shape_id = trip.shape_id.clone().unwrap_or_else(|| String::new());
-            mapping.insert(trip.route_id.clone(), shape_id);
+            mapping.insert((trip.route_id.clone(), Direction::of(trip)), shape_id);
         }
     }
 
     return mapping;
 }
 
-// Map route_id to [stop_id]
-fn build_route_stop_mapping(trips: &HashMap<String, Vec<Trip>>) -> HashMap<String, Vec<String>> {
-    let mut mapping: HashMap<String, Vec<String>> = HashMap::new();
+// Map route_id and direction to [stop_id]
+fn build_route_stop_mapping(
+    trips: &HashMap<String, Vec<Trip>>,
+) -> HashMap<(String, Direction), Vec<String>> {
+    let mut mapping: HashMap<(String, Direction), Vec<String>> = HashMap::new();
     for trip_list in trips.values() {
         for trip in trip_list {
             let stop_ids = trip
@@ -113,14 +279,15 @@ fn build_route_stop_mapping(trips: &HashMap<String, Vec<Trip>>) -> HashMap<Strin
                 .map(|stop_time| stop_time.stop_id.clone())
                 .collect::<Vec<String>>();
 
-            if let Some(vec) = mapping.get_mut(&trip.route_id) {
+            let key = (trip.route_id.clone(), Direction::of(trip));
+            if let Some(vec) = mapping.get_mut(&key) {
                 for stop_id in stop_ids {
                     if !vec.contains(&stop_id) {
                         vec.push(stop_id.clone());
                     }
                 }
             } else {
-                mapping.insert(trip.route_id.clone(), stop_ids);
+                mapping.insert(key, stop_ids);
             }
         }
     }
@@ -129,12 +296,14 @@ fn build_route_stop_mapping(trips: &HashMap<String, Vec<Trip>>) -> HashMap<Strin
 }
 
 fn get_route_coords(
-    route: &Route,
+    key: &(String, Direction),
     gtfs_data: &Gtfs,
-    route_to_shape: &HashMap<String, String>,
+    route_to_shape: &HashMap<(String, Direction), String>,
 ) -> Vec<[f64; 2]> {
-    if let Some(shape_id) = route_to_shape.get(&route.route_id) {
-        let route_shapes = gtfs_data.shapes.get(shape_id).unwrap();
+    if let Some(route_shapes) = route_to_shape
+        .get(key)
+        .and_then(|shape_id| gtfs_data.shapes.get(shape_id))
+    {
         route_shapes
             .iter()
             .map(|shape| [shape.shape_pt_lon, shape.shape_pt_lat])
@@ -2,7 +2,7 @@ use crate::gtfs::error::Error;
 use crate::gtfs::raw_gtfs::GtfsDataSet;
 use crate::gtfs::structs::*;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::sync::Arc;
 
@@ -30,6 +30,12 @@ pub struct Gtfs {
     pub fare_rules: HashMap<String, Vec<FareRule>>,
     /// All feed info
     pub feed_info: Vec<FeedInfo>,
+    /// Number of straight segments by `shape_id`, drawn between stops without a road path when
+    /// routes are exported. Not part of the feed, so it is not serialized.
+    pub approximate_shapes: HashMap<String, usize>,
+    /// Shapes drawn from stop to stop when routes are exported, since the feed has none for
+    /// their trip. Not part of the feed, so it is not serialized.
+    pub synthesized_shapes: HashSet<String>,
 }
 
 impl Serialize for Gtfs {
@@ -68,6 +74,13 @@ impl Gtfs {
         println!("  Fare attributes: {}", self.fare_attributes.len());
         println!("  Fare rules: {}", self.fare_rules.len());
         println!("  Feed info: {}", self.feed_info.len());
+        if !self.approximate_shapes.is_empty() {
+            println!(
+                "  Approximate shapes: {} ({} straight segments)",
+                self.approximate_shapes.len(),
+                self.approximate_shapes.values().sum::<usize>()
+            );
+        }
     }
 
     pub fn from_path<P>(path: P) -> Result<Gtfs, Error>
@@ -97,12 +110,17 @@ impl TryFrom<GtfsDataSet> for Gtfs {
         for f in raw.fare_rules.unwrap_or_else(|| Ok(Vec::new()))? {
             (*fare_rules.entry(f.fare_id.clone()).or_default()).push(f);
         }
-        let route_to_trips = trips.values().fold(HashMap::new(), |mut acc, t| {
+        let mut route_to_trips = trips.values().fold(HashMap::new(), |mut acc, t| {
             acc.entry(t.route_id.clone())
                 .or_insert_with(Vec::new)
                 .push(t.clone());
             acc
         });
+        // the trips of a route in trip id order, so that picking among them does not depend
+        // on the hash map order
+        for route_trips in route_to_trips.values_mut() {
+            route_trips.sort_by(|a: &Trip, b: &Trip| a.trip_id.cmp(&b.trip_id));
+        }
         Ok(Gtfs {
             stops: stops,
             routes: to_map(raw.routes?),
@@ -116,6 +134,8 @@ impl TryFrom<GtfsDataSet> for Gtfs {
             calendar_dates: to_calendar_dates(
                 raw.calendar_dates.unwrap_or_else(|| Ok(Vec::new()))?,
             ),
+            approximate_shapes: HashMap::new(),
+            synthesized_shapes: HashSet::new(),
         })
     }
 }
@@ -123,13 +143,14 @@ impl TryFrom<GtfsDataSet> for Gtfs {
 impl TryInto<GtfsDataSet> for Gtfs {
     type Error = Error;
     /// Tries to convert a [Gtfs] into a [GtfsDataSet]
+    ///
+    /// Every table is sorted by its ids so that identical feeds convert to identical data sets
     fn try_into(self) -> Result<GtfsDataSet, Error> {
         // Reconstruct stops and extract transfers and pathways from each stop.
         let mut raw_transfers = Vec::new();
         let mut raw_pathways = Vec::new();
-        let raw_stops: Vec<Stop> = self
-            .stops
-            .into_values()
+        let raw_stops: Vec<Stop> = sorted_values(self.stops)
+            .into_iter()
             .map(|arc_stop| {
                 // Clone the stop since the Arc is referenced in many places.
                 let mut stop = (*arc_stop).clone();
@@ -143,7 +164,7 @@ impl TryInto<GtfsDataSet> for Gtfs {
         let mut raw_stop_times = Vec::new();
         let mut raw_frequencies = Vec::new();
         let mut raw_trips = Vec::new();
-        for trip_list in self.trips.into_values() {
+        for trip_list in sorted_values(self.trips) {
             for mut trip in trip_list {
                 raw_stop_times.append(&mut trip.stop_times);
                 raw_frequencies.append(&mut trip.frequencies);
@@ -152,13 +173,25 @@ impl TryInto<GtfsDataSet> for Gtfs {
         }
 
         // Flatten other fields from maps.
-        let raw_routes: Vec<Route> = self.routes.into_values().collect();
-        let raw_shapes: Vec<Shape> = self.shapes.into_values().flatten().collect();
-        let raw_fare_attributes: Vec<FareAttribute> = self.fare_attributes.into_values().collect();
-        let raw_fare_rules: Vec<FareRule> = self.fare_rules.into_values().flatten().collect();
-        let raw_calendar: Vec<Calendar> = self.calendar.into_values().collect();
-        let raw_calendar_dates: Vec<CalendarDate> =
-            self.calendar_dates.into_values().flatten().collect();
+        raw_trips.sort_by(|a, b| a.trip_id.cmp(&b.trip_id));
+        raw_stop_times
+            .sort_by(|a, b| (&a.trip_id, a.stop_sequence).cmp(&(&b.trip_id, b.stop_sequence)));
+        raw_frequencies.sort_by(|a, b| {
+            (&a.trip_id, parse_gtfs_time(&a.start_time))
+                .cmp(&(&b.trip_id, parse_gtfs_time(&b.start_time)))
+        });
+        let raw_routes: Vec<Route> = sorted_values(self.routes);
+        let raw_shapes: Vec<Shape> = sorted_values(self.shapes).into_iter().flatten().collect();
+        let raw_fare_attributes: Vec<FareAttribute> = sorted_values(self.fare_attributes);
+        let raw_fare_rules: Vec<FareRule> = sorted_values(self.fare_rules)
+            .into_iter()
+            .flatten()
+            .collect();
+        let raw_calendar: Vec<Calendar> = sorted_values(self.calendar);
+        let raw_calendar_dates: Vec<CalendarDate> = sorted_values(self.calendar_dates)
+            .into_iter()
+            .flatten()
+            .collect();
 
         Ok(GtfsDataSet {
             agencies: Ok(self.agencies),
@@ -180,6 +213,13 @@ impl TryInto<GtfsDataSet> for Gtfs {
     }
 }
 
+/// Values of a map in the order of their keys
+fn sorted_values<V>(map: HashMap<String, V>) -> Vec<V> {
+    let mut entries: Vec<(String, V)> = map.into_iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries.into_iter().map(|(_, v)| v).collect()
+}
+
 fn to_map<O: Id>(elements: impl IntoIterator<Item = O>) -> HashMap<String, O> {
     elements
         .into_iter()
@@ -1,5 +1,8 @@
 pub mod error;
+pub mod feeds;
 pub mod geojson;
 pub mod gtfs;
 pub mod raw_gtfs;
+pub mod realtime;
 pub mod structs;
+pub mod validator;
@@ -4,14 +4,25 @@ use crate::gtfs::structs::*;
 use csv::StringRecord;
 use rusqlite::{params, Connection};
 use serde::{Deserialize, Deserializer, Serialize};
-use std::{fs::File, io::Read, path::Path, str::FromStr};
+use std::{
+    fs::File,
+    io::{Read, Seek, Write},
+    path::Path,
+    str::FromStr,
+};
 
 /// Helper function to deserialize optional fields that might fail to parse
+///
+/// Fields are read as text from the feed's files. Binary caches, which are not human
+/// readable, store the values themselves as serialized.
 pub fn deserialize_opt<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
 where
-    T: FromStr,
+    T: FromStr + Deserialize<'de>,
     D: Deserializer<'de>,
 {
+    if !deserializer.is_human_readable() {
+        return Option::<T>::deserialize(deserializer);
+    }
     let opt = Option::<String>::deserialize(deserializer)?;
     match opt {
         Some(s) if s.trim().is_empty() => Ok(None),
@@ -91,6 +102,93 @@ impl GtfsDataSet {
         );
     }
 
+    /// Write the dataset as a directory of GTFS text files
+    ///
+    /// Files that failed to load or have no rows are not written.
+    pub fn write_to_dir<P>(&self, path: P) -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+    {
+        let p = path.as_ref();
+        std::fs::create_dir_all(p)?;
+        for (file_name, contents) in self.to_csv_files()? {
+            std::fs::write(p.join(file_name), contents)?;
+        }
+        Ok(())
+    }
+
+    /// Write the data set as a zipped feed, with the same files `write_to_dir` writes
+    ///
+    /// # Returns
+    /// The writer, after the zip archive is finished
+    pub fn write_to_zip<W>(&self, writer: W) -> Result<W, Error>
+    where
+        W: Write + Seek,
+    {
+        let mut zip = zip::ZipWriter::new(writer);
+        let options = zip::write::SimpleFileOptions::default();
+        for (file_name, contents) in self.to_csv_files()? {
+            zip.start_file(file_name, options)?;
+            zip.write_all(&contents)?;
+        }
+        Ok(zip.finish()?)
+    }
+
+    /// Contents of each file of the data set, files without records are left out
+    fn to_csv_files(&self) -> Result<Vec<(&'static str, Vec<u8>)>, Error> {
+        let mut files = Vec::new();
+        GtfsDataSet::write_obj(&mut files, "agency.txt", Some(&self.agencies))?;
+        GtfsDataSet::write_obj(&mut files, "stops.txt", Some(&self.stops))?;
+        GtfsDataSet::write_obj(&mut files, "routes.txt", Some(&self.routes))?;
+        GtfsDataSet::write_obj(&mut files, "trips.txt", Some(&self.trips))?;
+        GtfsDataSet::write_obj(&mut files, "stop_times.txt", Some(&self.stop_times))?;
+        GtfsDataSet::write_obj(&mut files, "calendar.txt", self.calendar.as_ref())?;
+        GtfsDataSet::write_obj(
+            &mut files,
+            "calendar_dates.txt",
+            self.calendar_dates.as_ref(),
+        )?;
+        GtfsDataSet::write_obj(&mut files, "shapes.txt", self.shapes.as_ref())?;
+        GtfsDataSet::write_obj(
+            &mut files,
+            "fare_attributes.txt",
+            self.fare_attributes.as_ref(),
+        )?;
+        GtfsDataSet::write_obj(&mut files, "fare_rules.txt", self.fare_rules.as_ref())?;
+        GtfsDataSet::write_obj(&mut files, "frequencies.txt", self.frequencies.as_ref())?;
+        GtfsDataSet::write_obj(&mut files, "transfers.txt", self.transfers.as_ref())?;
+        GtfsDataSet::write_obj(&mut files, "pathways.txt", self.pathways.as_ref())?;
+        GtfsDataSet::write_obj(&mut files, "feed_info.txt", self.feed_info.as_ref())?;
+        GtfsDataSet::write_obj(&mut files, "translations.txt", self.translations.as_ref())?;
+        Ok(files)
+    }
+
+    fn write_obj<O>(
+        files: &mut Vec<(&'static str, Vec<u8>)>,
+        file_name: &'static str,
+        objs: Option<&Result<Vec<O>, Error>>,
+    ) -> Result<(), Error>
+    where
+        O: Serialize,
+    {
+        let objs = match objs {
+            Some(Ok(objs)) if !objs.is_empty() => objs,
+            _ => return Ok(()),
+        };
+        let csv_error = |e| Error::CSVError {
+            file_name: file_name.to_owned(),
+            source: e,
+            line_in_error: None,
+        };
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for obj in objs {
+            writer.serialize(obj).map_err(csv_error)?;
+        }
+        let contents = writer.into_inner().map_err(|e| e.into_error())?;
+        files.push((file_name, contents));
+        Ok(())
+    }
+
     fn read_from_dir(path: &Path) -> Result<GtfsDataSet, Error> {
         Ok(GtfsDataSet {
             agencies: GtfsDataSet::read_obj_from_path(path, "agency.txt"),
@@ -149,7 +247,10 @@ impl GtfsDataSet {
         }
     }
 
-    fn optional_read_obj_from_path<O>(path: &Path, file_name: &str) -> Option<Result<Vec<O>, Error>>
+    pub(crate) fn optional_read_obj_from_path<O>(
+        path: &Path,
+        file_name: &str,
+    ) -> Option<Result<Vec<O>, Error>>
     where
         for<'de> O: Deserialize<'de>,
     {
@@ -294,3 +395,27 @@ fn optional_file_summary<T>(objs: &Option<Result<Vec<T>, Error>>) -> String {
         None => "File not present".to_string(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn optional_fields_read_from_text_and_binary_caches() {
+        let csv = "shape_id,shape_pt_lat,shape_pt_lon,shape_pt_sequence,shape_dist_traveled\n\
+                   a,43.6,-79.4,1,\n\
+                   a,43.7,-79.4,2,12.5\n\
+                   a,43.8,-79.4,3,unknown\n";
+        let shapes: Vec<Shape> = csv::Reader::from_reader(csv.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let distances: Vec<Option<f64>> = shapes.iter().map(|s| s.shape_dist_traveled).collect();
+        assert_eq!(distances, vec![None, Some(12.5), None]);
+
+        let bytes = bincode::serialize(&shapes).unwrap();
+        let cached: Vec<Shape> = bincode::deserialize(&bytes).unwrap();
+        let cached: Vec<Option<f64>> = cached.iter().map(|s| s.shape_dist_traveled).collect();
+        assert_eq!(cached, distances);
+    }
+}
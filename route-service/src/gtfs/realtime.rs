@@ -0,0 +1,416 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
+use prost::Message;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::gtfs::error::Error;
+use crate::layers::grid::TimePeriod;
+
+/// Snapshot of a GTFS-realtime feed
+///
+/// Only the parts of the format used to observe headways and delays are decoded, trip updates
+/// and vehicle positions. Field numbers follow
+/// https://gtfs.org/documentation/realtime/proto/
+#[derive(Clone, PartialEq, Message)]
+pub struct FeedMessage {
+    #[prost(message, required, tag = "1")]
+    pub header: FeedHeader,
+    #[prost(message, repeated, tag = "2")]
+    pub entity: Vec<FeedEntity>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct FeedHeader {
+    #[prost(string, required, tag = "1")]
+    pub gtfs_realtime_version: String,
+    /// POSIX time the feed was created at
+    #[prost(uint64, optional, tag = "3")]
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct FeedEntity {
+    #[prost(string, required, tag = "1")]
+    pub id: String,
+    #[prost(bool, optional, tag = "2")]
+    pub is_deleted: Option<bool>,
+    #[prost(message, optional, tag = "3")]
+    pub trip_update: Option<TripUpdate>,
+    #[prost(message, optional, tag = "4")]
+    pub vehicle: Option<VehiclePosition>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TripUpdate {
+    #[prost(message, required, tag = "1")]
+    pub trip: TripDescriptor,
+    #[prost(message, repeated, tag = "2")]
+    pub stop_time_update: Vec<StopTimeUpdate>,
+    #[prost(uint64, optional, tag = "4")]
+    pub timestamp: Option<u64>,
+    /// Delay of the trip in seconds, for stops without their own
+    #[prost(int32, optional, tag = "5")]
+    pub delay: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct StopTimeUpdate {
+    #[prost(uint32, optional, tag = "1")]
+    pub stop_sequence: Option<u32>,
+    #[prost(message, optional, tag = "2")]
+    pub arrival: Option<StopTimeEvent>,
+    #[prost(message, optional, tag = "3")]
+    pub departure: Option<StopTimeEvent>,
+    #[prost(string, optional, tag = "4")]
+    pub stop_id: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct StopTimeEvent {
+    /// Seconds behind schedule, negative when early
+    #[prost(int32, optional, tag = "1")]
+    pub delay: Option<i32>,
+    /// POSIX time of the event
+    #[prost(int64, optional, tag = "2")]
+    pub time: Option<i64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TripDescriptor {
+    #[prost(string, optional, tag = "1")]
+    pub trip_id: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub start_date: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub route_id: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct VehiclePosition {
+    #[prost(message, optional, tag = "1")]
+    pub trip: Option<TripDescriptor>,
+    #[prost(uint32, optional, tag = "3")]
+    pub current_stop_sequence: Option<u32>,
+    /// `VehicleStopStatus`, see `STOPPED_AT`
+    #[prost(int32, optional, tag = "4")]
+    pub current_status: Option<i32>,
+    #[prost(uint64, optional, tag = "5")]
+    pub timestamp: Option<u64>,
+    #[prost(string, optional, tag = "7")]
+    pub stop_id: Option<String>,
+}
+
+/// `VehicleStopStatus` of a vehicle standing at its current stop
+pub const STOPPED_AT: i32 = 1;
+
+/// Headways and delays observed on a route during a time period
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ObservedService {
+    pub headway_samples: usize,
+    /// Mean time between two trips at the same stop, `None` without samples
+    pub mean_headway_secs: Option<f64>,
+    /// Departures the route makes over the whole period at the observed headway, to compare
+    /// with its scheduled departures in `TransitRoute::stop_times`
+    pub observed_departures: Option<f64>,
+    pub delay_samples: usize,
+    /// Mean delay at stops, negative when early, `None` without samples
+    pub mean_delay_secs: Option<f64>,
+}
+
+/// Summary of the realtime feeds ingested for a city
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct RealtimeSummary {
+    pub snapshots: usize,
+    pub entities: usize,
+    /// Entities whose route could not be found in the static feed
+    pub unmatched_entities: usize,
+    /// Observed service by route and period
+    pub routes: HashMap<String, HashMap<TimePeriod, ObservedService>>,
+}
+
+/// Observations gathered from successive snapshots of a city's realtime feeds
+///
+/// Snapshots repeat the predictions of the trips under way, so only the latest arrival and
+/// delay of each trip at each stop is kept.
+#[derive(Default)]
+pub struct RealtimeObservations {
+    snapshots: usize,
+    entities: usize,
+    unmatched_entities: usize,
+    /// Arrival time of each trip by route and stop
+    arrivals: HashMap<(String, String), HashMap<String, i64>>,
+    /// Route, time and delay of each trip at each stop, by trip and stop
+    delays: HashMap<(String, String), (String, i64, i32)>,
+}
+
+impl RealtimeObservations {
+    /// Decode a GTFS-realtime snapshot
+    pub fn decode(bytes: &[u8]) -> Result<FeedMessage, Error> {
+        Ok(FeedMessage::decode(bytes)?)
+    }
+
+    /// Add the trip updates and vehicle positions of a snapshot to the observations
+    ///
+    /// # Parameters
+    /// - `feed`: The snapshot
+    /// - `trip_routes`: Route of the trips whose descriptor has no route id
+    ///
+    /// # Returns
+    /// The number of entities of the snapshot whose route is unknown
+    pub fn ingest(&mut self, feed: &FeedMessage, trip_routes: &HashMap<String, String>) -> usize {
+        self.snapshots += 1;
+        let feed_time = feed.header.timestamp.map(|t| t as i64);
+        let mut unmatched = 0;
+        for entity in feed.entity.iter().filter(|e| !e.is_deleted()) {
+            self.entities += 1;
+            let matched = match (&entity.trip_update, &entity.vehicle) {
+                (Some(update), _) => self.ingest_trip_update(update, feed_time, trip_routes),
+                (None, Some(vehicle)) => self.ingest_vehicle(vehicle, feed_time, trip_routes),
+                (None, None) => true,
+            };
+            if !matched {
+                unmatched += 1;
+            }
+        }
+        self.unmatched_entities += unmatched;
+        unmatched
+    }
+
+    fn ingest_trip_update(
+        &mut self,
+        update: &TripUpdate,
+        feed_time: Option<i64>,
+        trip_routes: &HashMap<String, String>,
+    ) -> bool {
+        let Some((route_id, trip_key)) = resolve_trip(&update.trip, trip_routes) else {
+            return false;
+        };
+        for stop_update in &update.stop_time_update {
+            let Some(stop_key) = stop_key(stop_update.stop_id.as_ref(), stop_update.stop_sequence)
+            else {
+                continue;
+            };
+            let event = stop_update
+                .arrival
+                .as_ref()
+                .or(stop_update.departure.as_ref());
+            let time = event.and_then(|event| event.time);
+            if let (Some(time), Some(stop_id)) = (time, &stop_update.stop_id) {
+                self.arrivals
+                    .entry((route_id.clone(), stop_id.clone()))
+                    .or_default()
+                    .insert(trip_key.clone(), time);
+            }
+            let delay = event.and_then(|event| event.delay).or(update.delay);
+            let time = time.or(update.timestamp.map(|t| t as i64)).or(feed_time);
+            if let (Some(delay), Some(time)) = (delay, time) {
+                self.delays.insert(
+                    (trip_key.clone(), stop_key),
+                    (route_id.clone(), time, delay),
+                );
+            }
+        }
+        true
+    }
+
+    fn ingest_vehicle(
+        &mut self,
+        vehicle: &VehiclePosition,
+        feed_time: Option<i64>,
+        trip_routes: &HashMap<String, String>,
+    ) -> bool {
+        let Some((route_id, trip_key)) = vehicle
+            .trip
+            .as_ref()
+            .and_then(|trip| resolve_trip(trip, trip_routes))
+        else {
+            return false;
+        };
+        let time = vehicle.timestamp.map(|t| t as i64).or(feed_time);
+        if let (Some(STOPPED_AT), Some(stop_id), Some(time)) =
+            (vehicle.current_status, &vehicle.stop_id, time)
+        {
+            // the vehicle arrived when it was first seen at the stop
+            let arrival = self
+                .arrivals
+                .entry((route_id, stop_id.clone()))
+                .or_default()
+                .entry(trip_key)
+                .or_insert(time);
+            *arrival = (*arrival).min(time);
+        }
+        true
+    }
+
+    /// Headways and delays observed so far on each route, by period of the city's time zone
+    ///
+    /// Headways are the times between successive trips of a route at the same stop, both
+    /// arriving in the same period.
+    pub fn summary(&self, tz: Tz) -> RealtimeSummary {
+        let period_of = |time: i64| {
+            DateTime::from_timestamp(time, 0).and_then(|instant| TimePeriod::at(instant, tz))
+        };
+        // sums and counts of headways and delays by route and period
+        let mut sums: HashMap<(String, TimePeriod), (f64, usize, f64, usize)> = HashMap::new();
+        for ((route_id, _), trips) in &self.arrivals {
+            let mut times: Vec<i64> = trips.values().copied().collect();
+            times.sort_unstable();
+            for pair in times.windows(2) {
+                let period = period_of(pair[1]);
+                if period.is_none() || period != period_of(pair[0]) {
+                    continue;
+                }
+                let sum = sums.entry((route_id.clone(), period.unwrap())).or_default();
+                sum.0 += (pair[1] - pair[0]) as f64;
+                sum.1 += 1;
+            }
+        }
+        for (route_id, time, delay) in self.delays.values() {
+            if let Some(period) = period_of(*time) {
+                let sum = sums.entry((route_id.clone(), period)).or_default();
+                sum.2 += *delay as f64;
+                sum.3 += 1;
+            }
+        }
+
+        let mut routes: HashMap<String, HashMap<TimePeriod, ObservedService>> = HashMap::new();
+        for ((route_id, period), (headways, headway_samples, delays, delay_samples)) in sums {
+            let mean_headway_secs =
+                (headway_samples > 0).then(|| headways / headway_samples as f64);
+            let (start, end) = period.local_bounds();
+            routes.entry(route_id).or_default().insert(
+                period,
+                ObservedService {
+                    headway_samples,
+                    mean_headway_secs,
+                    observed_departures: mean_headway_secs
+                        .filter(|&headway| headway > 0.0)
+                        .map(|headway| (end - start) as f64 / headway),
+                    delay_samples,
+                    mean_delay_secs: (delay_samples > 0).then(|| delays / delay_samples as f64),
+                },
+            );
+        }
+        RealtimeSummary {
+            snapshots: self.snapshots,
+            entities: self.entities,
+            unmatched_entities: self.unmatched_entities,
+            routes,
+        }
+    }
+
+    /// Trips of a snapshot whose descriptor has a trip id but no route id
+    pub fn trips_without_route(feed: &FeedMessage) -> Vec<&str> {
+        feed.entity
+            .iter()
+            .filter_map(|entity| {
+                entity
+                    .trip_update
+                    .as_ref()
+                    .map(|update| &update.trip)
+                    .or(entity.vehicle.as_ref().and_then(|v| v.trip.as_ref()))
+            })
+            .filter(|trip| trip.route_id.is_none())
+            .filter_map(|trip| trip.trip_id.as_deref())
+            .collect()
+    }
+}
+
+/// Route of a trip and the key its observations are kept under, which tells apart the runs
+/// of a trip on different days
+fn resolve_trip(
+    trip: &TripDescriptor,
+    trip_routes: &HashMap<String, String>,
+) -> Option<(String, String)> {
+    let trip_id = trip.trip_id.as_ref()?;
+    let route_id = trip
+        .route_id
+        .clone()
+        .or_else(|| trip_routes.get(trip_id).cloned())?;
+    let trip_key = format!("{}@{}", trip_id, trip.start_date.as_deref().unwrap_or(""));
+    Some((route_id, trip_key))
+}
+
+/// Key of a stop of a stop time update, by stop id or else by stop sequence
+fn stop_key(stop_id: Option<&String>, stop_sequence: Option<u32>) -> Option<String> {
+    stop_id
+        .cloned()
+        .or_else(|| stop_sequence.map(|seq| format!("#{}", seq)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2025-03-04 08:00 in Toronto, during the AM rush
+    const AM_RUSH: i64 = 1741093200;
+
+    fn trip_update(trip_id: &str, route_id: Option<&str>, time: i64, delay: i32) -> FeedEntity {
+        FeedEntity {
+            id: trip_id.to_string(),
+            trip_update: Some(TripUpdate {
+                trip: TripDescriptor {
+                    trip_id: Some(trip_id.to_string()),
+                    start_date: Some("20250304".to_string()),
+                    route_id: route_id.map(str::to_string),
+                },
+                stop_time_update: vec![StopTimeUpdate {
+                    stop_id: Some("A".to_string()),
+                    arrival: Some(StopTimeEvent {
+                        delay: Some(delay),
+                        time: Some(time),
+                    }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn snapshots_aggregate_into_headways_and_delays() {
+        let feed = |entity: Vec<FeedEntity>| FeedMessage {
+            header: FeedHeader {
+                gtfs_realtime_version: "2.0".to_string(),
+                timestamp: Some(AM_RUSH as u64),
+            },
+            entity,
+        };
+        let first = feed(vec![
+            trip_update("t1", Some("1"), AM_RUSH, 60),
+            trip_update("t2", None, AM_RUSH + 600, 0),
+            trip_update("t9", None, AM_RUSH, 0),
+        ]);
+        // the next snapshot updates the prediction of t2 and adds t3
+        let second = feed(vec![
+            trip_update("t2", None, AM_RUSH + 660, 120),
+            trip_update("t3", Some("1"), AM_RUSH + 1260, 0),
+        ]);
+        let decoded = RealtimeObservations::decode(&first.encode_to_vec()).unwrap();
+        assert_eq!(decoded, first);
+        assert_eq!(
+            RealtimeObservations::trips_without_route(&first),
+            vec!["t2", "t9"]
+        );
+
+        let trip_routes = HashMap::from([("t2".to_string(), "1".to_string())]);
+        let mut observations = RealtimeObservations::default();
+        assert_eq!(observations.ingest(&first, &trip_routes), 1);
+        assert_eq!(observations.ingest(&second, &trip_routes), 0);
+
+        let summary = observations.summary(chrono_tz::America::Toronto);
+        assert_eq!(summary.snapshots, 2);
+        assert_eq!(summary.entities, 5);
+        assert_eq!(summary.unmatched_entities, 1);
+        let service = &summary.routes["1"][&TimePeriod::AmRush];
+        assert_eq!(service.headway_samples, 2);
+        assert_eq!(service.mean_headway_secs, Some(630.0));
+        assert_eq!(service.delay_samples, 3);
+        assert_eq!(service.mean_delay_secs, Some(60.0));
+        // 2.5 hours at one bus every 10.5 minutes
+        let departures = service.observed_departures.unwrap();
+        assert!((departures - 150.0 / 10.5).abs() < 1e-9);
+    }
+}
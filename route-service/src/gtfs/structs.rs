@@ -227,6 +227,28 @@ pub struct StopTime {
     pub stop: Arc<Stop>,
 }
 
+/// Parse a GTFS time (`H:MM:SS`, possibly past `24:00:00` for service after midnight)
+/// into seconds since the start of the service day.
+pub fn parse_gtfs_time(time: &str) -> Option<u32> {
+    let mut parts = time.trim().split(':').map(|p| p.parse::<u32>().ok());
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Some(h)), Some(Some(m)), Some(Some(s)), None) if m < 60 && s < 60 => {
+            Some(h * 3600 + m * 60 + s)
+        }
+        _ => None,
+    }
+}
+
+/// Format seconds since the start of the service day as a GTFS time (`HH:MM:SS`).
+pub fn format_gtfs_time(seconds: u32) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds % 3600) / 60,
+        seconds % 60
+    )
+}
+
 /// Pickup or drop-off type for a stop.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Default)]
 pub enum PickupDropoffType {
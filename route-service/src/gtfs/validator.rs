@@ -0,0 +1,244 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::gtfs::gtfs::Gtfs;
+use crate::gtfs::structs::LocationType;
+
+/// Kind of inconsistency between the files of a feed
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// A stop time refers to a stop that is not in the feed
+    DanglingStopId,
+    /// A trip has no shape or refers to a shape that is not in the feed
+    TripWithoutShape,
+    /// The stop times of a trip do not strictly increase in `stop_sequence`
+    NonIncreasingStopSequence,
+    /// A stop, station or entrance has no latitude or longitude
+    StopWithoutCoordinates,
+    /// A route has no trips
+    RouteWithoutTrips,
+    /// A trip has a `direction_id` other than 0 or 1, its direction is inferred from its stops
+    InvalidDirectionId,
+}
+
+/// An inconsistency found in a feed
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ValidationIssue {
+    pub kind: IssueKind,
+    /// Id of the stop, trip or route the issue is about
+    pub id: String,
+    pub message: String,
+}
+
+/// Inconsistencies found in a feed, e.g. one generated from an optimized network
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ValidationReport {
+    /// Number of issues of each kind
+    pub counts: BTreeMap<IssueKind, usize>,
+    /// Issues grouped by kind
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn push(&mut self, kind: IssueKind, id: &str, message: String) {
+        *self.counts.entry(kind).or_default() += 1;
+        self.issues.push(ValidationIssue {
+            kind,
+            id: id.to_string(),
+            message,
+        });
+    }
+}
+
+/// Check a feed for references the files do not resolve and for data they are missing
+///
+/// # Notes
+/// - Generic nodes and boarding areas may lack coordinates, other stops may not
+/// - Trips are checked in route id then trip id order so that reports can be compared
+pub fn validate(gtfs: &Gtfs) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    let mut trips: Vec<_> = gtfs.trips.values().flatten().collect();
+    trips.sort_by(|a, b| (&a.route_id, &a.trip_id).cmp(&(&b.route_id, &b.trip_id)));
+
+    for trip in &trips {
+        let mut dangling: Vec<&str> = trip
+            .stop_times
+            .iter()
+            .map(|st| st.stop_id.as_str())
+            .filter(|stop_id| !gtfs.stops.contains_key(*stop_id))
+            .collect();
+        dangling.dedup();
+        for stop_id in dangling {
+            report.push(
+                IssueKind::DanglingStopId,
+                stop_id,
+                format!("Trip {} stops at unknown stop {}", trip.trip_id, stop_id),
+            );
+        }
+    }
+    for trip in &trips {
+        match &trip.shape_id {
+            None => report.push(
+                IssueKind::TripWithoutShape,
+                &trip.trip_id,
+                format!("Trip {} has no shape", trip.trip_id),
+            ),
+            Some(shape_id) if !gtfs.shapes.contains_key(shape_id) => report.push(
+                IssueKind::TripWithoutShape,
+                &trip.trip_id,
+                format!("Trip {} has unknown shape {}", trip.trip_id, shape_id),
+            ),
+            Some(_) => {}
+        }
+    }
+    for trip in &trips {
+        if let Some(pair) = trip
+            .stop_times
+            .windows(2)
+            .find(|w| w[1].stop_sequence <= w[0].stop_sequence)
+        {
+            report.push(
+                IssueKind::NonIncreasingStopSequence,
+                &trip.trip_id,
+                format!(
+                    "Trip {} has stop_sequence {} after {}",
+                    trip.trip_id, pair[1].stop_sequence, pair[0].stop_sequence
+                ),
+            );
+        }
+    }
+
+    for trip in &trips {
+        if let Some(direction_id) = trip.direction_id.filter(|d| !matches!(d, 0 | 1)) {
+            report.push(
+                IssueKind::InvalidDirectionId,
+                &trip.trip_id,
+                format!("Trip {} has direction_id {}", trip.trip_id, direction_id),
+            );
+        }
+    }
+
+    let mut stops: Vec<_> = gtfs.stops.values().collect();
+    stops.sort_by(|a, b| a.stop_id.cmp(&b.stop_id));
+    for stop in stops {
+        let needs_coordinates = !matches!(
+            stop.location_type,
+            Some(LocationType::GenericNode | LocationType::BoardingArea)
+        );
+        if needs_coordinates && (stop.stop_lat.is_none() || stop.stop_lon.is_none()) {
+            report.push(
+                IssueKind::StopWithoutCoordinates,
+                &stop.stop_id,
+                format!("Stop {} has no coordinates", stop.stop_id),
+            );
+        }
+    }
+
+    let mut routes: Vec<&String> = gtfs.routes.keys().collect();
+    routes.sort();
+    for route_id in routes {
+        if gtfs
+            .trips
+            .get(route_id)
+            .is_none_or(|trips| trips.is_empty())
+        {
+            report.push(
+                IssueKind::RouteWithoutTrips,
+                route_id,
+                format!("Route {} has no trips", route_id),
+            );
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::structs::{Route, Shape, Stop, StopTime, Trip};
+    use std::sync::Arc;
+
+    fn stop(stop_id: &str, coordinates: Option<(f64, f64)>) -> Arc<Stop> {
+        Arc::new(Stop {
+            stop_id: stop_id.to_string(),
+            stop_lat: coordinates.map(|c| c.1),
+            stop_lon: coordinates.map(|c| c.0),
+            ..Default::default()
+        })
+    }
+
+    fn trip(trip_id: &str, shape_id: Option<&str>, stops: &[(&str, i32)]) -> Trip {
+        Trip {
+            route_id: "1".to_string(),
+            trip_id: trip_id.to_string(),
+            shape_id: shape_id.map(str::to_string),
+            stop_times: stops
+                .iter()
+                .map(|&(stop_id, stop_sequence)| StopTime {
+                    trip_id: trip_id.to_string(),
+                    stop_id: stop_id.to_string(),
+                    stop_sequence,
+                    stop: stop(stop_id, Some((0.0, 0.0))),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reports_each_kind_of_issue() {
+        let mut gtfs = Gtfs::default();
+        for s in [stop("a", Some((1.0, 2.0))), stop("b", Some((1.0, 2.1)))] {
+            gtfs.stops.insert(s.stop_id.clone(), s);
+        }
+        let route = |route_id: &str| Route {
+            route_id: route_id.to_string(),
+            ..Default::default()
+        };
+        gtfs.routes.insert("1".to_string(), route("1"));
+        gtfs.shapes.insert("s1".to_string(), vec![Shape::default()]);
+        gtfs.trips.insert(
+            "1".to_string(),
+            vec![trip("t1", Some("s1"), &[("a", 1), ("b", 2)])],
+        );
+        assert!(validate(&gtfs).is_valid());
+
+        gtfs.stops.insert("c".to_string(), stop("c", None));
+        gtfs.routes.insert("2".to_string(), route("2"));
+        gtfs.trips.get_mut("1").unwrap().extend([
+            trip("t2", None, &[("a", 1), ("x", 2), ("x", 3)]),
+            trip("t3", Some("s9"), &[("b", 2), ("a", 2)]),
+            Trip {
+                direction_id: Some(2),
+                ..trip("t4", Some("s1"), &[("a", 1), ("b", 2)])
+            },
+        ]);
+        let report = validate(&gtfs);
+        assert!(!report.is_valid());
+        let found: Vec<(IssueKind, &str)> = report
+            .issues
+            .iter()
+            .map(|i| (i.kind, i.id.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (IssueKind::DanglingStopId, "x"),
+                (IssueKind::TripWithoutShape, "t2"),
+                (IssueKind::TripWithoutShape, "t3"),
+                (IssueKind::NonIncreasingStopSequence, "t3"),
+                (IssueKind::InvalidDirectionId, "t4"),
+                (IssueKind::StopWithoutCoordinates, "c"),
+                (IssueKind::RouteWithoutTrips, "2"),
+            ]
+        );
+        assert_eq!(report.counts[&IssueKind::TripWithoutShape], 2);
+    }
+}
@@ -0,0 +1,221 @@
+use geo::{BoundingRect, Closest, ClosestPoint, Contains, Distance, Haversine};
+use geo_types::{Geometry, MultiPolygon, Point};
+use rstar::{Envelope, AABB};
+use rusqlite::{params, Connection, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+};
+use wkt::Wkt;
+
+use crate::gtfs::gtfs::Gtfs;
+
+use super::{geo_util, import_report::ClipReport};
+
+/// Default distance in meters outside the boundary within which stops are still kept
+pub const DEFAULT_CLIP_BUFFER_M: f64 = 1000.0;
+
+/// Boundary polygon of a city used to clip regional GTFS feeds.
+///
+/// The boundary is read from the optional `boundary` table of the city database.
+/// Every row contributes one polygon (or multipolygon) to the boundary and the
+/// largest `buffer_m` among the rows is used as the clipping buffer.
+pub struct CityBoundary {
+    pub polygons: MultiPolygon<f64>,
+    pub buffer_m: f64,
+}
+
+impl CityBoundary {
+    /// Load the boundary from the city database
+    ///
+    /// # Parameters
+    /// - `dbname`: Path to the city database
+    ///
+    /// # Returns
+    /// The boundary, or `None` if the database does not configure one
+    pub fn load(dbname: &str) -> Result<Option<CityBoundary>> {
+        let conn = Connection::open(dbname)?;
+
+        let has_table: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'boundary'",
+            params![],
+            |row| row.get(0),
+        )?;
+        if !has_table {
+            return Ok(None);
+        }
+
+        let mut stmt = conn.prepare("SELECT geom, buffer_m FROM boundary")?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<f64>>(1)?))
+        })?;
+
+        let mut polygons = Vec::new();
+        let mut buffer_m: Option<f64> = None;
+        for row in rows {
+            let (wkt_str, row_buffer) = row?;
+            let geometry = Wkt::<f64>::from_str(&wkt_str)
+                .ok()
+                .and_then(|wkt| Geometry::try_from(wkt).ok());
+            match geometry {
+                Some(Geometry::Polygon(polygon)) => polygons.push(polygon),
+                Some(Geometry::MultiPolygon(multi)) => polygons.extend(multi.0),
+                _ => {
+                    log::warn!("Ignoring boundary geometry that is not a polygon");
+                    continue;
+                }
+            }
+            if let Some(b) = row_buffer {
+                buffer_m = Some(buffer_m.map_or(b, |cur| cur.max(b)));
+            }
+        }
+
+        if polygons.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(CityBoundary {
+            polygons: MultiPolygon::new(polygons),
+            buffer_m: buffer_m.unwrap_or(DEFAULT_CLIP_BUFFER_M),
+        }))
+    }
+
+    /// Determines if a point lies inside the boundary or within the buffer around it.
+    ///
+    /// # Parameters
+    /// - `lon`: Longitude in WGS84 coordinates.
+    /// - `lat`: Latitude in WGS84 coordinates.
+    ///
+    /// # Returns
+    /// `true` if the point should be kept.
+    pub fn contains(&self, lon: f64, lat: f64) -> bool {
+        let point = Point::new(lon, lat);
+        if self.polygons.contains(&point) {
+            return true;
+        }
+
+        // Cheap rejection using the bounding box grown by the buffer before
+        // computing the distance to the closest point of the boundary
+        if let Some(rect) = self.polygons.bounding_rect() {
+            let envelope = geo_util::compute_envelope(lat, lon, self.buffer_m);
+            let rect = AABB::from_corners(rect.min().x_y().into(), rect.max().x_y().into());
+            if !envelope.intersects(&rect) {
+                return false;
+            }
+        }
+
+        match self.polygons.closest_point(&point) {
+            Closest::Intersection(_) => true,
+            Closest::SinglePoint(closest) => Haversine::distance(point, closest) <= self.buffer_m,
+            Closest::Indeterminate => false,
+        }
+    }
+
+    /// Clips a GTFS feed to the boundary.
+    ///
+    /// Stops outside the buffered boundary are removed along with their stop times.
+    /// Trips left with fewer than two stops are removed, as are routes left without
+    /// trips and shapes no longer referenced by any trip. Stops without coordinates
+    /// are always kept.
+    ///
+    /// # Parameters
+    /// - `gtfs`: The feed to clip in place
+    ///
+    /// # Returns
+    /// A report of everything that was removed
+    pub fn clip_gtfs(&self, gtfs: &mut Gtfs) -> ClipReport {
+        let mut report = ClipReport {
+            buffer_m: self.buffer_m,
+            stops_before: gtfs.stops.len(),
+            trips_before: gtfs.trips.values().map(|t| t.len()).sum(),
+            routes_before: gtfs.routes.len(),
+            ..Default::default()
+        };
+
+        let dropped_stops: HashSet<String> = gtfs
+            .stops
+            .values()
+            .filter(|stop| match (stop.stop_lon, stop.stop_lat) {
+                (Some(lon), Some(lat)) => !self.contains(lon, lat),
+                _ => false,
+            })
+            .map(|stop| stop.stop_id.clone())
+            .collect();
+
+        if dropped_stops.is_empty() {
+            return report;
+        }
+
+        gtfs.stops.retain(|id, _| !dropped_stops.contains(id));
+        // Transfers and pathways must not reference removed stops, otherwise
+        // the feed can no longer be rebuilt from its raw form (e.g. from cache)
+        for stop in gtfs.stops.values_mut() {
+            let dangling = stop
+                .transfers
+                .iter()
+                .any(|t| dropped_stops.contains(&t.to_stop_id))
+                || stop
+                    .pathways
+                    .iter()
+                    .any(|p| dropped_stops.contains(&p.to_stop_id));
+            if dangling {
+                let stop = Arc::make_mut(stop);
+                stop.transfers
+                    .retain(|t| !dropped_stops.contains(&t.to_stop_id));
+                stop.pathways
+                    .retain(|p| !dropped_stops.contains(&p.to_stop_id));
+            }
+        }
+
+        for trips in gtfs.trips.values_mut() {
+            trips.retain_mut(|trip| {
+                let before = trip.stop_times.len();
+                trip.stop_times
+                    .retain(|st| !dropped_stops.contains(&st.stop_id));
+                if trip.stop_times.len() < 2 {
+                    report.dropped_trip_ids.push(trip.trip_id.clone());
+                    false
+                } else {
+                    if trip.stop_times.len() < before {
+                        report.trimmed_trip_ids.push(trip.trip_id.clone());
+                    }
+                    true
+                }
+            });
+        }
+
+        gtfs.trips.retain(|_, trips| !trips.is_empty());
+        let mut dropped_routes: Vec<String> = gtfs
+            .routes
+            .keys()
+            .filter(|id| !gtfs.trips.contains_key(*id))
+            .cloned()
+            .collect();
+        gtfs.routes.retain(|id, _| gtfs.trips.contains_key(id));
+
+        let used_shapes: HashSet<&String> = gtfs
+            .trips
+            .values()
+            .flatten()
+            .filter_map(|t| t.shape_id.as_ref())
+            .collect();
+        let shapes_before = gtfs.shapes.len();
+        let shapes: HashMap<_, _> = gtfs
+            .shapes
+            .drain()
+            .filter(|(id, _)| used_shapes.contains(id))
+            .collect();
+        report.dropped_shapes = shapes_before - shapes.len();
+        gtfs.shapes = shapes;
+
+        let mut dropped_stops: Vec<String> = dropped_stops.into_iter().collect();
+        dropped_stops.sort();
+        dropped_routes.sort();
+        report.dropped_stop_ids = dropped_stops;
+        report.dropped_route_ids = dropped_routes;
+        report.dropped_trip_ids.sort();
+        report.trimmed_trip_ids.sort();
+        report
+    }
+}
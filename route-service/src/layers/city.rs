@@ -1,34 +1,211 @@
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
-use std::time::Instant;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{OnceLock, RwLock},
+    time::Instant,
+};
 
-use crate::{gtfs::gtfs::Gtfs, opt::aco2::OptimizedTransitNetwork};
+use crate::{
+    gtfs::{gtfs::Gtfs, realtime::RealtimeObservations},
+    opt::{
+        aco2::OptimizedTransitNetwork,
+        audit::AuditEvent,
+        checkpoint::Checkpoint,
+        demand::DemandModelConfig,
+        network_diff::{KpiRecord, RunRecord},
+        resources,
+        scenario::{Scenario, ScenarioStore},
+        search::SearchConfig,
+    },
+};
 
 use super::{
-    error::Error, grid::GridNetwork, road_network::RoadNetwork, transit_network::TransitNetwork,
+    boundary::CityBoundary,
+    city_profile::CityProfile,
+    data_info::{DataInfo, FeedValidity, SourceFile, TransitBuild},
+    error::Error,
+    grid::{self, GridNetwork},
+    import_report::{DirectionSource, ImportReport},
+    memory::{self, MemoryMode, MemoryReport},
+    road_network::RoadNetwork,
+    skim::CachedSkim,
+    stations,
+    stop_infrastructure::StopInfrastructure,
+    transit_network::{self, TransitNetwork},
+    zone_coverage::ZoneCoverage,
 };
 
-const CITY_CACHE_DIR: &str = "city_cache";
+/// Directory the cached files of cities are kept in unless another one is set, see
+/// `City::set_cache_dir`
+pub const DEFAULT_CACHE_DIR: &str = "city_cache";
+
+static CACHE_DIRS: RwLock<CacheDirs> = RwLock::new(CacheDirs {
+    all: None,
+    cities: BTreeMap::new(),
+});
+
+/// Where the cached files of cities are kept, see `City::cache_dir`
+struct CacheDirs {
+    /// Directory of every city, `DEFAULT_CACHE_DIR` if unset
+    all: Option<String>,
+    /// Directories of the cities that were given one of their own, by city name
+    cities: BTreeMap<String, String>,
+}
 
 /// Struct representing a city with its GTFS, grid, road and transit networks.
 #[derive(Serialize, Deserialize)]
 pub struct City {
     pub name: String,
+    /// Slim copy of the feed with the representative trip of each route, see `full_gtfs`
     pub gtfs: Gtfs,
     pub grid: GridNetwork,
     pub road: RoadNetwork,
     pub transit: TransitNetwork,
+    /// What was excluded from the source data while loading the city
+    pub import_report: ImportReport,
+    /// Shelters and pads at existing stops
+    pub stop_infra: StopInfrastructure,
+    /// Size and density used to compare the city with other cities
+    pub profile: CityProfile,
+    /// IANA time zone of the GTFS agencies, which the time periods of the city are in
+    pub timezone: String,
+    /// When and how the transit network was built
+    pub transit_build: TransitBuild,
+    /// Search parameters of the optimizer, stored on their own so that changing them does not
+    /// invalidate the city cache
+    #[serde(skip)]
+    pub search: SearchConfig,
+    /// How the road and grid networks were loaded
+    #[serde(skip)]
+    pub memory_mode: MemoryMode,
+    /// Sources the full feed is read from when it is first needed
+    #[serde(skip)]
+    gtfs_path: String,
+    #[serde(skip)]
+    db_path: String,
+    #[serde(skip)]
+    full_gtfs: OnceLock<Gtfs>,
+    /// Headways and delays observed in the realtime feeds ingested since the city was loaded
+    #[serde(skip)]
+    pub realtime: RealtimeObservations,
+    /// Zones the routes of `transit` stop in, built when the city is loaded
+    #[serde(skip)]
+    pub zone_coverage: ZoneCoverage,
+}
+
+/// The parts of a city built from its GTFS feed, cached so that loading the city does not
+/// parse the feed again
+#[derive(Serialize, Deserialize)]
+struct CityCore {
+    /// Feed the core was built from, a core of another feed version is rebuilt
+    gtfs_path: String,
+    gtfs: Gtfs,
+    import_report: ImportReport,
+    timezone: String,
+    transit: TransitNetwork,
+    transit_build: TransitBuild,
 }
 
 impl City {
     /// Prints statistics about the city's data structures
     pub fn print_stats(&self) {
-        println!("City: {}", self.name);
+        println!("City: {} ({})", self.name, self.timezone);
         self.gtfs.print_stats();
         self.grid.print_stats();
         self.road.print_stats();
         self.transit.print_stats();
     }
 
+    /// The full GTFS feed, read from disk on first use
+    ///
+    /// The city only keeps a slim copy of the feed in `gtfs`. Exports that need every trip,
+    /// shape or fare use this instead.
+    pub fn full_gtfs(&self) -> Result<&Gtfs, Error> {
+        if let Some(gtfs) = self.full_gtfs.get() {
+            return Ok(gtfs);
+        }
+        let start = Instant::now();
+        let (gtfs, _) = City::load_gtfs(&self.gtfs_path, &self.db_path)?;
+        log::debug!(
+            "Full GTFS of {} loaded in {}ms",
+            self.name,
+            start.elapsed().as_millis()
+        );
+        Ok(self.full_gtfs.get_or_init(|| gtfs))
+    }
+
+    /// Values of a numeric column of the zone table of the city's database, by zone id
+    ///
+    /// # Returns
+    /// `None` if the zone table has no such column
+    pub fn zone_column(&self, column: &str) -> Result<Option<HashMap<u32, f64>>, Error> {
+        Ok(grid::read_zone_column(&self.db_path, column)?)
+    }
+
+    /// Add a GTFS-realtime snapshot of trip updates and vehicle positions to `realtime`
+    ///
+    /// Trips the snapshot gives no route for are looked up in the full feed.
+    ///
+    /// # Returns
+    /// The number of entities of the snapshot and how many of them have an unknown route
+    pub fn ingest_realtime(&mut self, bytes: &[u8]) -> Result<(usize, usize), Error> {
+        let feed = RealtimeObservations::decode(bytes)?;
+        let unresolved: HashSet<&str> = RealtimeObservations::trips_without_route(&feed)
+            .into_iter()
+            .collect();
+        let mut trip_routes = HashMap::new();
+        if !unresolved.is_empty() {
+            for (route_id, trips) in &self.full_gtfs()?.trips {
+                for trip in trips {
+                    if unresolved.contains(trip.trip_id.as_str()) {
+                        trip_routes.insert(trip.trip_id.clone(), route_id.clone());
+                    }
+                }
+            }
+        }
+        let unmatched = self.realtime.ingest(&feed, &trip_routes);
+        Ok((feed.entity.len(), unmatched))
+    }
+
+    /// Time zone of the city, UTC if the feed's time zone is not a known IANA name
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+
+    /// Zones the routes of a network stop in, from the index of the city's own network with
+    /// the routes that differ in `transit` reindexed
+    pub fn zone_coverage_of(&self, transit: &TransitNetwork) -> Cow<'_, ZoneCoverage> {
+        if self.zone_coverage.is_current(transit) {
+            return Cow::Borrowed(&self.zone_coverage);
+        }
+        let mut coverage = self.zone_coverage.clone();
+        coverage.sync(transit);
+        Cow::Owned(coverage)
+    }
+
+    /// Keep the cached files of every city without a directory of its own in `dir`
+    pub fn set_cache_dir(dir: &str) {
+        CACHE_DIRS.write().unwrap().all = Some(dir.to_string());
+    }
+
+    /// Keep the cached files of one city in `dir`, e.g. to run several instances of a city
+    /// side by side without sharing their caches and logs
+    pub fn set_city_cache_dir(city_name: &str, dir: &str) {
+        let mut dirs = CACHE_DIRS.write().unwrap();
+        dirs.cities.insert(city_name.to_string(), dir.to_string());
+    }
+
+    /// Directory the cached files of a city are kept in, see `set_cache_dir`
+    pub fn cache_dir(city_name: &str) -> String {
+        let dirs = CACHE_DIRS.read().unwrap();
+        match dirs.cities.get(city_name) {
+            Some(dir) => dir.clone(),
+            None => dirs.all.as_deref().unwrap_or(DEFAULT_CACHE_DIR).to_string(),
+        }
+    }
+
     /// Load a city from disk or generate from source data
     ///
     /// # Parameters
@@ -48,13 +225,17 @@ impl City {
         invalidate_cache: bool,
     ) -> Result<City, Error> {
         let start = Instant::now();
-        let cache_file = format!("{}/{}.cached", CITY_CACHE_DIR, name);
+        let cache_file = format!("{}/{}.cached", City::cache_dir(name), name);
         if invalidate_cache {
             log::debug!("Invalidating cache, deleting file {}", cache_file);
             std::fs::remove_file(&cache_file).ok();
         }
 
-        if let Ok(city) = City::load_cached(name) {
+        if let Ok(mut city) = City::load_cached(name) {
+            city.search = City::load_search_config(name)?;
+            city.gtfs_path = gtfs_path.to_string();
+            city.db_path = db_path.to_string();
+            city.zone_coverage = ZoneCoverage::for_network(&city.transit);
             log::debug!(
                 "Cache found for city: {} (loaded in {}ms)",
                 name,
@@ -65,7 +246,7 @@ impl City {
             log::debug!("Cache not found for city: {}", name);
 
             let gtfs_start = Instant::now();
-            let gtfs = Gtfs::from_path(gtfs_path)?;
+            let (gtfs, mut import_report) = City::load_gtfs(gtfs_path, db_path)?;
             log::debug!("GTFS loaded in {}ms", gtfs_start.elapsed().as_millis());
 
             let grid_start = Instant::now();
@@ -82,25 +263,49 @@ impl City {
                 road_start.elapsed().as_millis()
             );
 
+            let stop_infra = StopInfrastructure::load(db_path)?;
+            if !stop_infra.is_empty() {
+                log::debug!("Loaded infrastructure of {} stops", stop_infra.len());
+            }
+
+            let search = City::load_search_config(name)?;
             let transit_start = Instant::now();
-            let transit = TransitNetwork::from_gtfs(&gtfs, &road, &grid)?;
+            let transit = TransitNetwork::from_gtfs(&gtfs, &road, &grid, &search)?;
             log::debug!(
                 "Transit network built in {}ms",
                 transit_start.elapsed().as_millis()
             );
+            import_report.approximate_geometry = transit.approximate_geometry(&road);
+            import_report.dropped_from_network = transit.dropped_route_counts();
 
-            let city = City {
+            let profile = CityProfile::new(&grid, &transit);
+            let timezone = agency_timezone(&gtfs);
+            let transit_build = TransitBuild::now(&search);
+            let mut city = City {
                 name: name.to_string(),
-                gtfs,
+                gtfs: transit_network::slim_gtfs(&gtfs),
                 grid,
                 road,
                 transit,
+                import_report,
+                stop_infra,
+                profile,
+                timezone,
+                transit_build,
+                search,
+                memory_mode: MemoryMode::Standard,
+                gtfs_path: gtfs_path.to_string(),
+                db_path: db_path.to_string(),
+                full_gtfs: OnceLock::new(),
+                realtime: RealtimeObservations::default(),
+                zone_coverage: ZoneCoverage::default(),
             };
+            city.zone_coverage = ZoneCoverage::for_network(&city.transit);
 
             if set_cache {
                 let cache_start = Instant::now();
                 log::debug!("Setting cache for city: {}", name);
-                std::fs::create_dir_all(CITY_CACHE_DIR)?;
+                std::fs::create_dir_all(City::cache_dir(name))?;
                 bincode::serialize_into(std::fs::File::create(cache_file)?, &city)?;
                 log::debug!("City cached in {}ms", cache_start.elapsed().as_millis());
             }
@@ -114,6 +319,153 @@ impl City {
         }
     }
 
+    /// Load the GTFS feed, clip it to the city boundary configured in the database
+    /// and classify the direction of its routes
+    ///
+    /// # Parameters
+    /// - `gtfs_path`: The path to the GTFS data
+    /// - `db_path`: The path to the database
+    ///
+    /// # Returns
+    /// The clipped feed and the import report describing what was excluded
+    pub(crate) fn load_gtfs(gtfs_path: &str, db_path: &str) -> Result<(Gtfs, ImportReport), Error> {
+        let mut gtfs = Gtfs::from_path(gtfs_path)?;
+        let mut report = ImportReport::default();
+
+        if let Some(boundary) = CityBoundary::load(db_path)? {
+            let clip_start = Instant::now();
+            let clip = boundary.clip_gtfs(&mut gtfs);
+            log::debug!(
+                "GTFS clipped to city boundary in {}ms: dropped {} stops, {} trips, {} routes",
+                clip_start.elapsed().as_millis(),
+                clip.dropped_stop_ids.len(),
+                clip.dropped_trip_ids.len(),
+                clip.dropped_route_ids.len()
+            );
+            report.clipping = Some(clip);
+        } else {
+            log::debug!("No city boundary configured, skipping GTFS clipping");
+        }
+
+        report.station_stops = stations::resolve_station_stops(&mut gtfs);
+        if !report.station_stops.is_empty() {
+            log::debug!(
+                "Resolved {} station and boarding area stops",
+                report.station_stops.len()
+            );
+        }
+
+        report.directions = transit_network::classify_route_directions(&gtfs);
+        report.inferred_directions = report
+            .directions
+            .iter()
+            .filter(|d| d.source == DirectionSource::Geometry)
+            .count();
+        if report.inferred_directions > 0 {
+            log::info!(
+                "Inferred the directions of {} of {} routes from their trips",
+                report.inferred_directions,
+                report.directions.len()
+            );
+        }
+
+        Ok((gtfs, report))
+    }
+
+    /// Replace the city's GTFS feed and rebuild the transit network from it
+    ///
+    /// The grid and road networks are kept. The new feed is kept whole, and read again from
+    /// `gtfs_path` if it is ever dropped. The cached core is removed since it no longer
+    /// matches the feed the city is configured to load on startup.
+    ///
+    /// # Parameters
+    /// - `gtfs`: The new feed, as returned by `load_gtfs`
+    /// - `import_report`: The import report of the new feed
+    /// - `gtfs_path`: The path the new feed was loaded from
+    pub fn replace_gtfs(
+        &mut self,
+        gtfs: Gtfs,
+        mut import_report: ImportReport,
+        gtfs_path: &str,
+    ) -> Result<(), Error> {
+        let start = Instant::now();
+        let mut transit = TransitNetwork::from_gtfs(&gtfs, &self.road, &self.grid, &self.search)?;
+        transit.set_demand_model(self.transit.demand_model.clone(), &self.grid, &self.search);
+        log::debug!(
+            "Transit network rebuilt for {} in {}ms",
+            self.name,
+            start.elapsed().as_millis()
+        );
+
+        self.profile = CityProfile::new(&self.grid, &transit);
+        self.timezone = agency_timezone(&gtfs);
+        self.gtfs = transit_network::slim_gtfs(&gtfs);
+        self.full_gtfs = OnceLock::from(gtfs);
+        self.gtfs_path = gtfs_path.to_string();
+        import_report.approximate_geometry = transit.approximate_geometry(&self.road);
+        import_report.dropped_from_network = transit.dropped_route_counts();
+        self.transit = transit;
+        self.transit_build = TransitBuild::now(&self.search);
+        self.zone_coverage = ZoneCoverage::for_network(&self.transit);
+        self.import_report = import_report;
+
+        let core_cache_file = format!("{}/{}_core.cached", City::cache_dir(&self.name), self.name);
+        std::fs::remove_file(&core_cache_file).ok();
+        Ok(())
+    }
+
+    /// Memory used by the process and how much of the road and grid networks is kept in it
+    pub fn memory_report(&self) -> MemoryReport {
+        MemoryReport {
+            mode: self.memory_mode,
+            rss_kb: memory::current_rss_kb(),
+            max_rss_kb: resources::max_rss_kb(),
+            road: self.road.memory_stats(),
+            grid: self.grid.memory_stats(),
+        }
+    }
+
+    /// Versions of the feed and when the files the city is loaded from and cached in last
+    /// changed
+    pub fn data_info(&self) -> DataInfo {
+        let today = chrono::Utc::now().with_timezone(&self.tz()).date_naive();
+        let feeds: Vec<FeedValidity> = self
+            .gtfs
+            .feed_info
+            .iter()
+            .map(|info| FeedValidity::new(info, today))
+            .collect();
+        let caches = [
+            ".cached",
+            "_core.cached",
+            "_opt_transit.cached",
+            "_road.adj",
+        ]
+        .iter()
+        .map(|suffix| {
+            SourceFile::stat(&format!(
+                "{}/{}{}",
+                City::cache_dir(&self.name),
+                self.name,
+                suffix
+            ))
+        })
+        .filter(|file| file.exists)
+        .collect();
+        DataInfo {
+            city: self.name.clone(),
+            feed_expired: feeds
+                .iter()
+                .any(|f| f.days_until_end.is_some_and(|d| d < 0)),
+            feeds,
+            gtfs: SourceFile::stat(&self.gtfs_path),
+            db: SourceFile::stat(&self.db_path),
+            caches,
+            transit_build: self.transit_build.clone(),
+            search_changed: self.search != self.transit_build.search,
+        }
+    }
+
     /// Load a city from cache
     ///
     /// # Parameters
@@ -123,7 +475,7 @@ impl City {
     /// The cached city or an error if not found
     fn load_cached(name: &str) -> Result<City, Error> {
         let start = Instant::now();
-        let cache_file = format!("{}/{}.cached", CITY_CACHE_DIR, name);
+        let cache_file = format!("{}/{}.cached", City::cache_dir(name), name);
         if std::path::Path::new(&cache_file).exists() {
             let city: City = bincode::deserialize_from(std::fs::File::open(cache_file)?)?;
             log::debug!(
@@ -137,44 +489,49 @@ impl City {
         }
     }
 
-    /// Load a city with TransitNetwork from cache and other attributes loaded normally
+    /// Load a city with its GTFS-derived core from cache and other attributes loaded normally
+    ///
+    /// The core holds the slim feed, the import report and the transit network. When it is
+    /// cached the feed is not parsed until `full_gtfs` is called.
     ///
     /// # Parameters
     /// - `name`: The name of the city
     /// - `gtfs_path`: The path to the GTFS data
     /// - `db_path`: The path to the database
-    /// - `set_transit_cache`: Whether to cache the TransitNetwork if not found
-    /// - `invalidate_transit_cache`: Whether to invalidate the TransitNetwork cache
+    /// - `set_transit_cache`: Whether to cache the core if not found
+    /// - `invalidate_transit_cache`: Whether to invalidate the core cache
+    /// - `memory_mode`: Whether to load the road and grid networks in full or to map the road
+    ///   adjacency from the city cache and read demand on demand
     ///
     /// # Returns
-    /// A city with TransitNetwork loaded from cache if available
+    /// A city with its core loaded from cache if available
     pub fn load_with_cached_transit(
         name: &str,
         gtfs_path: &str,
         db_path: &str,
         set_transit_cache: bool,
         invalidate_transit_cache: bool,
+        memory_mode: MemoryMode,
     ) -> Result<City, Error> {
         let start = Instant::now();
-        let transit_cache_file = format!("{}/{}_transit.cached", CITY_CACHE_DIR, name);
+        let core_cache_file = format!("{}/{}_core.cached", City::cache_dir(name), name);
 
         if invalidate_transit_cache {
-            log::debug!(
-                "Invalidating transit cache, deleting file {}",
-                transit_cache_file
-            );
-            std::fs::remove_file(&transit_cache_file).ok();
+            log::debug!("Invalidating core cache, deleting file {}", core_cache_file);
+            std::fs::remove_file(&core_cache_file).ok();
         }
 
-        // Load GTFS, grid, and road networks normally
-        log::debug!("Loading GTFS from {}", gtfs_path);
-        let gtfs_start = Instant::now();
-        let gtfs = Gtfs::from_path(gtfs_path)?;
-        log::debug!("GTFS loaded in {}ms", gtfs_start.elapsed().as_millis());
-
-        log::debug!("Loading grid network from {}", db_path);
+        // Load grid and road networks normally
+        log::debug!(
+            "Loading grid network from {} ({:?} memory)",
+            db_path,
+            memory_mode
+        );
         let grid_start = Instant::now();
-        let grid = GridNetwork::load(db_path)?;
+        let grid = match memory_mode {
+            MemoryMode::Standard => GridNetwork::load(db_path)?,
+            MemoryMode::Low => GridNetwork::load_lazy(db_path)?,
+        };
         log::debug!(
             "Grid network loaded in {}ms",
             grid_start.elapsed().as_millis()
@@ -182,63 +539,147 @@ impl City {
 
         log::debug!("Loading road network from {}", db_path);
         let road_start = Instant::now();
-        let road = RoadNetwork::load(db_path)?;
+        let road = match memory_mode {
+            MemoryMode::Standard => RoadNetwork::load(db_path)?,
+            MemoryMode::Low => RoadNetwork::load_mapped(
+                db_path,
+                &format!("{}/{}_road.adj", City::cache_dir(name), name),
+            )?,
+        };
         log::debug!(
             "Road network loaded in {}ms",
             road_start.elapsed().as_millis()
         );
 
-        // Try to load TransitNetwork from cache
-        let transit_start = Instant::now();
-        let transit = if std::path::Path::new(&transit_cache_file).exists() {
-            log::debug!("Loading transit network from cache");
-            let transit = bincode::deserialize_from(std::fs::File::open(transit_cache_file)?)?;
-            log::debug!(
-                "Transit network loaded from cache in {}ms",
-                transit_start.elapsed().as_millis()
-            );
-            transit
-        } else {
-            log::debug!("Building transit network from GTFS");
-            let build_start = Instant::now();
-            let transit = TransitNetwork::from_gtfs(&gtfs, &road, &grid)?;
-            log::debug!(
-                "Transit network built in {}ms",
-                build_start.elapsed().as_millis()
-            );
+        let stop_infra = StopInfrastructure::load(db_path)?;
+        if !stop_infra.is_empty() {
+            log::debug!("Loaded infrastructure of {} stops", stop_infra.len());
+        }
 
-            if set_transit_cache {
-                let cache_start = Instant::now();
-                log::debug!("Caching transit network to {}", transit_cache_file);
-                std::fs::create_dir_all(CITY_CACHE_DIR)?;
-                bincode::serialize_into(std::fs::File::create(transit_cache_file)?, &transit)?;
+        let search = City::load_search_config(name)?;
+
+        // Try to load the core from cache
+        let core_start = Instant::now();
+        let mut core = match City::load_core(&core_cache_file, gtfs_path) {
+            Some(core) => {
+                log::debug!(
+                    "City core loaded from cache in {}ms",
+                    core_start.elapsed().as_millis()
+                );
+                core
+            }
+            None => {
+                log::debug!("Loading GTFS from {}", gtfs_path);
+                let gtfs_start = Instant::now();
+                let (gtfs, mut import_report) = City::load_gtfs(gtfs_path, db_path)?;
+                log::debug!("GTFS loaded in {}ms", gtfs_start.elapsed().as_millis());
+
+                log::debug!("Building transit network from GTFS");
+                let build_start = Instant::now();
+                let transit = TransitNetwork::from_gtfs(&gtfs, &road, &grid, &search)?;
                 log::debug!(
-                    "Transit network cached in {}ms",
-                    cache_start.elapsed().as_millis()
+                    "Transit network built in {}ms",
+                    build_start.elapsed().as_millis()
                 );
+                import_report.dropped_from_network = transit.dropped_route_counts();
+
+                let core = CityCore {
+                    gtfs_path: gtfs_path.to_string(),
+                    gtfs: transit_network::slim_gtfs(&gtfs),
+                    import_report,
+                    timezone: agency_timezone(&gtfs),
+                    transit,
+                    transit_build: TransitBuild::now(&search),
+                };
+                if set_transit_cache {
+                    City::save_core(&core_cache_file, &core)?;
+                }
+                core
             }
-            transit
         };
+        // the road network is loaded anew while the core may be cached, built against another
+        // version of it whose nodes some stops may no longer map to
+        core.import_report.approximate_geometry = core.transit.approximate_geometry(&road);
 
-        let city = City {
+        let profile = CityProfile::new(&grid, &core.transit);
+        let mut city = City {
             name: name.to_string(),
-            gtfs,
+            gtfs: core.gtfs,
             grid,
             road,
-            transit,
+            transit: core.transit,
+            import_report: core.import_report,
+            stop_infra,
+            profile,
+            timezone: core.timezone,
+            transit_build: core.transit_build,
+            search,
+            memory_mode,
+            gtfs_path: gtfs_path.to_string(),
+            db_path: db_path.to_string(),
+            full_gtfs: OnceLock::new(),
+            realtime: RealtimeObservations::default(),
+            zone_coverage: ZoneCoverage::default(),
         };
+        city.zone_coverage = ZoneCoverage::for_network(&city.transit);
 
         log::debug!(
-            "City {} loaded with cached transit in {}ms",
+            "City {} loaded with cached core in {}ms",
             name,
             start.elapsed().as_millis()
         );
         Ok(city)
     }
 
+    /// Load a cached core, `None` if there is none, it cannot be read or it was built from
+    /// another feed
+    fn load_core(core_cache_file: &str, gtfs_path: &str) -> Option<CityCore> {
+        if !std::path::Path::new(core_cache_file).exists() {
+            return None;
+        }
+        let file = std::fs::File::open(core_cache_file).ok()?;
+        let core: CityCore = match bincode::deserialize_from(std::io::BufReader::new(file)) {
+            Ok(core) => core,
+            Err(e) => {
+                log::warn!("Ignoring unreadable core cache {}: {}", core_cache_file, e);
+                return None;
+            }
+        };
+        if core.gtfs_path != gtfs_path {
+            log::debug!(
+                "Core cache was built from {}, not {}",
+                core.gtfs_path,
+                gtfs_path
+            );
+            return None;
+        }
+        Some(core)
+    }
+
+    fn save_core(core_cache_file: &str, core: &CityCore) -> Result<(), Error> {
+        let cache_start = Instant::now();
+        log::debug!("Caching city core to {}", core_cache_file);
+        if let Some(dir) = std::path::Path::new(core_cache_file).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        bincode::serialize_into(
+            std::io::BufWriter::new(std::fs::File::create(core_cache_file)?),
+            core,
+        )?;
+        log::debug!(
+            "City core cached in {}ms",
+            cache_start.elapsed().as_millis()
+        );
+        Ok(())
+    }
+
     /// Load transit network from cache
     pub fn load_opt_transit_from_cache(city_name: &str) -> Result<OptimizedTransitNetwork, Error> {
-        let transit_cache_file = format!("{}/{}_opt_transit.cached", CITY_CACHE_DIR, city_name);
+        let transit_cache_file = format!(
+            "{}/{}_opt_transit.cached",
+            City::cache_dir(city_name),
+            city_name
+        );
 
         if std::path::Path::new(&transit_cache_file).exists() {
             log::debug!("Loading transit network from cache");
@@ -253,20 +694,383 @@ impl City {
         city_name: &str,
         transit: &OptimizedTransitNetwork,
     ) -> Result<(), Error> {
-        let transit_cache_file = format!("{}/{}_opt_transit.cached", CITY_CACHE_DIR, city_name);
+        let transit_cache_file = format!(
+            "{}/{}_opt_transit.cached",
+            City::cache_dir(city_name),
+            city_name
+        );
         log::debug!("Caching transit network to {}", transit_cache_file);
-        std::fs::create_dir_all(CITY_CACHE_DIR).unwrap();
+        std::fs::create_dir_all(City::cache_dir(city_name)).unwrap();
         bincode::serialize_into(std::fs::File::create(transit_cache_file).unwrap(), transit)
             .unwrap();
         Ok(())
     }
 
-    pub fn save_transit_to_cache(city_name: &str, transit: &TransitNetwork) -> Result<(), Error> {
-        let transit_cache_file = format!("{}/{}_transit.cached", CITY_CACHE_DIR, city_name);
-        log::debug!("Caching transit network to {}", transit_cache_file);
-        std::fs::create_dir_all(CITY_CACHE_DIR).unwrap();
-        bincode::serialize_into(std::fs::File::create(transit_cache_file).unwrap(), transit)
-            .unwrap();
+    /// Cache a transit network in place of the city's own, along with the rest of its core
+    pub fn save_transit_to_cache(&self, transit: &TransitNetwork) -> Result<(), Error> {
+        let core_cache_file = format!("{}/{}_core.cached", City::cache_dir(&self.name), self.name);
+        let core = CityCore {
+            gtfs_path: self.gtfs_path.clone(),
+            gtfs: self.gtfs.clone(),
+            import_report: self.import_report.clone(),
+            timezone: self.timezone.clone(),
+            transit: transit.clone(),
+            transit_build: self.transit_build.clone(),
+        };
+        City::save_core(&core_cache_file, &core)
+    }
+
+    /// Save a scenario of a city, replacing a scenario with the same name
+    pub fn save_scenario(city_name: &str, scenario: &Scenario) -> Result<(), Error> {
+        if !Scenario::valid_name(&scenario.name) {
+            return Err(Error::Error(format!(
+                "Invalid scenario name {:?}",
+                scenario.name
+            )));
+        }
+        let scenario_dir = format!("{}/{}_scenarios", City::cache_dir(city_name), city_name);
+        let scenario_file = format!("{}/{}.cached", scenario_dir, scenario.name);
+        log::debug!("Saving scenario to {}", scenario_file);
+        std::fs::create_dir_all(&scenario_dir)?;
+        bincode::serialize_into(
+            std::io::BufWriter::new(std::fs::File::create(scenario_file)?),
+            scenario,
+        )?;
+        Ok(())
+    }
+
+    /// Open the scenario database of a city, kept next to its saved scenarios
+    pub fn scenario_store(city_name: &str) -> Result<ScenarioStore, Error> {
+        let scenario_dir = format!("{}/{}_scenarios", City::cache_dir(city_name), city_name);
+        std::fs::create_dir_all(&scenario_dir)?;
+        ScenarioStore::open(&format!("{}/scenarios.db", scenario_dir))
+    }
+
+    /// Load a saved scenario of a city
+    pub fn load_scenario(city_name: &str, name: &str) -> Result<Scenario, Error> {
+        if !Scenario::valid_name(name) {
+            return Err(Error::CacheNotFound);
+        }
+        let scenario_file = format!(
+            "{}/{}_scenarios/{}.cached",
+            City::cache_dir(city_name),
+            city_name,
+            name
+        );
+        if !std::path::Path::new(&scenario_file).exists() {
+            return Err(Error::CacheNotFound);
+        }
+        log::debug!("Loading scenario from {}", scenario_file);
+        let file = std::io::BufReader::new(std::fs::File::open(scenario_file)?);
+        Ok(bincode::deserialize_from(file)?)
+    }
+
+    /// Save a checkpoint of a long optimization of a city, replacing the previous one
+    ///
+    /// The checkpoint is written next to its file and moved in place, so that a crash while
+    /// saving leaves the previous checkpoint intact.
+    pub fn save_checkpoint(
+        city_name: &str,
+        name: &str,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), Error> {
+        if !Scenario::valid_name(name) {
+            return Err(Error::Error(format!("Invalid checkpoint name {:?}", name)));
+        }
+        let checkpoint_dir = format!("{}/{}_checkpoints", City::cache_dir(city_name), city_name);
+        let checkpoint_file = format!("{}/{}.cached", checkpoint_dir, name);
+        let partial_file = format!("{}.partial", checkpoint_file);
+        log::debug!("Saving checkpoint to {}", checkpoint_file);
+        std::fs::create_dir_all(&checkpoint_dir)?;
+        bincode::serialize_into(
+            std::io::BufWriter::new(std::fs::File::create(&partial_file)?),
+            checkpoint,
+        )?;
+        std::fs::rename(partial_file, checkpoint_file)?;
+        Ok(())
+    }
+
+    /// Load the checkpoint of a long optimization of a city
+    pub fn load_checkpoint(city_name: &str, name: &str) -> Result<Checkpoint, Error> {
+        if !Scenario::valid_name(name) {
+            return Err(Error::CacheNotFound);
+        }
+        let checkpoint_file = format!(
+            "{}/{}_checkpoints/{}.cached",
+            City::cache_dir(city_name),
+            city_name,
+            name
+        );
+        if !std::path::Path::new(&checkpoint_file).exists() {
+            return Err(Error::CacheNotFound);
+        }
+        log::debug!("Loading checkpoint from {}", checkpoint_file);
+        let file = std::io::BufReader::new(std::fs::File::open(checkpoint_file)?);
+        Ok(bincode::deserialize_from(file)?)
+    }
+
+    /// Remove the checkpoint of an optimization that finished, if there is one
+    pub fn remove_checkpoint(city_name: &str, name: &str) -> Result<(), Error> {
+        if !Scenario::valid_name(name) {
+            return Ok(());
+        }
+        let checkpoint_file = format!(
+            "{}/{}_checkpoints/{}.cached",
+            City::cache_dir(city_name),
+            city_name,
+            name
+        );
+        match std::fs::remove_file(checkpoint_file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Save the travel time matrix of a network of a city, replacing the previous one
+    ///
+    /// # Arguments
+    /// - `key`: Name of the network, `original`, `optimized` or `scenario-` followed by the
+    ///   name of a scenario
+    pub fn save_skim(city_name: &str, key: &str, skim: &CachedSkim) -> Result<(), Error> {
+        if !Scenario::valid_name(key.strip_prefix("scenario-").unwrap_or(key)) {
+            return Err(Error::Error(format!("Invalid skim name {:?}", key)));
+        }
+        let skim_dir = format!("{}/{}_skims", City::cache_dir(city_name), city_name);
+        let skim_file = format!("{}/{}.cached", skim_dir, key);
+        log::debug!("Saving travel time matrix to {}", skim_file);
+        std::fs::create_dir_all(&skim_dir)?;
+        bincode::serialize_into(
+            std::io::BufWriter::new(std::fs::File::create(skim_file)?),
+            skim,
+        )?;
         Ok(())
     }
+
+    /// Load the travel time matrix of a network of a city, see `save_skim`
+    pub fn load_skim(city_name: &str, key: &str) -> Result<CachedSkim, Error> {
+        if !Scenario::valid_name(key.strip_prefix("scenario-").unwrap_or(key)) {
+            return Err(Error::CacheNotFound);
+        }
+        let skim_file = format!(
+            "{}/{}_skims/{}.cached",
+            City::cache_dir(city_name),
+            city_name,
+            key
+        );
+        if !std::path::Path::new(&skim_file).exists() {
+            return Err(Error::CacheNotFound);
+        }
+        log::debug!("Loading travel time matrix from {}", skim_file);
+        let file = std::io::BufReader::new(std::fs::File::open(skim_file)?);
+        Ok(bincode::deserialize_from(file)?)
+    }
+
+    /// Load the search parameters of a city, or the defaults if none were saved
+    pub fn load_search_config(city_name: &str) -> Result<SearchConfig, Error> {
+        let config_file = format!("{}/{}_search.json", City::cache_dir(city_name), city_name);
+        if !std::path::Path::new(&config_file).exists() {
+            return Ok(SearchConfig::default());
+        }
+        log::debug!("Loading search parameters from {}", config_file);
+        let config: SearchConfig = serde_json::from_reader(std::fs::File::open(&config_file)?)?;
+        config
+            .validate()
+            .map_err(|e| Error::Error(format!("Invalid {}: {}", config_file, e)))?;
+        Ok(config)
+    }
+
+    pub fn save_search_config(city_name: &str, config: &SearchConfig) -> Result<(), Error> {
+        let config_file = format!("{}/{}_search.json", City::cache_dir(city_name), city_name);
+        log::debug!("Saving search parameters to {}", config_file);
+        std::fs::create_dir_all(City::cache_dir(city_name))?;
+        std::fs::write(config_file, serde_json::to_string_pretty(config)?)?;
+        Ok(())
+    }
+
+    /// Load the demand model of a city, or the defaults if none was saved
+    pub fn load_demand_model(city_name: &str) -> Result<DemandModelConfig, Error> {
+        let config_file = format!("{}/{}_demand.json", City::cache_dir(city_name), city_name);
+        if !std::path::Path::new(&config_file).exists() {
+            return Ok(DemandModelConfig::default());
+        }
+        log::debug!("Loading demand model from {}", config_file);
+        let config: DemandModelConfig =
+            serde_json::from_reader(std::fs::File::open(&config_file)?)?;
+        config
+            .validate()
+            .map_err(|e| Error::Error(format!("Invalid {}: {}", config_file, e)))?;
+        Ok(config)
+    }
+
+    pub fn save_demand_model(city_name: &str, config: &DemandModelConfig) -> Result<(), Error> {
+        let config_file = format!("{}/{}_demand.json", City::cache_dir(city_name), city_name);
+        log::debug!("Saving demand model to {}", config_file);
+        std::fs::create_dir_all(City::cache_dir(city_name))?;
+        std::fs::write(config_file, serde_json::to_string_pretty(config)?)?;
+        Ok(())
+    }
+
+    /// Record a finished optimization run at the end of the city's run history
+    pub fn append_run_history(city_name: &str, record: &RunRecord) -> Result<(), Error> {
+        use std::io::Write;
+
+        let history_file = format!("{}/{}_runs.jsonl", City::cache_dir(city_name), city_name);
+        std::fs::create_dir_all(City::cache_dir(city_name))?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(history_file)?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+        Ok(())
+    }
+
+    /// Load the optimization runs of a city, oldest first. Unreadable lines are skipped.
+    pub fn load_run_history(city_name: &str) -> Result<Vec<RunRecord>, Error> {
+        let history_file = format!("{}/{}_runs.jsonl", City::cache_dir(city_name), city_name);
+        if !std::path::Path::new(&history_file).exists() {
+            return Ok(vec![]);
+        }
+        Ok(std::fs::read_to_string(history_file)?
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// Record the KPIs of an evaluation at the end of the city's KPI history
+    pub fn append_kpi_history(city_name: &str, record: &KpiRecord) -> Result<(), Error> {
+        use std::io::Write;
+
+        let history_file = format!("{}/{}_kpis.jsonl", City::cache_dir(city_name), city_name);
+        std::fs::create_dir_all(City::cache_dir(city_name))?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(history_file)?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+        Ok(())
+    }
+
+    /// Load the KPI history of a city, oldest first. Unreadable lines are skipped.
+    pub fn load_kpi_history(city_name: &str) -> Result<Vec<KpiRecord>, Error> {
+        let history_file = format!("{}/{}_kpis.jsonl", City::cache_dir(city_name), city_name);
+        if !std::path::Path::new(&history_file).exists() {
+            return Ok(vec![]);
+        }
+        Ok(std::fs::read_to_string(history_file)?
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// Record a call that changed the city at the end of its audit log
+    pub fn append_audit_event(city_name: &str, event: &AuditEvent) -> Result<(), Error> {
+        use std::io::Write;
+
+        let audit_file = format!("{}/{}_audit.jsonl", City::cache_dir(city_name), city_name);
+        std::fs::create_dir_all(City::cache_dir(city_name))?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(audit_file)?;
+        writeln!(file, "{}", serde_json::to_string(event)?)?;
+        Ok(())
+    }
+
+    /// Load the audit log of a city, oldest first. Unreadable lines are skipped.
+    pub fn load_audit_log(city_name: &str) -> Result<Vec<AuditEvent>, Error> {
+        let audit_file = format!("{}/{}_audit.jsonl", City::cache_dir(city_name), city_name);
+        if !std::path::Path::new(&audit_file).exists() {
+            return Ok(vec![]);
+        }
+        Ok(std::fs::read_to_string(audit_file)?
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+}
+
+/// Time zone of the feed's agencies
+///
+/// GTFS requires every agency of a feed to share a time zone, the first one is used if they
+/// do not. Feeds without agencies or with an unknown time zone fall back to UTC.
+fn agency_timezone(gtfs: &Gtfs) -> String {
+    let Some(agency) = gtfs.agencies.first() else {
+        log::warn!("Feed has no agency, assuming UTC for time periods");
+        return Tz::UTC.name().to_string();
+    };
+    if gtfs
+        .agencies
+        .iter()
+        .any(|a| a.agency_timezone != agency.agency_timezone)
+    {
+        log::warn!(
+            "Agencies have different time zones, using {} of {}",
+            agency.agency_timezone,
+            agency.agency_name
+        );
+    }
+    match agency.agency_timezone.trim().parse::<Tz>() {
+        Ok(tz) => tz.name().to_string(),
+        Err(_) => {
+            log::warn!(
+                "Unknown agency time zone {:?}, assuming UTC for time periods",
+                agency.agency_timezone
+            );
+            Tz::UTC.name().to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::demo_city::{DemoCity, DemoCityConfig};
+
+    #[test]
+    fn cached_core_reports_stops_off_the_current_road_network() {
+        let demo = DemoCity::generate(&DemoCityConfig {
+            cols: 4,
+            rows: 4,
+            routes: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        let name = format!("city_core_test_{}", std::process::id());
+        let dir = std::env::temp_dir().join(&name);
+        City::set_city_cache_dir(&name, dir.join("cache").to_str().unwrap());
+        let (db_path, gtfs_dir) = (dir.join("demo.db"), dir.join("gtfs"));
+        let (db, gtfs) = (db_path.to_str().unwrap(), gtfs_dir.to_str().unwrap());
+        demo.write_db(db).unwrap();
+        demo.write_gtfs(gtfs).unwrap();
+        let load =
+            || City::load_with_cached_transit(&name, gtfs, db, true, false, MemoryMode::Standard);
+
+        // every stop of a network built against the road network maps to one of its nodes
+        let city = load().unwrap();
+        assert!(city.import_report.approximate_geometry.is_empty());
+
+        // the cached network is kept when the road network is replaced
+        let conn = rusqlite::Connection::open(db).unwrap();
+        conn.execute("UPDATE nodes SET osmid = osmid + 1000000", [])
+            .unwrap();
+        drop(conn);
+        let city = load().unwrap();
+        let mut approximate: Vec<&str> = city
+            .import_report
+            .approximate_geometry
+            .iter()
+            .map(|a| a.route_id.as_str())
+            .collect();
+        approximate.sort();
+        let mut route_ids: Vec<&str> = city
+            .transit
+            .routes
+            .iter()
+            .map(|r| r.route_id.as_str())
+            .collect();
+        route_ids.sort();
+        assert_eq!(approximate, route_ids);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
 }
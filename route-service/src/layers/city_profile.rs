@@ -0,0 +1,118 @@
+use geo::ChamberlainDuquetteArea;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use super::{geo_util, grid::GridNetwork, transit_network::TransitNetwork};
+
+/// Residents per km² above which a zone is considered dense enough to support frequent transit
+pub const TRANSIT_SUPPORTIVE_DENSITY: f64 = 2000.0;
+
+/// Size and density of a city, used to make metrics comparable between cities.
+///
+/// Computed once when the city is loaded and again when its transit network is rebuilt.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CityProfile {
+    /// Residents of the populated zones
+    pub population: f64,
+    /// Area of the populated zones in km²
+    pub area_km2: f64,
+    /// Residents per km² of populated area
+    pub density_per_km2: f64,
+    /// Share of residents, from 0 to 1, living in zones at or above `TRANSIT_SUPPORTIVE_DENSITY`
+    pub transit_supportive_share: f64,
+    pub route_count: usize,
+    pub stop_count: usize,
+    /// Straight-line length of the outbound direction of every route in km
+    pub route_km: f64,
+}
+
+impl CityProfile {
+    /// Compute the profile of a city
+    ///
+    /// # Parameters
+    /// - `grid`: Zones of the city with their population
+    /// - `transit`: Transit network of the city
+    pub fn new(grid: &GridNetwork, transit: &TransitNetwork) -> CityProfile {
+        let mut population = 0.0;
+        let mut area_km2 = 0.0;
+        let mut supportive_population = 0.0;
+        for zone in grid.graph.node_weights().filter(|z| z.valid_zone()) {
+            let zone_area = zone.polygon.chamberlain_duquette_unsigned_area() / 1_000_000.0;
+            let zone_population = zone.population as f64;
+            population += zone_population;
+            area_km2 += zone_area;
+            if zone_area > 0.0 && zone_population / zone_area >= TRANSIT_SUPPORTIVE_DENSITY {
+                supportive_population += zone_population;
+            }
+        }
+
+        let stop_count = transit
+            .routes
+            .iter()
+            .flat_map(|r| r.outbound_stops.iter().chain(r.inbound_stops.iter()))
+            .map(|s| s.stop_id.as_str())
+            .collect::<HashSet<_>>()
+            .len();
+        let route_km = transit
+            .routes
+            .iter()
+            .flat_map(|r| r.outbound_stops.windows(2))
+            .map(|w| {
+                geo_util::haversine(w[0].geom.x(), w[0].geom.y(), w[1].geom.x(), w[1].geom.y())
+            })
+            .sum::<f64>()
+            / 1000.0;
+
+        CityProfile {
+            population,
+            area_km2,
+            density_per_km2: ratio(population, area_km2),
+            transit_supportive_share: ratio(supportive_population, population),
+            route_count: transit.routes.len(),
+            stop_count,
+            route_km,
+        }
+    }
+
+    /// Express network metrics relative to the size and density of the city
+    ///
+    /// # Parameters
+    /// - `coverage`: Average route coverage, from 0 to 100
+    /// - `avg_ridership`: Average ridership per route
+    pub fn normalize(&self, coverage: f64, avg_ridership: f64) -> NormalizedMetrics {
+        let total_ridership = avg_ridership * self.route_count as f64;
+        NormalizedMetrics {
+            ridership_per_1000_residents: ratio(total_ridership * 1000.0, self.population),
+            ridership_per_km2: ratio(total_ridership, self.area_km2),
+            route_km_per_km2: ratio(self.route_km, self.area_km2),
+            stops_per_1000_residents: ratio(self.stop_count as f64 * 1000.0, self.population),
+            // a city without transit-supportive zones keeps its coverage as is
+            density_adjusted_coverage: if self.transit_supportive_share > 0.0 {
+                (coverage / self.transit_supportive_share).min(100.0)
+            } else {
+                coverage
+            },
+        }
+    }
+}
+
+/// Network metrics made comparable between cities of different size and density
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct NormalizedMetrics {
+    pub ridership_per_1000_residents: f64,
+    pub ridership_per_km2: f64,
+    pub route_km_per_km2: f64,
+    pub stops_per_1000_residents: f64,
+    /// Coverage relative to the share of residents living at transit-supportive density, since
+    /// a sprawling city cannot reasonably be covered as well as a dense one. The unadjusted
+    /// coverage if no resident lives at that density.
+    pub density_adjusted_coverage: f64,
+}
+
+fn ratio(num: f64, denom: f64) -> f64 {
+    if denom > 0.0 {
+        num / denom
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,156 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{path::Path, time::SystemTime};
+
+use crate::{gtfs::structs::FeedInfo, opt::search::SearchConfig};
+
+/// When and with which parameters the transit network of a city was built
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TransitBuild {
+    /// When the network was built from the feed in RFC 3339 format
+    pub built_at: String,
+    /// Search parameters the route evals of the network were computed with
+    pub search: SearchConfig,
+}
+
+impl TransitBuild {
+    /// A network built now
+    pub fn now(search: &SearchConfig) -> TransitBuild {
+        TransitBuild {
+            built_at: Utc::now().to_rfc3339(),
+            search: search.clone(),
+        }
+    }
+}
+
+/// Version and validity of a feed, from its `feed_info.txt`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FeedValidity {
+    pub publisher: String,
+    pub version: Option<String>,
+    /// First day of service in `YYYYMMDD` format
+    pub start_date: Option<String>,
+    /// Last day of service in `YYYYMMDD` format
+    pub end_date: Option<String>,
+    /// Days from today to `end_date`, negative once the feed expired
+    pub days_until_end: Option<i64>,
+}
+
+impl FeedValidity {
+    pub fn new(info: &FeedInfo, today: NaiveDate) -> FeedValidity {
+        let days_until_end = info
+            .feed_end_date
+            .as_deref()
+            .and_then(|d| NaiveDate::parse_from_str(d.trim(), "%Y%m%d").ok())
+            .map(|end| (end - today).num_days());
+        FeedValidity {
+            publisher: info.feed_publisher_name.clone(),
+            version: info.feed_version.clone(),
+            start_date: info.feed_start_date.clone(),
+            end_date: info.feed_end_date.clone(),
+            days_until_end,
+        }
+    }
+}
+
+/// A file the city is loaded from or cached in
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SourceFile {
+    pub path: String,
+    pub exists: bool,
+    /// Last modification in RFC 3339 format, of the most recently modified file for a
+    /// directory
+    pub modified: Option<String>,
+    /// Size of the file, or of the files of a directory
+    pub bytes: u64,
+}
+
+impl SourceFile {
+    pub fn stat(path: &str) -> SourceFile {
+        let (modified, bytes) = stat_path(Path::new(path));
+        SourceFile {
+            path: path.to_string(),
+            exists: Path::new(path).exists(),
+            modified: modified.map(|m| DateTime::<Utc>::from(m).to_rfc3339()),
+            bytes,
+        }
+    }
+}
+
+/// Latest modification time and total size of a file or of the files in a directory
+fn stat_path(path: &Path) -> (Option<SystemTime>, u64) {
+    let Ok(meta) = std::fs::metadata(path) else {
+        return (None, 0);
+    };
+    if !meta.is_dir() {
+        return (meta.modified().ok(), meta.len());
+    }
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return (meta.modified().ok(), 0);
+    };
+    entries
+        .flatten()
+        .map(|entry| stat_path(&entry.path()))
+        .fold((None, 0), |(modified, bytes), (m, b)| {
+            (modified.max(m), bytes + b)
+        })
+}
+
+/// Where the data of a city comes from and how recent it is, to tell whether results are
+/// based on a stale feed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DataInfo {
+    pub city: String,
+    /// Versions of the feed, one per `feed_info.txt` record
+    pub feeds: Vec<FeedValidity>,
+    /// Whether the service period of any feed ended before today
+    pub feed_expired: bool,
+    pub gtfs: SourceFile,
+    pub db: SourceFile,
+    /// Cache files of the city, the ones that do not exist are left out
+    pub caches: Vec<SourceFile>,
+    pub transit_build: TransitBuild,
+    /// Whether the search parameters changed since the transit network was built, in which
+    /// case the route evals of the network were computed with the old ones
+    pub search_changed: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feed_validity_and_directory_stats() {
+        let info = FeedInfo {
+            feed_publisher_name: "Transit".to_string(),
+            feed_publisher_url: String::new(),
+            feed_lang: "en".to_string(),
+            default_lang: None,
+            feed_start_date: Some("20250101".to_string()),
+            feed_end_date: Some("20250131".to_string()),
+            feed_version: Some("v1".to_string()),
+            feed_contact_email: None,
+            feed_contact_url: None,
+        };
+        let day = |d: &str| NaiveDate::parse_from_str(d, "%Y%m%d").unwrap();
+        assert_eq!(
+            FeedValidity::new(&info, day("20250121")).days_until_end,
+            Some(10)
+        );
+        assert_eq!(
+            FeedValidity::new(&info, day("20250201")).days_until_end,
+            Some(-1)
+        );
+
+        let dir = std::env::temp_dir().join(format!("data_info_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("stops.txt"), "abc").unwrap();
+        std::fs::write(dir.join("nested").join("trips.txt"), "de").unwrap();
+        let stat = SourceFile::stat(dir.to_str().unwrap());
+        let missing = SourceFile::stat(dir.join("missing").to_str().unwrap());
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(stat.exists && stat.modified.is_some());
+        assert_eq!(stat.bytes, 5);
+        assert!(!missing.exists && missing.modified.is_none());
+    }
+}
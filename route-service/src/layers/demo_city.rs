@@ -0,0 +1,857 @@
+use geo_types::{LineString, Point, Polygon};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rusqlite::{params, Connection};
+use std::{collections::HashMap, path::Path};
+use wkt::ToWkt;
+
+use crate::gtfs::{
+    raw_gtfs::GtfsDataSet,
+    structs::{format_gtfs_time, Agency, Calendar, Route, RouteType, Shape, Stop, StopTime, Trip},
+};
+use crate::opt::accessibility::AVG_BUS_SPEED_KMH;
+
+use super::{
+    city::City,
+    error::Error,
+    grid::{Link, TimePeriod, Zone},
+};
+
+/// Meters per degree of latitude, and of longitude at the equator
+const METERS_PER_DEGREE: f64 = 111_320.0;
+/// Share of residents starting a trip every hour of each period of `TimePeriod::ALL`, as in
+/// the gravity model of the data scripts
+const TRIPS_GENERATED: [f64; 5] = [0.01, 0.1, 0.03, 0.1, 0.02];
+/// Distance in km over which the pull of a destination falls by a factor of e
+const DEMAND_DECAY_KM: f64 = 2.0;
+/// Seconds a bus waits at each stop
+const DWELL_SECS: u32 = 20;
+/// Distance in meters between a stop and the intersection it serves, on the right side of
+/// the street
+const STOP_OFFSET_M: f64 = 10.0;
+const ROUTE_COLORS: [&str; 6] = ["E6194B", "3CB44B", "4363D8", "F58231", "911EB4", "42D4F4"];
+
+/// Shape of a synthetic city
+#[derive(Clone, Debug)]
+pub struct DemoCityConfig {
+    /// Zones from west to east
+    pub cols: usize,
+    /// Zones from south to north
+    pub rows: usize,
+    /// Side of a square zone in meters
+    pub zone_size_m: f64,
+    /// Distance in meters between two parallel streets
+    pub block_size_m: f64,
+    /// Approximate distance in meters between two stops of a route
+    pub stop_spacing_m: f64,
+    /// Bus routes, alternating between east-west and north-south streets
+    pub routes: usize,
+    pub center_lat: f64,
+    pub center_lon: f64,
+    /// Time zone of the transit agency, e.g. `America/Toronto`
+    pub timezone: String,
+    /// Seed of the random variation of population and jobs between zones
+    pub seed: u64,
+}
+
+impl Default for DemoCityConfig {
+    fn default() -> Self {
+        DemoCityConfig {
+            cols: 12,
+            rows: 12,
+            zone_size_m: 500.0,
+            block_size_m: 250.0,
+            stop_spacing_m: 500.0,
+            routes: 6,
+            center_lat: 43.6532,
+            center_lon: -79.3832,
+            timezone: "America/Toronto".to_string(),
+            seed: 42,
+        }
+    }
+}
+
+/// A synthetic city with the tables of a city database and a GTFS feed, so the whole stack
+/// can run without real data
+///
+/// Population and jobs peak downtown, demand between zones follows a gravity model, streets
+/// form a Manhattan grid and each bus route runs the length of one street in both directions.
+pub struct DemoCity {
+    pub zones: Vec<Zone>,
+    pub links: Vec<Link>,
+    /// Street intersections, the osmid of a node is its index plus 1
+    pub nodes: Vec<Point>,
+    /// One way street segments as the osmids of their ends
+    pub edges: Vec<(u64, u64)>,
+    pub gtfs: GtfsDataSet,
+}
+
+/// Converts meters east and north of the south-west corner of the city to longitude and
+/// latitude
+struct Projection {
+    min_lon: f64,
+    min_lat: f64,
+    meters_per_degree_lon: f64,
+}
+
+impl Projection {
+    fn point(&self, x: f64, y: f64) -> Point {
+        Point::new(
+            self.min_lon + x / self.meters_per_degree_lon,
+            self.min_lat + y / METERS_PER_DEGREE,
+        )
+    }
+}
+
+impl DemoCity {
+    /// Generate a city
+    ///
+    /// # Parameters
+    /// - `config`: Size and location of the city
+    ///
+    /// # Returns
+    /// The city, or an error if the configuration cannot produce one
+    pub fn generate(config: &DemoCityConfig) -> Result<DemoCity, Error> {
+        if config.cols < 2 || config.rows < 2 {
+            return Err(Error::Error("A city needs at least 2x2 zones".to_string()));
+        }
+        if config.zone_size_m.is_nan()
+            || config.zone_size_m <= 0.0
+            || config.block_size_m.is_nan()
+            || config.block_size_m <= 0.0
+            || config.stop_spacing_m.is_nan()
+            || config.stop_spacing_m <= 0.0
+        {
+            return Err(Error::Error(
+                "Zone size, block size and stop spacing must be positive".to_string(),
+            ));
+        }
+        if config.timezone.parse::<chrono_tz::Tz>().is_err() {
+            return Err(Error::Error(format!(
+                "Unknown time zone {}",
+                config.timezone
+            )));
+        }
+
+        let width = config.cols as f64 * config.zone_size_m;
+        let height = config.rows as f64 * config.zone_size_m;
+        let meters_per_degree_lon = METERS_PER_DEGREE * config.center_lat.to_radians().cos();
+        let projection = Projection {
+            min_lon: config.center_lon - width / 2.0 / meters_per_degree_lon,
+            min_lat: config.center_lat - height / 2.0 / METERS_PER_DEGREE,
+            meters_per_degree_lon,
+        };
+
+        let zones = generate_zones(config, &projection);
+        let links = gravity_demand(config, &zones);
+        let streets = StreetGrid::new(config, width, height);
+        let gtfs = streets.bus_routes(config, &projection)?;
+        let nodes = streets.nodes(&projection);
+        let edges = streets.edges();
+
+        Ok(DemoCity {
+            zones,
+            links,
+            nodes,
+            edges,
+            gtfs,
+        })
+    }
+
+    pub fn print_stats(&self) {
+        let count = |table: Option<usize>| table.unwrap_or_default();
+        println!("Demo city:");
+        println!("  Zones: {}", self.zones.len());
+        println!(
+            "  Population: {}",
+            self.zones.iter().map(|z| z.population).sum::<u32>()
+        );
+        println!("  Jobs: {}", self.zones.iter().map(|z| z.jobs).sum::<u32>());
+        println!("  Demand links: {}", self.links.len());
+        println!("  Road nodes: {}", self.nodes.len());
+        println!("  Road edges: {}", self.edges.len());
+        println!(
+            "  Routes: {}",
+            count(self.gtfs.routes.as_ref().ok().map(Vec::len))
+        );
+        println!(
+            "  Stops: {}",
+            count(self.gtfs.stops.as_ref().ok().map(Vec::len))
+        );
+        println!(
+            "  Trips: {}",
+            count(self.gtfs.trips.as_ref().ok().map(Vec::len))
+        );
+    }
+
+    /// Write the zones, demand and road network to a new city database, replacing the file
+    /// if it exists
+    pub fn write_db(&self, db_path: &str) -> Result<(), Error> {
+        if Path::new(db_path).exists() {
+            std::fs::remove_file(db_path)?;
+        }
+        if let Some(dir) = Path::new(db_path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut conn = Connection::open(db_path)?;
+        conn.execute_batch(
+            "
+CREATE TABLE nodes (fid INTEGER PRIMARY KEY, geom POINT, osmid INTEGER, y REAL, x REAL);
+CREATE TABLE edges (
+    fid INTEGER PRIMARY KEY, geom LINESTRING, u INTEGER, v INTEGER, key INTEGER, osmid INTEGER
+);
+CREATE TABLE zone (zoneid INTEGER PRIMARY KEY, geom POLYGON, population REAL, jobs REAL);
+CREATE TABLE demand (
+    origid INTEGER,
+    destid INTEGER,
+    volume REAL,
+    volume_morning REAL,
+    volume_am_rush REAL,
+    volume_mid_day REAL,
+    volume_pm_rush REAL,
+    volume_evening REAL
+);",
+        )?;
+
+        let tx = conn.transaction()?;
+        {
+            let mut insert =
+                tx.prepare("INSERT INTO nodes (fid, geom, osmid, y, x) VALUES (?, ?, ?, ?, ?)")?;
+            for (i, node) in self.nodes.iter().enumerate() {
+                let osmid = i as u64 + 1;
+                insert.execute(params![osmid, node.wkt_string(), osmid, node.y(), node.x()])?;
+            }
+
+            let mut insert = tx.prepare(
+                "INSERT INTO edges (fid, geom, u, v, key, osmid) VALUES (?, ?, ?, ?, 0, ?)",
+            )?;
+            for (i, (u, v)) in self.edges.iter().enumerate() {
+                let fid = i as u64 + 1;
+                let geom = LineString::from(vec![
+                    self.nodes[*u as usize - 1],
+                    self.nodes[*v as usize - 1],
+                ]);
+                insert.execute(params![fid, geom.wkt_string(), u, v, fid])?;
+            }
+
+            let mut insert = tx
+                .prepare("INSERT INTO zone (zoneid, geom, population, jobs) VALUES (?, ?, ?, ?)")?;
+            for zone in &self.zones {
+                insert.execute(params![
+                    zone.zoneid,
+                    zone.polygon.wkt_string(),
+                    zone.population,
+                    zone.jobs
+                ])?;
+            }
+
+            let mut insert = tx.prepare("INSERT INTO demand VALUES (?, ?, ?, ?, ?, ?, ?, ?)")?;
+            for link in &self.links {
+                insert.execute(params![
+                    link.origid,
+                    link.destid,
+                    link.weight,
+                    link.weight_by_time[&TimePeriod::Morning],
+                    link.weight_by_time[&TimePeriod::AmRush],
+                    link.weight_by_time[&TimePeriod::MidDay],
+                    link.weight_by_time[&TimePeriod::PmRush],
+                    link.weight_by_time[&TimePeriod::Evening],
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Write the GTFS feed as a directory of text files
+    pub fn write_gtfs(&self, gtfs_dir: &str) -> Result<(), Error> {
+        Ok(self.gtfs.write_to_dir(gtfs_dir)?)
+    }
+}
+
+/// Generate a city and load it like a real one, for tests and benchmarks
+///
+/// The database and feed are written to a temporary directory named after the city, removed
+/// once the city is loaded. The name should be unique to the process, e.g. end with its id,
+/// since test binaries run at the same time.
+///
+/// # Panics
+/// If the city cannot be generated, written or loaded
+#[allow(dead_code)] // the server binary compiles the modules too but only its tests call this
+pub fn load_demo_city(name: &str, config: &DemoCityConfig) -> City {
+    let demo = DemoCity::generate(config).expect("Failed to generate the demo city");
+    let dir = std::env::temp_dir().join(name);
+    let (db_path, gtfs_dir) = (dir.join("demo.db"), dir.join("gtfs"));
+    demo.write_db(db_path.to_str().unwrap())
+        .expect("Failed to write the demo city's database");
+    demo.write_gtfs(gtfs_dir.to_str().unwrap())
+        .expect("Failed to write the demo city's feed");
+    let city = City::load(
+        name,
+        gtfs_dir.to_str().unwrap(),
+        db_path.to_str().unwrap(),
+        false,
+        false,
+    );
+    std::fs::remove_dir_all(&dir).ok();
+    city.expect("Failed to load the demo city")
+}
+
+/// Square zones, most populated downtown and with jobs even more concentrated there. About
+/// one zone in twenty is a park without residents or jobs.
+fn generate_zones(config: &DemoCityConfig, projection: &Projection) -> Vec<Zone> {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let size = config.zone_size_m;
+    let (center_x, center_y) = (
+        config.cols as f64 * size / 2.0,
+        config.rows as f64 * size / 2.0,
+    );
+    let max_km = center_x.hypot(center_y) / 1000.0;
+
+    let mut zones = vec![];
+    for row in 0..config.rows {
+        for col in 0..config.cols {
+            let (x, y) = (col as f64 * size, row as f64 * size);
+            let corners = [
+                (x, y),
+                (x + size, y),
+                (x + size, y + size),
+                (x, y + size),
+                (x, y),
+            ];
+            let polygon = Polygon::new(
+                LineString::from(
+                    corners
+                        .iter()
+                        .map(|&(x, y)| projection.point(x, y))
+                        .collect::<Vec<_>>(),
+                ),
+                vec![],
+            );
+
+            let km = (x + size / 2.0 - center_x).hypot(y + size / 2.0 - center_y) / 1000.0;
+            let park = rng.gen_bool(0.05);
+            let population = 4000.0 * (-km / (0.5 * max_km)).exp() * rng.gen_range(0.7..1.3);
+            let jobs = 6000.0 * (-km / (0.25 * max_km)).exp() * rng.gen_range(0.5..1.5) + 50.0;
+            zones.push(Zone {
+                zoneid: (row * config.cols + col + 1) as u32,
+                polygon,
+                population: if park { 0 } else { population.round() as u32 },
+                jobs: if park { 0 } else { jobs.round() as u32 },
+            });
+        }
+    }
+    zones
+}
+
+/// Hourly trips between zones in each time period, produced by and attracted to zones in
+/// proportion to their residents and jobs and less likely the farther apart the zones are
+///
+/// Commuters leave home in the morning and go back in the afternoon, other trips start and
+/// end anywhere people live or work.
+fn gravity_demand(config: &DemoCityConfig, zones: &[Zone]) -> Vec<Link> {
+    let center = |zone: &Zone| {
+        let i = zone.zoneid as usize - 1;
+        (
+            (i % config.cols) as f64 * config.zone_size_m,
+            (i / config.cols) as f64 * config.zone_size_m,
+        )
+    };
+    let centers: Vec<(f64, f64)> = zones.iter().map(center).collect();
+    let km = |i: usize, j: usize| {
+        (centers[i].0 - centers[j].0).hypot(centers[i].1 - centers[j].1) / 1000.0
+    };
+    let total_population: f64 = zones.iter().map(|z| z.population as f64).sum();
+
+    let mut volumes: HashMap<(usize, usize), HashMap<TimePeriod, f64>> = HashMap::new();
+    for (period, trips_per_hour) in TimePeriod::ALL.iter().zip(TRIPS_GENERATED) {
+        let residents = |z: &Zone| z.population as f64;
+        let workers = |z: &Zone| z.jobs as f64;
+        let anyone = |z: &Zone| (z.population + z.jobs) as f64;
+        let (production, attraction): (Vec<f64>, Vec<f64>) = zones
+            .iter()
+            .map(|z| match period {
+                TimePeriod::Morning | TimePeriod::AmRush => (residents(z), workers(z)),
+                TimePeriod::PmRush => (workers(z), residents(z)),
+                TimePeriod::MidDay | TimePeriod::Evening => (anyone(z), anyone(z)),
+            })
+            .unzip();
+        let total_production: f64 = production.iter().sum();
+        if total_production <= 0.0 {
+            continue;
+        }
+
+        for (i, produced) in production.iter().enumerate() {
+            let trips = total_population * trips_per_hour * produced / total_production;
+            let pull: Vec<f64> = attraction
+                .iter()
+                .enumerate()
+                .map(|(j, attracted)| {
+                    if i == j {
+                        0.0
+                    } else {
+                        attracted * (-km(i, j) / DEMAND_DECAY_KM).exp()
+                    }
+                })
+                .collect();
+            let total_pull: f64 = pull.iter().sum();
+            if trips <= 0.0 || total_pull <= 0.0 {
+                continue;
+            }
+            for (j, pull) in pull.iter().enumerate().filter(|(_, p)| **p > 0.0) {
+                *volumes
+                    .entry((i, j))
+                    .or_default()
+                    .entry(period.clone())
+                    .or_default() += trips * pull / total_pull;
+            }
+        }
+    }
+
+    // the evaluation expects a link between every pair of zones, including a zone and itself,
+    // even without demand
+    let mut links = vec![];
+    for i in 0..zones.len() {
+        for j in 0..zones.len() {
+            let mut by_period = volumes.remove(&(i, j)).unwrap_or_default();
+            for period in TimePeriod::ALL {
+                by_period.entry(period).or_default();
+            }
+            let daily: f64 = by_period
+                .iter()
+                .map(|(period, hourly)| {
+                    let (start, end) = period.local_bounds();
+                    hourly * (end - start) as f64 / 3600.0
+                })
+                .sum();
+            links.push(Link {
+                origid: zones[i].zoneid,
+                destid: zones[j].zoneid,
+                weight: daily,
+                weight_by_time: by_period,
+            });
+        }
+    }
+    links
+}
+
+/// Streets of a Manhattan grid, in meters east and north of the south-west corner
+struct StreetGrid {
+    /// Blocks from west to east and from south to north
+    nx: usize,
+    ny: usize,
+    /// Size of a block in meters
+    dx: f64,
+    dy: f64,
+}
+
+impl StreetGrid {
+    fn new(config: &DemoCityConfig, width: f64, height: f64) -> StreetGrid {
+        let nx = ((width / config.block_size_m).round() as usize).max(1);
+        let ny = ((height / config.block_size_m).round() as usize).max(1);
+        StreetGrid {
+            nx,
+            ny,
+            dx: width / nx as f64,
+            dy: height / ny as f64,
+        }
+    }
+
+    fn osmid(&self, i: usize, j: usize) -> u64 {
+        (j * (self.nx + 1) + i + 1) as u64
+    }
+
+    fn nodes(&self, projection: &Projection) -> Vec<Point> {
+        (0..=self.ny)
+            .flat_map(|j| (0..=self.nx).map(move |i| (i, j)))
+            .map(|(i, j)| projection.point(i as f64 * self.dx, j as f64 * self.dy))
+            .collect()
+    }
+
+    /// Every street segment in both directions
+    fn edges(&self) -> Vec<(u64, u64)> {
+        let mut edges = vec![];
+        for j in 0..=self.ny {
+            for i in 0..=self.nx {
+                let node = self.osmid(i, j);
+                if i < self.nx {
+                    let east = self.osmid(i + 1, j);
+                    edges.extend([(node, east), (east, node)]);
+                }
+                if j < self.ny {
+                    let north = self.osmid(i, j + 1);
+                    edges.extend([(node, north), (north, node)]);
+                }
+            }
+        }
+        edges
+    }
+
+    /// Bus routes along evenly spread inner streets, with a stop on the right side of the
+    /// street every few intersections and weekday trips from 5:00 to 22:00, more frequent
+    /// during the rush hours
+    fn bus_routes(
+        &self,
+        config: &DemoCityConfig,
+        projection: &Projection,
+    ) -> Result<GtfsDataSet, Error> {
+        let east_west = config.routes.div_ceil(2);
+        let north_south = config.routes / 2;
+        if config.routes == 0 || east_west >= self.ny || north_south >= self.nx {
+            return Err(Error::Error(format!(
+                "{} routes need a grid of at least {}x{} blocks, the city has {}x{}",
+                config.routes,
+                north_south + 1,
+                east_west + 1,
+                self.nx,
+                self.ny
+            )));
+        }
+        let spread = |k: usize, n: usize, blocks: usize| {
+            ((k + 1) as f64 * blocks as f64 / (n + 1) as f64).round() as usize
+        };
+
+        let mut stops: HashMap<String, Stop> = HashMap::new();
+        let (mut routes, mut trips, mut stop_times, mut shapes) = (vec![], vec![], vec![], vec![]);
+        for k in 0..config.routes {
+            // intersections along the street as grid coordinates, and the street's name
+            let (line, street): (Vec<(usize, usize)>, String) = if k % 2 == 0 {
+                let j = spread(k / 2, east_west, self.ny);
+                (
+                    (0..=self.nx).map(|i| (i, j)).collect(),
+                    street_name(j, false),
+                )
+            } else {
+                let i = spread(k / 2, north_south, self.nx);
+                (
+                    (0..=self.ny).map(|j| (i, j)).collect(),
+                    street_name(i, true),
+                )
+            };
+            let block = if k % 2 == 0 { self.dx } else { self.dy };
+            let stride = ((config.stop_spacing_m / block).round() as usize).max(1);
+            let mut served: Vec<(usize, usize)> = line.iter().copied().step_by(stride).collect();
+            if served.last() != line.last() {
+                served.push(*line.last().unwrap());
+            }
+
+            let route_id = (k + 1).to_string();
+            routes.push(Route {
+                route_id: route_id.clone(),
+                agency_id: Some("demo".to_string()),
+                route_short_name: Some(route_id.clone()),
+                route_long_name: Some(format!("{} Crosstown", street)),
+                route_type: RouteType::Bus,
+                route_color: Some(ROUTE_COLORS[k % ROUTE_COLORS.len()].to_string()),
+                route_text_color: Some("FFFFFF".to_string()),
+                ..Default::default()
+            });
+
+            for direction in 0..2 {
+                let mut nodes = served.clone();
+                if direction == 1 {
+                    nodes.reverse();
+                }
+                let (first, last) = (nodes[0], nodes[nodes.len() - 1]);
+                let heading = (
+                    (last.0 as i64 - first.0 as i64).signum() as f64,
+                    (last.1 as i64 - first.1 as i64).signum() as f64,
+                );
+                let suffix = match heading {
+                    (x, _) if x > 0.0 => "E",
+                    (x, _) if x < 0.0 => "W",
+                    (_, y) if y > 0.0 => "N",
+                    _ => "S",
+                };
+
+                // stops sit on the right of the direction of travel
+                let positions: Vec<(f64, f64)> = nodes
+                    .iter()
+                    .map(|&(i, j)| {
+                        (
+                            i as f64 * self.dx + heading.1 * STOP_OFFSET_M,
+                            j as f64 * self.dy - heading.0 * STOP_OFFSET_M,
+                        )
+                    })
+                    .collect();
+                let stop_ids: Vec<String> = nodes
+                    .iter()
+                    .map(|&(i, j)| format!("{}{}", self.osmid(i, j), suffix))
+                    .collect();
+                for ((&(i, j), &(x, y)), stop_id) in nodes.iter().zip(&positions).zip(&stop_ids) {
+                    let point = projection.point(x, y);
+                    stops.entry(stop_id.clone()).or_insert_with(|| Stop {
+                        stop_id: stop_id.clone(),
+                        stop_name: Some(format!(
+                            "{} & {}",
+                            street_name(j, false),
+                            street_name(i, true)
+                        )),
+                        stop_lat: Some(point.y()),
+                        stop_lon: Some(point.x()),
+                        ..Default::default()
+                    });
+                }
+
+                let shape_id = format!("{}_{}", route_id, direction);
+                shapes.extend(positions.iter().enumerate().map(|(seq, &(x, y))| {
+                    let point = projection.point(x, y);
+                    Shape {
+                        shape_id: shape_id.clone(),
+                        shape_pt_lat: point.y(),
+                        shape_pt_lon: point.x(),
+                        shape_pt_sequence: seq as i32 + 1,
+                        shape_dist_traveled: None,
+                    }
+                }));
+
+                // seconds from the first stop to each stop
+                let mut offsets = vec![0];
+                for w in positions.windows(2) {
+                    let meters = (w[1].0 - w[0].0).hypot(w[1].1 - w[0].1);
+                    let ride = (meters / (AVG_BUS_SPEED_KMH / 3.6)).round() as u32;
+                    offsets.push(offsets[offsets.len() - 1] + ride + DWELL_SECS);
+                }
+
+                let mut departure = 5 * 3600;
+                while let Some(period) = TimePeriod::from_local_seconds(departure) {
+                    let trip_id = format!(
+                        "{}_{}_{}",
+                        route_id,
+                        direction,
+                        format_gtfs_time(departure).replace(':', "")
+                    );
+                    trips.push(Trip {
+                        route_id: route_id.clone(),
+                        service_id: "weekday".to_string(),
+                        trip_id: trip_id.clone(),
+                        trip_headsign: Some(street_end(&stops[&stop_ids[stop_ids.len() - 1]])),
+                        direction_id: Some(direction),
+                        shape_id: Some(shape_id.clone()),
+                        ..Default::default()
+                    });
+                    for (seq, (stop_id, offset)) in stop_ids.iter().zip(&offsets).enumerate() {
+                        let time = Some(format_gtfs_time(departure + offset));
+                        stop_times.push(StopTime {
+                            trip_id: trip_id.clone(),
+                            arrival_time: time.clone(),
+                            departure_time: time,
+                            stop_id: stop_id.clone(),
+                            stop_sequence: seq as i32 + 1,
+                            ..Default::default()
+                        });
+                    }
+                    departure += match period {
+                        TimePeriod::AmRush | TimePeriod::PmRush => 600,
+                        TimePeriod::MidDay => 900,
+                        TimePeriod::Morning | TimePeriod::Evening => 1200,
+                    };
+                }
+            }
+        }
+
+        let mut stops: Vec<Stop> = stops.into_values().collect();
+        stops.sort_by(|a, b| a.stop_id.cmp(&b.stop_id));
+        Ok(GtfsDataSet {
+            agencies: Ok(vec![Agency {
+                agency_id: Some("demo".to_string()),
+                agency_name: "Demo Transit".to_string(),
+                agency_url: "https://example.com".to_string(),
+                agency_timezone: config.timezone.clone(),
+                agency_lang: Some("en".to_string()),
+                agency_phone: None,
+                agency_fare_url: None,
+                agency_email: None,
+            }]),
+            stops: Ok(stops),
+            routes: Ok(routes),
+            trips: Ok(trips),
+            stop_times: Ok(stop_times),
+            calendar: Some(Ok(vec![Calendar {
+                service_id: "weekday".to_string(),
+                monday: 1,
+                tuesday: 1,
+                wednesday: 1,
+                thursday: 1,
+                friday: 1,
+                saturday: 0,
+                sunday: 0,
+                start_date: "20250101".to_string(),
+                end_date: "20301231".to_string(),
+            }])),
+            calendar_dates: None,
+            shapes: Some(Ok(shapes)),
+            fare_attributes: None,
+            fare_rules: None,
+            frequencies: None,
+            transfers: None,
+            pathways: None,
+            feed_info: None,
+            translations: None,
+        })
+    }
+}
+
+/// Name of the `n`th street from the south or, for avenues, from the west
+fn street_name(n: usize, avenue: bool) -> String {
+    format!("{} {}", n + 1, if avenue { "Ave" } else { "St" })
+}
+
+/// Headsign of a trip ending at a stop, the cross street of its last stop
+fn street_end(stop: &Stop) -> String {
+    stop.stop_name
+        .as_deref()
+        .and_then(|name| name.split(" & ").nth(1))
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::{
+        grid::GridNetwork,
+        import_report::{DropReason, DroppedRoute},
+        road_network::RoadNetwork,
+        transit_network::TransitNetwork,
+    };
+    use crate::opt::eval::TransitRouteEvals;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn demo_city_loads() {
+        let city = load_demo_city(
+            &format!("demo_city_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 4,
+                rows: 4,
+                routes: 3,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(city.grid.graph.node_count(), 16);
+        assert!(city.grid.graph.edge_count() > 0);
+        assert_eq!(city.transit.routes.len(), 3);
+        for route in &city.transit.routes {
+            assert!(route.outbound_stops.len() >= 2);
+            assert_eq!(route.outbound_stops.len(), route.inbound_stops.len());
+        }
+        assert_eq!(city.timezone, "America/Toronto");
+        assert!(city.transit.dropped_routes.is_empty());
+
+        // routes without trips or without a trip visiting two stops are reported, not built
+        let mut gtfs = city.gtfs.clone();
+        let template = gtfs.routes.values().next().unwrap().clone();
+        let mut stub_trip = gtfs.trips[&template.route_id][0].clone();
+        stub_trip.stop_times.truncate(1);
+        for route_id in ["ghost", "stub"] {
+            let route = Route {
+                route_id: route_id.to_string(),
+                ..template.clone()
+            };
+            gtfs.routes.insert(route_id.to_string(), route);
+        }
+        gtfs.trips.insert("stub".to_string(), vec![stub_trip]);
+        let transit =
+            TransitNetwork::from_gtfs(&gtfs, &city.road, &city.grid, &city.search).unwrap();
+        assert_eq!(transit.routes.len(), 3);
+        assert_eq!(
+            transit.dropped_routes,
+            vec![
+                DroppedRoute {
+                    route_id: "ghost".to_string(),
+                    reason: DropReason::NoTrips,
+                    trips: 0,
+                },
+                DroppedRoute {
+                    route_id: "stub".to_string(),
+                    reason: DropReason::TooFewStops,
+                    trips: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            transit.dropped_route_counts(),
+            BTreeMap::from([(DropReason::NoTrips, 1), (DropReason::TooFewStops, 1)])
+        );
+
+        // the evaluation needs demand between every pair of zones
+        for route in &city.transit.routes {
+            let evals =
+                TransitRouteEvals::for_route(&city.transit, route, &city.grid, &city.search);
+            assert!(evals.avg_ridership > 0.0);
+        }
+    }
+
+    #[test]
+    fn low_memory_networks_match_standard_ones() {
+        let demo = DemoCity::generate(&DemoCityConfig {
+            cols: 4,
+            rows: 4,
+            routes: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("demo_city_low_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("demo.db");
+        let db = db_path.to_str().unwrap();
+        demo.write_db(db).unwrap();
+        let adjacency_path = dir.join("demo_road.adj");
+        let adjacency = adjacency_path.to_str().unwrap();
+
+        let (grid, lazy_grid) = (
+            GridNetwork::load(db).unwrap(),
+            GridNetwork::load_lazy(db).unwrap(),
+        );
+        let road = RoadNetwork::load(db).unwrap();
+        let mapped_road = RoadNetwork::load_mapped(db, adjacency).unwrap();
+        // the second load maps the file written by the first
+        let written = std::fs::metadata(adjacency).unwrap().modified().unwrap();
+        let remapped_road = RoadNetwork::load_mapped(db, adjacency).unwrap();
+        let rewritten = std::fs::metadata(adjacency).unwrap().modified().unwrap();
+
+        let zones: Vec<_> = grid.graph.node_indices().collect();
+        for &from in &zones {
+            for &to in &zones {
+                assert_eq!(
+                    grid.demand_between_zones(from, to),
+                    lazy_grid.demand_between_zones(from, to)
+                );
+            }
+            let ((out, into), (lazy_out, lazy_into)) =
+                (grid.demand_out_in(from), lazy_grid.demand_out_in(from));
+            assert!((out - lazy_out).abs() < 1e-9 && (into - lazy_into).abs() < 1e-9);
+        }
+        assert_eq!(
+            grid.desire_lines(None, 0.0),
+            lazy_grid.desire_lines(None, 0.0)
+        );
+        let stats = lazy_grid.memory_stats();
+        let lazy = stats.lazy_links.unwrap();
+        assert_eq!(stats.links_in_memory, 0);
+        assert_eq!(lazy.misses, zones.len() as u64);
+        assert!(lazy.hits > 0);
+
+        let nodes: Vec<_> = (1..=demo.nodes.len() as u64)
+            .map(|osmid| road.get_node_index_by_osmid(osmid).unwrap())
+            .collect();
+        for (&from, &to) in nodes.iter().zip(nodes.iter().rev()) {
+            let (meters, path) = road.get_road_distance(from, to);
+            for mapped in [&mapped_road, &remapped_road] {
+                let (mapped_meters, mapped_path) = mapped.get_road_distance(from, to);
+                assert!(
+                    (meters - mapped_meters).abs() < 0.01,
+                    "{} {}",
+                    meters,
+                    mapped_meters
+                );
+                assert_eq!(path.len(), mapped_path.len());
+            }
+        }
+        assert_eq!(mapped_road.edge_count(), road.edge_count());
+        assert_eq!(mapped_road.memory_stats().edges_in_memory, 0);
+        assert_eq!(written, rewritten);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -50,6 +50,51 @@ pub fn compute_envelope_rect(
     AABB::from_corners([min_lon, min_lat], [max_lon, max_lat])
 }
 
+/// Computes the angle between the bearings of two segments.
+///
+/// # Parameters
+/// - `a1`, `b1`: Start and end of the first segment.
+/// - `a2`, `b2`: Start and end of the second segment.
+///
+/// # Returns
+/// The absolute difference between the two bearings in degrees, in the range 0 to 180.
+pub fn bearing_difference(a1: Point, b1: Point, a2: Point, b2: Point) -> f64 {
+    let diff = (Geodesic::bearing(a1, b1) - Geodesic::bearing(a2, b2)).abs() % 360.0;
+    if diff > 180.0 {
+        360.0 - diff
+    } else {
+        diff
+    }
+}
+
+/// Computes the signed perpendicular offset of a point from the line through `a` and `b`.
+///
+/// # Parameters
+/// - `a`: Start of the directed segment.
+/// - `b`: End of the directed segment.
+/// - `p`: Point to measure.
+///
+/// # Returns
+/// The offset in meters, positive if `p` lies to the left of the direction of travel from
+/// `a` to `b` and negative if it lies to the right. Uses an equirectangular approximation,
+/// which is accurate at street scale.
+pub fn signed_offset(a: Point, b: Point, p: Point) -> f64 {
+    let lon_scale = LONGITUDE_DEGREE_METERS * a.y().to_radians().cos();
+    let (dx, dy) = (
+        (b.x() - a.x()) * lon_scale,
+        (b.y() - a.y()) * LATITUDE_DEGREE_METERS,
+    );
+    let (px, py) = (
+        (p.x() - a.x()) * lon_scale,
+        (p.y() - a.y()) * LATITUDE_DEGREE_METERS,
+    );
+    let len = (dx * dx + dy * dy).sqrt();
+    if len == 0.0 {
+        return 0.0;
+    }
+    (dx * py - dy * px) / len
+}
+
 /// Determines if the bearing from point `a` to point `b` is north-easterly.
 ///
 /// # Parameters
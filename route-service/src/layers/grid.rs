@@ -1,11 +1,27 @@
-use geo_types::Polygon;
-use petgraph::{graph::NodeIndex, Directed, Graph};
+use chrono::{DateTime, Timelike, Utc};
+use chrono_tz::Tz;
+use geo::Contains;
+use geo_types::{Point, Polygon};
+use lru::LruCache;
+use petgraph::{graph::NodeIndex, visit::EdgeRef, Directed, Direction, Graph};
 use rstar::{RTree, RTreeObject, AABB};
-use rusqlite::{params, Connection, Result};
+use rusqlite::{params, Connection, Result, ToSql};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, str::FromStr};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    num::NonZeroUsize,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 use wkt::Wkt;
 
+use super::memory::{GridMemoryStats, LazyLinkStats};
+use crate::ordering;
+
 // Layer 1 - Data structure describing grid network and O-D matrix data
 #[derive(Deserialize, Serialize)]
 pub struct GridNetwork {
@@ -15,23 +31,117 @@ pub struct GridNetwork {
     pub graph: Graph<Zone, Link>,
     /// Mapping of zone id to Zone node index
     node_map: HashMap<u32, NodeIndex>,
+    /// Points of interest (schools, hospitals, grocery, ...) in the city
+    pub pois: Vec<Poi>,
+    /// Number of points of interest in each zone by zone id and category
+    zone_pois: HashMap<u32, HashMap<String, u32>>,
+    /// Links read from the database on demand instead of held in `graph`, see `load_lazy`
+    #[serde(skip)]
+    lazy_links: Option<LazyLinks>,
+}
+
+/// Origin zones whose links are kept by a grid network loaded with `load_lazy`
+const LAZY_LINK_ORIGINS: usize = 2_048;
+
+/// Links of a grid network read from the database one origin zone at a time, keeping those of
+/// the most recently used origins
+struct LazyLinks {
+    conn: Mutex<Connection>,
+    /// Whether the demand table has the demand of each time period
+    by_time: bool,
+    origins: Mutex<LruCache<NodeIndex, Arc<HashMap<NodeIndex, Link>>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
 }
 
 impl GridNetwork {
     pub fn print_stats(&self) {
         println!("Grid network:");
         println!("  Zones: {}", self.graph.node_count());
-        println!("  Links: {}", self.graph.edge_count());
+        match &self.lazy_links {
+            Some(lazy) => println!(
+                "  Links: loaded on demand, {} origin zones cached",
+                lazy.origins.lock().unwrap().len()
+            ),
+            None => println!("  Links: {}", self.graph.edge_count()),
+        }
+    }
+
+    pub fn memory_stats(&self) -> GridMemoryStats {
+        GridMemoryStats {
+            zones: self.graph.node_count(),
+            links_in_memory: self.graph.edge_count(),
+            lazy_links: self.lazy_links.as_ref().map(|lazy| {
+                let origins = lazy.origins.lock().unwrap();
+                LazyLinkStats {
+                    cached_origins: origins.len(),
+                    capacity: origins.cap().get(),
+                    cached_links: origins.iter().map(|(_, links)| links.len()).sum(),
+                    hits: lazy.hits.load(Ordering::Relaxed),
+                    misses: lazy.misses.load(Ordering::Relaxed),
+                }
+            }),
+        }
     }
 
     pub fn load(dbname: &str) -> Result<GridNetwork> {
         let conn = Connection::open(dbname)?;
 
-        let links = read_links2(&conn).unwrap_or_else(|_| {
+        let links = read_links2(&conn, "", &[]).unwrap_or_else(|_| {
             log::error!("Failed to read links with time data, falling back to reading links without time data");
-            read_links(&conn).unwrap()
+            read_links(&conn, "", &[]).unwrap()
+        });
+        let mut grid = GridNetwork::load_zones(&conn)?;
+        for link in links {
+            if let (Some(&from_node), Some(&to_node)) = (
+                grid.node_map.get(&link.origid),
+                grid.node_map.get(&link.destid),
+            ) {
+                grid.graph.add_edge(from_node, to_node, link);
+            }
+        }
+        Ok(grid)
+    }
+
+    /// Load the zones of a grid network, reading the links leaving a zone from the database
+    /// the first time they are needed
+    ///
+    /// The links of the most recently used origin zones are kept in memory. Demand lookups
+    /// hold a connection to the database for as long as the grid network is loaded.
+    pub fn load_lazy(dbname: &str) -> Result<GridNetwork> {
+        let conn = Connection::open(dbname)?;
+        let mut grid = GridNetwork::load_zones(&conn)?;
+        for column in ["origid", "destid"] {
+            let index = format!(
+                "CREATE INDEX IF NOT EXISTS demand_{0} ON demand({0})",
+                column
+            );
+            if let Err(e) = conn.execute(&index, params![]) {
+                log::warn!(
+                    "Failed to index demand by {}, lookups will be slow: {}",
+                    column,
+                    e
+                );
+            }
+        }
+        let by_time: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('demand') WHERE name = 'volume_morning'",
+            params![],
+            |row| row.get(0),
+        )?;
+        grid.lazy_links = Some(LazyLinks {
+            conn: Mutex::new(conn),
+            by_time,
+            origins: Mutex::new(LruCache::new(NonZeroUsize::new(LAZY_LINK_ORIGINS).unwrap())),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
         });
-        let zones = read_zones(&conn)?;
+        Ok(grid)
+    }
+
+    /// A grid network of the zones and points of interest in the database, without links
+    fn load_zones(conn: &Connection) -> Result<GridNetwork> {
+        let zones = read_zones(conn)?;
 
         let mut rtree = RTree::<RTreeNode>::new();
         let mut graph = Graph::<Zone, Link, Directed>::new();
@@ -47,11 +157,19 @@ impl GridNetwork {
             node_map.insert(graph[node_index].zoneid, node_index);
         }
 
-        for link in links {
-            if let (Some(&from_node), Some(&to_node)) =
-                (node_map.get(&link.origid), node_map.get(&link.destid))
+        let pois = read_pois(conn)?;
+        let mut zone_pois: HashMap<u32, HashMap<String, u32>> = HashMap::new();
+        for poi in &pois {
+            if let Some(node) = rtree
+                .locate_all_at_point(&[poi.geom.x(), poi.geom.y()])
+                .map(|n| n.node_index)
+                .find(|&n| graph[n].polygon.contains(&poi.geom))
             {
-                graph.add_edge(from_node, to_node, link);
+                *zone_pois
+                    .entry(graph[node].zoneid)
+                    .or_default()
+                    .entry(poi.category.clone())
+                    .or_insert(0) += 1;
             }
         }
 
@@ -59,9 +177,91 @@ impl GridNetwork {
             rtree: rtree,
             graph: graph,
             node_map: node_map,
+            pois,
+            zone_pois,
+            lazy_links: None,
+        })
+    }
+
+    /// Read links from the database of a grid network loaded with `load_lazy`
+    ///
+    /// # Parameters
+    /// - `filter`: SQL clause selecting the rows of the demand table, e.g. `WHERE origid = ?1`
+    fn query_links(lazy: &LazyLinks, filter: &str, args: &[&dyn ToSql]) -> Vec<Link> {
+        let conn = lazy.conn.lock().unwrap();
+        let links = match lazy.by_time {
+            true => read_links2(&conn, filter, args),
+            false => read_links(&conn, filter, args),
+        };
+        links.unwrap_or_else(|e| {
+            log::error!("Failed to read links {}: {}", filter, e);
+            vec![]
         })
     }
 
+    /// Links leaving a zone by destination zone, of a grid network loaded with `load_lazy`
+    fn links_from(&self, lazy: &LazyLinks, from: NodeIndex) -> Arc<HashMap<NodeIndex, Link>> {
+        if let Some(links) = lazy.origins.lock().unwrap().get(&from) {
+            lazy.hits.fetch_add(1, Ordering::Relaxed);
+            return links.clone();
+        }
+        lazy.misses.fetch_add(1, Ordering::Relaxed);
+        let zoneid = self.graph[from].zoneid;
+        let links: Arc<HashMap<NodeIndex, Link>> = Arc::new(
+            GridNetwork::query_links(lazy, "WHERE origid = ?1", &[&zoneid])
+                .into_iter()
+                .filter_map(|link| Some((*self.node_map.get(&link.destid)?, link)))
+                .collect(),
+        );
+        lazy.origins.lock().unwrap().put(from, links.clone());
+        links
+    }
+
+    /// Every link between two zones as (origin, destination, link)
+    fn links(&self) -> Vec<(NodeIndex, NodeIndex, Cow<'_, Link>)> {
+        match &self.lazy_links {
+            Some(lazy) => GridNetwork::query_links(lazy, "", &[])
+                .into_iter()
+                .filter_map(|link| {
+                    let from = *self.node_map.get(&link.origid)?;
+                    let to = *self.node_map.get(&link.destid)?;
+                    Some((from, to, Cow::Owned(link)))
+                })
+                .collect(),
+            None => self
+                .graph
+                .edge_references()
+                .map(|edge| (edge.source(), edge.target(), Cow::Borrowed(edge.weight())))
+                .collect(),
+        }
+    }
+
+    /// Number of points of interest in a zone
+    ///
+    /// # Parameters
+    /// - `zone`: Node index of the zone
+    /// - `category`: Only count this category, or all categories if `None`
+    pub fn poi_count(&self, zone: NodeIndex, category: Option<&str>) -> u32 {
+        let counts = match self.zone_pois.get(&self.graph[zone].zoneid) {
+            Some(counts) => counts,
+            None => return 0,
+        };
+        match category {
+            Some(category) => counts.get(category).copied().unwrap_or(0),
+            None => counts.values().sum(),
+        }
+    }
+
+    /// Number of points of interest in a zone by category
+    pub fn poi_counts(&self, zone: NodeIndex) -> Option<&HashMap<String, u32>> {
+        self.zone_pois.get(&self.graph[zone].zoneid)
+    }
+
+    /// Whether the database had job counts for the zones
+    pub fn has_jobs(&self) -> bool {
+        self.graph.node_weights().any(|z| z.jobs > 0)
+    }
+
     pub fn find_nearest_zone(&self, x: f64, y: f64) -> Option<NodeIndex> {
         let point = [x, y];
         match self.rtree.locate_at_point(&point) {
@@ -70,6 +270,20 @@ impl GridNetwork {
         }
     }
 
+    /// Zone whose polygon contains a point
+    ///
+    /// Candidates are narrowed down with the rtree before the point-in-polygon test. A point
+    /// that is only inside the bounding box of zones, e.g. in a gap between polygons, falls
+    /// back to the first of them like `find_nearest_zone`.
+    pub fn find_containing_zone(&self, x: f64, y: f64) -> Option<NodeIndex> {
+        let point = Point::new(x, y);
+        self.rtree
+            .locate_all_at_point(&[x, y])
+            .map(|node| node.node_index)
+            .find(|&n| self.graph[n].polygon.contains(&point))
+            .or_else(|| self.find_nearest_zone(x, y))
+    }
+
     pub fn get_zone(&self, node_index: NodeIndex) -> &Zone {
         &self.graph[node_index]
     }
@@ -78,13 +292,31 @@ impl GridNetwork {
         self.node_map[&zoneid]
     }
 
+    /// Index of a zone, if the grid has one with this id
+    pub fn find_zone_idx_by_id(&self, zoneid: u32) -> Option<NodeIndex> {
+        self.node_map.get(&zoneid).copied()
+    }
+
     pub fn demand_between_zones(&self, from: NodeIndex, to: NodeIndex) -> f64 {
-        let link = self.graph.find_edge(from, to).unwrap();
-        self.graph[link].weight
+        match &self.lazy_links {
+            Some(lazy) => self.links_from(lazy, from)[&to].weight,
+            None => self.graph[self.graph.find_edge(from, to).unwrap()].weight,
+        }
     }
 
-    pub fn link_between_zones(&self, from: NodeIndex, to: NodeIndex) -> Option<&Link> {
-        self.graph.find_edge(from, to).map(|link| &self.graph[link])
+    /// Link between two zones, read from the database if the grid network was loaded with
+    /// `load_lazy`
+    pub fn link_between_zones(&self, from: NodeIndex, to: NodeIndex) -> Option<Cow<'_, Link>> {
+        match &self.lazy_links {
+            Some(lazy) => self
+                .links_from(lazy, from)
+                .get(&to)
+                .map(|link| Cow::Owned(link.clone())),
+            None => self
+                .graph
+                .find_edge(from, to)
+                .map(|link| Cow::Borrowed(&self.graph[link])),
+        }
     }
 
     pub fn demand_between_coords(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
@@ -97,6 +329,112 @@ impl GridNetwork {
         }
     }
 
+    /// Total demand leaving and arriving at a zone
+    ///
+    /// # Returns
+    /// A tuple of (demand out, demand in)
+    pub fn demand_out_in(&self, zone: NodeIndex) -> (f64, f64) {
+        if let Some(lazy) = &self.lazy_links {
+            let demand_out = self
+                .links_from(lazy, zone)
+                .iter()
+                .filter(|(to, _)| **to != zone)
+                .map(|(_, link)| link.weight)
+                .sum();
+            let zoneid = self.graph[zone].zoneid;
+            let demand_in = GridNetwork::query_links(lazy, "WHERE destid = ?1", &[&zoneid])
+                .iter()
+                .filter(|link| link.origid != zoneid)
+                .map(|link| link.weight)
+                .sum();
+            return (demand_out, demand_in);
+        }
+        let total = |direction| {
+            self.graph
+                .edges_directed(zone, direction)
+                .filter(|edge| edge.source() != edge.target())
+                .map(|edge| edge.weight().weight)
+                .sum::<f64>()
+        };
+        (total(Direction::Outgoing), total(Direction::Incoming))
+    }
+
+    /// Demand leaving and arriving at each zone, over the day and in each time period
+    ///
+    /// # Returns
+    /// The demand of every zone with demand to or from another zone
+    pub fn zone_demand(&self) -> HashMap<NodeIndex, ZoneDemand> {
+        let mut demand: HashMap<NodeIndex, ZoneDemand> = HashMap::new();
+        for (from, to, link) in self.links() {
+            if from == to {
+                continue;
+            }
+            let origin = demand.entry(from).or_default();
+            origin.demand_out += link.weight;
+            for period in TimePeriod::ALL {
+                origin
+                    .by_period
+                    .entry(period.clone())
+                    .or_default()
+                    .demand_out += link.period_weight(&period);
+            }
+            let destination = demand.entry(to).or_default();
+            destination.demand_in += link.weight;
+            for period in TimePeriod::ALL {
+                destination
+                    .by_period
+                    .entry(period.clone())
+                    .or_default()
+                    .demand_in += link.period_weight(&period);
+            }
+        }
+        demand
+    }
+
+    /// Demand between pairs of distinct zones, largest first
+    ///
+    /// # Parameters
+    /// - `period`: Only count the demand of a time period, the whole day if `None`
+    /// - `min_weight`: Leave out pairs with less demand
+    ///
+    /// # Returns
+    /// (origin, destination, demand) of each directed pair of zones
+    pub fn desire_lines(
+        &self,
+        period: Option<&TimePeriod>,
+        min_weight: f64,
+    ) -> Vec<(NodeIndex, NodeIndex, f64)> {
+        let mut lines: Vec<(NodeIndex, NodeIndex, f64)> = self
+            .links()
+            .into_iter()
+            .filter(|(from, to, _)| from != to)
+            .map(|(from, to, link)| {
+                let weight = match period {
+                    Some(period) => link.period_weight(period),
+                    None => link.weight,
+                };
+                (from, to, weight)
+            })
+            .filter(|(_, _, weight)| *weight > 0.0 && *weight >= min_weight)
+            .collect();
+        lines.sort_by(|a, b| {
+            ordering::cmp_f64_desc(a.2, b.2).then_with(|| (a.0, a.1).cmp(&(b.0, b.1)))
+        });
+        lines
+    }
+
+    /// Zones whose bounding box intersects a bounding box
+    ///
+    /// # Parameters
+    /// - `min`: Lower left corner as [lon, lat]
+    /// - `max`: Upper right corner as [lon, lat]
+    pub fn zones_in_bbox(&self, min: [f64; 2], max: [f64; 2]) -> Vec<NodeIndex> {
+        self.rtree
+            .locate_in_envelope_intersecting(&AABB::from_corners(min, max))
+            .map(|node| node.node_index)
+            .collect()
+    }
+
     pub fn get_all_valid_zones(&self) -> Vec<NodeIndex> {
         self.graph
             .node_indices()
@@ -105,16 +443,30 @@ impl GridNetwork {
     }
 }
 
-#[derive(PartialOrd, Ord, Clone, Deserialize, Serialize, Hash, Eq, PartialEq)]
+#[derive(PartialOrd, Ord, Clone, Debug, Deserialize, Serialize, Hash, Eq, PartialEq)]
 pub enum TimePeriod {
+    #[serde(alias = "morning")]
     Morning,
+    #[serde(alias = "am_rush")]
     AmRush,
+    #[serde(alias = "mid_day")]
     MidDay,
+    #[serde(alias = "pm_rush")]
     PmRush,
+    #[serde(alias = "evening")]
     Evening,
 }
 
 impl TimePeriod {
+    /// All periods in the order of the day
+    pub const ALL: [TimePeriod; 5] = [
+        TimePeriod::Morning,
+        TimePeriod::AmRush,
+        TimePeriod::MidDay,
+        TimePeriod::PmRush,
+        TimePeriod::Evening,
+    ];
+
     pub fn to_number(&self) -> usize {
         match self {
             TimePeriod::Morning => 1,
@@ -124,6 +476,37 @@ impl TimePeriod {
             TimePeriod::Evening => 5,
         }
     }
+
+    /// Start and end of the period in the city's local time, in seconds since midnight
+    pub fn local_bounds(&self) -> (u32, u32) {
+        match self {
+            TimePeriod::Morning => (5 * 3600, 7 * 3600),
+            TimePeriod::AmRush => (7 * 3600, 9 * 3600 + 1800),
+            TimePeriod::MidDay => (9 * 3600 + 1800, 15 * 3600),
+            TimePeriod::PmRush => (15 * 3600, 19 * 3600),
+            TimePeriod::Evening => (19 * 3600, 22 * 3600),
+        }
+    }
+
+    /// Period containing a local time of day, a time on a boundary belongs to the earlier period
+    ///
+    /// # Parameters
+    /// - `seconds`: Seconds since local midnight, e.g. a parsed GTFS time
+    ///
+    /// # Returns
+    /// The period, or `None` outside of the service hours covered by the periods
+    pub fn from_local_seconds(seconds: u32) -> Option<TimePeriod> {
+        TimePeriod::ALL.into_iter().find(|period| {
+            let (start, end) = period.local_bounds();
+            seconds >= start && seconds <= end
+        })
+    }
+
+    /// Period an instant falls in for a city in the given time zone
+    pub fn at(instant: DateTime<Utc>, tz: Tz) -> Option<TimePeriod> {
+        let local = instant.with_timezone(&tz);
+        TimePeriod::from_local_seconds(local.num_seconds_from_midnight())
+    }
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -135,11 +518,44 @@ pub struct Link {
     pub weight_by_time: HashMap<TimePeriod, f64>,
 }
 
+impl Link {
+    /// Demand during a time period, an even share of the daily demand if the period is unknown
+    pub fn period_weight(&self, period: &TimePeriod) -> f64 {
+        self.weight_by_time
+            .get(period)
+            .copied()
+            .unwrap_or(self.weight / TimePeriod::ALL.len() as f64)
+    }
+}
+
+/// Demand leaving and arriving at a zone
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ZoneDemand {
+    pub demand_out: f64,
+    pub demand_in: f64,
+    /// Demand of each time period, empty within a period
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub by_period: BTreeMap<TimePeriod, ZoneDemand>,
+}
+
+impl ZoneDemand {
+    /// Demand of a time period, or of the whole day if `None`
+    pub fn in_period(&self, period: Option<&TimePeriod>) -> (f64, f64) {
+        match period.map(|p| self.by_period.get(p)) {
+            Some(Some(demand)) => (demand.demand_out, demand.demand_in),
+            Some(None) => (0.0, 0.0),
+            None => (self.demand_out, self.demand_in),
+        }
+    }
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Zone {
     pub zoneid: u32,
     pub polygon: Polygon<f64>,
     pub population: u32,
+    /// Jobs located in the zone, 0 when the database has no job data
+    pub jobs: u32,
 }
 
 impl Zone {
@@ -148,6 +564,15 @@ impl Zone {
     }
 }
 
+/// A point of interest that residents may want to reach by transit
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Poi {
+    pub id: u64,
+    /// Free-form category, e.g. `school`, `hospital` or `grocery`
+    pub category: String,
+    pub geom: Point<f64>,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct RTreeNode {
     envelope: AABB<[f64; 2]>,
@@ -199,9 +624,13 @@ fn compute_envelope(polygon: &Polygon<f64>) -> AABB<[f64; 2]> {
     AABB::from_corners([min_x, min_y], [max_x, max_y])
 }
 
-fn read_links(conn: &Connection) -> Result<Vec<Link>> {
-    let mut stmt = conn.prepare("SELECT origid, destid, volume FROM demand")?;
-    let link_iter = stmt.query_map(params![], |row| {
+/// Read the links of the rows of the demand table selected by `filter`, e.g. `WHERE origid = ?1`
+fn read_links(conn: &Connection, filter: &str, args: &[&dyn ToSql]) -> Result<Vec<Link>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT origid, destid, volume FROM demand {}",
+        filter
+    ))?;
+    let link_iter = stmt.query_map(args, |row| {
         Ok(Link {
             origid: row.get(0)?,
             destid: row.get(1)?,
@@ -212,8 +641,9 @@ fn read_links(conn: &Connection) -> Result<Vec<Link>> {
     Ok(Vec::from_iter(link_iter.map(|x| x.unwrap())))
 }
 
-fn read_links2(conn: &Connection) -> Result<Vec<Link>> {
-    let mut stmt = conn.prepare(
+/// Like `read_links` with the demand of each time period
+fn read_links2(conn: &Connection, filter: &str, args: &[&dyn ToSql]) -> Result<Vec<Link>> {
+    let mut stmt = conn.prepare(&format!(
         "
 SELECT \
     origid, \
@@ -225,9 +655,10 @@ SELECT \
     volume_pm_rush, \
     volume_evening \
 FROM \
-    demand",
-    )?;
-    let link_iter = stmt.query_map(params![], |row| {
+    demand {}",
+        filter
+    ))?;
+    let link_iter = stmt.query_map(args, |row| {
         Ok(Link {
             origid: row.get(0)?,
             destid: row.get(1)?,
@@ -247,7 +678,17 @@ FROM \
 }
 
 fn read_zones(conn: &Connection) -> Result<Vec<Zone>> {
-    let mut stmt = conn.prepare("SELECT zoneid, geom, population FROM zone")?;
+    // jobs are optional, older databases only have the population
+    let has_jobs: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('zone') WHERE name = 'jobs'",
+        params![],
+        |row| row.get(0),
+    )?;
+    let mut stmt = if has_jobs {
+        conn.prepare("SELECT zoneid, geom, population, jobs FROM zone")?
+    } else {
+        conn.prepare("SELECT zoneid, geom, population, 0 FROM zone")?
+    };
     let zone_iter = stmt.query_map(params![], |row| {
         let wkt_str: String = row.get(1)?;
         let wkt = Wkt::from_str(&wkt_str).unwrap();
@@ -256,7 +697,76 @@ fn read_zones(conn: &Connection) -> Result<Vec<Zone>> {
             zoneid: row.get(0)?,
             polygon: polygon,
             population: row.get::<_, f64>(2)? as u32,
+            jobs: row.get::<_, Option<f64>>(3)?.unwrap_or(0.0) as u32,
         })
     })?;
     Ok(Vec::from_iter(zone_iter.map(|x| x.unwrap())))
 }
+
+/// Read a numeric column of the zone table, e.g. a demographic count, by zone id
+///
+/// # Returns
+/// `None` if the zone table has no such column. Values that are not numbers count as 0.
+pub fn read_zone_column(dbname: &str, column: &str) -> Result<Option<HashMap<u32, f64>>> {
+    let conn = Connection::open(dbname)?;
+    let has_column: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('zone') WHERE name = ?1",
+        params![column],
+        |row| row.get(0),
+    )?;
+    if !has_column {
+        return Ok(None);
+    }
+    let mut stmt = conn.prepare(&format!(
+        "SELECT zoneid, \"{}\" FROM zone",
+        column.replace('"', "\"\"")
+    ))?;
+    let values = stmt.query_map(params![], |row| {
+        let value = row.get::<_, Option<f64>>(1).ok().flatten();
+        Ok((row.get::<_, u32>(0)?, value.unwrap_or(0.0)))
+    })?;
+    Ok(Some(values.filter_map(|x| x.ok()).collect()))
+}
+
+/// Read points of interest from the optional `poi` table
+fn read_pois(conn: &Connection) -> Result<Vec<Poi>> {
+    let has_table: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'poi'",
+        params![],
+        |row| row.get(0),
+    )?;
+    if !has_table {
+        return Ok(vec![]);
+    }
+    let mut stmt = conn.prepare("SELECT id, category, geom FROM poi")?;
+    let poi_iter = stmt.query_map(params![], |row| {
+        let wkt_str: String = row.get(2)?;
+        let geom: Option<Point<f64>> = Wkt::from_str(&wkt_str)
+            .ok()
+            .and_then(|wkt: Wkt<f64>| wkt.try_into().ok());
+        Ok(geom.map(|geom| Poi {
+            id: row.get(0).unwrap_or_default(),
+            category: row.get(1).unwrap_or_default(),
+            geom,
+        }))
+    })?;
+    Ok(poi_iter.filter_map(|x| x.ok().flatten()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn period_follows_local_time() {
+        // 12:30 UTC is 07:30 in Toronto in winter and 21:30 in Tokyo
+        let instant = Utc.with_ymd_and_hms(2025, 1, 15, 12, 30, 0).unwrap();
+        assert!(TimePeriod::at(instant, chrono_tz::America::Toronto) == Some(TimePeriod::AmRush));
+        assert!(TimePeriod::at(instant, chrono_tz::Asia::Tokyo) == Some(TimePeriod::Evening));
+        assert!(TimePeriod::at(instant, Tz::UTC) == Some(TimePeriod::MidDay));
+        // boundaries belong to the earlier period, service past midnight to none
+        assert!(TimePeriod::from_local_seconds(7 * 3600) == Some(TimePeriod::Morning));
+        assert!(TimePeriod::from_local_seconds(25 * 3600).is_none());
+    }
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Summary of what happened to the source data while a city was being loaded.
+///
+/// The report is kept alongside the city so that anything silently excluded
+/// from the feed during import can be inspected after the fact.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct ImportReport {
+    /// Result of clipping the GTFS feed to the city boundary, `None` if no
+    /// boundary was configured for the city
+    pub clipping: Option<ClipReport>,
+    /// How the inbound and outbound direction of each route was determined
+    pub directions: Vec<RouteDirection>,
+    /// Number of routes whose directions were inferred from the stops of their trips, since
+    /// the feed does not give a valid `direction_id` for both of them
+    #[serde(default)]
+    pub inferred_directions: usize,
+    /// Routes with stops that are not mapped to the road network
+    pub approximate_geometry: Vec<ApproximateGeometry>,
+    /// Stations and boarding areas referenced by stop times, and the stops used instead
+    #[serde(default)]
+    pub station_stops: Vec<StationStop>,
+    /// Number of routes left out of the transit network built from the feed, by reason
+    #[serde(default)]
+    pub dropped_from_network: BTreeMap<DropReason, usize>,
+}
+
+/// Records the stops, trips and routes removed by clipping the GTFS feed
+/// to the city boundary.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct ClipReport {
+    /// Distance in meters outside the boundary polygon that is still kept
+    pub buffer_m: f64,
+    /// Number of stops in the feed before clipping
+    pub stops_before: usize,
+    /// Number of trips in the feed before clipping
+    pub trips_before: usize,
+    /// Number of routes in the feed before clipping
+    pub routes_before: usize,
+    /// Stops removed because they fell outside the buffered boundary
+    pub dropped_stop_ids: Vec<String>,
+    /// Trips removed because fewer than two of their stops remained
+    pub dropped_trip_ids: Vec<String>,
+    /// Trips that were kept but had some of their stops removed
+    pub trimmed_trip_ids: Vec<String>,
+    /// Routes removed because none of their trips remained
+    pub dropped_route_ids: Vec<String>,
+    /// Number of shapes removed because no remaining trip referenced them
+    pub dropped_shapes: usize,
+}
+
+/// How the direction of the trips of a route was determined
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DirectionSource {
+    /// Trips were split using their GTFS `direction_id`
+    DirectionId,
+    /// `direction_id` was missing, one-sided or not 0 or 1, trips were split by comparing
+    /// their stop order, end stops and bearing against the longest trip of the route
+    Geometry,
+}
+
+/// Direction classification of a single route.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RouteDirection {
+    pub route_id: String,
+    pub source: DirectionSource,
+    /// Longest trip classified as outbound, used to build the route
+    pub outbound_trip_id: String,
+    /// Longest trip classified as inbound, `None` for single direction routes (e.g. loops)
+    pub inbound_trip_id: Option<String>,
+    /// Number of trips classified as outbound
+    pub outbound_trips: usize,
+    /// Number of trips classified as inbound
+    pub inbound_trips: usize,
+    /// Trips whose `direction_id` contradicts the order of their stops, they are kept in the
+    /// direction the feed gives them
+    #[serde(default)]
+    pub mislabelled_trips: usize,
+}
+
+/// Why a route of the feed is left out of the transit network
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DropReason {
+    /// The feed has no trips for the route
+    NoTrips,
+    /// None of the trips of the route visits at least two stops
+    TooFewStops,
+}
+
+/// Route of the feed left out of the transit network
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DroppedRoute {
+    pub route_id: String,
+    pub reason: DropReason,
+    /// Number of trips of the route in the feed
+    pub trips: usize,
+}
+
+/// Bus route whose exported shape is partly drawn as straight segments, since some of its
+/// consecutive stops have no road path between them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApproximateGeometry {
+    pub route_id: String,
+    /// Number of consecutive outbound stop pairs where a stop is not mapped to a road node
+    pub segments: usize,
+}
+
+/// How stop times on a station or boarding area were moved to street level
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StationResolution {
+    /// A boarding area was replaced by its parent platform
+    ParentPlatform,
+    /// A station was replaced by one of its platforms
+    ChildPlatform,
+    /// A station without platforms was moved to one of its entrances
+    Entrance,
+    /// The station has no platform or entrance, it is kept where it is
+    Unresolved,
+}
+
+/// Stop times of a trip that referenced a station or boarding area
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StationStop {
+    /// Station or boarding area referenced by the stop times
+    pub stop_id: String,
+    pub resolution: StationResolution,
+    /// Platform that replaced the stop, or the entrance the station was moved to
+    pub replaced_by: Option<String>,
+    /// Number of stop times resolved this way
+    pub stop_times: usize,
+}
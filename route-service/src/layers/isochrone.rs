@@ -0,0 +1,237 @@
+use geo::{Centroid, ConcaveHull, Distance, Haversine};
+use geo_types::{MultiPoint, Point, Polygon};
+use petgraph::graph::NodeIndex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use super::{
+    grid::GridNetwork,
+    road_network::RoadNetwork,
+    transit_network::{TransitNetwork, TransitStop},
+};
+
+/// Walking speed turning minutes into meters walked along the roads, 4.8 km/h
+pub const WALK_SPEED_M_PER_MIN: f64 = 80.0;
+/// Walk times of the isochrones drawn when none are asked for, in minutes
+pub const DEFAULT_MINUTES: [u32; 3] = [5, 10, 15];
+/// Concavity of the hulls drawn around the reachable nodes, lower values follow the roads
+/// more closely
+const CONCAVITY: f64 = 2.0;
+
+/// Area within a walk time of a stop along the road network
+#[derive(Serialize, Clone, Debug)]
+pub struct Isochrone {
+    pub stop_id: String,
+    pub minutes: u32,
+    /// Road nodes within the walk time, the polygon is drawn around them
+    pub nodes: usize,
+    #[serde(skip)]
+    pub polygon: Polygon<f64>,
+}
+
+/// Zones whose centroid is within a walk time of a stop
+#[derive(Serialize, Clone, Debug)]
+pub struct WalkCoverage {
+    pub minutes: u32,
+    pub zones: usize,
+    pub population: u32,
+    /// Share of the population of the city's valid zones
+    pub population_share: f64,
+}
+
+/// Walk-shed coverage of a transit network
+#[derive(Serialize, Clone, Debug)]
+pub struct NetworkIsochrones {
+    /// The area of each stop, closer to it than to any other stop, within each walk time
+    pub isochrones: Vec<Isochrone>,
+    pub coverage: Vec<WalkCoverage>,
+}
+
+/// Isochrones of a stop
+///
+/// # Parameters
+/// - `stop`: The stop, walks start at its nearest road node
+/// - `road`: The road network walked along
+/// - `minutes`: Walk times to draw an isochrone for
+///
+/// # Returns
+/// One isochrone per walk time, in the order of `minutes`
+pub fn stop_isochrones(stop: &TransitStop, road: &RoadNetwork, minutes: &[u32]) -> Vec<Isochrone> {
+    let sources: Vec<(NodeIndex, f64)> = walk_source(stop, road).into_iter().collect();
+    let reached = road.walk_distances(&sources, max_walk_m(minutes));
+    minutes
+        .iter()
+        .map(|&minutes| isochrone(stop, &reached, road, minutes))
+        .collect()
+}
+
+/// Isochrones of every stop of a network and the population they cover
+///
+/// Walks start from all stops at once, each road node belongs to the stop it is nearest to,
+/// so the isochrones of neighbouring stops do not overlap.
+///
+/// # Parameters
+/// - `network`: The network whose stops are walked from
+/// - `road`: The road network walked along
+/// - `grid`: Zones whose population is covered
+/// - `minutes`: Walk times to draw isochrones for
+///
+/// # Returns
+/// The isochrones by stop id and walk time, and the coverage of each walk time
+pub fn network_isochrones(
+    network: &TransitNetwork,
+    road: &RoadNetwork,
+    grid: &GridNetwork,
+    minutes: &[u32],
+) -> NetworkIsochrones {
+    let mut stops: BTreeMap<&str, &TransitStop> = BTreeMap::new();
+    for route in &network.routes {
+        for stop in route.outbound_stops.iter().chain(&route.inbound_stops) {
+            stops.insert(stop.stop_id.as_str(), stop);
+        }
+    }
+    let (stops, sources): (Vec<&TransitStop>, Vec<(NodeIndex, f64)>) = stops
+        .into_values()
+        .filter_map(|stop| walk_source(stop, road).map(|source| (stop, source)))
+        .unzip();
+    let reached = road.walk_distances(&sources, max_walk_m(minutes));
+
+    let mut by_stop: Vec<HashMap<NodeIndex, (f64, usize)>> = vec![HashMap::new(); stops.len()];
+    for (&node, &(meters, stop)) in &reached {
+        by_stop[stop].insert(node, (meters, stop));
+    }
+    let mut isochrones = Vec::new();
+    for (stop, reached) in stops.iter().zip(&by_stop) {
+        for &minutes in minutes {
+            isochrones.push(isochrone(stop, reached, road, minutes));
+        }
+    }
+
+    let zones: Vec<(u32, Option<f64>)> = grid
+        .get_all_valid_zones()
+        .into_iter()
+        .map(|z| {
+            let zone = grid.get_zone(z);
+            let walk = zone.polygon.centroid().and_then(|centroid| {
+                let node = road.find_nearest_node(centroid.x(), centroid.y())?;
+                let &(meters, _) = reached.get(&node)?;
+                Some(meters + Haversine::distance(centroid, road.get_node(node).geom))
+            });
+            (zone.population, walk)
+        })
+        .collect();
+    let total_population: u32 = zones.iter().map(|&(population, _)| population).sum();
+    let coverage = minutes
+        .iter()
+        .map(|&minutes| {
+            let covered: Vec<u32> = zones
+                .iter()
+                .filter(|&&(_, walk)| {
+                    walk.is_some_and(|walk| walk <= minutes as f64 * WALK_SPEED_M_PER_MIN)
+                })
+                .map(|&(population, _)| population)
+                .collect();
+            let population: u32 = covered.iter().sum();
+            WalkCoverage {
+                minutes,
+                zones: covered.len(),
+                population,
+                population_share: match total_population {
+                    0 => 0.0,
+                    total => population as f64 / total as f64,
+                },
+            }
+        })
+        .collect();
+
+    NetworkIsochrones {
+        isochrones,
+        coverage,
+    }
+}
+
+/// Road node a walk from a stop starts at and the straight-line walk to reach it
+fn walk_source(stop: &TransitStop, road: &RoadNetwork) -> Option<(NodeIndex, f64)> {
+    let node = stop
+        .get_node_index(road)
+        .or_else(|| road.find_nearest_node(stop.geom.x(), stop.geom.y()))?;
+    Some((
+        node,
+        Haversine::distance(stop.geom, road.get_node(node).geom),
+    ))
+}
+
+fn max_walk_m(minutes: &[u32]) -> f64 {
+    minutes.iter().copied().max().unwrap_or(0) as f64 * WALK_SPEED_M_PER_MIN
+}
+
+/// Isochrone of a stop drawn around the reached nodes within a walk time
+fn isochrone(
+    stop: &TransitStop,
+    reached: &HashMap<NodeIndex, (f64, usize)>,
+    road: &RoadNetwork,
+    minutes: u32,
+) -> Isochrone {
+    let max_m = minutes as f64 * WALK_SPEED_M_PER_MIN;
+    let mut nodes: Vec<NodeIndex> = reached
+        .iter()
+        .filter(|(_, &(meters, _))| meters <= max_m)
+        .map(|(&node, _)| node)
+        .collect();
+    // the hull does not depend on the order of the points, sorting keeps it reproducible
+    nodes.sort();
+    let points: Vec<Point> = std::iter::once(stop.geom)
+        .chain(nodes.iter().map(|&node| road.get_node(node).geom))
+        .collect();
+    Isochrone {
+        stop_id: stop.stop_id.clone(),
+        minutes,
+        nodes: nodes.len(),
+        polygon: MultiPoint::from(points).concave_hull(CONCAVITY),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+    use geo::{Area, Intersects};
+
+    #[test]
+    fn isochrones_grow_with_the_walk_time() {
+        let city = load_demo_city(
+            &format!("isochrone_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 3,
+                ..Default::default()
+            },
+        );
+
+        let stop = &city.transit.routes[0].outbound_stops[0];
+        let isochrones = stop_isochrones(stop, &city.road, &DEFAULT_MINUTES);
+        assert_eq!(isochrones.len(), 3);
+        assert!(isochrones[0].nodes > 0);
+        for pair in isochrones.windows(2) {
+            assert!(pair[0].nodes <= pair[1].nodes);
+            assert!(pair[0].polygon.unsigned_area() <= pair[1].polygon.unsigned_area());
+        }
+        assert!(isochrones[2].polygon.intersects(&stop.geom));
+
+        let network = network_isochrones(&city.transit, &city.road, &city.grid, &DEFAULT_MINUTES);
+        assert!(network.isochrones.iter().any(|i| i.stop_id == stop.stop_id));
+        // a stop owns fewer nodes when its neighbours are walked from too
+        let owned = network
+            .isochrones
+            .iter()
+            .find(|i| i.stop_id == stop.stop_id && i.minutes == 15)
+            .unwrap();
+        assert!(owned.nodes <= isochrones[2].nodes);
+        for pair in network.coverage.windows(2) {
+            assert!(pair[0].population <= pair[1].population);
+        }
+        assert!(network.coverage[2].population_share > 0.0);
+        assert!(network.coverage[2].population_share <= 1.0);
+    }
+}
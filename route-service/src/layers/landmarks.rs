@@ -0,0 +1,334 @@
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use crate::ordering::{self, OrdF64};
+
+/// Cost of the cheapest paths between every node of a road network and a few landmark nodes,
+/// computed once per city to guide path searches
+///
+/// By the triangle inequality a path from `a` to `b` costs at least the path from a landmark
+/// to `b` less the path from the landmark to `a`, and at least the path from `a` to a
+/// landmark less the path from `b` to it. On a road network these bounds are much tighter
+/// than the straight line distance, so searches guided by them settle far fewer nodes (ALT,
+/// A* with landmarks and the triangle inequality).
+#[derive(Default, Deserialize, Serialize)]
+pub struct Landmarks {
+    nodes: Vec<NodeIndex>,
+    /// Cost from each landmark to every node, infinite for nodes it does not reach
+    from: Vec<Vec<f32>>,
+    /// Cost from every node to each landmark, infinite for nodes not reaching it
+    to: Vec<Vec<f32>>,
+}
+
+impl Landmarks {
+    /// Pick landmarks far apart and compute their costs to and from every node
+    ///
+    /// The first landmark is the node farthest from node 0, each next one the node farthest
+    /// from the landmarks picked so far, which puts them around the edge of the network where
+    /// their bounds are tightest.
+    ///
+    /// # Parameters
+    /// - `node_count`: Nodes of the network, indexed from 0
+    /// - `count`: Most landmarks to pick
+    /// - `forward`: Target and cost of the roads leaving a node
+    /// - `backward`: Source and cost of the roads entering a node
+    pub fn build(
+        node_count: usize,
+        count: usize,
+        forward: impl Fn(NodeIndex) -> Vec<(NodeIndex, f64)>,
+        backward: impl Fn(NodeIndex) -> Vec<(NodeIndex, f64)>,
+    ) -> Landmarks {
+        let mut landmarks = Landmarks::default();
+        if node_count == 0 {
+            return landmarks;
+        }
+        let farthest = |costs: &[f64]| {
+            (0..costs.len())
+                .filter(|&i| costs[i].is_finite())
+                .max_by(|&a, &b| ordering::cmp_f64(costs[a], costs[b]).then(b.cmp(&a)))
+                .map(NodeIndex::new)
+        };
+        let mut nearest = costs(node_count, NodeIndex::new(0), &forward);
+        let mut next = farthest(&nearest);
+        nearest.fill(f64::INFINITY);
+        while let Some(node) = next.filter(|_| landmarks.nodes.len() < count) {
+            if landmarks.nodes.contains(&node) {
+                break;
+            }
+            let from = costs(node_count, node, &forward);
+            let to = costs(node_count, node, &backward);
+            for (nearest, &cost) in nearest.iter_mut().zip(&from) {
+                *nearest = match nearest.is_finite() {
+                    true => nearest.min(cost),
+                    false => cost,
+                };
+            }
+            landmarks.nodes.push(node);
+            landmarks
+                .from
+                .push(from.iter().map(|&c| c as f32).collect());
+            landmarks.to.push(to.iter().map(|&c| c as f32).collect());
+            next = farthest(&nearest);
+        }
+        landmarks
+    }
+
+    /// Number of landmarks, as reported in the stats of the road network
+    pub(crate) fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Bytes taken by the costs of the landmarks
+    pub fn bytes(&self) -> usize {
+        self.from
+            .iter()
+            .chain(&self.to)
+            .map(|costs| costs.len() * std::mem::size_of::<f32>())
+            .sum()
+    }
+
+    /// Lower bound of the cost of a path from `a` to `b`, 0 without landmarks reaching both
+    pub fn lower_bound(&self, a: NodeIndex, b: NodeIndex) -> f64 {
+        // costs are kept as f32, the bound gives back their rounding so it stays a lower bound
+        let bound = |costs: &[f32], plus: NodeIndex, minus: NodeIndex| {
+            let (Some(&plus), Some(&minus)) = (costs.get(plus.index()), costs.get(minus.index()))
+            else {
+                return 0.0;
+            };
+            if !(plus.is_finite() && minus.is_finite()) {
+                return 0.0;
+            }
+            let (plus, minus) = (plus as f64, minus as f64);
+            plus - minus - (plus + minus) * f32::EPSILON as f64
+        };
+        self.from
+            .iter()
+            .zip(&self.to)
+            .map(|(from, to)| bound(from, b, a).max(bound(to, a, b)))
+            .fold(0.0, f64::max)
+    }
+}
+
+/// Cheapest cost from a node to every node, infinite for the nodes it does not reach
+fn costs(
+    node_count: usize,
+    source: NodeIndex,
+    roads: &impl Fn(NodeIndex) -> Vec<(NodeIndex, f64)>,
+) -> Vec<f64> {
+    let mut best = vec![f64::INFINITY; node_count];
+    let mut heap = BinaryHeap::from([(Reverse(OrdF64(0.0)), source)]);
+    best[source.index()] = 0.0;
+    while let Some((Reverse(OrdF64(cost)), node)) = heap.pop() {
+        if cost > best[node.index()] {
+            continue;
+        }
+        for (next, road) in roads(node) {
+            let next_cost = cost + road;
+            if next.index() < node_count && next_cost < best[next.index()] {
+                best[next.index()] = next_cost;
+                heap.push((Reverse(OrdF64(next_cost)), next));
+            }
+        }
+    }
+    best
+}
+
+/// Bidirectional A* search of the cheapest path between two nodes
+///
+/// A forward search from `from` and a backward search from `to` take turns settling the node
+/// with the lowest key, using the average of the bounds to `to` and from `from` as potential
+/// so the two searches stay consistent with each other. The search stops once no path
+/// through an unsettled node can beat the cheapest path where they met.
+///
+/// # Parameters
+/// - `forward`: Target and cost of the roads leaving a node
+/// - `backward`: Source and cost of the roads entering a node
+/// - `lower_bound`: Lower bound of the cost of a path between two nodes, 0 gives a
+///   bidirectional Dijkstra search
+///
+/// # Returns
+/// The cost and nodes of the cheapest path, `None` if `to` cannot be reached
+pub fn shortest_path(
+    from: NodeIndex,
+    to: NodeIndex,
+    forward: impl Fn(NodeIndex) -> Vec<(NodeIndex, f64)>,
+    backward: impl Fn(NodeIndex) -> Vec<(NodeIndex, f64)>,
+    lower_bound: impl Fn(NodeIndex, NodeIndex) -> f64,
+) -> Option<(f64, Vec<NodeIndex>)> {
+    if from == to {
+        return Some((0.0, vec![from]));
+    }
+    let potential = |node: NodeIndex| (lower_bound(node, to) - lower_bound(from, node)) / 2.0;
+    let mut searches = [
+        Search::new(from, potential(from)),
+        Search::new(to, -potential(to)),
+    ];
+    // cost of the cheapest path found so far and the node where its two halves meet
+    let mut best: Option<(f64, NodeIndex)> = None;
+
+    while let (Some(forward_key), Some(backward_key)) =
+        (searches[0].min_key(), searches[1].min_key())
+    {
+        if best.is_some_and(|(cost, _)| forward_key + backward_key >= cost) {
+            break;
+        }
+        let side = (backward_key < forward_key) as usize;
+        let (node, cost) = searches[side].pop();
+        let roads = match side {
+            0 => forward(node),
+            _ => backward(node),
+        };
+        for (next, road) in roads {
+            let next_cost = cost + road;
+            let key = match side {
+                0 => next_cost + potential(next),
+                _ => next_cost - potential(next),
+            };
+            if !searches[side].improve(next, node, next_cost, key) {
+                continue;
+            }
+            if let Some(&(other_cost, _)) = searches[1 - side].best.get(&next) {
+                if best.is_none_or(|(cost, _)| next_cost + other_cost < cost) {
+                    best = Some((next_cost + other_cost, next));
+                }
+            }
+        }
+    }
+
+    let (cost, meeting) = best?;
+    let mut path = searches[0].path_to(meeting);
+    path.reverse();
+    path.extend(searches[1].path_to(meeting).into_iter().skip(1));
+    Some((cost, path))
+}
+
+/// One direction of a bidirectional search
+struct Search {
+    /// Cheapest cost found to each node and the node it was reached from
+    best: HashMap<NodeIndex, (f64, Option<NodeIndex>)>,
+    /// Nodes by key, with the cost they were pushed with to skip stale entries
+    heap: BinaryHeap<(Reverse<OrdF64>, Reverse<OrdF64>, NodeIndex)>,
+}
+
+impl Search {
+    fn new(start: NodeIndex, key: f64) -> Search {
+        Search {
+            best: HashMap::from([(start, (0.0, None))]),
+            heap: BinaryHeap::from([(Reverse(OrdF64(key)), Reverse(OrdF64(0.0)), start)]),
+        }
+    }
+
+    /// Lowest key of the nodes left to settle
+    fn min_key(&mut self) -> Option<f64> {
+        while let Some((Reverse(OrdF64(key)), Reverse(OrdF64(cost)), node)) = self.heap.peek() {
+            if *cost <= self.best[node].0 {
+                return Some(*key);
+            }
+            self.heap.pop();
+        }
+        None
+    }
+
+    /// Settle the node with the lowest key, `min_key` must have returned a key
+    fn pop(&mut self) -> (NodeIndex, f64) {
+        let (_, Reverse(OrdF64(cost)), node) = self.heap.pop().unwrap();
+        (node, cost)
+    }
+
+    /// Record a path to `node` through `via` if it is cheaper than the one known
+    fn improve(&mut self, node: NodeIndex, via: NodeIndex, cost: f64, key: f64) -> bool {
+        if self
+            .best
+            .get(&node)
+            .is_some_and(|&(known, _)| known <= cost)
+        {
+            return false;
+        }
+        self.best.insert(node, (cost, Some(via)));
+        self.heap
+            .push((Reverse(OrdF64(key)), Reverse(OrdF64(cost)), node));
+        true
+    }
+
+    /// Nodes from `node` back to the start of the search
+    fn path_to(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        let mut path = vec![node];
+        while let Some(&(_, Some(prev))) = self.best.get(path.last().unwrap()) {
+            path.push(prev);
+        }
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn landmark_searches_find_the_cheapest_paths() {
+        // a 6 x 6 grid of two-way streets with one-way avenues along every other row
+        let (cols, rows) = (6, 6);
+        let node = |c: usize, r: usize| NodeIndex::new(r * cols + c);
+        let mut roads: Vec<(NodeIndex, NodeIndex, f64)> = vec![];
+        for r in 0..rows {
+            for c in 0..cols {
+                let meters = 100.0 + ((r * 7 + c * 13) % 5) as f64 * 20.0;
+                if c + 1 < cols {
+                    roads.push((node(c, r), node(c + 1, r), meters));
+                    if r % 2 == 0 {
+                        roads.push((node(c + 1, r), node(c, r), meters));
+                    }
+                }
+                if r + 1 < rows {
+                    roads.push((node(c, r), node(c, r + 1), meters * 1.5));
+                    roads.push((node(c, r + 1), node(c, r), meters * 1.5));
+                }
+            }
+        }
+        let forward = |n: NodeIndex| -> Vec<(NodeIndex, f64)> {
+            roads
+                .iter()
+                .filter(|r| r.0 == n)
+                .map(|r| (r.1, r.2))
+                .collect()
+        };
+        let backward = |n: NodeIndex| -> Vec<(NodeIndex, f64)> {
+            roads
+                .iter()
+                .filter(|r| r.1 == n)
+                .map(|r| (r.0, r.2))
+                .collect()
+        };
+
+        let landmarks = Landmarks::build(cols * rows, 4, forward, backward);
+        assert_eq!(landmarks.len(), 4);
+        assert_eq!(landmarks.bytes(), 2 * 4 * cols * rows * 4);
+        for a in 0..cols * rows {
+            let exact = costs(cols * rows, NodeIndex::new(a), &forward);
+            for b in 0..cols * rows {
+                let (a, b) = (NodeIndex::new(a), NodeIndex::new(b));
+                assert!(landmarks.lower_bound(a, b) <= exact[b.index()] + 1e-9);
+                let lower_bound = |x, y| landmarks.lower_bound(x, y);
+                let (cost, path) = shortest_path(a, b, forward, backward, lower_bound).unwrap();
+                assert!((cost - exact[b.index()]).abs() < 1e-6);
+                assert_eq!((path[0], *path.last().unwrap()), (a, b));
+                let walked: f64 = path
+                    .windows(2)
+                    .map(|p| forward(p[0]).iter().find(|r| r.0 == p[1]).unwrap().1)
+                    .sum();
+                assert!((walked - cost).abs() < 1e-6);
+            }
+        }
+        // nodes beyond the network have no bounds and cannot be reached
+        let outside = NodeIndex::new(cols * rows);
+        assert_eq!(landmarks.lower_bound(node(0, 0), outside), 0.0);
+        assert_eq!(
+            shortest_path(node(0, 0), outside, forward, backward, |_, _| 0.0),
+            None
+        );
+    }
+}
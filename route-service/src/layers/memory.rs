@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+
+/// How much of the road and grid networks of a city is kept in memory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryMode {
+    /// Road graph and Origin-Destination links are loaded in full
+    #[default]
+    Standard,
+    /// The road adjacency is memory-mapped from a file in the city cache and the
+    /// Origin-Destination links are read from the database one origin zone at a time, for
+    /// cities too large to fit in the memory of a small server. Path searches and demand
+    /// lookups are slower.
+    Low,
+}
+
+/// What the road network keeps in memory
+#[derive(Serialize, Clone, Debug)]
+pub struct RoadMemoryStats {
+    pub nodes: usize,
+    pub edges: usize,
+    /// Edges held in the in-memory graph, 0 when the adjacency is mapped
+    pub edges_in_memory: usize,
+    /// File the adjacency is mapped from
+    pub adjacency_file: Option<String>,
+    pub mapped_bytes: usize,
+    /// Landmarks bounding path costs and the bytes their costs take
+    pub landmarks: usize,
+    pub landmark_bytes: usize,
+    pub cached_paths: usize,
+    pub max_cached_paths: usize,
+}
+
+/// What the grid network keeps in memory
+#[derive(Serialize, Clone, Debug)]
+pub struct GridMemoryStats {
+    pub zones: usize,
+    /// Links held in the in-memory graph, 0 when links are loaded lazily
+    pub links_in_memory: usize,
+    pub lazy_links: Option<LazyLinkStats>,
+}
+
+/// Use of the cache of lazily loaded Origin-Destination links
+#[derive(Serialize, Clone, Debug)]
+pub struct LazyLinkStats {
+    /// Origin zones whose links are cached
+    pub cached_origins: usize,
+    pub capacity: usize,
+    /// Links of the cached origin zones
+    pub cached_links: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Memory used by the process and how the city is kept in it
+#[derive(Serialize, Clone, Debug)]
+pub struct MemoryReport {
+    pub mode: MemoryMode,
+    /// Resident set size now, `None` where it cannot be read
+    pub rss_kb: Option<u64>,
+    /// Largest resident set size so far
+    pub max_rss_kb: u64,
+    pub road: RoadMemoryStats,
+    pub grid: GridMemoryStats,
+}
+
+/// Resident set size of the process in KiB, read from `/proc/self/statm`
+#[cfg(unix)]
+pub fn current_rss_kb() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf only reads a configuration value
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    (page_size > 0).then(|| pages * page_size as u64 / 1024)
+}
+
+#[cfg(not(unix))]
+pub fn current_rss_kb() -> Option<u64> {
+    None
+}
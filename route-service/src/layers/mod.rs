@@ -1,6 +1,23 @@
+pub mod boundary;
 pub mod city;
+pub mod city_profile;
+pub mod data_info;
+pub mod demo_city;
 pub mod error;
 pub mod geo_util;
 pub mod grid;
+pub mod import_report;
+pub mod isochrone;
+pub mod landmarks;
+pub mod memory;
+pub mod raster;
+pub mod road_adjacency;
 pub mod road_network;
+pub mod router;
+pub mod skim;
+pub mod stations;
+pub mod stop_infrastructure;
 pub mod transit_network;
+pub mod turn_restrictions;
+pub mod vehicle;
+pub mod zone_coverage;
@@ -0,0 +1,370 @@
+use geo::Contains;
+use geo_types::Point;
+use petgraph::graph::NodeIndex;
+use std::collections::HashMap;
+
+use super::{error::Error, geo_util, grid::GridNetwork};
+
+/// Value of pixels outside of every zone
+pub const NODATA: f32 = -9999.0;
+
+/// Most pixels a raster may have, to keep a fine resolution over a large city from exhausting
+/// memory
+pub const MAX_PIXELS: usize = 25_000_000;
+
+/// Single band raster in WGS84 longitude/latitude
+pub struct Raster {
+    pub width: usize,
+    pub height: usize,
+    /// Longitude of the left edge of the raster
+    pub min_x: f64,
+    /// Latitude of the top edge of the raster
+    pub max_y: f64,
+    /// Width of a pixel in degrees of longitude
+    pub pixel_width: f64,
+    /// Height of a pixel in degrees of latitude
+    pub pixel_height: f64,
+    /// Pixel values row by row from the top left, `NODATA` outside of the zones
+    pub data: Vec<f32>,
+}
+
+impl Raster {
+    /// Burn per-zone values into a raster covering the grid
+    ///
+    /// # Parameters
+    /// - `grid`: Zones to rasterize
+    /// - `values`: Value of each zone, zones without a value are `NODATA`
+    /// - `resolution_m`: Approximate pixel size in meters, converted to degrees at the center
+    ///   latitude of the grid
+    ///
+    /// # Returns
+    /// A raster with the value of the zone containing the center of each pixel
+    pub fn rasterize(
+        grid: &GridNetwork,
+        values: &HashMap<NodeIndex, f64>,
+        resolution_m: f64,
+    ) -> Result<Raster, Error> {
+        if resolution_m.is_nan() || resolution_m <= 0.0 {
+            return Err(Error::Error(format!(
+                "Resolution must be positive, got {}",
+                resolution_m
+            )));
+        }
+        let envelope = grid.rtree.root().envelope();
+        let (lower, upper) = (envelope.lower(), envelope.upper());
+        if grid.graph.node_count() == 0 || lower[0] > upper[0] || lower[1] > upper[1] {
+            return Err(Error::Error("Grid has no zones to rasterize".to_string()));
+        }
+
+        let center_lat = (lower[1] + upper[1]) / 2.0;
+        let center_lon = (lower[0] + upper[0]) / 2.0;
+        let pixel = geo_util::compute_envelope(center_lat, center_lon, resolution_m / 2.0);
+        let pixel_width = pixel.upper()[0] - pixel.lower()[0];
+        let pixel_height = pixel.upper()[1] - pixel.lower()[1];
+
+        let width = (((upper[0] - lower[0]) / pixel_width).ceil() as usize).max(1);
+        let height = (((upper[1] - lower[1]) / pixel_height).ceil() as usize).max(1);
+        if width.saturating_mul(height) > MAX_PIXELS {
+            return Err(Error::Error(format!(
+                "A {}m raster would have {}x{} pixels, more than the maximum of {}",
+                resolution_m, width, height, MAX_PIXELS
+            )));
+        }
+
+        let mut data = vec![NODATA; width * height];
+        for row in 0..height {
+            let y = upper[1] - (row as f64 + 0.5) * pixel_height;
+            for col in 0..width {
+                let x = lower[0] + (col as f64 + 0.5) * pixel_width;
+                let point = Point::new(x, y);
+                let zone = grid
+                    .rtree
+                    .locate_all_at_point(&[x, y])
+                    .map(|node| node.get_node_index())
+                    .find(|&n| grid.get_zone(n).polygon.contains(&point));
+                if let Some(value) = zone.and_then(|z| values.get(&z)) {
+                    data[row * width + col] = *value as f32;
+                }
+            }
+        }
+
+        Ok(Raster {
+            width,
+            height,
+            min_x: lower[0],
+            max_y: upper[1],
+            pixel_width,
+            pixel_height,
+            data,
+        })
+    }
+
+    /// Encode the raster as an uncompressed single band Float32 GeoTIFF in EPSG:4326
+    pub fn to_geotiff(&self) -> Vec<u8> {
+        let mut tiff = TiffWriter::default();
+        tiff.long(256, self.width as u32); // ImageWidth
+        tiff.long(257, self.height as u32); // ImageLength
+        tiff.short(258, &[32]); // BitsPerSample
+        tiff.short(259, &[1]); // Compression: none
+        tiff.short(262, &[1]); // PhotometricInterpretation: BlackIsZero
+        tiff.long(273, 0); // StripOffsets, patched once the layout is known
+        tiff.short(277, &[1]); // SamplesPerPixel
+        tiff.long(278, self.height as u32); // RowsPerStrip
+        tiff.long(279, (self.data.len() * 4) as u32); // StripByteCounts
+        tiff.short(284, &[1]); // PlanarConfiguration: chunky
+        tiff.short(339, &[3]); // SampleFormat: IEEE floating point
+        tiff.double(33550, &[self.pixel_width, self.pixel_height, 0.0]); // ModelPixelScale
+        tiff.double(33922, &[0.0, 0.0, 0.0, self.min_x, self.max_y, 0.0]); // ModelTiepoint
+        tiff.short(
+            34735, // GeoKeyDirectory
+            &[
+                1, 1, 0, 3, // version 1.1.0 with 3 keys
+                1024, 0, 1, 2, // GTModelType: geographic
+                1025, 0, 1, 1, // GTRasterType: pixel is area
+                2048, 0, 1, 4326, // GeographicType: WGS84
+            ],
+        );
+        tiff.ascii(42113, &NODATA.to_string()); // GDAL_NODATA
+
+        let image: Vec<u8> = self.data.iter().flat_map(|v| v.to_le_bytes()).collect();
+        tiff.finish(&image)
+    }
+
+    /// Read an ESRI ASCII grid in WGS84 longitude/latitude, e.g. a digital elevation model
+    ///
+    /// The header gives `ncols`, `nrows`, `xllcorner` or `xllcenter`, `yllcorner` or
+    /// `yllcenter`, `cellsize` and optionally `nodata_value`, followed by the values row by
+    /// row from the top. Cells equal to `nodata_value` are `NODATA`.
+    pub fn read_ascii_grid(text: &str) -> Result<Raster, Error> {
+        let invalid = |reason: String| Error::Error(format!("Invalid ASCII grid: {}", reason));
+        let mut tokens = text.split_whitespace().peekable();
+        let mut header: HashMap<String, f64> = HashMap::new();
+        while let Some(key) = tokens.next_if(|t| t.starts_with(|c: char| c.is_ascii_alphabetic())) {
+            let value = tokens
+                .next()
+                .and_then(|v| v.parse::<f64>().ok())
+                .ok_or_else(|| invalid(format!("no value for {}", key)))?;
+            header.insert(key.to_ascii_lowercase(), value);
+        }
+        let field = |key: &str| {
+            header
+                .get(key)
+                .copied()
+                .ok_or_else(|| invalid(format!("missing {}", key)))
+        };
+        let (width, height) = (field("ncols")? as usize, field("nrows")? as usize);
+        let cellsize = field("cellsize")?;
+        if width == 0 || height == 0 || width.saturating_mul(height) > MAX_PIXELS {
+            return Err(invalid(format!("{}x{} cells", width, height)));
+        }
+        if cellsize.is_nan() || cellsize <= 0.0 {
+            return Err(invalid(format!("cell size {}", cellsize)));
+        }
+        // centers are half a cell inside the corners
+        let min_x =
+            field("xllcorner").or_else(|_| field("xllcenter").map(|x| x - cellsize / 2.0))?;
+        let min_y =
+            field("yllcorner").or_else(|_| field("yllcenter").map(|y| y - cellsize / 2.0))?;
+        let nodata = header.get("nodata_value").copied();
+
+        let data = tokens
+            .map(|t| match t.parse::<f64>() {
+                Ok(v) if Some(v) == nodata => Ok(NODATA),
+                Ok(v) => Ok(v as f32),
+                Err(_) => Err(invalid(format!("value {}", t))),
+            })
+            .collect::<Result<Vec<f32>, Error>>()?;
+        if data.len() != width * height {
+            return Err(invalid(format!(
+                "{} values for {}x{} cells",
+                data.len(),
+                width,
+                height
+            )));
+        }
+        Ok(Raster {
+            width,
+            height,
+            min_x,
+            max_y: min_y + height as f64 * cellsize,
+            pixel_width: cellsize,
+            pixel_height: cellsize,
+            data,
+        })
+    }
+
+    /// Value of the pixel containing a point, `None` outside of the raster or on `NODATA`
+    pub fn value_at(&self, x: f64, y: f64) -> Option<f32> {
+        let col = ((x - self.min_x) / self.pixel_width).floor();
+        let row = ((self.max_y - y) / self.pixel_height).floor();
+        if col < 0.0 || row < 0.0 || col >= self.width as f64 || row >= self.height as f64 {
+            return None;
+        }
+        let value = self.data[row as usize * self.width + col as usize];
+        (value != NODATA).then_some(value)
+    }
+}
+
+/// Field types of the TIFF tags written by `TiffWriter`
+const TIFF_ASCII: u16 = 2;
+const TIFF_SHORT: u16 = 3;
+const TIFF_LONG: u16 = 4;
+const TIFF_DOUBLE: u16 = 12;
+
+/// Minimal little-endian TIFF writer for a single image stored in one strip
+#[derive(Default)]
+struct TiffWriter {
+    /// Tag, field type, value count and value bytes of each entry
+    entries: Vec<(u16, u16, u32, Vec<u8>)>,
+}
+
+impl TiffWriter {
+    fn short(&mut self, tag: u16, values: &[u16]) {
+        let bytes = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.entries
+            .push((tag, TIFF_SHORT, values.len() as u32, bytes));
+    }
+
+    fn long(&mut self, tag: u16, value: u32) {
+        self.entries
+            .push((tag, TIFF_LONG, 1, value.to_le_bytes().to_vec()));
+    }
+
+    fn double(&mut self, tag: u16, values: &[f64]) {
+        let bytes = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.entries
+            .push((tag, TIFF_DOUBLE, values.len() as u32, bytes));
+    }
+
+    fn ascii(&mut self, tag: u16, value: &str) {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        self.entries
+            .push((tag, TIFF_ASCII, bytes.len() as u32, bytes));
+    }
+
+    /// Lay out the header, the directory, the values that do not fit in their entry and the
+    /// image, in that order
+    fn finish(mut self, image: &[u8]) -> Vec<u8> {
+        self.entries.sort_by_key(|e| e.0);
+
+        let ifd_offset = 8;
+        let ifd_size = 2 + self.entries.len() * 12 + 4;
+        let mut data_offset = ifd_offset + ifd_size;
+        let mut offsets = Vec::with_capacity(self.entries.len());
+        for (_, _, _, bytes) in &self.entries {
+            if bytes.len() > 4 {
+                offsets.push(Some(data_offset));
+                data_offset += bytes.len() + bytes.len() % 2;
+            } else {
+                offsets.push(None);
+            }
+        }
+        let image_offset = data_offset;
+        for entry in self.entries.iter_mut().filter(|e| e.0 == 273) {
+            entry.3 = (image_offset as u32).to_le_bytes().to_vec();
+        }
+
+        let mut out = Vec::with_capacity(image_offset + image.len());
+        out.extend_from_slice(b"II");
+        out.extend_from_slice(&42u16.to_le_bytes());
+        out.extend_from_slice(&(ifd_offset as u32).to_le_bytes());
+
+        out.extend_from_slice(&(self.entries.len() as u16).to_le_bytes());
+        for ((tag, field_type, count, bytes), offset) in self.entries.iter().zip(&offsets) {
+            out.extend_from_slice(&tag.to_le_bytes());
+            out.extend_from_slice(&field_type.to_le_bytes());
+            out.extend_from_slice(&count.to_le_bytes());
+            match offset {
+                Some(offset) => out.extend_from_slice(&(*offset as u32).to_le_bytes()),
+                None => {
+                    let mut inline = [0u8; 4];
+                    inline[..bytes.len()].copy_from_slice(bytes);
+                    out.extend_from_slice(&inline);
+                }
+            }
+        }
+        out.extend_from_slice(&0u32.to_le_bytes()); // no next directory
+
+        for (_, _, _, bytes) in self.entries.iter().filter(|e| e.3.len() > 4) {
+            out.extend_from_slice(bytes);
+            if bytes.len() % 2 == 1 {
+                out.push(0);
+            }
+        }
+        out.extend_from_slice(image);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u16(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]])
+    }
+
+    fn read_u32(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn geotiff_strip_points_at_pixels() {
+        let raster = Raster {
+            width: 3,
+            height: 2,
+            min_x: -79.5,
+            max_y: 43.7,
+            pixel_width: 0.001,
+            pixel_height: 0.001,
+            data: vec![1.0, 2.0, NODATA, 4.0, 5.0, 6.5],
+        };
+        let tiff = raster.to_geotiff();
+        assert_eq!(&tiff[..4], b"II*\0");
+
+        let ifd = read_u32(&tiff, 4) as usize;
+        let entries = read_u16(&tiff, ifd) as usize;
+        let tags: Vec<u16> = (0..entries)
+            .map(|i| read_u16(&tiff, ifd + 2 + i * 12))
+            .collect();
+        assert!(tags.windows(2).all(|w| w[0] < w[1]), "tags must be sorted");
+
+        let entry = |tag: u16| {
+            let i = tags.iter().position(|&t| t == tag).unwrap();
+            ifd + 2 + i * 12
+        };
+        assert_eq!(read_u32(&tiff, entry(256) + 8), 3);
+        assert_eq!(read_u32(&tiff, entry(257) + 8), 2);
+
+        let strip = read_u32(&tiff, entry(273) + 8) as usize;
+        assert_eq!(read_u32(&tiff, entry(279) + 8) as usize, 6 * 4);
+        assert_eq!(strip + 6 * 4, tiff.len());
+        let pixels: Vec<f32> = tiff[strip..]
+            .chunks(4)
+            .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        assert_eq!(pixels, raster.data);
+
+        let tiepoint = read_u32(&tiff, entry(33922) + 8) as usize;
+        let x = f64::from_le_bytes(tiff[tiepoint + 24..tiepoint + 32].try_into().unwrap());
+        assert_eq!(x, -79.5);
+    }
+
+    #[test]
+    fn reads_ascii_grid_values_at_points() {
+        let text = "ncols 3\nnrows 2\nxllcorner -122.5\nyllcorner 37.7\ncellsize 0.01\n\
+                    NODATA_value -1\n10 20 30\n40 -1 60\n";
+        let raster = Raster::read_ascii_grid(text).unwrap();
+        assert_eq!((raster.width, raster.height), (3, 2));
+        assert!((raster.max_y - 37.72).abs() < 1e-9);
+        assert_eq!(raster.value_at(-122.495, 37.715), Some(10.0));
+        assert_eq!(raster.value_at(-122.475, 37.705), Some(60.0));
+        assert_eq!(raster.value_at(-122.485, 37.705), None);
+        assert_eq!(raster.value_at(-122.6, 37.705), None);
+        assert!(Raster::read_ascii_grid(
+            "ncols 3\nnrows 2\nxllcorner 0\nyllcorner 0\ncellsize 1\n1 2"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use memmap2::Mmap;
+use petgraph::graph::NodeIndex;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Identifies adjacency files, followed by the format version
+const MAGIC: &[u8; 4] = b"RADJ";
+const VERSION: u32 = 2;
+/// Magic, version, node count and edge count
+const HEADER_BYTES: usize = 16;
+
+/// Outgoing and incoming roads of every intersection in compressed sparse row form,
+/// memory-mapped from a file so that only the pages a path search touches are kept in memory
+///
+/// After the header the file holds, in little endian:
+/// - the osmid of every node (u64), to tell whether the file matches the nodes it is used with
+/// - the index of the first outgoing edge of every node, then the number of edges (u32)
+/// - the target node of every edge (u32)
+/// - the length of every edge in meters (f32)
+/// - the same three sections for the incoming edges of every node, the source node in place
+///   of the target
+pub struct RoadAdjacency {
+    map: Mmap,
+    nodes: usize,
+    edges: usize,
+}
+
+impl RoadAdjacency {
+    /// Write the adjacency of a road network
+    ///
+    /// # Parameters
+    /// - `path`: File to write, replaced at once so that mapped copies of it stay valid
+    /// - `osmids`: osmid of each node by node index
+    /// - `edges`: Source, target and length in meters of every edge
+    pub fn write(path: &Path, osmids: &[u64], edges: &[(u32, u32, f32)]) -> io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&tmp_path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&(osmids.len() as u32).to_le_bytes())?;
+        out.write_all(&(edges.len() as u32).to_le_bytes())?;
+        for osmid in osmids {
+            out.write_all(&osmid.to_le_bytes())?;
+        }
+        let outgoing: Vec<(u32, u32, f32)> = edges.to_vec();
+        let incoming: Vec<(u32, u32, f32)> =
+            edges.iter().map(|&(from, to, m)| (to, from, m)).collect();
+        for mut rows in [outgoing, incoming] {
+            rows.sort_by_key(|(node, _, _)| *node);
+            let mut next = 0;
+            for node in 0..=osmids.len() as u32 {
+                while next < rows.len() && rows[next].0 < node {
+                    next += 1;
+                }
+                out.write_all(&(next as u32).to_le_bytes())?;
+            }
+            for (_, other, _) in &rows {
+                out.write_all(&other.to_le_bytes())?;
+            }
+            for (_, _, meters) in &rows {
+                out.write_all(&meters.to_le_bytes())?;
+            }
+        }
+        out.into_inner()?.sync_all()?;
+        std::fs::rename(tmp_path, path)
+    }
+
+    /// Map an adjacency file, failing if it does not belong to the given nodes
+    pub fn open(path: &Path, osmids: &[u64]) -> io::Result<RoadAdjacency> {
+        let file = File::open(path)?;
+        // SAFETY: adjacency files are only written through `write`, which renames a new file
+        // over the old one instead of modifying it, so the mapped bytes never change
+        let map = unsafe { Mmap::map(&file)? };
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+        if map.len() < HEADER_BYTES || &map[..4] != MAGIC {
+            return Err(invalid("not a road adjacency file"));
+        }
+        let word = |at: usize| u32::from_le_bytes(map[at..at + 4].try_into().unwrap());
+        if word(4) != VERSION {
+            return Err(invalid("unsupported road adjacency version"));
+        }
+        let (nodes, edges) = (word(8) as usize, word(12) as usize);
+        let expected = HEADER_BYTES + nodes * 8 + 2 * ((nodes + 1) * 4 + edges * 8);
+        if nodes != osmids.len() || map.len() != expected {
+            return Err(invalid(
+                "road adjacency file does not match the road network",
+            ));
+        }
+        let adjacency = RoadAdjacency { map, nodes, edges };
+        if (0..nodes).any(|i| adjacency.osmid(i) != osmids[i]) {
+            return Err(invalid(
+                "road adjacency file does not match the road network",
+            ));
+        }
+        Ok(adjacency)
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges
+    }
+
+    /// Size of the mapped file in bytes
+    pub fn mapped_bytes(&self) -> usize {
+        self.map.len()
+    }
+
+    fn u32_at(&self, at: usize) -> u32 {
+        u32::from_le_bytes(self.map[at..at + 4].try_into().unwrap())
+    }
+
+    fn osmid(&self, node: usize) -> u64 {
+        let at = HEADER_BYTES + node * 8;
+        u64::from_le_bytes(self.map[at..at + 8].try_into().unwrap())
+    }
+
+    /// Target and length in meters of the roads leaving a node
+    pub fn neighbors(&self, node: NodeIndex) -> impl Iterator<Item = (NodeIndex, f64)> + '_ {
+        self.roads(HEADER_BYTES + self.nodes * 8, node)
+    }
+
+    /// Source and length in meters of the roads entering a node
+    pub fn incoming(&self, node: NodeIndex) -> impl Iterator<Item = (NodeIndex, f64)> + '_ {
+        self.roads(
+            HEADER_BYTES + self.nodes * 8 + (self.nodes + 1) * 4 + self.edges * 8,
+            node,
+        )
+    }
+
+    /// Other node and length of the roads of a node, from the section starting at `offsets`
+    fn roads(
+        &self,
+        offsets: usize,
+        node: NodeIndex,
+    ) -> impl Iterator<Item = (NodeIndex, f64)> + '_ {
+        let others = offsets + (self.nodes + 1) * 4;
+        let lengths = others + self.edges * 4;
+        let (start, end) = match node.index() < self.nodes {
+            true => (
+                self.u32_at(offsets + node.index() * 4) as usize,
+                self.u32_at(offsets + node.index() * 4 + 4) as usize,
+            ),
+            false => (0, 0),
+        };
+        (start..end).map(move |edge| {
+            let other = self.u32_at(others + edge * 4);
+            let meters = f32::from_le_bytes(
+                self.map[lengths + edge * 4..lengths + edge * 4 + 4]
+                    .try_into()
+                    .unwrap(),
+            );
+            (NodeIndex::new(other as usize), meters as f64)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::landmarks;
+
+    #[test]
+    fn maps_adjacency_and_finds_shortest_paths() {
+        let path = std::env::temp_dir().join(format!("road_adj_{}.adj", std::process::id()));
+        let osmids = [10, 11, 12, 13];
+        // 0 -> 1 -> 3 is shorter than 0 -> 2 -> 3, 3 has no outgoing roads
+        let edges = [(2, 3, 5.0), (0, 1, 1.0), (1, 3, 2.0), (0, 2, 1.0)];
+        RoadAdjacency::write(&path, &osmids, &edges).unwrap();
+        let adjacency = RoadAdjacency::open(&path, &osmids).unwrap();
+        assert!(RoadAdjacency::open(&path, &[10, 11, 12, 99]).is_err());
+        assert_eq!(adjacency.edge_count(), 4);
+
+        let node = NodeIndex::new;
+        let mut out: Vec<(NodeIndex, f64)> = adjacency.neighbors(node(0)).collect();
+        out.sort_by_key(|(n, _)| *n);
+        assert_eq!(out, vec![(node(1), 1.0), (node(2), 1.0)]);
+        assert_eq!(adjacency.neighbors(node(3)).count(), 0);
+        let mut into: Vec<(NodeIndex, f64)> = adjacency.incoming(node(3)).collect();
+        into.sort_by_key(|(n, _)| *n);
+        assert_eq!(into, vec![(node(1), 2.0), (node(2), 5.0)]);
+        assert_eq!(adjacency.incoming(node(0)).count(), 0);
+
+        let shortest_path = |from, to, cost: &dyn Fn(NodeIndex, NodeIndex, f64) -> f64| {
+            landmarks::shortest_path(
+                from,
+                to,
+                |n| {
+                    adjacency
+                        .neighbors(n)
+                        .map(|(m, d)| (m, cost(n, m, d)))
+                        .collect()
+                },
+                |n| {
+                    adjacency
+                        .incoming(n)
+                        .map(|(m, d)| (m, cost(m, n, d)))
+                        .collect()
+                },
+                |_, _| 0.0,
+            )
+        };
+        assert_eq!(
+            shortest_path(node(0), node(3), &|_, _, m| m),
+            Some((3.0, vec![node(0), node(1), node(3)]))
+        );
+        // a costly road is avoided for a longer one
+        let avoid_1_3 = |from, to, m| match (from, to) == (node(1), node(3)) {
+            true => m * 10.0,
+            false => m,
+        };
+        assert_eq!(
+            shortest_path(node(0), node(3), &avoid_1_3),
+            Some((6.0, vec![node(0), node(2), node(3)]))
+        );
+        assert_eq!(shortest_path(node(3), node(0), &|_, _, m| m), None);
+        std::fs::remove_file(&path).ok();
+    }
+}
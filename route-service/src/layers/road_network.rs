@@ -1,13 +1,25 @@
 use geo::{algorithm::Length, Distance, Haversine};
 use geo_types::{LineString, Point};
-use petgraph::{algo::astar, graph::NodeIndex, visit::EdgeRef, Directed, Graph};
+use petgraph::{graph::NodeIndex, visit::EdgeRef, Directed, Direction, Graph};
 use rstar::{PointDistance, RTree, RTreeObject, AABB};
-use rusqlite::{params, Connection, Result};
+use rusqlite::{params, types::ValueRef, Connection, Result};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, collections::HashSet, str::FromStr};
+use std::{
+    cmp::Reverse, collections::BinaryHeap, collections::HashMap, collections::HashSet, path::Path,
+    str::FromStr, sync::RwLock, time::Instant,
+};
 use wkt::Wkt;
 
-use super::geo_util;
+use super::{
+    error::Error,
+    geo_util,
+    landmarks::{self, Landmarks},
+    memory::RoadMemoryStats,
+    raster::Raster,
+    road_adjacency::RoadAdjacency,
+    turn_restrictions::TurnRestrictions,
+};
+use crate::ordering::{self, OrdF64};
 
 // Layer 2 - Graph data strcture to store the nodes and edges of a city street network
 #[derive(Deserialize, Serialize)]
@@ -18,24 +30,176 @@ pub struct RoadNetwork {
     graph: Graph<Node, Edge>,
     /// osmid -> node index mapping
     node_map: HashMap<u64, NodeIndex>,
+    /// Costs to and from a few landmark nodes bounding path costs, built once when the roads
+    /// are loaded and kept in the city cache
+    landmarks: Landmarks,
+    /// Turns buses may not make, see `turn_allowed`
+    restrictions: TurnRestrictions,
+    /// Shortest paths already computed, shared by the optimizer and shape generation
+    #[serde(skip)]
+    path_cache: RwLock<HashMap<(NodeIndex, NodeIndex), RoadPath>>,
+    /// Roads between the nodes when they are memory-mapped instead of held in `graph`, see
+    /// `load_mapped`
+    #[serde(skip)]
+    adjacency: Option<(String, RoadAdjacency)>,
 }
 
+/// Length in meters and nodes of a path through the road network
+type RoadPath = (f64, Vec<NodeIndex>);
+
+/// Most paths kept in the path cache, new paths are computed but not cached past this
+const MAX_CACHED_PATHS: usize = 500_000;
+/// Most paths kept in the path cache of a road network with a memory-mapped adjacency
+const MAX_CACHED_PATHS_MAPPED: usize = 50_000;
+/// Landmarks picked to bound path costs, each keeps two costs of every node
+const LANDMARKS: usize = 8;
+
+/// Steepest grade a bus climbs or descends without the road being counted as impractical
+pub const MAX_BUS_GRADE: f64 = 0.10;
+/// Extra cost of a road per unit of grade, a 5% grade costs 25% more than a flat road
+const GRADE_PENALTY: f64 = 5.0;
+/// Cost multiplier of roads steeper than `MAX_BUS_GRADE`, so paths only take them when there
+/// is no reasonable detour
+const STEEP_ROAD_FACTOR: f64 = 10.0;
+/// Roads shorter than this in meters have no grade, their elevations are too close to tell
+const MIN_GRADE_LENGTH_M: f64 = 1.0;
+
 impl RoadNetwork {
     pub fn print_stats(&self) {
         println!("Road network:");
         println!("  Nodes: {}", self.graph.node_count());
-        println!("  Edges: {}", self.graph.edge_count());
+        println!("  Edges: {}", self.edge_count());
+        if let Some((path, adjacency)) = &self.adjacency {
+            println!(
+                "  Mapped from: {} ({} bytes)",
+                path,
+                adjacency.mapped_bytes()
+            );
+        }
+        println!("  Cached paths: {}", self.path_cache.read().unwrap().len());
+    }
+
+    pub fn edge_count(&self) -> usize {
+        match &self.adjacency {
+            Some((_, adjacency)) => adjacency.edge_count(),
+            None => self.graph.edge_count(),
+        }
+    }
+
+    fn max_cached_paths(&self) -> usize {
+        match self.adjacency {
+            Some(_) => MAX_CACHED_PATHS_MAPPED,
+            None => MAX_CACHED_PATHS,
+        }
+    }
+
+    pub fn memory_stats(&self) -> RoadMemoryStats {
+        RoadMemoryStats {
+            nodes: self.graph.node_count(),
+            edges: self.edge_count(),
+            edges_in_memory: self.graph.edge_count(),
+            adjacency_file: self.adjacency.as_ref().map(|(path, _)| path.clone()),
+            mapped_bytes: self.adjacency.as_ref().map_or(0, |(_, a)| a.mapped_bytes()),
+            landmarks: self.landmarks.len(),
+            landmark_bytes: self.landmarks.bytes(),
+            cached_paths: self.path_cache.read().unwrap().len(),
+            max_cached_paths: self.max_cached_paths(),
+        }
     }
 
     pub fn get_node(&self, node_index: NodeIndex) -> &Node {
         &self.graph[node_index]
     }
 
-    pub fn load(dbname: &str) -> Result<RoadNetwork> {
+    pub fn load(dbname: &str) -> Result<RoadNetwork, Error> {
         let conn = Connection::open(dbname)?;
+        let mut road = RoadNetwork::load_nodes(&conn, dbname)?;
+        for edge in read_edges(&conn)? {
+            if let (Some(&from_node), Some(&to_node)) =
+                (road.node_map.get(&edge.u), road.node_map.get(&edge.v))
+            {
+                let _ = road.graph.add_edge(from_node, to_node, edge);
+            }
+        }
+        road.restrictions = road.load_restrictions(&conn)?;
+        road.landmarks = road.build_landmarks();
+        Ok(road)
+    }
+
+    /// Load the nodes of a road network with its roads memory-mapped from an adjacency file
+    ///
+    /// Only the lengths of the roads are kept in the file, their geometry is not loaded. The
+    /// file is rebuilt from the database when it is missing, older than the database or does
+    /// not match its nodes.
+    ///
+    /// # Parameters
+    /// - `dbname`: The path to the database
+    /// - `adjacency_path`: The adjacency file, e.g. in the city cache
+    pub fn load_mapped(dbname: &str, adjacency_path: &str) -> Result<RoadNetwork, Error> {
+        let conn = Connection::open(dbname)?;
+        let mut road = RoadNetwork::load_nodes(&conn, dbname)?;
+        let osmids: Vec<u64> = road.graph.node_weights().map(|n| n.osmid).collect();
+
+        let modified = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let fresh = matches!(
+            (modified(adjacency_path), modified(dbname)),
+            (Some(adjacency), Some(db)) if adjacency >= db
+        );
+        let existing = fresh
+            .then(|| RoadAdjacency::open(Path::new(adjacency_path), &osmids))
+            .and_then(|adjacency| {
+                adjacency
+                    .map_err(|e| log::warn!("Rebuilding {}: {}", adjacency_path, e))
+                    .ok()
+            });
+        let adjacency = match existing {
+            Some(adjacency) => adjacency,
+            None => {
+                let start = Instant::now();
+                let edges: Vec<(u32, u32, f32)> = read_edges(&conn)?
+                    .into_iter()
+                    .filter_map(|edge| {
+                        let from = road.node_map.get(&edge.u)?.index() as u32;
+                        let to = road.node_map.get(&edge.v)?.index() as u32;
+                        Some((from, to, edge.geom.length::<Haversine>() as f32))
+                    })
+                    .collect();
+                if let Some(dir) = Path::new(adjacency_path).parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                RoadAdjacency::write(Path::new(adjacency_path), &osmids, &edges)?;
+                log::debug!(
+                    "Road adjacency of {} edges written to {} in {}ms",
+                    edges.len(),
+                    adjacency_path,
+                    start.elapsed().as_millis()
+                );
+                RoadAdjacency::open(Path::new(adjacency_path), &osmids)?
+            }
+        };
+        road.adjacency = Some((adjacency_path.to_string(), adjacency));
+        road.restrictions = road.load_restrictions(&conn)?;
+        road.landmarks = road.build_landmarks();
+        Ok(road)
+    }
 
-        let nodes = read_nodes(&conn)?;
-        let edges = read_edges(&conn)?;
+    /// A road network of the nodes in the database, without roads
+    ///
+    /// Nodes take their elevation from the `elevation` column of the nodes table. Those
+    /// without one take it from the digital elevation model next to the database, if there is
+    /// one, see `dem_path`.
+    fn load_nodes(conn: &Connection, dbname: &str) -> Result<RoadNetwork, Error> {
+        let mut nodes = read_nodes(conn)?;
+        let dem_path = dem_path(dbname);
+        if Path::new(&dem_path).exists() {
+            let dem = Raster::read_ascii_grid(&std::fs::read_to_string(&dem_path)?)?;
+            let mut filled = 0;
+            for node in nodes.iter_mut().filter(|n| n.elevation.is_none()) {
+                node.elevation = dem.value_at(node.geom.x(), node.geom.y());
+                filled += node.elevation.is_some() as usize;
+            }
+            log::debug!("Elevation of {} nodes read from {}", filled, dem_path);
+        }
 
         let mut rtree_nodes = RTree::<RTreeNode>::new();
         let mut graph = Graph::<Node, Edge, Directed>::new();
@@ -51,21 +215,43 @@ impl RoadNetwork {
             node_map.insert(graph[node_index].osmid, node_index);
         }
 
-        for edge in edges {
-            if let (Some(&from_node), Some(&to_node)) =
-                (node_map.get(&edge.u), node_map.get(&edge.v))
-            {
-                let _ = graph.add_edge(from_node, to_node, edge);
-            }
-        }
-
         Ok(RoadNetwork {
             rtree_nodes: rtree_nodes,
             graph: graph,
             node_map: node_map,
+            landmarks: Landmarks::default(),
+            restrictions: TurnRestrictions::default(),
+            path_cache: RwLock::new(HashMap::new()),
+            adjacency: None,
         })
     }
 
+    /// Turn restrictions of the database, once the roads they are resolved against are loaded
+    fn load_restrictions(&self, conn: &Connection) -> Result<TurnRestrictions, Error> {
+        let restrictions = TurnRestrictions::load(conn, &self.node_map, |node| {
+            self.roads_from(node)
+                .into_iter()
+                .map(|(next, _)| next)
+                .collect()
+        })?;
+        if !restrictions.is_empty() {
+            log::debug!("{} turns restricted", restrictions.len());
+        }
+        Ok(restrictions)
+    }
+
+    /// Whether a bus may go from `from` through `via` on to `to`: the turn is not restricted
+    /// and does not go back along the road it came on
+    pub fn turn_allowed(&self, from: NodeIndex, via: NodeIndex, to: NodeIndex) -> bool {
+        to != from && self.restrictions.allows(from, via, to)
+    }
+
+    /// Whether a bus may follow a path, making every turn along it
+    fn is_legal(&self, path: &[NodeIndex]) -> bool {
+        path.windows(3)
+            .all(|turn| self.turn_allowed(turn[0], turn[1], turn[2]))
+    }
+
     pub fn find_nearest_node(&self, x: f64, y: f64) -> Option<NodeIndex> {
         let point = [x, y];
         let nearest = self.rtree_nodes.nearest_neighbor(&point).unwrap();
@@ -79,12 +265,12 @@ impl RoadNetwork {
         for candidate in self.rtree_nodes.locate_in_envelope_intersecting(&envelope) {
             nearest_nodes.push(candidate.node_index);
         }
-        // sort by distance to x, y ascending
+        // sort by distance to x, y ascending, ties broken by node index
+        let p = &Point::new(x, y);
         nearest_nodes.sort_by(|a, b| {
-            let p = &Point::new(x, y);
             let a_dist = self.graph[*a].geom.distance_2(p);
             let b_dist = self.graph[*b].geom.distance_2(p);
-            a_dist.partial_cmp(&b_dist).unwrap()
+            ordering::cmp_f64(a_dist, b_dist).then_with(|| a.cmp(b))
         });
         nearest_nodes
     }
@@ -109,28 +295,272 @@ impl RoadNetwork {
         self.get_road_distance(from, to)
     }
 
+    /// Shortest road distance and path between two nodes, computed once per pair of nodes
     pub fn get_road_distance(&self, from: NodeIndex, to: NodeIndex) -> (f64, Vec<NodeIndex>) {
-        let heuristic = |n: NodeIndex| {
-            let a = self.graph[n].geom;
-            let b = self.graph[to].geom;
-            Haversine::distance(a, b)
+        if let Some(cached) = self.path_cache.read().unwrap().get(&(from, to)) {
+            return cached.clone();
+        }
+
+        let result = self.shortest_path(from, to);
+        let mut cache = self.path_cache.write().unwrap();
+        if cache.len() < self.max_cached_paths() {
+            cache.insert((from, to), result.clone());
+        }
+        result
+    }
+
+    /// Cheapest paths from a node to several targets, in a single search
+    ///
+    /// # Returns
+    /// The cost and nodes of the path to each target, targets that cannot be reached are left
+    /// out
+    pub fn paths_from(
+        &self,
+        from: NodeIndex,
+        targets: &[NodeIndex],
+    ) -> HashMap<NodeIndex, RoadPath> {
+        let mut left: HashSet<NodeIndex> = targets.iter().copied().collect();
+        let mut best: HashMap<NodeIndex, (f64, Option<NodeIndex>)> =
+            HashMap::from([(from, (0.0, None))]);
+        let mut heap = BinaryHeap::from([(Reverse(OrdF64(0.0)), from)]);
+        let mut paths = HashMap::new();
+        while let Some((Reverse(OrdF64(cost)), node)) = heap.pop() {
+            if cost > best[&node].0 {
+                continue;
+            }
+            if left.remove(&node) {
+                let mut path = vec![node];
+                while let Some(&(_, Some(prev))) = best.get(path.last().unwrap()) {
+                    path.push(prev);
+                }
+                path.reverse();
+                paths.insert(node, (cost, path));
+                if left.is_empty() {
+                    break;
+                }
+            }
+            for (next, road) in self.costs_from(node) {
+                let next_cost = cost + road;
+                if best.get(&next).is_some_and(|&(c, _)| c <= next_cost) {
+                    continue;
+                }
+                best.insert(next, (next_cost, Some(node)));
+                heap.push((Reverse(OrdF64(next_cost)), next));
+            }
+        }
+        // paths making a turn buses may not make are searched again turn by turn
+        let illegal: Vec<NodeIndex> = paths
+            .iter()
+            .filter(|(_, (_, path))| !self.is_legal(path))
+            .map(|(target, _)| *target)
+            .collect();
+        for target in illegal {
+            match self.restricted_path(from, target) {
+                Some(path) => paths.insert(target, path),
+                None => paths.remove(&target),
+            };
+        }
+        paths
+    }
+
+    /// Walk distance along the roads from the nearest of several sources to every node within
+    /// reach
+    ///
+    /// # Parameters
+    /// - `sources`: Start nodes and the distance already walked to reach them, in meters
+    /// - `max_m`: Farthest distance walked, in meters
+    ///
+    /// # Returns
+    /// The distance in meters to each reached node and the index in `sources` of the source
+    /// it is nearest to
+    pub fn walk_distances(
+        &self,
+        sources: &[(NodeIndex, f64)],
+        max_m: f64,
+    ) -> HashMap<NodeIndex, (f64, usize)> {
+        let mut best: HashMap<NodeIndex, (f64, usize)> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        for (source, &(node, meters)) in sources.iter().enumerate() {
+            if meters <= max_m && best.get(&node).is_none_or(|&(d, _)| meters < d) {
+                best.insert(node, (meters, source));
+                heap.push((Reverse(OrdF64(meters)), node, source));
+            }
+        }
+
+        while let Some((Reverse(OrdF64(distance)), node, source)) = heap.pop() {
+            if best[&node] != (distance, source) {
+                continue;
+            }
+            for (next, meters) in self.roads_from(node) {
+                let next_distance = distance + meters;
+                if next_distance > max_m
+                    || best.get(&next).is_some_and(|&(d, _)| d <= next_distance)
+                {
+                    continue;
+                }
+                best.insert(next, (next_distance, source));
+                heap.push((Reverse(OrdF64(next_distance)), next, source));
+            }
+        }
+        best
+    }
+
+    /// Target and length in meters of the roads leaving a node
+    fn roads_from(&self, node: NodeIndex) -> Vec<(NodeIndex, f64)> {
+        match &self.adjacency {
+            Some((_, adjacency)) => adjacency.neighbors(node).collect(),
+            None => self
+                .graph
+                .edges(node)
+                .map(|e| (e.target(), e.weight().geom.length::<Haversine>()))
+                .collect(),
+        }
+    }
+
+    /// Source and length in meters of the roads entering a node
+    fn roads_to(&self, node: NodeIndex) -> Vec<(NodeIndex, f64)> {
+        match &self.adjacency {
+            Some((_, adjacency)) => adjacency.incoming(node).collect(),
+            None => self
+                .graph
+                .edges_directed(node, Direction::Incoming)
+                .map(|e| (e.source(), e.weight().geom.length::<Haversine>()))
+                .collect(),
+        }
+    }
+
+    /// Target and cost of the roads leaving a node
+    fn costs_from(&self, node: NodeIndex) -> Vec<(NodeIndex, f64)> {
+        self.roads_from(node)
+            .into_iter()
+            .map(|(next, meters)| (next, self.road_cost(node, next, meters)))
+            .collect()
+    }
+
+    /// Source and cost of the roads entering a node
+    fn costs_to(&self, node: NodeIndex) -> Vec<(NodeIndex, f64)> {
+        self.roads_to(node)
+            .into_iter()
+            .map(|(prev, meters)| (prev, self.road_cost(prev, node, meters)))
+            .collect()
+    }
+
+    /// Pick the landmarks of the road network and compute their costs
+    fn build_landmarks(&self) -> Landmarks {
+        let start = Instant::now();
+        let landmarks = Landmarks::build(
+            self.graph.node_count(),
+            LANDMARKS,
+            |n| self.costs_from(n),
+            |n| self.costs_to(n),
+        );
+        log::debug!(
+            "{} landmarks of {} nodes computed in {}ms",
+            landmarks.len(),
+            self.graph.node_count(),
+            start.elapsed().as_millis()
+        );
+        landmarks
+    }
+
+    /// Cost of a road in meters, its length stretched by the grade between its nodes
+    ///
+    /// A road whose nodes lack an elevation costs its length. Penalties only ever add to the
+    /// length so that the straight line distance stays a lower bound of the cost.
+    fn road_cost(&self, from: NodeIndex, to: NodeIndex, meters: f64) -> f64 {
+        let (Some(a), Some(b)) = (self.graph[from].elevation, self.graph[to].elevation) else {
+            return meters;
         };
+        if meters < MIN_GRADE_LENGTH_M {
+            return meters;
+        }
+        let grade = (b - a).abs() as f64 / meters;
+        let cost = meters * (1.0 + GRADE_PENALTY * grade);
+        match grade > MAX_BUS_GRADE {
+            true => cost * STEEP_ROAD_FACTOR,
+            false => cost,
+        }
+    }
 
-        let edge_weight = |e: &Edge| e.geom.length::<Haversine>();
+    /// Lower bound of the cost of a path between two nodes, from the landmarks and the
+    /// straight line distance
+    fn lower_bound(&self, a: NodeIndex, b: NodeIndex) -> f64 {
+        let straight = Haversine::distance(self.graph[a].geom, self.graph[b].geom);
+        self.landmarks.lower_bound(a, b).max(straight)
+    }
 
-        let res = astar(
-            &self.graph,
+    /// Cheapest path between two nodes a bus may follow, by length with the roads' grade
+    /// penalties
+    ///
+    /// A bidirectional A* search bounded by the landmarks and the straight line distance, see
+    /// `landmarks::shortest_path`. Its path is searched again with `restricted_path` when it
+    /// makes a turn buses may not make.
+    fn shortest_path(&self, from: NodeIndex, to: NodeIndex) -> (f64, Vec<NodeIndex>) {
+        landmarks::shortest_path(
             from,
-            |node| node == to,
-            |e| edge_weight(e.weight()),
-            heuristic,
-        );
+            to,
+            |n| self.costs_from(n),
+            |n| self.costs_to(n),
+            |a, b| self.lower_bound(a, b),
+        )
+        .and_then(|path| match self.is_legal(&path.1) {
+            true => Some(path),
+            false => self.restricted_path(from, to),
+        })
+        .unwrap_or((0.0, vec![]))
+    }
 
-        if let Some((cost, path)) = res {
-            (cost, path)
-        } else {
-            (0.0, vec![])
+    /// Cheapest path between two nodes that only makes turns buses may make
+    ///
+    /// An A* search over the roads rather than the nodes, since the roads a path may take
+    /// from a node depend on the road it arrived on. Restrictions only make paths longer, so
+    /// the bounds of `shortest_path` still hold.
+    fn restricted_path(&self, from: NodeIndex, to: NodeIndex) -> Option<RoadPath> {
+        if from == to {
+            return Some((0.0, vec![from]));
         }
+        // a path is at a node having come from another, `NodeIndex::end()` at the start
+        let start = (NodeIndex::end(), from);
+        let mut best: HashMap<(NodeIndex, NodeIndex), (f64, NodeIndex)> =
+            HashMap::from([(start, (0.0, NodeIndex::end()))]);
+        let mut heap = BinaryHeap::from([(
+            Reverse(OrdF64(self.lower_bound(from, to))),
+            OrdF64(0.0),
+            start,
+        )]);
+        while let Some((_, OrdF64(cost), state)) = heap.pop() {
+            if cost > best[&state].0 {
+                continue;
+            }
+            let (prev, node) = state;
+            if node == to {
+                let mut path = vec![node];
+                let mut state = state;
+                while state.0 != NodeIndex::end() {
+                    path.push(state.0);
+                    state = (best[&state].1, state.0);
+                }
+                path.reverse();
+                return Some((cost, path));
+            }
+            for (next, road) in self.costs_from(node) {
+                if prev != NodeIndex::end() && !self.turn_allowed(prev, node, next) {
+                    continue;
+                }
+                let next_cost = cost + road;
+                let next_state = (node, next);
+                if best.get(&next_state).is_some_and(|&(c, _)| c <= next_cost) {
+                    continue;
+                }
+                best.insert(next_state, (next_cost, prev));
+                heap.push((
+                    Reverse(OrdF64(next_cost + self.lower_bound(next, to))),
+                    OrdF64(next_cost),
+                    next_state,
+                ));
+            }
+        }
+        None
     }
 }
 
@@ -139,6 +569,8 @@ pub struct Node {
     fid: u64,
     pub geom: Point,
     osmid: u64,
+    /// Height above sea level in meters, if known
+    pub elevation: Option<f32>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -177,26 +609,133 @@ struct Edge {
     osmid: u64,
 }
 
+/// Roads of the database, with both directions of the roads that are not one-way
 fn read_edges(conn: &Connection) -> Result<Vec<Edge>> {
-    let mut stmt = conn.prepare("SELECT fid, geom, u, v, key, osmid FROM edges")?;
+    let mut stmt = conn.prepare(match has_oneway(conn)? {
+        true => "SELECT fid, geom, u, v, key, osmid, oneway FROM edges",
+        false => "SELECT fid, geom, u, v, key, osmid, NULL FROM edges",
+    })?;
     let edge_iter = stmt.query_map(params![], |row| {
         let wkt_str: String = row.get(1)?;
         let wkt = Wkt::from_str(&wkt_str).unwrap();
         let line_string: LineString = wkt.try_into().unwrap();
-        Ok(Edge {
+        let edge = Edge {
             fid: row.get(0)?,
             geom: line_string,
             u: row.get(2)?,
             v: row.get(3)?,
             key: row.get(4)?,
             osmid: row.get(5)?,
-        })
+        };
+        Ok((edge, oneway_flag(row.get_ref(6)?)))
     })?;
-    Ok(Vec::from_iter(edge_iter.map(|x| x.unwrap())))
+    let edges: Vec<(Edge, Option<bool>)> = edge_iter.map(|x| x.unwrap()).collect();
+
+    let pairs: HashSet<(u64, u64)> = edges.iter().map(|(e, _)| (e.u, e.v)).collect();
+    let mut roads = Vec::with_capacity(edges.len());
+    for (edge, oneway) in edges {
+        if lacks_reverse(&pairs, edge.u, edge.v, oneway) {
+            let mut geom = edge.geom.clone();
+            geom.0.reverse();
+            roads.push(Edge {
+                fid: edge.fid,
+                geom,
+                u: edge.v,
+                v: edge.u,
+                key: edge.key,
+                osmid: edge.osmid,
+            });
+        }
+        roads.push(edge);
+    }
+    Ok(roads)
+}
+
+/// Roads of the ways turn restrictions start or end on, as the OSM ids of their two nodes and
+/// of their way, with both directions of the roads that are not one-way
+pub(super) fn read_restricted_roads(conn: &Connection) -> Result<Vec<(u64, u64, u64)>> {
+    let query = format!(
+        "SELECT u, v, osmid, {} FROM edges WHERE osmid IN \
+            (SELECT from_way FROM turn_restrictions UNION SELECT to_way FROM turn_restrictions)",
+        match has_oneway(conn)? {
+            true => "oneway",
+            false => "NULL",
+        }
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params![], |row| {
+        Ok((
+            row.get::<_, u64>(0)?,
+            row.get::<_, u64>(1)?,
+            row.get::<_, u64>(2)?,
+            oneway_flag(row.get_ref(3)?),
+        ))
+    })?;
+    let rows: Vec<(u64, u64, u64, Option<bool>)> = rows.collect::<Result<_>>()?;
+
+    let pairs: HashSet<(u64, u64)> = rows.iter().map(|&(u, v, ..)| (u, v)).collect();
+    let mut roads = vec![];
+    for (u, v, way, oneway) in rows {
+        roads.push((u, v, way));
+        if lacks_reverse(&pairs, u, v, oneway) {
+            roads.push((v, u, way));
+        }
+    }
+    Ok(roads)
+}
+
+/// Whether the edges table has a `oneway` column
+///
+/// Without it every row is a road in the direction from `u` to `v` only, as in OSMnx exports
+/// which have a row for each direction of two-way roads.
+fn has_oneway(conn: &Connection) -> Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('edges') WHERE name = 'oneway'",
+        params![],
+        |row| row.get(0),
+    )
+}
+
+/// One-way flag of a road, stored as a number or as text, `None` if it is missing
+fn oneway_flag(value: ValueRef) -> Option<bool> {
+    match value {
+        ValueRef::Integer(flag) => Some(flag != 0),
+        ValueRef::Text(flag) => match std::str::from_utf8(flag)
+            .ok()?
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "1" | "true" | "yes" => Some(true),
+            "0" | "false" | "no" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether a road flagged as two-way has no row for its other direction, which the network
+/// then gets as well
+fn lacks_reverse(pairs: &HashSet<(u64, u64)>, u: u64, v: u64, oneway: Option<bool>) -> bool {
+    oneway == Some(false) && !pairs.contains(&(v, u))
+}
+
+/// Digital elevation model of the city of a database, an ESRI ASCII grid next to it, e.g.
+/// `sf_dem.asc` for `sf.db`
+fn dem_path(dbname: &str) -> String {
+    let stem = dbname.strip_suffix(".db").unwrap_or(dbname);
+    format!("{}_dem.asc", stem)
 }
 
 fn read_nodes(conn: &Connection) -> Result<Vec<Node>> {
-    let mut stmt = conn.prepare("SELECT fid, geom, osmid FROM nodes")?;
+    let has_elevation: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('nodes') WHERE name = 'elevation'",
+        params![],
+        |row| row.get(0),
+    )?;
+    let mut stmt = conn.prepare(match has_elevation {
+        true => "SELECT fid, geom, osmid, elevation FROM nodes",
+        false => "SELECT fid, geom, osmid, NULL FROM nodes",
+    })?;
     let node_iter = stmt.query_map(params![], |row| {
         let wkt_str: String = row.get(1)?;
         let wkt = Wkt::from_str(&wkt_str).unwrap();
@@ -205,7 +744,196 @@ fn read_nodes(conn: &Connection) -> Result<Vec<Node>> {
             fid: row.get(0)?,
             geom: coord,
             osmid: row.get(2)?,
+            elevation: row.get::<_, Option<f64>>(3)?.map(|e| e as f32),
         })
     })?;
     Ok(Vec::from_iter(node_iter.map(|x| x.unwrap())))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::demo_city::{DemoCity, DemoCityConfig};
+
+    #[test]
+    fn steep_roads_cost_more_than_their_length() {
+        let demo = DemoCity::generate(&DemoCityConfig {
+            cols: 4,
+            rows: 4,
+            routes: 1,
+            ..Default::default()
+        })
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("road_elevation_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("demo.db");
+        let db = db_path.to_str().unwrap();
+        demo.write_db(db).unwrap();
+
+        let flat = RoadNetwork::load(db).unwrap();
+        let from = flat.get_node_index_by_osmid(1).unwrap();
+        let edge = flat.graph.edges(from).next().unwrap();
+        let (to, length) = (edge.target(), edge.weight().geom.length::<Haversine>());
+        assert_eq!(flat.get_road_distance(from, to), (length, vec![from, to]));
+
+        // a level DEM gives every node an elevation without penalizing any road
+        let (mut min_x, mut min_y) = (f64::MAX, f64::MAX);
+        for node in flat.graph.node_weights() {
+            (min_x, min_y) = (min_x.min(node.geom.x()), min_y.min(node.geom.y()));
+        }
+        let dem = format!(
+            "ncols 1\nnrows 1\nxllcorner {}\nyllcorner {}\ncellsize 1\n30\n",
+            min_x - 0.5,
+            min_y - 0.5
+        );
+        std::fs::write(dir.join("demo_dem.asc"), dem).unwrap();
+        let level = RoadNetwork::load(db).unwrap();
+        assert!(level
+            .graph
+            .node_weights()
+            .all(|n| n.elevation == Some(30.0)));
+        assert_eq!(level.get_road_distance(from, to).1, vec![from, to]);
+
+        // the db's elevations take precedence over the DEM
+        let conn = Connection::open(db).unwrap();
+        conn.execute("ALTER TABLE nodes ADD COLUMN elevation REAL", [])
+            .unwrap();
+        let osmid = flat.get_osmid_by_node_index(to);
+        let rise = 30.0 + length * 2.0 * MAX_BUS_GRADE;
+        conn.execute(
+            "UPDATE nodes SET elevation = ?1 WHERE osmid = ?2",
+            params![rise, osmid],
+        )
+        .unwrap();
+        drop(conn);
+        let adjacency = dir.join("demo_road.adj");
+        for hilly in [
+            RoadNetwork::load(db).unwrap(),
+            RoadNetwork::load_mapped(db, adjacency.to_str().unwrap()).unwrap(),
+        ] {
+            assert_eq!(hilly.get_node(to).elevation, Some(rise as f32));
+            assert_eq!(hilly.get_node(from).elevation, Some(30.0));
+            // every road into `to` climbs twice the steepest grade, so there is no detour
+            let (cost, path) = hilly.get_road_distance(from, to);
+            let steep = length * (1.0 + GRADE_PENALTY * 2.0 * MAX_BUS_GRADE) * STEEP_ROAD_FACTOR;
+            assert!((cost - steep).abs() < 0.1, "{} != {}", cost, steep);
+            assert_eq!(path, vec![from, to]);
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn paths_follow_one_way_roads_and_turn_restrictions() {
+        let demo = DemoCity::generate(&DemoCityConfig {
+            cols: 4,
+            rows: 4,
+            routes: 1,
+            ..Default::default()
+        })
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("road_turns_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("demo.db");
+        let db = db_path.to_str().unwrap();
+        demo.write_db(db).unwrap();
+
+        // a road from a to b and the road going straight on from b to c
+        let open = RoadNetwork::load(db).unwrap();
+        let a = open.get_node_index_by_osmid(1).unwrap();
+        let b = open.graph.edges(a).next().unwrap().target();
+        let geom = |n: NodeIndex| open.get_node(n).geom;
+        let c = open
+            .graph
+            .edges(b)
+            .map(|e| e.target())
+            .max_by(|x, y| {
+                let (dx, dy) = (geom(a).distance_2(&geom(*x)), geom(a).distance_2(&geom(*y)));
+                ordering::cmp_f64(dx, dy)
+            })
+            .unwrap();
+        let (straight, path) = open.get_road_distance(a, c);
+        assert_eq!(path, vec![a, b, c]);
+        assert!(!open.turn_allowed(a, b, a));
+
+        let conn = Connection::open(db).unwrap();
+        let way = |u: NodeIndex, v: NodeIndex| -> u64 {
+            conn.query_row(
+                "SELECT osmid FROM edges WHERE u = ?1 AND v = ?2",
+                params![
+                    open.get_osmid_by_node_index(u),
+                    open.get_osmid_by_node_index(v)
+                ],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        conn.execute_batch(
+            "CREATE TABLE turn_restrictions (
+                from_way INTEGER, via_node INTEGER, to_way INTEGER, restriction TEXT
+            )",
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO turn_restrictions VALUES (?1, ?2, ?3, 'no_straight_on')",
+            params![way(a, b), open.get_osmid_by_node_index(b), way(b, c)],
+        )
+        .unwrap();
+        let adjacency = dir.join("demo_road.adj");
+        for restricted in [
+            RoadNetwork::load(db).unwrap(),
+            RoadNetwork::load_mapped(db, adjacency.to_str().unwrap()).unwrap(),
+        ] {
+            assert!(!restricted.turn_allowed(a, b, c));
+            let (meters, path) = restricted.get_road_distance(a, c);
+            assert!(meters > straight + 1.0, "{} <= {}", meters, straight);
+            assert!(restricted.is_legal(&path));
+            assert_eq!((path[0], path[path.len() - 1]), (a, c));
+            assert_eq!(restricted.paths_from(a, &[c])[&c], (meters, path));
+        }
+
+        // only going straight on forbids the other turns
+        conn.execute(
+            "UPDATE turn_restrictions SET restriction = 'only_straight_on'",
+            [],
+        )
+        .unwrap();
+        let only = RoadNetwork::load(db).unwrap();
+        assert!(only.turn_allowed(a, b, c));
+        assert!(open
+            .graph
+            .edges(b)
+            .map(|e| e.target())
+            .filter(|n| *n != c)
+            .all(|n| !only.turn_allowed(a, b, n)));
+        conn.execute("DELETE FROM turn_restrictions", []).unwrap();
+
+        // a road flagged as two-way gets its missing direction, a one-way road does not
+        let length = open.get_road_distance(a, b).0;
+        conn.execute("ALTER TABLE edges ADD COLUMN oneway INTEGER DEFAULT 0", [])
+            .unwrap();
+        conn.execute(
+            "DELETE FROM edges WHERE u = ?1 AND v = ?2",
+            params![
+                open.get_osmid_by_node_index(b),
+                open.get_osmid_by_node_index(a)
+            ],
+        )
+        .unwrap();
+        let two_way = RoadNetwork::load(db).unwrap();
+        assert_eq!(two_way.get_road_distance(b, a), (length, vec![b, a]));
+        conn.execute(
+            "UPDATE edges SET oneway = 'True' WHERE u = ?1 AND v = ?2",
+            params![
+                open.get_osmid_by_node_index(a),
+                open.get_osmid_by_node_index(b)
+            ],
+        )
+        .unwrap();
+        drop(conn);
+        let one_way = RoadNetwork::load(db).unwrap();
+        assert_eq!(one_way.get_road_distance(a, b), (length, vec![a, b]));
+        let (detour, path) = one_way.get_road_distance(b, a);
+        assert!(detour > length + 1.0 && path.len() > 2);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
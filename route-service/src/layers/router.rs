@@ -0,0 +1,549 @@
+use geo::{Distance, Haversine};
+use geo_types::Point;
+use rstar::{primitives::GeomWithData, RTree};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::gtfs::gtfs::Gtfs;
+use crate::gtfs::structs::format_gtfs_time;
+use crate::opt::{gtfs_export, timetable};
+
+use super::{
+    geo_util,
+    isochrone::WALK_SPEED_M_PER_MIN,
+    road_network::RoadNetwork,
+    transit_network::{TransitNetwork, TransitStop},
+};
+
+/// Longest straight-line walk from the origin to the first stop or from the last stop to the
+/// destination, in meters
+pub const MAX_ACCESS_WALK_M: f64 = 800.0;
+/// Longest straight-line walk between two stops to transfer, in meters
+pub const MAX_TRANSFER_WALK_M: f64 = 400.0;
+/// Most rides of a journey when a query does not say
+pub const DEFAULT_MAX_RIDES: usize = 4;
+/// Cost of a transfer when a query does not say, in seconds of travel time
+pub const DEFAULT_TRANSFER_PENALTY_SECS: u32 = 300;
+
+/// An origin to destination query
+#[derive(Clone, Debug)]
+pub struct JourneyQuery {
+    pub from: Point,
+    pub to: Point,
+    /// Earliest departure from the origin, in seconds since the start of the service day
+    pub depart_at: u32,
+    pub max_rides: usize,
+    /// Travel time a transfer is worth when journeys are ranked, in seconds
+    pub transfer_penalty_secs: u32,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LegMode {
+    Walk,
+    Ride,
+}
+
+/// Part of a journey on foot or on board a trip of a route
+#[derive(Serialize, Clone, Debug)]
+pub struct JourneyLeg {
+    pub mode: LegMode,
+    /// Route ridden, rides only
+    pub route_id: Option<String>,
+    /// 0 outbound, 1 inbound, rides only
+    pub direction_id: Option<u8>,
+    /// Stop the leg starts at, `None` for a walk from the origin
+    pub from_stop_id: Option<String>,
+    /// Stop the leg ends at, `None` for a walk to the destination
+    pub to_stop_id: Option<String>,
+    /// `HH:MM:SS` local time
+    pub departure: String,
+    pub arrival: String,
+    /// Stops ridden past, rides only
+    pub stops: Option<usize>,
+    /// Straight-line distance walked, walks only
+    pub walk_m: Option<f64>,
+}
+
+/// A way to travel from the origin to the destination
+#[derive(Serialize, Clone, Debug)]
+pub struct Journey {
+    /// Latest departure from the origin that makes the first ride, `HH:MM:SS` local time
+    pub departure: String,
+    pub arrival: String,
+    pub duration_secs: u32,
+    pub rides: usize,
+    pub transfers: usize,
+    pub walk_m: f64,
+    /// Duration plus the transfer penalty of each transfer, journeys are ranked by it
+    pub cost_secs: u32,
+    pub legs: Vec<JourneyLeg>,
+}
+
+/// Stops of one direction of a route with the trips serving them
+struct Pattern {
+    route_id: String,
+    direction_id: u8,
+    stops: Vec<usize>,
+    /// Time of each stop from the first one, in seconds
+    offsets: Vec<u32>,
+    /// Departures of the trips from the first stop, earliest first
+    departures: Vec<u32>,
+}
+
+impl Pattern {
+    fn time(&self, trip: usize, position: usize) -> u32 {
+        self.departures[trip] + self.offsets[position]
+    }
+
+    /// Earliest trip leaving the stop at a position in the pattern at or after a time
+    fn earliest_trip(&self, position: usize, ready: u32) -> Option<usize> {
+        let offset = self.offsets[position];
+        let trip = self.departures.partition_point(|&d| d + offset < ready);
+        (trip < self.departures.len()).then_some(trip)
+    }
+}
+
+/// A ride reaching a stop, see `Round`
+#[derive(Clone, Copy)]
+struct Ride {
+    pattern: usize,
+    trip: usize,
+    /// Positions in the pattern the ride boards and alights at
+    board: usize,
+    alight: usize,
+}
+
+/// Earliest arrivals at the stops with at most as many rides as the round's number
+struct Round {
+    arrival: Vec<u32>,
+    /// Rides of this round that reached a stop earlier than before
+    ride: Vec<Option<Ride>>,
+    /// Walks from a stop reached by a ride of this round that reached a stop even earlier,
+    /// with the walk time
+    walk: Vec<Option<(usize, u32)>>,
+}
+
+impl Round {
+    fn after(previous: &Round) -> Round {
+        Round {
+            arrival: previous.arrival.clone(),
+            ride: vec![None; previous.arrival.len()],
+            walk: vec![None; previous.arrival.len()],
+        }
+    }
+
+    fn improved(&self, stop: usize) -> bool {
+        self.ride[stop].is_some() || self.walk[stop].is_some()
+    }
+}
+
+/// Journey planner over the timetable of a transit network
+///
+/// Routes run the frequencies of the exported GTFS feed, see `gtfs_export::export_gtfs`: the
+/// departures of each time period are spread evenly over it and shared by the directions of
+/// the route. Run times come from the route's trip in the source feed when it serves the same
+/// stops and are estimated from road distances otherwise.
+///
+/// Queries are answered by RAPTOR: round k finds the earliest arrival at every stop with at
+/// most k rides, scanning each route direction once from the stops the previous round reached.
+pub struct Router {
+    stops: Vec<Arc<TransitStop>>,
+    patterns: Vec<Pattern>,
+    /// Patterns serving each stop with the stop's position in them
+    stop_patterns: Vec<Vec<(usize, usize)>>,
+    /// Stops within a transfer walk of each stop with the walk time
+    transfers: Vec<Vec<(usize, u32)>>,
+    rtree: RTree<GeomWithData<[f64; 2], usize>>,
+}
+
+impl Router {
+    /// Build the timetable of a network
+    ///
+    /// # Parameters
+    /// - `network`: The network to route over, e.g. the optimized network
+    /// - `src_gtfs`: The GTFS data the network was built from, for run times
+    /// - `road`: The road network, to estimate run times missing from the feed
+    pub fn new(network: &TransitNetwork, src_gtfs: &Gtfs, road: &RoadNetwork) -> Router {
+        let mut stops: Vec<Arc<TransitStop>> = vec![];
+        let mut stop_index: HashMap<String, usize> = HashMap::new();
+        let mut patterns = vec![];
+        for route in &network.routes {
+            let directions: Vec<(u8, &Vec<Arc<TransitStop>>)> =
+                [(0, &route.outbound_stops), (1, &route.inbound_stops)]
+                    .into_iter()
+                    .filter(|(_, stops)| stops.len() > 1)
+                    .collect();
+            let departures: Vec<u32> = gtfs_export::departure_windows(route, directions.len())
+                .into_iter()
+                .flat_map(|(start, end, headway)| (start..end).step_by(headway.max(1) as usize))
+                .collect();
+            for (direction_id, route_stops) in directions {
+                let src_trip = src_gtfs.trips.get(&route.route_id).and_then(|trips| {
+                    trips
+                        .iter()
+                        .find(|trip| (trip.direction_id == Some(1)) == (direction_id == 1))
+                });
+                let offsets = src_trip
+                    .and_then(|trip| timetable::gtfs_offsets(route_stops, trip))
+                    .unwrap_or_else(|| timetable::estimated_offsets(route_stops, road));
+                let pattern_stops = route_stops
+                    .iter()
+                    .map(|stop| {
+                        *stop_index.entry(stop.stop_id.clone()).or_insert_with(|| {
+                            stops.push(stop.clone());
+                            stops.len() - 1
+                        })
+                    })
+                    .collect();
+                patterns.push(Pattern {
+                    route_id: route.route_id.clone(),
+                    direction_id,
+                    stops: pattern_stops,
+                    offsets,
+                    departures: departures.clone(),
+                });
+            }
+        }
+
+        let mut stop_patterns = vec![vec![]; stops.len()];
+        for (p, pattern) in patterns.iter().enumerate() {
+            for (position, &stop) in pattern.stops.iter().enumerate() {
+                stop_patterns[stop].push((p, position));
+            }
+        }
+        let rtree = RTree::bulk_load(
+            stops
+                .iter()
+                .enumerate()
+                .map(|(i, stop)| GeomWithData::new([stop.geom.x(), stop.geom.y()], i))
+                .collect(),
+        );
+        let mut router = Router {
+            stops,
+            patterns,
+            stop_patterns,
+            transfers: vec![],
+            rtree,
+        };
+        router.transfers = (0..router.stops.len())
+            .map(|stop| {
+                router
+                    .stops_near(router.stops[stop].geom, MAX_TRANSFER_WALK_M)
+                    .into_iter()
+                    .filter(|&(other, _)| other != stop)
+                    .map(|(other, meters)| (other, walk_secs(meters)))
+                    .collect()
+            })
+            .collect();
+        router
+    }
+
+    /// Stops within a straight-line distance of a point with their distance
+    fn stops_near(&self, point: Point, max_m: f64) -> Vec<(usize, f64)> {
+        let envelope = geo_util::compute_envelope(point.y(), point.x(), max_m);
+        self.rtree
+            .locate_in_envelope(&envelope)
+            .filter_map(|node| {
+                let meters = Haversine::distance(point, self.stops[node.data].geom);
+                (meters <= max_m).then_some((node.data, meters))
+            })
+            .collect()
+    }
+
+    /// Journeys from the origin to the destination of a query
+    ///
+    /// # Returns
+    /// The journeys that arrive earlier than every journey with fewer rides, a walk alone if
+    /// the destination is close enough, ranked by cost
+    pub fn plan(&self, query: &JourneyQuery) -> Vec<Journey> {
+        let mut journeys = vec![];
+        // best arrival at the destination so far, later arrivals are not worth exploring
+        let mut target = u32::MAX;
+        let direct_m = Haversine::distance(query.from, query.to);
+        if direct_m <= MAX_ACCESS_WALK_M {
+            let arrival = query.depart_at + walk_secs(direct_m);
+            target = arrival;
+            journeys.push(Journey {
+                departure: format_gtfs_time(query.depart_at),
+                arrival: format_gtfs_time(arrival),
+                duration_secs: arrival - query.depart_at,
+                rides: 0,
+                transfers: 0,
+                walk_m: direct_m,
+                cost_secs: arrival - query.depart_at,
+                legs: vec![walk_leg(None, None, query.depart_at, direct_m)],
+            });
+        }
+
+        let access: HashMap<usize, f64> = self
+            .stops_near(query.from, MAX_ACCESS_WALK_M)
+            .into_iter()
+            .collect();
+        let egress = self.stops_near(query.to, MAX_ACCESS_WALK_M);
+        let mut best = vec![u32::MAX; self.stops.len()];
+        let mut first = Round {
+            arrival: vec![u32::MAX; self.stops.len()],
+            ride: vec![None; self.stops.len()],
+            walk: vec![None; self.stops.len()],
+        };
+        let mut marked = vec![];
+        for (&stop, &meters) in &access {
+            let arrival = query.depart_at + walk_secs(meters);
+            first.arrival[stop] = arrival;
+            best[stop] = arrival;
+            marked.push(stop);
+        }
+        let mut rounds = vec![first];
+
+        for _ in 0..query.max_rides {
+            // each pattern is scanned from the first stop where it can be boarded
+            let mut queue: BTreeMap<usize, usize> = BTreeMap::new();
+            for &stop in &marked {
+                for &(pattern, position) in &self.stop_patterns[stop] {
+                    let start = queue.entry(pattern).or_insert(position);
+                    *start = (*start).min(position);
+                }
+            }
+            let previous = rounds.last().unwrap();
+            let mut round = Round::after(previous);
+            for (&p, &start) in &queue {
+                let pattern = &self.patterns[p];
+                let mut trip: Option<(usize, usize)> = None;
+                for position in start..pattern.stops.len() {
+                    let stop = pattern.stops[position];
+                    if let Some((trip, board)) = trip {
+                        let arrival = pattern.time(trip, position);
+                        if arrival < best[stop].min(target) {
+                            best[stop] = arrival;
+                            round.arrival[stop] = arrival;
+                            round.ride[stop] = Some(Ride {
+                                pattern: p,
+                                trip,
+                                board,
+                                alight: position,
+                            });
+                        }
+                    }
+                    let ready = previous.arrival[stop];
+                    if ready == u32::MAX {
+                        continue;
+                    }
+                    if let Some(earlier) = pattern.earliest_trip(position, ready) {
+                        if trip.is_none_or(|(current, _)| earlier < current) {
+                            trip = Some((earlier, position));
+                        }
+                    }
+                }
+            }
+
+            // transfers walk from the stops rides of this round alighted at
+            let alighted: Vec<usize> = (0..self.stops.len())
+                .filter(|&stop| round.ride[stop].is_some())
+                .collect();
+            for &from in &alighted {
+                let ride_arrival = {
+                    let ride = round.ride[from].unwrap();
+                    self.patterns[ride.pattern].time(ride.trip, ride.alight)
+                };
+                for &(to, secs) in &self.transfers[from] {
+                    let arrival = ride_arrival + secs;
+                    if arrival < best[to].min(target) {
+                        best[to] = arrival;
+                        round.arrival[to] = arrival;
+                        round.walk[to] = Some((from, secs));
+                    }
+                }
+            }
+
+            marked = (0..self.stops.len())
+                .filter(|&stop| round.improved(stop))
+                .collect();
+            rounds.push(round);
+            if marked.is_empty() {
+                break;
+            }
+
+            let round = rounds.len() - 1;
+            let arrival = egress
+                .iter()
+                .filter(|&&(stop, _)| rounds[round].improved(stop))
+                .map(|&(stop, meters)| (rounds[round].arrival[stop] + walk_secs(meters), stop))
+                .min();
+            if let Some((arrival, stop)) = arrival.filter(|&(arrival, _)| arrival < target) {
+                target = arrival;
+                let egress_m = egress.iter().find(|&&(s, _)| s == stop).unwrap().1;
+                journeys.push(self.journey(query, &rounds, &access, round, stop, egress_m));
+            }
+        }
+
+        journeys.sort_by_key(|journey| (journey.cost_secs, journey.duration_secs));
+        journeys
+    }
+
+    /// Retrace the legs of the journey reaching a stop in a round and walking on to the
+    /// destination
+    fn journey(
+        &self,
+        query: &JourneyQuery,
+        rounds: &[Round],
+        access: &HashMap<usize, f64>,
+        round: usize,
+        stop: usize,
+        egress_m: f64,
+    ) -> Journey {
+        let arrival = rounds[round].arrival[stop] + walk_secs(egress_m);
+        let mut legs = vec![walk_leg(
+            Some(&self.stops[stop]),
+            None,
+            rounds[round].arrival[stop],
+            egress_m,
+        )];
+        let mut first_ride = query.depart_at;
+        let (mut round, mut stop) = (round, stop);
+        while round > 0 {
+            let current = &rounds[round];
+            let alighted = match current.walk[stop] {
+                Some((from, _)) => {
+                    let ride = current.ride[from].unwrap();
+                    legs.push(walk_leg(
+                        Some(&self.stops[from]),
+                        Some(&self.stops[stop]),
+                        self.patterns[ride.pattern].time(ride.trip, ride.alight),
+                        Haversine::distance(self.stops[from].geom, self.stops[stop].geom),
+                    ));
+                    from
+                }
+                None => stop,
+            };
+            match current.ride[alighted] {
+                Some(ride) => {
+                    let pattern = &self.patterns[ride.pattern];
+                    let board = pattern.stops[ride.board];
+                    legs.push(JourneyLeg {
+                        mode: LegMode::Ride,
+                        route_id: Some(pattern.route_id.clone()),
+                        direction_id: Some(pattern.direction_id),
+                        from_stop_id: Some(self.stops[board].stop_id.clone()),
+                        to_stop_id: Some(self.stops[alighted].stop_id.clone()),
+                        departure: format_gtfs_time(pattern.time(ride.trip, ride.board)),
+                        arrival: format_gtfs_time(pattern.time(ride.trip, ride.alight)),
+                        stops: Some(ride.alight - ride.board),
+                        walk_m: None,
+                    });
+                    first_ride = pattern.time(ride.trip, ride.board);
+                    stop = board;
+                }
+                // reached by an earlier round
+                None => stop = alighted,
+            }
+            round -= 1;
+        }
+
+        // leave the origin just in time for the first ride
+        let access_m = access[&stop];
+        let departure = first_ride
+            .saturating_sub(walk_secs(access_m))
+            .max(query.depart_at);
+        legs.push(walk_leg(None, Some(&self.stops[stop]), departure, access_m));
+        legs.reverse();
+
+        let rides = legs.iter().filter(|leg| leg.mode == LegMode::Ride).count();
+        let transfers = rides.saturating_sub(1);
+        let duration_secs = arrival - departure;
+        Journey {
+            departure: format_gtfs_time(departure),
+            arrival: format_gtfs_time(arrival),
+            duration_secs,
+            rides,
+            transfers,
+            walk_m: legs.iter().filter_map(|leg| leg.walk_m).sum(),
+            cost_secs: duration_secs + transfers as u32 * query.transfer_penalty_secs,
+            legs,
+        }
+    }
+}
+
+fn walk_secs(meters: f64) -> u32 {
+    (meters / WALK_SPEED_M_PER_MIN * 60.0).round() as u32
+}
+
+fn walk_leg(
+    from: Option<&TransitStop>,
+    to: Option<&TransitStop>,
+    departure: u32,
+    meters: f64,
+) -> JourneyLeg {
+    JourneyLeg {
+        mode: LegMode::Walk,
+        route_id: None,
+        direction_id: None,
+        from_stop_id: from.map(|stop| stop.stop_id.clone()),
+        to_stop_id: to.map(|stop| stop.stop_id.clone()),
+        departure: format_gtfs_time(departure),
+        arrival: format_gtfs_time(departure + walk_secs(meters)),
+        stops: None,
+        walk_m: Some(meters),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::structs::parse_gtfs_time;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+    #[test]
+    fn journeys_ride_between_the_ends_of_a_route() {
+        let city = load_demo_city(
+            &format!("router_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 3,
+                ..Default::default()
+            },
+        );
+
+        let router = Router::new(&city.transit, &city.gtfs, &city.road);
+        let stops = &city.transit.routes[0].outbound_stops;
+        let (from, to) = (stops[0].geom, stops[stops.len() - 1].geom);
+        assert!(Haversine::distance(from, to) > MAX_ACCESS_WALK_M);
+        let mut query = JourneyQuery {
+            from,
+            to,
+            depart_at: 8 * 3600,
+            max_rides: DEFAULT_MAX_RIDES,
+            transfer_penalty_secs: DEFAULT_TRANSFER_PENALTY_SECS,
+        };
+        let journeys = router.plan(&query);
+        assert!(!journeys.is_empty());
+        for journey in &journeys {
+            assert!(journey.rides >= 1);
+            assert_eq!(journey.legs.first().unwrap().from_stop_id, None);
+            assert_eq!(journey.legs.last().unwrap().to_stop_id, None);
+            assert!(parse_gtfs_time(&journey.departure).unwrap() >= query.depart_at);
+            // each leg starts once the one before it ended
+            for pair in journey.legs.windows(2) {
+                let ended = parse_gtfs_time(&pair[0].arrival).unwrap();
+                assert!(parse_gtfs_time(&pair[1].departure).unwrap() >= ended);
+                assert_eq!(pair[0].to_stop_id, pair[1].from_stop_id);
+            }
+            assert_eq!(
+                journey.cost_secs,
+                journey.duration_secs + journey.transfers as u32 * DEFAULT_TRANSFER_PENALTY_SECS
+            );
+        }
+        // journeys with more rides only make the cut by arriving earlier
+        let mut by_rides: Vec<&Journey> = journeys.iter().collect();
+        by_rides.sort_by_key(|journey| journey.rides);
+        for pair in by_rides.windows(2) {
+            assert!(pair[1].arrival < pair[0].arrival);
+        }
+
+        // nothing runs after the evening period
+        query.depart_at = 23 * 3600;
+        assert!(router.plan(&query).is_empty());
+    }
+}
@@ -0,0 +1,396 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::time::Instant;
+
+use petgraph::graph::NodeIndex;
+
+use crate::opt::{
+    accessibility::{headway_minutes, AVG_BUS_SPEED_KMH, ROAD_DETOUR_FACTOR, WALK_TIME_MIN},
+    eval::{route_hash, TRANSFER_PENALTY_MIN},
+};
+use crate::ordering::OrdF64;
+
+use super::{
+    city::City, error::Error, geo_util, grid::GridNetwork, transit_network::TransitNetwork,
+};
+
+/// Longest trip kept in a travel time matrix when none is asked for, in minutes
+pub const DEFAULT_MAX_MINUTES: f64 = 120.0;
+
+/// Fastest trip by transit between two zones, split into its parts
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TravelTime {
+    pub origin: u32,
+    pub destination: u32,
+    pub total_min: f64,
+    pub in_vehicle_min: f64,
+    /// Wait for the first vehicle and for the vehicles transferred to
+    pub wait_min: f64,
+    /// Penalty of the transfers on top of their wait, see `TRANSFER_PENALTY_MIN`
+    pub transfer_min: f64,
+    /// Walk to the first stop and from the last one
+    pub walk_min: f64,
+    pub transfers: usize,
+}
+
+/// Travel times by transit between the zones of a city
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TravelTimeMatrix {
+    /// Longest trip kept, pairs of zones further apart are left out
+    pub max_minutes: f64,
+    /// Zones with residents that trips start from
+    pub zones: usize,
+    /// Trips between zones with residents and any other zone, sorted by origin and destination
+    pub times: Vec<TravelTime>,
+}
+
+/// A travel time matrix saved with the network it was computed for
+#[derive(Serialize, Deserialize)]
+pub struct CachedSkim {
+    pub network_hash: u64,
+    pub matrix: TravelTimeMatrix,
+}
+
+/// One row of the CSV export of travel time matrices
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    network: &'a str,
+    origin_zone: u32,
+    destination_zone: u32,
+    total_min: f64,
+    in_vehicle_min: f64,
+    wait_min: f64,
+    transfer_min: f64,
+    walk_min: f64,
+    transfers: usize,
+}
+
+/// Time of a path through the skim graph, split into its parts
+#[derive(Clone, Copy, Default)]
+struct Label {
+    in_vehicle: f64,
+    wait: f64,
+    transfer: f64,
+    transfers: usize,
+}
+
+impl Label {
+    fn total(&self) -> f64 {
+        self.in_vehicle + self.wait + self.transfer
+    }
+}
+
+/// Kinds of moves between the nodes of the skim graph
+#[derive(Clone, Copy)]
+enum Move {
+    /// Wait at a stop for the first vehicle of the trip
+    Board(f64),
+    /// Wait at a stop for the vehicle transferred to
+    Transfer(f64),
+    Ride(f64),
+    Alight,
+}
+
+/// Transit network as a graph of stops and rides, in the way of `accessibility::AccessGraph`
+///
+/// Each stop has an access node, where trips board their first vehicle, and a transfer node,
+/// where riders get off and may board another vehicle. Every position of a stop along a route
+/// has a ride node.
+struct SkimGraph {
+    /// Access node of each stop within walking distance of each zone
+    zone_access: HashMap<NodeIndex, Vec<usize>>,
+    /// Zones within walking distance of each transfer node, empty for other nodes
+    node_zones: Vec<Vec<NodeIndex>>,
+    edges: Vec<Vec<(usize, Move)>>,
+}
+
+impl SkimGraph {
+    fn build(transit: &TransitNetwork, grid: &GridNetwork) -> SkimGraph {
+        let mut stop_nodes: HashMap<&str, (usize, usize)> = HashMap::new();
+        let mut zone_access: HashMap<NodeIndex, Vec<usize>> = HashMap::new();
+        let mut node_zones: Vec<Vec<NodeIndex>> = vec![];
+        let mut edges: Vec<Vec<(usize, Move)>> = vec![];
+
+        for route in &transit.routes {
+            let wait = headway_minutes(route) / 2.0;
+            for stops in [&route.outbound_stops, &route.inbound_stops] {
+                let mut prev_ride: Option<usize> = None;
+                for (i, stop) in stops.iter().enumerate() {
+                    let (access, transfer) =
+                        *stop_nodes.entry(&stop.stop_id).or_insert_with(|| {
+                            let (access, transfer) = (edges.len(), edges.len() + 1);
+                            let zones = stop.nearby_zone_indices(grid);
+                            for zone in &zones {
+                                zone_access.entry(*zone).or_default().push(access);
+                            }
+                            edges.extend([vec![], vec![]]);
+                            node_zones.extend([vec![], zones]);
+                            (access, transfer)
+                        });
+                    let ride = edges.len();
+                    edges.push(vec![(transfer, Move::Alight)]);
+                    node_zones.push(vec![]);
+                    edges[access].push((ride, Move::Board(wait)));
+                    edges[transfer].push((ride, Move::Transfer(wait)));
+                    if let Some(prev) = prev_ride {
+                        let prev_stop = &stops[i - 1];
+                        let km = geo_util::haversine(
+                            prev_stop.geom.x(),
+                            prev_stop.geom.y(),
+                            stop.geom.x(),
+                            stop.geom.y(),
+                        ) / 1000.0;
+                        let minutes = km * ROAD_DETOUR_FACTOR / AVG_BUS_SPEED_KMH * 60.0;
+                        edges[prev].push((ride, Move::Ride(minutes)));
+                    }
+                    prev_ride = Some(ride);
+                }
+            }
+        }
+
+        SkimGraph {
+            zone_access,
+            node_zones,
+            edges,
+        }
+    }
+
+    /// Fastest trips from an origin zone to every zone reached within a time budget, other than
+    /// the origin itself
+    fn travel_times(
+        &self,
+        origin: NodeIndex,
+        grid: &GridNetwork,
+        max_minutes: f64,
+    ) -> Vec<TravelTime> {
+        let walk = 2.0 * WALK_TIME_MIN;
+        let mut best = vec![f64::INFINITY; self.edges.len()];
+        let mut labels = vec![Label::default(); self.edges.len()];
+        let mut heap = BinaryHeap::new();
+        for &access in self.zone_access.get(&origin).into_iter().flatten() {
+            best[access] = 0.0;
+            heap.push((Reverse(OrdF64(0.0)), access));
+        }
+
+        let mut reached: HashMap<NodeIndex, Label> = HashMap::new();
+        while let Some((Reverse(OrdF64(time)), node)) = heap.pop() {
+            if time > best[node] {
+                continue;
+            }
+            let label = labels[node];
+            for &zone in &self.node_zones[node] {
+                if zone != origin && !reached.contains_key(&zone) {
+                    reached.insert(zone, label);
+                }
+            }
+            for &(next, step) in &self.edges[node] {
+                let mut next_label = label;
+                match step {
+                    Move::Board(wait) => next_label.wait += wait,
+                    Move::Transfer(wait) => {
+                        next_label.wait += wait;
+                        next_label.transfer += TRANSFER_PENALTY_MIN;
+                        next_label.transfers += 1;
+                    }
+                    Move::Ride(minutes) => next_label.in_vehicle += minutes,
+                    Move::Alight => {}
+                }
+                let next_time = next_label.total();
+                if next_time < best[next] && next_time + walk <= max_minutes {
+                    best[next] = next_time;
+                    labels[next] = next_label;
+                    heap.push((Reverse(OrdF64(next_time)), next));
+                }
+            }
+        }
+
+        let origin_id = grid.get_zone(origin).zoneid;
+        let mut times: Vec<TravelTime> = reached
+            .into_iter()
+            .map(|(zone, label)| TravelTime {
+                origin: origin_id,
+                destination: grid.get_zone(zone).zoneid,
+                total_min: label.total() + walk,
+                in_vehicle_min: label.in_vehicle,
+                wait_min: label.wait,
+                transfer_min: label.transfer,
+                walk_min: walk,
+                transfers: label.transfers,
+            })
+            .collect();
+        times.sort_by_key(|t| t.destination);
+        times
+    }
+}
+
+impl TravelTimeMatrix {
+    /// Compute the travel times by transit between the zones of a city
+    ///
+    /// # Arguments
+    /// - `transit`: Transit network the trips ride
+    /// - `grid`: Zones of the city
+    /// - `max_minutes`: Longest trip kept, including walking and waiting
+    ///
+    /// # Notes
+    /// - Trips walk `WALK_TIME_MIN` to a stop within walking distance of the origin zone and
+    ///   from a stop within walking distance of the destination zone
+    /// - Boarding a route waits half its headway, and transferring to another route costs
+    ///   `TRANSFER_PENALTY_MIN` on top of the wait
+    /// - Vehicles ride the straight line between stops at `AVG_BUS_SPEED_KMH`, lengthened by
+    ///   `ROAD_DETOUR_FACTOR`
+    pub fn compute(
+        transit: &TransitNetwork,
+        grid: &GridNetwork,
+        max_minutes: f64,
+    ) -> TravelTimeMatrix {
+        let graph = SkimGraph::build(transit, grid);
+        let mut origins = grid.get_all_valid_zones();
+        origins.sort_by_key(|&z| grid.get_zone(z).zoneid);
+        TravelTimeMatrix {
+            max_minutes,
+            zones: origins.len(),
+            times: origins
+                .iter()
+                .flat_map(|&origin| graph.travel_times(origin, grid, max_minutes))
+                .collect(),
+        }
+    }
+
+    /// Travel times of a network of a city, read from the city cache if they were computed for
+    /// the same network and time budget before, otherwise computed and cached
+    ///
+    /// # Arguments
+    /// - `key`: Name the matrix is cached under, see `City::save_skim`
+    pub fn cached(
+        city_name: &str,
+        key: &str,
+        transit: &TransitNetwork,
+        grid: &GridNetwork,
+        max_minutes: f64,
+    ) -> TravelTimeMatrix {
+        let network_hash = network_hash(transit);
+        match City::load_skim(city_name, key) {
+            Ok(cached)
+                if cached.network_hash == network_hash
+                    && cached.matrix.max_minutes == max_minutes =>
+            {
+                return cached.matrix;
+            }
+            Ok(_) | Err(Error::CacheNotFound) => {}
+            Err(e) => log::warn!("Ignoring unreadable travel time matrix {}: {}", key, e),
+        }
+
+        let start = Instant::now();
+        let matrix = TravelTimeMatrix::compute(transit, grid, max_minutes);
+        log::debug!(
+            "Travel time matrix of {} computed in {}ms",
+            key,
+            start.elapsed().as_millis()
+        );
+        let cached = CachedSkim {
+            network_hash,
+            matrix,
+        };
+        if let Err(e) = City::save_skim(city_name, key, &cached) {
+            log::warn!("Failed to cache travel time matrix {}: {}", key, e);
+        }
+        cached.matrix
+    }
+
+    /// Write travel time matrices as CSV, one row per network and pair of zones
+    ///
+    /// # Arguments
+    /// - `matrices`: Name of each network and its matrix
+    pub fn to_csv(matrices: &[(&str, &TravelTimeMatrix)]) -> Result<Vec<u8>, csv::Error> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for (network, matrix) in matrices {
+            for time in &matrix.times {
+                writer.serialize(CsvRow {
+                    network,
+                    origin_zone: time.origin,
+                    destination_zone: time.destination,
+                    total_min: time.total_min,
+                    in_vehicle_min: time.in_vehicle_min,
+                    wait_min: time.wait_min,
+                    transfer_min: time.transfer_min,
+                    walk_min: time.walk_min,
+                    transfers: time.transfers,
+                })?;
+            }
+        }
+        writer.into_inner().map_err(|e| e.into_error().into())
+    }
+}
+
+/// Hash of the stops and departures of every route of a network, which its travel times
+/// depend on
+pub fn network_hash(transit: &TransitNetwork) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for route in &transit.routes {
+        route_hash(route).hash(&mut hasher);
+        let mut stop_times: Vec<(&usize, &usize)> = route.stop_times.iter().collect();
+        stop_times.sort();
+        stop_times.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+    #[test]
+    fn travel_times_add_up_and_follow_headways() {
+        let city = load_demo_city(
+            &format!("skim_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 3,
+                ..Default::default()
+            },
+        );
+
+        let matrix = TravelTimeMatrix::compute(&city.transit, &city.grid, DEFAULT_MAX_MINUTES);
+        assert!(!matrix.times.is_empty());
+        for time in &matrix.times {
+            assert_ne!(time.origin, time.destination);
+            let parts = time.in_vehicle_min + time.wait_min + time.transfer_min + time.walk_min;
+            assert!((time.total_min - parts).abs() < 1e-9);
+            assert!(time.total_min <= DEFAULT_MAX_MINUTES);
+            assert_eq!(
+                time.transfer_min,
+                time.transfers as f64 * TRANSFER_PENALTY_MIN
+            );
+        }
+        let longest = matrix.times.iter().map(|t| t.total_min).fold(0.0, f64::max);
+        let short = TravelTimeMatrix::compute(&city.transit, &city.grid, longest - 1.0);
+        assert!(short.times.len() < matrix.times.len());
+
+        // twice the departures halve the waits
+        let mut frequent = city.transit.clone();
+        for route in &mut frequent.routes {
+            if route.stop_times.is_empty() {
+                route.stop_times.insert(0, 100);
+            }
+            for departures in route.stop_times.values_mut() {
+                *departures *= 2;
+            }
+        }
+        assert_ne!(network_hash(&frequent), network_hash(&city.transit));
+        let faster = TravelTimeMatrix::compute(&frequent, &city.grid, DEFAULT_MAX_MINUTES);
+        let avg_wait = |m: &TravelTimeMatrix| {
+            m.times.iter().map(|t| t.wait_min).sum::<f64>() / m.times.len() as f64
+        };
+        assert!(faster.times.len() >= matrix.times.len());
+        assert!(avg_wait(&faster) < avg_wait(&matrix));
+
+        let csv = TravelTimeMatrix::to_csv(&[("original", &matrix)]).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("network,origin_zone,destination_zone,total_min"));
+        assert_eq!(csv.lines().count(), matrix.times.len() + 1);
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::gtfs::{
+    gtfs::Gtfs,
+    structs::{LocationType, Stop},
+};
+use crate::ordering;
+
+use super::{
+    geo_util,
+    import_report::{StationResolution, StationStop},
+};
+
+/// Move stop times off stations and boarding areas onto stops at street level
+///
+/// Some feeds put trips on parent stations or boarding areas, whose coordinates can be far
+/// from the street the buses run on. Stop times are resolved as follows:
+/// - a boarding area is replaced by its parent platform
+/// - a station is replaced by the child platform closest to the previous and next stops of the
+///   trip
+/// - a station without platforms is moved to one of its entrances, preferring entrances
+///   linked by a pathway, so that it is mapped to the road node at the entrance
+///
+/// # Parameters
+/// - `gtfs`: The feed to resolve in place
+///
+/// # Returns
+/// The substitutions made, with the number of stop times each applies to
+pub fn resolve_station_stops(gtfs: &mut Gtfs) -> Vec<StationStop> {
+    let is_platform = |stop: &Stop| {
+        matches!(
+            stop.location_type,
+            None | Some(LocationType::StopOrPlatform)
+        )
+    };
+    let needs_resolution = |stop: &Stop| {
+        matches!(
+            stop.location_type,
+            Some(LocationType::Station) | Some(LocationType::BoardingArea)
+        )
+    };
+    if !gtfs.stops.values().any(|s| needs_resolution(s)) {
+        return vec![];
+    }
+
+    // platforms and entrances of each station, in stop id order
+    let mut platforms: HashMap<&str, Vec<&Arc<Stop>>> = HashMap::new();
+    let mut entrances: HashMap<&str, Vec<&Arc<Stop>>> = HashMap::new();
+    let mut stops: Vec<&Arc<Stop>> = gtfs.stops.values().collect();
+    stops.sort_by(|a, b| a.stop_id.cmp(&b.stop_id));
+    for stop in stops {
+        let Some(parent) = stop.parent_station.as_deref() else {
+            continue;
+        };
+        if is_platform(stop) {
+            platforms.entry(parent).or_default().push(stop);
+        } else if stop.location_type == Some(LocationType::EntranceExit) {
+            entrances.entry(parent).or_default().push(stop);
+        }
+    }
+    let with_pathway: HashSet<&str> = gtfs
+        .stops
+        .values()
+        .flat_map(|s| s.pathways.iter())
+        .flat_map(|p| [p.from_stop_id.as_str(), p.to_stop_id.as_str()])
+        .collect();
+
+    // stations without platforms, moved to an entrance once for all their stop times
+    let mut moved_stations: HashMap<String, (Arc<Stop>, String)> = HashMap::new();
+    for station in gtfs.stops.values() {
+        if station.location_type != Some(LocationType::Station)
+            || platforms.contains_key(station.stop_id.as_str())
+        {
+            continue;
+        }
+        let Some(candidates) = entrances.get(station.stop_id.as_str()) else {
+            continue;
+        };
+        let linked: Vec<&Arc<Stop>> = candidates
+            .iter()
+            .copied()
+            .filter(|e| with_pathway.contains(e.stop_id.as_str()))
+            .collect();
+        let candidates = if linked.is_empty() {
+            candidates.clone()
+        } else {
+            linked
+        };
+        if let Some(entrance) = closest(&candidates, &[station]) {
+            let mut moved = Stop::clone(station);
+            moved.stop_lat = entrance.stop_lat;
+            moved.stop_lon = entrance.stop_lon;
+            moved_stations.insert(
+                station.stop_id.clone(),
+                (Arc::new(moved), entrance.stop_id.clone()),
+            );
+        }
+    }
+
+    let mut substitutions: BTreeMap<(String, StationResolution, Option<String>), usize> =
+        BTreeMap::new();
+    for trip in gtfs.trips.values_mut().flatten() {
+        for i in 0..trip.stop_times.len() {
+            let stop = trip.stop_times[i].stop.clone();
+            if !needs_resolution(&stop) {
+                continue;
+            }
+            let (resolution, replacement) = match stop.location_type {
+                Some(LocationType::BoardingArea) => match stop
+                    .parent_station
+                    .as_ref()
+                    .and_then(|p| gtfs.stops.get(p))
+                    .filter(|p| is_platform(p))
+                {
+                    Some(platform) => (StationResolution::ParentPlatform, Some(platform.clone())),
+                    None => (StationResolution::Unresolved, None),
+                },
+                _ => match platforms.get(stop.stop_id.as_str()) {
+                    Some(candidates) => {
+                        let neighbours: Vec<&Stop> = [i.checked_sub(1), Some(i + 1)]
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|j| trip.stop_times.get(j))
+                            .map(|st| st.stop.as_ref())
+                            .collect();
+                        let platform = closest(candidates, &neighbours).cloned();
+                        (StationResolution::ChildPlatform, platform)
+                    }
+                    None => match moved_stations.get(&stop.stop_id) {
+                        Some((moved, _)) => (StationResolution::Entrance, Some(moved.clone())),
+                        None => (StationResolution::Unresolved, None),
+                    },
+                },
+            };
+
+            let replaced_by = match (&resolution, &replacement) {
+                (StationResolution::Entrance, _) => {
+                    moved_stations.get(&stop.stop_id).map(|(_, e)| e.clone())
+                }
+                (_, Some(replacement)) => Some(replacement.stop_id.clone()),
+                _ => None,
+            };
+            if let Some(replacement) = replacement {
+                let stop_time = &mut trip.stop_times[i];
+                stop_time.stop_id = replacement.stop_id.clone();
+                stop_time.stop = replacement;
+            }
+            *substitutions
+                .entry((stop.stop_id.clone(), resolution, replaced_by))
+                .or_default() += 1;
+        }
+    }
+
+    for (station_id, (moved, _)) in moved_stations {
+        gtfs.stops.insert(station_id, moved);
+    }
+
+    substitutions
+        .into_iter()
+        .map(
+            |((stop_id, resolution, replaced_by), stop_times)| StationStop {
+                stop_id,
+                resolution,
+                replaced_by,
+                stop_times,
+            },
+        )
+        .collect()
+}
+
+/// The candidate with the smallest total distance to the given stops, the first candidate
+/// if none of them has coordinates
+fn closest<'a>(candidates: &[&'a Arc<Stop>], to: &[&Stop]) -> Option<&'a Arc<Stop>> {
+    let distance = |stop: &Stop| -> f64 {
+        to.iter()
+            .filter_map(|other| {
+                match (stop.stop_lon, stop.stop_lat, other.stop_lon, other.stop_lat) {
+                    (Some(x1), Some(y1), Some(x2), Some(y2)) => {
+                        Some(geo_util::haversine(x1, y1, x2, y2))
+                    }
+                    _ => None,
+                }
+            })
+            .sum()
+    };
+    candidates
+        .iter()
+        .copied()
+        .min_by(|a, b| ordering::cmp_f64(distance(a), distance(b)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::structs::{Pathway, StopTime, Trip};
+
+    fn stop(id: &str, location_type: LocationType, parent: Option<&str>, lon: f64) -> Stop {
+        Stop {
+            stop_id: id.to_string(),
+            stop_lat: Some(if id.ends_with("-far") { 0.005 } else { 0.0 }),
+            stop_lon: Some(lon),
+            location_type: Some(location_type),
+            parent_station: parent.map(str::to_string),
+            ..Stop::default()
+        }
+    }
+
+    fn trip(id: &str, stop_ids: &[&str], stops: &HashMap<String, Arc<Stop>>) -> Trip {
+        Trip {
+            route_id: "1".to_string(),
+            trip_id: id.to_string(),
+            stop_times: stop_ids
+                .iter()
+                .enumerate()
+                .map(|(i, s)| StopTime {
+                    trip_id: id.to_string(),
+                    stop_id: s.to_string(),
+                    stop_sequence: i as i32,
+                    stop: stops[*s].clone(),
+                    ..StopTime::default()
+                })
+                .collect(),
+            ..Trip::default()
+        }
+    }
+
+    #[test]
+    fn resolves_stations_to_street_level_stops() {
+        let mut entrance_link = stop("E2", LocationType::EntranceExit, Some("S2"), 0.021);
+        entrance_link.pathways.push(Pathway {
+            pathway_id: "P".to_string(),
+            from_stop_id: "E2".to_string(),
+            to_stop_id: "S2".to_string(),
+            pathway_mode: Default::default(),
+            is_bidirectional: Default::default(),
+            length: None,
+            traversal_time: None,
+            stair_count: None,
+            max_slope: None,
+            min_width: None,
+            signposted_as: None,
+            reversed_signposted_as: None,
+        });
+        let stops: HashMap<String, Arc<Stop>> = [
+            stop("A", LocationType::StopOrPlatform, None, 0.0),
+            // station with a platform on the street and one away from it
+            stop("S1", LocationType::Station, None, 0.01),
+            stop("S1-far", LocationType::StopOrPlatform, Some("S1"), 0.01),
+            stop("S1-east", LocationType::StopOrPlatform, Some("S1"), 0.011),
+            // station without platforms, the linked entrance is preferred
+            stop("S2", LocationType::Station, None, 0.02),
+            stop("E1", LocationType::EntranceExit, Some("S2"), 0.0201),
+            entrance_link,
+            stop("B", LocationType::BoardingArea, Some("S1-east"), 0.011),
+            stop("S3", LocationType::Station, None, 0.03),
+        ]
+        .into_iter()
+        .map(|s| (s.stop_id.clone(), Arc::new(s)))
+        .collect();
+        let mut gtfs = Gtfs {
+            trips: HashMap::from([(
+                "1".to_string(),
+                vec![
+                    trip("t1", &["A", "S1", "S2", "S3"], &stops),
+                    trip("t2", &["B", "A"], &stops),
+                ],
+            )]),
+            stops,
+            ..Gtfs::default()
+        };
+
+        let report = resolve_station_stops(&mut gtfs);
+        let stop_ids = |trip: usize| -> Vec<&str> {
+            gtfs.trips["1"][trip]
+                .stop_times
+                .iter()
+                .map(|st| st.stop_id.as_str())
+                .collect()
+        };
+        assert_eq!(stop_ids(0), vec!["A", "S1-east", "S2", "S3"]);
+        assert_eq!(stop_ids(1), vec!["S1-east", "A"]);
+        assert_eq!(gtfs.trips["1"][0].stop_times[2].stop.stop_lon, Some(0.021));
+        assert_eq!(gtfs.stops["S2"].stop_lon, Some(0.021));
+
+        let resolutions: Vec<(&str, StationResolution, Option<&str>)> = report
+            .iter()
+            .map(|s| (s.stop_id.as_str(), s.resolution, s.replaced_by.as_deref()))
+            .collect();
+        assert_eq!(
+            resolutions,
+            vec![
+                ("B", StationResolution::ParentPlatform, Some("S1-east")),
+                ("S1", StationResolution::ChildPlatform, Some("S1-east")),
+                ("S2", StationResolution::Entrance, Some("E2")),
+                ("S3", StationResolution::Unresolved, None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use rusqlite::{params, Connection, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Curbside infrastructure present at a stop
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct StopAmenities {
+    pub has_shelter: bool,
+    pub has_pad: bool,
+}
+
+impl StopAmenities {
+    /// Whether the stop has any infrastructure a new route could reuse
+    pub fn has_infrastructure(&self) -> bool {
+        self.has_shelter || self.has_pad
+    }
+}
+
+/// Curbside infrastructure of the stops of a city.
+///
+/// Read from the optional `stop_infrastructure` table of the city database, keyed by GTFS
+/// stop id. Stops missing from the table are assumed to have no infrastructure.
+#[derive(Default, Deserialize, Serialize)]
+pub struct StopInfrastructure {
+    stops: HashMap<String, StopAmenities>,
+}
+
+impl StopInfrastructure {
+    /// Load stop infrastructure from the city database
+    ///
+    /// # Parameters
+    /// - `dbname`: Path to the city database
+    ///
+    /// # Returns
+    /// The stop infrastructure, empty if the database does not have the table
+    pub fn load(dbname: &str) -> Result<StopInfrastructure> {
+        let conn = Connection::open(dbname)?;
+
+        let has_table: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'stop_infrastructure'",
+            params![],
+            |row| row.get(0),
+        )?;
+        if !has_table {
+            return Ok(StopInfrastructure::default());
+        }
+
+        let mut stmt =
+            conn.prepare("SELECT stop_id, has_shelter, has_pad FROM stop_infrastructure")?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                StopAmenities {
+                    has_shelter: row.get::<_, Option<bool>>(1)?.unwrap_or(false),
+                    has_pad: row.get::<_, Option<bool>>(2)?.unwrap_or(false),
+                },
+            ))
+        })?;
+        Ok(StopInfrastructure {
+            stops: rows.filter_map(|row| row.ok()).collect(),
+        })
+    }
+
+    /// Infrastructure at a stop, `None` if the stop is not listed
+    pub fn get(&self, stop_id: &str) -> Option<&StopAmenities> {
+        self.stops.get(stop_id)
+    }
+
+    /// Whether a stop has a shelter or a pad
+    pub fn has_infrastructure(&self, stop_id: &str) -> bool {
+        self.get(stop_id)
+            .map(|amenities| amenities.has_infrastructure())
+            .unwrap_or(false)
+    }
+
+    /// Number of stops listed in the table
+    pub fn len(&self) -> usize {
+        self.stops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stops.is_empty()
+    }
+}
@@ -1,7 +1,6 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
-use chrono::NaiveTime;
 use geo::{Distance, Haversine, Length, LineString};
 use geo_types::Point;
 use petgraph::graph::NodeIndex;
@@ -9,13 +8,27 @@ use rstar::{Envelope, PointDistance, RTree, RTreeObject, AABB};
 use serde::{Deserialize, Serialize};
 
 use crate::gtfs::gtfs::Gtfs;
-use crate::gtfs::structs::{Frequency, Route, RouteType, Shape, Stop, StopTime, Trip};
+use crate::gtfs::structs::{
+    parse_gtfs_time, Frequency, Route, RouteType, Shape, Stop, StopTime, Trip,
+};
+use crate::gtfs::validator;
 use crate::layers::error::Error;
+use crate::opt::consts;
+use crate::opt::demand::DemandModelConfig;
 use crate::opt::eval::{TransitNetworkEvals, TransitRouteEvals};
+use crate::opt::search::SearchConfig;
 
 use super::geo_util;
-use super::grid::{GridNetwork, Zone};
+use super::grid::{GridNetwork, TimePeriod, Zone};
+use super::import_report::{
+    ApproximateGeometry, DirectionSource, DropReason, DroppedRoute, RouteDirection,
+};
 use super::road_network::RoadNetwork;
+use super::vehicle::{RouteVehicles, VehicleProfile};
+
+/// `same_direction` decides by the end stops of two trips when pairing them up one way round
+/// is less than this share of the distance of pairing them up the other way round
+const ENDPOINT_MATCH_RATIO: f64 = 0.5;
 
 // Layer 3 - Data structure describing the transit network
 #[derive(Clone, Deserialize, Serialize)]
@@ -29,6 +42,12 @@ pub struct TransitNetwork {
     pub outbound_stops: RTree<RTreeNode>,
     /// Evaluation metrics for the transit network
     pub evals: Option<TransitNetworkEvals>,
+    /// Routes of the GTFS feed left out of the network, sorted by route id
+    #[serde(default)]
+    pub dropped_routes: Vec<DroppedRoute>,
+    /// Model splitting the demand of a zone pair between the routes serving it
+    #[serde(default)]
+    pub demand_model: DemandModelConfig,
 }
 
 #[derive(PartialEq, Clone, Deserialize, Serialize)]
@@ -39,6 +58,77 @@ pub struct TransitRoute {
     pub outbound_stops: Vec<Arc<TransitStop>>,
     pub evals: Option<TransitRouteEvals>,
     pub stop_times: HashMap<usize, usize>,
+    /// First and last trip of the route in the source GTFS, if it has times
+    pub service_span: Option<ServiceSpan>,
+    /// Vehicles the route runs with, `None` for those of the optimization parameters
+    #[serde(default)]
+    pub vehicle: Option<VehicleProfile>,
+}
+
+/// First and last trip times of a route, in seconds since the start of the service day
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct ServiceSpan {
+    pub first_trip_id: String,
+    /// Departure time of the first trip from its first stop
+    pub first_departure: u32,
+    pub last_trip_id: String,
+    /// Arrival time of the last trip at its last stop
+    pub last_arrival: u32,
+}
+
+impl ServiceSpan {
+    /// Extract the service span from a set of trips
+    ///
+    /// # Parameters
+    /// - `trips`: The trips of a route
+    ///
+    /// # Returns
+    /// The span from the earliest first departure to the latest last arrival,
+    /// or None if no trip has parsable times at its first and last stop
+    pub fn from_trips<'a>(trips: impl IntoIterator<Item = &'a Trip>) -> Option<ServiceSpan> {
+        let mut first: Option<(u32, &str)> = None;
+        let mut last: Option<(u32, &str)> = None;
+        for trip in trips {
+            let departure = trip.stop_times.first().and_then(|st| {
+                st.departure_time
+                    .as_deref()
+                    .or(st.arrival_time.as_deref())
+                    .and_then(parse_gtfs_time)
+            });
+            let arrival = trip.stop_times.last().and_then(|st| {
+                st.arrival_time
+                    .as_deref()
+                    .or(st.departure_time.as_deref())
+                    .and_then(parse_gtfs_time)
+            });
+            if let Some(departure) = departure {
+                if first.is_none_or(|(t, _)| departure < t) {
+                    first = Some((departure, &trip.trip_id));
+                }
+            }
+            if let Some(arrival) = arrival {
+                if last.is_none_or(|(t, _)| arrival > t) {
+                    last = Some((arrival, &trip.trip_id));
+                }
+            }
+        }
+        match (first, last) {
+            (Some((first_departure, first_trip)), Some((last_arrival, last_trip))) => {
+                Some(ServiceSpan {
+                    first_trip_id: first_trip.to_string(),
+                    first_departure,
+                    last_trip_id: last_trip.to_string(),
+                    last_arrival,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Check that this span starts no later and ends no earlier than another
+    pub fn covers(&self, other: &ServiceSpan) -> bool {
+        self.first_departure <= other.first_departure && self.last_arrival >= other.last_arrival
+    }
 }
 
 #[derive(PartialEq, Clone, Deserialize, Serialize)]
@@ -76,9 +166,11 @@ impl From<RouteType> for TransitRouteType {
 }
 
 impl TransitRoute {
+    /// Build a route evaluated with the search parameters of its city
     pub fn with_evals(
         network: &TransitNetwork,
         grid: &GridNetwork,
+        search: &SearchConfig,
         route_id: String,
         route_type: TransitRouteType,
         outbound_stops: Vec<Arc<TransitStop>>,
@@ -92,10 +184,19 @@ impl TransitRoute {
             outbound_stops: outbound_stops,
             evals: None,
             stop_times: stop_times,
+            service_span: None,
+            vehicle: None,
         };
-        route.evals = Some(TransitRouteEvals::for_route(network, &route, grid));
+        route.evals = Some(TransitRouteEvals::for_route(network, &route, grid, search));
         route
     }
+
+    /// Riders a vehicle of the route carries, `BUS_CAPACITY` for routes without a profile
+    pub fn capacity(&self) -> usize {
+        self.vehicle
+            .as_ref()
+            .map_or(consts::BUS_CAPACITY as usize, |v| v.capacity)
+    }
 }
 
 impl TransitNetwork {
@@ -104,40 +205,181 @@ impl TransitNetwork {
         println!("  Routes: {}", self.routes.len());
         println!("  Inbound stops: {}", self.inbound_stops.size());
         println!("  Outbound stops: {}", self.outbound_stops.size());
+        let counts = self.dropped_route_counts();
+        if !counts.is_empty() {
+            let reasons: Vec<String> = counts
+                .iter()
+                .map(|(reason, count)| format!("{:?}: {}", reason, count))
+                .collect();
+            println!(
+                "  Dropped routes: {} ({})",
+                self.dropped_routes.len(),
+                reasons.join(", ")
+            );
+        }
+    }
+
+    /// Number of routes of the GTFS feed left out of the network for each reason
+    pub fn dropped_route_counts(&self) -> BTreeMap<DropReason, usize> {
+        let mut counts = BTreeMap::new();
+        for route in &self.dropped_routes {
+            *counts.entry(route.reason).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Set the vehicle profile of each route, routes missing from `vehicles` have none
+    ///
+    /// The routes whose profile changes are evaluated again, their capacity changes their
+    /// scores.
+    ///
+    /// # Returns
+    /// The number of routes whose profile changed
+    pub fn assign_vehicles(
+        &mut self,
+        vehicles: &RouteVehicles,
+        grid: &GridNetwork,
+        search: &SearchConfig,
+    ) -> usize {
+        for route_id in vehicles.keys() {
+            if !self.routes.iter().any(|r| &r.route_id == route_id) {
+                log::warn!("Vehicle profile set for unknown route {}", route_id);
+            }
+        }
+        let mut changed = vec![];
+        for (i, route) in self.routes.iter_mut().enumerate() {
+            let vehicle = vehicles.get(&route.route_id).cloned();
+            if route.vehicle != vehicle {
+                route.vehicle = vehicle;
+                changed.push(i);
+            }
+        }
+        let evals: Vec<_> = changed
+            .iter()
+            .map(|&i| TransitRouteEvals::for_route(self, &self.routes[i], grid, search))
+            .collect();
+        for (&i, evals) in changed.iter().zip(evals) {
+            self.routes[i].evals = Some(evals);
+        }
+        changed.len()
+    }
+
+    /// Set the model splitting the demand between competing routes
+    ///
+    /// The routes are evaluated again if the model changed, since it changes their ridership
+    /// and scores.
+    ///
+    /// # Returns
+    /// Whether the model changed
+    pub fn set_demand_model(
+        &mut self,
+        model: DemandModelConfig,
+        grid: &GridNetwork,
+        search: &SearchConfig,
+    ) -> bool {
+        if self.demand_model == model {
+            return false;
+        }
+        self.demand_model = model;
+        let evals: Vec<_> = self
+            .routes
+            .iter()
+            .map(|route| {
+                route
+                    .evals
+                    .as_ref()
+                    .map(|_| TransitRouteEvals::for_route(self, route, grid, search))
+            })
+            .collect();
+        for (route, evals) in self.routes.iter_mut().zip(evals) {
+            if evals.is_some() {
+                route.evals = evals;
+            }
+        }
+        true
+    }
+
+    /// Stops of both directions within an envelope
+    ///
+    /// # Parameters
+    /// - `envelope`: Area to search
+    /// - `include_inbound`: Also return stops only found in the inbound direction of routes
+    ///
+    /// # Returns
+    /// Outbound stops followed by the inbound stops that are not also outbound stops. A stop
+    /// is considered the same if it has the same id or the exact same location.
+    pub fn stops_in_envelope(
+        &self,
+        envelope: &AABB<[f64; 2]>,
+        include_inbound: bool,
+    ) -> Vec<Arc<TransitStop>> {
+        let mut ids = HashSet::new();
+        let mut locations = HashSet::new();
+        let mut stops = vec![];
+        let inbound = include_inbound.then(|| self.inbound_stops.locate_in_envelope(envelope));
+        for node in self
+            .outbound_stops
+            .locate_in_envelope(envelope)
+            .chain(inbound.into_iter().flatten())
+        {
+            let location = (node.stop.geom.x().to_bits(), node.stop.geom.y().to_bits());
+            if ids.insert(node.stop.stop_id.as_str()) && locations.insert(location) {
+                stops.push(node.stop.clone());
+            }
+        }
+        stops
     }
 
     /// Build a transit network from GTFS data
     ///
     /// # Parameters
     /// - `gtfs`: The GTFS data
+    /// - `search`: Search parameters used to evaluate the coverage of the routes
     ///
     /// # Returns
-    /// A transit network
+    /// A transit network, with the routes that could not be built and why in `dropped_routes`
     ///
     /// For each routes, extracts the longest INBOUND and OUTBOUND trips
-    /// and classifies stops from these trips as INBOUND or OUTBOUND depending on their
-    /// geodesic bearing. Stops are stored in an RTree for spatial queries.
+    /// and classifies stops from these trips as INBOUND or OUTBOUND depending on the
+    /// GTFS `direction_id` of the trip, falling back to the trip geometry when it is
+    /// missing. Stops are stored in an RTree for spatial queries.
     pub fn from_gtfs(
         gtfs: &Gtfs,
         road: &RoadNetwork,
         grid: &GridNetwork,
+        search: &SearchConfig,
     ) -> Result<TransitNetwork, Error> {
         let mut routes = Vec::new();
         let mut inbound_stops_tree = RTree::new();
         let mut outbound_stops_tree = RTree::new();
         let mut stops_map = HashMap::new();
-        for route in gtfs.routes.values() {
+        let mut dropped_routes = Vec::new();
+        // in route id order, so the network and the stops it shares between routes are the
+        // same on every build
+        let mut gtfs_routes: Vec<&Route> = gtfs.routes.values().collect();
+        gtfs_routes.sort_by(|a, b| a.route_id.cmp(&b.route_id));
+        for route in gtfs_routes {
             // Get the longest trip in each direction
-            let (trip1, trip2) = match pick_inbound_outbound_trips(&route.route_id, gtfs) {
-                Some(trips) => trips,
-                None => continue,
+            let route_trips = match pick_inbound_outbound_trips(&route.route_id, gtfs) {
+                Ok(trips) => trips,
+                Err(reason) => {
+                    log::debug!("Dropping route {}: {:?}", route.route_id, reason);
+                    dropped_routes.push(DroppedRoute {
+                        route_id: route.route_id.clone(),
+                        reason,
+                        trips: gtfs.trips.get(&route.route_id).map_or(0, Vec::len),
+                    });
+                    continue;
+                }
             };
             let mut inbound_stops = vec![];
             let mut outbound_stops = vec![];
-            for trip in [trip1, trip2] {
+            let directed_trips = std::iter::once((route_trips.outbound, true))
+                .chain(route_trips.inbound.map(|trip| (trip, false)));
+            for (trip, is_outbound) in directed_trips {
                 let stop_to_osmid = map_transit_stops_to_osmid(trip, road);
                 // Classify route as "outbound" or "inbound"
-                let (insert_stops, insert_stops_tree) = if trip_is_outbound(trip) {
+                let (insert_stops, insert_stops_tree) = if is_outbound {
                     (&mut outbound_stops, &mut outbound_stops_tree)
                 } else {
                     (&mut inbound_stops, &mut inbound_stops_tree)
@@ -164,7 +406,7 @@ impl TransitNetwork {
                                 ),
                                 osmid: stop_to_osmid.get(&stop_times.stop_id).cloned(),
                                 zone: grid
-                                    .find_nearest_zone(
+                                    .find_containing_zone(
                                         stop_times.stop.stop_lon.unwrap_or_default(),
                                         stop_times.stop.stop_lat.unwrap_or_default(),
                                     )
@@ -199,33 +441,17 @@ impl TransitNetwork {
             if !trips.is_none() {
                 for trip in trips.unwrap() {
                     for s in &trip.stop_times {
-                        if let Some(departure_time) = &s.departure_time {
-                            if let Ok(time) = NaiveTime::parse_from_str(departure_time, "%H:%M:%S")
-                            {
-                                if s.stop_sequence == 1 {
-                                    if time >= NaiveTime::from_hms_opt(5, 0, 0).unwrap()
-                                        && time <= NaiveTime::from_hms_opt(7, 0, 0).unwrap()
-                                    {
-                                        *freq_hash.entry(1).or_insert(0) += 1;
-                                    } else if time >= NaiveTime::from_hms_opt(7, 0, 0).unwrap()
-                                        && time <= NaiveTime::from_hms_opt(9, 30, 0).unwrap()
-                                    {
-                                        *freq_hash.entry(2).or_insert(0) += 1;
-                                    } else if time >= NaiveTime::from_hms_opt(9, 30, 0).unwrap()
-                                        && time <= NaiveTime::from_hms_opt(15, 0, 0).unwrap()
-                                    {
-                                        *freq_hash.entry(3).or_insert(0) += 1;
-                                    } else if time >= NaiveTime::from_hms_opt(15, 0, 0).unwrap()
-                                        && time <= NaiveTime::from_hms_opt(19, 0, 0).unwrap()
-                                    {
-                                        *freq_hash.entry(4).or_insert(0) += 1;
-                                    } else if time >= NaiveTime::from_hms_opt(19, 0, 0).unwrap()
-                                        && time <= NaiveTime::from_hms_opt(22, 0, 0).unwrap()
-                                    {
-                                        *freq_hash.entry(5).or_insert(0) += 1;
-                                    }
-                                }
-                            }
+                        if s.stop_sequence != 1 {
+                            continue;
+                        }
+                        // GTFS times are in the agency's time zone, as are the period bounds
+                        if let Some(period) = s
+                            .departure_time
+                            .as_deref()
+                            .and_then(parse_gtfs_time)
+                            .and_then(TimePeriod::from_local_seconds)
+                        {
+                            *freq_hash.entry(period.to_number()).or_insert(0) += 1;
                         }
                     }
                 }
@@ -233,7 +459,10 @@ impl TransitNetwork {
 
             // Classify route type
             let route_type = if route.route_type == RouteType::Bus
-                && (is_intercity(trip1, road) || is_intercity(trip2, road))
+                && (is_intercity(route_trips.outbound, road)
+                    || route_trips
+                        .inbound
+                        .is_some_and(|trip| is_intercity(trip, road)))
             {
                 log::debug!("Classifying route {} as an intercity bus", route.route_id);
                 TransitRouteType::IntercityBus
@@ -248,6 +477,8 @@ impl TransitNetwork {
                 outbound_stops: outbound_stops,
                 stop_times: freq_hash,
                 evals: None,
+                service_span: trips.and_then(ServiceSpan::from_trips),
+                vehicle: None,
             });
         }
 
@@ -256,13 +487,15 @@ impl TransitNetwork {
             inbound_stops: inbound_stops_tree,
             outbound_stops: outbound_stops_tree,
             evals: None,
+            dropped_routes,
+            demand_model: DemandModelConfig::default(),
         };
 
         // Calculate all route evals first
         let route_evals: Vec<_> = network
             .routes
             .iter()
-            .map(|route| TransitRouteEvals::for_route(&network, route, grid))
+            .map(|route| TransitRouteEvals::for_route(&network, route, grid, search))
             .collect();
 
         // Then update the routes with their evaluations
@@ -275,8 +508,49 @@ impl TransitNetwork {
         Ok(network)
     }
 
+    /// Convert the whole transit network to GTFS format, see `to_gtfs_filtered`
+    ///
+    /// The feed is validated once built and the issues found are logged, see
+    /// `validator::validate`.
     pub fn to_gtfs(&self, src_gtfs: &Gtfs, road: &RoadNetwork) -> Gtfs {
-        return TransitNetwork::to_gtfs_filtered(self.routes.iter().collect(), src_gtfs, road);
+        let gtfs = TransitNetwork::to_gtfs_filtered(self.routes.iter().collect(), src_gtfs, road);
+        let report = validator::validate(&gtfs);
+        if !report.is_valid() {
+            log::warn!(
+                "GTFS generated from the transit network has {} issues: {:?}",
+                report.issues.len(),
+                report.counts
+            );
+        }
+        gtfs
+    }
+
+    /// Bus routes with consecutive outbound stops that are not both mapped to a road node
+    ///
+    /// # Parameters
+    /// - `road`: The road network
+    ///
+    /// # Returns
+    /// The number of such stop pairs of each route, which are exported as straight segments
+    /// by `to_gtfs`. Routes without any are left out.
+    pub fn approximate_geometry(&self, road: &RoadNetwork) -> Vec<ApproximateGeometry> {
+        self.routes
+            .iter()
+            .filter(|route| route.route_type == TransitRouteType::Bus)
+            .filter_map(|route| {
+                let segments = route
+                    .outbound_stops
+                    .windows(2)
+                    .filter(|w| {
+                        w[0].get_node_index(road).is_none() || w[1].get_node_index(road).is_none()
+                    })
+                    .count();
+                (segments > 0).then(|| ApproximateGeometry {
+                    route_id: route.route_id.clone(),
+                    segments,
+                })
+            })
+            .collect()
     }
 
     /// Convert the transit network to GTFS format
@@ -288,8 +562,11 @@ impl TransitNetwork {
     ///
     /// # Returns
     /// A GTFS object representing the transit network
-    /// Currently only outputs the OUTBOUND trip of each route.
-    /// TODO: Support inbound and outbound trips, display separate on frontend + geojson
+    /// Outputs a trip for each direction of a route, the outbound trip with `direction_id` 0
+    /// and the route id as trip and shape id, the inbound trip with `direction_id` 1 and
+    /// `{route_id}_inbound` as trip and shape id.
+    /// Stops without a road path between them (e.g. not mapped to a road node) are joined by a
+    /// straight segment, counted in `approximate_shapes`.
     pub fn to_gtfs_filtered(
         target_routes: Vec<&TransitRoute>,
         src_gtfs: &Gtfs,
@@ -299,6 +576,8 @@ impl TransitNetwork {
         let mut trips: HashMap<String, Vec<Trip>> = HashMap::new();
         let mut routes: HashMap<String, Route> = HashMap::new();
         let mut shapes: HashMap<String, Vec<Shape>> = HashMap::new();
+        let mut approximate_shapes: HashMap<String, usize> = HashMap::new();
+        let mut synthesized_shapes: HashSet<String> = HashSet::new();
         for route in target_routes {
             TransitNetwork::route_to_gtfs_helper(
                 route,
@@ -308,6 +587,8 @@ impl TransitNetwork {
                 &mut trips,
                 &mut routes,
                 &mut shapes,
+                &mut approximate_shapes,
+                &mut synthesized_shapes,
             );
         }
 
@@ -316,23 +597,49 @@ impl TransitNetwork {
             trips: trips,
             routes: routes,
             shapes: shapes,
+            approximate_shapes,
+            synthesized_shapes,
             ..Gtfs::default()
         }
     }
 
-    pub fn to_gtfs_copy(target_routes: Vec<&TransitRoute>, src_gtfs: &Gtfs) -> Gtfs {
+    /// Copy routes as they are in the source GTFS
+    ///
+    /// # Parameters
+    /// - `target_routes`: Routes to copy
+    /// - `src_gtfs`: The original GTFS data
+    /// - `road`: The road network, to draw the trips the feed has no shape for
+    ///
+    /// # Returns
+    /// A GTFS object with the representative trip of each direction of the routes
+    ///
+    /// # Notes
+    /// - Trips without a shape in the feed, e.g. when it has no `shapes.txt`, get one drawn
+    ///   from stop to stop, listed in `synthesized_shapes`. Bus trips follow the road paths
+    ///   between their stops as in `to_gtfs_filtered`, other trips are drawn as straight
+    ///   segments, both counted in `approximate_shapes`.
+    pub fn to_gtfs_copy(
+        target_routes: Vec<&TransitRoute>,
+        src_gtfs: &Gtfs,
+        road: &RoadNetwork,
+    ) -> Gtfs {
         let mut stops: HashMap<String, Arc<Stop>> = HashMap::new();
         let mut trips: HashMap<String, Vec<Trip>> = HashMap::new();
         let mut routes: HashMap<String, Route> = HashMap::new();
         let mut shapes: HashMap<String, Vec<Shape>> = HashMap::new();
+        let mut approximate_shapes: HashMap<String, usize> = HashMap::new();
+        let mut synthesized_shapes: HashSet<String> = HashSet::new();
         for route in target_routes {
             TransitNetwork::copy_route_from_gtfs_helper(
-                &src_gtfs.routes.get(&route.route_id).unwrap(),
+                route,
                 src_gtfs,
+                road,
                 &mut stops,
                 &mut trips,
                 &mut routes,
                 &mut shapes,
+                &mut approximate_shapes,
+                &mut synthesized_shapes,
             );
         }
 
@@ -341,44 +648,110 @@ impl TransitNetwork {
             trips: trips,
             routes: routes,
             shapes: shapes,
+            approximate_shapes,
+            synthesized_shapes,
             ..Gtfs::default()
         }
     }
 
     fn copy_route_from_gtfs_helper(
-        route: &Route,
+        route: &TransitRoute,
         src_gtfs: &Gtfs,
+        road: &RoadNetwork,
         stops: &mut HashMap<String, Arc<Stop>>,
         trips: &mut HashMap<String, Vec<Trip>>,
         routes: &mut HashMap<String, Route>,
         shapes: &mut HashMap<String, Vec<Shape>>,
+        approximate_shapes: &mut HashMap<String, usize>,
+        synthesized_shapes: &mut HashSet<String>,
     ) {
         let src_route = src_gtfs.routes.get(&route.route_id).unwrap();
         routes.insert(src_route.route_id.clone(), (*src_route).clone());
-        let trip = {
-            let (trip1, trip2) = pick_inbound_outbound_trips(&route.route_id, src_gtfs).unwrap();
-            if trip_is_outbound(trip1) {
-                trip1
-            } else {
-                trip2
-            }
+        let route_trips = match pick_inbound_outbound_trips(&route.route_id, src_gtfs) {
+            Ok(trips) => trips,
+            Err(_) => return,
         };
-        for src_trip in [trip] {
-            trips
-                .entry(route.route_id.clone())
-                .or_insert_with(Vec::new)
-                .push((*src_trip).clone());
-            if let Some(src_shape_id) = &src_trip.shape_id {
-                let src_shape = src_gtfs.shapes.get(src_shape_id).unwrap();
-                shapes.insert(src_shape_id.clone(), src_shape.clone());
-            }
+        let directed_trips = std::iter::once((route_trips.outbound, 0))
+            .chain(route_trips.inbound.map(|trip| (trip, 1)));
+        for (src_trip, direction_id) in directed_trips {
+            // directions are numbered as in `to_gtfs_filtered`, whatever the feed uses
+            let mut trip = (*src_trip).clone();
+            trip.direction_id = Some(direction_id);
             for src_stop_time in src_trip.stop_times.iter() {
                 let src_stop = src_gtfs.stops.get(&src_stop_time.stop_id).unwrap();
                 stops.insert(src_stop.stop_id.clone(), src_stop.clone());
             }
+            match src_trip
+                .shape_id
+                .as_ref()
+                .and_then(|id| src_gtfs.shapes.get(id))
+            {
+                Some(src_shape) => {
+                    shapes.insert(src_trip.shape_id.clone().unwrap(), src_shape.clone());
+                }
+                None => {
+                    let shape_id = format!("{}_synthesized", trip.trip_id);
+                    let (shape, straight_segments) = TransitNetwork::synthesize_shape_helper(
+                        route, &trip, &shape_id, src_gtfs, road, stops,
+                    );
+                    if straight_segments > 0 {
+                        approximate_shapes.insert(shape_id.clone(), straight_segments);
+                    }
+                    synthesized_shapes.insert(shape_id.clone());
+                    shapes.insert(shape_id.clone(), shape);
+                    trip.shape_id = Some(shape_id);
+                }
+            }
+            trips.entry(route.route_id.clone()).or_default().push(trip);
         }
     }
 
+    /// Shape of a trip the feed has no shape for
+    ///
+    /// # Returns
+    /// The shape and the number of straight segments in it
+    fn synthesize_shape_helper(
+        route: &TransitRoute,
+        trip: &Trip,
+        shape_id: &str,
+        src_gtfs: &Gtfs,
+        road: &RoadNetwork,
+        stops: &mut HashMap<String, Arc<Stop>>,
+    ) -> (Vec<Shape>, usize) {
+        let direction_stops = if trip.direction_id == Some(1) {
+            &route.inbound_stops
+        } else {
+            &route.outbound_stops
+        };
+        if route.route_type == TransitRouteType::Bus && direction_stops.len() >= 2 {
+            let (_, shape, straight_segments) = TransitNetwork::direction_to_gtfs_helper(
+                direction_stops,
+                &route.route_id,
+                shape_id,
+                trip.direction_id.unwrap_or(0),
+                src_gtfs,
+                road,
+                stops,
+            );
+            return (shape, straight_segments);
+        }
+        let shape: Vec<Shape> = trip
+            .stop_times
+            .iter()
+            .filter_map(|stop_time| Some((stop_time.stop.stop_lat?, stop_time.stop.stop_lon?)))
+            .enumerate()
+            .map(|(seq, (lat, lon))| Shape {
+                shape_id: shape_id.to_string(),
+                shape_pt_lat: lat,
+                shape_pt_lon: lon,
+                shape_pt_sequence: seq as i32,
+                ..Shape::default()
+            })
+            .collect();
+        let straight_segments = shape.len().saturating_sub(1);
+        (shape, straight_segments)
+    }
+
     fn route_to_gtfs_helper(
         route: &TransitRoute,
         src_gtfs: &Gtfs,
@@ -387,26 +760,91 @@ impl TransitNetwork {
         trips: &mut HashMap<String, Vec<Trip>>,
         routes: &mut HashMap<String, Route>,
         shapes: &mut HashMap<String, Vec<Shape>>,
+        approximate_shapes: &mut HashMap<String, usize>,
+        synthesized_shapes: &mut HashSet<String>,
     ) {
         if route.route_type != TransitRouteType::Bus {
             // Copy non-bus routes / trips / shapes / stops as is
             TransitNetwork::copy_route_from_gtfs_helper(
-                &src_gtfs.routes.get(&route.route_id).unwrap(),
+                route,
                 src_gtfs,
+                road,
                 stops,
                 trips,
                 routes,
                 shapes,
+                approximate_shapes,
+                synthesized_shapes,
             );
             return;
         }
         let route_id = route.route_id.clone();
+        let directions = [
+            (&route.outbound_stops, route_id.clone(), 0),
+            (&route.inbound_stops, format!("{}_inbound", route_id), 1),
+        ];
+        let mut route_trips = vec![];
+        for (direction_stops, trip_id, direction_id) in directions {
+            if direction_stops.is_empty() {
+                continue;
+            }
+            let (trip, shape, straight_segments) = TransitNetwork::direction_to_gtfs_helper(
+                direction_stops,
+                &route_id,
+                &trip_id,
+                direction_id,
+                src_gtfs,
+                road,
+                stops,
+            );
+            if straight_segments > 0 {
+                approximate_shapes.insert(trip_id.clone(), straight_segments);
+            }
+            shapes.insert(trip_id, shape);
+            route_trips.push(trip);
+        }
+        // TODO eventually can have many trips...
+        trips.insert(route_id.clone(), route_trips);
+        let gtfs_route = match src_gtfs.routes.get(&route_id) {
+            Some(src_route) => Route {
+                route_id: route_id.clone(),
+                route_short_name: src_route.route_short_name.clone(),
+                route_long_name: src_route.route_long_name.clone(),
+                route_desc: src_route.route_desc.clone(),
+                route_type: src_route.route_type,
+                route_url: src_route.route_url.clone(),
+                ..Route::default()
+            },
+            // routes created from scratch are not in the feed
+            None => Route {
+                route_id: route_id.clone(),
+                route_short_name: Some(route_id.clone()),
+                ..Route::default()
+            },
+        };
+        routes.insert(route_id.clone(), gtfs_route);
+    }
+
+    /// Trip and shape of one direction of a bus route
+    ///
+    /// # Returns
+    /// The trip, its shape and the number of straight segments in the shape
+    fn direction_to_gtfs_helper(
+        direction_stops: &[Arc<TransitStop>],
+        route_id: &str,
+        trip_id: &str,
+        direction_id: i16,
+        src_gtfs: &Gtfs,
+        road: &RoadNetwork,
+        stops: &mut HashMap<String, Arc<Stop>>,
+    ) -> (Trip, Vec<Shape>, usize) {
         let mut shape = Vec::new();
         let mut stop_times = Vec::new();
         let mut stop_sequence = 0;
         let mut prev_stop: Option<&Arc<TransitStop>> = None;
         let mut shape_pt_sequence = 0;
-        route.outbound_stops.iter().for_each(|stop| {
+        let mut straight_segments = 0;
+        direction_stops.iter().for_each(|stop| {
             let stop_id = stop.stop_id.clone();
             let gtfs_stop: Arc<Stop> = if !stops.contains_key(&stop_id) {
                 let src_stop = src_gtfs.stops.get(&stop_id).unwrap();
@@ -417,7 +855,7 @@ impl TransitNetwork {
             };
             // This probably needs to be fixed
             stop_times.push(StopTime {
-                trip_id: route_id.clone(),
+                trip_id: trip_id.to_string(),
                 stop_id: stop_id.clone(),
                 stop_sequence: stop_sequence,
                 stop: gtfs_stop.clone(),
@@ -426,12 +864,18 @@ impl TransitNetwork {
             // The trip points to a shape
             if let Some(ps) = prev_stop {
                 let (_, path) = ps.road_distance(stop, road);
-                for node_index in path.iter() {
-                    let node = road.get_node(*node_index);
+                let points: Vec<Point> = if path.is_empty() {
+                    // no road path, fall back to a straight line between the stops
+                    straight_segments += 1;
+                    vec![ps.geom, stop.geom]
+                } else {
+                    path.iter().map(|n| road.get_node(*n).geom).collect()
+                };
+                for point in points {
                     shape.push(Shape {
-                        shape_id: route_id.clone(),
-                        shape_pt_lat: node.geom.y(),
-                        shape_pt_lon: node.geom.x(),
+                        shape_id: trip_id.to_string(),
+                        shape_pt_lat: point.y(),
+                        shape_pt_lon: point.x(),
                         shape_pt_sequence: shape_pt_sequence,
                         ..Shape::default()
                     });
@@ -441,31 +885,15 @@ impl TransitNetwork {
             stop_sequence += 1;
             prev_stop = Some(stop);
         });
-        // TODO eventually can have many trips...
-        trips.insert(
-            route_id.clone(),
-            vec![Trip {
-                route_id: route_id.clone(),
-                trip_id: route_id.clone(),
-                shape_id: Some(route_id.clone()),
-                stop_times: stop_times,
-                ..Trip::default()
-            }],
-        );
-        let src_route = src_gtfs.routes.get(&route_id).unwrap();
-        routes.insert(
-            route_id.clone(),
-            Route {
-                route_id: route_id.clone(),
-                route_short_name: src_route.route_short_name.clone(),
-                route_long_name: src_route.route_long_name.clone(),
-                route_desc: src_route.route_desc.clone(),
-                route_type: src_route.route_type,
-                route_url: src_route.route_url.clone(),
-                ..Route::default()
-            },
-        );
-        shapes.insert(route_id.clone(), shape);
+        let trip = Trip {
+            route_id: route_id.to_string(),
+            trip_id: trip_id.to_string(),
+            direction_id: Some(direction_id),
+            shape_id: Some(trip_id.to_string()),
+            stop_times,
+            ..Trip::default()
+        };
+        (trip, shape, straight_segments)
     }
 }
 
@@ -594,6 +1022,88 @@ fn map_transit_stops_to_osmid(trip: &Trip, road: &RoadNetwork) -> HashMap<String
         .collect()
 }
 
+/// Trips picked to represent each direction of a route
+struct RouteTrips<'a> {
+    outbound: &'a Trip,
+    inbound: Option<&'a Trip>,
+    source: DirectionSource,
+    outbound_count: usize,
+    inbound_count: usize,
+    mislabelled_count: usize,
+}
+
+/// Keep the parts of a feed the optimizer and the network maps use
+///
+/// Routes, stops, agencies, calendars and feed info are kept whole. Each route keeps only the
+/// representative trip of each direction, with `direction_id` set to 0 for the outbound trip
+/// and 1 for the inbound trip, and their shapes, which is all `to_gtfs_copy` and
+/// `to_gtfs_filtered` read. Fares and the remaining trips and shapes are dropped.
+///
+/// # Parameters
+/// - `gtfs`: The full GTFS data
+///
+/// # Returns
+/// The slim feed, picking the representative trip of a route in it returns the same trip
+pub fn slim_gtfs(gtfs: &Gtfs) -> Gtfs {
+    let mut trips: HashMap<String, Vec<Trip>> = HashMap::new();
+    let mut shapes: HashMap<String, Vec<Shape>> = HashMap::new();
+    for route_id in gtfs.routes.keys() {
+        if let Ok(route_trips) = pick_inbound_outbound_trips(route_id, gtfs) {
+            let directed_trips = std::iter::once((route_trips.outbound, 0))
+                .chain(route_trips.inbound.map(|trip| (trip, 1)));
+            for (trip, direction_id) in directed_trips {
+                if let Some(shape) = trip.shape_id.as_ref().and_then(|id| gtfs.shapes.get(id)) {
+                    shapes.insert(trip.shape_id.clone().unwrap(), shape.clone());
+                }
+                let mut trip = trip.clone();
+                trip.direction_id = Some(direction_id);
+                trips.entry(route_id.clone()).or_default().push(trip);
+            }
+        }
+    }
+
+    Gtfs {
+        calendar: gtfs.calendar.clone(),
+        calendar_dates: gtfs.calendar_dates.clone(),
+        stops: gtfs.stops.clone(),
+        routes: gtfs.routes.clone(),
+        trips,
+        agencies: gtfs.agencies.clone(),
+        shapes,
+        feed_info: gtfs.feed_info.clone(),
+        ..Gtfs::default()
+    }
+}
+
+/// Classify the direction of every route in the GTFS data
+///
+/// # Parameters
+/// - `gtfs`: The GTFS data
+///
+/// # Returns
+/// The direction classification of each route with at least one usable trip, sorted by route id
+pub fn classify_route_directions(gtfs: &Gtfs) -> Vec<RouteDirection> {
+    let mut directions: Vec<RouteDirection> = gtfs
+        .routes
+        .keys()
+        .filter_map(|route_id| {
+            pick_inbound_outbound_trips(route_id, gtfs)
+                .ok()
+                .map(|trips| RouteDirection {
+                    route_id: route_id.clone(),
+                    source: trips.source,
+                    outbound_trip_id: trips.outbound.trip_id.clone(),
+                    inbound_trip_id: trips.inbound.map(|t| t.trip_id.clone()),
+                    outbound_trips: trips.outbound_count,
+                    inbound_trips: trips.inbound_count,
+                    mislabelled_trips: trips.mislabelled_count,
+                })
+        })
+        .collect();
+    directions.sort_by(|a, b| a.route_id.cmp(&b.route_id));
+    directions
+}
+
 /// Pick the longest trip in each direction
 ///
 /// # Parameters
@@ -601,42 +1111,151 @@ fn map_transit_stops_to_osmid(trip: &Trip, road: &RoadNetwork) -> HashMap<String
 /// - `gtfs`: The GTFS data
 ///
 /// # Returns
-/// A tuple containing the longest trip in each direction
-/// or None if 2 trips in different directions were not found.
+/// The longest trip in each direction, or why the route cannot be built if it has no trip
+/// with at least 2 stops. Trips are split by `direction_id` when both directions are present,
+/// counting the trips whose stops run against their `direction_id`. Otherwise they are split by
+/// comparing each trip to the longest trip of the route (see `same_direction`), and the inbound
+/// trip is `None` if all trips run the same way (e.g. loops). A `direction_id` other than 0 or 1
+/// is treated as missing.
 fn pick_inbound_outbound_trips<'a>(
     route_id: &String,
     gtfs: &'a Gtfs,
-) -> Option<(&'a Trip, &'a Trip)> {
-    if !gtfs.trips.contains_key(route_id) {
-        return None;
-    }
-    // Get the longest trip in each direction
-    let trip1 = gtfs
+) -> Result<RouteTrips<'a>, DropReason> {
+    let trips: Vec<&Trip> = gtfs
         .trips
         .get(route_id)
-        .unwrap()
+        .filter(|trips| !trips.is_empty())
+        .ok_or(DropReason::NoTrips)?
         .iter()
-        .filter(|trip| trip.direction_id == Some(0))
-        .max_by_key(|trip| trip.stop_times.len());
-    let trip2 = gtfs
-        .trips
-        .get(route_id)
-        .unwrap()
-        .iter()
-        .filter(|trip| trip.direction_id == Some(1))
-        .max_by_key(|trip| trip.stop_times.len());
-    if let (Some(trip1), Some(trip2)) = (trip1, trip2) {
-        // Ensure that the trips are in different directions
-        // assert_ne!(
-        //     trip_is_outbound(trip1),
-        //     trip_is_outbound(trip2),
-        //     "The trips must be in different directions"
-        // );
-        Some((trip1, trip2))
+        .filter(|trip| trip.stop_times.len() >= 2)
+        .collect();
+    let longest = |trips: &[&'a Trip]| {
+        trips
+            .iter()
+            .copied()
+            .max_by_key(|t| t.stop_times.len())
+            .ok_or(DropReason::TooFewStops)
+    };
+
+    let has_direction = |d: i16| trips.iter().any(|t| direction_of(t) == Some(d));
+    let mut mislabelled_count = 0;
+    let (source, outbound, inbound) = if has_direction(0) && has_direction(1) {
+        let reference = longest(
+            &trips
+                .iter()
+                .copied()
+                .filter(|t| direction_of(t) == Some(0))
+                .collect::<Vec<_>>(),
+        )?;
+        // Trips without a direction_id are assigned relative to direction 0
+        let (outbound, inbound): (Vec<&Trip>, Vec<&Trip>) =
+            trips.iter().partition(|t| match direction_of(t) {
+                Some(d) => d == 0,
+                None => same_direction(t, reference),
+            });
+        mislabelled_count = trips
+            .iter()
+            .filter(|t| direction_of(t).is_some_and(|d| (d == 0) != same_direction(t, reference)))
+            .count();
+        (DirectionSource::DirectionId, outbound, inbound)
     } else {
-        // If there are no trips in one direction, return None
-        None
+        let reference = longest(&trips)?;
+        let (same, opposite): (Vec<&Trip>, Vec<&Trip>) =
+            trips.iter().partition(|t| same_direction(t, reference));
+        // Keep the historical convention of labelling the north-easterly group as outbound
+        if opposite.is_empty() || trip_is_outbound(reference) {
+            (DirectionSource::Geometry, same, opposite)
+        } else {
+            (DirectionSource::Geometry, opposite, same)
+        }
+    };
+
+    Ok(RouteTrips {
+        outbound: longest(&outbound)?,
+        inbound: longest(&inbound).ok(),
+        source,
+        outbound_count: outbound.len(),
+        inbound_count: inbound.len(),
+        mislabelled_count,
+    })
+}
+
+/// `direction_id` of a trip, `None` if it is missing or not one of the two values GTFS allows
+fn direction_of(trip: &Trip) -> Option<i16> {
+    trip.direction_id.filter(|d| matches!(d, 0 | 1))
+}
+
+/// Check if a trip runs in the same direction as a reference trip
+///
+/// # Parameters
+/// - `trip`: The trip to check
+/// - `reference`: The trip to compare against
+///
+/// # Returns
+/// `true` if the trip runs in the same direction as the reference.
+/// Stops shared with the reference are compared first: the trip runs the same way if
+/// they are mostly visited in increasing order along the reference. When that is
+/// inconclusive, which happens for trips that share no stops with the reference (e.g.
+/// opposite sides of the street), the end stops are compared: a trip whose ends lie much
+/// closer to the ends of the reference one way round than the other runs that way, which
+/// holds for short turns and curved routes. The bearings from first to last stop decide the
+/// remaining trips. Loops, whose first and last stop coincide, are treated as the same
+/// direction.
+fn same_direction(trip: &Trip, reference: &Trip) -> bool {
+    let positions: HashMap<&str, usize> = reference
+        .stop_times
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, st)| (st.stop_id.as_str(), i))
+        .collect();
+    let shared: Vec<usize> = trip
+        .stop_times
+        .iter()
+        .filter_map(|st| positions.get(st.stop_id.as_str()).copied())
+        .collect();
+    let (forward, backward) = shared
+        .windows(2)
+        .fold((0, 0), |(f, b), w| match w[0].cmp(&w[1]) {
+            std::cmp::Ordering::Less => (f + 1, b),
+            std::cmp::Ordering::Greater => (f, b + 1),
+            std::cmp::Ordering::Equal => (f, b),
+        });
+    if forward != backward {
+        return forward > backward;
+    }
+
+    let (a1, b1) = trip_endpoints(trip);
+    let (a2, b2) = trip_endpoints(reference);
+    if a1 == b1 || a2 == b2 {
+        return true;
+    }
+    let aligned = Haversine::distance(a1, a2) + Haversine::distance(b1, b2);
+    let swapped = Haversine::distance(a1, b2) + Haversine::distance(b1, a2);
+    if aligned < swapped * ENDPOINT_MATCH_RATIO {
+        return true;
     }
+    if swapped < aligned * ENDPOINT_MATCH_RATIO {
+        return false;
+    }
+    geo_util::bearing_difference(a1, b1, a2, b2) <= 90.0
+}
+
+/// First and last stop locations of a trip
+fn trip_endpoints(trip: &Trip) -> (Point, Point) {
+    let location = |st: Option<&StopTime>| {
+        st.map(|st| {
+            Point::new(
+                st.stop.stop_lon.unwrap_or_default(),
+                st.stop.stop_lat.unwrap_or_default(),
+            )
+        })
+        .unwrap_or_else(|| Point::new(0.0, 0.0))
+    };
+    (
+        location(trip.stop_times.first()),
+        location(trip.stop_times.last()),
+    )
 }
 
 /// Check if the trip is outbound
@@ -649,20 +1268,7 @@ fn pick_inbound_outbound_trips<'a>(
 /// Outbound is defined as trips that have a northerly or
 /// easterly geodesic bearing
 fn trip_is_outbound(trip: &Trip) -> bool {
-    let (first, last) = (
-        trip.stop_times.first().unwrap().stop.clone(),
-        trip.stop_times.last().unwrap().stop.clone(),
-    );
-    let (a, b) = (
-        Point::new(
-            first.stop_lon.unwrap_or_default(),
-            first.stop_lat.unwrap_or_default(),
-        ),
-        Point::new(
-            last.stop_lon.unwrap_or_default(),
-            last.stop_lat.unwrap_or_default(),
-        ),
-    );
+    let (a, b) = trip_endpoints(trip);
     geo_util::is_outbound(a, b)
 }
 
@@ -695,12 +1301,24 @@ pub struct TransitStop {
     pub stop_id: String,
     pub geom: Point,
     osmid: Option<u64>,     // nearby road network osmid, if one exists
-    zone: Option<u32>,      // nearby zone id, if one exists
+    zone: Option<u32>,      // id of the zone containing this stop, assigned once at load
     nearby_zones: Vec<u32>, // zones within 400m radius of this stop (walking distance)
 }
 
 impl TransitStop {
-    fn get_node_index(&self, road: &RoadNetwork) -> Option<NodeIndex> {
+    /// A stop off the road network and outside every zone
+    #[cfg(test)]
+    pub(crate) fn new(stop_id: &str, geom: Point) -> TransitStop {
+        TransitStop {
+            stop_id: stop_id.to_string(),
+            geom,
+            osmid: None,
+            zone: None,
+            nearby_zones: vec![],
+        }
+    }
+
+    pub(crate) fn get_node_index(&self, road: &RoadNetwork) -> Option<NodeIndex> {
         if let Some(osmid) = self.osmid {
             road.get_node_index_by_osmid(osmid)
         } else {
@@ -720,6 +1338,9 @@ impl TransitStop {
     }
 
     /// Get the stops's enclosing zone node index
+    ///
+    /// The zone is assigned when the network is built, so this is a lookup and should be
+    /// preferred over testing zone polygons for containment.
     pub fn zone_index(&self, grid: &GridNetwork) -> Option<NodeIndex> {
         if let Some(zoneid) = self.zone {
             Some(grid.get_zone_idx_by_id(zoneid))
@@ -728,15 +1349,6 @@ impl TransitStop {
         }
     }
 
-    /// Get the stops's enclosing zone
-    pub fn zone<'a>(&self, grid: &'a GridNetwork) -> Option<&'a Zone> {
-        if let Some(idx) = self.zone_index(grid) {
-            Some(grid.get_zone(idx))
-        } else {
-            None
-        }
-    }
-
     pub fn nearby_zones<'a>(&self, grid: &'a GridNetwork) -> Vec<&'a Zone> {
         self.nearby_zones
             .iter()
@@ -744,6 +1356,16 @@ impl TransitStop {
             .collect()
     }
 
+    /// Id of the stop's enclosing zone
+    pub fn zone_id(&self) -> Option<u32> {
+        self.zone
+    }
+
+    /// Ids of the zones within walking distance of the stop
+    pub fn nearby_zone_ids(&self) -> &[u32] {
+        &self.nearby_zones
+    }
+
     pub fn nearby_zone_indices(&self, grid: &GridNetwork) -> Vec<NodeIndex> {
         self.nearby_zones
             .iter()
@@ -752,6 +1374,15 @@ impl TransitStop {
     }
 }
 
+/// Ids of the zones containing the outbound stops of a route
+pub fn route_zone_ids(route: &TransitRoute) -> HashSet<u32> {
+    route
+        .outbound_stops
+        .iter()
+        .filter_map(|stop| stop.zone_id())
+        .collect()
+}
+
 #[derive(PartialEq, Clone, Deserialize, Serialize)]
 pub struct RTreeNode {
     pub envelope: AABB<[f64; 2]>,
@@ -774,3 +1405,96 @@ impl RTreeObject for RTreeNode {
 fn compute_envelope(point: &Point<f64>) -> AABB<[f64; 2]> {
     return AABB::from_point(point.x_y().into());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::structs::{Route, Stop};
+
+    /// Trip through stops given as `(stop_id, lon, lat)`
+    fn trip(trip_id: &str, direction_id: Option<i16>, stops: &[(&str, f64, f64)]) -> Trip {
+        let stop_times = stops
+            .iter()
+            .enumerate()
+            .map(|(i, (stop_id, lon, lat))| StopTime {
+                trip_id: trip_id.to_string(),
+                stop_id: stop_id.to_string(),
+                stop_sequence: i as i32 + 1,
+                stop: Arc::new(Stop {
+                    stop_id: stop_id.to_string(),
+                    stop_lon: Some(*lon),
+                    stop_lat: Some(*lat),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .collect();
+        Trip {
+            route_id: "1".to_string(),
+            trip_id: trip_id.to_string(),
+            direction_id,
+            stop_times,
+            ..Default::default()
+        }
+    }
+
+    fn gtfs(trips: Vec<Trip>) -> Gtfs {
+        let mut gtfs = Gtfs::default();
+        gtfs.routes.insert(
+            "1".to_string(),
+            Route {
+                route_id: "1".to_string(),
+                ..Default::default()
+            },
+        );
+        gtfs.trips.insert("1".to_string(), trips);
+        gtfs
+    }
+
+    // Eastbound and westbound stops on opposite sides of the street, sharing no stop ids
+    const EAST: &[(&str, f64, f64)] = &[("e1", 0.0, 0.0), ("e2", 0.01, 0.0), ("e3", 0.02, 0.0)];
+    const WEST: &[(&str, f64, f64)] = &[
+        ("w3", 0.02, 0.0001),
+        ("w2", 0.01, 0.0001),
+        ("w1", 0.0, 0.0001),
+    ];
+
+    #[test]
+    fn directions_are_inferred_without_direction_id() {
+        let directions = classify_route_directions(&gtfs(vec![
+            trip("east", None, EAST),
+            trip("west", None, WEST),
+            // short turn of the eastbound trips, from the first to the second stop
+            trip("east_short", None, &EAST[..2]),
+        ]));
+        assert_eq!(directions.len(), 1);
+        let direction = &directions[0];
+        assert_eq!(direction.source, DirectionSource::Geometry);
+        assert_eq!(direction.outbound_trip_id, "east");
+        assert_eq!(direction.inbound_trip_id.as_deref(), Some("west"));
+        assert_eq!((direction.outbound_trips, direction.inbound_trips), (2, 1));
+    }
+
+    #[test]
+    fn invalid_direction_id_is_treated_as_missing() {
+        let directions = classify_route_directions(&gtfs(vec![
+            trip("east", Some(0), EAST),
+            trip("west", Some(2), WEST),
+        ]));
+        assert_eq!(directions[0].source, DirectionSource::Geometry);
+        assert_eq!(directions[0].inbound_trip_id.as_deref(), Some("west"));
+    }
+
+    #[test]
+    fn trips_against_their_direction_id_are_counted() {
+        let directions = classify_route_directions(&gtfs(vec![
+            trip("east", Some(0), EAST),
+            trip("west", Some(1), WEST),
+            trip("east_mislabelled", Some(1), EAST),
+        ]));
+        let direction = &directions[0];
+        assert_eq!(direction.source, DirectionSource::DirectionId);
+        assert_eq!(direction.mislabelled_trips, 1);
+        assert_eq!((direction.outbound_trips, direction.inbound_trips), (1, 2));
+    }
+}
@@ -0,0 +1,114 @@
+use petgraph::graph::NodeIndex;
+use rusqlite::{params, Connection, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use super::road_network;
+
+/// Turns buses may not make at intersections, from the OSM turn restrictions of a city
+///
+/// Read from the optional `turn_restrictions` table of the city database, with one row per
+/// OSM restriction relation: `from_way`, `via_node`, `to_way` and `restriction`, e.g.
+/// `no_left_turn` or `only_straight_on`. Restrictions are resolved to the road nodes they
+/// forbid going through in a row when the roads are loaded.
+#[derive(Default, Deserialize, Serialize)]
+pub struct TurnRestrictions {
+    /// Node arrived from, node turned at and node left towards of each forbidden turn
+    banned: HashSet<(u32, u32, u32)>,
+}
+
+impl TurnRestrictions {
+    /// Load the turn restrictions of the city database
+    ///
+    /// # Parameters
+    /// - `conn`: Connection to the city database
+    /// - `node_map`: Index of the road node of each OSM node id
+    /// - `roads_from`: Nodes reached by the roads leaving a node, for `only_` restrictions
+    ///   which forbid every other turn
+    ///
+    /// # Returns
+    /// The restrictions, empty if the database does not have the table. Restrictions of
+    /// unknown kinds or on roads missing from the network are skipped.
+    pub fn load(
+        conn: &Connection,
+        node_map: &HashMap<u64, NodeIndex>,
+        roads_from: impl Fn(NodeIndex) -> Vec<NodeIndex>,
+    ) -> Result<TurnRestrictions> {
+        let has_table: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'turn_restrictions'",
+            params![],
+            |row| row.get(0),
+        )?;
+        if !has_table {
+            return Ok(TurnRestrictions::default());
+        }
+
+        // roads of the ways the restrictions start or end on
+        let mut ways: HashMap<u64, Vec<(u64, u64)>> = HashMap::new();
+        for (u, v, way) in road_network::read_restricted_roads(conn)? {
+            ways.entry(way).or_default().push((u, v));
+        }
+
+        let mut stmt =
+            conn.prepare("SELECT from_way, via_node, to_way, restriction FROM turn_restrictions")?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, u64>(1)?,
+                row.get::<_, u64>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        let index = |osmid: &u64| node_map.get(osmid).map(|n| n.index() as u32);
+        let mut banned = HashSet::new();
+        for (from_way, via_node, to_way, restriction) in rows.filter_map(|row| row.ok()) {
+            let Some(via) = index(&via_node) else {
+                continue;
+            };
+            let roads = |way: u64| ways.get(&way).map(Vec::as_slice).unwrap_or_default();
+            let arrivals: Vec<u32> = roads(from_way)
+                .iter()
+                .filter(|(_, v)| *v == via_node)
+                .filter_map(|(u, _)| index(u))
+                .collect();
+            let departures: HashSet<u32> = roads(to_way)
+                .iter()
+                .filter(|(u, _)| *u == via_node)
+                .filter_map(|(_, v)| index(v))
+                .collect();
+            for &from in &arrivals {
+                if restriction.starts_with("no_") {
+                    // a U-turn restriction does not forbid going on along the same way
+                    let forbidden = departures
+                        .iter()
+                        .filter(|&&to| from_way != to_way || to == from);
+                    banned.extend(forbidden.map(|&to| (from, via, to)));
+                } else if restriction.starts_with("only_") {
+                    let others = roads_from(NodeIndex::new(via as usize))
+                        .into_iter()
+                        .map(|to| to.index() as u32)
+                        .filter(|to| !departures.contains(to));
+                    banned.extend(others.map(|to| (from, via, to)));
+                }
+            }
+        }
+        Ok(TurnRestrictions { banned })
+    }
+
+    /// Whether turning at `via` from the road from `from` onto the road to `to` is allowed
+    pub fn allows(&self, from: NodeIndex, via: NodeIndex, to: NodeIndex) -> bool {
+        self.is_empty()
+            || !self
+                .banned
+                .contains(&(from.index() as u32, via.index() as u32, to.index() as u32))
+    }
+
+    /// Number of forbidden turns
+    pub fn len(&self) -> usize {
+        self.banned.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.banned.is_empty()
+    }
+}
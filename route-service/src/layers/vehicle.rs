@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use super::road_network::MAX_BUS_GRADE;
+
+/// Vehicles a route runs with, which set how many riders a trip carries and which streets and
+/// stop patterns suit the route
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VehicleProfile {
+    /// Riders a vehicle carries, seated and standing
+    pub capacity: usize,
+    /// Smallest angle in degrees between the road a vehicle arrives on and the road it leaves
+    /// on, 0 allows U-turns
+    pub min_turn_angle: f64,
+    /// Steepest average grade a vehicle climbs or descends between two stops, as a fraction
+    pub max_grade: f64,
+    /// Usual distance in meters between two stops
+    pub stop_spacing: f64,
+}
+
+/// Profile of the vehicles of each route that has one, by route id
+pub type RouteVehicles = HashMap<String, VehicleProfile>;
+
+impl VehicleProfile {
+    /// Profiles available without configuration, by name
+    pub fn builtin() -> BTreeMap<String, VehicleProfile> {
+        let profiles = [
+            ("standard", 50, 2.0, MAX_BUS_GRADE, 350.0),
+            ("articulated", 90, 30.0, 0.08, 450.0),
+            ("minibus", 25, 2.0, 0.14, 250.0),
+            ("trolleybus", 60, 20.0, 0.12, 350.0),
+        ];
+        profiles
+            .into_iter()
+            .map(
+                |(name, capacity, min_turn_angle, max_grade, stop_spacing)| {
+                    let profile = VehicleProfile {
+                        capacity,
+                        min_turn_angle,
+                        max_grade,
+                        stop_spacing,
+                    };
+                    (name.to_string(), profile)
+                },
+            )
+            .collect()
+    }
+
+    /// Check that the values are usable by the evaluations
+    pub fn validate(&self) -> Result<(), String> {
+        if self.capacity == 0 {
+            return Err("capacity must be positive".to_string());
+        }
+        if !(0.0..=180.0).contains(&self.min_turn_angle) {
+            return Err("min_turn_angle must be between 0 and 180 degrees".to_string());
+        }
+        if self.max_grade <= 0.0 {
+            return Err("max_grade must be positive".to_string());
+        }
+        if self.stop_spacing <= 0.0 {
+            return Err("stop_spacing must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether a vehicle can turn from one road onto the next
+    ///
+    /// # Parameters
+    /// - `deviation`: Change of bearing in degrees between the roads, 0 going straight on and
+    ///   180 for a U-turn
+    pub fn can_turn(&self, deviation: f64) -> bool {
+        180.0 - deviation.abs() >= self.min_turn_angle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_profiles_are_valid_and_limit_turns() {
+        let profiles = VehicleProfile::builtin();
+        for (name, profile) in &profiles {
+            assert!(profile.validate().is_ok(), "{}", name);
+        }
+        let standard = &profiles["standard"];
+        let articulated = &profiles["articulated"];
+        assert!(articulated.capacity > standard.capacity);
+        assert!(standard.can_turn(90.0) && articulated.can_turn(-90.0));
+        assert!(standard.can_turn(170.0) && !articulated.can_turn(170.0));
+        assert!(!standard.can_turn(179.0));
+
+        let invalid = VehicleProfile {
+            capacity: 0,
+            ..standard.clone()
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
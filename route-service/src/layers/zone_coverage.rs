@@ -0,0 +1,195 @@
+use std::collections::{HashMap, HashSet};
+
+use super::transit_network::{route_zone_ids, TransitNetwork, TransitRoute};
+
+/// Zones the outbound stops of each route are in and the routes stopping in each zone, to
+/// count the routes linking two zones without scanning every route
+///
+/// Stops know their enclosing zone from the time the network is built, so keeping the index
+/// up to date as routes change only looks up the zones of the changed routes.
+#[derive(Clone, Default)]
+pub struct ZoneCoverage {
+    route_zones: HashMap<String, HashSet<u32>>,
+    zone_routes: HashMap<u32, HashSet<String>>,
+}
+
+impl ZoneCoverage {
+    pub fn for_network(transit: &TransitNetwork) -> ZoneCoverage {
+        let mut coverage = ZoneCoverage::default();
+        for route in &transit.routes {
+            coverage.update_route(route);
+        }
+        coverage
+    }
+
+    /// Index a route with the zones of its current stops, replacing the zones it had
+    pub fn update_route(&mut self, route: &TransitRoute) {
+        let zones = route_zone_ids(route);
+        if self.route_zones.get(&route.route_id) == Some(&zones) {
+            return;
+        }
+        self.remove_route(&route.route_id);
+        for zone in &zones {
+            self.zone_routes
+                .entry(*zone)
+                .or_default()
+                .insert(route.route_id.clone());
+        }
+        self.route_zones.insert(route.route_id.clone(), zones);
+    }
+
+    pub fn remove_route(&mut self, route_id: &str) {
+        let Some(zones) = self.route_zones.remove(route_id) else {
+            return;
+        };
+        for zone in zones {
+            if let Some(routes) = self.zone_routes.get_mut(&zone) {
+                routes.remove(route_id);
+                if routes.is_empty() {
+                    self.zone_routes.remove(&zone);
+                }
+            }
+        }
+    }
+
+    /// Update the index to the routes of a network, reindexing only the routes that changed
+    pub fn sync(&mut self, transit: &TransitNetwork) {
+        let route_ids: HashSet<&str> = transit.routes.iter().map(|r| r.route_id.as_str()).collect();
+        let removed: Vec<String> = self
+            .route_zones
+            .keys()
+            .filter(|id| !route_ids.contains(id.as_str()))
+            .cloned()
+            .collect();
+        for route_id in removed {
+            self.remove_route(&route_id);
+        }
+        for route in &transit.routes {
+            self.update_route(route);
+        }
+    }
+
+    /// Whether the index holds the routes of a network with their current stops
+    pub fn is_current(&self, transit: &TransitNetwork) -> bool {
+        self.route_zones.len() == transit.routes.len()
+            && transit.routes.iter().all(|route| {
+                self.route_zones
+                    .get(&route.route_id)
+                    .is_some_and(|zones| *zones == route_zone_ids(route))
+            })
+    }
+
+    /// Number of routes stopping in both zones, other than `excluded_route_id`
+    pub fn shared_routes(&self, u: u32, v: u32, excluded_route_id: Option<&str>) -> u32 {
+        let (Some(a), Some(b)) = (self.zone_routes.get(&u), self.zone_routes.get(&v)) else {
+            return 0;
+        };
+        let (fewer, more) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+        fewer
+            .iter()
+            .filter(|id| more.contains(*id) && Some(id.as_str()) != excluded_route_id)
+            .count() as u32
+    }
+
+    /// Ids of the routes stopping in both zones, other than `excluded_route_id`, see
+    /// `shared_routes`
+    pub fn shared_route_ids(&self, u: u32, v: u32, excluded_route_id: Option<&str>) -> Vec<&str> {
+        let (Some(a), Some(b)) = (self.zone_routes.get(&u), self.zone_routes.get(&v)) else {
+            return vec![];
+        };
+        let (fewer, more) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+        fewer
+            .iter()
+            .filter(|id| more.contains(*id) && Some(id.as_str()) != excluded_route_id)
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Routes linking each pair of zones
+    ///
+    /// # Arguments
+    /// - `zone_ids`: Zones to pair up, without duplicates
+    /// - `excluded_route_id`: Route not to count, e.g. the route being evaluated
+    ///
+    /// # Returns
+    /// Number of routes by pair `(u, v)` of zones with `u` before `v` in `zone_ids`, pairs no
+    /// route links are left out
+    pub fn linking_routes(
+        &self,
+        zone_ids: &[u32],
+        excluded_route_id: Option<&str>,
+    ) -> HashMap<(u32, u32), u32> {
+        let mut routes = HashMap::new();
+        for (i, u) in zone_ids.iter().enumerate() {
+            if !self.zone_routes.contains_key(u) {
+                continue;
+            }
+            for v in &zone_ids[i + 1..] {
+                let shared = self.shared_routes(*u, *v, excluded_route_id);
+                if shared > 0 {
+                    routes.insert((*u, *v), shared);
+                }
+            }
+        }
+        routes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+    #[test]
+    fn index_follows_route_changes() {
+        let city = load_demo_city(
+            &format!("zone_coverage_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 3,
+                ..Default::default()
+            },
+        );
+
+        let transit = &city.transit;
+        let coverage = ZoneCoverage::for_network(transit);
+        assert!(coverage.is_current(transit));
+        let first = &transit.routes[0];
+        let zones: Vec<u32> = route_zone_ids(first).into_iter().collect();
+        assert!(zones.len() >= 2);
+        // same counts as scanning every route
+        for ((u, v), shared) in coverage.linking_routes(&zones, None) {
+            let scanned = transit
+                .routes
+                .iter()
+                .map(route_zone_ids)
+                .filter(|z| z.contains(&u) && z.contains(&v))
+                .count() as u32;
+            assert_eq!(shared, scanned);
+            assert_eq!(
+                coverage.shared_routes(u, v, Some(&first.route_id)),
+                shared - 1
+            );
+        }
+
+        // dropping the stops of a route is picked up by a sync
+        let mut changed = transit.clone();
+        changed.routes[0].outbound_stops.truncate(1);
+        assert!(!coverage.is_current(&changed));
+        let mut synced = coverage.clone();
+        synced.sync(&changed);
+        assert!(synced.is_current(&changed));
+        assert_eq!(
+            synced.linking_routes(&zones, None),
+            ZoneCoverage::for_network(&changed).linking_routes(&zones, None)
+        );
+        changed.routes.remove(0);
+        synced.sync(&changed);
+        assert_eq!(
+            synced.linking_routes(&zones, None),
+            ZoneCoverage::for_network(&changed).linking_routes(&zones, None)
+        );
+        assert!(synced.is_current(&changed));
+    }
+}
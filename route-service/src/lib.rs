@@ -1,3 +1,5 @@
+pub mod config;
 pub mod gtfs;
 pub mod layers;
 pub mod opt;
+pub mod ordering;
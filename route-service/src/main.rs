@@ -1,44 +1,128 @@
+mod config;
 mod gtfs;
 mod layers;
 mod opt;
+mod ordering;
 mod server;
 
 use clap::Parser;
+use config::Config;
 use futures::future::join_all;
+use layers::city::City;
+use layers::memory::MemoryMode;
+use layers::vehicle::RouteVehicles;
 use log::info;
-use server::proxy::start_proxy_server;
-use server::server::start_server;
-use std::collections::HashMap;
+use server::notify::Webhook;
+use server::proxy::{start_proxy_server, CityConfig, CityLauncher};
+use server::server::{start_server, OptimizationLimits};
+use std::time::Duration;
 
 /// Transit route optimization and evaluation service
+///
+/// Settings are read from a TOML config file, see `config.example.toml`; the flags below
+/// override the values of the file.
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
 struct Args {
+    /// Config file, transit-works.toml in the working directory if it exists and none is given
+    #[clap(long)]
+    config: Option<String>,
+
     /// Path to GTFS data base directory
-    #[clap(
-        long,
-        default_value = "/Users/jeevanopel/workspace/transit-works/scripts/city_data"
-    )]
-    gtfs_base_path: String,
+    #[clap(long)]
+    gtfs_base_path: Option<String>,
 
     /// Path to database base directory
-    #[clap(
-        long,
-        default_value = "/Users/jeevanopel/workspace/transit-works/scripts/city_db"
-    )]
-    db_base_path: String,
+    #[clap(long)]
+    db_base_path: Option<String>,
+
+    /// Directory the caches and logs of the cities are written to
+    #[clap(long)]
+    cache_dir: Option<String>,
 
     /// Server host address
-    #[clap(long, default_value = "127.0.0.1")]
-    host: String,
+    #[clap(long)]
+    host: Option<String>,
 
     /// Proxy server port
-    #[clap(long, default_value_t = 8080)]
-    port: u16,
+    #[clap(long)]
+    port: Option<u16>,
 
     /// Cities to start servers for (comma separated)
-    #[clap(long, default_value = "toronto,sanfrancisco")]
-    cities: String,
+    #[clap(long)]
+    cities: Option<String>,
+
+    /// Webhooks notified when batch jobs finish, as comma separated city=url pairs
+    #[clap(long, default_value = "")]
+    webhooks: String,
+
+    /// Wall time limit in seconds of a single optimization request
+    #[clap(long)]
+    max_optimize_secs: Option<u64>,
+
+    /// Most routes a single optimization request may optimize
+    #[clap(long)]
+    max_optimize_routes: Option<usize>,
+
+    /// Most ACO generations run per route, regardless of the ACO parameters
+    #[clap(long)]
+    max_generations: Option<usize>,
+
+    /// Whether to keep the road and grid networks of the cities in memory, or to map the road
+    /// network from disk and read demand from the database as needed
+    #[clap(long, value_enum)]
+    memory_mode: Option<MemoryMode>,
+}
+
+impl Args {
+    /// Override the values of the config file with the flags that were given
+    fn apply(self, config: &mut Config) {
+        if let Some(path) = self.gtfs_base_path {
+            config.paths.gtfs_base_path = path;
+        }
+        if let Some(path) = self.db_base_path {
+            config.paths.db_base_path = path;
+        }
+        if let Some(path) = self.cache_dir {
+            config.paths.cache_dir = path;
+        }
+        if let Some(host) = self.host {
+            config.server.host = host;
+        }
+        if let Some(port) = self.port {
+            config.proxy.port = port;
+        }
+        if let Some(cities) = self.cities {
+            config.server.start = cities
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+        }
+        if let Some(memory_mode) = self.memory_mode {
+            config.server.memory_mode = memory_mode;
+        }
+        let server = &mut config.server;
+        server.max_optimize_secs = self.max_optimize_secs.or(server.max_optimize_secs);
+        server.max_optimize_routes = self.max_optimize_routes.or(server.max_optimize_routes);
+        server.max_generations = self.max_generations.or(server.max_generations);
+
+        // Parse the per-city webhooks from command line
+        for pair in self
+            .webhooks
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            match pair.split_once('=') {
+                Some((city, url)) => match config.cities.get_mut(city.trim()) {
+                    Some(entry) => entry.webhook_url = Some(url.trim().to_string()),
+                    None => eprintln!("Ignoring webhook for unknown city {}", city),
+                },
+                None => eprintln!("Ignoring webhook '{}', expected city=url", pair),
+            }
+        }
+    }
 }
 
 struct CityInfo {
@@ -46,40 +130,81 @@ struct CityInfo {
     port: u16,
     gtfs_path: String,
     db_path: String,
+    webhook_url: Option<String>,
+    vehicles: RouteVehicles,
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
+    // optimizer spans are printed when they close with the time spent in them, filtered by
+    // RUST_LOG like the logs, e.g. `trace` for the span of every ant
+    #[cfg(feature = "tracing")]
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::fmt()
+            .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+            .finish(),
+    )
+    .expect("Failed to set the tracing subscriber");
 
-    // Parse command line arguments
+    // Parse command line arguments over the config file
     let args = Args::parse();
+    let mut config = Config::load(args.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    args.apply(&mut config);
+    City::set_cache_dir(&config.paths.cache_dir);
 
-    // Define city to port mappings
-    let mut city_ports = HashMap::new();
-    city_ports.insert("toronto".to_string(), 8081);
-    city_ports.insert("sanfrancisco".to_string(), 8082);
-    city_ports.insert("vancouver".to_string(), 8083);
-    city_ports.insert("austin".to_string(), 8084);
-
-    // Parse the cities from command line
-    let cities: Vec<String> = args
-        .cities
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect();
+    // Drop the webhooks that cannot be notified
+    for (city, entry) in config.cities.iter_mut() {
+        if let Some(url) = &entry.webhook_url {
+            if let Err(e) = server::notify::validate_webhook_url(url) {
+                eprintln!("Ignoring webhook for {}: {}", city, e);
+                entry.webhook_url = None;
+            }
+        }
+    }
+
+    let optimization_limits = OptimizationLimits {
+        max_wall_time_secs: config.server.max_optimize_secs,
+        max_routes: config.server.max_optimize_routes,
+        max_generations: config.server.max_generations,
+    };
+    // Also starts the cities registered at runtime through the proxy's POST /admin/cities
+    let launcher = CityLauncher {
+        host: config.server.host.clone(),
+        gtfs_base_path: config.paths.gtfs_base_path.clone(),
+        db_base_path: config.paths.db_base_path.clone(),
+        optimization_limits,
+        memory_mode: config.server.memory_mode,
+        aco_defaults: config.aco.clone(),
+        vehicles: config
+            .cities
+            .keys()
+            .map(|city| (city.clone(), config.route_vehicles(city)))
+            .collect(),
+    };
 
-    // Prepare city info for each configured city
-    let city_servers: Vec<CityInfo> = cities
-        .into_iter()
-        .filter_map(|city| {
-            city_ports.get(&city).map(|&port| CityInfo {
+    // Prepare city info for each city started at launch
+    let city_servers: Vec<CityInfo> = config
+        .server
+        .start
+        .iter()
+        .filter_map(|city| match config.cities.get(city) {
+            Some(entry) => Some(CityInfo {
                 name: city.clone(),
-                port,
-                gtfs_path: format!("{}/{}/gtfs", args.gtfs_base_path, city),
-                db_path: format!("{}/{}.db", args.db_base_path, city),
-            })
+                port: entry.port,
+                gtfs_path: config.paths.gtfs_path(city),
+                db_path: config.paths.db_path(city),
+                webhook_url: entry.webhook_url.clone(),
+                vehicles: config.route_vehicles(city),
+            }),
+            None => {
+                eprintln!("Ignoring city {} that is not configured", city);
+                None
+            }
         })
         .collect();
 
@@ -92,17 +217,34 @@ async fn main() -> std::io::Result<()> {
 
     // Spawn a future for each city server
     let server_futures = city_servers.iter().map(|city| {
-        let host = args.host.clone();
+        let host = config.server.host.clone();
         let name = city.name.clone();
         let gtfs_path = city.gtfs_path.clone();
         let db_path = city.db_path.clone();
+        let webhook_url = city.webhook_url.clone();
         let port = city.port;
+        let memory_mode = config.server.memory_mode;
+        let aco_defaults = config.aco.clone();
+        let vehicles = city.vehicles.clone();
 
         info!("Configuring server for city {} on port {}", name, port);
 
         actix_web::rt::spawn(async move {
             info!("Starting server for {} on port {}", name, port);
-            if let Err(e) = start_server(&name, &gtfs_path, &db_path, &host, port).await {
+            if let Err(e) = start_server(
+                &name,
+                &gtfs_path,
+                &db_path,
+                &host,
+                port,
+                webhook_url.map(Webhook::configured),
+                optimization_limits,
+                memory_mode,
+                aco_defaults,
+                vehicles,
+            )
+            .await
+            {
                 eprintln!("Failed to start server for {}: {}", name, e);
             }
             Ok::<_, std::io::Error>(())
@@ -110,11 +252,15 @@ async fn main() -> std::io::Result<()> {
     });
 
     // Start the proxy server
-    info!("Starting proxy server on port {}", args.port);
-    let proxy_host = args.host.clone();
-    let proxy_port = args.port;
+    info!("Starting proxy server on port {}", config.proxy.port);
+    let proxy_host = config.server.host.clone();
+    let proxy_port = config.proxy.port;
+    let mut city_config = CityConfig::new(config.city_ports()).with_launcher(launcher);
+    city_config.default_city = config.proxy.default_city.clone();
+    city_config.request_timeout = Duration::from_secs(config.proxy.timeout_secs);
+    city_config.admin_token = config.proxy.admin_token.clone();
     let proxy_future = actix_web::rt::spawn(async move {
-        start_proxy_server(&proxy_host, proxy_port, city_ports.clone()).await
+        start_proxy_server(&proxy_host, proxy_port, city_config).await
     });
 
     // Combine all futures
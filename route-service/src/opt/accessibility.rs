@@ -0,0 +1,263 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+
+use crate::layers::{
+    geo_util,
+    grid::GridNetwork,
+    transit_network::{TransitNetwork, TransitRoute, TransitStop},
+};
+use crate::ordering::OrdF64;
+
+/// Average in-vehicle speed used to estimate ride times, in km/h
+pub const AVG_BUS_SPEED_KMH: f64 = 20.0;
+/// Ratio of road distance to straight line distance between consecutive stops
+pub const ROAD_DETOUR_FACTOR: f64 = 1.3;
+/// Time to walk between a zone and a stop within walking distance, in minutes
+pub const WALK_TIME_MIN: f64 = 5.0;
+/// Headway assumed for routes without departure data, in minutes
+pub const DEFAULT_HEADWAY_MIN: f64 = 10.0;
+/// Length of the service day the departure counts are spread over, in minutes
+const SERVICE_DAY_MIN: f64 = 17.0 * 60.0;
+/// Travel time budget of the job accessibility metric, in minutes
+pub const JOB_ACCESS_MINUTES: f64 = 45.0;
+
+/// Average minutes between departures of a route over the service day
+pub fn headway_minutes(route: &TransitRoute) -> f64 {
+    let departures: usize = route.stop_times.values().sum();
+    if departures > 0 {
+        SERVICE_DAY_MIN / departures as f64
+    } else {
+        DEFAULT_HEADWAY_MIN
+    }
+}
+
+/// Minutes riding between consecutive stops of a route at `AVG_BUS_SPEED_KMH`
+pub fn ride_minutes(from: &TransitStop, to: &TransitStop) -> f64 {
+    let km = geo_util::haversine(from.geom.x(), from.geom.y(), to.geom.x(), to.geom.y()) / 1000.0;
+    km * ROAD_DETOUR_FACTOR / AVG_BUS_SPEED_KMH * 60.0
+}
+
+/// Points of interest reachable from a zone by transit
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ZonePoiAccess {
+    pub zoneid: u32,
+    /// Number of reachable points of interest by category
+    pub reachable: HashMap<String, u32>,
+    pub total: u32,
+}
+
+/// Compute the points of interest reachable from every populated zone within a time budget
+///
+/// # Arguments
+/// - `transit`: Transit network data
+/// - `grid`: Grid network with zones and points of interest
+/// - `max_minutes`: Time budget for the whole trip including walking and waiting
+///
+/// # Returns
+/// - Points of interest reachable from each populated zone, sorted by zone id
+///
+/// # Notes
+/// - Travellers walk `WALK_TIME_MIN` to any stop whose walking radius covers their zone, wait
+///   half the headway of the route they board, ride at `AVG_BUS_SPEED_KMH` and walk
+///   `WALK_TIME_MIN` from the alighting stop to any zone it covers
+/// - Transfers between routes at the same stop cost another wait
+/// - Points of interest in the origin zone itself are always reachable
+pub fn poi_access(
+    transit: &TransitNetwork,
+    grid: &GridNetwork,
+    max_minutes: f64,
+) -> Vec<ZonePoiAccess> {
+    let graph = AccessGraph::build(transit, grid);
+
+    let mut result: Vec<ZonePoiAccess> = grid
+        .get_all_valid_zones()
+        .into_iter()
+        .map(|origin| {
+            let reached = graph.reachable_zones(origin, max_minutes);
+            let mut reachable: HashMap<String, u32> = HashMap::new();
+            for zone in reached {
+                if let Some(counts) = grid.poi_counts(zone) {
+                    for (category, count) in counts {
+                        *reachable.entry(category.clone()).or_insert(0) += count;
+                    }
+                }
+            }
+            ZonePoiAccess {
+                zoneid: grid.get_zone(origin).zoneid,
+                total: reachable.values().sum(),
+                reachable,
+            }
+        })
+        .collect();
+    result.sort_by_key(|z| z.zoneid);
+    result
+}
+
+/// Jobs reachable from a zone by transit
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ZoneJobAccess {
+    pub zoneid: u32,
+    pub population: u32,
+    pub jobs_reachable: u64,
+}
+
+/// Compute the jobs reachable from every populated zone within a time budget
+///
+/// # Arguments
+/// - `transit`: Transit network data
+/// - `grid`: Grid network with zones and their jobs
+/// - `max_minutes`: Time budget for the whole trip including walking and waiting
+///
+/// # Returns
+/// - Jobs reachable from each populated zone, sorted by zone id
+///
+/// # Notes
+/// - Travel times are estimated the same way as for `poi_access`
+pub fn job_access(
+    transit: &TransitNetwork,
+    grid: &GridNetwork,
+    max_minutes: f64,
+) -> Vec<ZoneJobAccess> {
+    let graph = AccessGraph::build(transit, grid);
+
+    let mut result: Vec<ZoneJobAccess> = grid
+        .get_all_valid_zones()
+        .into_iter()
+        .map(|origin| {
+            let zone = grid.get_zone(origin);
+            ZoneJobAccess {
+                zoneid: zone.zoneid,
+                population: zone.population,
+                jobs_reachable: graph
+                    .reachable_zones(origin, max_minutes)
+                    .into_iter()
+                    .map(|z| grid.get_zone(z).jobs as u64)
+                    .sum(),
+            }
+        })
+        .collect();
+    result.sort_by_key(|z| z.zoneid);
+    result
+}
+
+/// Average jobs reachable per resident
+pub fn avg_jobs_reachable(access: &[ZoneJobAccess]) -> f64 {
+    let population: f64 = access.iter().map(|z| z.population as f64).sum();
+    if population == 0.0 {
+        return 0.0;
+    }
+    access
+        .iter()
+        .map(|z| z.jobs_reachable as f64 * z.population as f64)
+        .sum::<f64>()
+        / population
+}
+
+/// Number of jobs in the zones served by a set of stops, used as an objective term
+///
+/// # Arguments
+/// - `zones`: Zones within walking distance of the route's stops
+/// - `grid`: Grid network with zones and their jobs
+pub fn jobs_served(zones: impl IntoIterator<Item = NodeIndex>, grid: &GridNetwork) -> f64 {
+    let zones: HashSet<NodeIndex> = zones.into_iter().collect();
+    zones.iter().map(|&z| grid.get_zone(z).jobs as f64).sum()
+}
+
+/// Number of points of interest in the zones served by a set of stops, used as an objective term
+///
+/// # Arguments
+/// - `zones`: Zones within walking distance of the route's stops
+/// - `grid`: Grid network with zones and points of interest
+pub fn pois_served(zones: impl IntoIterator<Item = NodeIndex>, grid: &GridNetwork) -> f64 {
+    let zones: HashSet<NodeIndex> = zones.into_iter().collect();
+    zones.iter().map(|&z| grid.poi_count(z, None) as f64).sum()
+}
+
+/// Graph of the stops of the transit network used for reachability queries.
+///
+/// Every stop has a platform node, and every position of a stop along a route has a ride node.
+/// Boarding goes from a platform to a ride node (waiting half the headway), riding goes between
+/// consecutive ride nodes and alighting goes back from a ride node to the platform for free.
+struct AccessGraph {
+    /// Zones within walking distance of each node, empty for ride nodes
+    node_zones: Vec<Vec<NodeIndex>>,
+    /// Platforms within walking distance of each zone
+    zone_platforms: HashMap<NodeIndex, Vec<usize>>,
+    /// Outgoing edges of every node with their cost in minutes
+    edges: Vec<Vec<(usize, f64)>>,
+}
+
+impl AccessGraph {
+    fn build(transit: &TransitNetwork, grid: &GridNetwork) -> AccessGraph {
+        let mut platform_ids: HashMap<&str, usize> = HashMap::new();
+        let mut node_zones: Vec<Vec<NodeIndex>> = vec![];
+        let mut zone_platforms: HashMap<NodeIndex, Vec<usize>> = HashMap::new();
+        let mut edges: Vec<Vec<(usize, f64)>> = vec![];
+
+        for route in &transit.routes {
+            let wait = headway_minutes(route) / 2.0;
+
+            for stops in [&route.outbound_stops, &route.inbound_stops] {
+                let mut prev_ride: Option<usize> = None;
+                for (i, stop) in stops.iter().enumerate() {
+                    let platform = *platform_ids.entry(&stop.stop_id).or_insert_with(|| {
+                        let id = edges.len();
+                        let zones = stop.nearby_zone_indices(grid);
+                        for zone in &zones {
+                            zone_platforms.entry(*zone).or_default().push(id);
+                        }
+                        edges.push(vec![]);
+                        node_zones.push(zones);
+                        id
+                    });
+                    let ride = edges.len();
+                    edges.push(vec![(platform, 0.0)]);
+                    node_zones.push(vec![]);
+                    edges[platform].push((ride, wait));
+                    if let Some(prev) = prev_ride {
+                        edges[prev].push((ride, ride_minutes(&stops[i - 1], stop)));
+                    }
+                    prev_ride = Some(ride);
+                }
+            }
+        }
+
+        AccessGraph {
+            node_zones,
+            zone_platforms,
+            edges,
+        }
+    }
+
+    /// Zones reachable from an origin zone within a time budget, including the origin
+    fn reachable_zones(&self, origin: NodeIndex, max_minutes: f64) -> HashSet<NodeIndex> {
+        let mut reached = HashSet::from([origin]);
+        let mut best = vec![f64::INFINITY; self.edges.len()];
+        let mut heap = BinaryHeap::new();
+        for &platform in self.zone_platforms.get(&origin).into_iter().flatten() {
+            best[platform] = WALK_TIME_MIN;
+            heap.push((Reverse(OrdF64(WALK_TIME_MIN)), platform));
+        }
+
+        while let Some((Reverse(OrdF64(time)), node)) = heap.pop() {
+            if time > best[node] {
+                continue;
+            }
+            if time + WALK_TIME_MIN <= max_minutes {
+                reached.extend(self.node_zones[node].iter().copied());
+            }
+            for &(next, cost) in &self.edges[node] {
+                let next_time = time + cost;
+                if next_time < best[next] && next_time + WALK_TIME_MIN <= max_minutes {
+                    best[next] = next_time;
+                    heap.push((Reverse(OrdF64(next_time)), next));
+                }
+            }
+        }
+        reached
+    }
+}
+
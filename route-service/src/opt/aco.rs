@@ -4,6 +4,8 @@ use crate::layers::{
     road_network::RoadNetwork,
     transit_network::{RTreeNode, TransitNetwork, TransitRoute, TransitRouteType, TransitStop},
 };
+use crate::opt::search::SearchConfig;
+use crate::ordering;
 use env_logger::init;
 use geo::{Distance, Haversine, Length, LineString, Point};
 use rand::rngs::StdRng;
@@ -40,6 +42,8 @@ pub struct ACO {
     aco_num_ant: usize,
     aco_max_gen: usize,
     max_gen: usize,
+    seed: u64,            // seed of the random choices of the ants
+    search: SearchConfig, // search parameters the routes are evaluated with
     heuristic_cache: HashMap<(String, String), f64>,
 }
 
@@ -57,6 +61,7 @@ impl ACO {
         println!("  Number of ants: {}", self.aco_num_ant);
         println!("  Number of ant iterations: {}", self.aco_max_gen);
         println!("  Number of iterations: {}", self.max_gen);
+        println!("  Seed: {}", self.seed);
     }
 
     pub fn init() -> Self {
@@ -75,6 +80,8 @@ impl ACO {
             aco_num_ant: aco_num_ant,
             aco_max_gen: aco_max_gen,
             max_gen: max_gen,
+            seed: 42,
+            search: SearchConfig::default(),
             heuristic_cache: HashMap::new(),
         }
     }
@@ -96,10 +103,24 @@ impl ACO {
             aco_num_ant: aco_num_ant,
             aco_max_gen: aco_max_gen,
             max_gen: max_gen,
+            seed: 42,
+            search: SearchConfig::default(),
             heuristic_cache: HashMap::new(),
         }
     }
 
+    /// Use another seed for the random choices of the ants, runs with the same seed match
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Evaluate the routes with the search parameters of a city, e.g. `City::search`
+    pub fn with_search(mut self, search: SearchConfig) -> Self {
+        self.search = search;
+        self
+    }
+
     // TODO cannot select stops that are not type BUS
     fn select_next_stop(
         &mut self,
@@ -121,7 +142,7 @@ impl ACO {
             return Some(end.clone());
         }
         //let mut rng = rand::thread_rng();
-        let mut rng = StdRng::seed_from_u64(42);
+        let mut rng = StdRng::seed_from_u64(self.seed);
         let mut choices = Vec::new();
         let mut weights = Vec::new();
 
@@ -377,15 +398,19 @@ impl ACO {
         }
 
         let inbound_stops = ACO::construct_inbound_stops(&stops, transit);
-        Some(TransitRoute::with_evals(
+        let mut new_route = TransitRoute::with_evals(
             transit,
             od,
+            &self.search,
             route.route_id.clone(),
             route.route_type.clone(),
             stops,
             inbound_stops,
             route.stop_times.clone(),
-        ))
+        );
+        new_route.service_span = route.service_span.clone();
+        new_route.vehicle = route.vehicle.clone();
+        Some(new_route)
     }
 
     /// Given a list of outbound stops, find the nearest inbound stop for each outbound stop
@@ -630,10 +655,11 @@ impl ACO {
             log::debug!("ACO generation {}", max_gen_i);
             // Sort the routes by their evaluate_route
             best_routes.sort_by(|a, b| {
-                ACO::evaluate_route(od, road, b)
-                    .0
-                    .partial_cmp(&ACO::evaluate_route(od, road, a).0)
-                    .unwrap()
+                ordering::cmp_f64_desc(
+                    ACO::evaluate_route(od, road, a).0,
+                    ACO::evaluate_route(od, road, b).0,
+                )
+                .then_with(|| a.route_id.cmp(&b.route_id))
             });
 
             for i in 0..best_routes.len() {
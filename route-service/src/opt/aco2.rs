@@ -1,9 +1,10 @@
 use std::{
-    collections::{HashMap, HashSet},
+    cell::Cell,
+    collections::{BTreeMap, HashMap, HashSet},
     sync::Arc,
+    time::{Duration, Instant},
 };
 
-use geo::Contains;
 use petgraph::graph::NodeIndex;
 use rand::{distributions::WeightedIndex, prelude::Distribution, rngs::StdRng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
@@ -11,16 +12,66 @@ use serde::{Deserialize, Serialize};
 use crate::layers::{
     city::City,
     geo_util,
+    grid::TimePeriod,
+    road_network::MAX_BUS_GRADE,
     transit_network::{TransitNetwork, TransitRoute, TransitRouteType, TransitStop},
+    vehicle::VehicleProfile,
 };
 
-use super::eval::{TransitNetworkEvals, TransitRouteEvals};
+use super::alignment;
+use super::area::StudyArea;
+use super::budget::{OperatingBudget, OperatingCost, RouteCost};
+use super::checkpoint::{Checkpoint, Checkpointing};
+use super::constraints::{constraint_of, RouteConstraint, RouteConstraints};
+use super::corridor::{CorridorDistances, Leg};
+use super::eval::{route_key, EvalCache, RouteKey, TransitNetworkEvals, TransitRouteEvals};
+use super::inbound;
+use super::metrics::{SearchStats, OPTIMIZER_METRICS};
+use super::objective::{ObjectiveSpec, RouteMeasures};
+use super::pareto::{FrontierRoute, ParetoFront, TradeOff};
+use super::progress::ProgressEvent;
+use super::resources::{ResourceMeter, ResourceUsage};
+use super::walking::WalkCheck;
+use crate::ordering;
 
 // should be less than 1.0
 const PUNISHMENT_NONLINEARITY: f64 = 0.3;
 // const PUNISHMENT_ROUTE_LEN: f64 = 0.2;
 const PUNISHMENT_BAD_TURN: f64 = 0.4;
 const PUNISHMENT_STOP_DIST: f64 = 0.1;
+const PUNISHMENT_CAPACITY: f64 = 0.3;
+const PUNISHMENT_GRADE: f64 = 0.2;
+/// Smallest turn angle of buses without a vehicle profile, anything but a U-turn
+const DEFAULT_MIN_TURN_ANGLE: f64 = 2.0;
+/// Most runs with different seeds a single optimization may restart
+pub const MAX_RESTARTS: usize = 16;
+
+/// Pheromone on each stop to stop edge, as left by an ACO run
+pub type Pheromones = HashMap<(String, String), f64>;
+
+/// Where an ACO run starts from instead of the route being optimized
+#[derive(Clone, Copy)]
+pub struct AcoSeed<'a> {
+    /// Route to start from, e.g. the route as saved in a previous scenario
+    pub route: &'a TransitRoute,
+    /// Pheromone left by an earlier run, the initial pheromone everywhere if `None`
+    pub pheromones: Option<&'a Pheromones>,
+}
+
+/// What the search for a route runs against
+pub struct SearchContext<'a> {
+    pub city: &'a City,
+    /// Network the route is scored in
+    pub opt_transit: &'a TransitNetwork,
+    /// Area the changes are restricted to, stops outside of it are kept in order and only
+    /// stops inside of it are added. The whole city if `None`.
+    pub area: Option<&'a StudyArea>,
+    /// Stop after the generation running when this instant passes, keeping the best route
+    /// found so far
+    pub deadline: Option<Instant>,
+    /// Called with the progress events of the search
+    pub on_progress: &'a mut dyn FnMut(ProgressEvent),
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct OptimizedTransitNetwork {
@@ -29,7 +80,7 @@ pub struct OptimizedTransitNetwork {
 }
 
 // struct to store all the tunable parameters for the ACO algorithm
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ACO {
     // ACO specific parameters
     pub alpha: f64,
@@ -45,15 +96,45 @@ pub struct ACO {
     pub bus_capacity: usize,
     pub min_stop_dist: f64,
     pub max_stop_dist: f64,
+    // Most departures a route can run in one time period, 0 to ignore vehicle capacity
+    pub max_departures: usize,
     // Punishment parameter
     pub min_route_len: usize,
     pub max_route_len: usize,
     pub max_nonlinearity: f64,
     pub avg_stop_dist: f64,
+    // Objective weight of the points of interest served by a route, 0 to ignore them
+    pub poi_weight: f64,
+    // Objective weight of the jobs served by a route, 0 to ignore them
+    pub jobs_weight: f64,
+    // Heuristic bonus for stops with a shelter or pad, 0 to ignore existing infrastructure
+    pub infra_bonus: f64,
+    // Refine the best route found by ACO with a greedy local search
+    pub local_search: bool,
+    // Largest increase in meters of any zone's walk to its nearest stop, 0 disables the check
+    pub max_walk_increase: f64,
+    // Routes with at least this many stops are optimized in chunks, 0 never splits routes
+    pub chunk_min_stops: usize,
+    // Number of stops each chunk of a long route aims for
+    pub chunk_len: usize,
+    // Weighted objectives the score of a route is made of
+    pub objective: ObjectiveSpec,
+    // Seed of the random choices of the ants, runs with the same seed and parameters match
+    pub seed: u64,
+    // Versions of a route kept on its frontier of coverage, ridership and road length, 0 only
+    // keeps the best score. Routes optimized in chunks have no frontier.
+    pub pareto_size: usize,
+    // Farthest in meters a candidate stop may be from the original alignment of the route, 0
+    // lets routes move anywhere within the search area
+    pub max_corridor_deviation: f64,
+    // Stops and segments each route must keep, set by the server rather than with the other
+    // parameters
+    #[serde(skip)]
+    pub constraints: Arc<RouteConstraints>,
 }
 
 // struct to support partial updates to ACO parameters
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct PartialACO {
     // ACO specific parameters
     pub alpha: Option<f64>,
@@ -69,11 +150,32 @@ pub struct PartialACO {
     pub bus_capacity: Option<usize>,
     pub min_stop_dist: Option<f64>,
     pub max_stop_dist: Option<f64>,
+    pub max_departures: Option<usize>,
     // Punishment parameter
     pub min_route_len: Option<usize>,
     pub max_route_len: Option<usize>,
     pub max_nonlinearity: Option<f64>,
     pub avg_stop_dist: Option<f64>,
+    pub poi_weight: Option<f64>,
+    pub jobs_weight: Option<f64>,
+    pub infra_bonus: Option<f64>,
+    pub local_search: Option<bool>,
+    pub max_walk_increase: Option<f64>,
+    pub chunk_min_stops: Option<usize>,
+    pub chunk_len: Option<usize>,
+    pub objective: Option<ObjectiveSpec>,
+    pub seed: Option<u64>,
+    pub pareto_size: Option<usize>,
+    pub max_corridor_deviation: Option<f64>,
+}
+
+impl PartialACO {
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.objective {
+            Some(objective) => objective.validate(),
+            None => Ok(()),
+        }
+    }
 }
 
 impl ACO {
@@ -94,8 +196,32 @@ impl ACO {
             max_route_len: 100,
             min_stop_dist: 100.0,
             max_stop_dist: 500.0,
+            max_departures: 30,
             max_nonlinearity: 2.0,
             avg_stop_dist: 350.0,
+            poi_weight: 0.0,
+            jobs_weight: 0.0,
+            infra_bonus: 0.1,
+            local_search: false,
+            max_walk_increase: 0.0,
+            chunk_min_stops: 100,
+            chunk_len: 40,
+            objective: ObjectiveSpec::default(),
+            seed: 42,
+            pareto_size: 0,
+            max_corridor_deviation: 0.0,
+            constraints: Arc::new(RouteConstraints::new()),
+        }
+    }
+
+    /// Vehicles of routes without a profile, buses of `bus_capacity` stopping every
+    /// `avg_stop_dist` meters that make any turn but a U-turn
+    pub fn default_vehicle(&self) -> VehicleProfile {
+        VehicleProfile {
+            capacity: self.bus_capacity,
+            min_turn_angle: DEFAULT_MIN_TURN_ANGLE,
+            max_grade: MAX_BUS_GRADE,
+            stop_spacing: self.avg_stop_dist,
         }
     }
 
@@ -115,8 +241,20 @@ impl ACO {
         println!("  max_route_len: {}", self.max_route_len);
         println!("  min_stop_dist: {}", self.min_stop_dist);
         println!("  max_stop_dist: {}", self.max_stop_dist);
+        println!("  max_departures: {}", self.max_departures);
         println!("  max_nonlinearity: {}", self.max_nonlinearity);
         println!("  avg_stop_dist: {}", self.avg_stop_dist);
+        println!("  poi_weight: {}", self.poi_weight);
+        println!("  jobs_weight: {}", self.jobs_weight);
+        println!("  infra_bonus: {}", self.infra_bonus);
+        println!("  local_search: {}", self.local_search);
+        println!("  max_walk_increase: {}", self.max_walk_increase);
+        println!("  chunk_min_stops: {}", self.chunk_min_stops);
+        println!("  chunk_len: {}", self.chunk_len);
+        println!("  objective: {}", self.objective);
+        println!("  seed: {}", self.seed);
+        println!("  pareto_size: {}", self.pareto_size);
+        println!("  max_corridor_deviation: {}", self.max_corridor_deviation);
     }
 
     // Update ACO parameters from a PartialACO
@@ -169,13 +307,60 @@ impl ACO {
         if let Some(avg_stop_dist) = partial.avg_stop_dist {
             self.avg_stop_dist = avg_stop_dist;
         }
+        if let Some(max_departures) = partial.max_departures {
+            self.max_departures = max_departures;
+        }
+        if let Some(poi_weight) = partial.poi_weight {
+            self.poi_weight = poi_weight;
+        }
+        if let Some(jobs_weight) = partial.jobs_weight {
+            self.jobs_weight = jobs_weight;
+        }
+        if let Some(infra_bonus) = partial.infra_bonus {
+            self.infra_bonus = infra_bonus;
+        }
+        if let Some(local_search) = partial.local_search {
+            self.local_search = local_search;
+        }
+        if let Some(max_walk_increase) = partial.max_walk_increase {
+            self.max_walk_increase = max_walk_increase;
+        }
+        if let Some(chunk_min_stops) = partial.chunk_min_stops {
+            self.chunk_min_stops = chunk_min_stops;
+        }
+        if let Some(chunk_len) = partial.chunk_len {
+            self.chunk_len = chunk_len;
+        }
+        if let Some(objective) = partial.objective {
+            self.objective = objective;
+        }
+        if let Some(seed) = partial.seed {
+            self.seed = seed;
+        }
+        if let Some(pareto_size) = partial.pareto_size {
+            self.pareto_size = pareto_size;
+        }
+        if let Some(max_corridor_deviation) = partial.max_corridor_deviation {
+            self.max_corridor_deviation = max_corridor_deviation;
+        }
     }
 }
 
+/// Pheromone on the edges between stops.
+///
+/// Evaporation is applied lazily: every entry remembers the generation it was last written in
+/// and is decayed by `(1 - rho)^(generations since)` when read, so `decay` does not have to
+/// visit every edge each generation.
 struct PheromoneMap {
-    pheromone: HashMap<(String, String), f64>,
+    /// Pheromone of each edge and the generation it was last written in
+    pheromone: HashMap<(String, String), (f64, u32)>,
     aco: Arc<ACO>,
     init_pheromone: f64,
+    /// Number of times `decay` has been called
+    generation: u32,
+    /// Reads of edges written before and of edges still at the initial pheromone
+    hits: Cell<u64>,
+    misses: Cell<u64>,
 }
 
 impl PheromoneMap {
@@ -184,31 +369,71 @@ impl PheromoneMap {
             pheromone: HashMap::new(),
             init_pheromone: aco.init_pheromone,
             aco,
+            generation: 0,
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+        }
+    }
+
+    /// Pheromone written in `written_gen` after the evaporation since then
+    fn decayed(&self, value: f64, written_gen: u32) -> f64 {
+        let elapsed = self.generation - written_gen;
+        if elapsed == 0 {
+            value
+        } else {
+            value * (1.0 - self.aco.rho).powi(elapsed as i32)
         }
     }
 
     pub fn get(&self, from: &str, to: &str) -> f64 {
         match self.pheromone.get(&(from.to_string(), to.to_string())) {
-            Some(&val) => val,
-            None => self.aco.init_pheromone,
+            Some(&(val, written_gen)) => {
+                self.hits.set(self.hits.get() + 1);
+                self.decayed(val, written_gen)
+            }
+            None => {
+                self.misses.set(self.misses.get() + 1);
+                self.aco.init_pheromone
+            }
         }
     }
 
     pub fn update(&mut self, from: &str, to: &str, f: impl Fn(f64) -> f64) -> f64 {
-        let pheromone = self
-            .pheromone
-            .entry((from.to_string(), to.to_string()))
-            .or_insert(self.aco.init_pheromone);
-        *pheromone = f(*pheromone)
+        let current = match self.pheromone.get(&(from.to_string(), to.to_string())) {
+            Some(&(val, written_gen)) => self.decayed(val, written_gen),
+            None => self.aco.init_pheromone,
+        };
+        let pheromone = f(current)
             .max(self.aco.pheromone_min)
             .min(self.aco.pheromone_max);
-        *pheromone
+        self.pheromone.insert(
+            (from.to_string(), to.to_string()),
+            (pheromone, self.generation),
+        );
+        pheromone
     }
 
-    pub fn decay(&mut self) {
-        for (_, val) in self.pheromone.iter_mut() {
-            *val *= 1.0 - self.aco.rho;
+    /// Start from the pheromone of an earlier run, as if it was written in this generation
+    fn seed(&mut self, pheromones: &Pheromones) {
+        for ((from, to), value) in pheromones {
+            let value = value
+                .max(self.aco.pheromone_min)
+                .min(self.aco.pheromone_max);
+            self.pheromone
+                .insert((from.clone(), to.clone()), (value, self.generation));
         }
+    }
+
+    /// Current pheromone of every edge written so far
+    fn snapshot(&self) -> Pheromones {
+        self.pheromone
+            .iter()
+            .map(|(edge, &(value, written_gen))| (edge.clone(), self.decayed(value, written_gen)))
+            .collect()
+    }
+
+    pub fn decay(&mut self) {
+        self.generation += 1;
         self.init_pheromone *= 1.0 - self.aco.rho;
     }
 
@@ -220,7 +445,11 @@ impl PheromoneMap {
 }
 
 // Helper function to calculate route-specific parameters
-fn calculate_route_specific_params(route: &TransitRoute, city: &City, base_params: &ACO) -> ACO {
+pub(crate) fn calculate_route_specific_params(
+    route: &TransitRoute,
+    city: &City,
+    base_params: &ACO,
+) -> ACO {
     let mut route_params = base_params.clone();
 
     if route.outbound_stops.len() > 1 {
@@ -256,33 +485,279 @@ pub fn run_aco(
     city: &City,
     opt_transit: &TransitNetwork,
 ) -> Option<(TransitRoute, f64)> {
+    run_aco_with_progress(params, route, city, opt_transit, None, &mut |_| {})
+}
+
+/// Seeds of the runs of an optimization restarted `n_restarts` times, `seed` and the seeds
+/// following it
+pub fn restart_seeds(seed: u64, n_restarts: usize) -> Vec<u64> {
+    (0..n_restarts as u64)
+        .map(|i| seed.wrapping_add(i))
+        .collect()
+}
+
+/// Run ACO on a route, reporting a `GenerationCompleted` event after every generation
+///
+/// # Arguments
+/// - `deadline`: Stop after the generation running when this instant passes, keeping the best
+///   route found so far
+pub fn run_aco_with_progress(
+    params: ACO,
+    route: &TransitRoute,
+    city: &City,
+    opt_transit: &TransitNetwork,
+    deadline: Option<Instant>,
+    on_progress: &mut dyn FnMut(ProgressEvent),
+) -> Option<(TransitRoute, f64)> {
+    run_aco_from_seed(
+        params,
+        route,
+        None,
+        &mut SearchContext {
+            city,
+            opt_transit,
+            area: None,
+            deadline,
+            on_progress,
+        },
+    )
+    .0
+}
+
+/// Run ACO on a route starting from a seed, e.g. the route of a previous scenario
+///
+/// # Arguments
+/// - `seed`: Route and pheromone to start from, the route itself and the initial pheromone
+///   if `None`
+/// - `ctx`: City and network the route is optimized in, the area the changes are restricted
+///   to, the deadline and the progress callback, see `SearchContext`
+///
+/// # Returns
+/// - The optimized route and its score if it scores better than `route`, which may be the
+///   seed route itself
+/// - The pheromone at the end of the run, to seed a later run with
+///
+/// # Notes
+/// - Candidate stops are taken around the seed route
+/// - Routes with at least `chunk_min_stops` stops are split at major transfer stops and
+///   optimized chunk by chunk, see `search_chunks`
+pub fn run_aco_from_seed(
+    params: ACO,
+    route: &TransitRoute,
+    seed: Option<AcoSeed>,
+    ctx: &mut SearchContext,
+) -> (Option<(TransitRoute, f64)>, Pheromones) {
     if route.route_type != TransitRouteType::Bus {
-        return None;
+        return (None, Pheromones::new());
     }
     if route.outbound_stops.len() < 2 {
-        return None;
+        return (None, Pheromones::new());
     }
+    let start_route = match &seed {
+        Some(seed) if seed.route.outbound_stops.len() >= 2 => seed.route,
+        _ => route,
+    };
+    let seed_pheromones = seed.as_ref().and_then(|seed| seed.pheromones);
 
     // Calculate route-specific stop distance metrics
-    let route_params = calculate_route_specific_params(route, city, &params);
+    let route_params = calculate_route_specific_params(route, ctx.city, &params);
+    let max_walk_increase = route_params.max_walk_increase;
+
+    // long routes are optimized chunk by chunk to keep the search space of each run small
+    let boundaries = if route_params.chunk_min_stops > 0
+        && start_route.outbound_stops.len() >= route_params.chunk_min_stops
+    {
+        let transfers = transfer_counts(start_route, ctx.opt_transit);
+        chunk_boundaries(&transfers, route_params.chunk_len)
+    } else {
+        vec![]
+    };
+    let (gen_best_route, gen_best_eval, init_eval, pheromones) = if boundaries.len() > 2 {
+        search_chunks(
+            route_params,
+            route,
+            start_route,
+            &boundaries,
+            seed_pheromones,
+            ctx,
+        )
+    } else {
+        search_route(
+            route_params,
+            route,
+            start_route,
+            seed_pheromones,
+            &HashSet::new(),
+            ctx,
+        )
+    };
+    let accepted = accept_route(
+        route,
+        gen_best_route,
+        gen_best_eval,
+        init_eval,
+        max_walk_increase,
+        ctx,
+    );
+    (accepted, pheromones)
+}
 
+/// Turn the best outbound stops a search found for a route into the route that replaces it
+///
+/// # Arguments
+/// - `route`: Route as it runs now
+/// - `best_route`, `best_eval`: Best route the search found and its score
+/// - `init_eval`: Score of `route`
+/// - `max_walk_increase`: See `ACO::max_walk_increase`, 0 to skip the walk check
+///
+/// # Returns
+/// The new route with its inbound stops, evaluations and stop times, and its score. `None`
+/// if it does not score better than `route` or makes zones walk too much further.
+pub(crate) fn accept_route(
+    route: &TransitRoute,
+    mut best_route: TransitRoute,
+    best_eval: f64,
+    init_eval: f64,
+    max_walk_increase: f64,
+    ctx: &mut SearchContext,
+) -> Option<(TransitRoute, f64)> {
+    if best_eval <= init_eval {
+        return None;
+    }
+    let (city, opt_transit) = (ctx.city, ctx.opt_transit);
+    // searches only build the outbound direction, routes running both ways get their inbound
+    // stops back along it
+    if !route.inbound_stops.is_empty() {
+        best_route.inbound_stops =
+            inbound::mirror_inbound(&best_route.outbound_stops, opt_transit, &city.road);
+    }
+
+    if max_walk_increase > 0.0 {
+        let check = WalkCheck::for_route_change(
+            route,
+            &best_route,
+            opt_transit,
+            &city.grid,
+            max_walk_increase,
+        );
+        if !check.passed() {
+            log::debug!(
+                "Route {} rejected, {} zones walk more than {}m further",
+                route.route_id,
+                check.violations.len(),
+                max_walk_increase
+            );
+            (ctx.on_progress)(ProgressEvent::WalkConstraintViolated {
+                route_id: route.route_id.clone(),
+                check,
+            });
+            return None;
+        }
+    }
+    let evals = TransitRouteEvals::for_route(opt_transit, &best_route, &city.grid, &city.search);
+    best_route.evals = Some(evals);
+    best_route.stop_times = route.stop_times.clone();
+    Some((best_route, best_eval))
+}
+
+/// Run ACO and the local search on a route
+///
+/// # Arguments
+/// - `route`: Route the initial score is computed for
+/// - `start_route`: Route the ants start from, `route` itself or a seed route
+/// - `excluded_stop_ids`: Stops that cannot be added to the route
+///
+/// # Returns
+/// - The best route found, `start_route` if nothing scored better, and its score
+/// - The score of `route`
+/// - The pheromone at the end of the run
+fn search_route(
+    route_params: ACO,
+    route: &TransitRoute,
+    start_route: &TransitRoute,
+    seed_pheromones: Option<&Pheromones>,
+    excluded_stop_ids: &HashSet<&str>,
+    ctx: &mut SearchContext,
+) -> (TransitRoute, f64, f64, Pheromones) {
+    let (city, area) = (ctx.city, ctx.area);
+    let start = Instant::now();
+    #[cfg(feature = "tracing")]
+    let _search = tracing::info_span!("search_route", route_id = %route.route_id).entered();
     // Initialize the pheromone map
     let aco = Arc::new(route_params);
     let mut pheromone_map = PheromoneMap::new(aco.clone());
-    let mut heuristic_map = HashMap::new();
+    if let Some(pheromones) = seed_pheromones {
+        pheromone_map.seed(pheromones);
+    }
+    let mut heuristic_map = HeuristicMap::default();
+    let mut eval_cache = EvalCache::new();
 
     // get the stop choices
-    let stops = filter_stops_by_route_bbox(route, city, 250.0);
-    // can speed up by precomputing stops to zone mapping in city struct?
-    let zone_to_zone_coverage = filter_zones_by_stops(&stops, city, opt_transit);
-
+    let mut stops = filter_stops_by_route_bbox(start_route, city, city.search.bbox_padding);
+    let zone_to_zone_coverage = filter_zones_by_stops(&stops, city, ctx.opt_transit);
+    let distances = CorridorDistances::build(&stops, &city.road);
+    if let Some(area) = area {
+        stops.retain(|s| area.contains(s));
+    }
+    alignment::retain_near_alignment(
+        &mut stops,
+        &route.route_id,
+        city,
+        aco.max_corridor_deviation,
+    );
+    if !excluded_stop_ids.is_empty() {
+        stops.retain(|s| !excluded_stop_ids.contains(s.stop_id.as_str()));
+    }
+    let space = SearchSpace {
+        params: &aco,
+        city,
+        distances: &distances,
+        stops: &stops,
+        zone_to_zone_coverage: &zone_to_zone_coverage,
+        area,
+    };
     // Run the ACO algorithm
-    let mut gen_best_route = route.clone();
-    let mut gen_best_eval = evaluate_route(&aco, &gen_best_route, &city, &zone_to_zone_coverage).0;
-    let init_eval = gen_best_eval;
+    let init_eval = evaluate_route(&aco, route, city, &distances, &zone_to_zone_coverage).0;
+    let mut gen_best_route = start_route.clone();
+    let mut gen_best_eval = if std::ptr::eq(start_route, route) {
+        init_eval
+    } else {
+        evaluate_route(
+            &aco,
+            &gen_best_route,
+            city,
+            &distances,
+            &zone_to_zone_coverage,
+        )
+        .0
+    };
     let mut update_pheromone = vec![];
-    let mut rng = StdRng::seed_from_u64(42);
+    let mut rng = StdRng::seed_from_u64(aco.seed);
+    let mut frontier = ParetoFront::new(aco.pareto_size);
+    if aco.pareto_size > 0 {
+        let start = frontier_route(
+            &aco,
+            &gen_best_route,
+            gen_best_eval,
+            city,
+            &distances,
+            &zone_to_zone_coverage,
+        );
+        frontier.insert(start);
+    }
+    let mut generations = 0;
     for gen_i in 0..aco.max_gen {
+        if ctx.deadline.is_some_and(|d| Instant::now() >= d) {
+            log::debug!(
+                "Deadline reached for route {} after {} generations",
+                route.route_id,
+                gen_i
+            );
+            break;
+        }
+        generations = gen_i + 1;
+        #[cfg(feature = "tracing")]
+        let _generation = tracing::debug_span!("generation", generation = gen_i + 1).entered();
         log::debug!("Generation: {}", gen_i);
         // pheromone evaporation
         pheromone_map.decay();
@@ -295,21 +770,33 @@ pub fn run_aco(
         update_pheromone.clear();
         let mut curr_best_route = gen_best_route.clone();
         let mut curr_best_eval = gen_best_eval;
+        let mut ant = Ant {
+            space: &space,
+            pheromone_map: &pheromone_map,
+            heuristic_map: &mut heuristic_map,
+            rng: &mut rng,
+        };
         for ant_i in 0..aco.num_ant {
             log::debug!("  Ant: {}", ant_i);
+            #[cfg(feature = "tracing")]
+            let _ant = tracing::trace_span!("ant", ant = ant_i).entered();
             // each ant attempts to build a better route
-            if let Some(new_route) = adjust_route(
-                &aco,
-                &gen_best_route,
-                &city,
-                &pheromone_map,
-                &mut heuristic_map,
-                &stops,
-                &zone_to_zone_coverage,
-                &mut rng,
-            ) {
-                let new_route_eval =
-                    evaluate_route(&aco, &new_route, &city, &zone_to_zone_coverage).0;
+            if let Some(new_route) = adjust_route(&mut ant, &gen_best_route) {
+                let new_route_eval = eval_cache
+                    .get_or_insert_with(route_key(&new_route), || {
+                        evaluate_route(&aco, &new_route, city, &distances, &zone_to_zone_coverage)
+                    })
+                    .0;
+                if aco.pareto_size > 0 {
+                    frontier.insert(frontier_route(
+                        &aco,
+                        &new_route,
+                        new_route_eval,
+                        city,
+                        &distances,
+                        &zone_to_zone_coverage,
+                    ));
+                }
                 if new_route_eval > curr_best_eval {
                     update_pheromone.push((curr_best_route, curr_best_eval));
                     curr_best_route = new_route;
@@ -327,48 +814,734 @@ pub fn run_aco(
         } else {
             update_pheromone.push((curr_best_route, curr_best_eval));
         }
+
+        (ctx.on_progress)(ProgressEvent::GenerationCompleted {
+            route_id: route.route_id.clone(),
+            generation: gen_i + 1,
+            max_gen: aco.max_gen,
+            best_score: gen_best_eval,
+        });
     }
 
-    if gen_best_eval > init_eval {
-        let evals = TransitRouteEvals::for_route(opt_transit, &gen_best_route, &city.grid);
-        gen_best_route.evals = Some(evals);
-        gen_best_route.stop_times = route.stop_times.clone();
-        return Some((gen_best_route, gen_best_eval));
-    } else {
-        return None;
+    (ctx.on_progress)(ProgressEvent::SearchSpace {
+        route_id: route.route_id.clone(),
+        candidate_stops: stops.len(),
+        candidate_zone_pairs: zone_to_zone_coverage.len(),
+        heuristic_entries: heuristic_map.values.len(),
+        cached_evaluations: eval_cache.hits(),
+    });
+
+    if aco.local_search {
+        let aco_eval = gen_best_eval;
+        (gen_best_route, gen_best_eval) =
+            local_search(&space, gen_best_route, gen_best_eval, &mut eval_cache);
+        if gen_best_eval > init_eval {
+            (ctx.on_progress)(ProgressEvent::LocalSearchCompleted {
+                route_id: route.route_id.clone(),
+                initial_score: init_eval,
+                aco_score: aco_eval,
+                final_score: gen_best_eval,
+            });
+        }
+        if aco.pareto_size > 0 {
+            let best = frontier_route(
+                &aco,
+                &gen_best_route,
+                gen_best_eval,
+                city,
+                &distances,
+                &zone_to_zone_coverage,
+            );
+            frontier.insert(best);
+        }
+    }
+
+    if aco.pareto_size > 0 {
+        (ctx.on_progress)(ProgressEvent::ParetoFrontier {
+            route_id: route.route_id.clone(),
+            routes: frontier.into_routes(),
+        });
+    }
+
+    OPTIMIZER_METRICS.record_search(SearchStats {
+        route_id: &route.route_id,
+        duration: start.elapsed(),
+        generations,
+        heuristic_hits: heuristic_map.hits,
+        heuristic_misses: heuristic_map.misses,
+        pheromone_hits: pheromone_map.hits.get(),
+        pheromone_misses: pheromone_map.misses.get(),
+    });
+
+    (
+        gen_best_route,
+        gen_best_eval,
+        init_eval,
+        pheromone_map.snapshot(),
+    )
+}
+
+/// A route placed on the frontier of the routes ACO found, see `ACO::pareto_size`
+fn frontier_route(
+    params: &ACO,
+    route: &TransitRoute,
+    score: f64,
+    city: &City,
+    distances: &CorridorDistances,
+    zone_to_zone_coverage: &HashMap<(u32, u32), u32>,
+) -> FrontierRoute {
+    let road_m: f64 = route
+        .outbound_stops
+        .windows(2)
+        .map(|w| distances.leg(&w[0], &w[1], &city.road).meters)
+        .sum();
+    // nonlinearity only discounts objective scores, which the trade-off does not use
+    let measures = RouteMeasures::new(
+        params,
+        route,
+        city,
+        zone_to_zone_coverage,
+        road_m / 1000.0,
+        1.0,
+    );
+    FrontierRoute {
+        stop_ids: route
+            .outbound_stops
+            .iter()
+            .map(|s| s.stop_id.clone())
+            .collect(),
+        trade_off: TradeOff::new(&measures),
+        score,
+    }
+}
+
+/// Optimize a long route chunk by chunk, then score the stitched route as a whole
+///
+/// Each chunk is optimized as a route of its own between two boundary stops, which it keeps.
+/// Stops of the other chunks cannot be added to a chunk, so the stitched route never visits a
+/// stop twice.
+///
+/// # Arguments
+/// - `boundaries`: Indices in the stops of `start_route` the chunks start and end at, see
+///   `chunk_boundaries`
+///
+/// # Returns
+/// Same as `search_route`, with both scores computed over the zones of `route` and of the
+/// stitched route. No frontier is kept, the chunks are not versions of the whole route.
+fn search_chunks(
+    route_params: ACO,
+    route: &TransitRoute,
+    start_route: &TransitRoute,
+    boundaries: &[usize],
+    seed_pheromones: Option<&Pheromones>,
+    ctx: &mut SearchContext,
+) -> (TransitRoute, f64, f64, Pheromones) {
+    let chunks = boundaries.len() - 1;
+    log::debug!(
+        "Optimizing route {} with {} stops in {} chunks",
+        route.route_id,
+        start_route.outbound_stops.len(),
+        chunks
+    );
+    let stops = &start_route.outbound_stops;
+    let mut stitched = vec![stops[0].clone()];
+    let mut pheromones = Pheromones::new();
+    let chunk_params = ACO {
+        pareto_size: 0,
+        ..route_params.clone()
+    };
+    // the route with the given outbound stops, without its timetable
+    let with_stops = |outbound_stops: Vec<Arc<TransitStop>>| TransitRoute {
+        route_id: route.route_id.clone(),
+        route_type: route.route_type.clone(),
+        outbound_stops,
+        inbound_stops: vec![],
+        evals: None,
+        stop_times: HashMap::new(),
+        service_span: route.service_span.clone(),
+        vehicle: route.vehicle.clone(),
+    };
+    for (i, pair) in boundaries.windows(2).enumerate() {
+        let chunk = with_stops(stops[pair[0]..=pair[1]].to_vec());
+        // stops already in the stitched route or in a later chunk
+        let excluded: HashSet<&str> = stitched
+            .iter()
+            .chain(&stops[pair[1] + 1..])
+            .map(|s| s.stop_id.as_str())
+            .collect();
+        let (best, _, _, chunk_pheromones) = search_route(
+            chunk_params.clone(),
+            &chunk,
+            &chunk,
+            seed_pheromones,
+            &excluded,
+            ctx,
+        );
+        // the first stop of the chunk is the last stop of the previous one
+        stitched.extend(best.outbound_stops.into_iter().skip(1));
+        (ctx.on_progress)(ProgressEvent::ChunkCompleted {
+            route_id: route.route_id.clone(),
+            chunk: i + 1,
+            chunks,
+            first_stop_id: stops[pair[0]].stop_id.clone(),
+            last_stop_id: stops[pair[1]].stop_id.clone(),
+        });
+        pheromones.extend(chunk_pheromones);
+    }
+
+    let stitched = with_stops(stitched);
+    let mut eval_stops = route.outbound_stops.clone();
+    eval_stops.extend(stitched.outbound_stops.iter().cloned());
+    let city = ctx.city;
+    let zone_to_zone_coverage = filter_zones_by_stops(&eval_stops, city, ctx.opt_transit);
+    let distances = CorridorDistances::build(&eval_stops, &city.road);
+    let init_eval = evaluate_route(
+        &route_params,
+        route,
+        city,
+        &distances,
+        &zone_to_zone_coverage,
+    )
+    .0;
+    let eval = evaluate_route(
+        &route_params,
+        &stitched,
+        city,
+        &distances,
+        &zone_to_zone_coverage,
+    )
+    .0;
+    (stitched, eval, init_eval, pheromones)
+}
+
+/// Number of other routes stopping at each outbound stop of a route
+fn transfer_counts(route: &TransitRoute, transit: &TransitNetwork) -> Vec<usize> {
+    let mut routes_at_stop: HashMap<&str, usize> = HashMap::new();
+    for other in transit
+        .routes
+        .iter()
+        .filter(|r| r.route_id != route.route_id)
+    {
+        let stop_ids: HashSet<&str> = other
+            .outbound_stops
+            .iter()
+            .chain(&other.inbound_stops)
+            .map(|s| s.stop_id.as_str())
+            .collect();
+        for stop_id in stop_ids {
+            *routes_at_stop.entry(stop_id).or_default() += 1;
+        }
+    }
+    route
+        .outbound_stops
+        .iter()
+        .map(|s| routes_at_stop.get(s.stop_id.as_str()).copied().unwrap_or(0))
+        .collect()
+}
+
+/// Split a route into chunks of about `chunk_len` stops at its major transfer stops
+///
+/// Each boundary is the stop with the most transfers between half and one and a half
+/// `chunk_len` stops after the previous boundary, the one closest to `chunk_len` stops on a
+/// tie, and no chunk is shorter than half of `chunk_len`.
+///
+/// # Arguments
+/// - `transfers`: Number of other routes at each stop of the route, see `transfer_counts`
+///
+/// # Returns
+/// Indices of the stops the chunks start and end at, from the first to the last stop
+fn chunk_boundaries(transfers: &[usize], chunk_len: usize) -> Vec<usize> {
+    let n = transfers.len();
+    if n < 2 {
+        return vec![];
+    }
+    let chunk_len = chunk_len.max(2);
+    let (min_len, max_len) = (chunk_len / 2, chunk_len + chunk_len / 2);
+    let mut boundaries = vec![0];
+    let mut start = 0;
+    while n - 1 - start > max_len {
+        let window = start + min_len..=(start + max_len).min(n - 1 - min_len);
+        let target = start + chunk_len;
+        let boundary = window
+            .max_by(|&a, &b| {
+                transfers[a]
+                    .cmp(&transfers[b])
+                    .then_with(|| b.abs_diff(target).cmp(&a.abs_diff(target)))
+            })
+            .unwrap();
+        boundaries.push(boundary);
+        start = boundary;
+    }
+    boundaries.push(n - 1);
+    boundaries
+}
+
+/// Most passes of the local search over a route, each pass keeps the first improving move
+const LOCAL_SEARCH_MAX_PASSES: usize = 50;
+/// Longest run of stops reversed by a 2-opt move of the local search
+const LOCAL_SEARCH_MAX_SEGMENT: usize = 4;
+
+/// Greedy local search around a route, applying the first move that improves the score until
+/// no move does
+///
+/// # Arguments
+/// - `space`: What the route is searched over, its candidate stops can be inserted in the
+///   route
+/// - `route`: Best route found by ACO
+/// - `score`: Score of `route`
+/// - `eval_cache`: Evaluations of the routes the ants built, shared with the moves
+///
+/// # Returns
+/// - The improved route and its score, or the given route and score if no move helped
+///
+/// # Notes
+/// - Moves are tried in a fixed order, so the result only depends on the input route
+/// - Moves are reversing a short run of stops (2-opt), removing a stop and inserting a
+///   candidate stop within `max_stop_dist` of both its new neighbours. The first and last
+///   stops, the stops outside the area of `space` and the locked stops and segments of the
+///   route never change.
+fn local_search(
+    space: &SearchSpace,
+    mut route: TransitRoute,
+    mut score: f64,
+    eval_cache: &mut EvalCache<RouteKey, (f64, f64)>,
+) -> (TransitRoute, f64) {
+    let (params, area) = (space.params, space.area);
+    for pass in 0..LOCAL_SEARCH_MAX_PASSES {
+        let current = &route.outbound_stops;
+        let n = current.len();
+        let mut moves: Vec<Vec<Arc<TransitStop>>> = vec![];
+        let constraint = constraint_of(&params.constraints, &route);
+        let locked: Vec<bool> = current
+            .iter()
+            .map(|s| {
+                area.is_some_and(|area| !area.contains(s))
+                    || constraint.is_some_and(|c| c.locks_stop(&s.stop_id))
+            })
+            .collect();
+
+        // 2-opt: reverse stops i..=j
+        for i in 1..n.saturating_sub(2) {
+            for j in i + 1..(i + LOCAL_SEARCH_MAX_SEGMENT).min(n - 1) {
+                if locked[i..=j].iter().any(|l| *l) {
+                    continue;
+                }
+                let mut candidate = current.clone();
+                candidate[i..=j].reverse();
+                moves.push(candidate);
+            }
+        }
+        // remove stop i
+        if n > params.min_route_len.max(2) {
+            for i in (1..n - 1).filter(|i| !locked[*i]) {
+                let mut candidate = current.clone();
+                candidate.remove(i);
+                moves.push(candidate);
+            }
+        }
+        // insert a stop between i - 1 and i
+        if n < params.max_route_len {
+            let in_route: HashSet<&str> = current.iter().map(|s| s.stop_id.as_str()).collect();
+            for i in 1..n {
+                let (prev, next) = (&current[i - 1], &current[i]);
+                if constraint.is_some_and(|c| c.locks_segment(&prev.stop_id, &next.stop_id)) {
+                    continue;
+                }
+                for stop in space
+                    .stops
+                    .iter()
+                    .filter(|s| !in_route.contains(s.stop_id.as_str()))
+                {
+                    let near = |other: &TransitStop| {
+                        geo_util::haversine(
+                            stop.geom.x(),
+                            stop.geom.y(),
+                            other.geom.x(),
+                            other.geom.y(),
+                        ) <= params.max_stop_dist
+                    };
+                    if near(prev) && near(next) {
+                        let mut candidate = current.clone();
+                        candidate.insert(i, stop.clone());
+                        moves.push(candidate);
+                    }
+                }
+            }
+        }
+
+        let improved = moves.into_iter().find_map(|outbound_stops| {
+            let candidate = TransitRoute {
+                outbound_stops,
+                ..route.clone()
+            };
+            let candidate_score = eval_cache
+                .get_or_insert_with(route_key(&candidate), || {
+                    evaluate_route(
+                        params,
+                        &candidate,
+                        space.city,
+                        space.distances,
+                        space.zone_to_zone_coverage,
+                    )
+                })
+                .0;
+            (candidate_score > score).then_some((candidate, candidate_score))
+        });
+        match improved {
+            Some((better, better_score)) => {
+                log::debug!(
+                    "  Local search pass {}: {} -> {}",
+                    pass,
+                    score,
+                    better_score
+                );
+                route = better;
+                score = better_score;
+            }
+            None => break,
+        }
     }
+    (route, score)
+}
+
+/// Search run to optimize a single route
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    /// Ant colony optimization followed by the local search, see `run_aco_from_seed`
+    #[default]
+    Aco,
+    /// Simulated annealing over stop insertions, removals and swaps, see `sa::run_sa`
+    Sa,
+}
+
+/// How zone-to-zone coverage is computed while optimizing a batch of routes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", try_from = "CoverageModeSpec")]
+pub enum CoverageMode {
+    /// Coverage is recomputed from the network as routes are optimized, so earlier
+    /// optimizations in the batch affect how later routes are scored
+    #[default]
+    Live,
+    /// Coverage is computed from the network as it was at the start of the batch,
+    /// making the result independent of the order routes are optimized in
+    Frozen,
+    /// Coverage snapshot is refreshed after every K optimized routes, K is at least 1
+    RefreshEvery(usize),
 }
 
+/// `CoverageMode` as written in requests, checked by `CoverageMode::try_from`
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CoverageModeSpec {
+    Live,
+    Frozen,
+    RefreshEvery(usize),
+}
+
+impl TryFrom<CoverageModeSpec> for CoverageMode {
+    type Error = String;
+
+    fn try_from(spec: CoverageModeSpec) -> Result<Self, Self::Error> {
+        match spec {
+            CoverageModeSpec::Live => Ok(CoverageMode::Live),
+            CoverageModeSpec::Frozen => Ok(CoverageMode::Frozen),
+            CoverageModeSpec::RefreshEvery(k) => CoverageMode::refresh_every(k),
+        }
+    }
+}
+
+impl CoverageMode {
+    /// Refresh the coverage snapshot after every `k` optimized routes
+    ///
+    /// # Returns
+    /// - An error if `k` is 0, which would never refresh the snapshot
+    pub fn refresh_every(k: usize) -> Result<CoverageMode, String> {
+        if k == 0 {
+            return Err("coverage refresh interval must be at least 1".to_string());
+        }
+        Ok(CoverageMode::RefreshEvery(k))
+    }
+}
+
+/// Limits on the resources a batch optimization may use, enforced regardless of the ACO
+/// parameters
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BatchLimits {
+    /// Time after which no further route is started and the running route stops early
+    pub max_wall_time: Option<Duration>,
+    /// Upper bound on the generations run per route, lowering `ACO::max_gen` if needed
+    pub max_generations: Option<usize>,
+}
+
+/// How a batch of routes is optimized
+#[derive(Clone, Copy, Default)]
+pub struct BatchOptions<'a> {
+    /// How zone-to-zone coverage is computed while the routes are optimized
+    pub coverage_mode: CoverageMode,
+    pub limits: BatchLimits,
+    /// Area the changes are restricted to, see `SearchContext::area`
+    pub area: Option<&'a StudyArea>,
+    /// Operating budget the network must stay within, an optimized route is only accepted if
+    /// the network with it does, `None` to accept routes whatever they cost
+    pub budget: Option<&'a OperatingBudget>,
+}
+
+/// Outcome of optimizing a batch of routes
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BatchResult {
+    /// Ids of the routes that were improved, in the order they were optimized
+    pub optimized_route_ids: Vec<String>,
+    /// Coverage mode used while scoring the routes
+    pub coverage_mode: CoverageMode,
+    /// Number of times the coverage snapshot was taken during the batch
+    pub coverage_snapshots: usize,
+    /// Ids of the routes that were not attempted because the wall time limit was reached
+    #[serde(default)]
+    pub skipped_route_ids: Vec<String>,
+    /// Ids of the routes whose run the wall time limit cut off before it found a better
+    /// version, which may still be optimizable
+    #[serde(default)]
+    pub interrupted_route_ids: Vec<String>,
+    /// Ids of the routes whose optimized version was rejected for exceeding the operating
+    /// budget
+    #[serde(default)]
+    pub over_budget_route_ids: Vec<String>,
+    /// Share, from 0 to 1, of the score improvement of the batch made by the local search,
+    /// `None` if it did not run or improved nothing
+    #[serde(default)]
+    pub local_search_share: Option<f64>,
+    /// Time and memory the batch used
+    #[serde(default)]
+    pub resources: ResourceUsage,
+    /// Versions of each route trading off coverage, ridership and road length, for a planner
+    /// to pick from. Empty unless `ACO::pareto_size` is set.
+    #[serde(default)]
+    pub frontiers: BTreeMap<String, Vec<FrontierRoute>>,
+}
+
+impl BatchResult {
+    /// Whether the wall time limit kept a route from being optimized, by skipping it or by
+    /// cutting its run off, so that it should not be taken as already optimal
+    pub fn ran_out_of_time(&self, route_id: &str) -> bool {
+        self.skipped_route_ids.iter().any(|id| id == route_id)
+            || self.interrupted_route_ids.iter().any(|id| id == route_id)
+    }
+}
+
+/// Optimize routes one after the other, replacing them in `opt_transit`
+///
+/// # Arguments
+/// - `options`: Coverage mode, limits, area and budget of the batch, see `BatchOptions`
 pub fn run_aco_batch(
     params: ACO,
     routes: &Vec<&TransitRoute>,
     city: &City,
     opt_transit: &mut TransitNetwork,
-) -> Vec<String> {
+    options: BatchOptions,
+) -> BatchResult {
+    run_aco_batch_with_progress(params, routes, city, opt_transit, options, &mut |_| {})
+}
+
+/// `run_aco_batch`, reporting the progress events of each route as it is optimized
+pub fn run_aco_batch_with_progress(
+    params: ACO,
+    routes: &Vec<&TransitRoute>,
+    city: &City,
+    opt_transit: &mut TransitNetwork,
+    options: BatchOptions,
+    on_progress: &mut dyn FnMut(ProgressEvent),
+) -> BatchResult {
+    run_batch(
+        params,
+        routes,
+        city,
+        opt_transit,
+        options,
+        None,
+        on_progress,
+    )
+}
+
+/// `run_aco_batch`, saving a checkpoint every `checkpointing.every` routes and carrying on
+/// from `checkpointing.resume` if set
+fn run_batch(
+    params: ACO,
+    routes: &Vec<&TransitRoute>,
+    city: &City,
+    opt_transit: &mut TransitNetwork,
+    options: BatchOptions,
+    mut checkpointing: Option<Checkpointing>,
+    on_progress: &mut dyn FnMut(ProgressEvent),
+) -> BatchResult {
+    let BatchOptions {
+        coverage_mode,
+        limits,
+        area,
+        budget,
+    } = options;
+    let deadline = limits.max_wall_time.map(|t| Instant::now() + t);
+    let mut meter = ResourceMeter::start();
+
+    // Cost of each bus route of the network, kept up to date to check routes against the budget
+    let budget = budget.filter(|b| !b.is_unlimited());
+    let mut route_costs: HashMap<String, RouteCost> = match budget {
+        Some(_) => opt_transit
+            .routes
+            .iter()
+            .filter(|r| r.route_type == TransitRouteType::Bus)
+            .map(|r| (r.route_id.clone(), RouteCost::new(r, &city.road)))
+            .collect(),
+        None => HashMap::new(),
+    };
+    let mut over_budget_route_ids = vec![];
+
     // Calculate route-specific parameters and sort routes by evaluation ascending (worst first)
     let mut routes_with_params = routes
         .iter()
         .map(|route| {
             // Calculate route-specific parameters for evaluation
-            let route_params = calculate_route_specific_params(route, city, &params);
+            let mut route_params = calculate_route_specific_params(route, city, &params);
+            if let Some(max_generations) = limits.max_generations {
+                route_params.max_gen = route_params.max_gen.min(max_generations);
+            }
 
             // get the stop choices
-            let stops = filter_stops_by_route_bbox(route, city, 250.0);
-            // can speed up by precomputing stops to zone mapping in city struct?
+            let stops = filter_stops_by_route_bbox(route, city, city.search.bbox_padding);
             let zone_to_zone_coverage = filter_zones_by_stops(&stops, city, opt_transit);
-            let eval = evaluate_route(&route_params, route, city, &zone_to_zone_coverage);
+            let eval = evaluate_route(
+                &route_params,
+                route,
+                city,
+                &CorridorDistances::default(),
+                &zone_to_zone_coverage,
+            );
             (route, eval.0, route_params)
         })
         .collect::<Vec<_>>();
-    routes_with_params.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+    // lowest scoring routes first, ties by route id so batches run in the same order every time
+    routes_with_params
+        .sort_by(|a, b| ordering::cmp_f64(a.1, b.1).then_with(|| a.0.route_id.cmp(&b.0.route_id)));
+
+    // a resumed batch carries on in the order it started with, from the first route not done
+    let resume = checkpointing.as_mut().and_then(|c| c.resume.take());
+    let (route_ids, mut progress, mut optimized_route_ids, mut pheromones) = match resume {
+        Some(checkpoint) => {
+            let position: HashMap<&str, usize> = checkpoint
+                .route_ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| (id.as_str(), i))
+                .collect();
+            routes_with_params.retain(|(route, ..)| {
+                position
+                    .get(route.route_id.as_str())
+                    .is_some_and(|i| *i >= checkpoint.progress)
+            });
+            routes_with_params.sort_by_key(|(route, ..)| position[route.route_id.as_str()]);
+            (
+                checkpoint.route_ids.clone(),
+                checkpoint.progress,
+                checkpoint.network.optimized_routes,
+                checkpoint.pheromones,
+            )
+        }
+        None => (
+            routes_with_params
+                .iter()
+                .map(|(route, ..)| route.route_id.clone())
+                .collect(),
+            0,
+            vec![],
+            HashMap::new(),
+        ),
+    };
+
+    // Snapshot of the network used to compute coverage, None when coverage is live
+    let mut coverage_snapshots = 0;
+    let mut coverage_snapshot = match coverage_mode {
+        CoverageMode::Live => None,
+        CoverageMode::Frozen | CoverageMode::RefreshEvery(_) => {
+            coverage_snapshots += 1;
+            Some(opt_transit.clone())
+        }
+    };
 
     // run aco on the routes and update the transit network
-    let mut optimized_route_ids = vec![];
-    let (mut count, tot) = (1, routes_with_params.len());
+    let mut skipped_route_ids = vec![];
+    let mut interrupted_route_ids = vec![];
+    // score improvements of the routes the local search ran on, to report its share
+    let (mut local_search_gain, mut total_gain) = (0.0, 0.0);
+    let mut frontiers = BTreeMap::new();
+    let tot = route_ids.len();
     for (route, _, route_params) in routes_with_params {
-        println!("Optimizing route: {}, {}/{}", route.route_id, count, tot);
-        count += 1;
-        if let Some((optimized_route, eval)) = run_aco(route_params, route, city, opt_transit) {
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            skipped_route_ids.push(route.route_id.clone());
+            continue;
+        }
+        println!(
+            "Optimizing route: {}, {}/{}",
+            route.route_id,
+            progress + 1,
+            tot
+        );
+        let coverage_transit = coverage_snapshot.as_ref().unwrap_or(&*opt_transit);
+        let (result, route_pheromones) = run_aco_from_seed(
+            route_params,
+            route,
+            None,
+            &mut SearchContext {
+                city,
+                opt_transit: coverage_transit,
+                area,
+                deadline,
+                on_progress: &mut |event| {
+                    meter.observe(&event);
+                    match &event {
+                        ProgressEvent::LocalSearchCompleted {
+                            initial_score,
+                            aco_score,
+                            final_score,
+                            ..
+                        } => {
+                            local_search_gain += final_score - aco_score;
+                            total_gain += final_score - initial_score;
+                        }
+                        ProgressEvent::ParetoFrontier { route_id, routes } => {
+                            frontiers.insert(route_id.clone(), routes.clone());
+                        }
+                        _ => {}
+                    }
+                    on_progress(event);
+                },
+            },
+        );
+        // given more time, a run the deadline cut off may still have improved the route
+        if result.is_none() && deadline.is_some_and(|d| Instant::now() >= d) {
+            interrupted_route_ids.push(route.route_id.clone());
+        }
+        let result = result.filter(|(optimized_route, _)| {
+            let Some(budget) = budget else {
+                return true;
+            };
+            let cost = RouteCost::new(optimized_route, &city.road);
+            let before = OperatingCost::total(route_costs.values());
+            let after = OperatingCost::total(
+                route_costs
+                    .iter()
+                    .filter(|(id, _)| **id != optimized_route.route_id)
+                    .map(|(_, c)| c)
+                    .chain([&cost]),
+            );
+            if !budget.allows(&before, &after) {
+                println!("  Route rejected, it would exceed the operating budget");
+                over_budget_route_ids.push(optimized_route.route_id.clone());
+                return false;
+            }
+            route_costs.insert(optimized_route.route_id.clone(), cost);
+            true
+        });
+        if let Some((optimized_route, eval)) = result {
             println!("  Route optimized with score: {}", eval);
             // Update the network by replacing the route
             let route_id = optimized_route.route_id.clone();
@@ -379,41 +1552,149 @@ pub fn run_aco_batch(
             {
                 opt_transit.routes[idx] = optimized_route;
                 optimized_route_ids.push(route_id);
+
+                if let CoverageMode::RefreshEvery(k) = coverage_mode {
+                    if k > 0 && optimized_route_ids.len() % k == 0 {
+                        log::debug!("Refreshing coverage snapshot");
+                        coverage_snapshots += 1;
+                        coverage_snapshot = Some(opt_transit.clone());
+                    }
+                }
+            }
+        }
+
+        progress += 1;
+        if let Some(checkpointing) = checkpointing.as_mut().filter(|c| c.every > 0) {
+            pheromones.insert(route.route_id.clone(), route_pheromones);
+            if Checkpoint::due(progress, checkpointing.every) {
+                log::debug!("Saving checkpoint after {}/{} routes", progress, tot);
+                (checkpointing.save)(&Checkpoint {
+                    saved_at: chrono::Local::now().to_rfc3339(),
+                    route_ids: route_ids.clone(),
+                    progress,
+                    network: OptimizedTransitNetwork {
+                        network: opt_transit.clone(),
+                        optimized_routes: optimized_route_ids.clone(),
+                    },
+                    pheromones: pheromones.clone(),
+                    live: None,
+                });
             }
         }
     }
-    optimized_route_ids
+
+    if !skipped_route_ids.is_empty() {
+        log::warn!(
+            "Wall time limit reached, skipped {} routes",
+            skipped_route_ids.len()
+        );
+    }
+
+    BatchResult {
+        optimized_route_ids,
+        coverage_mode,
+        coverage_snapshots,
+        skipped_route_ids,
+        interrupted_route_ids,
+        over_budget_route_ids,
+        local_search_share: (total_gain > 0.0).then(|| local_search_gain / total_gain),
+        resources: meter.finish(),
+        frontiers,
+    }
 }
 
+/// Optimize every route of a network one after the other in a copy of it
+///
+/// # Arguments
+/// - `budget`: Operating budget the network must stay within, an optimized route is only
+///   accepted if the network with it does, `None` to accept routes whatever they cost
+/// - `checkpointing`: How often to save a checkpoint and which one to resume from, so that a
+///   run that stopped can be carried on, `None` to save none. A resumed run starts from the
+///   network of the checkpoint and optimizes the routes it had not done.
+///
+/// # Returns
+/// The optimized network, or an error if the checkpoint has routes `transit` does not
+///
+/// # Notes
+/// - A network that starts over budget may still accept routes that do not raise the
+///   measures it is over, see `OperatingBudget::allows`
+/// - Checkpoints are saved between routes, the route running when the run stopped is
+///   optimized again from the start
+/// - The coverage snapshot of a frozen or refreshed `coverage_mode` is taken again from the
+///   network a run resumes from
 pub fn run_aco_network(
     params: ACO,
     city: &City,
     transit: &TransitNetwork,
-) -> OptimizedTransitNetwork {
+    coverage_mode: CoverageMode,
+    budget: Option<&OperatingBudget>,
+    checkpointing: Option<Checkpointing>,
+) -> Result<OptimizedTransitNetwork, String> {
     let routes = transit.routes.iter().collect::<Vec<_>>();
 
-    // Create a mutable copy of the transit network
-    let mut opt_transit = transit.clone();
+    // Create a mutable copy of the transit network, or of the one the run is resumed from
+    let resume = checkpointing.as_ref().and_then(|c| c.resume.as_ref());
+    let mut opt_transit = match resume {
+        Some(checkpoint) => {
+            if let Some(missing) = checkpoint
+                .route_ids
+                .iter()
+                .find(|id| !routes.iter().any(|r| &r.route_id == *id))
+            {
+                return Err(format!(
+                    "Route {} of the checkpoint is not in the network",
+                    missing
+                ));
+            }
+            log::info!(
+                "Resuming from checkpoint saved at {} after {}/{} routes",
+                checkpoint.saved_at,
+                checkpoint.progress,
+                checkpoint.route_ids.len()
+            );
+            checkpoint.network.network.clone()
+        }
+        None => transit.clone(),
+    };
 
-    // Optimize routes and update the network in-place
-    let optimized_route_ids = run_aco_batch(params, &routes, city, &mut opt_transit);
+    let options = BatchOptions {
+        coverage_mode,
+        budget,
+        ..BatchOptions::default()
+    };
+    let result = run_batch(
+        params,
+        &routes,
+        city,
+        &mut opt_transit,
+        options,
+        checkpointing,
+        &mut |_| {},
+    );
+    if !result.over_budget_route_ids.is_empty() {
+        log::info!(
+            "Kept {} routes that would exceed the operating budget",
+            result.over_budget_route_ids.len()
+        );
+    }
 
     // Update the network evals
     opt_transit.evals = Some(TransitNetworkEvals::for_network(&opt_transit, &city.grid));
 
-    OptimizedTransitNetwork {
+    Ok(OptimizedTransitNetwork {
         network: opt_transit,
-        optimized_routes: optimized_route_ids,
-    }
+        optimized_routes: result.optimized_route_ids,
+    })
 }
 
 // Helpers for ACO
 
 // Computes a score for the route and a punishment factor for the route
-fn evaluate_route(
+pub(crate) fn evaluate_route(
     params: &ACO,
     route: &TransitRoute,
     city: &City,
+    distances: &CorridorDistances,
     zone_to_zone_coverage: &HashMap<(u32, u32), u32>,
 ) -> (f64, f64) {
     // 1 - Compute nonlinearity Z_r
@@ -421,26 +1702,29 @@ fn evaluate_route(
     if stops.len() < 2 {
         return (0.0, 1.0);
     }
+    // vehicles of the route, those of the params if it has no profile
+    let vehicle = route
+        .vehicle
+        .clone()
+        .unwrap_or_else(|| params.default_vehicle());
     let mut road_dist = 0.0;
     let mut bad_turn_count = 0;
-    let mut path_pi = vec![];
+    let mut steep_leg_count = 0;
+    let mut leg_pi = Leg::default();
     for w in stops.windows(2) {
-        let (from, to) = (&w[0], &w[1]);
-        let (dist_ij, path_ij) = from.road_distance(to, &city.road);
-        // check if path_ij is a u-turn or large detour from path_pi
-        let (p0, p1) = (path_pi.get(path_pi.len() - 2), path_pi.last());
-        let (c0, c1) = (path_ij.first(), path_ij.get(1));
-        if let (Some(p0), Some(p1), Some(c0), Some(c1)) = (p0, p1, c0, c1) {
-            let (p0, p1) = (city.road.get_node(*p0).geom, city.road.get_node(*p1).geom);
-            let (c0, c1) = (city.road.get_node(*c0).geom, city.road.get_node(*c1).geom);
-            let diff = angle_diff(p0, p1, c0, c1);
-            if diff.abs() > 178.0 {
-                bad_turn_count += 1;
-            }
+        let leg_ij = distances.leg(&w[0], &w[1], &city.road);
+        // check if leg_ij is a turn buses may not make or too sharp for the vehicles from leg_pi
+        if !turn_allowed(&leg_pi, &leg_ij, city)
+            || turn_between(&leg_pi, &leg_ij, city).is_some_and(|angle| !vehicle.can_turn(angle))
+        {
+            bad_turn_count += 1;
+        }
+        if leg_grade(&leg_ij, city).is_some_and(|grade| grade > vehicle.max_grade) {
+            steep_leg_count += 1;
         }
         // add the distance to the total road distance
-        road_dist += dist_ij;
-        path_pi = path_ij;
+        road_dist += leg_ij.meters;
+        leg_pi = leg_ij;
     }
     let straight_line_dist = geo_util::haversine(
         stops.first().unwrap().geom.x(),
@@ -450,39 +1734,23 @@ fn evaluate_route(
     );
     let nonlinearity = road_dist / straight_line_dist;
 
-    // 2 - Compute demand p_r
-    let mut zones = vec![];
-    let mut zones_count = HashMap::new();
-    for stop in stops {
-        // possible dont add demand for bad stops to not reward them
-        let zone = stop.zone_index(&city.grid);
-        if let Some(zone) = zone {
-            if !zones.contains(&zone) {
-                zones.push(zone);
-                zones_count.insert(zone, 1);
-            } else {
-                *zones_count.entry(zone).or_insert(0) += 1;
-            }
-        }
-    }
-    let mut demand = 0.0;
-    for i in 0..zones.len() {
-        for j in i + 1..zones.len() {
-            let (u, v) = (
-                city.grid.get_zone(zones[i]).zoneid,
-                city.grid.get_zone(zones[j]).zoneid,
-            );
-            let coverage = *zone_to_zone_coverage.get(&(u, v)).unwrap_or(&1) as f64;
-            demand += (city.grid.demand_between_zones(zones[i], zones[j])
-                + city.grid.demand_between_zones(zones[j], zones[i]))
-                * zones_count[&zones[i]] as f64
-                * 0.75
-                / coverage;
-        }
-    }
+    // 2 - Score the route by the objectives of the params
+    let measures = RouteMeasures::new(
+        params,
+        route,
+        city,
+        zone_to_zone_coverage,
+        road_dist / 1000.0,
+        nonlinearity,
+    );
+    let score = params.objective.score(&measures);
 
-    // compute score
-    let score = demand / ((road_dist / 1000.0) * nonlinearity);
+    // departures needed in the busiest period to carry the peak load
+    let required_departures = if params.max_departures > 0 && vehicle.capacity > 0 {
+        peak_period_load(&measures.zones, city) / vehicle.capacity as f64
+    } else {
+        0.0
+    };
 
     // calculate average distance between stops
     let avg_stop_dist = if stops.len() > 1 {
@@ -491,6 +1759,12 @@ fn evaluate_route(
         0.0
     };
 
+    // stop spacing norms of the vehicles, the params' range scaled to their usual spacing
+    let spacing_scale = vehicle.stop_spacing / params.avg_stop_dist;
+    let avg_stop_norm = vehicle.stop_spacing;
+    let min_stop_norm = params.min_stop_dist * spacing_scale;
+    let max_stop_norm = params.max_stop_dist * spacing_scale;
+
     // determine punishment factor
     let mut punishment_factor = 0.0;
     if nonlinearity > params.max_nonlinearity - 0.5 {
@@ -500,25 +1774,23 @@ fn evaluate_route(
     }
     if bad_turn_count > 0 {
         let expected_stops =
-            ((straight_line_dist / params.avg_stop_dist) * params.max_nonlinearity).ceil();
+            ((straight_line_dist / avg_stop_norm) * params.max_nonlinearity).ceil();
         punishment_factor += PUNISHMENT_BAD_TURN
             * (bad_turn_count as f64 / (expected_stops as f64 * 0.1).max(10.0)).min(1.0);
     }
     if avg_stop_dist > 0.0 {
         // Calculate deviation from average stop distance
-        if avg_stop_dist <= params.min_stop_dist || avg_stop_dist >= params.max_stop_dist {
+        if avg_stop_dist <= min_stop_norm || avg_stop_dist >= max_stop_norm {
             // Outside allowed range - maximum punishment
             punishment_factor += PUNISHMENT_STOP_DIST;
         } else {
             // Inside allowed range - scale based on distance from ideal average
-            let normalized_deviation = if avg_stop_dist < params.avg_stop_dist {
+            let normalized_deviation = if avg_stop_dist < avg_stop_norm {
                 // Below average: normalize between min and avg
-                (params.avg_stop_dist - avg_stop_dist)
-                    / (params.avg_stop_dist - params.min_stop_dist)
+                (avg_stop_norm - avg_stop_dist) / (avg_stop_norm - min_stop_norm)
             } else {
                 // Above average: normalize between avg and max
-                (avg_stop_dist - params.avg_stop_dist)
-                    / (params.max_stop_dist - params.avg_stop_dist)
+                (avg_stop_dist - avg_stop_norm) / (max_stop_norm - avg_stop_norm)
             };
 
             // Apply non-linear scaling (quadratic growth)
@@ -527,13 +1799,28 @@ fn evaluate_route(
         }
     }
 
+    if steep_leg_count > 0 {
+        // the vehicles cannot climb between some stops, punished in full from a fifth of the legs
+        let legs = (stops.len() - 1) as f64;
+        punishment_factor += PUNISHMENT_GRADE * (steep_leg_count as f64 / (legs * 0.2)).min(1.0);
+    }
+
+    if params.max_departures > 0 && required_departures > params.max_departures as f64 {
+        // a single route cannot carry the corridor's demand at a realistic frequency
+        let max_departures = params.max_departures as f64;
+        punishment_factor += PUNISHMENT_CAPACITY
+            * ((required_departures - max_departures) / max_departures).min(1.0);
+    }
+
     log::debug!(
-        "  Score: {}, Punishment: {}, Nonlinearity: {}, Bad Turn: {}, Avg Stop Dist: {:?}m",
+        "  Score: {}, Punishment: {}, Nonlinearity: {}, Bad Turn: {}, Steep Legs: {}, Avg Stop Dist: {:?}m, Required Departures: {:.1}",
         score,
         punishment_factor,
         nonlinearity,
         bad_turn_count,
+        steep_leg_count,
         avg_stop_dist,
+        required_departures,
     );
 
     (
@@ -542,43 +1829,112 @@ fn evaluate_route(
     )
 }
 
+/// Forecast the peak on-board load of a route
+///
+/// # Arguments
+/// - `zones`: Zones served by the route in stop order
+/// - `city`: City with the OD matrix
+///
+/// # Returns
+/// The largest load on any segment, in either direction and in any time period
+///
+/// # Notes
+/// - Riders board in the zone of their origin and stay on board until the zone of their
+///   destination, so the load of a segment is the demand between every zone pair spanning it
+/// - Links without period weights have their daily demand spread evenly over the periods
+fn peak_period_load(zones: &[NodeIndex], city: &City) -> f64 {
+    let mut peak: f64 = 0.0;
+    for period in TimePeriod::ALL {
+        let demand = |from: NodeIndex, to: NodeIndex| {
+            city.grid
+                .link_between_zones(from, to)
+                .map_or(0.0, |link| link.period_weight(&period))
+        };
+        // change in load at each zone, in the direction of travel
+        let mut outbound = vec![0.0; zones.len()];
+        let mut inbound = vec![0.0; zones.len()];
+        for i in 0..zones.len() {
+            for j in i + 1..zones.len() {
+                let d = demand(zones[i], zones[j]);
+                outbound[i] += d;
+                outbound[j] -= d;
+                let d = demand(zones[j], zones[i]);
+                inbound[i] += d;
+                inbound[j] -= d;
+            }
+        }
+        for changes in [outbound, inbound] {
+            let mut load = 0.0;
+            for change in changes {
+                load += change;
+                peak = peak.max(load);
+            }
+        }
+    }
+    peak
+}
+
+/// Heuristic score of the stop to stop edges computed so far in a search
+#[derive(Default)]
+struct HeuristicMap {
+    values: HashMap<(String, String), f64>,
+    /// Lookups answered from `values` and lookups that computed the score
+    hits: u64,
+    misses: u64,
+}
+
+/// What a search builds the routes of its ants from
+struct SearchSpace<'a> {
+    params: &'a ACO,
+    city: &'a City,
+    distances: &'a CorridorDistances,
+    /// Candidate stops that can be added to the route
+    stops: &'a [Arc<TransitStop>],
+    zone_to_zone_coverage: &'a HashMap<(u32, u32), u32>,
+    /// Area the changes are restricted to, see `SearchContext::area`
+    area: Option<&'a StudyArea>,
+}
+
+/// An ant building a route over a search space, with the pheromone of its generation
+struct Ant<'a> {
+    space: &'a SearchSpace<'a>,
+    pheromone_map: &'a PheromoneMap,
+    heuristic_map: &'a mut HeuristicMap,
+    rng: &'a mut StdRng,
+}
+
 // Compute the heuristic score for selecting a stop
 fn compute_heuristic(
     from: &TransitStop,
     to: &TransitStop,
     city: &City,
-    heuristic_map: &mut HashMap<(String, String), f64>,
+    distances: &CorridorDistances,
+    heuristic_map: &mut HeuristicMap,
     zone_to_zone_coverage: &HashMap<(u32, u32), u32>,
-    path_prev: &Vec<NodeIndex>,
+    leg_prev: &Leg,
 ) -> f64 {
-    if let Some(val) = heuristic_map.get(&(from.stop_id.clone(), to.stop_id.clone())) {
+    if let Some(val) = heuristic_map
+        .values
+        .get(&(from.stop_id.clone(), to.stop_id.clone()))
+    {
+        heuristic_map.hits += 1;
         return *val;
     }
-    let (road_dist, path_curr) = from.road_distance(to, &city.road);
-    // check if path_ij is a u-turn or large detour from path_pi
-    let (p0, p1) = (path_prev.get(path_prev.len() - 2), path_prev.last());
-    let (c0, c1) = (path_curr.first(), path_curr.get(1));
-    if let (Some(p0), Some(p1), Some(c0), Some(c1)) = (p0, p1, c0, c1) {
-        let (p0, p1) = (city.road.get_node(*p0).geom, city.road.get_node(*p1).geom);
-        let (c0, c1) = (city.road.get_node(*c0).geom, city.road.get_node(*c1).geom);
-        let diff = angle_diff(p0, p1, c0, c1);
-        if diff.abs() > 178.0 {
-            return 0.0;
-        }
-    }
-    let demand_ij =
-        city.grid
-            .demand_between_coords(from.geom.x(), from.geom.y(), to.geom.x(), to.geom.y());
-    let demand_ji =
-        city.grid
-            .demand_between_coords(to.geom.x(), to.geom.y(), from.geom.x(), from.geom.y());
-    let zone_i = from.zone(&city.grid);
-    let zone_j = to.zone(&city.grid);
-    if zone_i.is_none() || zone_j.is_none() {
+    heuristic_map.misses += 1;
+    let leg_curr = distances.leg(from, to, &city.road);
+    // a bus cannot turn back or make a restricted turn from leg_prev onto leg_curr
+    if !turn_allowed(leg_prev, &leg_curr, city) {
         return 0.0;
     }
-    let zone_i = zone_i.unwrap();
-    let zone_j = zone_j.unwrap();
+    let road_dist = leg_curr.meters;
+    let (idx_i, idx_j) = match (from.zone_index(&city.grid), to.zone_index(&city.grid)) {
+        (Some(i), Some(j)) => (i, j),
+        _ => return 0.0,
+    };
+    let demand_ij = city.grid.demand_between_zones(idx_i, idx_j);
+    let demand_ji = city.grid.demand_between_zones(idx_j, idx_i);
+    let zone_i = city.grid.get_zone(idx_i);
+    let zone_j = city.grid.get_zone(idx_j);
     let coverage_ij = *zone_to_zone_coverage
         .get(&(zone_i.zoneid, zone_j.zoneid))
         .unwrap_or(&1) as f64;
@@ -587,38 +1943,90 @@ fn compute_heuristic(
         .unwrap_or(&1) as f64;
     let h = (demand_ij + demand_ji + 0.01)
         / ((road_dist * 2.0) * (coverage_ij + coverage_ji + 1.0) + 0.01);
-    heuristic_map.insert((from.stop_id.clone(), to.stop_id.clone()), h);
+    heuristic_map
+        .values
+        .insert((from.stop_id.clone(), to.stop_id.clone()), h);
     h
 }
 
-fn adjust_route(
-    params: &ACO,
-    route: &TransitRoute,
-    city: &City,
-    pheromone_map: &PheromoneMap,
-    heuristic_map: &mut HashMap<(String, String), f64>,
-    stops: &Vec<Arc<TransitStop>>,
-    zone_to_zone_coverage: &HashMap<(u32, u32), u32>,
-    rng: &mut StdRng,
-) -> Option<TransitRoute> {
-    let first = route.outbound_stops.first().unwrap();
-    let last = route.outbound_stops.last().unwrap();
+fn adjust_route(ant: &mut Ant, route: &TransitRoute) -> Option<TransitRoute> {
+    let area = ant.space.area;
+    // the route is rebuilt between consecutive locked stops, the first and last stop, the
+    // stops outside the area and the stops of the route's constraint
+    let n = route.outbound_stops.len();
+    let constraint = constraint_of(&ant.space.params.constraints, route);
+    let locked: Vec<usize> = (0..n)
+        .filter(|&i| {
+            let stop = &route.outbound_stops[i];
+            i == 0
+                || i == n - 1
+                || area.is_some_and(|a| !a.contains(stop))
+                || constraint.is_some_and(|c| c.locks_stop(&stop.stop_id))
+        })
+        .collect();
 
+    let first = route.outbound_stops.first().unwrap();
     let mut new_stops = vec![first.clone()];
     let mut visited = HashSet::new(); // Use this visited list
     visited.insert(first.stop_id.clone());
+    for pair in locked.windows(2) {
+        let target = &route.outbound_stops[pair[1]];
+        let from = &route.outbound_stops[pair[0]];
+        let locked_segment =
+            constraint.is_some_and(|c| c.locks_segment(&from.stop_id, &target.stop_id));
+        if pair[1] == pair[0] + 1 && (area.is_some() || locked_segment) {
+            // no stop of the area between the locked stops or a locked segment, keep the
+            // segment as is
+            visited.insert(target.stop_id.clone());
+            new_stops.push(target.clone());
+            continue;
+        }
+        if !extend_route_to(ant, &mut new_stops, &mut visited, target, constraint) {
+            return None;
+        }
+    }
+
+    Some(TransitRoute {
+        route_id: route.route_id.clone(),
+        route_type: route.route_type.clone(),
+        outbound_stops: new_stops,
+        inbound_stops: vec![],
+        evals: None,
+        stop_times: HashMap::new(),
+        service_span: route.service_span.clone(),
+        vehicle: route.vehicle.clone(),
+    })
+}
+
+/// Extend a route being built by an ant with stops until it reaches `target`
+///
+/// # Arguments
+/// - `constraint`: Constraint of the route, its locked stops are only reached as targets
+///
+/// # Returns
+/// - `true` if the route reached `target`, which is its last stop
+fn extend_route_to(
+    ant: &mut Ant,
+    new_stops: &mut Vec<Arc<TransitStop>>,
+    visited: &mut HashSet<String>,
+    target: &Arc<TransitStop>,
+    constraint: Option<&RouteConstraint>,
+) -> bool {
+    let (params, city) = (ant.space.params, ant.space.city);
+    let start = new_stops.last().unwrap().clone();
+    let start_len = new_stops.len();
     let mut radius = params.max_stop_dist;
-    let max_radius = params.max_stop_dist * 3.0;
+    let max_radius = params.max_stop_dist * city.search.max_radius_factor;
     loop {
         if geo_util::haversine(
             new_stops.last().unwrap().geom.x(),
             new_stops.last().unwrap().geom.y(),
-            last.geom.x(),
-            last.geom.y(),
+            target.geom.x(),
+            target.geom.y(),
         ) < params.max_stop_dist
-            && rng.gen_bool(0.5)
+            && ant.rng.gen_bool(0.5)
         {
-            new_stops.push(last.clone());
+            new_stops.push(target.clone());
             break;
         }
         if new_stops.len() >= params.max_route_len {
@@ -626,13 +2034,13 @@ fn adjust_route(
             break;
         }
         let choices = valid_next_stops(
-            params,
+            ant.space,
             new_stops.last().unwrap(),
-            first,
-            last,
-            &stops,
+            &start,
+            target,
             radius,
-            new_stops.len(),
+            new_stops.len() - start_len + 1,
+            constraint,
         );
         // let choices = filter_stops_by_dir(params, new_stops.last().unwrap(), last, city, radius);
         if choices.is_empty() {
@@ -644,27 +2052,25 @@ fn adjust_route(
                     geo_util::haversine(
                         new_stops.last().unwrap().geom.x(),
                         new_stops.last().unwrap().geom.y(),
-                        last.geom.x(),
-                        last.geom.y()
+                        target.geom.x(),
+                        target.geom.y()
                     )
                 );
                 break;
             } else {
-                radius *= 2.0;
+                radius *= city.search.radius_growth;
                 continue;
             }
         }
         if let Some(next) = select_next_stop_from_choices(
-            params,
+            ant,
             new_stops.last().unwrap(),
-            new_stops.get(new_stops.len() - 2),
-            city,
-            pheromone_map,
-            heuristic_map,
+            new_stops
+                .len()
+                .checked_sub(2)
+                .and_then(|i| new_stops.get(i)),
             &choices,
-            &visited,
-            &zone_to_zone_coverage,
-            rng,
+            visited,
         ) {
             visited.insert(next.stop_id.clone());
             new_stops.push(next);
@@ -675,43 +2081,27 @@ fn adjust_route(
             if radius >= max_radius {
                 break;
             }
-            radius *= 2.0;
+            radius *= city.search.radius_growth;
             continue;
         }
     }
 
-    if new_stops.last().unwrap().stop_id != last.stop_id {
-        return None;
-    }
-
-    Some(TransitRoute {
-        route_id: route.route_id.clone(),
-        route_type: route.route_type.clone(),
-        outbound_stops: new_stops,
-        inbound_stops: vec![],
-        evals: None,
-        stop_times: HashMap::new(),
-    })
+    new_stops.last().unwrap().stop_id == target.stop_id
 }
 
 /// Stochastically select a stop based on ACO formula using heuristic and pheomone values
 fn select_next_stop_from_choices(
-    params: &ACO,
+    ant: &mut Ant,
     curr: &Arc<TransitStop>,
     prev: Option<&Arc<TransitStop>>,
-    city: &City,
-    pheromone_map: &PheromoneMap,
-    heuristic_map: &mut HashMap<(String, String), f64>,
     choices: &Vec<Arc<TransitStop>>,
     visited: &HashSet<String>,
-    zone_to_zone_coverage: &HashMap<(u32, u32), u32>,
-    rng: &mut StdRng,
 ) -> Option<Arc<TransitStop>> {
+    let (params, city, distances) = (ant.space.params, ant.space.city, ant.space.distances);
     // get the path from prev to curr, to determine if curr to stop (next) is good
-    let path = if let Some(prev) = prev {
-        prev.road_distance(curr, &city.road).1
-    } else {
-        vec![]
+    let leg = match prev {
+        Some(prev) => distances.leg(prev, curr, &city.road),
+        None => Leg::default(),
     };
     // compute probability of visiting each stop
     let mut weights = vec![];
@@ -731,9 +2121,10 @@ fn select_next_stop_from_choices(
             curr,
             stop,
             city,
-            heuristic_map,
-            &zone_to_zone_coverage,
-            &path,
+            distances,
+            ant.heuristic_map,
+            ant.space.zone_to_zone_coverage,
+            &leg,
         );
         if heuristic == 0.0 {
             // very low probability of selecting this stop
@@ -741,7 +2132,14 @@ fn select_next_stop_from_choices(
             continue;
         }
 
-        let pheromone = pheromone_map.get(&curr.stop_id, &stop.stop_id);
+        // prefer stops that can be served without building a shelter or pad
+        let heuristic = if city.stop_infra.has_infrastructure(&stop.stop_id) {
+            heuristic * (1.0 + params.infra_bonus)
+        } else {
+            heuristic
+        };
+
+        let pheromone = ant.pheromone_map.get(&curr.stop_id, &stop.stop_id);
         let weight = heuristic.powf(params.alpha) * pheromone.powf(params.beta);
         weights.push(weight);
     }
@@ -752,12 +2150,12 @@ fn select_next_stop_from_choices(
 
     // select the next stop
     let dist = WeightedIndex::new(&weights).unwrap();
-    let next = &choices[dist.sample(rng)];
+    let next = &choices[dist.sample(ant.rng)];
     Some(next.clone())
 }
 
-///
-fn filter_stops_by_route_bbox(
+/// Candidate stops within `padding_meters` of the bounding box of a route's outbound stops
+pub(crate) fn filter_stops_by_route_bbox(
     route: &TransitRoute,
     city: &City,
     padding_meters: f64,
@@ -799,69 +2197,62 @@ fn filter_stops_by_route_bbox(
         max_lat
     );
 
-    city.transit
-        .outbound_stops
-        .locate_in_envelope(&envelope)
-        .map(|s| s.stop.clone())
-        .collect::<Vec<_>>()
+    let stops = city
+        .transit
+        .stops_in_envelope(&envelope, city.search.include_inbound_stops);
+    if log::log_enabled!(log::Level::Debug) {
+        let outbound = city.transit.stops_in_envelope(&envelope, false).len();
+        log::debug!(
+            "{} candidate stops, {} only served inbound",
+            stops.len(),
+            stops.len() - outbound
+        );
+    }
+    stops
 }
 
-fn filter_zones_by_stops(
+pub(crate) fn filter_zones_by_stops(
     stops: &Vec<Arc<TransitStop>>,
     city: &City,
     opt_transit: &TransitNetwork,
 ) -> HashMap<(u32, u32), u32> {
-    let mut zone_to_zone_coverage = HashMap::new();
     let mut zones = vec![];
     for stop in stops {
-        let zone = stop.zone_index(&city.grid);
-        if let Some(zone) = zone {
+        if let Some(zone) = stop.zone_id() {
             if !zones.contains(&zone) {
                 zones.push(zone);
             }
         }
     }
-    for i in 0..zones.len() {
-        for j in i + 1..zones.len() {
-            for route in opt_transit.routes.iter() {
-                if route
-                    .outbound_stops
-                    .iter()
-                    .any(|stop| city.grid.get_zone(zones[i]).polygon.contains(&stop.geom))
-                    && route
-                        .outbound_stops
-                        .iter()
-                        .any(|stop| city.grid.get_zone(zones[j]).polygon.contains(&stop.geom))
-                {
-                    *zone_to_zone_coverage
-                        .entry((
-                            city.grid.get_zone(zones[i]).zoneid,
-                            city.grid.get_zone(zones[j]).zoneid,
-                        ))
-                        .or_insert(0) += 1;
-                }
-            }
-        }
-    }
-    zone_to_zone_coverage
+    city.zone_coverage_of(opt_transit)
+        .linking_routes(&zones, None)
 }
 
+/// Candidate stops an ant building a route from `first` to `last` can go to from `curr`
+///
+/// Locked stops of `constraint` other than `last` are left out: they keep their place in
+/// the route and must not be visited out of order.
 fn valid_next_stops(
-    params: &ACO,
+    space: &SearchSpace,
     curr: &Arc<TransitStop>,
     first: &Arc<TransitStop>,
     last: &Arc<TransitStop>,
-    stops: &Vec<Arc<TransitStop>>,
     radius: f64,
     stops_so_far: usize,
+    constraint: Option<&RouteConstraint>,
 ) -> Vec<Arc<TransitStop>> {
+    let params = space.params;
     let dist_fl = geo_util::haversine(first.geom.x(), first.geom.y(), last.geom.x(), last.geom.y());
     // Use the route-specific avg_stop_dist parameter for expected stops calculation
     let expected_stops =
         ((dist_fl / params.avg_stop_dist) * params.max_nonlinearity).ceil() as usize;
 
-    stops
+    space
+        .stops
         .iter()
+        .filter(|stop| {
+            stop.stop_id == last.stop_id || !constraint.is_some_and(|c| c.locks_stop(&stop.stop_id))
+        })
         .filter(|stop| {
             let dist =
                 geo_util::haversine(curr.geom.x(), curr.geom.y(), stop.geom.x(), stop.geom.y());
@@ -878,6 +2269,34 @@ fn valid_next_stops(
         .collect()
 }
 
+/// Whether a bus coming along `prev` may go on along `next` where the two paths meet, see
+/// `RoadNetwork::turn_allowed`. Legs without roads or that do not meet at a road node are
+/// allowed.
+fn turn_allowed(prev: &Leg, next: &Leg, city: &City) -> bool {
+    match (prev.end, next.start) {
+        (Some((p0, p1)), Some((c0, c1))) if p1 == c0 => city.road.turn_allowed(p0, p1, c1),
+        _ => true,
+    }
+}
+
+/// Change of bearing in degrees from the last road of one leg to the first road of the next,
+/// `None` if either leg has no road
+fn turn_between(prev: &Leg, next: &Leg, city: &City) -> Option<f64> {
+    let ((p0, p1), (c0, c1)) = (prev.end?, next.start?);
+    let (p0, p1) = (city.road.get_node(p0).geom, city.road.get_node(p1).geom);
+    let (c0, c1) = (city.road.get_node(c0).geom, city.road.get_node(c1).geom);
+    Some(angle_diff(p0, p1, c0, c1))
+}
+
+/// Average grade of a leg from its first road node to its last, as a fraction, `None` if the
+/// nodes have no elevation
+fn leg_grade(leg: &Leg, city: &City) -> Option<f64> {
+    let ((from, _), (_, to)) = (leg.start?, leg.end?);
+    let from = city.road.get_node(from).elevation?;
+    let to = city.road.get_node(to).elevation?;
+    (leg.meters > 0.0).then(|| (to - from).abs() as f64 / leg.meters)
+}
+
 /// Compute the angle difference between two bearings a->b and c->d
 /// Returns a value between -180 and 180
 fn angle_diff(
@@ -898,3 +2317,617 @@ fn angle_diff(
     assert!(-180.0 <= angle && angle <= 180.0);
     angle
 }
+
+/// Internals of the search exposed to the benchmarks in `benches/`, not part of the API
+#[doc(hidden)]
+#[allow(dead_code)] // the server binary compiles the modules too but never calls these
+pub mod bench {
+    use super::*;
+
+    /// Candidate stops, road distances and zone coverage a search of a route works with, built
+    /// once so that the benchmarks only time the functions called for each ant
+    pub struct SearchSpace {
+        pub params: ACO,
+        pub stops: Vec<Arc<TransitStop>>,
+        distances: CorridorDistances,
+        coverage: HashMap<(u32, u32), u32>,
+    }
+
+    impl SearchSpace {
+        pub fn new(route: &TransitRoute, city: &City) -> SearchSpace {
+            let params = calculate_route_specific_params(route, city, &ACO::init());
+            let stops = filter_stops_by_route_bbox(route, city, city.search.bbox_padding);
+            let coverage = filter_zones_by_stops(&stops, city, &city.transit);
+            let distances = CorridorDistances::build(&stops, &city.road);
+            SearchSpace {
+                params,
+                stops,
+                distances,
+                coverage,
+            }
+        }
+
+        /// Score of a route, see `evaluate_route`
+        pub fn evaluate_route(&self, route: &TransitRoute, city: &City) -> f64 {
+            evaluate_route(&self.params, route, city, &self.distances, &self.coverage).0
+        }
+
+        /// Heuristic of going on from `from` to `to` after arriving at `from` from `prev`,
+        /// computed as on the first lookup of a search
+        pub fn compute_heuristic(
+            &self,
+            prev: &TransitStop,
+            from: &TransitStop,
+            to: &TransitStop,
+            city: &City,
+        ) -> f64 {
+            let leg_prev = self.distances.leg(prev, from, &city.road);
+            compute_heuristic(
+                from,
+                to,
+                city,
+                &self.distances,
+                &mut HeuristicMap::default(),
+                &self.coverage,
+                &leg_prev,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::seq::SliceRandom;
+    use std::time::Instant;
+
+    /// The previous implementation of `PheromoneMap` decaying every entry each generation
+    struct EagerPheromoneMap {
+        pheromone: HashMap<(String, String), f64>,
+        aco: Arc<ACO>,
+    }
+
+    impl EagerPheromoneMap {
+        fn get(&self, from: &str, to: &str) -> f64 {
+            match self.pheromone.get(&(from.to_string(), to.to_string())) {
+                Some(&val) => val,
+                None => self.aco.init_pheromone,
+            }
+        }
+
+        fn update(&mut self, from: &str, to: &str, f: impl Fn(f64) -> f64) -> f64 {
+            let pheromone = self
+                .pheromone
+                .entry((from.to_string(), to.to_string()))
+                .or_insert(self.aco.init_pheromone);
+            *pheromone = f(*pheromone)
+                .max(self.aco.pheromone_min)
+                .min(self.aco.pheromone_max);
+            *pheromone
+        }
+
+        fn decay(&mut self) {
+            for (_, val) in self.pheromone.iter_mut() {
+                *val *= 1.0 - self.aco.rho;
+            }
+        }
+    }
+
+    /// Run the same random sequence of updates against both maps, calling `check` with
+    /// both after every generation
+    fn run_both(
+        num_stops: usize,
+        generations: usize,
+        updates_per_gen: usize,
+        mut check: impl FnMut(&PheromoneMap, &EagerPheromoneMap, &[String]),
+    ) -> (PheromoneMap, EagerPheromoneMap) {
+        let aco = Arc::new(ACO::init());
+        let mut lazy = PheromoneMap::new(aco.clone());
+        let mut eager = EagerPheromoneMap {
+            pheromone: HashMap::new(),
+            aco,
+        };
+        let stops: Vec<String> = (0..num_stops).map(|i| format!("stop{}", i)).collect();
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..generations {
+            lazy.decay();
+            eager.decay();
+            for _ in 0..updates_per_gen {
+                let from = stops.choose(&mut rng).unwrap();
+                let to = stops.choose(&mut rng).unwrap();
+                let score = rng.gen_range(0.0..40.0);
+                let a = lazy.update(from, to, |x| x + score);
+                let b = eager.update(from, to, |x| x + score);
+                assert!((a - b).abs() <= 1e-9 * b.abs().max(1.0));
+            }
+            check(&lazy, &eager, &stops);
+        }
+        (lazy, eager)
+    }
+
+    #[test]
+    fn lazy_decay_matches_eager_decay() {
+        run_both(30, 60, 40, |lazy, eager, stops| {
+            for from in stops {
+                for to in stops {
+                    let (a, b) = (lazy.get(from, to), eager.get(from, to));
+                    assert!(
+                        (a - b).abs() <= 1e-9 * b.abs().max(1.0),
+                        "{} -> {}: lazy {} != eager {}",
+                        from,
+                        to,
+                        a,
+                        b
+                    );
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn decay_below_minimum_is_not_clamped_until_updated() {
+        let aco = Arc::new(ACO::init());
+        let mut map = PheromoneMap::new(aco.clone());
+        map.update("a", "b", |_| aco.pheromone_min);
+        for _ in 0..5 {
+            map.decay();
+        }
+        let expected = aco.pheromone_min * (1.0 - aco.rho).powi(5);
+        assert!((map.get("a", "b") - expected).abs() < 1e-12);
+        assert_eq!(map.update("a", "b", |x| x), aco.pheromone_min);
+    }
+
+    /// Compare the time spent per generation by both implementations on a large corridor.
+    /// Run with `cargo test --release pheromone_decay_benchmark -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn pheromone_decay_benchmark() {
+        let (mut lazy, mut eager) = run_both(2000, 50, 2000, |_, _, _| {});
+        let generations = 200;
+
+        let start = Instant::now();
+        for _ in 0..generations {
+            eager.decay();
+            eager.update("stop0", "stop1", |x| x + 1.0);
+        }
+        let eager_time = start.elapsed();
+
+        let start = Instant::now();
+        for _ in 0..generations {
+            lazy.decay();
+            lazy.update("stop0", "stop1", |x| x + 1.0);
+        }
+        let lazy_time = start.elapsed();
+
+        println!(
+            "{} edges, {} generations: eager {:?}, lazy {:?}",
+            eager.pheromone.len(),
+            generations,
+            eager_time,
+            lazy_time
+        );
+    }
+
+    #[test]
+    fn coverage_refresh_interval_must_be_positive() {
+        let parse = |value| serde_json::from_value::<CoverageMode>(value);
+        assert_eq!(
+            parse(serde_json::json!({ "refresh_every": 3 })).unwrap(),
+            CoverageMode::RefreshEvery(3)
+        );
+        assert_eq!(
+            parse(serde_json::json!("frozen")).unwrap(),
+            CoverageMode::Frozen
+        );
+        assert!(parse(serde_json::json!({ "refresh_every": 0 })).is_err());
+        assert!(CoverageMode::refresh_every(0).is_err());
+    }
+
+    #[test]
+    fn area_keeps_stops_outside_of_it() {
+        use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+        let city = load_demo_city(
+            &format!("aco_area_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 3,
+                ..Default::default()
+            },
+        );
+
+        // the eastern half of the longest route
+        let route = city
+            .transit
+            .routes
+            .iter()
+            .max_by_key(|r| r.outbound_stops.len())
+            .unwrap();
+        let mut lons: Vec<f64> = route.outbound_stops.iter().map(|s| s.geom.x()).collect();
+        lons.sort_by(|a, b| ordering::cmp_f64(*a, *b));
+        let west = lons[lons.len() / 2] - 1e-9;
+        let area = StudyArea::from_geojson(&serde_json::json!({
+            "type": "Polygon",
+            "coordinates": [[[west, -90.0], [180.0, -90.0], [180.0, 90.0], [west, 90.0], [west, -90.0]]]
+        }))
+        .unwrap();
+        let outside = |stops: &[Arc<TransitStop>]| -> Vec<String> {
+            stops
+                .iter()
+                .filter(|s| !area.contains(s))
+                .map(|s| s.stop_id.clone())
+                .collect()
+        };
+        assert!(!outside(&route.outbound_stops).is_empty());
+
+        let params = calculate_route_specific_params(route, &city, &ACO::init());
+        let pheromone_map = PheromoneMap::new(Arc::new(params.clone()));
+        let mut stops = filter_stops_by_route_bbox(route, &city, city.search.bbox_padding);
+        let coverage = filter_zones_by_stops(&stops, &city, &city.transit);
+        let distances = CorridorDistances::build(&stops, &city.road);
+        stops.retain(|s| area.contains(s));
+        let space = SearchSpace {
+            params: &params,
+            city: &city,
+            distances: &distances,
+            stops: &stops,
+            zone_to_zone_coverage: &coverage,
+            area: Some(&area),
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut built = 0;
+        for _ in 0..50 {
+            let mut ant = Ant {
+                space: &space,
+                pheromone_map: &pheromone_map,
+                heuristic_map: &mut HeuristicMap::default(),
+                rng: &mut rng,
+            };
+            let Some(new_route) = adjust_route(&mut ant, route) else {
+                continue;
+            };
+            built += 1;
+            assert_eq!(
+                outside(&new_route.outbound_stops),
+                outside(&route.outbound_stops)
+            );
+        }
+        assert!(built > 0);
+
+        let (best, _) = local_search(
+            &space,
+            route.clone(),
+            f64::NEG_INFINITY,
+            &mut EvalCache::new(),
+        );
+        assert_eq!(
+            outside(&best.outbound_stops),
+            outside(&route.outbound_stops)
+        );
+    }
+
+    #[test]
+    fn chunks_split_at_transfer_stops() {
+        // too short to split
+        assert_eq!(chunk_boundaries(&[0; 50], 40), vec![0, 49]);
+
+        // no transfers, chunks of chunk_len stops
+        assert_eq!(chunk_boundaries(&[0; 121], 40), vec![0, 40, 80, 120]);
+
+        // the transfer stop with the most routes within reach is preferred
+        let mut transfers = vec![0; 121];
+        transfers[30] = 2;
+        transfers[45] = 3;
+        transfers[100] = 1;
+        assert_eq!(chunk_boundaries(&transfers, 40), vec![0, 45, 100, 120]);
+
+        // the last chunk is never shorter than half of chunk_len
+        let mut transfers = vec![0; 121];
+        transfers[110] = 5;
+        let boundaries = chunk_boundaries(&transfers, 40);
+        assert!(boundaries.windows(2).all(|w| w[1] - w[0] >= 20));
+        assert_eq!(boundaries.last(), Some(&120));
+    }
+
+    #[test]
+    fn batch_returns_pareto_frontier_of_each_route() {
+        use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+        let city = load_demo_city(
+            &format!("aco_pareto_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 4,
+                rows: 4,
+                routes: 2,
+                ..Default::default()
+            },
+        );
+
+        let mut params = ACO::init();
+        params.max_gen = 3;
+        params.num_ant = 5;
+        params.pareto_size = 4;
+        let routes: Vec<&TransitRoute> = city.transit.routes.iter().collect();
+        let mut transit = city.transit.clone();
+        let result = run_aco_batch(
+            params,
+            &routes,
+            &city,
+            &mut transit,
+            BatchOptions::default(),
+        );
+
+        assert_eq!(result.frontiers.len(), routes.len());
+        for frontier in result.frontiers.values() {
+            assert!(!frontier.is_empty() && frontier.len() <= 4);
+            for a in frontier {
+                assert!(frontier
+                    .iter()
+                    .all(|b| !b.trade_off.dominates(&a.trade_off)));
+            }
+            assert!(frontier
+                .windows(2)
+                .all(|w| w[0].trade_off.road_km <= w[1].trade_off.road_km));
+        }
+    }
+
+    #[test]
+    fn resumed_network_matches_an_uninterrupted_run() {
+        use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+        let city = load_demo_city(
+            &format!("aco_checkpoint_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 3,
+                ..Default::default()
+            },
+        );
+
+        let params = ACO::init();
+        let stops = |network: &OptimizedTransitNetwork| -> BTreeMap<String, Vec<String>> {
+            network
+                .network
+                .routes
+                .iter()
+                .map(|r| {
+                    let ids = r.outbound_stops.iter().map(|s| s.stop_id.clone());
+                    (r.route_id.clone(), ids.collect())
+                })
+                .collect()
+        };
+
+        // checkpoints go through bincode as they would through the city cache
+        let mut saved = vec![];
+        let full = run_aco_network(
+            params.clone(),
+            &city,
+            &city.transit,
+            CoverageMode::Live,
+            None,
+            Some(Checkpointing {
+                every: 1,
+                resume: None,
+                save: &mut |checkpoint| saved.push(bincode::serialize(checkpoint).unwrap()),
+            }),
+        )
+        .unwrap();
+        assert_eq!(saved.len(), city.transit.routes.len());
+        assert!(!full.optimized_routes.is_empty());
+
+        let checkpoint: Checkpoint = bincode::deserialize(&saved[0]).unwrap();
+        assert_eq!(checkpoint.progress, 1);
+        assert_eq!(checkpoint.pheromones.len(), 1);
+        let mut resaved = 0;
+        let resumed = run_aco_network(
+            params.clone(),
+            &city,
+            &city.transit,
+            CoverageMode::Live,
+            None,
+            Some(Checkpointing {
+                every: 1,
+                resume: Some(checkpoint),
+                save: &mut |checkpoint| {
+                    resaved += 1;
+                    assert!(checkpoint.progress > 1);
+                },
+            }),
+        )
+        .unwrap();
+        assert_eq!(resaved, city.transit.routes.len() - 1);
+        assert_eq!(resumed.optimized_routes, full.optimized_routes);
+        assert_eq!(stops(&resumed), stops(&full));
+
+        // a checkpoint of another network cannot be resumed
+        let mut checkpoint: Checkpoint = bincode::deserialize(&saved[0]).unwrap();
+        checkpoint.route_ids.push("missing".to_string());
+        let resume = Checkpointing {
+            every: 1,
+            resume: Some(checkpoint),
+            save: &mut |_| {},
+        };
+        assert!(run_aco_network(
+            params,
+            &city,
+            &city.transit,
+            CoverageMode::Live,
+            None,
+            Some(resume)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn network_stays_within_the_operating_budget() {
+        use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+        let city = load_demo_city(
+            &format!("aco_budget_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 3,
+                ..Default::default()
+            },
+        );
+
+        let before = OperatingCost::for_network(&city.transit, &city.road);
+        let budget = OperatingBudget {
+            max_fleet: Some(before.fleet),
+            max_revenue_hours: Some(before.revenue_hours + 1e-6),
+            max_route_km: Some(before.route_km + 1e-6),
+        };
+        let optimized = run_aco_network(
+            ACO::init(),
+            &city,
+            &city.transit,
+            CoverageMode::Live,
+            Some(&budget),
+            None,
+        )
+        .unwrap();
+        let after = OperatingCost::for_network(&optimized.network, &city.road);
+        assert!(budget.within(&after), "{:?} over {:?}", after, budget);
+    }
+
+    #[test]
+    fn long_routes_are_optimized_in_chunks() {
+        use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+        let city = load_demo_city(
+            &format!("aco_chunks_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 3,
+                ..Default::default()
+            },
+        );
+
+        let route = city
+            .transit
+            .routes
+            .iter()
+            .max_by_key(|r| r.outbound_stops.len())
+            .unwrap();
+        let n = route.outbound_stops.len();
+        let mut params = ACO::init();
+        params.max_gen = 5;
+        params.num_ant = 5;
+        params.chunk_min_stops = 2;
+        params.chunk_len = (n / 3).max(2);
+
+        let mut chunks = vec![];
+        let (optimized, _) = run_aco_from_seed(
+            params,
+            route,
+            None,
+            &mut SearchContext {
+                city: &city,
+                opt_transit: &city.transit,
+                area: None,
+                deadline: None,
+                on_progress: &mut |event| {
+                    if let ProgressEvent::ChunkCompleted {
+                        chunk,
+                        chunks: total,
+                        first_stop_id,
+                        last_stop_id,
+                        ..
+                    } = event
+                    {
+                        chunks.push((chunk, total, first_stop_id, last_stop_id));
+                    }
+                },
+            },
+        );
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.last().unwrap().0, chunks.last().unwrap().1);
+        assert_eq!(chunks[0].2, route.outbound_stops[0].stop_id);
+        assert_eq!(
+            chunks.last().unwrap().3,
+            route.outbound_stops[n - 1].stop_id
+        );
+        assert!(chunks.windows(2).all(|w| w[0].3 == w[1].2));
+
+        if let Some((optimized, _)) = optimized {
+            // stitched at the chunk boundaries without repeating a stop
+            let stop_ids: Vec<&str> = optimized
+                .outbound_stops
+                .iter()
+                .map(|s| s.stop_id.as_str())
+                .collect();
+            let unique: HashSet<&str> = stop_ids.iter().copied().collect();
+            assert_eq!(unique.len(), stop_ids.len());
+            for (_, _, first_stop_id, _) in &chunks {
+                assert!(unique.contains(first_stop_id.as_str()));
+            }
+        }
+    }
+
+    #[test]
+    fn vehicle_profiles_change_route_evaluations() {
+        use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+        use crate::layers::vehicle::RouteVehicles;
+
+        let mut city = load_demo_city(
+            &format!("aco_vehicle_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 2,
+                ..Default::default()
+            },
+        );
+
+        let params = ACO::init();
+        let route = city.transit.routes[0].clone();
+        let stops = filter_stops_by_route_bbox(&route, &city, city.search.bbox_padding);
+        let coverage = filter_zones_by_stops(&stops, &city, &city.transit);
+        let distances = CorridorDistances::build(&stops, &city.road);
+        let punishment = |vehicle: Option<VehicleProfile>| {
+            let route = TransitRoute {
+                vehicle,
+                ..route.clone()
+            };
+            evaluate_route(&params, &route, &city, &distances, &coverage).1
+        };
+
+        // the defaults of the params apply to routes without a profile
+        let default = punishment(None);
+        assert_eq!(punishment(Some(params.default_vehicle())), default);
+        // stops far closer together than the vehicle's spacing are punished
+        let express = VehicleProfile {
+            stop_spacing: 5000.0,
+            ..params.default_vehicle()
+        };
+        assert!(punishment(Some(express)) > default);
+
+        let minibus = VehicleProfile::builtin()["minibus"].clone();
+        let vehicles = RouteVehicles::from([(route.route_id.clone(), minibus.clone())]);
+        let score = |city: &City| {
+            city.transit.routes[0]
+                .evals
+                .as_ref()
+                .unwrap()
+                .economic_score
+        };
+        let before = score(&city);
+        assert_eq!(
+            city.transit
+                .assign_vehicles(&vehicles, &city.grid, &city.search),
+            1
+        );
+        assert_eq!(city.transit.routes[0].vehicle, Some(minibus));
+        assert_eq!(city.transit.routes[0].capacity(), 25);
+        assert_ne!(score(&city), before);
+        assert_eq!(
+            city.transit
+                .assign_vehicles(&vehicles, &city.grid, &city.search),
+            0
+        );
+    }
+}
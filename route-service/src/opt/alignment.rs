@@ -0,0 +1,183 @@
+use geo::{Closest, ClosestPoint, Distance, Haversine, LineString, MultiLineString, Point};
+use std::sync::Arc;
+
+use crate::layers::{city::City, transit_network::TransitStop};
+
+/// Line a route follows in the source feed, which optimized versions of the route can be
+/// kept close to so that planners can run them on the same streets
+pub struct Alignment {
+    lines: MultiLineString<f64>,
+}
+
+impl Alignment {
+    /// Alignment of a route from the shapes of all its trips in the full feed, or from the
+    /// stops of the original route in both directions when the feed has no shape for it
+    ///
+    /// The slim feed of the city is used if the full feed cannot be read, it only has the
+    /// shapes of the representative trips.
+    ///
+    /// # Returns
+    /// `None` for routes that are not in the source feed, e.g. proposed ones
+    pub fn of_route(route_id: &str, city: &City) -> Option<Alignment> {
+        let gtfs = city.full_gtfs().unwrap_or_else(|e| {
+            log::warn!(
+                "Full GTFS of {} not available for the alignment of route {}: {}",
+                city.name,
+                route_id,
+                e
+            );
+            &city.gtfs
+        });
+        let mut lines: Vec<LineString<f64>> = vec![];
+        for trip in gtfs.trips.get(route_id).into_iter().flatten() {
+            let Some(shape) = trip.shape_id.as_ref().and_then(|id| gtfs.shapes.get(id)) else {
+                continue;
+            };
+            let mut points: Vec<_> = shape.iter().collect();
+            points.sort_by_key(|p| p.shape_pt_sequence);
+            lines.push(
+                points
+                    .iter()
+                    .map(|p| (p.shape_pt_lon, p.shape_pt_lat))
+                    .collect(),
+            );
+        }
+        if lines.is_empty() {
+            let route = city
+                .transit
+                .routes
+                .iter()
+                .find(|r| r.route_id == route_id)?;
+            for stops in [&route.outbound_stops, &route.inbound_stops] {
+                lines.push(stops.iter().map(|s| s.geom).collect());
+            }
+        }
+        lines.retain(|line| !line.0.is_empty());
+        (!lines.is_empty()).then(|| Alignment {
+            lines: MultiLineString::new(lines),
+        })
+    }
+
+    /// Distance in meters from a point to the nearest point of the alignment
+    ///
+    /// The nearest point is found in longitude and latitude, which is close enough over the
+    /// width of a corridor.
+    pub fn distance(&self, point: Point<f64>) -> f64 {
+        match self.lines.closest_point(&point) {
+            Closest::Intersection(p) | Closest::SinglePoint(p) => Haversine::distance(point, p),
+            Closest::Indeterminate => f64::INFINITY,
+        }
+    }
+}
+
+/// Drop the candidate stops farther than `max_deviation` meters from the alignment of a route
+///
+/// Stops are kept when `max_deviation` is 0 or the route has no alignment, see
+/// `Alignment::of_route`.
+pub fn retain_near_alignment(
+    stops: &mut Vec<Arc<TransitStop>>,
+    route_id: &str,
+    city: &City,
+    max_deviation: f64,
+) {
+    if max_deviation <= 0.0 {
+        return;
+    }
+    let Some(alignment) = Alignment::of_route(route_id, city) else {
+        return;
+    };
+    let before = stops.len();
+    stops.retain(|s| alignment.distance(s.geom) <= max_deviation);
+    log::debug!(
+        "{} of {} candidate stops within {}m of the alignment of route {}",
+        stops.len(),
+        before,
+        max_deviation,
+        route_id
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::structs::Shape;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+    use crate::opt::aco2;
+
+    #[test]
+    fn candidate_stops_stay_near_the_original_alignment() {
+        let city = load_demo_city(
+            &format!("alignment_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 2,
+                ..Default::default()
+            },
+        );
+
+        let route = &city.transit.routes[0];
+        let alignment = Alignment::of_route(&route.route_id, &city).unwrap();
+        for stop in &route.outbound_stops {
+            assert!(alignment.distance(stop.geom) < 50.0, "{}", stop.stop_id);
+        }
+        assert!(Alignment::of_route("proposed", &city).is_none());
+
+        let all = aco2::filter_stops_by_route_bbox(route, &city, 2000.0);
+        let mut near = all.clone();
+        retain_near_alignment(&mut near, &route.route_id, &city, 150.0);
+        assert!(near.len() < all.len());
+        assert!(near.iter().all(|s| alignment.distance(s.geom) <= 150.0));
+        let mut unchanged = all.clone();
+        retain_near_alignment(&mut unchanged, &route.route_id, &city, 0.0);
+        assert_eq!(unchanged.len(), all.len());
+    }
+
+    #[test]
+    fn alignment_follows_the_shapes_of_every_trip() {
+        let mut city = load_demo_city(
+            &format!("alignment_trips_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 6,
+                rows: 6,
+                routes: 1,
+                ..Default::default()
+            },
+        );
+        let route_id = city.transit.routes[0].route_id.clone();
+
+        // a short branch trip, which is not the representative trip kept in the slim feed
+        let mut full = city.gtfs.clone();
+        let mut branch = full.trips[&route_id][0].clone();
+        branch.trip_id = "branch".to_string();
+        branch.shape_id = Some("branch".to_string());
+        branch.stop_times.truncate(2);
+        let start = city.transit.routes[0].outbound_stops[0].geom;
+        let end = Point::new(start.x(), start.y() + 0.05);
+        full.shapes.insert(
+            "branch".to_string(),
+            [start, end]
+                .iter()
+                .enumerate()
+                .map(|(i, p)| Shape {
+                    shape_id: "branch".to_string(),
+                    shape_pt_lat: p.y(),
+                    shape_pt_lon: p.x(),
+                    shape_pt_sequence: i as i32,
+                    shape_dist_traveled: None,
+                })
+                .collect(),
+        );
+        full.trips.get_mut(&route_id).unwrap().push(branch);
+        let report = city.import_report.clone();
+        let gtfs_path = city.data_info().gtfs.path;
+        city.replace_gtfs(full, report, &gtfs_path).unwrap();
+        assert!(city.zone_coverage.is_current(&city.transit));
+        assert!(city.gtfs.trips[&route_id]
+            .iter()
+            .all(|t| t.trip_id != "branch"));
+
+        let alignment = Alignment::of_route(&route_id, &city).unwrap();
+        assert!(alignment.distance(end) < 1.0);
+    }
+}
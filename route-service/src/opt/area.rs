@@ -0,0 +1,235 @@
+use geo::{Centroid, Contains};
+use geo_types::{Coord, LineString, MultiPolygon, Point, Polygon};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+use crate::layers::{
+    geo_util,
+    grid::GridNetwork,
+    transit_network::{TransitNetwork, TransitRoute, TransitStop},
+};
+
+/// A district of the city that an optimization is restricted to
+///
+/// Stops outside the area are locked: optimized routes keep them in the same order, and only
+/// stops inside the area can be added to a route.
+pub struct StudyArea {
+    pub polygons: MultiPolygon<f64>,
+}
+
+impl StudyArea {
+    /// Read the area from a GeoJSON `Polygon` or `MultiPolygon` geometry, or a `Feature`
+    /// with one of them as its geometry
+    pub fn from_geojson(value: &Value) -> Result<StudyArea, String> {
+        let polygons = match value["type"].as_str() {
+            Some("Feature") => return StudyArea::from_geojson(&value["geometry"]),
+            Some("Polygon") => vec![parse_polygon(&value["coordinates"])?],
+            Some("MultiPolygon") => value["coordinates"]
+                .as_array()
+                .ok_or("MultiPolygon coordinates must be an array")?
+                .iter()
+                .map(parse_polygon)
+                .collect::<Result<_, _>>()?,
+            other => {
+                return Err(format!(
+                    "Expected a Polygon, MultiPolygon or Feature, got {:?}",
+                    other
+                ))
+            }
+        };
+        Ok(StudyArea {
+            polygons: MultiPolygon::new(polygons),
+        })
+    }
+
+    pub fn contains(&self, stop: &TransitStop) -> bool {
+        self.polygons.contains(&stop.geom)
+    }
+
+    /// Share, from 0 to 1, of the outbound stops of a route inside the area
+    pub fn stop_share(&self, route: &TransitRoute) -> f64 {
+        if route.outbound_stops.is_empty() {
+            return 0.0;
+        }
+        let inside = route
+            .outbound_stops
+            .iter()
+            .filter(|s| self.contains(s))
+            .count();
+        inside as f64 / route.outbound_stops.len() as f64
+    }
+}
+
+/// A ring as an array of `[lon, lat]` positions
+fn parse_ring(value: &Value) -> Result<LineString<f64>, String> {
+    let positions = value
+        .as_array()
+        .ok_or("Ring must be an array of positions")?;
+    let coords = positions
+        .iter()
+        .map(|p| match (p[0].as_f64(), p[1].as_f64()) {
+            (Some(x), Some(y)) => Ok(Coord { x, y }),
+            _ => Err(format!("Invalid position {}", p)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if coords.len() < 4 {
+        return Err("Ring must have at least 4 positions".to_string());
+    }
+    Ok(LineString::new(coords))
+}
+
+/// A polygon as an exterior ring followed by its holes
+fn parse_polygon(value: &Value) -> Result<Polygon<f64>, String> {
+    let rings = value
+        .as_array()
+        .filter(|rings| !rings.is_empty())
+        .ok_or("Polygon coordinates must be a non-empty array of rings")?;
+    let exterior = parse_ring(&rings[0])?;
+    let interiors = rings[1..]
+        .iter()
+        .map(parse_ring)
+        .collect::<Result<_, _>>()?;
+    Ok(Polygon::new(exterior, interiors))
+}
+
+/// Transit service within a study area
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AreaMetrics {
+    /// Stops in the area served by any route
+    pub stops_served: usize,
+    /// Zones whose centroid is in the area
+    pub zones: usize,
+    /// Zones in the area within walking distance of a served stop
+    pub zones_served: usize,
+    pub population_served: u64,
+    pub jobs_served: u64,
+    /// Straight-line length in km of the stop to stop segments of all routes with both
+    /// stops in the area
+    pub route_km: f64,
+    /// Average ridership of the routes selected for the optimization
+    pub avg_ridership: f64,
+}
+
+impl AreaMetrics {
+    /// Measure the outbound service of a network within an area
+    ///
+    /// # Arguments
+    /// - `route_ids`: Routes whose ridership is averaged, routes without evals are ignored
+    pub fn new(
+        area: &StudyArea,
+        transit: &TransitNetwork,
+        grid: &GridNetwork,
+        route_ids: &[String],
+    ) -> AreaMetrics {
+        let mut served_stops = HashSet::new();
+        let mut near_zones = HashSet::new();
+        let mut route_km = 0.0;
+        for route in &transit.routes {
+            for stop in route.outbound_stops.iter().filter(|s| area.contains(s)) {
+                if served_stops.insert(stop.stop_id.as_str()) {
+                    near_zones.extend(stop.nearby_zone_ids().iter().copied());
+                }
+            }
+            route_km += route
+                .outbound_stops
+                .windows(2)
+                .filter(|w| area.contains(&w[0]) && area.contains(&w[1]))
+                .map(|w| {
+                    geo_util::haversine(w[0].geom.x(), w[0].geom.y(), w[1].geom.x(), w[1].geom.y())
+                        / 1000.0
+                })
+                .sum::<f64>();
+        }
+
+        let mut metrics = AreaMetrics {
+            stops_served: served_stops.len(),
+            route_km,
+            ..AreaMetrics::default()
+        };
+        for zone in grid.graph.node_indices().map(|i| grid.get_zone(i)) {
+            let in_area = zone
+                .polygon
+                .centroid()
+                .is_some_and(|c: Point<f64>| area.polygons.contains(&c));
+            if !in_area {
+                continue;
+            }
+            metrics.zones += 1;
+            if near_zones.contains(&zone.zoneid) {
+                metrics.zones_served += 1;
+                metrics.population_served += zone.population as u64;
+                metrics.jobs_served += zone.jobs as u64;
+            }
+        }
+
+        let ridership: Vec<f64> = transit
+            .routes
+            .iter()
+            .filter(|r| route_ids.contains(&r.route_id))
+            .filter_map(|r| r.evals.as_ref().map(|e| e.avg_ridership))
+            .collect();
+        if !ridership.is_empty() {
+            metrics.avg_ridership = ridership.iter().sum::<f64>() / ridership.len() as f64;
+        }
+        metrics
+    }
+
+    /// Change from `self` to `after`
+    pub fn delta(&self, after: &AreaMetrics) -> AreaMetricsDelta {
+        AreaMetricsDelta {
+            stops_served: after.stops_served as i64 - self.stops_served as i64,
+            zones_served: after.zones_served as i64 - self.zones_served as i64,
+            population_served: after.population_served as i64 - self.population_served as i64,
+            jobs_served: after.jobs_served as i64 - self.jobs_served as i64,
+            route_km: after.route_km - self.route_km,
+            avg_ridership: after.avg_ridership - self.avg_ridership,
+        }
+    }
+}
+
+/// Change of the metrics of a study area made by an optimization
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AreaMetricsDelta {
+    pub stops_served: i64,
+    pub zones_served: i64,
+    pub population_served: i64,
+    pub jobs_served: i64,
+    pub route_km: f64,
+    pub avg_ridership: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_geojson_polygons() {
+        let square = serde_json::json!({
+            "type": "Feature",
+            "properties": {},
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": [[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0], [0.0, 0.0]]]
+            }
+        });
+        let area = StudyArea::from_geojson(&square).unwrap();
+        assert!(area.polygons.contains(&Point::new(0.5, 0.5)));
+        assert!(!area.polygons.contains(&Point::new(1.5, 0.5)));
+
+        let multi = serde_json::json!({
+            "type": "MultiPolygon",
+            "coordinates": [
+                [[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 0.0]]],
+                [[[2.0, 0.0], [3.0, 0.0], [3.0, 1.0], [2.0, 0.0]]]
+            ]
+        });
+        assert_eq!(StudyArea::from_geojson(&multi).unwrap().polygons.0.len(), 2);
+
+        let point = serde_json::json!({ "type": "Point", "coordinates": [0.0, 0.0] });
+        assert!(StudyArea::from_geojson(&point).is_err());
+        let open =
+            serde_json::json!({ "type": "Polygon", "coordinates": [[[0.0, 0.0], [1.0, 0.0]]] });
+        assert!(StudyArea::from_geojson(&open).is_err());
+    }
+}
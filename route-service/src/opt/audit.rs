@@ -0,0 +1,143 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A call that changed the state of a city, kept in its audit log to follow who changed
+/// what and when
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEvent {
+    /// When the call finished in RFC 3339 format
+    pub at: String,
+    /// Identity of the caller given by the authenticating proxy, `anonymous` without one
+    pub user: String,
+    pub method: String,
+    /// Endpoint pattern the call matched, e.g. `/optimize-route/{route_id}`
+    pub endpoint: String,
+    pub path: String,
+    /// Hash of the path, query string and body of the call, see `ParamsHasher`
+    pub params_hash: String,
+    /// HTTP status of the response
+    pub status: u16,
+    /// Revision of the city state before the call
+    pub revision_before: u64,
+    /// Revision of the city state after the call, one more than `revision_before` if the
+    /// call succeeded
+    pub revision_after: u64,
+}
+
+/// 64-bit FNV-1a hash of the parameters of a call
+///
+/// Unlike `DefaultHasher`, the hash does not change between builds, so hashes of calls
+/// recorded by different versions of the service can be compared.
+pub struct ParamsHasher(u64);
+
+impl Default for ParamsHasher {
+    fn default() -> Self {
+        ParamsHasher::new()
+    }
+}
+
+impl ParamsHasher {
+    pub fn new() -> ParamsHasher {
+        ParamsHasher(0xcbf29ce484222325)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    /// The hash as 16 hex digits
+    pub fn finish(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+/// Filters of an audit log query, unset filters match every event
+#[derive(Default, Debug)]
+pub struct AuditFilter {
+    pub user: Option<String>,
+    /// Endpoint pattern or path of the call
+    pub endpoint: Option<String>,
+    /// Only events at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only events before this time
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        if self.user.as_ref().is_some_and(|user| *user != event.user) {
+            return false;
+        }
+        if self
+            .endpoint
+            .as_ref()
+            .is_some_and(|e| *e != event.endpoint && *e != event.path)
+        {
+            return false;
+        }
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        let Ok(at) = DateTime::parse_from_rfc3339(&event.at) else {
+            return false;
+        };
+        self.since.is_none_or(|since| at >= since) && self.until.is_none_or(|until| at < until)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(user: &str, at: &str) -> AuditEvent {
+        AuditEvent {
+            at: at.to_string(),
+            user: user.to_string(),
+            method: "POST".to_string(),
+            endpoint: "/accept-route/{route_id}".to_string(),
+            path: "/accept-route/12".to_string(),
+            params_hash: String::new(),
+            status: 200,
+            revision_before: 0,
+            revision_after: 1,
+        }
+    }
+
+    #[test]
+    fn params_hash_is_fnv1a() {
+        assert_eq!(ParamsHasher::new().finish(), "cbf29ce484222325");
+        let mut hasher = ParamsHasher::new();
+        hasher.update(b"foo");
+        assert_eq!(hasher.finish(), "dcb27518fed9d577");
+    }
+
+    #[test]
+    fn filter_matches_user_endpoint_and_time() {
+        let e = event("alice", "2025-03-01T12:00:00+00:00");
+        assert!(AuditFilter::default().matches(&e));
+        let by_endpoint = |endpoint: &str| AuditFilter {
+            endpoint: Some(endpoint.to_string()),
+            ..AuditFilter::default()
+        };
+        assert!(by_endpoint("/accept-route/{route_id}").matches(&e));
+        assert!(by_endpoint("/accept-route/12").matches(&e));
+        assert!(!by_endpoint("/reject-route/12").matches(&e));
+        let by_user = AuditFilter {
+            user: Some("bob".to_string()),
+            ..AuditFilter::default()
+        };
+        assert!(!by_user.matches(&e));
+
+        let time = |s: &str| Some(s.parse::<DateTime<Utc>>().unwrap());
+        let window = AuditFilter {
+            since: time("2025-03-01T12:00:00Z"),
+            until: time("2025-03-01T13:00:00Z"),
+            ..AuditFilter::default()
+        };
+        assert!(window.matches(&e));
+        assert!(!window.matches(&event("alice", "2025-03-01T13:00:00+00:00")));
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::layers::{
+    grid::TimePeriod,
+    road_network::RoadNetwork,
+    transit_network::{TransitNetwork, TransitRoute, TransitRouteType},
+};
+
+use super::frequency::{self, FrequencyParams};
+use super::timetable;
+
+/// Operating budget a network of bus routes must stay within, a limit left `None` is not
+/// enforced
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct OperatingBudget {
+    /// Buses in service at once in the busiest time period
+    pub max_fleet: Option<usize>,
+    /// Hours buses spend carrying riders over a day, layovers excluded
+    pub max_revenue_hours: Option<f64>,
+    /// Kilometers of road the routes run on, both directions counted
+    pub max_route_km: Option<f64>,
+}
+
+impl OperatingBudget {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_fleet == Some(0) {
+            return Err("max_fleet must be positive".to_string());
+        }
+        if self
+            .max_revenue_hours
+            .is_some_and(|h| !(h.is_finite() && h > 0.0))
+        {
+            return Err("max_revenue_hours must be positive".to_string());
+        }
+        if self
+            .max_route_km
+            .is_some_and(|km| !(km.is_finite() && km > 0.0))
+        {
+            return Err("max_route_km must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether no limit is set
+    pub fn is_unlimited(&self) -> bool {
+        self.max_fleet.is_none() && self.max_revenue_hours.is_none() && self.max_route_km.is_none()
+    }
+
+    /// Whether a network of this cost is within every limit
+    pub fn within(&self, cost: &OperatingCost) -> bool {
+        self.max_fleet.is_none_or(|max| cost.fleet <= max)
+            && self
+                .max_revenue_hours
+                .is_none_or(|max| cost.revenue_hours <= max)
+            && self.max_route_km.is_none_or(|max| cost.route_km <= max)
+    }
+
+    /// Whether a change of the network from `before` to `after` is allowed
+    ///
+    /// A change is allowed if every measure with a limit ends within it or at most at its
+    /// cost before, so a network already over budget can still trade its routes for cheaper
+    /// ones.
+    pub fn allows(&self, before: &OperatingCost, after: &OperatingCost) -> bool {
+        self.max_fleet
+            .is_none_or(|max| after.fleet <= max.max(before.fleet))
+            && self
+                .max_revenue_hours
+                .is_none_or(|max| after.revenue_hours <= max.max(before.revenue_hours))
+            && self
+                .max_route_km
+                .is_none_or(|max| after.route_km <= max.max(before.route_km))
+    }
+
+    /// Share of each limit a network of this cost uses
+    pub fn utilization(&self, cost: &OperatingCost) -> BudgetUtilization {
+        BudgetUtilization {
+            budget: *self,
+            cost: cost.clone(),
+            fleet: self.max_fleet.map(|max| cost.fleet as f64 / max as f64),
+            revenue_hours: self.max_revenue_hours.map(|max| cost.revenue_hours / max),
+            route_km: self.max_route_km.map(|max| cost.route_km / max),
+            within_budget: self.within(cost),
+        }
+    }
+}
+
+/// Cost of running a bus route through the day
+#[derive(Clone, Debug)]
+pub struct RouteCost {
+    /// Buses the departures of each time period keep in service at once
+    pub vehicles: BTreeMap<TimePeriod, usize>,
+    pub revenue_hours: f64,
+    pub route_km: f64,
+}
+
+impl RouteCost {
+    /// Estimate the cost of a route from its departures and road distances
+    ///
+    /// # Notes
+    /// - Run times are estimated as the timetable does, a bus waiting the default
+    ///   `FrequencyParams::layover_min` at each end before heading back
+    /// - Routes without an inbound direction run back along their outbound stops
+    pub fn new(route: &TransitRoute, road: &RoadNetwork) -> RouteCost {
+        let inbound = if route.inbound_stops.is_empty() {
+            &route.outbound_stops
+        } else {
+            &route.inbound_stops
+        };
+        let (mut run_min, mut meters) = (0.0, 0.0);
+        for stops in [&route.outbound_stops, inbound] {
+            let offsets = timetable::estimated_offsets(stops, road);
+            run_min += offsets.last().copied().unwrap_or(0) as f64 / 60.0;
+            meters += stops
+                .windows(2)
+                .map(|pair| pair[0].road_distance(&pair[1], road).0)
+                .sum::<f64>();
+        }
+        let cycle_min = run_min + 2.0 * FrequencyParams::default().layover_min;
+
+        let mut vehicles = BTreeMap::new();
+        let mut revenue_hours = 0.0;
+        for period in TimePeriod::ALL {
+            let (start, end) = period.local_bounds();
+            let period_min = (end - start) as f64 / 60.0;
+            let departures = frequency::departures_in(route, &period) as f64;
+            revenue_hours += departures * run_min / 60.0;
+            vehicles.insert(
+                period,
+                (cycle_min * departures / period_min).ceil() as usize,
+            );
+        }
+        RouteCost {
+            vehicles,
+            revenue_hours,
+            route_km: meters / 1000.0,
+        }
+    }
+}
+
+/// Cost of running the bus routes of a network
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct OperatingCost {
+    /// Buses in service at once in the busiest time period
+    pub fleet: usize,
+    pub revenue_hours: f64,
+    pub route_km: f64,
+}
+
+impl OperatingCost {
+    /// Cost of running routes side by side, sharing their fleet between time periods
+    pub fn total<'a>(routes: impl IntoIterator<Item = &'a RouteCost>) -> OperatingCost {
+        let mut vehicles: BTreeMap<&TimePeriod, usize> = BTreeMap::new();
+        let mut cost = OperatingCost::default();
+        for route in routes {
+            for (period, count) in &route.vehicles {
+                *vehicles.entry(period).or_default() += count;
+            }
+            cost.revenue_hours += route.revenue_hours;
+            cost.route_km += route.route_km;
+        }
+        cost.fleet = vehicles.into_values().max().unwrap_or(0);
+        cost
+    }
+
+    /// Cost of running the bus routes of a network
+    pub fn for_network(transit: &TransitNetwork, road: &RoadNetwork) -> OperatingCost {
+        let routes: Vec<RouteCost> = transit
+            .routes
+            .iter()
+            .filter(|r| r.route_type == TransitRouteType::Bus)
+            .map(|r| RouteCost::new(r, road))
+            .collect();
+        OperatingCost::total(&routes)
+    }
+}
+
+/// Cost of a network next to its budget
+#[derive(Clone, Debug, Serialize)]
+pub struct BudgetUtilization {
+    pub budget: OperatingBudget,
+    pub cost: OperatingCost,
+    /// Share of `max_fleet` used, above 1 when over budget, `None` without a limit
+    pub fleet: Option<f64>,
+    /// Share of `max_revenue_hours` used
+    pub revenue_hours: Option<f64>,
+    /// Share of `max_route_km` used
+    pub route_km: Option<f64>,
+    pub within_budget: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cost(fleet: usize, revenue_hours: f64, route_km: f64) -> OperatingCost {
+        OperatingCost {
+            fleet,
+            revenue_hours,
+            route_km,
+        }
+    }
+
+    #[test]
+    fn budgets_allow_changes_within_or_below_their_limits() {
+        let budget = OperatingBudget {
+            max_fleet: Some(10),
+            max_route_km: Some(100.0),
+            ..Default::default()
+        };
+        assert!(budget.validate().is_ok());
+        assert!(budget.within(&cost(10, 1e6, 100.0)));
+        assert!(!budget.within(&cost(11, 0.0, 100.0)));
+        assert!(budget.allows(&cost(5, 0.0, 50.0), &cost(10, 500.0, 100.0)));
+        assert!(!budget.allows(&cost(5, 0.0, 50.0), &cost(5, 0.0, 101.0)));
+        // over budget, a network may only get cheaper on the measures it is over
+        assert!(budget.allows(&cost(12, 0.0, 50.0), &cost(12, 0.0, 90.0)));
+        assert!(!budget.allows(&cost(12, 0.0, 50.0), &cost(13, 0.0, 50.0)));
+        assert!(OperatingBudget::default().allows(&cost(0, 0.0, 0.0), &cost(99, 99.0, 99.0)));
+
+        let utilization = budget.utilization(&cost(5, 20.0, 120.0));
+        assert_eq!(utilization.fleet, Some(0.5));
+        assert_eq!(utilization.revenue_hours, None);
+        assert_eq!(utilization.route_km, Some(1.2));
+        assert!(!utilization.within_budget);
+
+        let invalid = OperatingBudget {
+            max_revenue_hours: Some(-1.0),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn routes_share_the_fleet_between_periods() {
+        let route = |morning, evening| RouteCost {
+            vehicles: BTreeMap::from([
+                (TimePeriod::Morning, morning),
+                (TimePeriod::Evening, evening),
+            ]),
+            revenue_hours: 10.0,
+            route_km: 5.0,
+        };
+        let total = OperatingCost::total(&[route(4, 1), route(1, 2)]);
+        assert_eq!(total, cost(5, 20.0, 10.0));
+    }
+}
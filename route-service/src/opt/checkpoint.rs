@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::aco2::{OptimizedTransitNetwork, Pheromones};
+use super::audit::ParamsHasher;
+use super::progress::ParamChange;
+
+/// State of a long optimization saved to disk as it runs, so that it can be resumed after a
+/// crash instead of starting over
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// When the checkpoint was saved in RFC 3339 format
+    pub saved_at: String,
+    /// Routes of the optimization in the order they are optimized
+    pub route_ids: Vec<String>,
+    /// Steps done, routes of a batch or iterations of a live session
+    pub progress: usize,
+    /// The network as optimized so far
+    pub network: OptimizedTransitNetwork,
+    /// Pheromone at the end of the last ACO run of each route, for the routes it was kept for
+    pub pheromones: HashMap<String, Pheromones>,
+    /// State of the live session the checkpoint was saved by, `None` for a batch
+    pub live: Option<LiveProgress>,
+}
+
+/// What a live session needs besides the network to carry on where it stopped
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LiveProgress {
+    /// Whether each route of `Checkpoint::route_ids` converged
+    pub converged_routes: Vec<bool>,
+    /// Number of times each route of `Checkpoint::route_ids` was optimized
+    pub optimize_attempts: Vec<usize>,
+    /// Parameters changed while the session ran, replayed over the server's on resume
+    pub param_changes: Vec<ParamChange>,
+}
+
+/// When to save checkpoints of a batch and which one to resume from
+pub struct Checkpointing<'a> {
+    /// Routes optimized between two checkpoints
+    pub every: usize,
+    /// Checkpoint of an earlier run of the same optimization to carry on from
+    pub resume: Option<Checkpoint>,
+    /// Called with each checkpoint, e.g. to write it to disk
+    pub save: &'a mut dyn FnMut(&Checkpoint),
+}
+
+impl Checkpoint {
+    /// Name of the checkpoint of a live session optimizing the given routes, in order
+    ///
+    /// The name does not change between builds, so a restarted server finds the checkpoints
+    /// saved before it stopped.
+    pub fn live_name(route_ids: &[String]) -> String {
+        let mut hasher = ParamsHasher::new();
+        for route_id in route_ids {
+            hasher.update(route_id.as_bytes());
+            hasher.update(&[0]);
+        }
+        format!("live-{}", hasher.finish())
+    }
+
+    /// Whether a checkpoint is due after `progress` steps, when saving one every `every` steps
+    pub fn due(progress: usize, every: usize) -> bool {
+        every > 0 && progress > 0 && progress.is_multiple_of(every)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn live_names_depend_on_the_routes_and_their_order() {
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        let name = Checkpoint::live_name(&ids(&["1", "2"]));
+        assert!(name.starts_with("live-"));
+        assert_eq!(name, Checkpoint::live_name(&ids(&["1", "2"])));
+        assert_ne!(name, Checkpoint::live_name(&ids(&["2", "1"])));
+        assert_ne!(name, Checkpoint::live_name(&ids(&["12"])));
+
+        assert!(!Checkpoint::due(0, 5));
+        assert!(!Checkpoint::due(4, 5));
+        assert!(Checkpoint::due(10, 5));
+        assert!(!Checkpoint::due(10, 0));
+    }
+}
@@ -0,0 +1,428 @@
+use geo::{Distance, Haversine};
+use geo_types::Point;
+use rstar::RTree;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::layers::{
+    city::City,
+    road_network::RoadNetwork,
+    transit_network::{self, TransitNetwork, TransitRoute, TransitRouteType, TransitStop},
+};
+use crate::ordering;
+
+use super::eval::TransitRouteEvals;
+use super::inbound;
+
+/// Most metres between a stop and a stop of another route for riders to take either route
+const SHARED_STOP_DIST: f64 = 250.0;
+
+/// Which route pairs `propose_consolidations` looks for
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConsolidateParams {
+    /// Least share, from 0 to 1, of the outbound stops of the shorter route of a pair that
+    /// the longer route serves for the pair to be consolidated
+    pub min_overlap: f64,
+    /// Most pairs proposed, the most overlapping first
+    pub max_pairs: usize,
+}
+
+impl Default for ConsolidateParams {
+    fn default() -> Self {
+        ConsolidateParams {
+            min_overlap: 0.6,
+            max_pairs: 10,
+        }
+    }
+}
+
+impl ConsolidateParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.min_overlap > 0.0 && self.min_overlap <= 1.0) {
+            return Err("min_overlap must be between 0 and 1".to_string());
+        }
+        if self.max_pairs == 0 {
+            return Err("max_pairs must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// What happens to the shorter route of a pair
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Consolidation {
+    /// The longer route serves all its stops, it is removed
+    Remove,
+    /// Its stops the longer route does not serve extend the longer route, it is removed
+    Merge,
+    /// It is cut back to the stops the longer route does not serve
+    Truncate,
+}
+
+/// A pair of overlapping routes and how to consolidate them
+#[derive(Clone, Serialize)]
+pub struct ConsolidationProposal {
+    /// The longer route of the pair, which keeps running
+    pub kept_route_id: String,
+    /// The shorter route of the pair, which is removed or truncated
+    pub absorbed_route_id: String,
+    /// Share of the outbound stops of the absorbed route the kept route serves
+    pub stop_overlap: f64,
+    /// Share of the zones of the route with fewer zones the other route also has stops in
+    pub zone_overlap: f64,
+    pub action: Consolidation,
+    /// Outbound stops of each route replacing the pair
+    pub replacement_stop_ids: BTreeMap<String, Vec<String>>,
+    /// Evals of both routes of the pair in the network as is
+    pub before: BTreeMap<String, TransitRouteEvals>,
+    /// Evals of the replacement routes once they run instead of the pair
+    pub after: BTreeMap<String, TransitRouteEvals>,
+    /// Outbound road kilometres of the pair
+    pub road_km_before: f64,
+    /// Outbound road kilometres of the replacement routes
+    pub road_km_after: f64,
+}
+
+/// Find bus routes largely running along each other and propose how to consolidate them
+///
+/// # Parameters
+/// - `params`: Which pairs to look for
+/// - `city`: The city the routes run in
+/// - `transit`: Network the routes are taken from, which is left as is
+///
+/// # Returns
+/// The proposals, most overlapping pair first, each with the routes replacing the pair
+///
+/// # Notes
+/// - Pairs are found among routes with stops in a shared zone. A stop of the shorter route is
+///   served by the longer one if one of its stops is within `SHARED_STOP_DIST`.
+/// - A route is part of one proposal at most, so proposals can be applied together
+/// - The shorter route is merged into the longer one when the stops only it serves form a
+///   branch at one of its ends that meets the longer route near one of its ends, and is
+///   truncated to the longest run of such stops otherwise
+pub fn propose_consolidations(
+    params: &ConsolidateParams,
+    city: &City,
+    transit: &TransitNetwork,
+) -> Result<Vec<(ConsolidationProposal, Vec<TransitRoute>)>, String> {
+    params.validate()?;
+    let routes: Vec<&TransitRoute> = transit
+        .routes
+        .iter()
+        .filter(|r| r.route_type == TransitRouteType::Bus && r.outbound_stops.len() >= 2)
+        .collect();
+    let zones: Vec<HashSet<u32>> = routes
+        .iter()
+        .map(|r| transit_network::route_zone_ids(r))
+        .collect();
+    let trees: Vec<RTree<[f64; 2]>> = routes.iter().map(|r| stop_tree(r)).collect();
+
+    // routes with stops in a shared zone
+    let mut zone_routes: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, route_zones) in zones.iter().enumerate() {
+        for zone in route_zones {
+            zone_routes.entry(*zone).or_default().push(i);
+        }
+    }
+    let mut candidates: HashSet<(usize, usize)> = HashSet::new();
+    for indices in zone_routes.values() {
+        for (n, &a) in indices.iter().enumerate() {
+            for &b in &indices[n + 1..] {
+                candidates.insert((a.min(b), a.max(b)));
+            }
+        }
+    }
+
+    let mut pairs = vec![];
+    for (a, b) in candidates {
+        // the shorter route is the one absorbed, ties go by route id
+        let (kept, absorbed) = match routes[a]
+            .outbound_stops
+            .len()
+            .cmp(&routes[b].outbound_stops.len())
+            .then_with(|| routes[b].route_id.cmp(&routes[a].route_id))
+        {
+            std::cmp::Ordering::Less => (b, a),
+            _ => (a, b),
+        };
+        let served: Vec<bool> = routes[absorbed]
+            .outbound_stops
+            .iter()
+            .map(|stop| serves(&trees[kept], stop))
+            .collect();
+        let stop_overlap = served.iter().filter(|s| **s).count() as f64 / served.len() as f64;
+        if stop_overlap < params.min_overlap {
+            continue;
+        }
+        let shared_zones = zones[kept].intersection(&zones[absorbed]).count();
+        let zone_overlap =
+            shared_zones as f64 / zones[kept].len().min(zones[absorbed].len()).max(1) as f64;
+        pairs.push((kept, absorbed, served, stop_overlap, zone_overlap));
+    }
+    pairs.sort_by(|a, b| {
+        ordering::cmp_f64_desc(a.3, b.3)
+            .then_with(|| ordering::cmp_f64_desc(a.4, b.4))
+            .then_with(|| routes[a.1].route_id.cmp(&routes[b.1].route_id))
+            .then_with(|| routes[a.0].route_id.cmp(&routes[b.0].route_id))
+    });
+
+    let mut used = HashSet::new();
+    let mut proposals = vec![];
+    for (kept, absorbed, served, stop_overlap, zone_overlap) in pairs {
+        if proposals.len() >= params.max_pairs {
+            break;
+        }
+        if used.contains(&kept) || used.contains(&absorbed) {
+            continue;
+        }
+        used.insert(kept);
+        used.insert(absorbed);
+        let (kept, absorbed) = (routes[kept], routes[absorbed]);
+        let (action, replacements) = consolidate(kept, absorbed, &served, transit, &city.road);
+
+        let mut after_network = transit.clone();
+        after_network
+            .routes
+            .retain(|r| r.route_id != kept.route_id && r.route_id != absorbed.route_id);
+        after_network.routes.extend(replacements.iter().cloned());
+        let evals = |network: &TransitNetwork, routes: &[&TransitRoute]| {
+            routes
+                .iter()
+                .map(|r| {
+                    let evals = TransitRouteEvals::for_route(network, r, &city.grid, &city.search);
+                    (r.route_id.clone(), evals)
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+        let replacement_refs: Vec<&TransitRoute> = replacements.iter().collect();
+        let proposal = ConsolidationProposal {
+            kept_route_id: kept.route_id.clone(),
+            absorbed_route_id: absorbed.route_id.clone(),
+            stop_overlap,
+            zone_overlap,
+            action,
+            replacement_stop_ids: replacements
+                .iter()
+                .map(|r| {
+                    let stop_ids = r.outbound_stops.iter().map(|s| s.stop_id.clone());
+                    (r.route_id.clone(), stop_ids.collect())
+                })
+                .collect(),
+            before: evals(transit, &[kept, absorbed]),
+            after: evals(&after_network, &replacement_refs),
+            road_km_before: road_km(kept, &city.road) + road_km(absorbed, &city.road),
+            road_km_after: replacements.iter().map(|r| road_km(r, &city.road)).sum(),
+        };
+        proposals.push((proposal, replacements));
+    }
+    Ok(proposals)
+}
+
+/// Routes replacing a pair of overlapping routes
+///
+/// # Parameters
+/// - `served`: Whether `kept` serves each outbound stop of `absorbed`
+fn consolidate(
+    kept: &TransitRoute,
+    absorbed: &TransitRoute,
+    served: &[bool],
+    transit: &TransitNetwork,
+    road: &RoadNetwork,
+) -> (Consolidation, Vec<TransitRoute>) {
+    let runs = unique_runs(served);
+    let Some(&(start, end)) = runs.iter().max_by_key(|(start, end)| end - start) else {
+        return (Consolidation::Remove, vec![kept.clone()]);
+    };
+    let stops = &absorbed.outbound_stops;
+
+    // a branch at one end of the absorbed route, joining the kept route near one of its ends
+    if runs.len() == 1 && (start == 0 || end == stops.len() - 1) {
+        let (branch, junction) = if start == 0 {
+            (&stops[..=end], &stops[end + 1])
+        } else {
+            (&stops[start..], &stops[start - 1])
+        };
+        let nearest = nearest_stop_index(&kept.outbound_stops, junction);
+        let last = kept.outbound_stops.len() - 1;
+        if nearest <= 1 || nearest + 1 >= last {
+            let kept_ids: HashSet<&str> = kept
+                .outbound_stops
+                .iter()
+                .map(|s| s.stop_id.as_str())
+                .collect();
+            let mut branch: Vec<Arc<TransitStop>> = branch
+                .iter()
+                .filter(|s| !kept_ids.contains(s.stop_id.as_str()))
+                .cloned()
+                .collect();
+            // the branch runs away from the junction, off the end of the kept route it meets
+            if start == 0 {
+                branch.reverse();
+            }
+            let outbound_stops = if nearest + 1 >= last {
+                kept.outbound_stops.iter().cloned().chain(branch).collect()
+            } else {
+                branch.reverse();
+                branch
+                    .into_iter()
+                    .chain(kept.outbound_stops.iter().cloned())
+                    .collect()
+            };
+            let merged = replacement(kept, outbound_stops, transit, road);
+            return (Consolidation::Merge, vec![merged]);
+        }
+    }
+
+    // keep a stop shared with the kept route at each end, for riders to transfer
+    let truncated = stops[start.saturating_sub(1)..=(end + 1).min(stops.len() - 1)].to_vec();
+    if truncated.len() < 2 {
+        return (Consolidation::Remove, vec![kept.clone()]);
+    }
+    let truncated = replacement(absorbed, truncated, transit, road);
+    (Consolidation::Truncate, vec![kept.clone(), truncated])
+}
+
+/// A route running along new outbound stops, and back along them if it ran both ways
+fn replacement(
+    route: &TransitRoute,
+    outbound_stops: Vec<Arc<TransitStop>>,
+    transit: &TransitNetwork,
+    road: &RoadNetwork,
+) -> TransitRoute {
+    let inbound_stops = if route.inbound_stops.is_empty() {
+        vec![]
+    } else {
+        inbound::mirror_inbound(&outbound_stops, transit, road)
+    };
+    TransitRoute {
+        outbound_stops,
+        inbound_stops,
+        evals: None,
+        ..route.clone()
+    }
+}
+
+/// First and last index of each run of consecutive stops not served by the other route
+fn unique_runs(served: &[bool]) -> Vec<(usize, usize)> {
+    let mut runs = vec![];
+    let mut start = None;
+    for (i, served) in served.iter().enumerate() {
+        match (start, served) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                runs.push((s, i - 1));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        runs.push((s, served.len() - 1));
+    }
+    runs
+}
+
+/// Points of the stops of a route in both directions
+fn stop_tree(route: &TransitRoute) -> RTree<[f64; 2]> {
+    RTree::bulk_load(
+        route
+            .outbound_stops
+            .iter()
+            .chain(route.inbound_stops.iter())
+            .map(|s| [s.geom.x(), s.geom.y()])
+            .collect(),
+    )
+}
+
+fn serves(tree: &RTree<[f64; 2]>, stop: &TransitStop) -> bool {
+    tree.nearest_neighbor(&[stop.geom.x(), stop.geom.y()])
+        .is_some_and(|p| Haversine::distance(Point::new(p[0], p[1]), stop.geom) <= SHARED_STOP_DIST)
+}
+
+fn nearest_stop_index(stops: &[Arc<TransitStop>], to: &TransitStop) -> usize {
+    stops
+        .iter()
+        .enumerate()
+        .min_by(|a, b| {
+            ordering::cmp_f64(
+                Haversine::distance(a.1.geom, to.geom),
+                Haversine::distance(b.1.geom, to.geom),
+            )
+        })
+        .map_or(0, |(i, _)| i)
+}
+
+fn road_km(route: &TransitRoute, road: &RoadNetwork) -> f64 {
+    route
+        .outbound_stops
+        .windows(2)
+        .map(|w| w[0].road_distance(&w[1], road).0)
+        .sum::<f64>()
+        / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+    #[test]
+    fn removes_a_route_running_along_another() {
+        let city = load_demo_city(
+            &format!("consolidate_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 3,
+                ..Default::default()
+            },
+        );
+
+        // a short route over the middle of another one
+        let mut transit = city.transit.clone();
+        let long = &transit.routes[0];
+        let short = TransitRoute {
+            route_id: "short".to_string(),
+            outbound_stops: long.outbound_stops[1..4].to_vec(),
+            inbound_stops: vec![],
+            ..long.clone()
+        };
+        transit.routes.push(short);
+
+        let params = ConsolidateParams {
+            min_overlap: 1.0,
+            max_pairs: 10,
+        };
+        let proposals = propose_consolidations(&params, &city, &transit).unwrap();
+        let (proposal, replacements) = proposals
+            .iter()
+            .find(|(p, _)| p.absorbed_route_id == "short")
+            .unwrap();
+        assert_eq!(proposal.action, Consolidation::Remove);
+        assert_eq!(proposal.stop_overlap, 1.0);
+        assert!(proposal.road_km_after < proposal.road_km_before);
+        assert_eq!(proposal.before.len(), 2);
+        assert_eq!(replacements.len(), 1);
+        assert!(!proposal.after.contains_key("short"));
+
+        let invalid = ConsolidateParams {
+            min_overlap: 0.0,
+            ..params
+        };
+        assert!(propose_consolidations(&invalid, &city, &transit).is_err());
+    }
+
+    #[test]
+    fn runs_of_unserved_stops() {
+        assert_eq!(unique_runs(&[true, true]), vec![]);
+        assert_eq!(unique_runs(&[false, true, true]), vec![(0, 0)]);
+        assert_eq!(
+            unique_runs(&[true, false, false, true, false]),
+            vec![(1, 2), (4, 4)]
+        );
+        assert_eq!(unique_runs(&[false, false]), vec![(0, 1)]);
+    }
+}
@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::layers::transit_network::{TransitRoute, TransitStop};
+
+/// Stops and segments a route must keep through optimizations, e.g. a hospital, a transfer hub
+/// or the connection to a subway station
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RouteConstraint {
+    /// Stops the route must serve
+    #[serde(default)]
+    pub locked_stops: Vec<String>,
+    /// Pairs of stops the route must run between without stopping in between
+    #[serde(default)]
+    pub locked_segments: Vec<(String, String)>,
+}
+
+/// Constraints of each route, by route id
+pub type RouteConstraints = HashMap<String, RouteConstraint>;
+
+impl RouteConstraint {
+    pub fn is_empty(&self) -> bool {
+        self.locked_stops.is_empty() && self.locked_segments.is_empty()
+    }
+
+    /// Whether a stop must stay on the route, on its own or as the end of a locked segment
+    pub fn locks_stop(&self, stop_id: &str) -> bool {
+        self.locked_stops.iter().any(|s| s == stop_id)
+            || self
+                .locked_segments
+                .iter()
+                .any(|(from, to)| from == stop_id || to == stop_id)
+    }
+
+    /// Whether the route must run from `from` to `to` without stopping in between
+    pub fn locks_segment(&self, from: &str, to: &str) -> bool {
+        self.locked_segments
+            .iter()
+            .any(|(a, b)| a == from && b == to)
+    }
+
+    /// Check that outbound stops serve every locked stop and run every locked segment
+    ///
+    /// # Returns
+    /// The first locked stop or segment that the stops miss, as a message
+    pub fn check(&self, stops: &[Arc<TransitStop>]) -> Result<(), String> {
+        let stop_ids: Vec<&str> = stops.iter().map(|s| s.stop_id.as_str()).collect();
+        self.check_ids(&stop_ids)
+    }
+
+    fn check_ids(&self, stops: &[&str]) -> Result<(), String> {
+        let served = |stop_id: &str| stops.contains(&stop_id);
+        if let Some(stop_id) = self.locked_stops.iter().find(|s| !served(s)) {
+            return Err(format!("Stop {} is not served by the route", stop_id));
+        }
+        let runs = |from: &str, to: &str| stops.windows(2).any(|w| w[0] == from && w[1] == to);
+        if let Some((from, to)) = self.locked_segments.iter().find(|(a, b)| !runs(a, b)) {
+            return Err(format!(
+                "The route does not run from stop {} directly to stop {}",
+                from, to
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Constraint of a route, `None` if nothing of it is locked
+pub fn constraint_of<'a>(
+    constraints: &'a RouteConstraints,
+    route: &TransitRoute,
+) -> Option<&'a RouteConstraint> {
+    constraints
+        .get(&route.route_id)
+        .filter(|constraint| !constraint.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_must_keep_locked_stops_and_segments() {
+        let constraint = RouteConstraint {
+            locked_stops: vec!["hospital".to_string()],
+            locked_segments: vec![("hub".to_string(), "subway".to_string())],
+        };
+        assert!(constraint.locks_stop("hospital"));
+        assert!(constraint.locks_stop("subway"));
+        assert!(!constraint.locks_stop("a"));
+        assert!(constraint.locks_segment("hub", "subway"));
+        assert!(!constraint.locks_segment("subway", "hub"));
+
+        assert!(constraint
+            .check_ids(&["a", "hospital", "hub", "subway", "b"])
+            .is_ok());
+        assert!(constraint
+            .check_ids(&["a", "hub", "subway", "b"])
+            .unwrap_err()
+            .contains("hospital"));
+        assert!(constraint
+            .check_ids(&["hospital", "hub", "a", "subway"])
+            .is_err());
+    }
+}
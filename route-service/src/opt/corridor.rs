@@ -0,0 +1,185 @@
+use geo::{Distance, Haversine};
+use petgraph::graph::NodeIndex;
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use crate::layers::{road_network::RoadNetwork, transit_network::TransitStop};
+
+/// Marks a path with fewer than two road nodes
+const NO_NODE: u32 = u32::MAX;
+
+/// Road distance from one stop to the next with the road nodes its path turns through
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Leg {
+    pub meters: f64,
+    /// First two road nodes of the path, `None` if it has fewer than two
+    pub start: Option<(NodeIndex, NodeIndex)>,
+    /// Last two road nodes of the path, `None` if it has fewer than two
+    pub end: Option<(NodeIndex, NodeIndex)>,
+}
+
+impl Leg {
+    fn new(meters: f64, path: &[NodeIndex]) -> Leg {
+        let n = path.len();
+        Leg {
+            meters,
+            start: (n >= 2).then(|| (path[0], path[1])),
+            end: (n >= 2).then(|| (path[n - 2], path[n - 1])),
+        }
+    }
+}
+
+/// Road distances between every pair of candidate stops of a route, computed before a search
+/// so the ants and the local search look them up instead of searching the road network
+///
+/// Each row is filled by one search from its stop to all the others, see
+/// `RoadNetwork::paths_from`. Only the length and the ends of each path are kept, in flat
+/// arrays of `stops * stops` cells.
+#[derive(Default)]
+pub struct CorridorDistances {
+    /// Row and column of each stop
+    index: HashMap<String, usize>,
+    /// Road node of each stop, `NO_NODE` for stops off the road network
+    nodes: Vec<u32>,
+    /// Road distance in meters from the stop of the row to the stop of the column
+    meters: Vec<f32>,
+    /// Second and second to last road node of each path, `NO_NODE` if it has fewer than two
+    turns: Vec<(u32, u32)>,
+}
+
+impl CorridorDistances {
+    /// Compute the road distances between the given stops
+    ///
+    /// Distances are those of `TransitStop::road_distance`: the straight line distance when
+    /// either stop is off the road network and 0 when there is no road path between them.
+    pub fn build(stops: &[Arc<TransitStop>], road: &RoadNetwork) -> CorridorDistances {
+        let start = Instant::now();
+        let mut index = HashMap::new();
+        for stop in stops {
+            let next = index.len();
+            index.entry(stop.stop_id.clone()).or_insert(next);
+        }
+        let mut unique: Vec<&TransitStop> = vec![];
+        for stop in stops {
+            if index[&stop.stop_id] == unique.len() {
+                unique.push(stop);
+            }
+        }
+        let n = unique.len();
+        let nodes: Vec<Option<NodeIndex>> = unique.iter().map(|s| s.get_node_index(road)).collect();
+        let mut targets: Vec<NodeIndex> = nodes.iter().flatten().copied().collect();
+        targets.sort();
+        targets.dedup();
+
+        let mut meters = Vec::with_capacity(n * n);
+        let mut turns = Vec::with_capacity(n * n);
+        for (from, from_node) in unique.iter().zip(&nodes) {
+            let paths = from_node.map(|node| road.paths_from(node, &targets));
+            for (to, to_node) in unique.iter().zip(&nodes) {
+                let (distance, path) = match (&paths, to_node) {
+                    (Some(paths), Some(to_node)) => paths
+                        .get(to_node)
+                        .map_or((0.0, &[][..]), |(m, path)| (*m, &path[..])),
+                    _ => (Haversine::distance(from.geom, to.geom), &[][..]),
+                };
+                meters.push(distance as f32);
+                turns.push(match path.len() >= 2 {
+                    true => (path[1].index() as u32, path[path.len() - 2].index() as u32),
+                    false => (NO_NODE, NO_NODE),
+                });
+            }
+        }
+        log::debug!(
+            "Road distances between {} corridor stops computed in {}ms",
+            n,
+            start.elapsed().as_millis()
+        );
+        CorridorDistances {
+            index,
+            nodes: nodes
+                .iter()
+                .map(|n| n.map_or(NO_NODE, |n| n.index() as u32))
+                .collect(),
+            meters,
+            turns,
+        }
+    }
+
+    /// Road distance between two stops, searched on the road network for stops outside of
+    /// the corridor
+    pub fn leg(&self, from: &TransitStop, to: &TransitStop, road: &RoadNetwork) -> Leg {
+        let (Some(&i), Some(&j)) = (self.index.get(&from.stop_id), self.index.get(&to.stop_id))
+        else {
+            let (meters, path) = from.road_distance(to, road);
+            return Leg::new(meters, &path);
+        };
+        let cell = i * self.nodes.len() + j;
+        let node = |n: u32| NodeIndex::new(n as usize);
+        let (second, second_to_last) = self.turns[cell];
+        let has_path = second != NO_NODE;
+        Leg {
+            meters: self.meters[cell] as f64,
+            start: has_path.then(|| (node(self.nodes[i]), node(second))),
+            end: has_path.then(|| (node(second_to_last), node(self.nodes[j]))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+    use crate::opt::aco2;
+
+    #[test]
+    fn paths_with_fewer_than_two_nodes_have_no_turns() {
+        let (a, b) = (NodeIndex::new(3), NodeIndex::new(7));
+        for path in [vec![], vec![a]] {
+            let leg = Leg::new(12.0, &path);
+            assert_eq!((leg.start, leg.end), (None, None));
+            assert_eq!(leg.meters, 12.0);
+        }
+        let leg = Leg::new(12.0, &[a, b]);
+        assert_eq!((leg.start, leg.end), (Some((a, b)), Some((a, b))));
+    }
+
+    #[test]
+    fn corridor_distances_match_road_distances() {
+        let city = load_demo_city(
+            &format!("corridor_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 6,
+                rows: 6,
+                routes: 2,
+                ..Default::default()
+            },
+        );
+
+        let route = &city.transit.routes[0];
+        let stops = aco2::filter_stops_by_route_bbox(route, &city, city.search.bbox_padding);
+        let corridor = CorridorDistances::build(&stops, &city.road);
+        assert_eq!(corridor.meters.len(), stops.len() * stops.len());
+        for from in &stops {
+            for to in &stops {
+                let (meters, path) = from.road_distance(to, &city.road);
+                let leg = corridor.leg(from, to, &city.road);
+                assert!(
+                    (leg.meters - meters).abs() < 0.01,
+                    "{} -> {}: {} != {}",
+                    from.stop_id,
+                    to.stop_id,
+                    leg.meters,
+                    meters
+                );
+                // paths of the same cost may differ, but they start and end at the same nodes
+                let expected = Leg::new(meters, &path);
+                assert_eq!(leg.start.map(|s| s.0), expected.start.map(|s| s.0));
+                assert_eq!(leg.end.map(|e| e.1), expected.end.map(|e| e.1));
+            }
+        }
+        // stops outside of the corridor are searched on the road network
+        let empty = CorridorDistances::default();
+        let (a, b) = (&stops[0], &stops[stops.len() - 1]);
+        let (meters, path) = a.road_distance(b, &city.road);
+        assert_eq!(empty.leg(a, b, &city.road), Leg::new(meters, &path));
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::layers::{
+    transit_network::{TransitNetwork, TransitRoute},
+    zone_coverage::ZoneCoverage,
+};
+
+use super::accessibility;
+
+/// Parameters of the model splitting the demand between two zones over the routes serving
+/// both, stored per city next to the city cache.
+///
+/// Riders weigh each route by its generalized minutes, the minutes ridden between the zones
+/// times `in_vehicle_weight` plus half its headway times `wait_weight`, and a route carries
+/// the logit share `exp(-scale * minutes) / sum(exp(-scale * minutes_k))` of the demand.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DemandModelConfig {
+    // weight of a minute ridden between the zones
+    pub in_vehicle_weight: f64,
+    // weight of a minute waited for the route, riders mind waiting more than riding
+    pub wait_weight: f64,
+    // how strongly riders favour the quicker route per generalized minute, 0 splits the
+    // demand evenly like a count of the routes serving the zones
+    pub scale: f64,
+}
+
+impl Default for DemandModelConfig {
+    fn default() -> Self {
+        DemandModelConfig {
+            in_vehicle_weight: 1.0,
+            wait_weight: 2.0,
+            scale: 0.1,
+        }
+    }
+}
+
+// struct to support partial updates to the demand model
+#[derive(Clone, Deserialize)]
+pub struct PartialDemandModelConfig {
+    pub in_vehicle_weight: Option<f64>,
+    pub wait_weight: Option<f64>,
+    pub scale: Option<f64>,
+}
+
+impl DemandModelConfig {
+    /// Apply a partial update, leaving the config untouched if the result would be invalid
+    ///
+    /// # Arguments
+    /// - `partial`: Parameters to change
+    ///
+    /// # Returns
+    /// - A description of the first invalid parameter on error
+    pub fn update_from_partial(&mut self, partial: PartialDemandModelConfig) -> Result<(), String> {
+        let mut updated = self.clone();
+        if let Some(in_vehicle_weight) = partial.in_vehicle_weight {
+            updated.in_vehicle_weight = in_vehicle_weight;
+        }
+        if let Some(wait_weight) = partial.wait_weight {
+            updated.wait_weight = wait_weight;
+        }
+        if let Some(scale) = partial.scale {
+            updated.scale = scale;
+        }
+        updated.validate()?;
+        *self = updated;
+        Ok(())
+    }
+
+    /// Check that the parameters can be used to split the demand
+    pub fn validate(&self) -> Result<(), String> {
+        let params = [
+            ("in_vehicle_weight", self.in_vehicle_weight),
+            ("wait_weight", self.wait_weight),
+            ("scale", self.scale),
+        ];
+        for (name, value) in params {
+            if !value.is_finite() || value < 0.0 {
+                return Err(format!("{} must be at least 0, got {}", name, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Generalized minutes of riding a route for `ride_minutes` after waiting half its headway
+    fn generalized_minutes(&self, ride_minutes: f64, headway_minutes: f64) -> f64 {
+        self.in_vehicle_weight * ride_minutes + self.wait_weight * headway_minutes / 2.0
+    }
+
+    /// Logit share of the demand a route taking `minutes` carries against the other routes
+    fn share(&self, minutes: f64, other_minutes: impl IntoIterator<Item = f64>) -> f64 {
+        // exp(-scale * minutes) over the sum, divided through to keep the exponents small
+        let total: f64 = other_minutes
+            .into_iter()
+            .map(|other| (self.scale * (minutes - other)).exp())
+            .sum();
+        1.0 / (1.0 + total)
+    }
+}
+
+/// Share of the demand of each pair of zones a route carries against the other routes of the
+/// network serving the same pair
+///
+/// # Arguments
+/// - `transit`: Transit network with the competing routes and the demand model to split with
+/// - `route`: Route to evaluate, a route of `transit` with the same id does not compete with it
+///
+/// # Returns
+/// - Share by pair of zones of the outbound stops of the route, in both orders. Pairs no other
+///   route serves are left out, the route carries all of their demand.
+///
+/// # Notes
+/// - Ride minutes between two zones go from the first stop a route makes in one zone to the
+///   first stop it makes in the other, see `accessibility::ride_minutes`
+pub fn route_shares(transit: &TransitNetwork, route: &TransitRoute) -> HashMap<(u32, u32), f64> {
+    let model = &transit.demand_model;
+    let coverage = ZoneCoverage::for_network(transit);
+    let routes: HashMap<&str, &TransitRoute> = transit
+        .routes
+        .iter()
+        .map(|r| (r.route_id.as_str(), r))
+        .collect();
+    let (zones, minutes) = zone_ride_minutes(route);
+    let headway = accessibility::headway_minutes(route);
+    // ride minutes and headway of the competing routes, computed on first use
+    let mut competitors: HashMap<&str, (HashMap<u32, f64>, f64)> = HashMap::new();
+
+    let mut shares = HashMap::new();
+    for (i, u) in zones.iter().enumerate() {
+        for v in &zones[i + 1..] {
+            let others = coverage.shared_route_ids(*u, *v, Some(&route.route_id));
+            if others.is_empty() {
+                continue;
+            }
+            let other_minutes: Vec<f64> = others
+                .into_iter()
+                .filter_map(|id| {
+                    let (minutes, headway) = competitors.entry(id).or_insert_with(|| {
+                        let other = routes[id];
+                        (
+                            zone_ride_minutes(other).1,
+                            accessibility::headway_minutes(other),
+                        )
+                    });
+                    let ride = (minutes.get(v)? - minutes.get(u)?).abs();
+                    Some(model.generalized_minutes(ride, *headway))
+                })
+                .collect();
+            let ride = (minutes[v] - minutes[u]).abs();
+            let share = model.share(model.generalized_minutes(ride, headway), other_minutes);
+            shares.insert((*u, *v), share);
+            shares.insert((*v, *u), share);
+        }
+    }
+    shares
+}
+
+/// Zones of the outbound stops of a route in the order it reaches them, with the minutes
+/// ridden from the first stop to the first stop in each zone
+fn zone_ride_minutes(route: &TransitRoute) -> (Vec<u32>, HashMap<u32, f64>) {
+    let mut zones = vec![];
+    let mut minutes = HashMap::new();
+    let mut elapsed = 0.0;
+    for (i, stop) in route.outbound_stops.iter().enumerate() {
+        if i > 0 {
+            elapsed += accessibility::ride_minutes(&route.outbound_stops[i - 1], stop);
+        }
+        if let Some(zone) = stop.zone_id() {
+            minutes.entry(zone).or_insert_with(|| {
+                zones.push(zone);
+                elapsed
+            });
+        }
+    }
+    (zones, minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+    #[test]
+    fn quicker_routes_carry_more_of_the_demand() {
+        let model = DemandModelConfig::default();
+        let even = DemandModelConfig {
+            scale: 0.0,
+            ..model.clone()
+        };
+        assert_eq!(even.share(10.0, [30.0, 5.0]), 1.0 / 3.0);
+        assert!(model.share(10.0, [20.0]) > 0.5);
+        assert!(model.share(20.0, [10.0]) < 0.5);
+        assert!((model.share(10.0, [20.0]) + model.share(20.0, [10.0]) - 1.0).abs() < 1e-9);
+        assert!(model.generalized_minutes(10.0, 10.0) > model.generalized_minutes(10.0, 5.0));
+
+        let mut updated = model.clone();
+        let invalid = PartialDemandModelConfig {
+            in_vehicle_weight: None,
+            wait_weight: Some(-1.0),
+            scale: Some(0.5),
+        };
+        assert!(updated.update_from_partial(invalid).is_err());
+        assert_eq!(updated, model);
+    }
+
+    #[test]
+    fn frequent_routes_carry_more_of_a_shared_zone_pair() {
+        let mut transit = load_demo_city(
+            &format!("demand_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 3,
+                ..Default::default()
+            },
+        )
+        .transit;
+
+        // a copy of a route running twice as often competes for all of its zone pairs
+        let route = transit.routes[0].clone();
+        let mut frequent = route.clone();
+        frequent.route_id = format!("{}_frequent", route.route_id);
+        for departures in frequent.stop_times.values_mut() {
+            *departures *= 2;
+        }
+        transit.routes.push(frequent.clone());
+
+        let shares = route_shares(&transit, &route);
+        let frequent_shares = route_shares(&transit, &frequent);
+        assert!(!shares.is_empty());
+        for (pair, share) in &shares {
+            assert!(*share < 0.5);
+            assert!((share + frequent_shares[pair] - 1.0).abs() < 1e-9);
+        }
+
+        // without a preference for quicker routes the demand is split evenly
+        transit.demand_model.scale = 0.0;
+        for share in route_shares(&transit, &route).values() {
+            assert_eq!(*share, 0.5);
+        }
+    }
+}
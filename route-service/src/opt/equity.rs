@@ -0,0 +1,237 @@
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::layers::{grid::GridNetwork, transit_network::TransitNetwork};
+
+use super::eval;
+use crate::ordering;
+
+/// Groups of zones of about the same size the zones are split into by population
+const POPULATION_GROUPS: usize = 5;
+
+/// Service of the zones of one population group
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct PopulationGroup {
+    /// 1 for the least populated zones, up to `POPULATION_GROUPS` for the most populated
+    pub group: usize,
+    pub zones: usize,
+    pub population: u64,
+    /// Share of the population of the group living within walking distance of a stop
+    pub covered_share: f64,
+    /// Expected transfers of the trips of the group, weighted by population
+    pub avg_transfers: f64,
+    /// Daily seat-kilometers offered in the zones of the group per resident
+    pub seat_km_per_capita: f64,
+}
+
+/// Service of the people counted by a column of the zone table, assuming they are spread
+/// evenly over each zone
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct DemographicEquity {
+    pub column: String,
+    pub total: f64,
+    pub covered_share: f64,
+    pub avg_transfers: f64,
+    pub seat_km_per_capita: f64,
+}
+
+/// How evenly a transit network serves the residents of a city
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct EquityEvals {
+    pub total_population: u64,
+    /// Population of the zones within walking distance of a stop of the network
+    pub covered_population: u64,
+    pub covered_share: f64,
+    /// Gini coefficient of the seat-kilometers per resident across residents, 0 when everyone
+    /// gets the same service and 1 when a single zone gets all of it
+    pub service_gini: f64,
+    /// Zones with residents, split by population from the least to the most populated
+    pub by_population: Vec<PopulationGroup>,
+    pub demographic: Option<DemographicEquity>,
+}
+
+/// Service of a single zone
+struct ZoneService {
+    zone: NodeIndex,
+    population: f64,
+    covered: bool,
+    transfers: f64,
+    seat_km_per_capita: f64,
+}
+
+impl EquityEvals {
+    /// Evaluate the equity of a transit network
+    ///
+    /// # Arguments
+    /// - `transit`: Transit network data
+    /// - `od`: Origin-Destination matrix data
+    /// - `zone_to_transfers`: Expected transfers of the trips of each zone, see
+    ///   `eval::transfer_impedance`
+    /// - `demographic`: Name of a column of the zone table and its value for each zone id, to
+    ///   also evaluate the service of the people it counts
+    pub fn for_network(
+        transit: &TransitNetwork,
+        od: &GridNetwork,
+        zone_to_transfers: &HashMap<NodeIndex, f64>,
+        demographic: Option<(&str, &HashMap<u32, f64>)>,
+    ) -> EquityEvals {
+        let covered: HashSet<u32> = transit
+            .routes
+            .iter()
+            .flat_map(|r| r.outbound_stops.iter().chain(r.inbound_stops.iter()))
+            .flat_map(|s| s.nearby_zone_ids().iter().copied())
+            .collect();
+        let seat_km: HashMap<u32, f64> = eval::service_density(transit, od)
+            .into_iter()
+            .map(|d| (d.zoneid, d.seat_km_per_capita))
+            .collect();
+
+        let mut zones: Vec<ZoneService> = od
+            .graph
+            .node_indices()
+            .map(|ni| {
+                let zone = od.get_zone(ni);
+                ZoneService {
+                    zone: ni,
+                    population: zone.population as f64,
+                    covered: covered.contains(&zone.zoneid),
+                    transfers: zone_to_transfers.get(&ni).copied().unwrap_or(0.0),
+                    seat_km_per_capita: seat_km.get(&zone.zoneid).copied().unwrap_or(0.0),
+                }
+            })
+            .collect();
+        zones.sort_by(|a, b| {
+            ordering::cmp_f64(a.population, b.population).then(a.zone.cmp(&b.zone))
+        });
+
+        let total_population: f64 = zones.iter().map(|z| z.population).sum();
+        let covered_population: f64 = zones
+            .iter()
+            .filter(|z| z.covered)
+            .map(|z| z.population)
+            .sum();
+        let service_gini = gini(
+            &zones
+                .iter()
+                .map(|z| (z.seat_km_per_capita, z.population))
+                .collect::<Vec<_>>(),
+        );
+
+        let populated: Vec<&ZoneService> = zones.iter().filter(|z| z.population > 0.0).collect();
+        let groups = POPULATION_GROUPS.min(populated.len());
+        let by_population = (0..groups)
+            .map(|group| {
+                let start = group * populated.len() / groups;
+                let end = (group + 1) * populated.len() / groups;
+                let members = &populated[start..end];
+                let weights: Vec<f64> = members.iter().map(|z| z.population).collect();
+                let service = weighted_service(members, &weights);
+                PopulationGroup {
+                    group: group + 1,
+                    zones: members.len(),
+                    population: weights.iter().sum::<f64>() as u64,
+                    covered_share: service.0,
+                    avg_transfers: service.1,
+                    seat_km_per_capita: service.2,
+                }
+            })
+            .collect();
+
+        let demographic = demographic.map(|(column, values)| {
+            let members: Vec<&ZoneService> = zones.iter().collect();
+            let weights: Vec<f64> = members
+                .iter()
+                .map(|z| {
+                    let zoneid = od.get_zone(z.zone).zoneid;
+                    values.get(&zoneid).copied().unwrap_or(0.0).max(0.0)
+                })
+                .collect();
+            let (covered_share, avg_transfers, seat_km_per_capita) =
+                weighted_service(&members, &weights);
+            DemographicEquity {
+                column: column.to_string(),
+                total: weights.iter().sum(),
+                covered_share,
+                avg_transfers,
+                seat_km_per_capita,
+            }
+        });
+
+        EquityEvals {
+            total_population: total_population as u64,
+            covered_population: covered_population as u64,
+            covered_share: if total_population > 0.0 {
+                covered_population / total_population
+            } else {
+                0.0
+            },
+            service_gini,
+            by_population,
+            demographic,
+        }
+    }
+}
+
+/// Covered share, expected transfers and seat-kilometers per person of zones, weighting each
+/// zone by the people it counts
+fn weighted_service(zones: &[&ZoneService], weights: &[f64]) -> (f64, f64, f64) {
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 {
+        return (0.0, 0.0, 0.0);
+    }
+    let mean = |value: &dyn Fn(&ZoneService) -> f64| {
+        zones
+            .iter()
+            .zip(weights)
+            .map(|(z, w)| value(z) * w)
+            .sum::<f64>()
+            / total
+    };
+    (
+        mean(&|z| if z.covered { 1.0 } else { 0.0 }),
+        mean(&|z| z.transfers),
+        mean(&|z| z.seat_km_per_capita),
+    )
+}
+
+/// Gini coefficient of values each held by a weight of people
+///
+/// # Returns
+/// 0 when every person holds the same value or no one holds any
+fn gini(values: &[(f64, f64)]) -> f64 {
+    let mut values: Vec<(f64, f64)> = values.iter().filter(|(_, w)| *w > 0.0).copied().collect();
+    values.sort_by(|a, b| ordering::cmp_f64(a.0, b.0));
+    let weight: f64 = values.iter().map(|(_, w)| w).sum();
+    let held: f64 = values.iter().map(|(v, w)| v * w).sum();
+    if weight <= 0.0 || held <= 0.0 {
+        return 0.0;
+    }
+
+    // one minus twice the area under the Lorenz curve
+    let mut area = 0.0;
+    let mut cumulative = 0.0;
+    for (value, w) in values {
+        let next = cumulative + value * w;
+        area += w * (cumulative + next);
+        cumulative = next;
+    }
+    1.0 - area / (weight * held)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gini_measures_inequality_across_people() {
+        assert_eq!(gini(&[(2.0, 10.0), (2.0, 30.0)]), 0.0);
+        assert_eq!(gini(&[]), 0.0);
+        assert_eq!(gini(&[(0.0, 10.0)]), 0.0);
+        // one of four people holds everything
+        let g = gini(&[(0.0, 3.0), (4.0, 1.0)]);
+        assert!((g - 0.75).abs() < 1e-9, "{}", g);
+        // people without weight do not count
+        assert_eq!(gini(&[(1.0, 5.0), (100.0, 0.0)]), 0.0);
+    }
+}
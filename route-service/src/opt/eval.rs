@@ -1,28 +1,46 @@
 use core::f64;
-use std::{collections::HashMap, collections::HashSet, sync::Arc};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
 
-use geo::Contains;
+use geo::GeodesicArea;
 use petgraph::graph::NodeIndex;
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
 
 use crate::gtfs::gtfs::Gtfs;
 
 use crate::layers::{
     geo_util,
-    grid::GridNetwork,
+    grid::{GridNetwork, Link, TimePeriod},
     transit_network::{TransitNetwork, TransitRoute, TransitStop},
 };
 
-use super::consts::{self};
+use super::accessibility;
+use super::demand;
+use super::equity::EquityEvals;
+use super::search::SearchConfig;
+use crate::ordering;
 
 const ADJUSTMENT_FACTOR: f64 = 1.0;
 const DEFAULT_FREQUENCY: f64 = 10.0;
+/// Minutes a transfer costs a rider on top of the wait, for walking between stops and the
+/// inconvenience of changing vehicles
+pub const TRANSFER_PENALTY_MIN: f64 = 5.0;
+/// Transfers counted for trips the network cannot serve
+const UNREACHABLE_TRANSFERS: f64 = 5.0;
 
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct TransitNetworkEvals {
     pub avg_transfers: f64,
+    /// Expected minutes a trip waits at transfers
+    pub avg_transfer_wait: f64,
+    /// Expected minutes a trip loses to transfers, see `TransferImpedance`
+    pub avg_impedance: f64,
     pub zone_to_transfers: HashMap<NodeIndex, f64>,
+    /// Coverage and service of the residents by zone population
+    pub equity: EquityEvals,
 }
 
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
@@ -31,14 +49,20 @@ pub struct TransitRouteEvals {
     pub avg_ridership: f64,
     pub economic_score: f64,
     pub coverage: f64,
+    /// Peak on-board load over seat capacity for each time period
+    pub load_factor: BTreeMap<TimePeriod, f64>,
 }
 
 impl TransitNetworkEvals {
     pub fn for_network(transit: &TransitNetwork, od: &GridNetwork) -> TransitNetworkEvals {
-        let (avg_transfers, zone_to_transfers) = average_transfers(transit, od);
+        let impedance = transfer_impedance(transit, od);
+        let equity = EquityEvals::for_network(transit, od, &impedance.zone_to_transfers, None);
         TransitNetworkEvals {
-            avg_transfers,
-            zone_to_transfers,
+            avg_transfers: impedance.avg_transfers,
+            avg_transfer_wait: impedance.avg_transfer_wait,
+            avg_impedance: impedance.avg_impedance,
+            zone_to_transfers: impedance.zone_to_transfers,
+            equity,
         }
     }
 }
@@ -48,19 +72,89 @@ impl TransitRouteEvals {
         transit: &TransitNetwork,
         route: &TransitRoute,
         od: &GridNetwork,
+        search: &SearchConfig,
     ) -> TransitRouteEvals {
         let (ridership, avg_ridership) = ridership_over_route(transit, route, od);
-        let economic_score = evaluate_economic_score(route, od, transit);
-        let coverage = evaluate_coverage(&route.outbound_stops, od);
+        let economic_score = economic_score(route, od, &ridership);
+        let coverage = evaluate_coverage(&route.outbound_stops, od, search.coverage_radius);
+        let load_factor = load_factor_by_period(transit, route, od);
         TransitRouteEvals {
             ridership,
             avg_ridership,
             economic_score,
             coverage,
+            load_factor,
+        }
+    }
+}
+
+/// Evaluations by a key of what was evaluated, e.g. the `RouteKey` of a candidate route, so that
+/// a candidate built again, e.g. by another ant or by the local search, is only evaluated once
+///
+/// Evaluations only hold for the network and parameters they were computed with: a cache is
+/// made for one search, and a search against a changed network starts from an empty one.
+pub struct EvalCache<K, V> {
+    entries: HashMap<K, V>,
+    hits: usize,
+}
+
+impl<K: Hash + Eq, V: Clone> Default for EvalCache<K, V> {
+    fn default() -> Self {
+        EvalCache::new()
+    }
+}
+
+impl<K: Hash + Eq, V: Clone> EvalCache<K, V> {
+    pub fn new() -> EvalCache<K, V> {
+        EvalCache {
+            entries: HashMap::new(),
+            hits: 0,
+        }
+    }
+
+    /// Evaluation of `key`, computed by `evaluate` unless the key was evaluated before
+    pub fn get_or_insert_with(&mut self, key: K, evaluate: impl FnOnce() -> V) -> V {
+        if let Some(value) = self.entries.get(&key) {
+            self.hits += 1;
+            return value.clone();
         }
+        let value = evaluate();
+        self.entries.insert(key, value.clone());
+        value
+    }
+
+    /// Evaluations answered from the cache
+    pub fn hits(&self) -> usize {
+        self.hits
     }
 }
 
+/// Id of a route with the ids of its outbound and inbound stops, in order
+pub type RouteKey = (String, Vec<String>, Vec<String>);
+
+/// Key of a route in an `EvalCache`, two routes with the same key are evaluated the same
+pub fn route_key(route: &TransitRoute) -> RouteKey {
+    let stop_ids = |stops: &[Arc<TransitStop>]| stops.iter().map(|s| s.stop_id.clone()).collect();
+    (
+        route.route_id.clone(),
+        stop_ids(&route.outbound_stops),
+        stop_ids(&route.inbound_stops),
+    )
+}
+
+/// Hash of the id and stops of a route, in order in each direction
+pub fn route_hash(route: &TransitRoute) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    route.route_id.hash(&mut hasher);
+    for stops in [&route.outbound_stops, &route.inbound_stops] {
+        stops.len().hash(&mut hasher);
+        for stop in stops {
+            stop.stop_id.hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
 /// transit score is out of 100 and a combination of avg transfers, avg ridership, and coverage
 pub fn transit_score(
     avg_transfers: f64, // 0 - 3
@@ -101,14 +195,89 @@ pub fn avg_ridership(transit: &TransitNetwork, od: &GridNetwork) -> f64 {
 /// # Notes
 /// - Ridership is calculated by summing the demand between zones for all pairs of stops
 /// - Ridership is distributed equally over all stops in the same zone
-/// - TODO: Distribute ridership over other nearby routes if they service the same zone-to-zone demand
+/// - Demand served by other routes of the network too is split between them by the demand
+///   model of the network, see `demand::route_shares`
 pub fn ridership_over_route(
     transit: &TransitNetwork,
     route: &TransitRoute,
     od: &GridNetwork,
 ) -> (Vec<f64>, f64) {
-    // get other routes serving demand
-    let zone_to_zone_coverage = determine_routes_zone_to_zone_coverage(transit, od, route);
+    let shares = demand::route_shares(transit, route);
+    let ridership = ridership_profile(route, od, &shares, |link| link.weight);
+
+    let avg_ridership = ridership.iter().sum::<f64>() / ridership.len().max(1) as f64;
+
+    (ridership, avg_ridership)
+}
+
+/// Riders at an outbound stop of a route over the day
+#[derive(Clone, Debug, Serialize)]
+pub struct StopRidership {
+    pub stop_id: String,
+    pub lon: f64,
+    pub lat: f64,
+    pub boardings: f64,
+    pub alightings: f64,
+    /// Riders on board when the bus leaves the stop
+    pub load: f64,
+}
+
+/// Evaluate the riders boarding and alighting at each outbound stop of a route, the profile
+/// `ridership_over_route` sums up
+///
+/// # Arguments
+/// - `transit`: Transit network data, other routes serving a zone pair take a share of its
+///   demand
+/// - `route`: Route to evaluate
+/// - `od`: Origin-Destination matrix data
+pub fn ridership_by_stop(
+    transit: &TransitNetwork,
+    route: &TransitRoute,
+    od: &GridNetwork,
+) -> Vec<StopRidership> {
+    let shares = demand::route_shares(transit, route);
+    stop_activity(route, od, &shares, |link| link.weight)
+        .into_iter()
+        .zip(&route.outbound_stops)
+        .map(|((boardings, alightings, load), stop)| StopRidership {
+            stop_id: stop.stop_id.clone(),
+            lon: stop.geom.x(),
+            lat: stop.geom.y(),
+            boardings,
+            alightings,
+            load,
+        })
+        .collect()
+}
+
+/// Cumulative on-board load at each outbound stop of a route for a given demand
+///
+/// # Arguments
+/// - `route`: Route to evaluate
+/// - `od`: Origin-Destination matrix data
+/// - `shares`: Share of the demand of each zone pair the route carries, all of it for pairs
+///   left out
+/// - `demand`: Demand of an OD link to assign to the route
+fn ridership_profile(
+    route: &TransitRoute,
+    od: &GridNetwork,
+    shares: &HashMap<(u32, u32), f64>,
+    demand: impl Fn(&Link) -> f64,
+) -> Vec<f64> {
+    stop_activity(route, od, shares, demand)
+        .into_iter()
+        .map(|(_, _, load)| load)
+        .collect()
+}
+
+/// Riders boarding, alighting and on board at each outbound stop of a route for a given
+/// demand, see `ridership_profile`
+fn stop_activity(
+    route: &TransitRoute,
+    od: &GridNetwork,
+    shares: &HashMap<(u32, u32), f64>,
+    demand: impl Fn(&Link) -> f64,
+) -> Vec<(f64, f64, f64)> {
     let stops = &route.outbound_stops;
     let mut zones = vec![];
     let mut stop_to_zone = HashMap::new();
@@ -124,60 +293,126 @@ pub fn ridership_over_route(
         }
     }
     let mut zone_to_ridership = HashMap::new();
+    let mut zone_to_activity: HashMap<NodeIndex, (f64, f64)> = HashMap::new();
     for i in 0..zones.len() {
         // people getting off
         for j in 0..i {
             let (u, v) = (od.get_zone(zones[i]).zoneid, od.get_zone(zones[j]).zoneid);
-            let coverage = (*zone_to_zone_coverage.get(&(u, v)).unwrap_or(&0) + 1) as f64;
+            let share = shares.get(&(u, v)).copied().unwrap_or(1.0);
             let demand_ij = od.link_between_zones(zones[i], zones[j]).unwrap();
-            let ridership_ij = demand_ij.weight / coverage;
+            let ridership_ij = demand(&demand_ij) * share;
             *zone_to_ridership.entry(zones[i]).or_insert(0.0) -= ridership_ij;
+            zone_to_activity.entry(zones[i]).or_default().1 += ridership_ij;
         }
         // people getting on
         for j in i + 1..zones.len() {
             let (u, v) = (od.get_zone(zones[i]).zoneid, od.get_zone(zones[j]).zoneid);
-            let coverage = (*zone_to_zone_coverage.get(&(u, v)).unwrap_or(&0) + 1) as f64;
+            let share = shares.get(&(u, v)).copied().unwrap_or(1.0);
             let demand_ij = od.link_between_zones(zones[i], zones[j]).unwrap();
-            let ridership_ij = demand_ij.weight / coverage;
+            let ridership_ij = demand(&demand_ij) * share;
             *zone_to_ridership.entry(zones[i]).or_insert(0.0) += ridership_ij;
+            zone_to_activity.entry(zones[i]).or_default().0 += ridership_ij;
         }
     }
 
-    let mut ridership = vec![];
+    // riders of a zone are spread evenly over its stops
+    let mut activity = vec![];
+    let mut load = 0.0;
     for stop in stops {
-        if let Some(zone) = stop_to_zone.get(&stop.stop_id) {
-            // Check if both maps contain the zone
-            if let (Some(&ridership_key), Some(&count)) =
-                (zone_to_ridership.get(zone), zone_to_count.get(zone))
-            {
-                // Make sure count is not zero to avoid division by zero
-                if count > 0 {
-                    let ridership_stop = ridership_key / count as f64;
-                    ridership.push(ridership_stop);
-                } else {
-                    ridership.push(0.0);
-                }
-            } else {
-                // Either zone_to_ridership or zone_to_count doesn't have this zone
-                ridership.push(0.0);
+        let zone = stop_to_zone.get(&stop.stop_id);
+        let count = zone.and_then(|zone| zone_to_count.get(zone)).copied();
+        let (boardings, alightings, net) = match (zone, count) {
+            (Some(zone), Some(count)) if count > 0 => {
+                let (on, off) = zone_to_activity.get(zone).copied().unwrap_or_default();
+                let net = zone_to_ridership.get(zone).copied().unwrap_or(0.0);
+                let count = count as f64;
+                (on / count, off / count, net / count)
             }
-        } else {
-            ridership.push(0.0);
-        }
+            _ => (0.0, 0.0, 0.0),
+        };
+        load += net;
+        activity.push((boardings, alightings, load));
     }
 
-    for i in 1..ridership.len() {
-        ridership[i] += ridership[i - 1];
-    }
+    activity
+}
 
-    let avg_ridership = ridership.iter().sum::<f64>() / ridership.len().max(1) as f64;
+/// Peak on-board load of a route in each time period
+///
+/// # Arguments
+/// - `transit`: Transit network data
+/// - `route`: Route to evaluate
+/// - `od`: Origin-Destination matrix data
+///
+/// # Returns
+/// - Riders on the busiest segment of the route over each period, sharing the demand of a
+///   zone pair with the other routes serving it
+///
+/// # Notes
+/// - Demand of a period comes from the period weights of the OD links. Links without period
+///   weights have their daily demand spread evenly over the periods.
+pub fn peak_load_by_period(
+    transit: &TransitNetwork,
+    route: &TransitRoute,
+    od: &GridNetwork,
+) -> BTreeMap<TimePeriod, f64> {
+    let shares = demand::route_shares(transit, route);
+    TimePeriod::ALL
+        .into_iter()
+        .map(|period| {
+            let ridership =
+                ridership_profile(route, od, &shares, |link| link.period_weight(&period));
+            (period, ridership.iter().copied().fold(0.0, f64::max))
+        })
+        .collect()
+}
 
-    (ridership, avg_ridership)
+/// Load factor of a route in each time period
+///
+/// # Arguments
+/// - `transit`: Transit network data
+/// - `route`: Route to evaluate
+/// - `od`: Origin-Destination matrix data
+///
+/// # Returns
+/// - Peak on-board load divided by the seats offered in each period, values above 1 mean
+///   the route is crowded in that period
+///
+/// # Notes
+/// - Peak loads are given by `peak_load_by_period`
+/// - Seats offered are given by `seats_in_period`
+pub fn load_factor_by_period(
+    transit: &TransitNetwork,
+    route: &TransitRoute,
+    od: &GridNetwork,
+) -> BTreeMap<TimePeriod, f64> {
+    peak_load_by_period(transit, route, od)
+        .into_iter()
+        .map(|(period, peak_load)| {
+            let seats = seats_in_period(route, &period);
+            let load_factor = if seats > 0.0 { peak_load / seats } else { 0.0 };
+            (period, load_factor)
+        })
+        .collect()
+}
+
+/// Seats a route offers in a time period, its departures in the period times its capacity
+/// with `DEFAULT_FREQUENCY` departures for periods the route has no data for
+pub fn seats_in_period(route: &TransitRoute, period: &TimePeriod) -> f64 {
+    let departures = route
+        .stop_times
+        .get(&period.to_number())
+        .map_or(DEFAULT_FREQUENCY, |&f| f as f64);
+    departures * route.capacity() as f64
 }
 
 /// Function to evaluate the coverage of a route
-/// Coverage is calculated using the ratio of the ridership over the sum population around a 400m radius of each stop
-pub fn evaluate_coverage(route_stops: &Vec<Arc<TransitStop>>, od: &GridNetwork) -> f64 {
+/// Coverage is calculated using the ratio of the ridership over the sum population around a `radius` (400m by default) of each stop
+pub fn evaluate_coverage(
+    route_stops: &Vec<Arc<TransitStop>>,
+    od: &GridNetwork,
+    radius: f64,
+) -> f64 {
     let mut curr_populations = 0.0;
     let mut total_population = 0.0;
     for stop in route_stops {
@@ -188,7 +423,7 @@ pub fn evaluate_coverage(route_stops: &Vec<Arc<TransitStop>>, od: &GridNetwork)
         }
         let zone = od.get_zone(node.unwrap());
         curr_populations += zone.population as f64;
-        let env = geo_util::compute_envelope(y, x, 400.0);
+        let env = geo_util::compute_envelope(y, x, radius);
         let nodes_in_envelope = od.rtree.locate_in_envelope_intersecting(&env);
         let mut total_population_stop = 0.0;
         for n in nodes_in_envelope {
@@ -201,11 +436,15 @@ pub fn evaluate_coverage(route_stops: &Vec<Arc<TransitStop>>, od: &GridNetwork)
     curr_populations / (total_population + 1.0) * 100.0
 }
 
-pub fn evaluate_network_coverage(transit: &TransitNetwork, od: &GridNetwork) -> f64 {
+pub fn evaluate_network_coverage(
+    transit: &TransitNetwork,
+    od: &GridNetwork,
+    search: &SearchConfig,
+) -> f64 {
     let mut total_coverage = 0.0;
     for route in &transit.routes {
         let coverage = route.evals.as_ref().map_or_else(
-            || evaluate_coverage(&route.outbound_stops, od),
+            || evaluate_coverage(&route.outbound_stops, od, search.coverage_radius),
             |e| e.coverage,
         );
         total_coverage += coverage;
@@ -218,11 +457,22 @@ pub fn evaluate_network_coverage(transit: &TransitNetwork, od: &GridNetwork) ->
     total_coverage / transit.routes.len() as f64
 }
 
+/// Evaluate the share of the seats of a route its riders fill, out of 100
+///
+/// Ridership comes from `ridership_over_route`, so demand served by other routes too is split
+/// by the demand model of the network.
 pub fn evaluate_economic_score(
     route: &TransitRoute,
     od: &GridNetwork,
     transit: &TransitNetwork,
 ) -> f64 {
+    let (ridership, _) = ridership_over_route(transit, route, od);
+    economic_score(route, od, &ridership)
+}
+
+/// Economic score of a route with the ridership profile of `ridership_over_route`, see
+/// `evaluate_economic_score`
+fn economic_score(route: &TransitRoute, od: &GridNetwork, ridership: &[f64]) -> f64 {
     let route_stops = if route.outbound_stops.len() >= 2 {
         &route.outbound_stops
     } else {
@@ -231,12 +481,11 @@ pub fn evaluate_economic_score(
     if route_stops.len() < 2 {
         return 0.0;
     }
-    let (ridership, _) = ridership_over_route(transit, route, od);
 
     let from_stop = &route_stops[0];
     let to_stop = &route_stops[1];
-    let from_zone = od.find_nearest_zone(from_stop.geom.x(), from_stop.geom.y());
-    let to_zone = od.find_nearest_zone(to_stop.geom.x(), to_stop.geom.y());
+    let from_zone = from_stop.zone_index(od);
+    let to_zone = to_stop.zone_index(od);
     let mut period: usize = 1;
     if let (Some(from), Some(to)) = (from_zone, to_zone) {
         if let Some(link) = od.link_between_zones(from, to) {
@@ -256,7 +505,7 @@ pub fn evaluate_economic_score(
     let f = stop_frequencies
         .get(&period)
         .unwrap_or(&(DEFAULT_FREQUENCY as usize));
-    let div = consts::BUS_CAPACITY as f64 * (*f as f64) / route.outbound_stops.len() as f64;
+    let div = route.capacity() as f64 * (*f as f64) / route.outbound_stops.len() as f64;
 
     let avg_ridership =
         ridership.iter().map(|&x| x.min(div as f64)).sum::<f64>() / ridership.len() as f64;
@@ -285,47 +534,209 @@ pub fn evaluate_network_economic_score(transit: &TransitNetwork, od: &GridNetwor
     total_score / (transit.routes.len() as f64)
 }
 
-pub fn determine_routes_zone_to_zone_coverage(
-    transit: &TransitNetwork,
-    grid: &GridNetwork,
-    opt_route: &TransitRoute,
-) -> HashMap<(u32, u32), u32> {
-    let mut num_routes = HashMap::new();
-    let mut zones = vec![];
-    for stop in &opt_route.outbound_stops {
-        let zone = stop.zone_index(grid);
-        if let Some(zone) = zone {
-            if !zones.contains(&zone) {
-                zones.push(zone);
-            }
+/// Per-zone metric that can be mapped or rasterized
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ZoneMetric {
+    /// Total demand leaving and arriving at the zone
+    Demand,
+    /// Number of routes with a stop within walking distance of the zone
+    Coverage,
+    /// Expected number of transfers of trips starting in the zone
+    Transfers,
+}
+
+impl ZoneMetric {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ZoneMetric::Demand => "demand",
+            ZoneMetric::Coverage => "coverage",
+            ZoneMetric::Transfers => "transfers",
         }
     }
-    for i in 0..zones.len() {
-        for j in i + 1..zones.len() {
-            for route in transit.routes.iter() {
-                if route.route_id == opt_route.route_id {
-                    continue;
-                }
-                if route
+}
+
+impl std::str::FromStr for ZoneMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "demand" => Ok(ZoneMetric::Demand),
+            "coverage" => Ok(ZoneMetric::Coverage),
+            "transfers" => Ok(ZoneMetric::Transfers),
+            _ => Err(format!(
+                "unknown metric '{}', expected demand, coverage or transfers",
+                s
+            )),
+        }
+    }
+}
+
+/// Evaluate a metric for every zone of the grid
+///
+/// # Arguments
+/// - `metric`: Metric to evaluate
+/// - `transit`: Transit network data
+/// - `od`: Origin-Destination matrix data
+///
+/// # Returns
+/// - Value of the metric by zone, zones without a value are left out
+///
+/// # Notes
+/// - Transfers come from the cached network evaluation when available, since computing them
+///   is expensive
+pub fn zone_metric(
+    metric: ZoneMetric,
+    transit: &TransitNetwork,
+    od: &GridNetwork,
+) -> HashMap<NodeIndex, f64> {
+    match metric {
+        ZoneMetric::Demand => od
+            .graph
+            .node_indices()
+            .map(|zone| {
+                let (demand_out, demand_in) = od.demand_out_in(zone);
+                (zone, demand_out + demand_in)
+            })
+            .collect(),
+        ZoneMetric::Coverage => {
+            let mut zone_routes: HashMap<NodeIndex, HashSet<&str>> = HashMap::new();
+            for route in &transit.routes {
+                for stop in route
                     .outbound_stops
                     .iter()
-                    .any(|s| grid.get_zone(zones[i]).polygon.contains(&s.geom))
-                    && route
-                        .outbound_stops
-                        .iter()
-                        .any(|s| grid.get_zone(zones[j]).polygon.contains(&s.geom))
+                    .chain(route.inbound_stops.iter())
                 {
-                    let (u, v) = (
-                        grid.get_zone(zones[i]).zoneid,
-                        grid.get_zone(zones[j]).zoneid,
-                    );
-                    *num_routes.entry((u, v)).or_insert(0) += 1;
-                    *num_routes.entry((v, u)).or_insert(0) += 1;
+                    for zone in stop.nearby_zone_indices(od) {
+                        zone_routes
+                            .entry(zone)
+                            .or_default()
+                            .insert(route.route_id.as_str());
+                    }
+                }
+            }
+            od.graph
+                .node_indices()
+                .map(|zone| {
+                    let routes = zone_routes.get(&zone).map_or(0, |r| r.len());
+                    (zone, routes as f64)
+                })
+                .collect()
+        }
+        ZoneMetric::Transfers => match &transit.evals {
+            Some(evals) => evals.zone_to_transfers.clone(),
+            None => average_transfers(transit, od).1,
+        },
+    }
+}
+
+/// Service density statistics of a single zone
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ZoneServiceDensity {
+    pub zoneid: u32,
+    pub population: u32,
+    pub area_km2: f64,
+    /// Distinct stops in the zone per 1,000 residents
+    pub stops_per_1000: f64,
+    /// Kilometers of route served in the zone per square kilometer of zone area
+    pub route_km_per_km2: f64,
+    /// Daily seat-kilometers offered in the zone per resident
+    pub seat_km_per_capita: f64,
+}
+
+/// Evaluate service density statistics for every zone of the grid
+///
+/// # Arguments
+/// - `transit`: Transit network data
+/// - `od`: Origin-Destination matrix data
+///
+/// # Returns
+/// - Service density of each zone, sorted by zone id
+///
+/// # Notes
+/// - Each segment between consecutive stops of a route is measured as a straight line and split
+///   equally between the zones of its two stops
+/// - Seat-km uses the number of daily departures of the route (`DEFAULT_FREQUENCY` per period
+///   when the route has no departure data) and the bus capacity
+/// - Per capita statistics are 0 for zones without residents
+pub fn service_density(transit: &TransitNetwork, od: &GridNetwork) -> Vec<ZoneServiceDensity> {
+    let mut zone_stops: HashMap<NodeIndex, HashSet<&str>> = HashMap::new();
+    let mut zone_route_km: HashMap<NodeIndex, f64> = HashMap::new();
+    let mut zone_seat_km: HashMap<NodeIndex, f64> = HashMap::new();
+
+    for route in &transit.routes {
+        for stop in route
+            .outbound_stops
+            .iter()
+            .chain(route.inbound_stops.iter())
+        {
+            if let Some(zone) = stop.zone_index(od) {
+                zone_stops.entry(zone).or_default().insert(&stop.stop_id);
+            }
+        }
+
+        let stops = if route.outbound_stops.len() >= 2 {
+            &route.outbound_stops
+        } else {
+            &route.inbound_stops
+        };
+        let daily_departures = if route.stop_times.is_empty() {
+            DEFAULT_FREQUENCY * TimePeriod::ALL.len() as f64
+        } else {
+            route.stop_times.values().sum::<usize>() as f64
+        };
+        let seats = daily_departures * route.capacity() as f64;
+
+        for pair in stops.windows(2) {
+            let km = geo_util::haversine(
+                pair[0].geom.x(),
+                pair[0].geom.y(),
+                pair[1].geom.x(),
+                pair[1].geom.y(),
+            ) / 1000.0;
+            for stop in pair {
+                if let Some(zone) = stop.zone_index(od) {
+                    *zone_route_km.entry(zone).or_insert(0.0) += km / 2.0;
+                    *zone_seat_km.entry(zone).or_insert(0.0) += km / 2.0 * seats;
                 }
             }
         }
     }
-    num_routes
+
+    let mut densities: Vec<ZoneServiceDensity> = od
+        .graph
+        .node_indices()
+        .map(|ni| {
+            let zone = od.get_zone(ni);
+            let population = zone.population as f64;
+            let area_km2 = zone.polygon.geodesic_area_unsigned() / 1_000_000.0;
+            let stops = zone_stops.get(&ni).map_or(0, |s| s.len()) as f64;
+            let route_km = zone_route_km.get(&ni).copied().unwrap_or(0.0);
+            let seat_km = zone_seat_km.get(&ni).copied().unwrap_or(0.0);
+            ZoneServiceDensity {
+                zoneid: zone.zoneid,
+                population: zone.population,
+                area_km2,
+                stops_per_1000: if population > 0.0 {
+                    stops / population * 1000.0
+                } else {
+                    0.0
+                },
+                route_km_per_km2: if area_km2 > 0.0 {
+                    route_km / area_km2
+                } else {
+                    0.0
+                },
+                seat_km_per_capita: if population > 0.0 {
+                    seat_km / population
+                } else {
+                    0.0
+                },
+            }
+        })
+        .collect();
+    densities.sort_by_key(|d| d.zoneid);
+    densities
 }
 
 /// Evaluate the expected number of transfers for trips using the transit network
@@ -344,10 +755,32 @@ pub fn average_transfers(
     transit: &TransitNetwork,
     od: &GridNetwork,
 ) -> (f64, HashMap<NodeIndex, f64>) {
+    let impedance = transfer_impedance(transit, od);
+    (impedance.avg_transfers, impedance.zone_to_transfers)
+}
+
+/// Transfers of the trips in the city and the time riders lose to them, weighted by the volume
+/// of each OD edge
+pub struct TransferImpedance {
+    pub avg_transfers: f64,
+    /// Expected minutes waited at transfers, half the headway of every route transferred to
+    pub avg_transfer_wait: f64,
+    /// Expected minutes lost to transfers, the wait plus `TRANSFER_PENALTY_MIN` per transfer
+    pub avg_impedance: f64,
+    pub zone_to_transfers: HashMap<NodeIndex, f64>,
+}
+
+/// Evaluate the transfers of trips using the transit network along with the wait they cause
+///
+/// A trip makes as few transfers as possible, and among the ways to do so waits the least.
+/// Transferring onto a route that runs every 30 minutes thus weighs more than onto one that
+/// runs every 5 minutes, which the number of transfers alone does not tell apart.
+pub fn transfer_impedance(transit: &TransitNetwork, od: &GridNetwork) -> TransferImpedance {
     // save which routes access which zones to speed up computation
     // define some acceptable walking radius for a transfer
     let mut zone_to_routes = HashMap::new();
     let mut route_to_zones = HashMap::new();
+    let mut route_waits = HashMap::new();
 
     for route in &transit.routes {
         let mut zones = HashSet::new();
@@ -364,11 +797,16 @@ pub fn average_transfers(
                 .push(route.route_id.clone());
         }
         route_to_zones.insert(route.route_id.clone(), zones);
+        route_waits.insert(
+            route.route_id.clone(),
+            accessibility::headway_minutes(route) / 2.0,
+        );
     }
 
     let zones = od.get_all_valid_zones();
 
     let mut expected_transfers = 0.0;
+    let mut expected_wait = 0.0;
     let mut total_volume = 0.0;
     let mut zone_to_transfers = HashMap::new();
     for from in &zones {
@@ -377,10 +815,16 @@ pub fn average_transfers(
         }
         log::trace!("Zone {:?}", od.get_zone(*from).zoneid);
 
-        let transfers_map =
-            compute_all_transfers_from_zone(&zone_to_routes, &route_to_zones, *from, &zones);
+        let transfers_map = compute_all_transfers_from_zone(
+            &zone_to_routes,
+            &route_to_zones,
+            &route_waits,
+            *from,
+            &zones,
+        );
 
         let mut zone_expected_transfers = 0.0;
+        let mut zone_expected_wait = 0.0;
         let mut zone_total_volume = 0.0;
 
         for to in &zones {
@@ -389,8 +833,9 @@ pub fn average_transfers(
             }
             let demand = od.demand_between_zones(*from, *to);
 
-            if let Some(transfers) = transfers_map.get(to) {
+            if let Some((transfers, wait)) = transfers_map.get(to) {
                 zone_expected_transfers += *transfers * demand;
+                zone_expected_wait += *wait * demand;
                 zone_total_volume += demand;
             }
         }
@@ -398,91 +843,100 @@ pub fn average_transfers(
         if zone_total_volume > 0.0 {
             zone_to_transfers.insert(*from, zone_expected_transfers / zone_total_volume);
             expected_transfers += zone_expected_transfers;
+            expected_wait += zone_expected_wait;
             total_volume += zone_total_volume;
         }
     }
 
-    let avg_transfers = if total_volume > 0.0 {
-        expected_transfers / total_volume
+    let (avg_transfers, avg_transfer_wait) = if total_volume > 0.0 {
+        (
+            expected_transfers / total_volume,
+            expected_wait / total_volume,
+        )
     } else {
-        0.0
+        (0.0, 0.0)
     };
-    (avg_transfers, zone_to_transfers)
+    TransferImpedance {
+        avg_transfers,
+        avg_transfer_wait,
+        avg_impedance: avg_transfer_wait + avg_transfers * TRANSFER_PENALTY_MIN,
+        zone_to_transfers,
+    }
 }
 
 /// Calculate minimum transfers from a source zone to all possible destination zones
-/// using a single BFS traversal
+/// using a BFS traversal, one level per transfer
+///
+/// # Returns
+/// - Map of zone index to the number of transfers and the minutes waited at them, the least
+///   wait among the ways to reach the zone with the fewest transfers
 fn compute_all_transfers_from_zone(
     zone_to_routes: &HashMap<NodeIndex, Vec<String>>,
     route_to_zones: &HashMap<String, HashSet<NodeIndex>>,
+    route_waits: &HashMap<String, f64>,
     from: NodeIndex,
     zones: &Vec<NodeIndex>,
-) -> HashMap<NodeIndex, f64> {
+) -> HashMap<NodeIndex, (f64, f64)> {
     let mut transfers_map = HashMap::new();
 
     // If source zone has no routes, all destinations are unreachable
-    if !zone_to_routes.contains_key(&from) {
+    let Some(source_routes) = zone_to_routes.get(&from) else {
         return transfers_map;
-    }
+    };
 
     // Source to source is always 0 transfers
-    transfers_map.insert(from, 0.0);
+    transfers_map.insert(from, (0.0, 0.0));
 
-    // Track visited zones to avoid cycles
-    let mut visited = HashSet::new();
-    visited.insert(from);
-
-    // Queue of (zone, transfers)
-    let mut queue = VecDeque::new();
-    queue.push_back((from, 0.0));
-
-    // Initialize with direct connections (0 transfers)
-    for route in zone_to_routes[&from].iter() {
+    // Initialize with direct connections (0 transfers, the first wait is not a transfer)
+    let mut frontier = vec![];
+    for route in source_routes {
         if let Some(reachable_zones) = route_to_zones.get(route) {
             for &zone in reachable_zones {
-                if zone != from {
-                    transfers_map.insert(zone, 0.0); // Direct connection = 0 transfers
-                    visited.insert(zone);
-                    queue.push_back((zone, 0.0));
-                }
+                transfers_map.entry(zone).or_insert_with(|| {
+                    frontier.push(zone);
+                    (0.0, 0.0)
+                });
             }
         }
     }
 
-    while let Some((current_zone, transfers)) = queue.pop_front() {
-        // Get all routes from the current zone
-        if let Some(routes) = zone_to_routes.get(&current_zone) {
-            for route in routes {
-                // Get all zones reachable from this route
-                if let Some(reachable_zones) = route_to_zones.get(route) {
-                    for &next_zone in reachable_zones {
-                        if next_zone == from || visited.contains(&next_zone) {
-                            continue;
-                        }
-
-                        // If we haven't seen this zone yet or found a better path
-                        let new_transfers = transfers + 1.0;
-                        let update = match transfers_map.get(&next_zone) {
-                            None => true,
-                            Some(&existing) => new_transfers < existing,
-                        };
-
-                        if update {
-                            transfers_map.insert(next_zone, new_transfers);
-                            visited.insert(next_zone);
-                            queue.push_back((next_zone, new_transfers));
-                        }
+    let default_wait = accessibility::DEFAULT_HEADWAY_MIN / 2.0;
+    let mut transfers = 0.0;
+    while !frontier.is_empty() {
+        transfers += 1.0;
+
+        // Least wait to each zone first reached with one more transfer
+        let mut next_level: HashMap<NodeIndex, f64> = HashMap::new();
+        for zone in &frontier {
+            let wait = transfers_map[zone].1;
+            for route in zone_to_routes.get(zone).into_iter().flatten() {
+                let Some(reachable_zones) = route_to_zones.get(route) else {
+                    continue;
+                };
+                let boarded_wait = wait + route_waits.get(route).copied().unwrap_or(default_wait);
+                for &next_zone in reachable_zones {
+                    if transfers_map.contains_key(&next_zone) {
+                        continue;
                     }
+                    let best = next_level.entry(next_zone).or_insert(f64::INFINITY);
+                    *best = best.min(boarded_wait);
                 }
             }
         }
+
+        frontier = next_level.keys().copied().collect();
+        for (zone, wait) in next_level {
+            transfers_map.insert(zone, (transfers, wait));
+        }
     }
 
     // Apply a penalty for unreachable zones
-    let penalty = 5.0;
     for &zone in zones {
         if zone != from && !transfers_map.contains_key(&zone) {
-            transfers_map.insert(zone, penalty);
+            transfers_map.insert(
+                zone,
+                (UNREACHABLE_TRANSFERS, UNREACHABLE_TRANSFERS * default_wait),
+            );
         }
     }
 
@@ -497,6 +951,8 @@ pub struct RankedRoute {
     pub score_before: f64,
     pub score_after: f64,
     pub improvement: f64,
+    pub load_factor_before: BTreeMap<TimePeriod, f64>,
+    pub load_factor_after: BTreeMap<TimePeriod, f64>,
 }
 
 pub fn rank_routes_by_improvement(
@@ -538,10 +994,189 @@ pub fn rank_routes_by_improvement(
                 score_before: original_score,
                 score_after: optimized_score,
                 improvement: improvement_pct,
+                load_factor_before: original
+                    .evals
+                    .as_ref()
+                    .map(|e| e.load_factor.clone())
+                    .unwrap_or_default(),
+                load_factor_after: optimized
+                    .evals
+                    .as_ref()
+                    .map(|e| e.load_factor.clone())
+                    .unwrap_or_default(),
             });
         }
     }
-    // sort by highest improvement first
-    ranked_routes.sort_by(|a, b| b.improvement.partial_cmp(&a.improvement).unwrap());
+    sort_ranked_routes(&mut ranked_routes);
     ranked_routes
 }
+
+/// Sort by highest improvement first, routes with an undefined improvement (e.g. no original
+/// ridership) last and ties by route id
+fn sort_ranked_routes(ranked_routes: &mut [RankedRoute]) {
+    ranked_routes.sort_by(|a, b| {
+        ordering::cmp_f64_desc(a.improvement, b.improvement)
+            .then_with(|| a.route_id.cmp(&b.route_id))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+    use crate::layers::transit_network::TransitRouteType;
+
+    fn ranked(route_id: &str, improvement: f64) -> RankedRoute {
+        RankedRoute {
+            route_id: route_id.to_string(),
+            route_short_name: String::new(),
+            route_long_name: String::new(),
+            score_before: 0.0,
+            score_after: 0.0,
+            improvement,
+            load_factor_before: BTreeMap::new(),
+            load_factor_after: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn transfers_wait_for_the_route_transferred_to() {
+        let zone = NodeIndex::new;
+        // a local route from zone 0 to 1, and a route from 1 to 2 running every `headway`
+        let transfers_onto = |headway: f64| {
+            let zone_to_routes = HashMap::from([
+                (zone(0), vec!["local".to_string()]),
+                (zone(1), vec!["local".to_string(), "feeder".to_string()]),
+                (zone(2), vec!["feeder".to_string()]),
+            ]);
+            let route_to_zones = HashMap::from([
+                ("local".to_string(), HashSet::from([zone(0), zone(1)])),
+                ("feeder".to_string(), HashSet::from([zone(1), zone(2)])),
+            ]);
+            let route_waits = HashMap::from([
+                ("local".to_string(), 5.0),
+                ("feeder".to_string(), headway / 2.0),
+            ]);
+            compute_all_transfers_from_zone(
+                &zone_to_routes,
+                &route_to_zones,
+                &route_waits,
+                zone(0),
+                &vec![zone(0), zone(1), zone(2), zone(3)],
+            )
+        };
+
+        let frequent = transfers_onto(5.0);
+        let infrequent = transfers_onto(30.0);
+        assert_eq!(frequent[&zone(1)], (0.0, 0.0));
+        assert_eq!(frequent[&zone(2)], (1.0, 2.5));
+        assert_eq!(infrequent[&zone(2)], (1.0, 15.0));
+        assert_eq!(
+            frequent[&zone(3)],
+            (
+                UNREACHABLE_TRANSFERS,
+                UNREACHABLE_TRANSFERS * accessibility::DEFAULT_HEADWAY_MIN / 2.0
+            )
+        );
+    }
+
+    #[test]
+    fn eval_cache_evaluates_each_stop_sequence_once() {
+        let stops: Vec<Arc<TransitStop>> = ["a", "b", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, id)| Arc::new(TransitStop::new(id, geo::Point::new(i as f64 * 0.01, 0.0))))
+            .collect();
+        let route = TransitRoute {
+            route_id: "1".to_string(),
+            route_type: TransitRouteType::Bus,
+            inbound_stops: vec![],
+            outbound_stops: stops,
+            evals: None,
+            stop_times: HashMap::new(),
+            service_span: None,
+            vehicle: None,
+        };
+
+        let mut cache = EvalCache::new();
+        let mut evaluations = 0;
+        for _ in 0..3 {
+            let copy = route.clone();
+            cache.get_or_insert_with(route_key(&copy), || {
+                evaluations += 1;
+                1.0
+            });
+        }
+        assert_eq!((evaluations, cache.hits()), (1, 2));
+
+        // the same stops in another order, or in the other direction, is another route
+        let mut reversed = route.clone();
+        reversed.outbound_stops.reverse();
+        assert_ne!(route_key(&reversed), route_key(&route));
+        assert_eq!(cache.get_or_insert_with(route_key(&reversed), || 2.0), 2.0);
+        let mut inbound = route.clone();
+        inbound.inbound_stops = std::mem::take(&mut inbound.outbound_stops);
+        assert_eq!(cache.get_or_insert_with(route_key(&inbound), || 3.0), 3.0);
+        assert_eq!(cache.get_or_insert_with(route_key(&route), || 4.0), 1.0);
+    }
+
+    #[test]
+    fn ranked_routes_sort_nan_last_and_ties_by_id() {
+        let mut routes = vec![
+            ranked("c", 10.0),
+            ranked("nan", f64::NAN),
+            ranked("a", 10.0),
+            ranked("b", 25.0),
+            ranked("d", -5.0),
+        ];
+        sort_ranked_routes(&mut routes);
+        let ids: Vec<_> = routes.iter().map(|r| r.route_id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "a", "c", "d", "nan"]);
+    }
+
+    #[test]
+    fn ranked_routes_sort_is_independent_of_input_order() {
+        let routes = || {
+            vec![
+                ranked("x", 1.0),
+                ranked("y", f64::NAN),
+                ranked("z", 1.0),
+                ranked("w", f64::INFINITY),
+            ]
+        };
+        let mut forward = routes();
+        let mut backward = routes();
+        backward.reverse();
+        sort_ranked_routes(&mut forward);
+        sort_ranked_routes(&mut backward);
+        let ids = |r: &[RankedRoute]| r.iter().map(|r| r.route_id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&forward), ids(&backward));
+    }
+
+    #[test]
+    fn stop_ridership_adds_up_to_the_route_profile() {
+        let city = load_demo_city(
+            &format!("eval_stops_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 4,
+                rows: 4,
+                routes: 2,
+                ..Default::default()
+            },
+        );
+
+        for route in &city.transit.routes {
+            let stops = ridership_by_stop(&city.transit, route, &city.grid);
+            let (profile, _) = ridership_over_route(&city.transit, route, &city.grid);
+            assert_eq!(stops.len(), route.outbound_stops.len());
+            assert_eq!(stops.iter().map(|s| s.load).collect::<Vec<_>>(), profile);
+
+            let mut load = 0.0;
+            for stop in &stops {
+                assert!(stop.boardings >= 0.0 && stop.alightings >= 0.0);
+                load += stop.boardings - stop.alightings;
+                assert!((stop.load - load).abs() < 1e-6);
+            }
+        }
+    }
+}
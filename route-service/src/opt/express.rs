@@ -0,0 +1,270 @@
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::layers::{
+    grid::{GridNetwork, TimePeriod},
+    transit_network::{TransitNetwork, TransitRoute, TransitRouteType, TransitStop},
+};
+
+use super::eval::{self, TransitRouteEvals};
+use super::search::SearchConfig;
+use crate::ordering;
+
+/// Appended to the id of a route to name its express overlay
+pub const EXPRESS_SUFFIX: &str = "-express";
+/// Minutes a bus loses at each stop it serves by slowing down, dwelling and pulling out
+const STOP_TIME_MIN: f64 = 0.75;
+
+/// How to build the express overlay of a route
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExpressParams {
+    /// Share of the stops of the local route the express serves, in (0, 1)
+    pub stop_share: f64,
+    /// Fewest stops the express serves in each direction, including both terminals
+    pub min_stops: usize,
+    /// Minutes between express departures in every time period
+    pub headway_minutes: f64,
+}
+
+impl ExpressParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.stop_share > 0.0 && self.stop_share < 1.0) {
+            return Err("stop_share must be between 0 and 1".to_string());
+        }
+        if self.min_stops < 2 {
+            return Err("min_stops must be at least 2".to_string());
+        }
+        if !(self.headway_minutes.is_finite() && self.headway_minutes > 0.0) {
+            return Err("headway_minutes must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Local and express performance over the periods of the day
+#[derive(Clone, Serialize)]
+pub struct CombinedMetrics {
+    /// Average on-board load of the local route alone
+    pub avg_ridership_before: f64,
+    /// Average on-board load of the local route plus that of the express
+    pub avg_ridership_after: f64,
+    /// Peak load over seats of the local route alone
+    pub load_factor_before: BTreeMap<TimePeriod, f64>,
+    /// Peak loads of both routes over the seats of both routes
+    pub load_factor_after: BTreeMap<TimePeriod, f64>,
+}
+
+/// An express overlay proposed for a route, along with the local route it runs next to
+#[derive(Clone, Serialize)]
+pub struct ExpressProposal {
+    pub route_id: String,
+    pub express_route_id: String,
+    /// Outbound stops the express serves, in order
+    pub express_stop_ids: Vec<String>,
+    /// Outbound stops of the local route the express skips
+    pub skipped_stops: usize,
+    pub headway_minutes: f64,
+    /// Express departures by time period number, like `TransitRoute::stop_times`
+    pub departures_by_period: HashMap<usize, usize>,
+    /// Minutes an end to end outbound trip saves on the express
+    pub minutes_saved: f64,
+    /// The local route before the express runs
+    pub local_before: TransitRouteEvals,
+    /// The local route once it shares its riders with the express
+    pub local_after: TransitRouteEvals,
+    pub express: TransitRouteEvals,
+    pub combined: CombinedMetrics,
+}
+
+/// Trips starting or ending at each stop between its zone and the zones of the other stops of
+/// the route, shared between the stops of the same zone
+pub fn stop_activity(stops: &[Arc<TransitStop>], od: &GridNetwork) -> Vec<f64> {
+    let zones: Vec<Option<NodeIndex>> = stops.iter().map(|s| s.zone_index(od)).collect();
+    let mut stops_per_zone: HashMap<NodeIndex, usize> = HashMap::new();
+    for zone in zones.iter().flatten() {
+        *stops_per_zone.entry(*zone).or_insert(0) += 1;
+    }
+    let route_zones: HashSet<NodeIndex> = stops_per_zone.keys().copied().collect();
+
+    zones
+        .iter()
+        .map(|zone| {
+            let Some(zone) = zone else {
+                return 0.0;
+            };
+            let trips: f64 = route_zones
+                .iter()
+                .filter(|other| *other != zone)
+                .map(|other| {
+                    od.demand_between_zones(*zone, *other) + od.demand_between_zones(*other, *zone)
+                })
+                .sum();
+            trips / stops_per_zone[zone] as f64
+        })
+        .collect()
+}
+
+/// Indices of the stops an express serves, in stop order
+///
+/// Both terminals are always served, the other stops are the busiest ones until `count`
+/// stops are served. Ties go to the earlier stop.
+pub fn express_stop_indices(activity: &[f64], count: usize) -> Vec<usize> {
+    let n = activity.len();
+    if n <= 2 || count >= n {
+        return (0..n).collect();
+    }
+    let mut inner: Vec<usize> = (1..n - 1).collect();
+    inner.sort_by(|&a, &b| ordering::cmp_f64_desc(activity[a], activity[b]).then(a.cmp(&b)));
+    let mut kept: Vec<usize> = inner.into_iter().take(count.saturating_sub(2)).collect();
+    kept.push(0);
+    kept.push(n - 1);
+    kept.sort();
+    kept
+}
+
+/// Departures per time period of a route running every `headway_minutes`, at least one
+pub fn departures_for_headway(headway_minutes: f64) -> HashMap<usize, usize> {
+    TimePeriod::ALL
+        .iter()
+        .map(|period| {
+            let (start, end) = period.local_bounds();
+            let minutes = (end - start) as f64 / 60.0;
+            let departures = ((minutes / headway_minutes).round() as usize).max(1);
+            (period.to_number(), departures)
+        })
+        .collect()
+}
+
+/// Propose an express overlay of a route on the same alignment, serving its busiest stops
+///
+/// # Arguments
+/// - `route`: The local route, which keeps all of its stops and departures
+/// - `transit`: Network the local route runs in, the express is added to it for evaluation
+/// - `od`: Origin-Destination matrix data
+/// - `search`: Search parameters the route evals are computed with
+/// - `params`: How to build the express
+///
+/// # Returns
+/// - The proposal with the local and express metrics, and the express route
+/// - An error if the route is not a bus route or has too few stops for the express to skip
+///   any
+pub fn propose_express(
+    route: &TransitRoute,
+    transit: &TransitNetwork,
+    od: &GridNetwork,
+    search: &SearchConfig,
+    params: &ExpressParams,
+) -> Result<(ExpressProposal, TransitRoute), String> {
+    params.validate()?;
+    if route.route_type != TransitRouteType::Bus {
+        return Err(format!("Route {} is not a bus route", route.route_id));
+    }
+    let select = |stops: &[Arc<TransitStop>]| -> Vec<Arc<TransitStop>> {
+        let count =
+            ((stops.len() as f64 * params.stop_share).ceil() as usize).max(params.min_stops);
+        express_stop_indices(&stop_activity(stops, od), count)
+            .into_iter()
+            .map(|i| stops[i].clone())
+            .collect()
+    };
+    let outbound_stops = select(&route.outbound_stops);
+    let skipped_stops = route.outbound_stops.len() - outbound_stops.len();
+    if skipped_stops == 0 {
+        return Err(format!(
+            "Route {} has too few stops for an express to skip any",
+            route.route_id
+        ));
+    }
+
+    let departures_by_period = departures_for_headway(params.headway_minutes);
+    let express = TransitRoute {
+        route_id: format!("{}{}", route.route_id, EXPRESS_SUFFIX),
+        route_type: route.route_type.clone(),
+        inbound_stops: select(&route.inbound_stops),
+        outbound_stops,
+        evals: None,
+        stop_times: departures_by_period.clone(),
+        service_span: route.service_span.clone(),
+        vehicle: route.vehicle.clone(),
+    };
+
+    let local_before = TransitRouteEvals::for_route(transit, route, od, search);
+    let mut combined_transit = transit.clone();
+    combined_transit.routes.push(express.clone());
+    let local_after = TransitRouteEvals::for_route(&combined_transit, route, od, search);
+    let express_evals = TransitRouteEvals::for_route(&combined_transit, &express, od, search);
+
+    let load_factor_after = TimePeriod::ALL
+        .into_iter()
+        .map(|period| {
+            let (local_seats, express_seats) = (
+                eval::seats_in_period(route, &period),
+                eval::seats_in_period(&express, &period),
+            );
+            let peak_loads = local_after.load_factor[&period] * local_seats
+                + express_evals.load_factor[&period] * express_seats;
+            let seats = local_seats + express_seats;
+            (period, if seats > 0.0 { peak_loads / seats } else { 0.0 })
+        })
+        .collect();
+    let combined = CombinedMetrics {
+        avg_ridership_before: local_before.avg_ridership,
+        avg_ridership_after: local_after.avg_ridership + express_evals.avg_ridership,
+        load_factor_before: local_before.load_factor.clone(),
+        load_factor_after,
+    };
+
+    let proposal = ExpressProposal {
+        route_id: route.route_id.clone(),
+        express_route_id: express.route_id.clone(),
+        express_stop_ids: express
+            .outbound_stops
+            .iter()
+            .map(|s| s.stop_id.clone())
+            .collect(),
+        skipped_stops,
+        headway_minutes: params.headway_minutes,
+        departures_by_period,
+        minutes_saved: skipped_stops as f64 * STOP_TIME_MIN,
+        local_before,
+        local_after,
+        express: express_evals,
+        combined,
+    };
+    Ok((proposal, express))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn express_serves_terminals_and_busiest_stops() {
+        let activity = [1.0, 5.0, 2.0, 9.0, 5.0, 0.5];
+        assert_eq!(express_stop_indices(&activity, 4), vec![0, 1, 3, 5]);
+        assert_eq!(express_stop_indices(&activity, 3), vec![0, 3, 5]);
+        // terminals are kept even below two stops, and every stop when asked for all
+        assert_eq!(express_stop_indices(&activity, 1), vec![0, 5]);
+        assert_eq!(
+            express_stop_indices(&activity, 6),
+            (0..6).collect::<Vec<_>>()
+        );
+
+        let departures = departures_for_headway(15.0);
+        assert_eq!(departures[&TimePeriod::AmRush.to_number()], 10);
+        assert_eq!(departures[&TimePeriod::Morning.to_number()], 8);
+        assert!(departures_for_headway(1000.0).values().all(|&d| d == 1));
+
+        let params = |stop_share, min_stops, headway_minutes| ExpressParams {
+            stop_share,
+            min_stops,
+            headway_minutes,
+        };
+        assert!(params(0.3, 2, 10.0).validate().is_ok());
+        assert!(params(1.0, 2, 10.0).validate().is_err());
+        assert!(params(0.3, 1, 10.0).validate().is_err());
+        assert!(params(0.3, 2, 0.0).validate().is_err());
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::layers::{
+    grid::{GridNetwork, TimePeriod},
+    road_network::RoadNetwork,
+    transit_network::{TransitNetwork, TransitRoute},
+};
+
+use super::eval;
+use super::timetable;
+
+/// How departures are assigned to routes by `FrequencyPlan::new`
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FrequencyParams {
+    /// Buses that can be in service at once in any time period, over all the planned routes
+    pub fleet_size: usize,
+    /// Longest minutes between departures of a route running in a period
+    pub max_headway_min: f64,
+    /// Shortest minutes between departures of a route
+    pub min_headway_min: f64,
+    /// Minutes a bus waits at each end of a route before heading back
+    pub layover_min: f64,
+    /// Share of the seats riders should fill on the busiest segment, in (0, 1]
+    pub target_load: f64,
+}
+
+impl Default for FrequencyParams {
+    fn default() -> Self {
+        FrequencyParams {
+            fleet_size: 0,
+            max_headway_min: 60.0,
+            min_headway_min: 5.0,
+            layover_min: 5.0,
+            target_load: 0.85,
+        }
+    }
+}
+
+impl FrequencyParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.fleet_size == 0 {
+            return Err("fleet_size must be positive".to_string());
+        }
+        if !(self.min_headway_min.is_finite() && self.min_headway_min > 0.0) {
+            return Err("min_headway_min must be positive".to_string());
+        }
+        if !(self.max_headway_min.is_finite() && self.max_headway_min >= self.min_headway_min) {
+            return Err("max_headway_min must be at least min_headway_min".to_string());
+        }
+        if !(self.layover_min.is_finite() && self.layover_min >= 0.0) {
+            return Err("layover_min must not be negative".to_string());
+        }
+        if !(self.target_load > 0.0 && self.target_load <= 1.0) {
+            return Err("target_load must be between 0 and 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Departures of a route in a time period
+#[derive(Clone, Serialize)]
+pub struct PeriodFrequency {
+    pub period: TimePeriod,
+    /// Departures in the period before the plan
+    pub departures_before: usize,
+    /// Departures in the period
+    pub departures: usize,
+    /// Minutes between departures, `None` if the route does not run in the period
+    pub headway_min: Option<f64>,
+    /// Buses the departures keep in service at once
+    pub vehicles: usize,
+    /// Riders on the busiest segment over the period, see `eval::peak_load_by_period`
+    pub peak_load: f64,
+    /// Peak load over the seats of the departures
+    pub load_factor: f64,
+}
+
+/// Departures planned for a route
+#[derive(Clone, Serialize)]
+pub struct RouteFrequencies {
+    pub route_id: String,
+    /// Minutes for a bus to run the route out and back, layovers included
+    pub cycle_min: f64,
+    pub periods: Vec<PeriodFrequency>,
+}
+
+/// Buses in service at once over the planned routes in a time period
+#[derive(Clone, Serialize)]
+pub struct FleetUse {
+    pub period: TimePeriod,
+    pub vehicles_before: usize,
+    pub vehicles: usize,
+}
+
+/// Departures of routes in each time period, sized to their demand within a fleet
+#[derive(Clone, Serialize)]
+pub struct FrequencyPlan {
+    pub params: FrequencyParams,
+    pub fleet: Vec<FleetUse>,
+    /// Planned routes, sorted by route id
+    pub routes: Vec<RouteFrequencies>,
+}
+
+/// Departures of a route in a time period, as evaluated by `eval` for routes without any
+pub(crate) fn departures_in(route: &TransitRoute, period: &TimePeriod) -> usize {
+    if route.stop_times.is_empty() {
+        return (eval::seats_in_period(route, period) / route.capacity() as f64) as usize;
+    }
+    route
+        .stop_times
+        .get(&period.to_number())
+        .copied()
+        .unwrap_or(0)
+}
+
+/// What the plan needs to know about a route
+struct PlannedRoute<'a> {
+    route: &'a TransitRoute,
+    cycle_min: f64,
+    peak_loads: BTreeMap<TimePeriod, f64>,
+}
+
+impl PlannedRoute<'_> {
+    /// Departures of the route in a period before the plan, as evaluated by `eval`
+    fn departures_before(&self, period: &TimePeriod) -> usize {
+        departures_in(self.route, period)
+    }
+
+    /// Routes without departures run in every period
+    fn runs_in(&self, period: &TimePeriod) -> bool {
+        self.route.stop_times.is_empty()
+            || self.route.stop_times.get(&period.to_number()) > Some(&0)
+    }
+
+    /// Buses needed at once to run the given departures over a period
+    fn vehicles(&self, departures: usize, period_min: f64) -> usize {
+        (self.cycle_min * departures as f64 / period_min).ceil() as usize
+    }
+}
+
+impl FrequencyPlan {
+    /// Assign departures to routes in each time period
+    ///
+    /// # Parameters
+    /// - `routes`: Routes to plan, sharing the fleet
+    /// - `transit`: Network the routes run in, other routes serving the same zone pairs take
+    ///   a share of their demand
+    /// - `grid`: Grid network with the demand of each period
+    /// - `road`: Road network used to estimate run times
+    ///
+    /// # Returns
+    /// The plan, or an error if the fleet cannot run every route at `max_headway_min` in the
+    /// periods it runs in
+    ///
+    /// # Notes
+    /// - Every route keeps the periods it runs in, starting at `max_headway_min`
+    /// - Departures are then added one at a time to the route carrying the most riders over
+    ///   the target load per extra bus, while a bus is free and the route is above
+    ///   `min_headway_min`
+    /// - Run times are estimated from road distances, both directions taking as long as the
+    ///   outbound one
+    pub fn new(
+        routes: &[&TransitRoute],
+        transit: &TransitNetwork,
+        grid: &GridNetwork,
+        road: &RoadNetwork,
+        params: FrequencyParams,
+    ) -> Result<FrequencyPlan, String> {
+        params.validate()?;
+        let mut planned: Vec<PlannedRoute> = routes
+            .iter()
+            .map(|&route| {
+                let run_secs = timetable::estimated_offsets(&route.outbound_stops, road)
+                    .last()
+                    .copied()
+                    .unwrap_or(0);
+                PlannedRoute {
+                    route,
+                    cycle_min: 2.0 * (run_secs as f64 / 60.0 + params.layover_min),
+                    peak_loads: eval::peak_load_by_period(transit, route, grid),
+                }
+            })
+            .collect();
+        planned.sort_by(|a, b| a.route.route_id.cmp(&b.route.route_id));
+
+        let mut fleet = vec![];
+        let mut periods: Vec<Vec<PeriodFrequency>> = vec![vec![]; planned.len()];
+        for period in TimePeriod::ALL {
+            let (start, end) = period.local_bounds();
+            let period_min = (end - start) as f64 / 60.0;
+            let min_departures = ((period_min / params.max_headway_min).ceil() as usize).max(1);
+            let max_departures =
+                ((period_min / params.min_headway_min).floor() as usize).max(min_departures);
+
+            let mut departures: Vec<usize> = planned
+                .iter()
+                .map(|p| {
+                    if p.runs_in(&period) {
+                        min_departures
+                    } else {
+                        0
+                    }
+                })
+                .collect();
+            let mut vehicles: usize = planned
+                .iter()
+                .zip(&departures)
+                .map(|(p, &d)| p.vehicles(d, period_min))
+                .sum();
+            if vehicles > params.fleet_size {
+                return Err(format!(
+                    "{} buses are needed to run every route at least every {} minutes in period {:?}, the fleet has {}",
+                    vehicles, params.max_headway_min, period, params.fleet_size
+                ));
+            }
+
+            loop {
+                // riders carried over the target load per extra bus of one more departure
+                let mut best: Option<(usize, f64, usize)> = None;
+                for (i, p) in planned.iter().enumerate() {
+                    let d = departures[i];
+                    if d == 0 || d >= max_departures {
+                        continue;
+                    }
+                    let load = p.peak_loads[&period];
+                    let seats = p.route.capacity() as f64 * params.target_load;
+                    let gain = load.min(seats * (d + 1) as f64) - load.min(seats * d as f64);
+                    let cost = p.vehicles(d + 1, period_min) - p.vehicles(d, period_min);
+                    if gain <= 0.0 || vehicles + cost > params.fleet_size {
+                        continue;
+                    }
+                    let value = if cost == 0 {
+                        f64::INFINITY
+                    } else {
+                        gain / cost as f64
+                    };
+                    if best.is_none_or(|(_, best_value, _)| value > best_value) {
+                        best = Some((i, value, cost));
+                    }
+                }
+                let Some((i, _, cost)) = best else {
+                    break;
+                };
+                departures[i] += 1;
+                vehicles += cost;
+            }
+
+            let mut vehicles_before = 0;
+            for (i, p) in planned.iter().enumerate() {
+                let d = departures[i];
+                let before = p.departures_before(&period);
+                vehicles_before += p.vehicles(before, period_min);
+                let peak_load = p.peak_loads[&period];
+                periods[i].push(PeriodFrequency {
+                    period: period.clone(),
+                    departures_before: before,
+                    departures: d,
+                    headway_min: (d > 0).then(|| period_min / d as f64),
+                    vehicles: p.vehicles(d, period_min),
+                    peak_load,
+                    load_factor: if d > 0 {
+                        peak_load / (d as f64 * p.route.capacity() as f64)
+                    } else {
+                        0.0
+                    },
+                });
+            }
+            fleet.push(FleetUse {
+                period,
+                vehicles_before,
+                vehicles,
+            });
+        }
+
+        Ok(FrequencyPlan {
+            params,
+            fleet,
+            routes: planned
+                .iter()
+                .zip(periods)
+                .map(|(p, periods)| RouteFrequencies {
+                    route_id: p.route.route_id.clone(),
+                    cycle_min: p.cycle_min,
+                    periods,
+                })
+                .collect(),
+        })
+    }
+
+    /// Set the departures of the planned routes of a network to those of the plan
+    ///
+    /// # Returns
+    /// Ids of the routes whose departures changed
+    pub fn apply(&self, transit: &mut TransitNetwork) -> Vec<String> {
+        let mut changed = vec![];
+        for planned in &self.routes {
+            let Some(route) = transit
+                .routes
+                .iter_mut()
+                .find(|r| r.route_id == planned.route_id)
+            else {
+                continue;
+            };
+            let stop_times = planned
+                .periods
+                .iter()
+                .filter(|p| p.departures > 0)
+                .map(|p| (p.period.to_number(), p.departures))
+                .collect();
+            if route.stop_times != stop_times {
+                route.stop_times = stop_times;
+                changed.push(planned.route_id.clone());
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+    #[test]
+    fn plans_departures_within_the_fleet() {
+        let city = load_demo_city(
+            &format!("frequency_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 4,
+                rows: 4,
+                routes: 3,
+                ..Default::default()
+            },
+        );
+        let routes: Vec<&TransitRoute> = city.transit.routes.iter().collect();
+        let plan = |fleet_size| {
+            let params = FrequencyParams {
+                fleet_size,
+                ..Default::default()
+            };
+            FrequencyPlan::new(&routes, &city.transit, &city.grid, &city.road, params)
+        };
+        assert!(plan(0).is_err());
+
+        let large = plan(1000).unwrap();
+        assert_eq!(large.routes.len(), routes.len());
+        for fleet in &large.fleet {
+            assert!(fleet.vehicles <= 1000);
+        }
+        for route in &large.routes {
+            for period in &route.periods {
+                let headway = period.headway_min.unwrap();
+                assert!((5.0 - 1e-9..=60.0).contains(&headway));
+            }
+        }
+
+        // the fleet needed to run every route hourly is just enough
+        let hourly_fleet = TimePeriod::ALL
+            .iter()
+            .map(|period| {
+                let (start, end) = period.local_bounds();
+                let period_min = (end - start) as f64 / 60.0;
+                let departures = (period_min / 60.0).ceil();
+                large
+                    .routes
+                    .iter()
+                    .map(|r| (r.cycle_min * departures / period_min).ceil() as usize)
+                    .sum::<usize>()
+            })
+            .max()
+            .unwrap();
+        assert!(plan(hourly_fleet - 1).is_err());
+        let small = plan(hourly_fleet).unwrap();
+        for (fleet, period) in small.fleet.iter().zip(TimePeriod::ALL) {
+            assert!(fleet.vehicles <= hourly_fleet);
+            for route in &small.routes {
+                let planned = route.periods.iter().find(|p| p.period == period).unwrap();
+                assert!(planned.headway_min.unwrap() <= 60.0);
+            }
+        }
+
+        let mut transit = city.transit.clone();
+        assert!(!large.apply(&mut transit).is_empty());
+        assert!(large.apply(&mut transit).is_empty());
+    }
+}
@@ -1,14 +1,22 @@
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     layers::{
         city::City,
         transit_network::{TransitNetwork, TransitRoute},
     },
-    opt::aco2::{run_aco, ACO},
+    opt::{
+        aco2::{run_aco, PartialACO, ACO},
+        eval::EvalCache,
+        progress::ProgressEvent,
+    },
+    ordering,
 };
 
 /// Configuration parameters for the genetic algorithm
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct GAConfig {
     pub population_size: usize,
     pub max_generations: usize,
@@ -16,6 +24,15 @@ pub struct GAConfig {
     pub crossover_rate: f64,
     pub elitism_count: usize,
     pub tournament_size: usize,
+    /// Parameters the GA does not tune, e.g. the objective, taken by every individual
+    #[serde(skip)]
+    pub base: ACO,
+}
+
+impl Default for GAConfig {
+    fn default() -> Self {
+        GAConfig::new()
+    }
 }
 
 /// Representation of ACO parameters as a chromosome for GA optimization
@@ -44,7 +61,30 @@ impl GAConfig {
             crossover_rate: 0.7,
             elitism_count: 2,
             tournament_size: 3,
+            base: ACO::init(),
+        }
+    }
+
+    /// Check that the configuration can run, e.g. that elitism leaves room for offspring
+    pub fn validate(&self) -> Result<(), String> {
+        if self.population_size < 2 {
+            return Err("population_size must be at least 2".to_string());
+        }
+        if self.elitism_count >= self.population_size {
+            return Err("elitism_count must be less than population_size".to_string());
         }
+        if self.tournament_size == 0 {
+            return Err("tournament_size must be positive".to_string());
+        }
+        for (name, rate) in [
+            ("mutation_rate", self.mutation_rate),
+            ("crossover_rate", self.crossover_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!("{} must be between 0 and 1", name));
+            }
+        }
+        Ok(())
     }
 
     /// Create a custom configuration for the genetic algorithm
@@ -63,14 +103,28 @@ impl GAConfig {
             crossover_rate,
             elitism_count,
             tournament_size,
+            base: ACO::init(),
         }
     }
 
     /// Run genetic algorithm to find optimal ACO parameters
     pub fn optimize_aco_params(&self, route: &TransitRoute, city: &City) -> Option<(ACO, f64)> {
-        let mut rng = rand::thread_rng();
+        self.optimize_aco_params_with_progress(route, city, &city.transit, &mut |_| {})
+    }
 
-        let transit = city.transit.clone();
+    /// Run genetic algorithm to find optimal ACO parameters for a route of a network
+    ///
+    /// # Parameters
+    /// - `transit`: Network the route is optimized in, e.g. an optimized one
+    /// - `on_progress`: Receives a `TuningGenerationCompleted` event after each generation
+    pub fn optimize_aco_params_with_progress(
+        &self,
+        route: &TransitRoute,
+        city: &City,
+        transit: &TransitNetwork,
+        on_progress: &mut dyn FnMut(ProgressEvent),
+    ) -> Option<(ACO, f64)> {
+        let mut rng = rand::thread_rng();
 
         log::info!(
             "Starting GA optimization for route {} with population={}, generations={}",
@@ -85,20 +139,20 @@ impl GAConfig {
             self.population_size
         );
         let mut population = self.initialize_population(&mut rng);
+        // offspring often repeat the parameters of an individual already evaluated, e.g. when
+        // no parameter mutated, so fitnesses are kept by parameters for the whole run
+        let mut fitness_cache = EvalCache::new();
 
         // Evaluate initial population
         log::info!("Evaluating initial population");
         for (i, individual) in population.iter_mut().enumerate() {
             log::debug!("Evaluating individual {}/{}", i + 1, self.population_size);
-            self.evaluate_fitness(individual, route, city, &transit);
+            self.evaluate_fitness(individual, route, city, transit, &mut fitness_cache);
         }
 
         // Keep track of best solution
         population.sort_by(|a, b| {
-            b.fitness
-                .unwrap_or(0.0)
-                .partial_cmp(&a.fitness.unwrap_or(0.0))
-                .unwrap()
+            ordering::cmp_f64_desc(a.fitness.unwrap_or(0.0), b.fitness.unwrap_or(0.0))
         });
 
         let mut best_solution = population[0].clone();
@@ -151,16 +205,13 @@ impl GAConfig {
             for (i, individual) in population.iter_mut().enumerate() {
                 if individual.fitness.is_none() {
                     log::trace!("Evaluating individual {}/{}", i + 1, self.population_size);
-                    self.evaluate_fitness(individual, route, city, &transit);
+                    self.evaluate_fitness(individual, route, city, transit, &mut fitness_cache);
                 }
             }
 
             // Sort by fitness for next generation
             population.sort_by(|a, b| {
-                b.fitness
-                    .unwrap_or(0.0)
-                    .partial_cmp(&a.fitness.unwrap_or(0.0))
-                    .unwrap()
+                ordering::cmp_f64_desc(a.fitness.unwrap_or(0.0), b.fitness.unwrap_or(0.0))
             });
 
             // Update best solution
@@ -195,11 +246,19 @@ impl GAConfig {
                 best_fitness,
                 avg_fitness
             );
+            on_progress(ProgressEvent::TuningGenerationCompleted {
+                route_id: route.route_id.clone(),
+                generation: generation + 1,
+                max_generations: self.max_generations,
+                best_fitness,
+                avg_fitness,
+            });
         }
 
         log::info!(
-            "GA optimization completed. Final best fitness: {}",
-            best_fitness
+            "GA optimization completed. Final best fitness: {}, {} evaluations cached",
+            best_fitness,
+            fitness_cache.hits()
         );
 
         // Return best solution found
@@ -240,6 +299,20 @@ impl GAConfig {
             max_stop_dist: rng.gen_range(300.0..700.0),
             max_nonlinearity: rng.gen_range(1.5..3.5),
             avg_stop_dist: rng.gen_range(150.0..300.0),
+            // objective weights and operating limits are chosen by the user, not tuned
+            max_departures: self.base.max_departures,
+            poi_weight: self.base.poi_weight,
+            jobs_weight: self.base.jobs_weight,
+            infra_bonus: rng.gen_range(0.0..0.3),
+            local_search: self.base.local_search,
+            max_walk_increase: self.base.max_walk_increase,
+            chunk_min_stops: self.base.chunk_min_stops,
+            chunk_len: self.base.chunk_len,
+            objective: self.base.objective.clone(),
+            seed: self.base.seed,
+            pareto_size: self.base.pareto_size,
+            max_corridor_deviation: self.base.max_corridor_deviation,
+            constraints: self.base.constraints.clone(),
         }
     }
 
@@ -250,14 +323,18 @@ impl GAConfig {
         route: &TransitRoute,
         city: &City,
         transit: &TransitNetwork,
+        fitness_cache: &mut EvalCache<Vec<u64>, f64>,
     ) {
-        // Run ACO with the parameters and evaluate the result
-        if let Some((_, score)) = run_aco(individual.aco_params.clone(), route, city, transit) {
-            individual.fitness = Some(score);
-        } else {
-            // If ACO fails to find a route, assign a low fitness
-            individual.fitness = Some(0.01);
-        }
+        let key = chromosome_key(&individual.aco_params);
+        let fitness = fitness_cache.get_or_insert_with(key, || {
+            // Run ACO with the parameters and evaluate the result
+            match run_aco(individual.aco_params.clone(), route, city, transit) {
+                Some((_, score)) => score,
+                // If ACO fails to find a route, assign a low fitness
+                None => 0.01,
+            }
+        });
+        individual.fitness = Some(fitness);
     }
 
     /// Select an individual using tournament selection
@@ -360,6 +437,23 @@ impl GAConfig {
                 } else {
                     p2.avg_stop_dist
                 },
+                max_departures: p1.max_departures,
+                poi_weight: p1.poi_weight,
+                jobs_weight: p1.jobs_weight,
+                infra_bonus: if rng.gen_bool(0.5) {
+                    p1.infra_bonus
+                } else {
+                    p2.infra_bonus
+                },
+                local_search: p1.local_search,
+                max_walk_increase: p1.max_walk_increase,
+                chunk_min_stops: p1.chunk_min_stops,
+                chunk_len: p1.chunk_len,
+                objective: p1.objective.clone(),
+                seed: p1.seed,
+                pareto_size: p1.pareto_size,
+                max_corridor_deviation: p1.max_corridor_deviation,
+                constraints: p1.constraints.clone(),
             },
             fitness: None,
         }
@@ -482,6 +576,54 @@ impl GAConfig {
     }
 }
 
+/// The parameters of `params` that the GA tunes, to apply over other parameters with
+/// `ACO::update_from_partial`
+pub fn tuned_params(params: &ACO) -> PartialACO {
+    PartialACO {
+        alpha: Some(params.alpha),
+        beta: Some(params.beta),
+        rho: Some(params.rho),
+        q0: Some(params.q0),
+        num_ant: Some(params.num_ant),
+        max_gen: Some(params.max_gen),
+        pheromone_max: Some(params.pheromone_max),
+        pheromone_min: Some(params.pheromone_min),
+        init_pheromone: Some(params.init_pheromone),
+        bus_capacity: Some(params.bus_capacity),
+        min_stop_dist: Some(params.min_stop_dist),
+        max_stop_dist: Some(params.max_stop_dist),
+        min_route_len: Some(params.min_route_len),
+        max_route_len: Some(params.max_route_len),
+        max_nonlinearity: Some(params.max_nonlinearity),
+        avg_stop_dist: Some(params.avg_stop_dist),
+        infra_bonus: Some(params.infra_bonus),
+        ..Default::default()
+    }
+}
+
+/// Key of a chromosome in the fitness cache, the bits of the parameters the GA tunes
+fn chromosome_key(params: &ACO) -> Vec<u64> {
+    vec![
+        params.alpha.to_bits(),
+        params.beta.to_bits(),
+        params.rho.to_bits(),
+        params.q0.to_bits(),
+        params.num_ant as u64,
+        params.max_gen as u64,
+        params.pheromone_max.to_bits(),
+        params.pheromone_min.to_bits(),
+        params.init_pheromone.to_bits(),
+        params.bus_capacity as u64,
+        params.min_route_len as u64,
+        params.max_route_len as u64,
+        params.min_stop_dist.to_bits(),
+        params.max_stop_dist.to_bits(),
+        params.max_nonlinearity.to_bits(),
+        params.avg_stop_dist.to_bits(),
+        params.infra_bonus.to_bits(),
+    ]
+}
+
 /// Run genetic algorithm to find optimal ACO parameters for a route
 ///
 /// This function uses default GA parameters. For more control, create a GAConfig
@@ -0,0 +1,408 @@
+use chrono::{Duration, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::gtfs::gtfs::Gtfs;
+use crate::gtfs::structs::{format_gtfs_time, parse_gtfs_time, Calendar, Frequency, Trip};
+use crate::layers::{
+    grid::TimePeriod,
+    road_network::RoadNetwork,
+    transit_network::{TransitNetwork, TransitRoute},
+};
+
+use super::accessibility::{AVG_BUS_SPEED_KMH, DEFAULT_HEADWAY_MIN};
+use super::timetable;
+
+/// Service the trips run on when the source feed has neither calendars nor calendar dates
+const DEFAULT_SERVICE_ID: &str = "daily";
+
+/// How the trips of an exported feed are scheduled
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ScheduleOptions {
+    /// Average speed along the roads of trips whose times are estimated, in km/h
+    pub avg_speed_kmh: f64,
+    /// Minutes between departures in every time period of routes without departures, e.g.
+    /// routes created from scratch
+    pub default_headway_min: f64,
+    /// Write every departure of the service day as a trip with its own stop times, instead of
+    /// one trip per direction repeated by frequencies
+    pub explicit_trips: bool,
+}
+
+impl Default for ScheduleOptions {
+    fn default() -> Self {
+        ScheduleOptions {
+            avg_speed_kmh: AVG_BUS_SPEED_KMH,
+            default_headway_min: DEFAULT_HEADWAY_MIN,
+            explicit_trips: false,
+        }
+    }
+}
+
+impl ScheduleOptions {
+    /// Check that the options can schedule trips
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.avg_speed_kmh.is_finite() || self.avg_speed_kmh <= 0.0 {
+            return Err(format!(
+                "avg_speed_kmh must be positive, got {}",
+                self.avg_speed_kmh
+            ));
+        }
+        if !self.default_headway_min.is_finite() || self.default_headway_min < 1.0 {
+            return Err(format!(
+                "default_headway_min must be at least 1, got {}",
+                self.default_headway_min
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Convert a transit network to a complete feed that standard transit tools can load
+///
+/// # Parameters
+/// - `network`: The network to export, e.g. the optimized network or a scenario's
+/// - `src_gtfs`: The GTFS data the network was built from
+/// - `road`: The road network, to draw shapes and estimate run times
+/// - `options`: Speed and headways of the estimated schedules, and whether trips repeat by
+///   frequencies
+///
+/// # Returns
+/// The trips, stops, routes and shapes of `TransitNetwork::to_gtfs`, completed with
+/// - the agencies, calendars and feed info of the source feed
+/// - the service of the route's trip in the source feed for each trip, the first service of
+///   the feed for routes that are not in it
+/// - the stop times of each trip, taken from the source trip when it serves the same stops
+///   and estimated from road distances at `avg_speed_kmh` otherwise, starting at the route's
+///   first departure
+/// - frequencies spreading the route's departures in each time period evenly over it, every
+///   `default_headway_min` for routes without departures
+/// - with `explicit_trips`, a trip for each of these departures instead of frequencies, with
+///   ids made of the trip id and the departure time, e.g. `{route_id}_073000`
+pub fn export_gtfs(
+    network: &TransitNetwork,
+    src_gtfs: &Gtfs,
+    road: &RoadNetwork,
+    options: &ScheduleOptions,
+) -> Gtfs {
+    let mut gtfs = network.to_gtfs(src_gtfs, road);
+    gtfs.agencies = src_gtfs.agencies.clone();
+    gtfs.calendar = src_gtfs.calendar.clone();
+    gtfs.calendar_dates = src_gtfs.calendar_dates.clone();
+    gtfs.feed_info = src_gtfs.feed_info.clone();
+
+    let default_service_id = match first_service_id(src_gtfs) {
+        Some(service_id) => service_id,
+        None => {
+            gtfs.calendar
+                .insert(DEFAULT_SERVICE_ID.to_string(), daily_calendar());
+            DEFAULT_SERVICE_ID.to_string()
+        }
+    };
+    let default_agency_id = src_gtfs
+        .agencies
+        .first()
+        .and_then(|agency| agency.agency_id.clone());
+    for (route_id, route) in gtfs.routes.iter_mut() {
+        match src_gtfs.routes.get(route_id) {
+            Some(src_route) => {
+                route.agency_id = src_route.agency_id.clone();
+                route.route_color = src_route.route_color.clone();
+                route.route_text_color = src_route.route_text_color.clone();
+            }
+            None => route.agency_id = default_agency_id.clone(),
+        }
+    }
+
+    for route in &network.routes {
+        let Some(trips) = gtfs.trips.get_mut(&route.route_id) else {
+            continue;
+        };
+        let src_trips = src_gtfs.trips.get(&route.route_id);
+        let windows = schedule_windows(route, trips.len(), options);
+        let first_departure = windows
+            .first()
+            .map(|&(start, _, _)| start)
+            .or(route.service_span.as_ref().map(|span| span.first_departure))
+            .unwrap_or(TimePeriod::Morning.local_bounds().0);
+        for trip in trips.iter_mut() {
+            let src_trip = src_trips.and_then(|src_trips| {
+                src_trips
+                    .iter()
+                    .find(|src_trip| src_trip.direction_id == trip.direction_id)
+            });
+            if trip.service_id.is_empty() {
+                trip.service_id = src_trip
+                    .map(|src_trip| src_trip.service_id.clone())
+                    .unwrap_or_else(|| default_service_id.clone());
+            }
+            schedule_trip(trip, route, src_trip, first_departure, road, options);
+            trip.frequencies = windows
+                .iter()
+                .map(|&(start, end, headway_secs)| Frequency {
+                    trip_id: trip.trip_id.clone(),
+                    start_time: format_gtfs_time(start),
+                    end_time: format_gtfs_time(end),
+                    headway_secs,
+                    exact_times: None,
+                })
+                .collect();
+        }
+        if options.explicit_trips {
+            *trips = trips.iter().flat_map(expand_frequencies).collect();
+        }
+    }
+    gtfs
+}
+
+/// Departure windows of a route, see `departure_windows`, or windows every
+/// `default_headway_min` over the whole service day for routes without departures
+fn schedule_windows(
+    route: &TransitRoute,
+    directions: usize,
+    options: &ScheduleOptions,
+) -> Vec<(u32, u32, i64)> {
+    let windows = departure_windows(route, directions);
+    if !windows.is_empty() {
+        return windows;
+    }
+    let headway_secs = (options.default_headway_min * 60.0).round() as i64;
+    TimePeriod::ALL
+        .iter()
+        .map(|period| {
+            let (start, end) = period.local_bounds();
+            (start, end, headway_secs)
+        })
+        .collect()
+}
+
+/// A trip with its own stop times for every departure of a trip repeated by frequencies
+///
+/// A trip without frequencies is kept as is.
+fn expand_frequencies(trip: &Trip) -> Vec<Trip> {
+    if trip.frequencies.is_empty() {
+        return vec![trip.clone()];
+    }
+    let times: Vec<Option<u32>> = trip
+        .stop_times
+        .iter()
+        .map(|st| st.departure_time.as_deref().and_then(parse_gtfs_time))
+        .collect();
+    let Some(Some(first)) = times.first().copied() else {
+        return vec![trip.clone()];
+    };
+
+    let mut expanded = vec![];
+    for frequency in &trip.frequencies {
+        let (Some(start), Some(end)) = (
+            parse_gtfs_time(&frequency.start_time),
+            parse_gtfs_time(&frequency.end_time),
+        ) else {
+            continue;
+        };
+        for departure in (start..end).step_by(frequency.headway_secs.max(1) as usize) {
+            let trip_id = format!(
+                "{}_{}",
+                trip.trip_id,
+                format_gtfs_time(departure).replace(':', "")
+            );
+            let mut departure_trip = Trip {
+                trip_id: trip_id.clone(),
+                frequencies: vec![],
+                ..trip.clone()
+            };
+            for (stop_time, time) in departure_trip.stop_times.iter_mut().zip(&times) {
+                let time = time.map(|t| format_gtfs_time(departure + t - first));
+                stop_time.trip_id = trip_id.clone();
+                stop_time.arrival_time = time.clone();
+                stop_time.departure_time = time;
+            }
+            expanded.push(departure_trip);
+        }
+    }
+    expanded
+}
+
+/// Give times to the stop times of an exported trip that has none
+///
+/// Trips copied from the source feed keep their times.
+fn schedule_trip(
+    trip: &mut Trip,
+    route: &TransitRoute,
+    src_trip: Option<&Trip>,
+    first_departure: u32,
+    road: &RoadNetwork,
+    options: &ScheduleOptions,
+) {
+    let has_times = trip
+        .stop_times
+        .iter()
+        .all(|st| st.departure_time.is_some() || st.arrival_time.is_some());
+    if has_times {
+        return;
+    }
+    let stops = match trip.direction_id {
+        Some(1) => &route.inbound_stops,
+        _ => &route.outbound_stops,
+    };
+    let offsets = src_trip
+        .and_then(|src_trip| timetable::gtfs_offsets(stops, src_trip))
+        .unwrap_or_else(|| timetable::estimated_offsets_at(stops, road, options.avg_speed_kmh));
+    for (stop_time, offset) in trip.stop_times.iter_mut().zip(offsets) {
+        let time = Some(format_gtfs_time(first_departure + offset));
+        stop_time.arrival_time = time.clone();
+        stop_time.departure_time = time;
+    }
+}
+
+/// Start, end and headway in seconds of each time period the route departs in
+///
+/// The departures of the route in a period are shared by its `directions` trips.
+pub(crate) fn departure_windows(route: &TransitRoute, directions: usize) -> Vec<(u32, u32, i64)> {
+    TimePeriod::ALL
+        .iter()
+        .filter_map(|period| {
+            let departures = *route.stop_times.get(&period.to_number())?;
+            let per_direction = departures.div_ceil(directions.max(1));
+            (per_direction > 0).then(|| {
+                let (start, end) = period.local_bounds();
+                let headway = (end - start) as f64 / per_direction as f64;
+                (start, end, headway.round() as i64)
+            })
+        })
+        .collect()
+}
+
+/// Service of the source feed that routes created from scratch run on, the first calendar
+/// by id, or the first calendar date when the feed has no calendar
+fn first_service_id(src_gtfs: &Gtfs) -> Option<String> {
+    let calendar = src_gtfs.calendar.keys().min();
+    calendar
+        .or_else(|| src_gtfs.calendar_dates.keys().min())
+        .cloned()
+}
+
+/// Calendar running every day for a year from today
+fn daily_calendar() -> Calendar {
+    let today = Local::now().date_naive();
+    Calendar {
+        service_id: DEFAULT_SERVICE_ID.to_string(),
+        monday: 1,
+        tuesday: 1,
+        wednesday: 1,
+        thursday: 1,
+        friday: 1,
+        saturday: 1,
+        sunday: 1,
+        start_date: today.format("%Y%m%d").to_string(),
+        end_date: (today + Duration::days(365)).format("%Y%m%d").to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::raw_gtfs::GtfsDataSet;
+    use crate::gtfs::structs::StopTime;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+    use std::collections::HashSet;
+
+    #[test]
+    fn exported_trips_have_service_times_and_frequencies() {
+        let city = load_demo_city(
+            &format!("gtfs_export_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 3,
+                ..Default::default()
+            },
+        );
+
+        let gtfs = export_gtfs(
+            &city.transit,
+            &city.gtfs,
+            &city.road,
+            &ScheduleOptions::default(),
+        );
+        assert_eq!(gtfs.agencies.len(), 1);
+        assert!(!gtfs.calendar.is_empty());
+        let trips: Vec<&Trip> = gtfs.trips.values().flatten().collect();
+        assert!(!trips.is_empty());
+        for trip in trips {
+            assert!(gtfs.calendar.contains_key(&trip.service_id));
+            assert!(!trip.frequencies.is_empty());
+            let times: Vec<u32> = trip
+                .stop_times
+                .iter()
+                .map(|st| parse_gtfs_time(st.departure_time.as_deref().unwrap()).unwrap())
+                .collect();
+            assert!(times.windows(2).all(|w| w[0] <= w[1]));
+        }
+        for route in gtfs.routes.values() {
+            assert_eq!(route.agency_id.as_deref(), Some("demo"));
+        }
+
+        // a route the feed does not have, without departures
+        let mut network = city.transit.clone();
+        let scratch = &mut network.routes[0];
+        scratch.route_id = "scratch".to_string();
+        scratch.stop_times.clear();
+        let run_time = |options: &ScheduleOptions| {
+            let gtfs = export_gtfs(&network, &city.gtfs, &city.road, options);
+            let trip = gtfs.trips["scratch"][0].clone();
+            let time = |st: Option<&StopTime>| {
+                parse_gtfs_time(st.unwrap().departure_time.as_deref().unwrap()).unwrap()
+            };
+            (
+                time(trip.stop_times.last()) - time(trip.stop_times.first()),
+                trip,
+            )
+        };
+        let (fast, trip) = run_time(&ScheduleOptions::default());
+        assert!(fast > 0);
+        assert_eq!(trip.frequencies.len(), TimePeriod::ALL.len());
+        assert!(trip.frequencies.iter().all(|f| f.headway_secs == 600));
+        let (slow, _) = run_time(&ScheduleOptions {
+            avg_speed_kmh: AVG_BUS_SPEED_KMH / 2.0,
+            ..Default::default()
+        });
+        assert!(slow > fast);
+
+        let options = ScheduleOptions {
+            default_headway_min: 30.0,
+            explicit_trips: true,
+            ..Default::default()
+        };
+        assert!(options.validate().is_ok());
+        let gtfs = export_gtfs(&network, &city.gtfs, &city.road, &options);
+        let trips = &gtfs.trips["scratch"];
+        let outbound: Vec<&Trip> = trips.iter().filter(|t| t.direction_id == Some(0)).collect();
+        let departures: u32 = TimePeriod::ALL
+            .iter()
+            .map(|period| {
+                let (start, end) = period.local_bounds();
+                (end - start).div_ceil(1800)
+            })
+            .sum();
+        assert_eq!(outbound.len() as u32, departures);
+        let ids: HashSet<&str> = trips.iter().map(|t| t.trip_id.as_str()).collect();
+        assert_eq!(ids.len(), trips.len());
+        for trip in trips {
+            assert!(trip.frequencies.is_empty());
+            assert!(trip.stop_times.iter().all(|st| st.trip_id == trip.trip_id));
+        }
+        let dataset: Result<GtfsDataSet, _> = gtfs.try_into();
+        // explicit trips need no frequencies
+        assert!(dataset
+            .unwrap()
+            .frequencies
+            .is_none_or(|f| f.unwrap().is_empty()));
+        assert!(ScheduleOptions {
+            avg_speed_kmh: 0.0,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use geo::{Distance, Haversine};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::layers::{
+    road_network::RoadNetwork,
+    transit_network::{TransitNetwork, TransitStop},
+};
+
+/// Most metres between an outbound stop and the inbound stop serving it
+const MAX_PAIR_DIST: f64 = 250.0;
+
+/// Inbound stops of a route running back along its outbound stops
+///
+/// # Parameters
+/// - `outbound`: Outbound stops of the route, in order
+/// - `transit`: Network whose inbound stops the route can use
+/// - `road`: Road network the route runs on
+///
+/// # Returns
+/// The inbound stops in order, from the end of the outbound direction back to its start
+///
+/// # Notes
+/// - Each outbound stop is paired with the nearest inbound stop of the network within
+///   `MAX_PAIR_DIST`, usually the stop across the street, or with itself if there is none
+/// - A stop the bus cannot drive to from the previous inbound stop, e.g. on a one-way street
+///   running the other way, is passed over for the next nearest one. Outbound stops whose
+///   candidates are all unreachable get no inbound stop.
+/// - Stops without a road node are joined by a straight line, as in `to_gtfs_filtered`
+pub fn mirror_inbound(
+    outbound: &[Arc<TransitStop>],
+    transit: &TransitNetwork,
+    road: &RoadNetwork,
+) -> Vec<Arc<TransitStop>> {
+    let mut inbound: Vec<Arc<TransitStop>> = vec![];
+    let mut used = HashSet::new();
+    for stop in outbound.iter().rev() {
+        let (x, y) = stop.geom.x_y();
+        let next = transit
+            .inbound_stops
+            .nearest_neighbor_iter(&[x, y])
+            .map(|node| &node.stop)
+            .take_while(|s| Haversine::distance(s.geom, stop.geom) <= MAX_PAIR_DIST)
+            .chain(std::iter::once(stop))
+            .filter(|s| !used.contains(s.stop_id.as_str()))
+            .find(|s| inbound.last().is_none_or(|prev| reachable(prev, s, road)))
+            .cloned();
+        if let Some(next) = next {
+            used.insert(next.stop_id.clone());
+            inbound.push(next);
+        }
+    }
+    inbound
+}
+
+/// Whether a bus can drive from one stop to the other
+fn reachable(from: &TransitStop, to: &TransitStop, road: &RoadNetwork) -> bool {
+    match (from.get_node_index(road), to.get_node_index(road)) {
+        (Some(a), Some(b)) => a == b || !road.get_road_distance(a, b).1.is_empty(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+    #[test]
+    fn mirrors_outbound_stops_in_reverse() {
+        let city = load_demo_city(
+            &format!("inbound_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 6,
+                rows: 6,
+                routes: 2,
+                ..Default::default()
+            },
+        );
+
+        for route in &city.transit.routes {
+            let inbound = mirror_inbound(&route.outbound_stops, &city.transit, &city.road);
+            assert_eq!(inbound.len(), route.outbound_stops.len());
+            // the route runs back from its last outbound stop to its first
+            let outbound_ends = (
+                route.outbound_stops.first().unwrap(),
+                route.outbound_stops.last().unwrap(),
+            );
+            assert!(Haversine::distance(inbound[0].geom, outbound_ends.1.geom) <= MAX_PAIR_DIST);
+            assert!(
+                Haversine::distance(inbound.last().unwrap().geom, outbound_ends.0.geom)
+                    <= MAX_PAIR_DIST
+            );
+            for pair in inbound.windows(2) {
+                assert!(reachable(&pair[0], &pair[1], &city.road));
+            }
+            let ids: HashSet<&str> = inbound.iter().map(|s| s.stop_id.as_str()).collect();
+            assert_eq!(ids.len(), inbound.len());
+        }
+        assert!(mirror_inbound(&[], &city.transit, &city.road).is_empty());
+    }
+}
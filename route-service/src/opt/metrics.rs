@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Buckets in seconds of the durations of a route search, which run for seconds to minutes
+const SEARCH_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+/// Counters of the optimizer shared by every search the process runs, exposed by `/metrics`
+pub static OPTIMIZER_METRICS: OptimizerMetrics = OptimizerMetrics::new();
+
+/// Cumulative histogram in the Prometheus sense: each bucket counts the observations up to
+/// its bound
+pub struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub const fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: Vec::new(),
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        if self.counts.is_empty() {
+            self.counts = vec![0; self.bounds.len()];
+        }
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Write the samples of the histogram in the Prometheus text format
+    ///
+    /// # Arguments
+    /// - `name`: Name of the metric, without the `_bucket`, `_sum` and `_count` suffixes
+    /// - `labels`: Labels of the samples, e.g. `endpoint="/grid"`, empty for none
+    pub fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (i, bound) in self.bounds.iter().enumerate() {
+            let count = self.counts.get(i).copied().unwrap_or(0);
+            writeln!(out, "{name}_bucket{{{labels}{sep}le=\"{bound}\"}} {count}").unwrap();
+        }
+        let count = self.count;
+        writeln!(out, "{name}_bucket{{{labels}{sep}le=\"+Inf\"}} {count}").unwrap();
+        let braces = |labels: &str| match labels {
+            "" => String::new(),
+            labels => format!("{{{labels}}}"),
+        };
+        writeln!(out, "{name}_sum{} {}", braces(labels), self.sum).unwrap();
+        writeln!(out, "{name}_count{} {}", braces(labels), count).unwrap();
+    }
+}
+
+/// Write the `HELP` and `TYPE` lines introducing a metric
+pub fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+}
+
+/// What a single search of a route did, see `OptimizerMetrics::record_search`
+pub struct SearchStats<'a> {
+    pub route_id: &'a str,
+    pub duration: Duration,
+    /// ACO generations run, fewer than `ACO::max_gen` when the deadline was reached
+    pub generations: usize,
+    pub heuristic_hits: u64,
+    pub heuristic_misses: u64,
+    pub pheromone_hits: u64,
+    pub pheromone_misses: u64,
+}
+
+pub struct OptimizerMetrics {
+    searches: AtomicU64,
+    generations: AtomicU64,
+    heuristic_hits: AtomicU64,
+    heuristic_misses: AtomicU64,
+    pheromone_hits: AtomicU64,
+    pheromone_misses: AtomicU64,
+    durations: Mutex<Histogram>,
+    /// Duration in seconds and generations per second of the last search of each route
+    last_searches: Mutex<BTreeMap<String, (f64, f64)>>,
+}
+
+impl OptimizerMetrics {
+    const fn new() -> OptimizerMetrics {
+        OptimizerMetrics {
+            searches: AtomicU64::new(0),
+            generations: AtomicU64::new(0),
+            heuristic_hits: AtomicU64::new(0),
+            heuristic_misses: AtomicU64::new(0),
+            pheromone_hits: AtomicU64::new(0),
+            pheromone_misses: AtomicU64::new(0),
+            durations: Mutex::new(Histogram::new(SEARCH_BUCKETS)),
+            last_searches: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Record a finished search of a route, chunks of a route optimized in chunks count as
+    /// searches of their own
+    pub fn record_search(&self, stats: SearchStats) {
+        let secs = stats.duration.as_secs_f64();
+        self.searches.fetch_add(1, Ordering::Relaxed);
+        self.generations
+            .fetch_add(stats.generations as u64, Ordering::Relaxed);
+        self.heuristic_hits
+            .fetch_add(stats.heuristic_hits, Ordering::Relaxed);
+        self.heuristic_misses
+            .fetch_add(stats.heuristic_misses, Ordering::Relaxed);
+        self.pheromone_hits
+            .fetch_add(stats.pheromone_hits, Ordering::Relaxed);
+        self.pheromone_misses
+            .fetch_add(stats.pheromone_misses, Ordering::Relaxed);
+        self.durations.lock().unwrap().observe(secs);
+        let rate = if secs > 0.0 {
+            stats.generations as f64 / secs
+        } else {
+            0.0
+        };
+        self.last_searches
+            .lock()
+            .unwrap()
+            .insert(stats.route_id.to_string(), (secs, rate));
+    }
+
+    /// Write the metrics in the Prometheus text format
+    pub fn write(&self, out: &mut String) {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let counters = [
+            (
+                "route_service_route_searches_total",
+                "Route searches run by the optimizer",
+                load(&self.searches),
+            ),
+            (
+                "route_service_aco_generations_total",
+                "ACO generations run over all route searches",
+                load(&self.generations),
+            ),
+        ];
+        for (name, help, value) in counters {
+            write_header(out, name, "counter", help);
+            writeln!(out, "{name} {value}").unwrap();
+        }
+
+        let caches = [
+            (
+                "heuristic",
+                load(&self.heuristic_hits),
+                load(&self.heuristic_misses),
+            ),
+            (
+                "pheromone",
+                load(&self.pheromone_hits),
+                load(&self.pheromone_misses),
+            ),
+        ];
+        let name = "route_service_optimizer_cache_hits_total";
+        write_header(out, name, "counter", "Lookups answered from the map");
+        for (cache, hits, _) in caches {
+            writeln!(out, "{name}{{cache=\"{cache}\"}} {hits}").unwrap();
+        }
+        let name = "route_service_optimizer_cache_misses_total";
+        write_header(out, name, "counter", "Lookups the map had no entry for");
+        for (cache, _, misses) in caches {
+            writeln!(out, "{name}{{cache=\"{cache}\"}} {misses}").unwrap();
+        }
+        let name = "route_service_optimizer_cache_hit_ratio";
+        write_header(
+            out,
+            name,
+            "gauge",
+            "Share of the lookups answered from the map",
+        );
+        for (cache, hits, misses) in caches {
+            let ratio = match hits + misses {
+                0 => 0.0,
+                lookups => hits as f64 / lookups as f64,
+            };
+            writeln!(out, "{name}{{cache=\"{cache}\"}} {ratio}").unwrap();
+        }
+
+        let name = "route_service_route_search_seconds";
+        write_header(out, name, "histogram", "Duration of the route searches");
+        self.durations.lock().unwrap().write(out, name, "");
+
+        let last_searches = self.last_searches.lock().unwrap();
+        let name = "route_service_route_last_search_seconds";
+        write_header(
+            out,
+            name,
+            "gauge",
+            "Duration of the last search of each route",
+        );
+        for (route_id, (secs, _)) in last_searches.iter() {
+            writeln!(out, "{name}{{route_id=\"{}\"}} {secs}", escape(route_id)).unwrap();
+        }
+        let name = "route_service_aco_generations_per_second";
+        write_header(
+            out,
+            name,
+            "gauge",
+            "ACO generations per second of the last search of each route",
+        );
+        for (route_id, (_, rate)) in last_searches.iter() {
+            writeln!(out, "{name}{{route_id=\"{}\"}} {rate}", escape(route_id)).unwrap();
+        }
+    }
+}
+
+/// Escape a label value of the Prometheus text format
+pub fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::new(&[1.0, 5.0]);
+        for value in [0.5, 2.0, 7.0] {
+            histogram.observe(value);
+        }
+        let mut out = String::new();
+        histogram.write(&mut out, "latency", "endpoint=\"/grid\"");
+        assert_eq!(
+            out,
+            "latency_bucket{endpoint=\"/grid\",le=\"1\"} 1\n\
+             latency_bucket{endpoint=\"/grid\",le=\"5\"} 2\n\
+             latency_bucket{endpoint=\"/grid\",le=\"+Inf\"} 3\n\
+             latency_sum{endpoint=\"/grid\"} 9.5\n\
+             latency_count{endpoint=\"/grid\"} 3\n"
+        );
+    }
+}
@@ -1,5 +1,35 @@
+pub mod accessibility;
 pub mod aco;
 pub mod aco2;
-mod consts;
+pub mod alignment;
+pub mod area;
+pub mod audit;
+pub mod budget;
+pub mod checkpoint;
+pub mod consolidate;
+pub mod constraints;
+pub(crate) mod consts;
+pub mod corridor;
+pub mod demand;
+pub mod equity;
 pub mod eval;
+pub mod express;
+pub mod frequency;
+pub mod gtfs_export;
+pub mod inbound;
 pub mod ga_params;
+pub mod metrics;
+pub mod network_diff;
+pub mod new_route;
+pub mod objective;
+pub mod pareto;
+pub mod progress;
+pub mod queue;
+pub mod resources;
+pub mod review;
+pub mod sa;
+pub mod scenario;
+pub mod search;
+pub mod timetable;
+pub mod validation;
+pub mod walking;
@@ -0,0 +1,633 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use geo_types::Point;
+
+use crate::layers::{
+    geo_util,
+    grid::GridNetwork,
+    transit_network::{TransitNetwork, TransitRoute},
+};
+
+use super::eval::TransitRouteEvals;
+use super::resources::ResourceUsage;
+use crate::ordering;
+
+/// Summary of a set of values
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Distribution {
+    pub count: usize,
+    pub min: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl Distribution {
+    /// Summarize values, NaN values are ignored
+    pub fn new(values: &[f64]) -> Distribution {
+        let mut sorted: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+        if sorted.is_empty() {
+            return Distribution::default();
+        }
+        sorted.sort_by(|a, b| ordering::cmp_f64(*a, *b));
+
+        // nearest rank
+        let percentile = |p: f64| sorted[((sorted.len() - 1) as f64 * p).round() as usize];
+        Distribution {
+            count: sorted.len(),
+            min: sorted[0],
+            p25: percentile(0.25),
+            median: percentile(0.5),
+            p75: percentile(0.75),
+            max: sorted[sorted.len() - 1],
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+        }
+    }
+}
+
+/// Aggregate changes an optimization made to a transit network
+///
+/// Stops and zones are compared across the whole network, so a stop dropped from an optimized
+/// route but still served by another route is not counted as removed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct NetworkDiff {
+    /// Routes whose outbound stops changed
+    pub routes_changed: usize,
+    /// Straight-line length in km of the stop to stop segments only found after the optimization
+    pub route_km_added: f64,
+    /// Straight-line length in km of the stop to stop segments only found before the optimization
+    pub route_km_removed: f64,
+    /// Stops that no route served before the optimization
+    pub stops_added: usize,
+    /// Stops that no route serves after the optimization
+    pub stops_removed: usize,
+    /// Zones within walking distance of a stop only after the optimization
+    pub zones_gaining_service: usize,
+    /// Zones within walking distance of a stop only before the optimization
+    pub zones_losing_service: usize,
+    /// Change of the average ridership of each changed route, in percent
+    pub improvement_pct: Distribution,
+}
+
+impl NetworkDiff {
+    /// Compare a network with the versions of some of its routes before they were optimized
+    ///
+    /// # Arguments
+    /// - `before`: Routes before the optimization, routes not in `after` are ignored
+    /// - `after`: The network after the optimization
+    ///
+    /// # Returns
+    /// - The changes between the network with `before` routes and `after`
+    pub fn new(before: &[TransitRoute], after: &TransitNetwork) -> NetworkDiff {
+        let after_routes: HashMap<&str, &TransitRoute> = after
+            .routes
+            .iter()
+            .map(|r| (r.route_id.as_str(), r))
+            .collect();
+        let before_routes: HashMap<&str, &TransitRoute> = before
+            .iter()
+            .filter(|r| after_routes.contains_key(r.route_id.as_str()))
+            .map(|r| (r.route_id.as_str(), r))
+            .collect();
+
+        let mut diff = NetworkDiff::default();
+        let mut improvements = vec![];
+        for (route_id, old) in &before_routes {
+            let new = after_routes[route_id];
+            if stop_ids(old) == stop_ids(new) {
+                continue;
+            }
+            diff.routes_changed += 1;
+
+            let (old_segments, new_segments) = (segments(old), segments(new));
+            diff.route_km_added += new_segments
+                .iter()
+                .filter(|(k, _)| !old_segments.contains_key(k))
+                .map(|(_, km)| km)
+                .sum::<f64>();
+            diff.route_km_removed += old_segments
+                .iter()
+                .filter(|(k, _)| !new_segments.contains_key(k))
+                .map(|(_, km)| km)
+                .sum::<f64>();
+
+            if let (Some(old_evals), Some(new_evals)) = (&old.evals, &new.evals) {
+                if old_evals.avg_ridership > 0.0 {
+                    improvements.push(
+                        (new_evals.avg_ridership - old_evals.avg_ridership)
+                            / old_evals.avg_ridership
+                            * 100.0,
+                    );
+                }
+            }
+        }
+        diff.improvement_pct = Distribution::new(&improvements);
+
+        // the network before the optimization, with the changed routes swapped back
+        let network_before: Vec<&TransitRoute> = after
+            .routes
+            .iter()
+            .map(|r| *before_routes.get(r.route_id.as_str()).unwrap_or(&r))
+            .collect();
+        let network_after: Vec<&TransitRoute> = after.routes.iter().collect();
+
+        let (stops_before, zones_before) = served(&network_before);
+        let (stops_after, zones_after) = served(&network_after);
+        diff.stops_added = stops_after.difference(&stops_before).count();
+        diff.stops_removed = stops_before.difference(&stops_after).count();
+        diff.zones_gaining_service = zones_after.difference(&zones_before).count();
+        diff.zones_losing_service = zones_before.difference(&zones_after).count();
+        diff
+    }
+}
+
+/// Differences between two versions of a route, e.g. optimized with different parameters
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RouteDiff {
+    /// Whether both versions serve the same outbound stops in the same order
+    pub identical: bool,
+    /// Outbound stops of the first version that the second does not serve, in stop order
+    pub stops_only_in_first: Vec<String>,
+    /// Outbound stops of the second version that the first does not serve, in stop order
+    pub stops_only_in_second: Vec<String>,
+    /// Outbound stops served by both versions
+    pub shared_stops: usize,
+    /// Straight-line length in km of the outbound stop to stop segments of the second version
+    /// minus that of the first
+    pub route_km_delta: f64,
+    /// Average ridership of the second version minus that of the first, if both have evals
+    pub avg_ridership_delta: Option<f64>,
+    pub coverage_delta: Option<f64>,
+    pub economic_score_delta: Option<f64>,
+}
+
+impl RouteDiff {
+    pub fn new(first: &TransitRoute, second: &TransitRoute) -> RouteDiff {
+        let (first_stops, second_stops) = (stop_ids(first), stop_ids(second));
+        let (first_set, second_set): (HashSet<&str>, HashSet<&str>) = (
+            first_stops.iter().copied().collect(),
+            second_stops.iter().copied().collect(),
+        );
+        let only_in = |stops: &[&str], other: &HashSet<&str>| -> Vec<String> {
+            stops
+                .iter()
+                .filter(|s| !other.contains(*s))
+                .map(|s| s.to_string())
+                .collect()
+        };
+        let km = |route: &TransitRoute| segments(route).values().sum::<f64>();
+        let delta = |f: fn(&TransitRouteEvals) -> f64| match (&first.evals, &second.evals) {
+            (Some(a), Some(b)) => Some(f(b) - f(a)),
+            _ => None,
+        };
+        RouteDiff {
+            identical: first_stops == second_stops,
+            stops_only_in_first: only_in(&first_stops, &second_set),
+            stops_only_in_second: only_in(&second_stops, &first_set),
+            shared_stops: first_set.intersection(&second_set).count(),
+            route_km_delta: km(second) - km(first),
+            avg_ridership_delta: delta(|e| e.avg_ridership),
+            coverage_delta: delta(|e| e.coverage),
+            economic_score_delta: delta(|e| e.economic_score),
+        }
+    }
+}
+
+/// How the service at a stop changed with an optimization
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum StopImpactKind {
+    /// No route served the stop before the optimization
+    Gained,
+    /// No route serves the stop after the optimization
+    Lost,
+    /// Routes serve the stop before and after the optimization, not necessarily the same ones
+    Unchanged,
+}
+
+/// Routes serving a stop before and after an optimization
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StopImpact {
+    pub stop_id: String,
+    pub lon: f64,
+    pub lat: f64,
+    pub impact: StopImpactKind,
+    /// Routes serving the stop in either direction before the optimization, sorted
+    pub routes_before: Vec<String>,
+    /// Routes serving the stop in either direction after the optimization, sorted
+    pub routes_after: Vec<String>,
+}
+
+impl StopImpact {
+    /// Impact of an optimization on every stop served before or after it
+    ///
+    /// # Arguments
+    /// - `before`: Routes before the optimization, routes not in `after` are ignored
+    /// - `after`: The network after the optimization
+    ///
+    /// # Returns
+    /// - The stops sorted by stop id
+    pub fn for_network(before: &[TransitRoute], after: &TransitNetwork) -> Vec<StopImpact> {
+        let before_routes: HashMap<&str, &TransitRoute> =
+            before.iter().map(|r| (r.route_id.as_str(), r)).collect();
+        // the network before the optimization, with the changed routes swapped back
+        let network_before: Vec<&TransitRoute> = after
+            .routes
+            .iter()
+            .map(|r| *before_routes.get(r.route_id.as_str()).unwrap_or(&r))
+            .collect();
+        let network_after: Vec<&TransitRoute> = after.routes.iter().collect();
+
+        let mut stops: BTreeMap<&str, StopImpact> = BTreeMap::new();
+        for (routes, is_after) in [(&network_before, false), (&network_after, true)] {
+            for route in routes {
+                let mut seen = HashSet::new();
+                for stop in route
+                    .outbound_stops
+                    .iter()
+                    .chain(route.inbound_stops.iter())
+                {
+                    let stop_id = stop.stop_id.as_str();
+                    if !seen.insert(stop_id) {
+                        continue;
+                    }
+                    let impact = stops.entry(stop_id).or_insert_with(|| StopImpact {
+                        stop_id: stop_id.to_string(),
+                        lon: stop.geom.x(),
+                        lat: stop.geom.y(),
+                        impact: StopImpactKind::Unchanged,
+                        routes_before: vec![],
+                        routes_after: vec![],
+                    });
+                    match is_after {
+                        false => impact.routes_before.push(route.route_id.clone()),
+                        true => impact.routes_after.push(route.route_id.clone()),
+                    }
+                }
+            }
+        }
+
+        stops
+            .into_values()
+            .map(|mut stop| {
+                stop.routes_before.sort();
+                stop.routes_after.sort();
+                stop.impact = match (stop.routes_before.is_empty(), stop.routes_after.is_empty()) {
+                    (true, _) => StopImpactKind::Gained,
+                    (_, true) => StopImpactKind::Lost,
+                    _ => StopImpactKind::Unchanged,
+                };
+                stop
+            })
+            .collect()
+    }
+}
+
+/// Farthest in meters a stop can be from a stop the route no longer serves for the route to
+/// count as moving the stop, rather than as removing one stop and adding another
+const MAX_STOP_MOVE_M: f64 = 400.0;
+
+/// Whether a route is only in one of two networks or changed between them
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// A stop of a route replaced by a stop nearby
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MovedStop {
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    /// Straight-line distance between the two stops
+    pub meters: f64,
+}
+
+/// How the outbound stops of a route changed between two networks
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RouteChange {
+    pub route_id: String,
+    pub change: RouteChangeKind,
+    /// Stops only served after, other than those a stop moved to, in stop order
+    pub added_stops: Vec<String>,
+    /// Stops only served before, other than those moved, in stop order
+    pub removed_stops: Vec<String>,
+    /// Stops replaced by a stop within `MAX_STOP_MOVE_M` meters, in the order of the stops
+    /// served before
+    pub moved_stops: Vec<MovedStop>,
+    /// Straight-line length in km of the outbound stop to stop segments, 0 for a route missing
+    /// from the network
+    pub length_km_before: f64,
+    pub length_km_after: f64,
+}
+
+/// A zone that gained or lost a stop within walking distance
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ZoneChange {
+    pub zone_id: u32,
+    /// Whether the zone gained service, or lost it
+    pub gained: bool,
+    pub population: u32,
+    pub jobs: u32,
+}
+
+/// Changes between an original network and an optimized one, route by route and zone by zone,
+/// for a summary of the changes rather than an overlay of both networks
+///
+/// Unlike `NetworkDiff`, routes added or removed by the optimization count as changes.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DetailedNetworkDiff {
+    /// Routes whose outbound stops changed, sorted by route id
+    pub routes: Vec<RouteChange>,
+    /// Zones whose walking access to the outbound stops of the network changed, sorted by
+    /// zone id
+    pub zones: Vec<ZoneChange>,
+    pub population_gaining_service: u64,
+    pub population_losing_service: u64,
+    /// Straight-line length in km of the routes of each network
+    pub length_km_before: f64,
+    pub length_km_after: f64,
+}
+
+impl DetailedNetworkDiff {
+    /// Compare two networks
+    ///
+    /// # Arguments
+    /// - `before`: Routes of the original network
+    /// - `after`: Routes of the optimized network
+    /// - `grid`: Zones of the city, for the population and jobs of the zones that changed
+    pub fn new(
+        before: &[TransitRoute],
+        after: &[TransitRoute],
+        grid: &GridNetwork,
+    ) -> DetailedNetworkDiff {
+        let by_id = |routes: &'_ [TransitRoute]| -> BTreeMap<String, usize> {
+            routes
+                .iter()
+                .enumerate()
+                .map(|(i, r)| (r.route_id.clone(), i))
+                .collect()
+        };
+        let (before_ids, after_ids) = (by_id(before), by_id(after));
+        let route_ids: BTreeSet<&String> = before_ids.keys().chain(after_ids.keys()).collect();
+
+        let mut diff = DetailedNetworkDiff::default();
+        for route_id in route_ids {
+            let old = before_ids.get(route_id).map(|&i| &before[i]);
+            let new = after_ids.get(route_id).map(|&i| &after[i]);
+            let change = match (old, new) {
+                (None, Some(_)) => RouteChangeKind::Added,
+                (Some(_), None) => RouteChangeKind::Removed,
+                (Some(old), Some(new)) if stop_ids(old) != stop_ids(new) => {
+                    RouteChangeKind::Changed
+                }
+                _ => continue,
+            };
+            let (old_stops, new_stops) = (stop_points(old), stop_points(new));
+            let (removed, added) = (
+                stops_only_in(&old_stops, &new_stops),
+                stops_only_in(&new_stops, &old_stops),
+            );
+            let moved_stops = match_moved_stops(&removed, &added);
+            let is_moved = |id: &&str| {
+                moved_stops
+                    .iter()
+                    .any(|m| m.from_stop_id == *id || m.to_stop_id == *id)
+            };
+            let length = |route: Option<&TransitRoute>| {
+                route.map_or(0.0, |r| segments(r).values().sum::<f64>())
+            };
+            diff.routes.push(RouteChange {
+                route_id: route_id.clone(),
+                change,
+                added_stops: added
+                    .iter()
+                    .map(|(id, _)| id)
+                    .filter(|id| !is_moved(id))
+                    .map(|id| id.to_string())
+                    .collect(),
+                removed_stops: removed
+                    .iter()
+                    .map(|(id, _)| id)
+                    .filter(|id| !is_moved(id))
+                    .map(|id| id.to_string())
+                    .collect(),
+                moved_stops,
+                length_km_before: length(old),
+                length_km_after: length(new),
+            });
+        }
+
+        let total_km = |routes: &[TransitRoute]| {
+            routes
+                .iter()
+                .map(|r| segments(r).values().sum::<f64>())
+                .sum::<f64>()
+        };
+        diff.length_km_before = total_km(before);
+        diff.length_km_after = total_km(after);
+
+        let (_, zones_before) = served(&before.iter().collect::<Vec<_>>());
+        let (_, zones_after) = served(&after.iter().collect::<Vec<_>>());
+        let mut changed: Vec<(u32, bool)> = zones_after
+            .difference(&zones_before)
+            .map(|&z| (z, true))
+            .chain(zones_before.difference(&zones_after).map(|&z| (z, false)))
+            .collect();
+        changed.sort();
+        for (zone_id, gained) in changed {
+            let Some(zone) = grid.find_zone_idx_by_id(zone_id).map(|i| grid.get_zone(i)) else {
+                continue;
+            };
+            match gained {
+                true => diff.population_gaining_service += zone.population as u64,
+                false => diff.population_losing_service += zone.population as u64,
+            }
+            diff.zones.push(ZoneChange {
+                zone_id,
+                gained,
+                population: zone.population,
+                jobs: zone.jobs,
+            });
+        }
+        diff
+    }
+}
+
+/// Pair stops a route no longer serves with stops it newly serves nearby, the closest pairs
+/// first, each stop in at most one pair
+///
+/// # Returns
+/// The pairs within `MAX_STOP_MOVE_M` meters, in the order of `removed`
+fn match_moved_stops(
+    removed: &[(&str, Point<f64>)],
+    added: &[(&str, Point<f64>)],
+) -> Vec<MovedStop> {
+    let mut pairs = vec![];
+    for (i, (_, from)) in removed.iter().enumerate() {
+        for (j, (_, to)) in added.iter().enumerate() {
+            let meters = geo_util::haversine(from.x(), from.y(), to.x(), to.y());
+            if meters <= MAX_STOP_MOVE_M {
+                pairs.push((meters, i, j));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| ordering::cmp_f64(a.0, b.0).then((a.1, a.2).cmp(&(b.1, b.2))));
+
+    let mut moves: Vec<(usize, MovedStop)> = vec![];
+    let (mut used_removed, mut used_added) = (HashSet::new(), HashSet::new());
+    for (meters, i, j) in pairs {
+        if used_removed.contains(&i) || used_added.contains(&j) {
+            continue;
+        }
+        used_removed.insert(i);
+        used_added.insert(j);
+        moves.push((
+            i,
+            MovedStop {
+                from_stop_id: removed[i].0.to_string(),
+                to_stop_id: added[j].0.to_string(),
+                meters,
+            },
+        ));
+    }
+    moves.sort_by_key(|(i, _)| *i);
+    moves.into_iter().map(|(_, m)| m).collect()
+}
+
+fn stop_points(route: Option<&TransitRoute>) -> Vec<(&str, Point<f64>)> {
+    route
+        .into_iter()
+        .flat_map(|r| r.outbound_stops.iter())
+        .map(|s| (s.stop_id.as_str(), s.geom))
+        .collect()
+}
+
+/// Stops of `stops` missing from `other`, in the order of `stops`
+fn stops_only_in<'a>(
+    stops: &[(&'a str, Point<f64>)],
+    other: &[(&str, Point<f64>)],
+) -> Vec<(&'a str, Point<f64>)> {
+    let other: HashSet<&str> = other.iter().map(|(id, _)| *id).collect();
+    stops
+        .iter()
+        .filter(|(id, _)| !other.contains(id))
+        .copied()
+        .collect()
+}
+
+fn stop_ids(route: &TransitRoute) -> Vec<&str> {
+    route
+        .outbound_stops
+        .iter()
+        .map(|s| s.stop_id.as_str())
+        .collect()
+}
+
+/// Length in km of each consecutive pair of outbound stops
+fn segments(route: &TransitRoute) -> HashMap<(&str, &str), f64> {
+    route
+        .outbound_stops
+        .windows(2)
+        .map(|w| {
+            let km =
+                geo_util::haversine(w[0].geom.x(), w[0].geom.y(), w[1].geom.x(), w[1].geom.y())
+                    / 1000.0;
+            ((w[0].stop_id.as_str(), w[1].stop_id.as_str()), km)
+        })
+        .collect()
+}
+
+/// Stops served by the outbound direction of the routes and the zones within walking
+/// distance of them
+fn served<'a>(routes: &[&'a TransitRoute]) -> (HashSet<&'a str>, HashSet<u32>) {
+    let mut stops = HashSet::new();
+    let mut zones = HashSet::new();
+    for stop in routes.iter().flat_map(|r| r.outbound_stops.iter()) {
+        if stops.insert(stop.stop_id.as_str()) {
+            zones.extend(stop.nearby_zone_ids().iter().copied());
+        }
+    }
+    (stops, zones)
+}
+
+/// A finished optimization run, kept to follow the changes made to a city over time
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RunRecord {
+    /// Kind of run, e.g. `optimize-routes`
+    pub job: String,
+    /// End of the run in RFC 3339 format
+    pub finished_at: String,
+    pub duration_ms: u128,
+    pub routes_requested: usize,
+    pub optimized_route_ids: Vec<String>,
+    pub diff: NetworkDiff,
+    /// Time and memory the run used, missing from runs recorded before it was tracked
+    #[serde(default)]
+    pub resources: Option<ResourceUsage>,
+}
+
+/// KPIs of a network as reported by evaluate-network
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NetworkKpis {
+    pub coverage: f64,
+    pub economic_score: f64,
+    pub avg_transfers: f64,
+    /// Minutes waited at transfers on average
+    pub avg_transfer_wait: f64,
+    /// Minutes of transfers and waiting added to trips on average
+    pub avg_impedance: f64,
+    pub avg_ridership: f64,
+    pub transit_score: f64,
+}
+
+/// KPIs of the original and optimized networks at the time they were evaluated, kept to chart
+/// how the network evolves over time
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KpiRecord {
+    /// Time of the evaluation in RFC 3339 format
+    pub recorded_at: String,
+    /// Whether optimizations not yet accepted counted towards the optimized network
+    pub include_proposed: bool,
+    /// Optimized routes in the evaluated network
+    pub optimized_routes: usize,
+    pub original: NetworkKpis,
+    pub optimized: NetworkKpis,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distribution_uses_nearest_rank() {
+        let dist = Distribution::new(&[5.0, f64::NAN, 1.0, 3.0, 2.0, 4.0]);
+        assert_eq!(dist.count, 5);
+        assert_eq!((dist.min, dist.max), (1.0, 5.0));
+        assert_eq!((dist.p25, dist.median, dist.p75), (2.0, 3.0, 4.0));
+        assert_eq!(dist.mean, 3.0);
+        assert_eq!(Distribution::new(&[]), Distribution::default());
+    }
+
+    #[test]
+    fn nearest_added_stops_count_as_moves() {
+        // about 111m per 0.001 degree of latitude
+        let at = |lat: f64| Point::new(-79.4, 43.65 + lat);
+        let removed = [("a", at(0.0)), ("b", at(0.01)), ("c", at(0.02))];
+        let added = [("x", at(0.0105)), ("y", at(0.001)), ("z", at(0.05))];
+        let moves = match_moved_stops(&removed, &added);
+        let pairs: Vec<(&str, &str)> = moves
+            .iter()
+            .map(|m| (m.from_stop_id.as_str(), m.to_stop_id.as_str()))
+            .collect();
+        // c is too far from any added stop and z from any removed one
+        assert_eq!(pairs, vec![("a", "y"), ("b", "x")]);
+        assert!((moves[0].meters - 111.0).abs() < 1.0, "{}", moves[0].meters);
+        assert!(match_moved_stops(&removed, &[]).is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+use geo::{Centroid, Distance, Haversine};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::layers::{
+    city::City,
+    transit_network::{TransitNetwork, TransitRoute, TransitRouteType, TransitStop},
+};
+
+use super::accessibility::DEFAULT_HEADWAY_MIN;
+use super::aco2::{self, SearchContext, ACO};
+use super::eval::TransitRouteEvals;
+use super::express::departures_for_headway;
+use super::inbound;
+
+/// Prepended to the number of a route created from scratch to form its id
+pub const NEW_ROUTE_PREFIX: &str = "new-";
+
+/// An end of a new route, e.g. `{"stop": "1234"}` or `{"zone": 42}`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Terminal {
+    /// A stop of the network
+    Stop(String),
+    /// A zone, served by the stop of the network nearest to its centroid
+    Zone(u32),
+}
+
+impl Terminal {
+    fn resolve(&self, city: &City, transit: &TransitNetwork) -> Result<Arc<TransitStop>, String> {
+        match self {
+            Terminal::Stop(stop_id) => transit
+                .outbound_stops
+                .iter()
+                .chain(transit.inbound_stops.iter())
+                .map(|node| &node.stop)
+                .find(|stop| &stop.stop_id == stop_id)
+                .cloned()
+                .ok_or_else(|| format!("Stop {} not found", stop_id)),
+            Terminal::Zone(zoneid) => {
+                let zone = city
+                    .grid
+                    .find_zone_idx_by_id(*zoneid)
+                    .map(|idx| city.grid.get_zone(idx))
+                    .ok_or_else(|| format!("Zone {} not found", zoneid))?;
+                let centroid = zone
+                    .polygon
+                    .centroid()
+                    .ok_or_else(|| format!("Zone {} has no area", zoneid))?;
+                transit
+                    .outbound_stops
+                    .nearest_neighbor(&[centroid.x(), centroid.y()])
+                    .map(|node| node.stop.clone())
+                    .ok_or_else(|| "The network has no stops".to_string())
+            }
+        }
+    }
+}
+
+/// Where a new route runs and how often
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NewRouteParams {
+    /// First stop of the outbound direction
+    pub from: Terminal,
+    /// Last stop of the outbound direction
+    pub to: Terminal,
+    /// Minutes between departures in every time period
+    #[serde(default = "default_headway_minutes")]
+    pub headway_minutes: f64,
+}
+
+fn default_headway_minutes() -> f64 {
+    DEFAULT_HEADWAY_MIN
+}
+
+/// Create a bus route between two terminals that no route of the network runs yet
+///
+/// # Parameters
+/// - `aco`: ACO parameters the route is searched with
+/// - `params`: Terminals and headway of the route
+/// - `city`: The city the route runs in
+/// - `transit`: Network the route is added to, whose stops it serves
+///
+/// # Returns
+/// The route, with a fresh id, its inbound stops and its evals, or an error if the terminals
+/// cannot be found or joined by road
+///
+/// # Notes
+/// - The ants start from a seed route that follows the shortest road path between the
+///   terminals, stopping at the stop of the network nearest to the path every
+///   `avg_stop_dist` metres. The seed is kept if they find nothing better.
+/// - The inbound direction runs back along the outbound stops, see `inbound::mirror_inbound`
+pub fn create_route(
+    aco: ACO,
+    params: &NewRouteParams,
+    city: &City,
+    transit: &TransitNetwork,
+) -> Result<TransitRoute, String> {
+    if !(params.headway_minutes.is_finite() && params.headway_minutes > 0.0) {
+        return Err("headway_minutes must be positive".to_string());
+    }
+    let from = params.from.resolve(city, transit)?;
+    let to = params.to.resolve(city, transit)?;
+    if from.stop_id == to.stop_id {
+        return Err(format!(
+            "Both terminals are served by stop {}",
+            from.stop_id
+        ));
+    }
+
+    let seed = TransitRoute {
+        route_id: next_route_id(city, transit),
+        route_type: TransitRouteType::Bus,
+        outbound_stops: seed_stops(&from, &to, aco.avg_stop_dist, city, transit)?,
+        inbound_stops: vec![],
+        evals: None,
+        stop_times: departures_for_headway(params.headway_minutes),
+        service_span: None,
+        vehicle: None,
+    };
+    let (result, _) = aco2::run_aco_from_seed(
+        aco,
+        &seed,
+        None,
+        &mut SearchContext {
+            city,
+            opt_transit: transit,
+            area: None,
+            deadline: None,
+            on_progress: &mut |_| {},
+        },
+    );
+    let mut route = result.map_or(seed, |(route, _)| route);
+    route.inbound_stops = inbound::mirror_inbound(&route.outbound_stops, transit, &city.road);
+    route.evals = Some(TransitRouteEvals::for_route(
+        transit,
+        &route,
+        &city.grid,
+        &city.search,
+    ));
+    Ok(route)
+}
+
+/// First id `{NEW_ROUTE_PREFIX}{n}` neither the feed nor the network uses
+fn next_route_id(city: &City, transit: &TransitNetwork) -> String {
+    let used: HashSet<&str> = city
+        .gtfs
+        .routes
+        .keys()
+        .map(|id| id.as_str())
+        .chain(transit.routes.iter().map(|r| r.route_id.as_str()))
+        .collect();
+    (1..)
+        .map(|n| format!("{}{}", NEW_ROUTE_PREFIX, n))
+        .find(|id| !used.contains(id.as_str()))
+        .unwrap()
+}
+
+/// Outbound stops along the shortest road path between two stops
+fn seed_stops(
+    from: &Arc<TransitStop>,
+    to: &Arc<TransitStop>,
+    stop_dist: f64,
+    city: &City,
+    transit: &TransitNetwork,
+) -> Result<Vec<Arc<TransitStop>>, String> {
+    let (_, path) = from.road_distance(to, &city.road);
+    let joined = match (
+        from.get_node_index(&city.road),
+        to.get_node_index(&city.road),
+    ) {
+        (Some(a), Some(b)) => a == b || !path.is_empty(),
+        _ => true,
+    };
+    if !joined {
+        return Err(format!(
+            "No road path from stop {} to stop {}",
+            from.stop_id, to.stop_id
+        ));
+    }
+
+    let mut stops = vec![from.clone()];
+    let mut used: HashSet<&str> = HashSet::from([from.stop_id.as_str(), to.stop_id.as_str()]);
+    let mut travelled = 0.0;
+    for pair in path.windows(2) {
+        let (a, b) = (
+            city.road.get_node(pair[0]).geom,
+            city.road.get_node(pair[1]).geom,
+        );
+        travelled += Haversine::distance(a, b);
+        if travelled < stop_dist {
+            continue;
+        }
+        let next = transit
+            .outbound_stops
+            .nearest_neighbor_iter(&[b.x(), b.y()])
+            .map(|node| &node.stop)
+            .take_while(|stop| Haversine::distance(stop.geom, b) <= stop_dist / 2.0)
+            .find(|stop| !used.contains(stop.stop_id.as_str()));
+        if let Some(next) = next {
+            used.insert(next.stop_id.as_str());
+            stops.push(next.clone());
+            travelled = 0.0;
+        }
+    }
+    stops.push(to.clone());
+    Ok(stops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+    #[test]
+    fn creates_a_route_between_terminals() {
+        let city = load_demo_city(
+            &format!("new_route_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 2,
+                ..Default::default()
+            },
+        );
+
+        // from the start of one route to the end of another
+        let from = city.transit.routes[0].outbound_stops[0].clone();
+        let to = city.transit.routes[1]
+            .outbound_stops
+            .last()
+            .unwrap()
+            .clone();
+        let params = NewRouteParams {
+            from: Terminal::Stop(from.stop_id.clone()),
+            to: Terminal::Stop(to.stop_id.clone()),
+            headway_minutes: 15.0,
+        };
+        let route = create_route(ACO::init(), &params, &city, &city.transit).unwrap();
+        assert_eq!(route.route_id, format!("{}1", NEW_ROUTE_PREFIX));
+        assert_eq!(route.outbound_stops[0].stop_id, from.stop_id);
+        assert_eq!(route.outbound_stops.last().unwrap().stop_id, to.stop_id);
+        assert!(!route.inbound_stops.is_empty());
+        assert!(route.evals.is_some());
+        assert_eq!(route.stop_times, departures_for_headway(15.0));
+
+        let zone = Terminal::Zone(to.zone_id().unwrap());
+        assert!(zone.resolve(&city, &city.transit).is_ok());
+        assert!(Terminal::Zone(u32::MAX)
+            .resolve(&city, &city.transit)
+            .is_err());
+        let missing = NewRouteParams {
+            from: Terminal::Stop("missing".to_string()),
+            ..params.clone()
+        };
+        assert!(create_route(ACO::init(), &missing, &city, &city.transit).is_err());
+        let same = NewRouteParams {
+            to: Terminal::Stop(from.stop_id.clone()),
+            ..params
+        };
+        assert!(create_route(ACO::init(), &same, &city, &city.transit).is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+use crate::layers::{city::City, transit_network::TransitRoute};
+
+use super::accessibility;
+use super::aco2::ACO;
+
+/// A term of the score ACO maximizes when optimizing a route
+///
+/// Every objective is a benefit per km of road the route runs on, so that terms can be mixed
+/// in an `ObjectiveSpec`. Penalties for the shape of the route are applied on top of the
+/// combined score by `aco2::evaluate_route`.
+pub trait ObjectiveFn: Sync {
+    /// Name the objective is referred to by in an `ObjectiveSpec`
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    fn score(&self, route: &RouteMeasures) -> f64;
+}
+
+/// Every objective an `ObjectiveSpec` can combine
+pub static OBJECTIVES: &[&dyn ObjectiveFn] =
+    &[&DemandPerKm, &CoverageGain, &TransferImpact, &Equity];
+
+/// The objective with the given name
+pub fn lookup(name: &str) -> Option<&'static dyn ObjectiveFn> {
+    OBJECTIVES.iter().copied().find(|o| o.name() == name)
+}
+
+/// What the objectives need to know about a route being scored
+pub struct RouteMeasures<'a> {
+    pub params: &'a ACO,
+    pub route: &'a TransitRoute,
+    pub city: &'a City,
+    /// Number of routes serving each pair of zones, by zone id
+    pub zone_to_zone_coverage: &'a HashMap<(u32, u32), u32>,
+    /// Zones of the stops in stop order, without repeats
+    pub zones: Vec<NodeIndex>,
+    /// Number of stops in each zone of `zones`
+    pub stops_per_zone: HashMap<NodeIndex, usize>,
+    /// Length of the route along the road network in km
+    pub road_km: f64,
+    /// Road distance over the straight-line distance between the first and last stop
+    pub nonlinearity: f64,
+}
+
+impl<'a> RouteMeasures<'a> {
+    pub fn new(
+        params: &'a ACO,
+        route: &'a TransitRoute,
+        city: &'a City,
+        zone_to_zone_coverage: &'a HashMap<(u32, u32), u32>,
+        road_km: f64,
+        nonlinearity: f64,
+    ) -> RouteMeasures<'a> {
+        let mut zones = vec![];
+        let mut stops_per_zone = HashMap::new();
+        for stop in &route.outbound_stops {
+            // possible dont add demand for bad stops to not reward them
+            if let Some(zone) = stop.zone_index(&city.grid) {
+                let count = stops_per_zone.entry(zone).or_insert(0);
+                if *count == 0 {
+                    zones.push(zone);
+                }
+                *count += 1;
+            }
+        }
+        RouteMeasures {
+            params,
+            route,
+            city,
+            zone_to_zone_coverage,
+            zones,
+            stops_per_zone,
+            road_km,
+            nonlinearity,
+        }
+    }
+
+    /// Number of routes serving both zones, at least 1
+    fn routes_between(&self, u: NodeIndex, v: NodeIndex) -> u32 {
+        let (u, v) = (
+            self.city.grid.get_zone(u).zoneid,
+            self.city.grid.get_zone(v).zoneid,
+        );
+        self.zone_to_zone_coverage
+            .get(&(u, v))
+            .or_else(|| self.zone_to_zone_coverage.get(&(v, u)))
+            .copied()
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    /// Demand in both directions between two zones
+    pub(crate) fn demand(&self, u: NodeIndex, v: NodeIndex) -> f64 {
+        self.city.grid.demand_between_zones(u, v) + self.city.grid.demand_between_zones(v, u)
+    }
+
+    /// Zones within walking distance of the stops
+    pub(crate) fn walkable_zones(&self) -> HashSet<NodeIndex> {
+        self.route
+            .outbound_stops
+            .iter()
+            .flat_map(|s| s.nearby_zone_indices(&self.city.grid))
+            .collect()
+    }
+}
+
+/// Demand between the zones of the stops, shared with the other routes serving the same zone
+/// pairs, plus the weighted points of interest and jobs in walking distance, per km and
+/// discounted by nonlinearity
+pub struct DemandPerKm;
+
+impl ObjectiveFn for DemandPerKm {
+    fn name(&self) -> &'static str {
+        "demand_per_km"
+    }
+
+    fn description(&self) -> &'static str {
+        "Demand between the zones served, shared with other routes, per km"
+    }
+
+    fn score(&self, route: &RouteMeasures) -> f64 {
+        let (grid, zones) = (&route.city.grid, &route.zones);
+        let mut demand = 0.0;
+        for i in 0..zones.len() {
+            for j in i + 1..zones.len() {
+                let (u, v) = (
+                    grid.get_zone(zones[i]).zoneid,
+                    grid.get_zone(zones[j]).zoneid,
+                );
+                let coverage = *route.zone_to_zone_coverage.get(&(u, v)).unwrap_or(&1) as f64;
+                demand += route.demand(zones[i], zones[j])
+                    * route.stops_per_zone[&zones[i]] as f64
+                    * 0.75
+                    / coverage;
+            }
+        }
+
+        // points of interest and jobs within walking distance of the route's stops
+        let params = route.params;
+        let walkable = if params.poi_weight > 0.0 || params.jobs_weight > 0.0 {
+            route.walkable_zones()
+        } else {
+            HashSet::new()
+        };
+        let pois = if params.poi_weight > 0.0 {
+            accessibility::pois_served(walkable.iter().copied(), grid)
+        } else {
+            0.0
+        };
+        let jobs = if params.jobs_weight > 0.0 {
+            accessibility::jobs_served(walkable.iter().copied(), grid)
+        } else {
+            0.0
+        };
+
+        (demand + params.poi_weight * pois + params.jobs_weight * jobs)
+            / (route.road_km * route.nonlinearity)
+    }
+}
+
+/// Residents and jobs within walking distance of the stops per km
+pub struct CoverageGain;
+
+impl ObjectiveFn for CoverageGain {
+    fn name(&self) -> &'static str {
+        "coverage_gain"
+    }
+
+    fn description(&self) -> &'static str {
+        "Residents and jobs within walking distance of the stops per km"
+    }
+
+    fn score(&self, route: &RouteMeasures) -> f64 {
+        let grid = &route.city.grid;
+        let people: f64 = route
+            .walkable_zones()
+            .into_iter()
+            .map(|z| {
+                let zone = grid.get_zone(z);
+                zone.population as f64 + zone.jobs as f64
+            })
+            .sum();
+        people / route.road_km
+    }
+}
+
+/// Demand between zone pairs no other route serves, trips the route saves a transfer on, per
+/// km
+pub struct TransferImpact;
+
+impl ObjectiveFn for TransferImpact {
+    fn name(&self) -> &'static str {
+        "transfer_impact"
+    }
+
+    fn description(&self) -> &'static str {
+        "Demand between zones no other route connects directly per km"
+    }
+
+    fn score(&self, route: &RouteMeasures) -> f64 {
+        let zones = &route.zones;
+        let mut demand = 0.0;
+        for i in 0..zones.len() {
+            for j in i + 1..zones.len() {
+                // the route being optimized is counted among the routes serving the pair
+                if route.routes_between(zones[i], zones[j]) <= 1 {
+                    demand += route.demand(zones[i], zones[j]);
+                }
+            }
+        }
+        demand / route.road_km
+    }
+}
+
+/// Residents of the zones of the stops, each counted in inverse proportion to the routes
+/// already linking its zone to the rest of the route, per km
+pub struct Equity;
+
+impl ObjectiveFn for Equity {
+    fn name(&self) -> &'static str {
+        "equity"
+    }
+
+    fn description(&self) -> &'static str {
+        "Residents served, weighted towards zones with the fewest direct routes, per km"
+    }
+
+    fn score(&self, route: &RouteMeasures) -> f64 {
+        let zones = &route.zones;
+        let residents: f64 = zones
+            .iter()
+            .map(|&zone| {
+                let links = zones
+                    .iter()
+                    .filter(|&&other| other != zone)
+                    .map(|&other| route.routes_between(zone, other))
+                    .min()
+                    .unwrap_or(1);
+                route.city.grid.get_zone(zone).population as f64 / links as f64
+            })
+            .sum();
+        residents / route.road_km
+    }
+}
+
+/// Weighted sum of objectives by name, e.g. `{"demand_per_km": 0.7, "equity": 0.3}`
+///
+/// The objectives are not normalized, the weights have to account for their scales.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ObjectiveSpec {
+    pub weights: BTreeMap<String, f64>,
+}
+
+impl Default for ObjectiveSpec {
+    fn default() -> Self {
+        ObjectiveSpec {
+            weights: BTreeMap::from([(DemandPerKm.name().to_string(), 1.0)]),
+        }
+    }
+}
+
+impl ObjectiveSpec {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(name) = self.weights.keys().find(|name| lookup(name).is_none()) {
+            let known: Vec<&str> = OBJECTIVES.iter().map(|o| o.name()).collect();
+            return Err(format!(
+                "Unknown objective {:?}, expected one of {}",
+                name,
+                known.join(", ")
+            ));
+        }
+        if self.weights.values().any(|w| !w.is_finite() || *w < 0.0) {
+            return Err("Objective weights must be finite and not negative".to_string());
+        }
+        if !self.weights.values().any(|w| *w > 0.0) {
+            return Err("At least one objective weight must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Weighted sum of the objectives, skipping unknown ones and those weighted 0
+    pub fn score(&self, route: &RouteMeasures) -> f64 {
+        self.weights
+            .iter()
+            .filter(|(_, weight)| **weight > 0.0)
+            .filter_map(|(name, weight)| lookup(name).map(|o| weight * o.score(route)))
+            .sum()
+    }
+}
+
+/// Parse a spec given as `name:weight` pairs separated by commas, a name alone weighs 1
+impl FromStr for ObjectiveSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = BTreeMap::new();
+        for term in s.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            let (name, weight) = match term.split_once(':') {
+                Some((name, weight)) => (
+                    name.trim(),
+                    weight
+                        .trim()
+                        .parse::<f64>()
+                        .map_err(|_| format!("Invalid weight in objective term {:?}", term))?,
+                ),
+                None => (term, 1.0),
+            };
+            weights.insert(name.to_string(), weight);
+        }
+        let spec = ObjectiveSpec { weights };
+        spec.validate()?;
+        Ok(spec)
+    }
+}
+
+impl fmt::Display for ObjectiveSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let terms: Vec<String> = self
+            .weights
+            .iter()
+            .map(|(name, weight)| format!("{}:{}", name, weight))
+            .collect();
+        write!(f, "{}", terms.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_validates_specs() {
+        let spec: ObjectiveSpec = "demand_per_km:0.7, equity".parse().unwrap();
+        assert_eq!(spec.weights["demand_per_km"], 0.7);
+        assert_eq!(spec.weights["equity"], 1.0);
+        assert_eq!(spec.to_string(), "demand_per_km:0.7,equity:1");
+        assert_eq!(spec.to_string().parse::<ObjectiveSpec>().unwrap(), spec);
+
+        assert!("ridership".parse::<ObjectiveSpec>().is_err());
+        assert!("equity:x".parse::<ObjectiveSpec>().is_err());
+        assert!("equity:0".parse::<ObjectiveSpec>().is_err());
+        assert!("equity:-1,coverage_gain".parse::<ObjectiveSpec>().is_err());
+        assert!(ObjectiveSpec::default().validate().is_ok());
+
+        let json: ObjectiveSpec =
+            serde_json::from_value(serde_json::json!({ "transfer_impact": 2.0 })).unwrap();
+        assert_eq!(json.weights["transfer_impact"], 2.0);
+        for objective in OBJECTIVES {
+            assert!(lookup(objective.name()).is_some());
+        }
+    }
+}
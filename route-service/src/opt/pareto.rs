@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+
+use super::objective::RouteMeasures;
+use crate::ordering;
+
+/// What a version of a route trades off against the others: the people it reaches, the trips
+/// it can carry and the road it runs on, which its operating cost grows with
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct TradeOff {
+    /// Residents and jobs within walking distance of the stops
+    pub coverage: f64,
+    /// Trips between the zones of the stops, in both directions
+    pub ridership: f64,
+    /// Length of the route along the road network
+    pub road_km: f64,
+}
+
+impl TradeOff {
+    pub fn new(route: &RouteMeasures) -> TradeOff {
+        let grid = &route.city.grid;
+        let coverage = route
+            .walkable_zones()
+            .into_iter()
+            .map(|z| {
+                let zone = grid.get_zone(z);
+                zone.population as f64 + zone.jobs as f64
+            })
+            .sum();
+        let zones = &route.zones;
+        let mut ridership = 0.0;
+        for i in 0..zones.len() {
+            for j in i + 1..zones.len() {
+                ridership += route.demand(zones[i], zones[j]);
+            }
+        }
+        TradeOff {
+            coverage,
+            ridership,
+            road_km: route.road_km,
+        }
+    }
+
+    /// At least as good as `other` on every criterion and better on one
+    pub fn dominates(&self, other: &TradeOff) -> bool {
+        let (a, b) = (self.criteria(), other.criteria());
+        a.iter().zip(&b).all(|(a, b)| a >= b) && a.iter().zip(&b).any(|(a, b)| a > b)
+    }
+
+    /// Criteria, all to maximize
+    fn criteria(&self) -> [f64; 3] {
+        [self.coverage, self.ridership, -self.road_km]
+    }
+}
+
+/// Version of a route on the frontier
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FrontierRoute {
+    pub stop_ids: Vec<String>,
+    #[serde(flatten)]
+    pub trade_off: TradeOff,
+    /// Score of the route under the objectives of the run, penalties included
+    pub score: f64,
+}
+
+/// Versions of a route that no other version found beats on every criterion of `TradeOff`
+///
+/// The frontier holds at most `capacity` routes. When it is full, the route in its most
+/// crowded part is dropped as in NSGA-II, so the routes kept spread across the trade-offs.
+pub struct ParetoFront {
+    routes: Vec<FrontierRoute>,
+    capacity: usize,
+}
+
+impl ParetoFront {
+    pub fn new(capacity: usize) -> ParetoFront {
+        ParetoFront {
+            routes: vec![],
+            capacity,
+        }
+    }
+
+    /// Add a route unless the frontier has the same stops or a route dominating it, dropping
+    /// the routes it dominates
+    ///
+    /// # Returns
+    /// Whether the route is on the frontier
+    pub fn insert(&mut self, route: FrontierRoute) -> bool {
+        if self.capacity == 0
+            || self
+                .routes
+                .iter()
+                .any(|r| r.stop_ids == route.stop_ids || r.trade_off.dominates(&route.trade_off))
+        {
+            return false;
+        }
+        self.routes
+            .retain(|r| !route.trade_off.dominates(&r.trade_off));
+        self.routes.push(route);
+        if self.routes.len() <= self.capacity {
+            return true;
+        }
+        let crowding = crowding_distances(&self.routes);
+        let most_crowded = (0..self.routes.len())
+            .min_by(|&a, &b| ordering::cmp_f64(crowding[a], crowding[b]))
+            .unwrap();
+        self.routes.remove(most_crowded);
+        most_crowded != self.routes.len()
+    }
+
+    /// Routes of the frontier from the shortest to the longest
+    pub fn into_routes(mut self) -> Vec<FrontierRoute> {
+        self.routes.sort_by(|a, b| {
+            ordering::cmp_f64(a.trade_off.road_km, b.trade_off.road_km)
+                .then_with(|| a.stop_ids.cmp(&b.stop_ids))
+        });
+        self.routes
+    }
+}
+
+/// Crowding distance of each route, the sum over the criteria of the gap between its two
+/// neighbours along the criterion over the range of the criterion. The routes at either end
+/// of a criterion are never the most crowded.
+fn crowding_distances(routes: &[FrontierRoute]) -> Vec<f64> {
+    let mut distances = vec![0.0; routes.len()];
+    for c in 0..3 {
+        let value = |i: usize| routes[i].trade_off.criteria()[c];
+        let mut order: Vec<usize> = (0..routes.len()).collect();
+        order.sort_by(|&a, &b| ordering::cmp_f64(value(a), value(b)));
+        let (first, last) = (order[0], order[order.len() - 1]);
+        distances[first] = f64::INFINITY;
+        distances[last] = f64::INFINITY;
+        let range = value(last) - value(first);
+        if range <= 0.0 {
+            continue;
+        }
+        for w in order.windows(3) {
+            distances[w[1]] += (value(w[2]) - value(w[0])) / range;
+        }
+    }
+    distances
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(id: &str, coverage: f64, ridership: f64, road_km: f64) -> FrontierRoute {
+        FrontierRoute {
+            stop_ids: vec![id.to_string()],
+            trade_off: TradeOff {
+                coverage,
+                ridership,
+                road_km,
+            },
+            score: 0.0,
+        }
+    }
+
+    fn ids(front: ParetoFront) -> Vec<String> {
+        front
+            .into_routes()
+            .into_iter()
+            .map(|r| r.stop_ids[0].clone())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_non_dominated_routes_spread_across_trade_offs() {
+        let mut front = ParetoFront::new(10);
+        assert!(front.insert(route("a", 10.0, 10.0, 5.0)));
+        // dominated by a, or the same stops as a
+        assert!(!front.insert(route("b", 9.0, 10.0, 5.0)));
+        assert!(!front.insert(route("a", 20.0, 20.0, 1.0)));
+        // a shorter route reaching fewer people trades off with a
+        assert!(front.insert(route("c", 5.0, 5.0, 2.0)));
+        // dominates a
+        assert!(front.insert(route("d", 10.0, 12.0, 5.0)));
+        assert_eq!(ids(front), vec!["c", "d"]);
+
+        // the route between two close neighbours is dropped, the extremes are kept
+        let mut front = ParetoFront::new(3);
+        for (i, km) in [1.0, 2.0, 2.1, 4.0].into_iter().enumerate() {
+            front.insert(route(&i.to_string(), km, km, km));
+        }
+        assert_eq!(ids(front), vec!["0", "2", "3"]);
+
+        assert!(!ParetoFront::new(0).insert(route("a", 1.0, 1.0, 1.0)));
+    }
+}
@@ -0,0 +1,365 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::aco2::PartialACO;
+use super::pareto::FrontierRoute;
+use super::walking::WalkCheck;
+
+/// Where a multi-route optimization is at, shared by the per-route progress events
+#[derive(Serialize, Clone, Debug)]
+pub struct IterationProgress {
+    /// Iterations run so far over all routes, starting at 1
+    pub iteration: usize,
+    pub total_iterations: usize,
+    pub current_route: String,
+    pub current_route_index: usize,
+    pub routes_count: usize,
+    pub all_route_ids: Vec<String>,
+    /// Iteration of the current route, starting at 1
+    pub route_iteration: usize,
+    pub iterations_per_route: usize,
+    pub converged_routes: Vec<bool>,
+    pub optimize_attempts: Vec<usize>,
+}
+
+/// Parameters changed while a live optimization was running
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ParamChange {
+    /// Iterations that ran before the change, the next ones use the new parameters
+    pub iteration: usize,
+    /// How the change was requested, `websocket` or `rest`
+    pub source: String,
+    /// Parameters that were set, the others are unchanged
+    pub changes: PartialACO,
+}
+
+/// Progress of an optimization, streamed to clients as it runs.
+///
+/// Events serialize to a JSON object with an `event` field naming the variant, e.g.
+/// `{"event": "generation_completed", "route_id": "12", ...}`, so every transport sends the
+/// same payload.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// The optimization of the given routes is about to start
+    Started {
+        message: String,
+        routes: Vec<String>,
+        /// Live session whose parameters can be changed while it runs
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session_id: Option<u64>,
+        /// Iterations done before, for a live session resumed from its checkpoint
+        #[serde(skip_serializing_if = "Option::is_none")]
+        resumed_at: Option<usize>,
+    },
+    /// The parameters of a live session changed between two iterations
+    ParamsUpdated {
+        session_id: u64,
+        #[serde(flatten)]
+        change: Box<ParamChange>,
+    },
+    /// One ACO generation finished for a route
+    GenerationCompleted {
+        route_id: String,
+        /// Generation that finished, starting at 1
+        generation: usize,
+        max_gen: usize,
+        /// Score of the best route found so far
+        best_score: f64,
+    },
+    /// Size of the search space ACO explored for a route
+    SearchSpace {
+        route_id: String,
+        /// Stops the route could be built from
+        candidate_stops: usize,
+        /// Origin and destination zone pairs scored while evaluating the route
+        candidate_zone_pairs: usize,
+        /// Stop to stop heuristic values computed by the ants
+        heuristic_entries: usize,
+        /// Candidate routes the ants scored from the evaluation cache, see `EvalCache`
+        cached_evaluations: usize,
+    },
+    /// One chunk of a long route optimized in chunks finished, see `ACO::chunk_min_stops`
+    ChunkCompleted {
+        route_id: String,
+        /// Chunk that finished, starting at 1
+        chunk: usize,
+        chunks: usize,
+        /// Stops the chunk starts and ends at, kept as they are
+        first_stop_id: String,
+        last_stop_id: String,
+    },
+    /// The local search run after ACO finished for a route that improved
+    LocalSearchCompleted {
+        route_id: String,
+        /// Score of the route before optimization
+        initial_score: f64,
+        /// Score of the best route found by ACO
+        aco_score: f64,
+        /// Score after the local search
+        final_score: f64,
+    },
+    /// Versions of a route ACO found trading off coverage, ridership and road length, see
+    /// `ACO::pareto_size`
+    ParetoFrontier {
+        route_id: String,
+        /// From the shortest to the longest route
+        routes: Vec<FrontierRoute>,
+    },
+    /// The best route found moved stops too far from some zones and was rejected
+    WalkConstraintViolated { route_id: String, check: WalkCheck },
+    /// ACO could not improve a route any further
+    RouteConverged {
+        message: String,
+        warning: String,
+        converged_route: String,
+        converged_route_index: usize,
+        noop_route_ids: Vec<String>,
+        #[serde(flatten)]
+        progress: IterationProgress,
+    },
+    /// ACO found a better version of a route
+    RouteOptimized {
+        message: String,
+        /// Optimized network as GeoJSON
+        geojson: Value,
+        /// Score of each optimized route
+        evaluation: Vec<(String, f64)>,
+        optimized_routes: usize,
+        #[serde(flatten)]
+        progress: IterationProgress,
+    },
+    /// Every iteration ran, or every route converged before that
+    BatchFinished {
+        message: String,
+        iteration: usize,
+        total_iterations: usize,
+        all_converged: bool,
+        early_completion: bool,
+        converged_routes: Vec<bool>,
+        optimize_attempts: Vec<usize>,
+        /// Parameters changed during the batch, oldest first
+        param_changes: Vec<ParamChange>,
+    },
+    /// One generation of the genetic algorithm tuning the ACO parameters of a route finished
+    TuningGenerationCompleted {
+        route_id: String,
+        /// Generation that finished, starting at 1
+        generation: usize,
+        max_generations: usize,
+        /// Best score ACO reached with any parameters tried so far
+        best_fitness: f64,
+        /// Average score of the generation's parameters
+        avg_fitness: f64,
+    },
+    /// The optimization stopped because of an error
+    Error {
+        error: String,
+        /// Kind of failure, as in the `code` of an error response
+        code: String,
+    },
+}
+
+impl ProgressEvent {
+    /// Name of the event, as found in the `event` field of the JSON payload
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProgressEvent::Started { .. } => "started",
+            ProgressEvent::ParamsUpdated { .. } => "params_updated",
+            ProgressEvent::GenerationCompleted { .. } => "generation_completed",
+            ProgressEvent::SearchSpace { .. } => "search_space",
+            ProgressEvent::ChunkCompleted { .. } => "chunk_completed",
+            ProgressEvent::LocalSearchCompleted { .. } => "local_search_completed",
+            ProgressEvent::ParetoFrontier { .. } => "pareto_frontier",
+            ProgressEvent::WalkConstraintViolated { .. } => "walk_constraint_violated",
+            ProgressEvent::RouteConverged { .. } => "route_converged",
+            ProgressEvent::RouteOptimized { .. } => "route_optimized",
+            ProgressEvent::BatchFinished { .. } => "batch_finished",
+            ProgressEvent::TuningGenerationCompleted { .. } => "tuning_generation_completed",
+            ProgressEvent::Error { .. } => "error",
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Server-sent events frame carrying the event, named after the variant
+    pub fn to_sse(&self) -> String {
+        format!("event: {}\ndata: {}\n\n", self.name(), self.to_json())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::pareto::TradeOff;
+    use crate::opt::walking::WalkViolation;
+    use serde_json::Map;
+
+    const OPENAPI_SPEC: &str = include_str!("../../../docs/openapi.json");
+
+    /// Properties and required properties of a schema of the spec, following `allOf`
+    fn schema_properties(spec: &Value, schema: &Value) -> (Vec<String>, Vec<String>) {
+        if let Some(path) = schema["$ref"].as_str() {
+            let name = path.trim_start_matches("#/components/schemas/");
+            return schema_properties(spec, &spec["components"]["schemas"][name]);
+        }
+        let (mut properties, mut required) = (vec![], vec![]);
+        for part in schema["allOf"].as_array().into_iter().flatten() {
+            let (p, r) = schema_properties(spec, part);
+            properties.extend(p);
+            required.extend(r);
+        }
+        let keys = schema["properties"].as_object().map(Map::keys);
+        properties.extend(keys.into_iter().flatten().cloned());
+        let names = schema["required"].as_array().into_iter().flatten();
+        required.extend(names.filter_map(|r| r.as_str().map(str::to_string)));
+        (properties, required)
+    }
+
+    #[test]
+    fn openapi_spec_describes_every_event() {
+        let spec: Value = serde_json::from_str(OPENAPI_SPEC).unwrap();
+        let progress = IterationProgress {
+            iteration: 1,
+            total_iterations: 2,
+            current_route: "1".to_string(),
+            current_route_index: 0,
+            routes_count: 1,
+            all_route_ids: vec!["1".to_string()],
+            route_iteration: 1,
+            iterations_per_route: 2,
+            converged_routes: vec![false],
+            optimize_attempts: vec![1],
+        };
+        let change = ParamChange {
+            iteration: 1,
+            source: "rest".to_string(),
+            changes: PartialACO {
+                alpha: Some(1.0),
+                ..Default::default()
+            },
+        };
+        let route_id = "1".to_string();
+        let events = vec![
+            ProgressEvent::Started {
+                message: String::new(),
+                routes: vec![route_id.clone()],
+                session_id: Some(1),
+                resumed_at: Some(3),
+            },
+            ProgressEvent::ParamsUpdated {
+                session_id: 1,
+                change: Box::new(change.clone()),
+            },
+            ProgressEvent::GenerationCompleted {
+                route_id: route_id.clone(),
+                generation: 1,
+                max_gen: 2,
+                best_score: 1.0,
+            },
+            ProgressEvent::SearchSpace {
+                route_id: route_id.clone(),
+                candidate_stops: 1,
+                candidate_zone_pairs: 1,
+                heuristic_entries: 1,
+                cached_evaluations: 1,
+            },
+            ProgressEvent::ChunkCompleted {
+                route_id: route_id.clone(),
+                chunk: 1,
+                chunks: 2,
+                first_stop_id: "a".to_string(),
+                last_stop_id: "b".to_string(),
+            },
+            ProgressEvent::LocalSearchCompleted {
+                route_id: route_id.clone(),
+                initial_score: 1.0,
+                aco_score: 2.0,
+                final_score: 3.0,
+            },
+            ProgressEvent::ParetoFrontier {
+                route_id: route_id.clone(),
+                routes: vec![FrontierRoute {
+                    stop_ids: vec!["a".to_string()],
+                    trade_off: TradeOff {
+                        coverage: 1.0,
+                        ridership: 1.0,
+                        road_km: 1.0,
+                    },
+                    score: 1.0,
+                }],
+            },
+            ProgressEvent::WalkConstraintViolated {
+                route_id: route_id.clone(),
+                check: WalkCheck {
+                    max_increase_m: 100.0,
+                    zones_checked: 1,
+                    worst_increase_m: 200.0,
+                    violations: vec![WalkViolation {
+                        zoneid: 1,
+                        population: 10,
+                        before_m: 100.0,
+                        after_m: 300.0,
+                    }],
+                },
+            },
+            ProgressEvent::RouteConverged {
+                message: String::new(),
+                warning: String::new(),
+                converged_route: route_id.clone(),
+                converged_route_index: 0,
+                noop_route_ids: vec![],
+                progress: progress.clone(),
+            },
+            ProgressEvent::RouteOptimized {
+                message: String::new(),
+                geojson: serde_json::json!({ "type": "FeatureCollection", "features": [] }),
+                evaluation: vec![(route_id.clone(), 1.0)],
+                optimized_routes: 1,
+                progress,
+            },
+            ProgressEvent::BatchFinished {
+                message: String::new(),
+                iteration: 2,
+                total_iterations: 2,
+                all_converged: false,
+                early_completion: false,
+                converged_routes: vec![false],
+                optimize_attempts: vec![1],
+                param_changes: vec![change],
+            },
+            ProgressEvent::TuningGenerationCompleted {
+                route_id,
+                generation: 1,
+                max_generations: 2,
+                best_fitness: 1.0,
+                avg_fitness: 0.5,
+            },
+            ProgressEvent::Error {
+                error: String::new(),
+                code: "internal".to_string(),
+            },
+        ];
+
+        let mapping = &spec["components"]["schemas"]["ProgressEvent"]["discriminator"]["mapping"];
+        assert_eq!(mapping.as_object().unwrap().len(), events.len());
+        for event in events {
+            let payload: Value = serde_json::from_str(&event.to_json()).unwrap();
+            let schema = serde_json::json!({ "$ref": mapping[event.name()] });
+            let (properties, required) = schema_properties(&spec, &schema);
+            assert!(
+                !properties.is_empty(),
+                "{} is not in the spec",
+                event.name()
+            );
+            for key in payload.as_object().unwrap().keys() {
+                assert!(properties.contains(key), "{}.{}", event.name(), key);
+            }
+            for key in required {
+                assert!(payload.get(&key).is_some(), "{}.{}", event.name(), key);
+            }
+        }
+    }
+}
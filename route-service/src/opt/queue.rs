@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+
+use crate::layers::{
+    geo_util,
+    transit_network::{TransitNetwork, TransitRoute, TransitRouteType},
+};
+
+use crate::ordering;
+
+/// Weights of the metrics in the badness score of a route, 0 to ignore a metric
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct BadnessWeights {
+    /// Weight of low average ridership
+    pub ridership: f64,
+    /// Weight of high nonlinearity
+    pub nonlinearity: f64,
+    /// Weight of poor coverage
+    pub coverage: f64,
+}
+
+impl Default for BadnessWeights {
+    fn default() -> Self {
+        BadnessWeights {
+            ridership: 1.0,
+            nonlinearity: 1.0,
+            coverage: 1.0,
+        }
+    }
+}
+
+impl BadnessWeights {
+    pub fn validate(&self) -> Result<(), String> {
+        let weights = [self.ridership, self.nonlinearity, self.coverage];
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+            return Err("Weights must be finite and not negative".to_string());
+        }
+        if weights.iter().sum::<f64>() == 0.0 {
+            return Err("At least one weight must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A route waiting to be optimized
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueueEntry {
+    pub route_id: String,
+    /// Weighted average, from 0 to 1, of the percentile rank of each metric among the queued
+    /// routes, higher is worse
+    pub badness: f64,
+    pub avg_ridership: f64,
+    /// Length along the outbound stops over the straight-line distance between the first and
+    /// last stop
+    pub nonlinearity: f64,
+    pub coverage: f64,
+    /// Pinned by a planner, pinned routes come first in the order they were pinned in
+    pub pinned: bool,
+}
+
+impl QueueEntry {
+    fn new(route: &TransitRoute) -> QueueEntry {
+        let (avg_ridership, coverage) = route
+            .evals
+            .as_ref()
+            .map_or((0.0, 0.0), |e| (e.avg_ridership, e.coverage));
+        QueueEntry {
+            route_id: route.route_id.clone(),
+            badness: 0.0,
+            avg_ridership,
+            nonlinearity: nonlinearity(route),
+            coverage,
+            pinned: false,
+        }
+    }
+}
+
+/// Queue of the bus routes that have not been optimized yet, worst routes first
+#[derive(Default)]
+pub struct OptimizationQueue {
+    pinned: Vec<String>,
+    pub weights: BadnessWeights,
+}
+
+impl OptimizationQueue {
+    /// Routes pinned to the front of the queue, in order
+    pub fn pinned(&self) -> &[String] {
+        &self.pinned
+    }
+
+    /// Replace the pinned routes, duplicates are ignored
+    pub fn set_pinned(&mut self, route_ids: Vec<String>) {
+        self.pinned.clear();
+        for route_id in route_ids {
+            if !self.pinned.contains(&route_id) {
+                self.pinned.push(route_id);
+            }
+        }
+    }
+
+    /// The queue for a network
+    ///
+    /// # Arguments
+    /// - `transit`: The network as loaded, with the evals of every route
+    /// - `optimized_route_ids`: Routes left out of the queue, including pinned ones
+    pub fn entries(
+        &self,
+        transit: &TransitNetwork,
+        optimized_route_ids: &[String],
+    ) -> Vec<QueueEntry> {
+        let entries = transit
+            .routes
+            .iter()
+            .filter(|r| r.route_type == TransitRouteType::Bus)
+            .filter(|r| !optimized_route_ids.contains(&r.route_id))
+            .map(QueueEntry::new)
+            .collect();
+        rank(entries, &self.weights, &self.pinned)
+    }
+}
+
+/// Length along the stops of a route over the straight-line distance between its ends, 1 for
+/// loops and routes with fewer than two stops
+fn nonlinearity(route: &TransitRoute) -> f64 {
+    let stops = &route.outbound_stops;
+    let dist = |a: &geo_types::Point, b: &geo_types::Point| {
+        geo_util::haversine(a.x(), a.y(), b.x(), b.y())
+    };
+    let (Some(first), Some(last)) = (stops.first(), stops.last()) else {
+        return 1.0;
+    };
+    let straight = dist(&first.geom, &last.geom);
+    if straight < 1.0 {
+        return 1.0;
+    }
+    stops
+        .windows(2)
+        .map(|w| dist(&w[0].geom, &w[1].geom))
+        .sum::<f64>()
+        / straight
+}
+
+/// Score and order queue entries, pinned entries first in pin order, then by badness
+fn rank(
+    mut entries: Vec<QueueEntry>,
+    weights: &BadnessWeights,
+    pinned: &[String],
+) -> Vec<QueueEntry> {
+    // percentile rank from 0 (best) to 1 (worst) of each entry for a metric
+    let ranks = |worse: &dyn Fn(&QueueEntry, &QueueEntry) -> std::cmp::Ordering| -> Vec<f64> {
+        let mut order: Vec<usize> = (0..entries.len()).collect();
+        order.sort_by(|a, b| worse(&entries[*a], &entries[*b]));
+        let mut ranks = vec![0.0; entries.len()];
+        let denominator = entries.len().saturating_sub(1).max(1) as f64;
+        for (rank, i) in order.into_iter().enumerate() {
+            ranks[i] = rank as f64 / denominator;
+        }
+        ranks
+    };
+    let ridership = ranks(&|a, b| ordering::cmp_f64(b.avg_ridership, a.avg_ridership));
+    let nonlinearity = ranks(&|a, b| ordering::cmp_f64(a.nonlinearity, b.nonlinearity));
+    let coverage = ranks(&|a, b| ordering::cmp_f64(b.coverage, a.coverage));
+
+    let total = weights.ridership + weights.nonlinearity + weights.coverage;
+    for (i, entry) in entries.iter_mut().enumerate() {
+        entry.badness = if total > 0.0 {
+            (weights.ridership * ridership[i]
+                + weights.nonlinearity * nonlinearity[i]
+                + weights.coverage * coverage[i])
+                / total
+        } else {
+            0.0
+        };
+        entry.pinned = pinned.contains(&entry.route_id);
+    }
+
+    let pin_index = |e: &QueueEntry| pinned.iter().position(|p| *p == e.route_id);
+    entries.sort_by(|a, b| match (pin_index(a), pin_index(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => {
+            ordering::cmp_f64_desc(a.badness, b.badness).then_with(|| a.route_id.cmp(&b.route_id))
+        }
+    });
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(route_id: &str, avg_ridership: f64, nonlinearity: f64, coverage: f64) -> QueueEntry {
+        QueueEntry {
+            route_id: route_id.to_string(),
+            badness: 0.0,
+            avg_ridership,
+            nonlinearity,
+            coverage,
+            pinned: false,
+        }
+    }
+
+    #[test]
+    fn worst_routes_come_first_after_pinned_ones() {
+        let entries = vec![
+            entry("good", 100.0, 1.1, 50.0),
+            entry("bad", 10.0, 1.8, 5.0),
+            entry("mixed", 20.0, 1.0, 40.0),
+        ];
+        let ids = |entries: &[QueueEntry]| -> Vec<String> {
+            entries.iter().map(|e| e.route_id.clone()).collect()
+        };
+
+        let ranked = rank(entries.clone(), &BadnessWeights::default(), &[]);
+        assert_eq!(ids(&ranked), vec!["bad", "mixed", "good"]);
+        assert_eq!(ranked[0].badness, 1.0);
+
+        // only nonlinearity counts
+        let weights = BadnessWeights {
+            ridership: 0.0,
+            nonlinearity: 1.0,
+            coverage: 0.0,
+        };
+        let ranked = rank(entries.clone(), &weights, &[]);
+        assert_eq!(ids(&ranked), vec!["bad", "good", "mixed"]);
+
+        let pinned = vec!["good".to_string(), "unknown".to_string()];
+        let ranked = rank(entries, &BadnessWeights::default(), &pinned);
+        assert_eq!(ids(&ranked), vec!["good", "bad", "mixed"]);
+        assert!(ranked[0].pinned && !ranked[1].pinned);
+    }
+
+    #[test]
+    fn weights_must_be_positive() {
+        assert!(BadnessWeights::default().validate().is_ok());
+        let zero = BadnessWeights {
+            ridership: 0.0,
+            nonlinearity: 0.0,
+            coverage: 0.0,
+        };
+        assert!(zero.validate().is_err());
+        let negative = BadnessWeights {
+            ridership: -1.0,
+            ..BadnessWeights::default()
+        };
+        assert!(negative.validate().is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use super::progress::ProgressEvent;
+
+/// Time and memory used by an optimization, to plan capacity for larger cities
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ResourceUsage {
+    pub wall_ms: u128,
+    /// User and system CPU time of the thread that ran the optimization
+    pub cpu_ms: u128,
+    /// Peak resident set size of the whole process when the optimization finished, in KiB
+    pub max_rss_kb: u64,
+    /// Most candidate stops offered to a single route
+    pub peak_candidate_stops: usize,
+    /// Most origin and destination zone pairs scored for a single route
+    pub peak_candidate_zone_pairs: usize,
+    /// Stop to stop heuristic values computed over all routes
+    pub heuristic_cache_entries: usize,
+    /// Candidate routes scored from the evaluation cache over all routes
+    #[serde(default)]
+    pub eval_cache_hits: usize,
+}
+
+/// Measures the resources used between `start` and `finish`
+///
+/// The search space sizes are taken from the `SearchSpace` events passed to `observe`.
+pub struct ResourceMeter {
+    start: Instant,
+    cpu_start: Duration,
+    usage: ResourceUsage,
+}
+
+impl ResourceMeter {
+    pub fn start() -> ResourceMeter {
+        ResourceMeter {
+            start: Instant::now(),
+            cpu_start: thread_cpu_time(),
+            usage: ResourceUsage::default(),
+        }
+    }
+
+    /// Record the search space of a route, other events are ignored
+    pub fn observe(&mut self, event: &ProgressEvent) {
+        if let ProgressEvent::SearchSpace {
+            candidate_stops,
+            candidate_zone_pairs,
+            heuristic_entries,
+            cached_evaluations,
+            ..
+        } = event
+        {
+            self.usage.peak_candidate_stops = self.usage.peak_candidate_stops.max(*candidate_stops);
+            self.usage.peak_candidate_zone_pairs = self
+                .usage
+                .peak_candidate_zone_pairs
+                .max(*candidate_zone_pairs);
+            self.usage.heuristic_cache_entries += heuristic_entries;
+            self.usage.eval_cache_hits += cached_evaluations;
+        }
+    }
+
+    pub fn finish(mut self) -> ResourceUsage {
+        self.usage.wall_ms = self.start.elapsed().as_millis();
+        self.usage.cpu_ms = thread_cpu_time().saturating_sub(self.cpu_start).as_millis();
+        self.usage.max_rss_kb = max_rss_kb();
+        self.usage
+    }
+}
+
+/// CPU time of the calling thread, or of the whole process where per thread usage is not
+/// available, zero where neither is
+fn thread_cpu_time() -> Duration {
+    cpu_time().unwrap_or(Duration::ZERO)
+}
+
+/// Peak resident set size of the process in KiB, zero where it is not available
+pub(crate) fn max_rss_kb() -> u64 {
+    peak_rss_kb().unwrap_or(0)
+}
+
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+    #[cfg(target_os = "linux")]
+    let who = libc::RUSAGE_THREAD;
+    #[cfg(not(target_os = "linux"))]
+    let who = libc::RUSAGE_SELF;
+
+    let usage = rusage(who)?;
+    let timeval = |tv: libc::timeval| {
+        Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+    };
+    Some(timeval(usage.ru_utime) + timeval(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
+
+#[cfg(unix)]
+fn peak_rss_kb() -> Option<u64> {
+    let usage = rusage(libc::RUSAGE_SELF)?;
+    // macOS reports bytes, other platforms KiB
+    if cfg!(target_os = "macos") {
+        Some(usage.ru_maxrss as u64 / 1024)
+    } else {
+        Some(usage.ru_maxrss as u64)
+    }
+}
+
+#[cfg(not(unix))]
+fn peak_rss_kb() -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+fn rusage(who: libc::c_int) -> Option<libc::rusage> {
+    // SAFETY: rusage is plain old data and getrusage only writes to the struct it is given
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    (unsafe { libc::getrusage(who, &mut usage) } == 0).then_some(usage)
+}
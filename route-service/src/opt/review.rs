@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::layers::{grid::GridNetwork, transit_network::TransitNetwork};
+
+use super::eval::TransitNetworkEvals;
+
+/// Review state of an optimized route
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewState {
+    /// Optimized but not yet reviewed by a planner
+    #[default]
+    Proposed,
+    Accepted,
+    Rejected,
+}
+
+/// Review states of the optimized routes of a network.
+///
+/// Every optimized route starts out proposed, and a planner moves it to accepted or
+/// rejected. Optimizing a route again puts it back into the proposed state.
+#[derive(Default)]
+pub struct RouteReviews {
+    states: HashMap<String, ReviewState>,
+}
+
+impl RouteReviews {
+    /// Review state of an optimized route
+    pub fn state(&self, route_id: &str) -> ReviewState {
+        self.states.get(route_id).copied().unwrap_or_default()
+    }
+
+    /// Mark a route as proposed, e.g. after it was (re-)optimized
+    pub fn propose(&mut self, route_id: &str) {
+        self.states.remove(route_id);
+    }
+
+    /// Move a proposed route to the accepted or rejected state
+    ///
+    /// # Returns
+    /// The current state as the error if the route is not proposed
+    pub fn review(&mut self, route_id: &str, state: ReviewState) -> Result<(), ReviewState> {
+        match self.state(route_id) {
+            ReviewState::Proposed => {
+                self.states.insert(route_id.to_string(), state);
+                Ok(())
+            }
+            current => Err(current),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+
+    /// Optimized routes that are not rejected, with proposed routes only if requested
+    ///
+    /// # Arguments
+    /// - `optimized_route_ids`: Routes that have been optimized
+    /// - `include_proposed`: Whether to keep routes that have not been reviewed yet
+    pub fn visible(&self, optimized_route_ids: &[String], include_proposed: bool) -> Vec<String> {
+        optimized_route_ids
+            .iter()
+            .filter(|id| match self.state(id) {
+                ReviewState::Accepted => true,
+                ReviewState::Proposed => include_proposed,
+                ReviewState::Rejected => false,
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Build the network made of the original routes with only the given routes replaced by their
+/// optimized version, and the given routes the original network lacks added
+///
+/// # Arguments
+/// - `original`: Network before optimization
+/// - `optimized`: Network with all optimized routes
+/// - `route_ids`: Routes to take from the optimized network
+/// - `grid`: Grid network used to evaluate the result
+pub fn merged_network(
+    original: &TransitNetwork,
+    optimized: &TransitNetwork,
+    route_ids: &[String],
+    grid: &GridNetwork,
+) -> TransitNetwork {
+    let mut network = original.clone();
+    for route in network.routes.iter_mut() {
+        if !route_ids.contains(&route.route_id) {
+            continue;
+        }
+        if let Some(opt_route) = optimized
+            .routes
+            .iter()
+            .find(|r| r.route_id == route.route_id)
+        {
+            *route = opt_route.clone();
+        }
+    }
+    // routes created from scratch have no original to replace
+    for opt_route in &optimized.routes {
+        if route_ids.contains(&opt_route.route_id)
+            && !original
+                .routes
+                .iter()
+                .any(|r| r.route_id == opt_route.route_id)
+        {
+            network.routes.push(opt_route.clone());
+        }
+    }
+    if !route_ids.is_empty() {
+        network.evals = Some(TransitNetworkEvals::for_network(&network, grid));
+    }
+    network
+}
@@ -0,0 +1,290 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{collections::HashSet, sync::Arc, time::Instant};
+
+use crate::layers::{
+    city::City,
+    geo_util,
+    transit_network::{TransitNetwork, TransitRoute, TransitRouteType, TransitStop},
+};
+
+use super::aco2::{self, SearchContext, ACO};
+use super::alignment;
+use super::constraints::{constraint_of, RouteConstraint};
+use super::corridor::CorridorDistances;
+use super::eval::{route_key, EvalCache};
+use super::progress::ProgressEvent;
+
+/// Temperature at the start of the annealing, as a share of the score of the route
+const INITIAL_TEMPERATURE: f64 = 0.05;
+/// Temperature at the end of the annealing, as a share of the score of the route
+const FINAL_TEMPERATURE: f64 = 0.0005;
+/// Moves drawn in an iteration before giving up on it, e.g. when no stop can be inserted
+const MAX_MOVE_ATTEMPTS: usize = 20;
+
+/// Run simulated annealing on a route, a simpler baseline to benchmark ACO against
+///
+/// Each iteration perturbs the outbound stops of the current route by inserting, removing or
+/// swapping a stop, and scores the result with the same evaluation as ACO. A better route is
+/// always kept, a worse one with probability `exp(-loss / temperature)`, the temperature
+/// cooling geometrically over the run.
+///
+/// # Arguments
+/// - `params`: ACO parameters, the route constraints and `seed` apply as they do to ACO
+/// - `start_route`: Route the search starts from, e.g. the route of a previous scenario,
+///   `route` itself if `None`
+/// - `deadline`: Stop once this instant passes, keeping the best route found so far
+///
+/// # Returns
+/// The optimized route and its score if it scores better than `route`
+///
+/// # Notes
+/// - Runs `max_gen * num_ant` iterations, one evaluation each like an ant's route, and
+///   reports a `GenerationCompleted` event every `num_ant` iterations
+/// - The first and last stops never change
+pub fn run_sa(
+    params: ACO,
+    route: &TransitRoute,
+    city: &City,
+    opt_transit: &TransitNetwork,
+    start_route: Option<&TransitRoute>,
+    deadline: Option<Instant>,
+    on_progress: &mut dyn FnMut(ProgressEvent),
+) -> Option<(TransitRoute, f64)> {
+    if route.route_type != TransitRouteType::Bus || route.outbound_stops.len() < 2 {
+        return None;
+    }
+    let start_route = start_route
+        .filter(|r| r.outbound_stops.len() >= 2)
+        .unwrap_or(route);
+    let params = aco2::calculate_route_specific_params(route, city, &params);
+    let mut stops = aco2::filter_stops_by_route_bbox(start_route, city, city.search.bbox_padding);
+    let coverage = aco2::filter_zones_by_stops(&stops, city, opt_transit);
+    let distances = CorridorDistances::build(&stops, &city.road);
+    alignment::retain_near_alignment(
+        &mut stops,
+        &route.route_id,
+        city,
+        params.max_corridor_deviation,
+    );
+    let mut eval_cache = EvalCache::new();
+    let mut evaluate = |route: &TransitRoute| {
+        eval_cache
+            .get_or_insert_with(route_key(route), || {
+                aco2::evaluate_route(&params, route, city, &distances, &coverage)
+            })
+            .0
+    };
+
+    let init_eval = evaluate(route);
+    let mut current = start_route.clone();
+    let mut current_eval = evaluate(&current);
+    let (mut best, mut best_eval) = (current.clone(), current_eval);
+
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let per_generation = params.num_ant.max(1);
+    let iterations = params.max_gen * per_generation;
+    let scale = init_eval.abs().max(f64::EPSILON);
+    let (initial, last) = (INITIAL_TEMPERATURE * scale, FINAL_TEMPERATURE * scale);
+    for i in 0..iterations {
+        if i % per_generation == 0 && deadline.is_some_and(|d| Instant::now() >= d) {
+            log::debug!(
+                "Deadline reached for route {} after {} iterations",
+                route.route_id,
+                i
+            );
+            break;
+        }
+        let temperature = initial * (last / initial).powf(i as f64 / iterations as f64);
+        if let Some(outbound_stops) = perturb(
+            &params,
+            &current.outbound_stops,
+            &stops,
+            constraint_of(&params.constraints, route),
+            &mut rng,
+        ) {
+            let candidate = TransitRoute {
+                outbound_stops,
+                ..current.clone()
+            };
+            let candidate_eval = evaluate(&candidate);
+            let accept = candidate_eval >= current_eval
+                || rng.gen::<f64>() < ((candidate_eval - current_eval) / temperature).exp();
+            if accept {
+                current = candidate;
+                current_eval = candidate_eval;
+                if current_eval > best_eval {
+                    best = current.clone();
+                    best_eval = current_eval;
+                    log::debug!("  Iteration {}: new best route {}", i, best_eval);
+                }
+            }
+        }
+        if (i + 1) % per_generation == 0 {
+            on_progress(ProgressEvent::GenerationCompleted {
+                route_id: route.route_id.clone(),
+                generation: (i + 1) / per_generation,
+                max_gen: params.max_gen,
+                best_score: best_eval,
+            });
+        }
+    }
+
+    aco2::accept_route(
+        route,
+        best,
+        best_eval,
+        init_eval,
+        params.max_walk_increase,
+        &mut SearchContext {
+            city,
+            opt_transit,
+            area: None,
+            deadline,
+            on_progress,
+        },
+    )
+}
+
+/// Draw a random move of the stops of a route
+///
+/// # Returns
+/// The stops after inserting a candidate stop within `max_stop_dist` of both its new
+/// neighbours, removing a stop or swapping two stops. `None` if no valid move was drawn.
+/// Moves that break `constraint` are not valid.
+fn perturb(
+    params: &ACO,
+    current: &[Arc<TransitStop>],
+    stops: &[Arc<TransitStop>],
+    constraint: Option<&RouteConstraint>,
+    rng: &mut StdRng,
+) -> Option<Vec<Arc<TransitStop>>> {
+    let allowed = |moved: &[Arc<TransitStop>]| constraint.is_none_or(|c| c.check(moved).is_ok());
+    let n = current.len();
+    for _ in 0..MAX_MOVE_ATTEMPTS {
+        match rng.gen_range(0..3) {
+            // insert a stop between i - 1 and i
+            0 if n < params.max_route_len => {
+                let i = rng.gen_range(1..n);
+                let (prev, next) = (&current[i - 1], &current[i]);
+                let in_route: HashSet<&str> = current.iter().map(|s| s.stop_id.as_str()).collect();
+                let near = |stop: &TransitStop, other: &TransitStop| {
+                    geo_util::haversine(
+                        stop.geom.x(),
+                        stop.geom.y(),
+                        other.geom.x(),
+                        other.geom.y(),
+                    ) <= params.max_stop_dist
+                };
+                let choices: Vec<&Arc<TransitStop>> = stops
+                    .iter()
+                    .filter(|s| !in_route.contains(s.stop_id.as_str()))
+                    .filter(|s| near(s, prev) && near(s, next))
+                    .collect();
+                if choices.is_empty() {
+                    continue;
+                }
+                let mut moved = current.to_vec();
+                moved.insert(i, choices[rng.gen_range(0..choices.len())].clone());
+                if allowed(&moved) {
+                    return Some(moved);
+                }
+            }
+            // remove stop i
+            1 if n > params.min_route_len.max(2) => {
+                let mut moved = current.to_vec();
+                moved.remove(rng.gen_range(1..n - 1));
+                if allowed(&moved) {
+                    return Some(moved);
+                }
+            }
+            // swap stops i and j
+            2 if n > 3 => {
+                let i = rng.gen_range(1..n - 1);
+                let j = rng.gen_range(1..n - 1);
+                if i == j {
+                    continue;
+                }
+                let mut moved = current.to_vec();
+                moved.swap(i, j);
+                if allowed(&moved) {
+                    return Some(moved);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::demo_city::{load_demo_city, DemoCityConfig};
+
+    #[test]
+    fn annealing_improves_routes_deterministically() {
+        let city = load_demo_city(
+            &format!("sa_test_{}", std::process::id()),
+            &DemoCityConfig {
+                cols: 8,
+                rows: 8,
+                routes: 3,
+                ..Default::default()
+            },
+        );
+
+        let mut params = ACO::init();
+        params.max_gen = 10;
+        let mut improved = 0;
+        for route in &city.transit.routes {
+            let mut generations = 0;
+            let result = run_sa(
+                params.clone(),
+                route,
+                &city,
+                &city.transit,
+                None,
+                None,
+                &mut |event| {
+                    if let ProgressEvent::GenerationCompleted { .. } = event {
+                        generations += 1;
+                    }
+                },
+            );
+            assert_eq!(generations, params.max_gen);
+            let again = run_sa(
+                params.clone(),
+                route,
+                &city,
+                &city.transit,
+                None,
+                None,
+                &mut |_| {},
+            );
+            let stop_ids = |r: &Option<(TransitRoute, f64)>| {
+                r.as_ref().map(|(r, score)| {
+                    let ids: Vec<String> =
+                        r.outbound_stops.iter().map(|s| s.stop_id.clone()).collect();
+                    (ids, *score)
+                })
+            };
+            assert_eq!(stop_ids(&result), stop_ids(&again));
+
+            let Some((optimized, _)) = result else {
+                continue;
+            };
+            improved += 1;
+            assert_eq!(optimized.route_id, route.route_id);
+            assert!(optimized.evals.is_some());
+            assert_eq!(
+                optimized.outbound_stops.first().unwrap().stop_id,
+                route.outbound_stops.first().unwrap().stop_id
+            );
+            assert_eq!(
+                optimized.outbound_stops.last().unwrap().stop_id,
+                route.outbound_stops.last().unwrap().stop_id
+            );
+        }
+        assert!(improved > 0, "annealing improved no route");
+    }
+}
@@ -0,0 +1,170 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::layers::{error::Error, transit_network::TransitNetwork};
+
+use super::aco2::{AcoSeed, Pheromones, ACO};
+use super::eval::TransitNetworkEvals;
+
+/// An optimized network saved under a name, so that later optimizations can start from it
+/// instead of the original GTFS geometry
+#[derive(Serialize, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    /// When the scenario was saved in RFC 3339 format
+    pub saved_at: String,
+    pub network: TransitNetwork,
+    pub optimized_route_ids: Vec<String>,
+    /// Pheromone at the end of the last ACO run of each route, for the routes it was kept for
+    pub pheromones: HashMap<String, Pheromones>,
+}
+
+impl Scenario {
+    /// Check that a name can be used as a scenario name, which is also its file name
+    pub fn valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= 64
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    /// Seed to optimize a route from its state in this scenario
+    ///
+    /// # Returns
+    /// - The route of the scenario and its pheromone if it was kept, `None` if the scenario
+    ///   does not have the route
+    pub fn seed(&self, route_id: &str) -> Option<AcoSeed<'_>> {
+        let route = self
+            .network
+            .routes
+            .iter()
+            .find(|r| r.route_id == route_id)?;
+        Some(AcoSeed {
+            route,
+            pheromones: self.pheromones.get(route_id),
+        })
+    }
+}
+
+/// Metadata of a network stored in a scenario database
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ScenarioRecord {
+    /// Assigned by the database when the scenario is stored
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    /// When the scenario was stored in RFC 3339 format
+    pub saved_at: String,
+    /// ACO parameters the network was optimized with
+    pub params: ACO,
+    /// Evaluations of the network, `None` if they could not be read back
+    pub evals: Option<TransitNetworkEvals>,
+    pub optimized_route_ids: Vec<String>,
+}
+
+/// Scenarios of a city kept in a sqlite database, so that they outlive the server and can be
+/// compared over the course of a planning project
+///
+/// Unlike the scenarios saved by name in the city cache, stored scenarios are never replaced:
+/// each one gets a new id.
+pub struct ScenarioStore {
+    conn: Connection,
+}
+
+impl ScenarioStore {
+    /// Open a scenario database, creating it if needed
+    pub fn open(path: &str) -> Result<ScenarioStore, Error> {
+        let conn = Connection::open(path)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS scenarios (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                description TEXT,
+                saved_at TEXT NOT NULL,
+                params TEXT NOT NULL,
+                evals TEXT,
+                optimized_route_ids TEXT NOT NULL,
+                network BLOB NOT NULL
+            )",
+            params![],
+        )?;
+        Ok(ScenarioStore { conn })
+    }
+
+    /// Store a network with its metadata
+    ///
+    /// # Returns
+    /// The id of the new scenario, the id of `record` is ignored
+    pub fn insert(&self, record: &ScenarioRecord, network: &TransitNetwork) -> Result<i64, Error> {
+        self.conn.execute(
+            "INSERT INTO scenarios
+                (name, description, saved_at, params, evals, optimized_route_ids, network)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                record.name,
+                record.description,
+                record.saved_at,
+                serde_json::to_string(&record.params)?,
+                record
+                    .evals
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?,
+                serde_json::to_string(&record.optimized_route_ids)?,
+                bincode::serialize(network)?,
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Metadata of the stored scenarios, newest first
+    pub fn list(&self) -> Result<Vec<ScenarioRecord>, Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, description, saved_at, params, evals, optimized_route_ids
+                FROM scenarios ORDER BY id DESC",
+        )?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, String>(6)?,
+            ))
+        })?;
+        let mut records = vec![];
+        for row in rows {
+            let (id, name, description, saved_at, params, evals, optimized_route_ids) = row?;
+            records.push(ScenarioRecord {
+                id,
+                name,
+                description,
+                saved_at,
+                params: serde_json::from_str(&params)?,
+                evals: evals.and_then(|evals| serde_json::from_str(&evals).ok()),
+                optimized_route_ids: serde_json::from_str(&optimized_route_ids)?,
+            });
+        }
+        Ok(records)
+    }
+
+    /// Network of a stored scenario, `None` if there is no scenario with this id
+    pub fn network(&self, id: i64) -> Result<Option<TransitNetwork>, Error> {
+        let network: Option<Vec<u8>> = self
+            .conn
+            .query_row(
+                "SELECT network FROM scenarios WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(match network {
+            Some(network) => Some(bincode::deserialize(&network)?),
+            None => None,
+        })
+    }
+}
@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+
+/// Spatial search parameters of the optimizer and of the coverage evaluation.
+///
+/// Stored per city next to the city cache so they can be tuned without recompiling.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    // meters added around the bounding box of a route when collecting candidate stops
+    pub bbox_padding: f64,
+    // meters around a stop whose population counts towards its coverage
+    pub coverage_radius: f64,
+    // factor the next stop search radius grows by when no stop is found
+    pub radius_growth: f64,
+    // largest search radius, as a multiple of the route's max stop distance
+    pub max_radius_factor: f64,
+    // also offer stops only served in the inbound direction as candidates
+    pub include_inbound_stops: bool,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            bbox_padding: 250.0,
+            coverage_radius: 400.0,
+            radius_growth: 2.0,
+            max_radius_factor: 3.0,
+            include_inbound_stops: true,
+        }
+    }
+}
+
+// struct to support partial updates to the search parameters
+#[derive(Clone, Deserialize)]
+pub struct PartialSearchConfig {
+    pub bbox_padding: Option<f64>,
+    pub coverage_radius: Option<f64>,
+    pub radius_growth: Option<f64>,
+    pub max_radius_factor: Option<f64>,
+    pub include_inbound_stops: Option<bool>,
+}
+
+impl SearchConfig {
+    /// Apply a partial update, leaving the config untouched if the result would be invalid
+    ///
+    /// # Arguments
+    /// - `partial`: Parameters to change
+    ///
+    /// # Returns
+    /// - A description of the first invalid parameter on error
+    pub fn update_from_partial(&mut self, partial: PartialSearchConfig) -> Result<(), String> {
+        let mut updated = self.clone();
+        if let Some(bbox_padding) = partial.bbox_padding {
+            updated.bbox_padding = bbox_padding;
+        }
+        if let Some(coverage_radius) = partial.coverage_radius {
+            updated.coverage_radius = coverage_radius;
+        }
+        if let Some(radius_growth) = partial.radius_growth {
+            updated.radius_growth = radius_growth;
+        }
+        if let Some(max_radius_factor) = partial.max_radius_factor {
+            updated.max_radius_factor = max_radius_factor;
+        }
+        if let Some(include_inbound_stops) = partial.include_inbound_stops {
+            updated.include_inbound_stops = include_inbound_stops;
+        }
+        updated.validate()?;
+        *self = updated;
+        Ok(())
+    }
+
+    /// Check that the parameters can be used by the search
+    ///
+    /// # Notes
+    /// - The radius has to grow for the stop search to terminate
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.bbox_padding.is_finite() || self.bbox_padding < 0.0 {
+            return Err(format!(
+                "bbox_padding must be at least 0, got {}",
+                self.bbox_padding
+            ));
+        }
+        if !self.coverage_radius.is_finite() || self.coverage_radius <= 0.0 {
+            return Err(format!(
+                "coverage_radius must be positive, got {}",
+                self.coverage_radius
+            ));
+        }
+        if !self.radius_growth.is_finite() || self.radius_growth <= 1.0 {
+            return Err(format!(
+                "radius_growth must be greater than 1, got {}",
+                self.radius_growth
+            ));
+        }
+        if !self.max_radius_factor.is_finite() || self.max_radius_factor < 1.0 {
+            return Err(format!(
+                "max_radius_factor must be at least 1, got {}",
+                self.max_radius_factor
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::gtfs::structs::{format_gtfs_time, parse_gtfs_time, Trip};
+use crate::layers::{
+    grid::TimePeriod,
+    road_network::RoadNetwork,
+    transit_network::{TransitRoute, TransitStop},
+};
+
+use super::accessibility::AVG_BUS_SPEED_KMH;
+
+/// Time a bus spends at each intermediate stop when run times are estimated, in seconds
+const DWELL_SECS: f64 = 20.0;
+/// Length of the sample window of a timetable preview, in seconds
+const SAMPLE_SECS: u32 = 3600;
+
+/// Where the run times of a timetable come from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunTimeSource {
+    /// Times of the route's representative trip in the GTFS feed
+    Gtfs,
+    /// Road distance at `AVG_BUS_SPEED_KMH` plus `DWELL_SECS` at each intermediate stop
+    Estimated,
+}
+
+/// Departures of a route at one of its stops during the sample window
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StopDepartures {
+    pub stop_id: String,
+    /// Time from the first stop, in seconds
+    pub offset_secs: u32,
+    /// Departure times as `HH:MM:SS` local time
+    pub departures: Vec<String>,
+}
+
+/// Preview of the outbound timetable of a route over a one hour window of a time period
+///
+/// Trips leave the first stop at even intervals over the period, as many as the route
+/// departs during it in the GTFS feed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TimetablePreview {
+    /// Departures from the first stop over the whole period
+    pub departures_in_period: usize,
+    /// Minutes between departures, `None` without departures
+    pub headway_minutes: Option<f64>,
+    /// Time from the first to the last stop, in minutes
+    pub run_time_minutes: f64,
+    pub run_time_source: RunTimeSource,
+    pub stops: Vec<StopDepartures>,
+}
+
+impl TimetablePreview {
+    /// Build the timetable preview of a route
+    ///
+    /// # Parameters
+    /// - `route`: The route, departures are taken from its `stop_times`
+    /// - `period`: Time period the sample window starts at
+    /// - `road`: Road network used to estimate run times
+    /// - `trip`: GTFS trip the route's outbound stops come from, used for run times when it
+    ///   has times at every stop
+    ///
+    /// # Returns
+    /// The preview, departures past the end of the service day are left out
+    pub fn for_route(
+        route: &TransitRoute,
+        period: &TimePeriod,
+        road: &RoadNetwork,
+        trip: Option<&Trip>,
+    ) -> TimetablePreview {
+        let (offsets, run_time_source) =
+            match trip.and_then(|trip| gtfs_offsets(&route.outbound_stops, trip)) {
+                Some(offsets) => (offsets, RunTimeSource::Gtfs),
+                None => (
+                    estimated_offsets(&route.outbound_stops, road),
+                    RunTimeSource::Estimated,
+                ),
+            };
+
+        let (period_start, period_end) = period.local_bounds();
+        let departures_in_period = route
+            .stop_times
+            .get(&period.to_number())
+            .copied()
+            .unwrap_or(0);
+        let headway = (departures_in_period > 0)
+            .then(|| (period_end - period_start) as f64 / departures_in_period as f64);
+        let window_end = (period_start + SAMPLE_SECS).min(period_end);
+        let first_stop_departures: Vec<u32> = match headway {
+            Some(headway) => (0..departures_in_period)
+                .map(|i| period_start + (i as f64 * headway).round() as u32)
+                .take_while(|&t| t < window_end)
+                .collect(),
+            None => vec![],
+        };
+
+        let stops = route
+            .outbound_stops
+            .iter()
+            .zip(&offsets)
+            .map(|(stop, &offset)| StopDepartures {
+                stop_id: stop.stop_id.clone(),
+                offset_secs: offset,
+                departures: first_stop_departures
+                    .iter()
+                    .map(|t| format_gtfs_time(t + offset))
+                    .collect(),
+            })
+            .collect();
+
+        TimetablePreview {
+            departures_in_period,
+            headway_minutes: headway.map(|h| h / 60.0),
+            run_time_minutes: offsets.last().copied().unwrap_or(0) as f64 / 60.0,
+            run_time_source,
+            stops,
+        }
+    }
+}
+
+/// Time of each stop from the first one in a GTFS trip, `None` if a stop has no time in the
+/// trip or times go backwards
+pub(crate) fn gtfs_offsets(stops: &[Arc<TransitStop>], trip: &Trip) -> Option<Vec<u32>> {
+    let time = |stop_id: &str| {
+        trip.stop_times
+            .iter()
+            .find(|st| st.stop_id == stop_id)
+            .and_then(|st| {
+                st.departure_time
+                    .as_deref()
+                    .or(st.arrival_time.as_deref())
+                    .and_then(parse_gtfs_time)
+            })
+    };
+    let times: Vec<u32> = stops
+        .iter()
+        .map(|stop| time(&stop.stop_id))
+        .collect::<Option<_>>()?;
+    let first = *times.first()?;
+    if times.windows(2).any(|w| w[1] < w[0]) {
+        return None;
+    }
+    Some(times.into_iter().map(|t| t - first).collect())
+}
+
+/// Time of each stop from the first one, estimated from road distances
+pub(crate) fn estimated_offsets(stops: &[Arc<TransitStop>], road: &RoadNetwork) -> Vec<u32> {
+    estimated_offsets_at(stops, road, AVG_BUS_SPEED_KMH)
+}
+
+/// Time of each stop from the first one, estimated from road distances driven at a speed in
+/// km/h
+pub(crate) fn estimated_offsets_at(
+    stops: &[Arc<TransitStop>],
+    road: &RoadNetwork,
+    speed_kmh: f64,
+) -> Vec<u32> {
+    let speed_m_per_s = speed_kmh * 1000.0 / 3600.0;
+    let mut offsets = Vec::with_capacity(stops.len());
+    let mut elapsed = 0.0;
+    for (i, stop) in stops.iter().enumerate() {
+        if i > 1 {
+            elapsed += DWELL_SECS;
+        }
+        if i > 0 {
+            let (distance, _) = stops[i - 1].road_distance(stop, road);
+            elapsed += distance / speed_m_per_s;
+        }
+        offsets.push(elapsed.round() as u32);
+    }
+    offsets
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+
+use crate::gtfs::gtfs::Gtfs;
+use crate::layers::{
+    geo_util,
+    road_network::RoadNetwork,
+    transit_network::{ServiceSpan, TransitRoute},
+};
+
+/// Stops closer than this to the centerline of the mapped road are not checked,
+/// since their side of the street cannot be told apart from digitization noise
+const MIN_SIDE_OFFSET_M: f64 = 4.0;
+
+/// Side of the road vehicles drive on, which determines the side buses serve stops from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DrivingSide {
+    #[default]
+    Right,
+    Left,
+}
+
+/// A single problem found while validating a route
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValidationIssue {
+    /// The bus passes the stop with the stop on the far side of the road
+    WrongSideOfStreet {
+        stop_id: String,
+        /// Position of the stop in the route's outbound stop sequence
+        stop_index: usize,
+        /// Distance of the stop from the traversed road edge in meters
+        offset_m: f64,
+    },
+    /// The exported feed serves a shorter span of the day than the original route,
+    /// times are in seconds since the start of the service day
+    ServiceSpanNotPreserved {
+        original: ServiceSpan,
+        /// `None` if the exported trips have no times
+        exported: Option<ServiceSpan>,
+    },
+}
+
+/// Result of validating a route
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RouteValidation {
+    pub route_id: String,
+    pub valid: bool,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl RouteValidation {
+    /// Run every validation on the outbound stops of a route
+    ///
+    /// # Arguments
+    /// - `route`: Route to validate
+    /// - `road`: Road network the route's stops are mapped to
+    /// - `driving_side`: Side of the road vehicles drive on
+    pub fn for_route(
+        route: &TransitRoute,
+        road: &RoadNetwork,
+        driving_side: DrivingSide,
+    ) -> RouteValidation {
+        let issues = validate_side_of_street(route, road, driving_side);
+        RouteValidation {
+            route_id: route.route_id.clone(),
+            valid: issues.is_empty(),
+            issues,
+        }
+    }
+
+    /// Validate an optimized route together with its exported GTFS
+    ///
+    /// # Arguments
+    /// - `original`: Route before optimization
+    /// - `optimized`: Route after optimization
+    /// - `exported`: GTFS exported for the optimized route
+    /// - `road`: Road network the route's stops are mapped to
+    /// - `driving_side`: Side of the road vehicles drive on
+    pub fn for_export(
+        original: &TransitRoute,
+        optimized: &TransitRoute,
+        exported: &Gtfs,
+        road: &RoadNetwork,
+        driving_side: DrivingSide,
+    ) -> RouteValidation {
+        let mut validation = RouteValidation::for_route(optimized, road, driving_side);
+        if let Some(issue) = validate_service_span(original, exported) {
+            validation.issues.push(issue);
+            validation.valid = false;
+        }
+        validation
+    }
+}
+
+/// Check that an exported feed retains at least the service span of the original route
+///
+/// # Arguments
+/// - `original`: Route before optimization, carrying the span extracted from the source GTFS
+/// - `exported`: GTFS exported for the route
+///
+/// # Returns
+/// - A `ServiceSpanNotPreserved` issue if the exported trips start later or end earlier than
+///   the original, or have no times at all. Routes without an original span are not checked.
+pub fn validate_service_span(original: &TransitRoute, exported: &Gtfs) -> Option<ValidationIssue> {
+    let original_span = original.service_span.as_ref()?;
+    let exported_span = exported
+        .trips
+        .get(&original.route_id)
+        .and_then(ServiceSpan::from_trips);
+    match &exported_span {
+        Some(span) if span.covers(original_span) => None,
+        _ => Some(ValidationIssue::ServiceSpanNotPreserved {
+            original: original_span.clone(),
+            exported: exported_span,
+        }),
+    }
+}
+
+/// Check that every stop of a route is on the kerb side of the road the bus travels along
+///
+/// # Arguments
+/// - `route`: Route to validate
+/// - `road`: Road network the route's stops are mapped to
+/// - `driving_side`: Side of the road vehicles drive on
+///
+/// # Returns
+/// - A `WrongSideOfStreet` issue for every stop served from the wrong side
+///
+/// # Notes
+/// - The direction of travel at a stop is taken from the last road edge of the path arriving
+///   at the stop, or the first edge of the path leaving it for the first stop
+/// - Stops without a road path (e.g. not mapped to the road network) are skipped, as are
+///   stops within `MIN_SIDE_OFFSET_M` of the road centerline
+pub fn validate_side_of_street(
+    route: &TransitRoute,
+    road: &RoadNetwork,
+    driving_side: DrivingSide,
+) -> Vec<ValidationIssue> {
+    let stops = &route.outbound_stops;
+    let paths: Vec<_> = stops
+        .windows(2)
+        .map(|w| w[0].road_distance(&w[1], road).1)
+        .collect();
+
+    let mut issues = vec![];
+    for (i, stop) in stops.iter().enumerate() {
+        // Edge the bus traverses when serving the stop
+        let arriving = i
+            .checked_sub(1)
+            .and_then(|p| paths.get(p))
+            .filter(|path| path.len() >= 2)
+            .map(|path| (path[path.len() - 2], path[path.len() - 1]));
+        let leaving = paths
+            .get(i)
+            .filter(|path| path.len() >= 2)
+            .map(|path| (path[0], path[1]));
+        let (from, to) = match arriving.or(leaving) {
+            Some(edge) => edge,
+            None => continue,
+        };
+
+        let offset =
+            geo_util::signed_offset(road.get_node(from).geom, road.get_node(to).geom, stop.geom);
+        if offset.abs() < MIN_SIDE_OFFSET_M {
+            continue;
+        }
+        let on_left = offset > 0.0;
+        let wrong_side = match driving_side {
+            DrivingSide::Right => on_left,
+            DrivingSide::Left => !on_left,
+        };
+        if wrong_side {
+            issues.push(ValidationIssue::WrongSideOfStreet {
+                stop_id: stop.stop_id.clone(),
+                stop_index: i,
+                offset_m: offset.abs(),
+            });
+        }
+    }
+    issues
+}
@@ -0,0 +1,143 @@
+use geo::Centroid;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::layers::{
+    geo_util,
+    grid::GridNetwork,
+    transit_network::{TransitNetwork, TransitRoute},
+};
+
+use crate::ordering;
+
+/// Farthest a zone's nearest stop is looked for, in meters. Zones without a stop this close
+/// count as this far from one.
+pub const WALK_SEARCH_RADIUS_M: f64 = 2000.0;
+
+/// A zone whose walk to its nearest stop grows by more than the allowed increase
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WalkViolation {
+    pub zoneid: u32,
+    pub population: u32,
+    /// Straight-line distance from the zone's centroid to its nearest served stop, in meters
+    pub before_m: f64,
+    pub after_m: f64,
+}
+
+/// Change of the walk from zones to their nearest stop caused by replacing a route
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WalkCheck {
+    pub max_increase_m: f64,
+    /// Zones near a stop the change leaves unserved, the only ones whose walk can grow
+    pub zones_checked: usize,
+    /// Largest increase of any checked zone, in meters
+    pub worst_increase_m: f64,
+    /// Zones whose walk grows by more than `max_increase_m`, worst first
+    pub violations: Vec<WalkViolation>,
+}
+
+impl WalkCheck {
+    /// Compare the walk to the nearest stop of the zones affected by replacing a route
+    ///
+    /// # Arguments
+    /// - `before`: Route as it is in `network`
+    /// - `after`: Candidate replacing it, only its outbound stops are compared
+    /// - `network`: Network the route is part of, the other routes keep serving their stops
+    /// - `grid`: Zones to check
+    /// - `max_increase_m`: Largest allowed increase of any zone's walk
+    ///
+    /// # Notes
+    /// - Only zones within walking distance of a stop served before but not after the change
+    ///   are checked, other zones keep their nearest stop
+    /// - Walks are straight-line distances capped at `WALK_SEARCH_RADIUS_M`
+    pub fn for_route_change(
+        before: &TransitRoute,
+        after: &TransitRoute,
+        network: &TransitNetwork,
+        grid: &GridNetwork,
+        max_increase_m: f64,
+    ) -> WalkCheck {
+        let mut served_by_others: HashSet<&str> = HashSet::new();
+        for route in network
+            .routes
+            .iter()
+            .filter(|r| r.route_id != before.route_id)
+        {
+            for stop in route.outbound_stops.iter().chain(&route.inbound_stops) {
+                served_by_others.insert(&stop.stop_id);
+            }
+        }
+        let served_before: HashSet<&str> = before
+            .outbound_stops
+            .iter()
+            .chain(&before.inbound_stops)
+            .map(|s| s.stop_id.as_str())
+            .chain(served_by_others.iter().copied())
+            .collect();
+        let served_after: HashSet<&str> = after
+            .outbound_stops
+            .iter()
+            .chain(&before.inbound_stops)
+            .map(|s| s.stop_id.as_str())
+            .chain(served_by_others.iter().copied())
+            .collect();
+
+        let mut zones: Vec<u32> = before
+            .outbound_stops
+            .iter()
+            .filter(|s| !served_after.contains(s.stop_id.as_str()))
+            .flat_map(|s| s.nearby_zone_ids().iter().copied())
+            .collect::<HashSet<u32>>()
+            .into_iter()
+            .collect();
+        zones.sort();
+
+        let mut worst_increase_m: f64 = 0.0;
+        let mut violations = vec![];
+        for &zoneid in &zones {
+            let zone = grid.get_zone(grid.get_zone_idx_by_id(zoneid));
+            let Some(centroid) = zone.polygon.centroid() else {
+                continue;
+            };
+            let envelope =
+                geo_util::compute_envelope(centroid.y(), centroid.x(), WALK_SEARCH_RADIUS_M);
+            let nearby = network.stops_in_envelope(&envelope, true);
+            let nearest = |served: &HashSet<&str>| {
+                nearby
+                    .iter()
+                    .chain(&after.outbound_stops)
+                    .filter(|s| served.contains(s.stop_id.as_str()))
+                    .map(|s| {
+                        geo_util::haversine(centroid.x(), centroid.y(), s.geom.x(), s.geom.y())
+                    })
+                    .fold(WALK_SEARCH_RADIUS_M, f64::min)
+            };
+            let (before_m, after_m) = (nearest(&served_before), nearest(&served_after));
+            let increase = after_m - before_m;
+            worst_increase_m = worst_increase_m.max(increase);
+            if increase > max_increase_m {
+                violations.push(WalkViolation {
+                    zoneid,
+                    population: zone.population,
+                    before_m,
+                    after_m,
+                });
+            }
+        }
+        violations.sort_by(|a, b| {
+            ordering::cmp_f64_desc(a.after_m - a.before_m, b.after_m - b.before_m)
+                .then(a.zoneid.cmp(&b.zoneid))
+        });
+
+        WalkCheck {
+            max_increase_m,
+            zones_checked: zones.len(),
+            worst_increase_m,
+            violations,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
@@ -0,0 +1,126 @@
+use std::cmp::Ordering;
+
+/// Compare two floats in ascending order with NaN sorted after every number.
+///
+/// Unlike `partial_cmp().unwrap()` this never panics, and unlike `f64::total_cmp` it treats
+/// `-0.0` and `0.0` as equal and places every NaN last regardless of its sign, so a NaN score
+/// always ranks worst.
+pub fn cmp_f64(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// Compare two floats in descending order, still with NaN sorted after every number
+pub fn cmp_f64_desc(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (false, false) => cmp_f64(b, a),
+        _ => cmp_f64(a, b),
+    }
+}
+
+/// A float ordered by `f64::total_cmp`, for keys of heaps and other ordered collections
+///
+/// Equality follows the same total order, so `-0.0` and `0.0` differ and NaN equals itself.
+#[derive(Clone, Copy, Debug)]
+pub struct OrdF64(pub f64);
+
+impl PartialEq for OrdF64 {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OrdF64 {}
+
+impl PartialOrd for OrdF64 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrdF64 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascending_puts_nan_last() {
+        let mut values = [3.0, f64::NAN, -1.0, 2.0, -f64::NAN, 0.0];
+        values.sort_by(|a, b| cmp_f64(*a, *b));
+        assert_eq!(&values[..4], &[-1.0, 0.0, 2.0, 3.0]);
+        assert!(values[4..].iter().all(|v| v.is_nan()));
+    }
+
+    #[test]
+    fn descending_puts_nan_last() {
+        let mut values = [f64::NAN, 3.0, -1.0, f64::INFINITY, 2.0];
+        values.sort_by(|a, b| cmp_f64_desc(*a, *b));
+        assert_eq!(&values[..4], &[f64::INFINITY, 3.0, 2.0, -1.0]);
+        assert!(values[4].is_nan());
+    }
+
+    #[test]
+    fn signed_zeros_are_equal() {
+        assert_eq!(cmp_f64(-0.0, 0.0), Ordering::Equal);
+        assert_eq!(cmp_f64_desc(0.0, -0.0), Ordering::Equal);
+    }
+
+    #[test]
+    fn sort_is_stable_for_ties() {
+        let mut items = [
+            ("b", 1.0),
+            ("a", f64::NAN),
+            ("c", 1.0),
+            ("d", 0.5),
+            ("e", f64::NAN),
+        ];
+        items.sort_by(|x, y| cmp_f64_desc(x.1, y.1));
+        let ids: Vec<_> = items.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec!["b", "c", "d", "a", "e"]);
+    }
+
+    #[test]
+    fn comparison_is_consistent() {
+        let values = [
+            f64::NAN,
+            -2.0,
+            -0.0,
+            0.0,
+            1.5,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ];
+        for &a in &values {
+            for &b in &values {
+                assert_eq!(cmp_f64(a, b), cmp_f64(b, a).reverse());
+                assert_eq!(cmp_f64_desc(a, b), cmp_f64_desc(b, a).reverse());
+            }
+        }
+    }
+
+    #[test]
+    fn ord_f64_pops_the_smallest_first_from_a_reversed_heap() {
+        use std::{cmp::Reverse, collections::BinaryHeap};
+
+        let mut heap: BinaryHeap<_> = [2.5, 0.0, 7.0, 1.0]
+            .into_iter()
+            .map(|v| Reverse(OrdF64(v)))
+            .collect();
+        let mut popped = vec![];
+        while let Some(Reverse(OrdF64(v))) = heap.pop() {
+            popped.push(v);
+        }
+        assert_eq!(popped, vec![0.0, 1.0, 2.5, 7.0]);
+        assert_eq!(OrdF64(f64::NAN), OrdF64(f64::NAN));
+        assert!(OrdF64(-0.0) < OrdF64(0.0));
+    }
+}
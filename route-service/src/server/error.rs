@@ -0,0 +1,115 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::opt::progress::ProgressEvent;
+
+/// Error answering a request
+///
+/// The response body has the message in `error`, a `code` naming the kind of failure that
+/// clients can match on, and `details` about the request, `null` if there are none.
+#[derive(Error, Debug)]
+pub enum ServiceError {
+    /// The city is still loading or failed to load
+    #[error("City data not loaded")]
+    CityNotLoaded,
+    /// The optimized network has not been set up
+    #[error("Optimized transit data not loaded")]
+    NetworkNotLoaded,
+    /// A route, stop, workspace or other resource named by the request does not exist
+    #[error("{0}")]
+    NotFound(String),
+    /// The request is malformed or its parameters are invalid
+    #[error("{0}")]
+    InvalidRequest(String),
+    /// The request conflicts with the state of the city, e.g. a route already being optimized
+    #[error("{0}")]
+    Conflict(String),
+    /// The optimization ran but found no better route
+    #[error("{0}")]
+    NotOptimized(String),
+    /// The request body is larger than allowed
+    #[error("{0}")]
+    PayloadTooLarge(String),
+    /// The server failed to read or write its data
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl ServiceError {
+    /// Name of the kind of failure, as found in the `code` field of the response
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServiceError::CityNotLoaded => "city_not_loaded",
+            ServiceError::NetworkNotLoaded => "network_not_loaded",
+            ServiceError::NotFound(_) => "not_found",
+            ServiceError::InvalidRequest(_) => "invalid_request",
+            ServiceError::Conflict(_) => "conflict",
+            ServiceError::NotOptimized(_) => "not_optimized",
+            ServiceError::PayloadTooLarge(_) => "payload_too_large",
+            ServiceError::Internal(_) => "internal",
+        }
+    }
+
+    /// The error response, with `details` giving more about the failure
+    pub fn with_details(&self, details: Value) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": self.to_string(),
+            "code": self.code(),
+            "details": details,
+        }))
+    }
+}
+
+impl ResponseError for ServiceError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ServiceError::CityNotLoaded | ServiceError::NetworkNotLoaded => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ServiceError::NotFound(_) => StatusCode::NOT_FOUND,
+            ServiceError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            ServiceError::Conflict(_) => StatusCode::CONFLICT,
+            ServiceError::NotOptimized(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ServiceError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ServiceError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.with_details(Value::Null)
+    }
+}
+
+impl From<ServiceError> for ProgressEvent {
+    fn from(error: ServiceError) -> Self {
+        ProgressEvent::Error {
+            error: error.to_string(),
+            code: error.code().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::MessageBody;
+
+    #[test]
+    fn errors_answer_with_their_status_and_code() {
+        let response = ServiceError::NotFound("Route 1 not found".to_string())
+            .with_details(serde_json::json!({ "route_id": "1" }));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: Value =
+            serde_json::from_slice(&response.into_body().try_into_bytes().unwrap()).unwrap();
+        assert_eq!(body["error"], "Route 1 not found");
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(body["details"]["route_id"], "1");
+
+        let response = ServiceError::CityNotLoaded.error_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let event = ProgressEvent::from(ServiceError::CityNotLoaded);
+        let event: Value = serde_json::from_str(&event.to_json()).unwrap();
+        assert_eq!(event["code"], "city_not_loaded");
+    }
+}
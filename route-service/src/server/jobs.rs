@@ -0,0 +1,318 @@
+use actix::prelude::*;
+use actix_web::web;
+use actix_web_actors::ws;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::opt::progress::ProgressEvent;
+use crate::server::server::AppState;
+use crate::server::store::StoreStats;
+
+/// Most finished jobs kept for `GET /jobs/{id}`, the oldest are forgotten first
+const FINISHED_JOBS_MAX: usize = 100;
+
+/// Work of a job, run on the worker thread. It reports its progress to the callback and
+/// returns the body of the endpoint's response, or an error message.
+pub(crate) type JobWork =
+    Box<dyn FnOnce(&AppState, &mut dyn FnMut(ProgressEvent)) -> Result<Value, String> + Send>;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn finished(self) -> bool {
+        matches!(self, JobStatus::Succeeded | JobStatus::Failed)
+    }
+}
+
+/// A job as reported by `GET /jobs/{id}`
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct Job {
+    pub id: u64,
+    /// Endpoint that submitted the job, e.g. `optimize-routes`
+    pub kind: &'static str,
+    pub status: JobStatus,
+    /// Routes the job optimizes
+    pub routes: Vec<String>,
+    pub submitted_at: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    /// Jobs that run before this one, while it is queued
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_position: Option<usize>,
+    /// Progress events the job reported so far
+    pub events: usize,
+    /// Latest progress event of the job
+    pub progress: Option<ProgressEvent>,
+    /// Body of the endpoint's response, once the job succeeded
+    pub result: Option<Value>,
+    pub error: Option<String>,
+}
+
+/// Optimizations submitted by the endpoints, run one after the other on a worker thread
+///
+/// An endpoint submitting a job answers with its id at once instead of holding the server's
+/// locks until the optimization finishes. The worker thread is started by the first job
+/// submitted and stops once the queue is empty.
+#[derive(Default)]
+pub(crate) struct JobQueue {
+    state: Mutex<QueueState>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    next_id: u64,
+    jobs: BTreeMap<u64, Job>,
+    /// Jobs waiting for the worker, first to run first
+    pending: VecDeque<(u64, JobWork)>,
+    worker_running: bool,
+    /// Clients following a job that has not finished, see `JobQueue::subscribe`
+    subscribers: HashMap<u64, Vec<UnboundedSender<String>>>,
+    /// Finished jobs forgotten to stay within `FINISHED_JOBS_MAX`
+    evicted: u64,
+}
+
+impl QueueState {
+    fn snapshot(&self, job: &Job) -> Job {
+        let mut job = job.clone();
+        job.queue_position = self.pending.iter().position(|(id, _)| *id == job.id);
+        job
+    }
+
+    /// Send a message to the clients following a job, dropping those that went away
+    fn publish(&mut self, id: u64, message: &str) {
+        if let Some(subscribers) = self.subscribers.get_mut(&id) {
+            subscribers.retain(|tx| tx.unbounded_send(message.to_string()).is_ok());
+        }
+    }
+
+    /// Forget the oldest finished jobs past `FINISHED_JOBS_MAX`
+    fn evict_finished(&mut self) {
+        let finished: Vec<u64> = self
+            .jobs
+            .values()
+            .filter(|job| job.status.finished())
+            .map(|job| job.id)
+            .collect();
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(FINISHED_JOBS_MAX))
+        {
+            self.jobs.remove(id);
+            self.evicted += 1;
+        }
+    }
+}
+
+/// Message sent to the clients of a job once it finished, the job as `GET /jobs/{id}`
+/// reports it
+fn finished_message(job: &Job) -> String {
+    serde_json::json!({ "event": "job_finished", "job": job }).to_string()
+}
+
+impl JobQueue {
+    /// Queue a job, starting the worker thread if it is not running
+    ///
+    /// # Parameters
+    /// - `kind`: Endpoint submitting the job
+    /// - `routes`: Routes the job optimizes
+    ///
+    /// # Returns
+    /// The job as it was queued
+    pub fn submit(
+        data: &web::Data<AppState>,
+        kind: &'static str,
+        routes: Vec<String>,
+        work: JobWork,
+    ) -> Job {
+        let mut state = data.jobs.state.lock().unwrap();
+        state.next_id += 1;
+        let id = state.next_id;
+        state.jobs.insert(
+            id,
+            Job {
+                id,
+                kind,
+                status: JobStatus::Queued,
+                routes,
+                submitted_at: chrono::Local::now().to_rfc3339(),
+                started_at: None,
+                finished_at: None,
+                queue_position: None,
+                events: 0,
+                progress: None,
+                result: None,
+                error: None,
+            },
+        );
+        state.pending.push_back((id, work));
+        if !state.worker_running {
+            state.worker_running = true;
+            let data = data.clone();
+            thread::spawn(move || run_jobs(&data));
+        }
+        state.snapshot(&state.jobs[&id])
+    }
+
+    pub fn get(&self, id: u64) -> Option<Job> {
+        let state = self.state.lock().unwrap();
+        state.jobs.get(&id).map(|job| state.snapshot(job))
+    }
+
+    /// Every job remembered, oldest first
+    pub fn list(&self) -> Vec<Job> {
+        let state = self.state.lock().unwrap();
+        state.jobs.values().map(|job| state.snapshot(job)).collect()
+    }
+
+    /// Size and evictions of the jobs remembered, as reported by `/debug/stores`
+    ///
+    /// Only finished jobs are evicted, so `entries` may exceed `max_entries` while jobs are
+    /// queued or running.
+    pub fn stats(&self) -> StoreStats {
+        let state = self.state.lock().unwrap();
+        StoreStats {
+            name: "jobs",
+            entries: state.jobs.len(),
+            max_entries: FINISHED_JOBS_MAX,
+            ttl_secs: None,
+            evicted: state.evicted,
+            expired: 0,
+        }
+    }
+
+    /// Follow a job
+    ///
+    /// # Returns
+    /// A stream of the job's progress events as JSON, ending with the `job_finished` message,
+    /// which is all a job that already finished sends. `None` if there is no such job.
+    pub fn subscribe(&self, id: u64) -> Option<UnboundedReceiver<String>> {
+        let mut state = self.state.lock().unwrap();
+        let job = state.jobs.get(&id)?;
+        let (tx, rx) = mpsc::unbounded();
+        if job.status.finished() {
+            tx.unbounded_send(finished_message(job)).ok();
+        } else {
+            state.subscribers.entry(id).or_default().push(tx);
+        }
+        Some(rx)
+    }
+
+    /// Mark the next queued job as running
+    fn start_next(&self) -> Option<(u64, JobWork)> {
+        let mut state = self.state.lock().unwrap();
+        let Some((id, work)) = state.pending.pop_front() else {
+            state.worker_running = false;
+            return None;
+        };
+        if let Some(job) = state.jobs.get_mut(&id) {
+            job.status = JobStatus::Running;
+            job.started_at = Some(chrono::Local::now().to_rfc3339());
+        }
+        Some((id, work))
+    }
+
+    fn report(&self, id: u64, event: ProgressEvent) {
+        let mut state = self.state.lock().unwrap();
+        state.publish(id, &event.to_json());
+        if let Some(job) = state.jobs.get_mut(&id) {
+            job.events += 1;
+            job.progress = Some(event);
+        }
+    }
+
+    fn finish(&self, id: u64, outcome: Result<Value, String>) {
+        let mut state = self.state.lock().unwrap();
+        let Some(job) = state.jobs.get_mut(&id) else {
+            return;
+        };
+        job.finished_at = Some(chrono::Local::now().to_rfc3339());
+        match outcome {
+            Ok(result) => {
+                job.status = JobStatus::Succeeded;
+                job.result = Some(result);
+            }
+            Err(error) => {
+                job.status = JobStatus::Failed;
+                job.error = Some(error);
+            }
+        }
+        let message = finished_message(job);
+        state.publish(id, &message);
+        // dropping the senders ends the subscribers' streams
+        state.subscribers.remove(&id);
+        state.evict_finished();
+    }
+}
+
+/// Run the queued jobs until there are none left
+fn run_jobs(data: &AppState) {
+    while let Some((id, work)) = data.jobs.start_next() {
+        log::info!("Running job {}", id);
+        let mut on_progress = |event: ProgressEvent| data.jobs.report(id, event);
+        // a job that panics fails alone, the next ones still run
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| work(data, &mut on_progress)))
+            .unwrap_or_else(|_| Err(format!("Job {} panicked", id)));
+        data.jobs.finish(id, outcome);
+    }
+}
+
+/// WebSocket sending the progress events of a job to a client as they are reported, then the
+/// `job_finished` message, after which the socket is closed
+pub(crate) struct JobWs {
+    events: Option<UnboundedReceiver<String>>,
+}
+
+impl JobWs {
+    pub fn new(events: UnboundedReceiver<String>) -> Self {
+        JobWs {
+            events: Some(events),
+        }
+    }
+}
+
+impl Actor for JobWs {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(events) = self.events.take() {
+            ctx.add_stream(events);
+        }
+    }
+}
+
+impl StreamHandler<String> for JobWs {
+    fn handle(&mut self, message: String, ctx: &mut Self::Context) {
+        ctx.text(message);
+    }
+
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        ctx.close(None);
+        ctx.stop();
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for JobWs {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(_) => {}
+            Err(_) => ctx.stop(),
+        }
+    }
+}
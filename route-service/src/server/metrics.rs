@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::layers::memory::MemoryReport;
+use crate::opt::metrics::{escape, write_header, Histogram};
+
+/// Buckets in seconds of the request latencies, the Prometheus defaults
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Latencies of the requests the server answered, by method, endpoint and status
+///
+/// Endpoints are the route patterns, e.g. `/route/{route_id}`, so that every route of a city
+/// counts under the same endpoint. Requests no route matched are counted as `unmatched`.
+#[derive(Default)]
+pub(crate) struct RequestMetrics {
+    latencies: Mutex<BTreeMap<(String, String, u16), Histogram>>,
+}
+
+impl RequestMetrics {
+    pub fn observe(&self, method: &str, endpoint: Option<&str>, status: u16, latency: Duration) {
+        let key = (
+            method.to_string(),
+            endpoint.unwrap_or("unmatched").to_string(),
+            status,
+        );
+        self.latencies
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Histogram::new(LATENCY_BUCKETS))
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Write the latencies in the Prometheus text format
+    pub fn write(&self, out: &mut String) {
+        let name = "route_service_http_request_duration_seconds";
+        write_header(
+            out,
+            name,
+            "histogram",
+            "Latency of the requests by endpoint",
+        );
+        for ((method, endpoint, status), histogram) in self.latencies.lock().unwrap().iter() {
+            let labels = format!(
+                "method=\"{}\",endpoint=\"{}\",status=\"{}\"",
+                method,
+                escape(endpoint),
+                status
+            );
+            histogram.write(out, name, &labels);
+        }
+    }
+}
+
+/// Write how much memory the process and the loaded city take in the Prometheus text format
+pub(crate) fn write_memory(out: &mut String, city_name: &str, report: &MemoryReport) {
+    let city = escape(city_name);
+    let mut gauge = |name: &str, help: &str, value: u64| {
+        write_header(out, name, "gauge", help);
+        writeln!(out, "{name}{{city=\"{city}\"}} {value}").unwrap();
+    };
+    if let Some(rss_kb) = report.rss_kb {
+        gauge(
+            "route_service_resident_memory_bytes",
+            "Resident set size of the process",
+            rss_kb * 1024,
+        );
+    }
+    gauge(
+        "route_service_max_resident_memory_bytes",
+        "Largest resident set size of the process so far",
+        report.max_rss_kb * 1024,
+    );
+    let road = &report.road;
+    gauge(
+        "route_service_road_nodes",
+        "Nodes of the road network",
+        road.nodes as u64,
+    );
+    gauge(
+        "route_service_road_edges_in_memory",
+        "Road edges held in the in-memory graph",
+        road.edges_in_memory as u64,
+    );
+    gauge(
+        "route_service_road_mapped_bytes",
+        "Bytes of the road adjacency mapped from the city cache",
+        road.mapped_bytes as u64,
+    );
+    gauge(
+        "route_service_road_landmark_bytes",
+        "Bytes taken by the landmark costs bounding road paths",
+        road.landmark_bytes as u64,
+    );
+    gauge(
+        "route_service_road_cached_paths",
+        "Road paths kept in the path cache",
+        road.cached_paths as u64,
+    );
+    let grid = &report.grid;
+    gauge(
+        "route_service_grid_zones",
+        "Zones of the grid network",
+        grid.zones as u64,
+    );
+    gauge(
+        "route_service_grid_links_in_memory",
+        "Origin-Destination links held in the in-memory graph",
+        grid.links_in_memory as u64,
+    );
+    if let Some(lazy) = &grid.lazy_links {
+        gauge(
+            "route_service_grid_cached_links",
+            "Origin-Destination links of the origin zones in the lazy link cache",
+            lazy.cached_links as u64,
+        );
+        gauge(
+            "route_service_grid_link_cache_hits",
+            "Origin zones whose links were found in the lazy link cache",
+            lazy.hits,
+        );
+        gauge(
+            "route_service_grid_link_cache_misses",
+            "Origin zones whose links were read from the database",
+            lazy.misses,
+        );
+    }
+}
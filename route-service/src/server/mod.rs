@@ -1,4 +1,14 @@
 pub mod cors;
+pub mod error;
+pub mod jobs;
+pub mod metrics;
+pub mod notify;
 pub mod opt_ws;
 pub mod proxy;
+pub mod route_history;
+pub mod route_locks;
 pub mod server;
+pub mod store;
+pub mod workspace;
+#[cfg(test)]
+mod tests;
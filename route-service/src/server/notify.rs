@@ -0,0 +1,424 @@
+use actix_tls::connect::rustls_0_23::{reexports::ClientConfig, webpki_roots_cert_store};
+use actix_tls::connect::{Connector as TcpConnector, Resolve, Resolver};
+use awc::{Client, Connector};
+use futures::future::LocalBoxFuture;
+use log::{debug, warn};
+use serde::Serialize;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Completed,
+    Failed,
+}
+
+/// Change in average ridership over the routes a job optimized
+#[derive(Serialize, Clone, Debug)]
+pub struct ImprovementStats {
+    pub avg_ridership_before: f64,
+    pub avg_ridership_after: f64,
+    pub improvement_pct: f64,
+}
+
+/// Summary of a finished job, sent as the body of a webhook notification
+#[derive(Serialize, Clone, Debug)]
+pub struct JobSummary {
+    pub job: String,
+    pub city: String,
+    pub status: JobStatus,
+    pub routes_requested: usize,
+    pub routes_optimized: Vec<String>,
+    pub routes_failed: Vec<String>,
+    /// `None` if no route was optimized
+    pub improvement: Option<ImprovementStats>,
+    pub duration_ms: u128,
+    pub finished_at: String,
+    pub errors: Vec<String>,
+}
+
+impl JobSummary {
+    /// One line description of the job, used for chat webhooks
+    fn headline(&self) -> String {
+        let mut line = format!(
+            "[{}] {} {:?}: {}/{} routes optimized in {:.1}s",
+            self.city,
+            self.job,
+            self.status,
+            self.routes_optimized.len(),
+            self.routes_requested,
+            self.duration_ms as f64 / 1000.0
+        );
+        if let Some(improvement) = &self.improvement {
+            line.push_str(&format!(", ridership {:+.1}%", improvement.improvement_pct));
+        }
+        if !self.errors.is_empty() {
+            line.push_str(&format!(", {} errors", self.errors.len()));
+        }
+        line
+    }
+}
+
+/// A webhook notified when batch jobs finish
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Webhook {
+    pub url: String,
+    /// Whether the URL was given in a request, so it may only reach public addresses
+    pub public_only: bool,
+}
+
+impl Webhook {
+    /// A webhook configured for a city, which may be on the server's own network
+    pub fn configured(url: String) -> Webhook {
+        Webhook {
+            url,
+            public_only: false,
+        }
+    }
+
+    /// A webhook given in a request, see `validate_request_webhook_url`
+    pub fn requested(url: String) -> Webhook {
+        Webhook {
+            url,
+            public_only: true,
+        }
+    }
+}
+
+/// Check that a webhook URL can be notified
+pub fn validate_webhook_url(url: &str) -> Result<(), String> {
+    let parsed = Url::parse(url).map_err(|e| format!("Invalid webhook URL '{}': {}", url, e))?;
+    match parsed.scheme() {
+        "http" | "https" => Ok(()),
+        scheme => Err(format!(
+            "Unsupported webhook URL scheme '{}', expected http or https",
+            scheme
+        )),
+    }
+}
+
+/// Check that a webhook URL given in a request can be notified without reaching the
+/// server's own network
+///
+/// Unlike the webhooks configured for a city, the host of the URL must only resolve to public
+/// addresses, so requests cannot make the server post to loopback, private or link-local
+/// services. The host is resolved, so this blocks until its addresses are known.
+pub fn validate_request_webhook_url(url: &str) -> Result<(), String> {
+    resolve_public(url).map(|_| ())
+}
+
+/// Resolve the host of a webhook URL to an address, checking that every address it resolves
+/// to is public
+fn resolve_public(url: &str) -> Result<SocketAddr, String> {
+    validate_webhook_url(url)?;
+    let parsed = Url::parse(url).map_err(|e| format!("Invalid webhook URL '{}': {}", url, e))?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| format!("Webhook URL '{}' has no host", url))?;
+    let port = parsed.port_or_known_default().unwrap_or(80);
+    // IPv6 hosts are bracketed in URLs but not for resolution
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Cannot resolve webhook host '{}': {}", host, e))?
+        .collect();
+    for addr in &addrs {
+        if !is_public(addr.ip()) {
+            return Err(format!(
+                "Webhook host '{}' resolves to {}, which is not a public address",
+                host,
+                addr.ip()
+            ));
+        }
+    }
+    let addr = *addrs
+        .first()
+        .ok_or_else(|| format!("Webhook host '{}' has no address", host))?;
+    Ok(addr)
+}
+
+/// Whether an address can be reached from outside of the server's own network
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // carrier-grade NAT, 100.64.0.0/10
+                || (ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let segment = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local, fc00::/7
+                || (segment & 0xfe00) == 0xfc00
+                // link-local, fe80::/10
+                || (segment & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// `send` from a thread outside of the server's runtime, e.g. a background job's, without
+/// waiting for the webhook to respond
+pub fn send_in_background(webhook: Webhook, summary: JobSummary) {
+    std::thread::spawn(move || {
+        actix_web::rt::System::new().block_on(async move { send(&webhook, &summary).await })
+    });
+}
+
+/// Post a job summary to a webhook.
+///
+/// Slack incoming webhooks receive a text message, every other URL receives the summary as
+/// JSON. Redirects are not followed, and a webhook given in a request is resolved again and
+/// only connected to at the public address it was checked to resolve to, so its host cannot
+/// point the server to its own network after the request was validated. Delivery failures
+/// are logged and otherwise ignored.
+pub async fn send(webhook: &Webhook, summary: &JobSummary) {
+    let url = webhook.url.as_str();
+    let is_slack = Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h == "hooks.slack.com"))
+        .unwrap_or(false);
+    let body = if is_slack {
+        serde_json::json!({ "text": summary.headline() })
+    } else {
+        serde_json::json!(summary)
+    };
+
+    let pinned = match webhook.public_only {
+        true => match public_addr(url).await {
+            Ok(addr) => Some(addr),
+            Err(e) => {
+                warn!("Failed to notify webhook {}: {}", url, e);
+                return;
+            }
+        },
+        false => None,
+    };
+    match client(pinned, tls_config())
+        .post(url)
+        .send_json(&body)
+        .await
+    {
+        Ok(res) if res.status().is_success() => {
+            debug!("Notified webhook {} of {} job", url, summary.job)
+        }
+        Ok(res) => warn!("Webhook {} responded with {}", url, res.status()),
+        Err(e) => warn!("Failed to notify webhook {}: {}", url, e),
+    }
+}
+
+/// Address a webhook given in a request is posted to, its host resolved now after checking
+/// again that it is public
+async fn public_addr(url: &str) -> Result<SocketAddr, String> {
+    let checked = url.to_string();
+    actix_web::rt::task::spawn_blocking(move || resolve_public(&checked))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Resolves every host to the address a webhook's host was checked to resolve to
+struct PinnedResolver(IpAddr);
+
+impl Resolve for PinnedResolver {
+    fn lookup<'a>(
+        &'a self,
+        _host: &'a str,
+        port: u16,
+    ) -> LocalBoxFuture<'a, Result<Vec<SocketAddr>, Box<dyn std::error::Error>>> {
+        let addr = SocketAddr::new(self.0, port);
+        Box::pin(async move { Ok(vec![addr]) })
+    }
+}
+
+/// Client posting to webhooks
+///
+/// The URL keeps its host, which the TLS handshake is made and the certificate checked for,
+/// while the connection goes to `pinned` if it is given instead of resolving the host again.
+fn client(pinned: Option<SocketAddr>, tls: Arc<ClientConfig>) -> Client {
+    let resolver = match pinned {
+        Some(addr) => Resolver::custom(PinnedResolver(addr.ip())),
+        None => Resolver::default(),
+    };
+    let connector = Connector::new()
+        .connector(TcpConnector::new(resolver).service())
+        .rustls_0_23(tls);
+    Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .disable_redirects()
+        .connector(connector)
+        .finish()
+}
+
+/// TLS settings of the connections to webhooks, trusting the usual root certificates
+fn tls_config() -> Arc<ClientConfig> {
+    Arc::new(
+        ClientConfig::builder()
+            .with_root_certificates(webpki_roots_cert_store())
+            .with_no_client_auth(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_webhooks_must_reach_public_hosts() {
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://localhost/hook",
+            "http://10.1.2.3/hook",
+            "http://192.168.0.10/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+            "ftp://8.8.8.8/hook",
+        ] {
+            assert!(validate_request_webhook_url(url).is_err(), "{}", url);
+        }
+        assert!(validate_request_webhook_url("https://8.8.8.8/hook").is_ok());
+        // the city's own webhook may point anywhere
+        assert!(validate_webhook_url("http://127.0.0.1:8080/hook").is_ok());
+    }
+
+    #[actix_web::test]
+    async fn webhooks_do_not_follow_redirects_or_reach_private_hosts() {
+        use actix_web::{web, HttpRequest, HttpResponse, HttpServer};
+        use std::sync::Mutex;
+
+        let hits = web::Data::new(Mutex::new(Vec::<String>::new()));
+        let server_hits = hits.clone();
+        let server = HttpServer::new(move || {
+            actix_web::App::new()
+                .app_data(server_hits.clone())
+                .default_service(web::to(
+                    |req: HttpRequest, hits: web::Data<Mutex<Vec<String>>>| async move {
+                        hits.lock().unwrap().push(req.path().to_string());
+                        match req.path() {
+                            "/hook" => HttpResponse::TemporaryRedirect()
+                                .insert_header(("Location", "/redirected"))
+                                .finish(),
+                            _ => HttpResponse::Ok().finish(),
+                        }
+                    },
+                ))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        actix_rt::spawn(server);
+
+        let summary = JobSummary {
+            job: "optimize-routes".to_string(),
+            city: "test".to_string(),
+            status: JobStatus::Completed,
+            routes_requested: 0,
+            routes_optimized: vec![],
+            routes_failed: vec![],
+            improvement: None,
+            duration_ms: 0,
+            finished_at: String::new(),
+            errors: vec![],
+        };
+        send(
+            &Webhook::configured(format!("http://{}/hook", addr)),
+            &summary,
+        )
+        .await;
+        send(
+            &Webhook::requested(format!("http://{}/requested", addr)),
+            &summary,
+        )
+        .await;
+        handle.stop(true).await;
+
+        assert_eq!(*hits.lock().unwrap(), vec!["/hook".to_string()]);
+    }
+
+    #[actix_web::test]
+    async fn webhooks_are_posted_over_https_to_the_pinned_address() {
+        use actix_web::{web, HttpRequest, HttpResponse, HttpServer};
+        use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+        use rustls::{RootCertStore, ServerConfig};
+        use std::sync::Mutex;
+
+        // a certificate for a host that does not resolve, so only the pinned address reaches
+        // the server
+        let certified =
+            rcgen::generate_simple_self_signed(vec!["webhook.test".to_string()]).unwrap();
+        let cert = CertificateDer::from(certified.cert.der().to_vec());
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        let server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key.into())
+            .unwrap();
+
+        let hosts = web::Data::new(Mutex::new(Vec::<String>::new()));
+        let server_hosts = hosts.clone();
+        let server = HttpServer::new(move || {
+            actix_web::App::new()
+                .app_data(server_hosts.clone())
+                .default_service(web::to(
+                    |req: HttpRequest, hosts: web::Data<Mutex<Vec<String>>>| async move {
+                        hosts
+                            .lock()
+                            .unwrap()
+                            .push(req.connection_info().host().to_string());
+                        HttpResponse::Ok().finish()
+                    },
+                ))
+        })
+        .workers(1)
+        .bind_rustls_0_23(("127.0.0.1", 0), server_config)
+        .unwrap();
+        let addr = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        actix_rt::spawn(server);
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let tls = Arc::new(
+            ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        );
+        let url = format!("https://webhook.test:{}/hook", addr.port());
+        let res = client(Some(addr), tls.clone())
+            .post(&url)
+            .send_json(&serde_json::json!({}))
+            .await;
+        // the certificate is checked for the host of the URL
+        let mismatched = client(Some(addr), tls)
+            .post(format!("https://other.test:{}/hook", addr.port()))
+            .send_json(&serde_json::json!({}))
+            .await;
+        handle.stop(true).await;
+
+        assert!(res.unwrap().status().is_success());
+        assert!(mismatched.is_err());
+        assert_eq!(
+            *hosts.lock().unwrap(),
+            vec![format!("webhook.test:{}", addr.port())]
+        );
+    }
+}
@@ -1,11 +1,24 @@
-use crate::opt::aco2;
+use crate::layers::{city::City, transit_network::TransitNetwork};
+use crate::opt::aco2::{self, OptimizedTransitNetwork, PartialACO};
+use crate::opt::checkpoint::{Checkpoint, LiveProgress};
+use crate::opt::progress::{IterationProgress, ParamChange, ProgressEvent};
+use crate::server::error::ServiceError;
 use crate::server::server::{get_optimized_geojson, AppState};
 
 use actix::prelude::*;
 use actix_web::web;
 use actix_web_actors::ws;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
 use std::time::{Duration, Instant};
 
+/// Id of the next live session
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Iterations between two checkpoints of a live session
+const CHECKPOINT_EVERY: usize = 5;
+
 // WebSocket actor for live optimization
 pub(crate) struct OptimizationWs {
     app_state: web::Data<AppState>,
@@ -17,6 +30,10 @@ pub(crate) struct OptimizationWs {
     iterations_per_route: usize, // Number of iterations to run per route
     converged_routes: Vec<bool>, // Track which routes have converged
     optimize_attempts_per_route: Vec<usize>, // Track optimization attempts for each route
+    session_id: u64,            // Id the session's parameters are changed by
+    params: aco2::ACO,          // ACO parameters of the session, copied from the server's
+    param_changes: Vec<ParamChange>, // Parameters changed while the session runs
+    resumed_at: Option<usize>,  // Iterations done before, if resumed from a checkpoint
 }
 
 impl OptimizationWs {
@@ -24,9 +41,9 @@ impl OptimizationWs {
         let iterations_per_route = 10; // 10 iterations per route
         let total_iterations = iterations_per_route * route_ids.len(); // Total iterations across all routes
         let routes_count = route_ids.len();
+        let params = app_state.optimization_params();
 
         Self {
-            app_state,
             route_ids: route_ids.clone(),
             iterations_done: 0,
             total_iterations,
@@ -35,6 +52,173 @@ impl OptimizationWs {
             iterations_per_route,
             converged_routes: vec![false; routes_count], // Initialize all routes as not converged
             optimize_attempts_per_route: vec![0; routes_count], // Initialize optimization attempts count
+            session_id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+            params,
+            param_changes: vec![],
+            resumed_at: None,
+            app_state,
+        }
+    }
+
+    /// A session carrying on from the checkpoint of an earlier session on the same routes
+    ///
+    /// The routes the earlier session optimized are put back into the active workspace, the
+    /// other routes of the checkpoint are left out. The parameter changes of the earlier
+    /// session are applied over the server's parameters again.
+    pub fn resume(
+        app_state: web::Data<AppState>,
+        route_ids: Vec<String>,
+        checkpoint: Checkpoint,
+    ) -> Result<Self, String> {
+        let live = match checkpoint.live {
+            Some(live) if checkpoint.route_ids == route_ids => live,
+            _ => return Err("The checkpoint is not of a live session on these routes".to_string()),
+        };
+        if live.converged_routes.len() != route_ids.len()
+            || live.optimize_attempts.len() != route_ids.len()
+        {
+            return Err("The checkpoint is corrupt".to_string());
+        }
+
+        {
+            let mut optimized_transit_guard = app_state.optimized_transit.write().unwrap();
+            let Some(optimized_transit) = optimized_transit_guard.as_mut() else {
+                return Err("City data not loaded".to_string());
+            };
+            let mut optimized_route_ids = app_state.optimized_route_ids.lock().unwrap();
+            let mut reviews = app_state.route_reviews.lock().unwrap();
+            let OptimizedTransitNetwork {
+                network,
+                optimized_routes,
+            } = checkpoint.network;
+            for route in network.routes {
+                if !route_ids.contains(&route.route_id)
+                    || !optimized_routes.contains(&route.route_id)
+                {
+                    continue;
+                }
+                let route_id = route.route_id.clone();
+                optimized_transit.routes.retain(|r| r.route_id != route_id);
+                optimized_transit.routes.push(route);
+                if !optimized_route_ids.contains(&route_id) {
+                    optimized_route_ids.push(route_id.clone());
+                }
+                reviews.propose(&route_id);
+            }
+            let mut route_pheromones = app_state.route_pheromones.lock().unwrap();
+            for (route_id, pheromones) in checkpoint.pheromones {
+                route_pheromones.insert(route_id, pheromones);
+            }
+        }
+
+        let mut session = Self::new(app_state, route_ids);
+        for change in &live.param_changes {
+            session.params.update_from_partial(change.changes.clone());
+        }
+        session.iterations_done = checkpoint.progress;
+        session.converged_routes = live.converged_routes;
+        session.optimize_attempts_per_route = live.optimize_attempts;
+        session.param_changes = live.param_changes;
+        session.resumed_at = Some(checkpoint.progress);
+        Ok(session)
+    }
+
+    /// Save where the session is at, to resume it if it stops before finishing
+    fn save_checkpoint(
+        &self,
+        city: &City,
+        optimized_transit: &TransitNetwork,
+        optimized_route_ids: &[String],
+    ) {
+        let route_pheromones = self.app_state.route_pheromones.lock().unwrap().to_map();
+        let checkpoint = Checkpoint {
+            saved_at: chrono::Local::now().to_rfc3339(),
+            route_ids: self.route_ids.clone(),
+            progress: self.iterations_done,
+            network: OptimizedTransitNetwork {
+                network: optimized_transit.clone(),
+                optimized_routes: optimized_route_ids.to_vec(),
+            },
+            pheromones: route_pheromones
+                .into_iter()
+                .filter(|(route_id, _)| self.route_ids.contains(route_id))
+                .collect(),
+            live: Some(LiveProgress {
+                converged_routes: self.converged_routes.clone(),
+                optimize_attempts: self.optimize_attempts_per_route.clone(),
+                param_changes: self.param_changes.clone(),
+            }),
+        };
+        let name = Checkpoint::live_name(&self.route_ids);
+        if let Err(e) = City::save_checkpoint(&city.name, &name, &checkpoint) {
+            println!(
+                "Failed to save checkpoint of live session {}: {}",
+                self.session_id, e
+            );
+        }
+    }
+
+    /// Remove the checkpoint of the session once it finished
+    fn remove_checkpoint(&self) {
+        let city_guard = self.app_state.city.read().unwrap();
+        let Some(city) = &*city_guard else {
+            return;
+        };
+        let name = Checkpoint::live_name(&self.route_ids);
+        if let Err(e) = City::remove_checkpoint(&city.name, &name) {
+            println!(
+                "Failed to remove checkpoint of live session {}: {}",
+                self.session_id, e
+            );
+        }
+    }
+
+    fn send(ctx: &mut ws::WebsocketContext<Self>, event: ProgressEvent) {
+        ctx.text(event.to_json());
+    }
+
+    /// Change the parameters the next iterations run with and record it in the session log
+    fn update_params(
+        &mut self,
+        changes: PartialACO,
+        source: &str,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) -> Result<ParamChange, String> {
+        changes.validate()?;
+        println!(
+            "Updating parameters of live session {} after iteration {}",
+            self.session_id, self.iterations_done
+        );
+        self.params.update_from_partial(changes.clone());
+        let change = ParamChange {
+            iteration: self.iterations_done,
+            source: source.to_string(),
+            changes,
+        };
+        self.param_changes.push(change.clone());
+        Self::send(
+            ctx,
+            ProgressEvent::ParamsUpdated {
+                session_id: self.session_id,
+                change: Box::new(change.clone()),
+            },
+        );
+        Ok(change)
+    }
+
+    /// Progress of the iteration running for the route at `route_index`
+    fn progress(&self, route_index: usize, route_iteration: usize) -> IterationProgress {
+        IterationProgress {
+            iteration: self.iterations_done + 1,
+            total_iterations: self.total_iterations,
+            current_route: self.route_ids[route_index].clone(),
+            current_route_index: route_index,
+            routes_count: self.route_ids.len(),
+            all_route_ids: self.route_ids.clone(),
+            route_iteration,
+            iterations_per_route: self.iterations_per_route,
+            converged_routes: self.converged_routes.clone(),
+            optimize_attempts: self.optimize_attempts_per_route.clone(),
         }
     }
 
@@ -42,6 +226,20 @@ impl OptimizationWs {
         // Check if we've completed all iterations
         if self.iterations_done >= self.total_iterations {
             println!("Completed all iterations for routes {:?}", self.route_ids);
+            self.remove_checkpoint();
+            Self::send(
+                ctx,
+                ProgressEvent::BatchFinished {
+                    message: "Completed all iterations".to_string(),
+                    iteration: self.total_iterations,
+                    total_iterations: self.total_iterations,
+                    all_converged: self.converged_routes.iter().all(|&c| c),
+                    early_completion: false,
+                    converged_routes: self.converged_routes.clone(),
+                    optimize_attempts: self.optimize_attempts_per_route.clone(),
+                    param_changes: self.param_changes.clone(),
+                },
+            );
             ctx.close(None);
             return;
         }
@@ -84,17 +282,19 @@ impl OptimizationWs {
             // If all routes have converged, we can finish early
             if !found_non_converged {
                 println!("All routes have converged, finishing optimization early");
-                ctx.text(
-                    serde_json::to_string(&serde_json::json!({
-                        "message": "All routes have converged to optimal solutions",
-                        "iteration": self.total_iterations,
-                        "total_iterations": self.total_iterations,
-                        "all_converged": true,
-                        "early_completion": true,
-                        "converged_routes": self.converged_routes.clone(),
-                        "optimize_attempts": self.optimize_attempts_per_route.clone()
-                    }))
-                    .unwrap(),
+                self.remove_checkpoint();
+                Self::send(
+                    ctx,
+                    ProgressEvent::BatchFinished {
+                        message: "All routes have converged to optimal solutions".to_string(),
+                        iteration: self.total_iterations,
+                        total_iterations: self.total_iterations,
+                        all_converged: true,
+                        early_completion: true,
+                        converged_routes: self.converged_routes.clone(),
+                        optimize_attempts: self.optimize_attempts_per_route.clone(),
+                        param_changes: self.param_changes.clone(),
+                    },
                 );
                 ctx.close(None);
                 return;
@@ -129,176 +329,264 @@ impl OptimizationWs {
             self.iterations_per_route
         );
 
-        // Update heartbeat timestamp to prevent timeout during long-running optimization
+        let workspace = self
+            .app_state
+            .workspaces
+            .lock()
+            .unwrap()
+            .active()
+            .to_string();
+        let app_state = self.app_state.clone();
+        let params = self.params.clone();
+        let addr = ctx.address();
         self.heartbeat = Instant::now();
 
+        // ACO runs on a thread of its own, so the session sends its generation events as they
+        // are reported and keeps answering the client meanwhile
+        thread::spawn(move || {
+            let outcome =
+                Self::run_iteration(&app_state, &workspace, &route_id, params, &mut |event| {
+                    addr.do_send(Progress(event))
+                });
+            addr.do_send(IterationFinished {
+                workspace,
+                route_id,
+                route_index: current_route_index,
+                route_iteration,
+                outcome,
+            });
+        });
+    }
+
+    /// Optimize the route of an iteration in a copy of the workspace's network and write it
+    /// back if ACO found a better version
+    fn run_iteration(
+        app_state: &AppState,
+        workspace: &str,
+        route_id: &str,
+        params: aco2::ACO,
+        on_progress: &mut dyn FnMut(ProgressEvent),
+    ) -> IterationOutcome {
+        // a request optimizing the route from a copy of the network would overwrite this
+        // iteration's result, wait for it to finish instead
+        let _route_lock = match app_state
+            .route_locks
+            .try_lock(workspace, &[route_id.to_string()])
+        {
+            Ok(guard) => guard,
+            Err(e) => return IterationOutcome::Busy(e),
+        };
+
         // Access the city data (immutable)
-        let city_guard = match self.app_state.city.lock() {
+        let city_guard = match app_state.city.read() {
             Ok(guard) => guard,
             Err(e) => {
                 println!("Failed to acquire lock on city data: {}", e);
-                ctx.text(
-                    serde_json::to_string(&serde_json::json!({
-                        "error": "Server error: Failed to access city data"
-                    }))
-                    .unwrap(),
-                );
-                ctx.close(None);
-                return;
+                return IterationOutcome::Failed(ServiceError::Internal(
+                    "Failed to access city data".to_string(),
+                ));
             }
         };
+        let Some(city) = &*city_guard else {
+            println!("City data not loaded");
+            return IterationOutcome::Failed(ServiceError::CityNotLoaded);
+        };
 
-        if let Some(city) = &*city_guard {
-            // Get the optimized transit data
-            let mut optimized_transit_guard = match self.app_state.optimized_transit.lock() {
-                Ok(guard) => guard,
-                Err(e) => {
-                    println!("Failed to acquire lock on optimized transit data: {}", e);
-                    ctx.text(
-                        serde_json::to_string(&serde_json::json!({
-                            "error": "Server error: Failed to access optimized transit data"
-                        }))
-                        .unwrap(),
-                    );
-                    ctx.close(None);
-                    return;
-                }
-            };
-
-            let optimized_transit = optimized_transit_guard.as_mut().unwrap();
-            let mut all_evaluations = Vec::new();
-            let mut optimized_count = 0;
-            let mut optimized_route_ids_guard = self.app_state.optimized_route_ids.lock().unwrap();
-
-            // Find the specific route to optimize in this iteration
-            let route = optimized_transit
-                .routes
-                .iter()
-                .find(|r| r.route_id == route_id)
-                .cloned();
+        // the route is optimized in a copy of the network, so the other requests keep
+        // answering while it runs, only writing it back locks the network
+        let network = match app_state.optimized_transit.read() {
+            Ok(guard) => guard.as_ref().unwrap().clone(),
+            Err(e) => {
+                println!("Failed to acquire lock on optimized transit data: {}", e);
+                return IterationOutcome::Failed(ServiceError::Internal(
+                    "Failed to access optimized transit data".to_string(),
+                ));
+            }
+        };
+        let Some(route) = network.routes.iter().find(|r| r.route_id == route_id) else {
+            return IterationOutcome::RouteNotFound;
+        };
 
-            if let Some(route) = route {
-                // Create ACO instance for this optimization iteration
-                let aco = self.app_state.aco_params.lock().unwrap().clone();
+        let Some((opt_route, eval)) =
+            aco2::run_aco_with_progress(params.clone(), route, city, &network, None, on_progress)
+        else {
+            return IterationOutcome::NotImproved;
+        };
 
-                // Increment the optimization attempt counter for this route
-                self.optimize_attempts_per_route[current_route_index] += 1;
+        let mut workspaces = app_state.workspaces.lock().unwrap();
+        let mut optimized_transit_guard = app_state.optimized_transit.write().unwrap();
+        let mut optimized_route_ids_guard = app_state.optimized_route_ids.lock().unwrap();
+        // the workspace may have been deactivated while the route was optimized
+        let (optimized_transit, optimized_route_ids) = match workspaces.get_mut(
+            Some(workspace),
+            optimized_transit_guard.as_mut().unwrap(),
+            &mut optimized_route_ids_guard,
+        ) {
+            Ok(workspace) => workspace,
+            Err(e) => return IterationOutcome::Failed(ServiceError::NotFound(e)),
+        };
+        app_state.route_history.lock().unwrap().record(
+            workspace,
+            &opt_route,
+            "optimize-live",
+            Some(eval),
+            &params,
+        );
+        // Update the route in optimized_transit for next iteration
+        optimized_transit.routes.retain(|r| r.route_id != route_id);
+        optimized_transit.routes.push(opt_route);
 
-                match aco2::run_aco(aco, &route, &city, &optimized_transit) {
-                    Some((opt_route, eval)) => {
-                        // Update the route in optimized_transit for next iteration
-                        optimized_transit.routes.retain(|r| r.route_id != route_id);
-                        optimized_transit.routes.push(opt_route);
+        // Ensure route ID is in the optimized list
+        if !optimized_route_ids.iter().any(|id| id == route_id) {
+            optimized_route_ids.push(route_id.to_string());
+        }
+        app_state.route_reviews.lock().unwrap().propose(route_id);
+        IterationOutcome::Optimized(eval)
+    }
 
-                        // Ensure route ID is in the optimized list
-                        if !optimized_route_ids_guard.contains(&route_id) {
-                            optimized_route_ids_guard.push(route_id.clone());
-                        }
+    /// Report the outcome of an iteration to the client and schedule the next one
+    fn finish_iteration(
+        &mut self,
+        finished: IterationFinished,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let IterationFinished {
+            workspace,
+            route_id,
+            route_index: current_route_index,
+            route_iteration,
+            outcome,
+        } = finished;
+        self.heartbeat = Instant::now();
 
-                        all_evaluations.push((route_id.clone(), eval));
-                        optimized_count += 1;
-                    }
-                    None => {
-                        println!(
-                            "Failed to optimize route {} - marking as converged",
-                            route_id
-                        );
-
-                        // if this is the first iteration for this route, it is optimal already, mark it as noop
-                        let noop_route_ids = {
-                            let mut noop_route_ids_guard =
-                                self.app_state.noop_route_ids.lock().unwrap();
-                            if route_iteration == 1 {
-                                println!("Route {} is already optimal, marking as noop", route_id);
-                                if !noop_route_ids_guard.contains(&route_id) {
-                                    noop_route_ids_guard.push(route_id.clone());
-                                }
-                            }
-                            noop_route_ids_guard.clone()
-                        };
-
-                        // Mark this route as converged
-                        self.converged_routes[current_route_index] = true;
-
-                        // No optimization was performed, but we need to send a message to the client
-                        let convergence_msg = serde_json::json!({
-                            "message": format!("Route {} has converged to optimal solution", route_id),
-                            "warning": format!("Route {} reached optimal solution", route_id),
-                            "iteration": self.iterations_done + 1,
-                            "total_iterations": self.total_iterations,
-                            "current_route": route_id,
-                            "current_route_index": current_route_index,
-                            "routes_count": self.route_ids.len(),
-                            "all_route_ids": self.route_ids,
-                            "route_iteration": route_iteration, // Current iteration number for this route
-                            "iterations_per_route": self.iterations_per_route,
-                            "converged_routes": self.converged_routes.clone(), // Include which routes have converged
-                            "optimize_attempts": self.optimize_attempts_per_route.clone(),
-                            "converged": true,
-                            "converged_route": route_id,
-                            "converged_route_index": current_route_index,
-                            "noop_route_ids": noop_route_ids,
-                        });
-
-                        ctx.text(serde_json::to_string(&convergence_msg).unwrap());
-                    }
-                }
-            } else {
+        let mut all_evaluations = Vec::new();
+        let mut optimized_count = 0;
+        match outcome {
+            IterationOutcome::Busy(e) => {
+                println!("{}, retrying", e);
+                let current_iteration = self.iterations_done;
+                let addr = ctx.address();
+                ctx.run_later(Duration::from_millis(500), move |_, _| {
+                    addr.do_send(RunNextIteration {
+                        iteration: current_iteration,
+                    });
+                });
+                return;
+            }
+            IterationOutcome::Failed(error) => {
+                Self::send(ctx, error.into());
+                ctx.close(None);
+                return;
+            }
+            IterationOutcome::RouteNotFound => {
                 println!("Route {} not found", route_id);
                 // Mark this route as converged (or essentially skipped)
                 self.converged_routes[current_route_index] = true;
             }
+            IterationOutcome::Optimized(eval) => {
+                // Increment the optimization attempt counter for this route
+                self.optimize_attempts_per_route[current_route_index] += 1;
+                all_evaluations.push((route_id.clone(), eval));
+                optimized_count += 1;
+            }
+            IterationOutcome::NotImproved => {
+                self.optimize_attempts_per_route[current_route_index] += 1;
+                println!(
+                    "Failed to optimize route {} - marking as converged",
+                    route_id
+                );
 
-            // Send an update for all routes
-            if optimized_count > 0 {
-                let response = serde_json::json!({
-                    "message": format!("Optimized route {} (route {}/{}, iteration {}/{})",
-                                    route_id, current_route_index + 1, self.route_ids.len(),
-                                    route_iteration, self.iterations_per_route),
-                    "geojson": get_optimized_geojson(city, optimized_transit, &optimized_route_ids_guard),
-                    "evaluation": all_evaluations,
-                    "iteration": self.iterations_done + 1,
-                    "total_iterations": self.total_iterations,
-                    "current_route": route_id,
-                    "current_route_index": current_route_index,
-                    "routes_count": self.route_ids.len(),
-                    "all_route_ids": self.route_ids,
-                    "route_iteration": route_iteration,
-                    "iterations_per_route": self.iterations_per_route,
-                    "converged_routes": self.converged_routes.clone(),
-                    "optimize_attempts": self.optimize_attempts_per_route.clone(),
-                    "optimized_routes": optimized_count
-                });
+                // if this is the first iteration for this route, it is optimal already, mark it as noop
+                let noop_route_ids = {
+                    let mut noop_route_ids_guard = self.app_state.noop_route_ids.lock().unwrap();
+                    if route_iteration == 1 {
+                        println!("Route {} is already optimal, marking as noop", route_id);
+                        noop_route_ids_guard.insert(route_id.clone(), ());
+                    }
+                    noop_route_ids_guard.keys()
+                };
 
-                // Send the update via WebSocket
-                ctx.text(serde_json::to_string(&response).unwrap());
-            }
+                // Mark this route as converged
+                self.converged_routes[current_route_index] = true;
 
-            // Increment iteration counter
-            self.iterations_done += 1;
+                // No optimization was performed, but we need to send a message to the client
+                Self::send(
+                    ctx,
+                    ProgressEvent::RouteConverged {
+                        message: format!("Route {} has converged to optimal solution", route_id),
+                        warning: format!("Route {} reached optimal solution", route_id),
+                        converged_route: route_id.clone(),
+                        converged_route_index: current_route_index,
+                        noop_route_ids,
+                        progress: self.progress(current_route_index, route_iteration),
+                    },
+                );
+            }
+        }
 
-            // Schedule next iteration with a short delay
-            let current_iteration = self.iterations_done;
-            let addr = ctx.address();
-            ctx.run_later(Duration::from_millis(500), move |_, _| {
-                addr.do_send(RunNextIteration {
-                    iteration: current_iteration,
-                });
-            });
-        } else {
-            let error_msg = "City data not loaded";
-            println!("{}", error_msg);
-            ctx.text(
-                serde_json::to_string(&serde_json::json!({
-                    "error": error_msg
-                }))
-                .unwrap(),
-            );
+        let city_guard = self.app_state.city.read().unwrap();
+        let Some(city) = &*city_guard else {
+            println!("City data not loaded");
+            Self::send(ctx, ServiceError::CityNotLoaded.into());
             ctx.close(None);
+            return;
+        };
+        let workspaces = self.app_state.workspaces.lock().unwrap();
+        let optimized_transit_guard = self.app_state.optimized_transit.read().unwrap();
+        let optimized_route_ids_guard = self.app_state.optimized_route_ids.lock().unwrap();
+        let (optimized_transit, optimized_route_ids) = match workspaces.get(
+            Some(&workspace),
+            optimized_transit_guard.as_ref().unwrap(),
+            &optimized_route_ids_guard,
+        ) {
+            Ok(workspace) => workspace,
+            Err(e) => {
+                Self::send(ctx, ServiceError::NotFound(e).into());
+                ctx.close(None);
+                return;
+            }
+        };
+
+        // Send an update for all routes
+        if optimized_count > 0 {
+            let event = ProgressEvent::RouteOptimized {
+                message: format!(
+                    "Optimized route {} (route {}/{}, iteration {}/{})",
+                    route_id,
+                    current_route_index + 1,
+                    self.route_ids.len(),
+                    route_iteration,
+                    self.iterations_per_route
+                ),
+                geojson: get_optimized_geojson(
+                    city,
+                    optimized_transit,
+                    optimized_route_ids,
+                    &self.app_state.route_reviews.lock().unwrap(),
+                ),
+                evaluation: all_evaluations,
+                optimized_routes: optimized_count,
+                progress: self.progress(current_route_index, route_iteration),
+            };
+
+            // Send the update via WebSocket
+            Self::send(ctx, event);
         }
 
-        // Update heartbeat timestamp again after the long optimization process
-        self.heartbeat = Instant::now();
+        // Increment iteration counter
+        self.iterations_done += 1;
+        if Checkpoint::due(self.iterations_done, CHECKPOINT_EVERY) {
+            self.save_checkpoint(city, optimized_transit, optimized_route_ids);
+        }
+
+        // Schedule next iteration with a short delay
+        let current_iteration = self.iterations_done;
+        let addr = ctx.address();
+        ctx.run_later(Duration::from_millis(500), move |_, _| {
+            addr.do_send(RunNextIteration {
+                iteration: current_iteration,
+            });
+        });
     }
 
     // Heartbeat to keep connection alive
@@ -346,6 +634,57 @@ impl Handler<RunNextIteration> for OptimizationWs {
     }
 }
 
+/// Generation event of the iteration running on its own thread, sent on to the client
+struct Progress(ProgressEvent);
+
+impl Message for Progress {
+    type Result = ();
+}
+
+impl Handler<Progress> for OptimizationWs {
+    type Result = ();
+
+    fn handle(&mut self, msg: Progress, ctx: &mut ws::WebsocketContext<Self>) {
+        self.heartbeat = Instant::now();
+        Self::send(ctx, msg.0);
+    }
+}
+
+/// How the run of an iteration ended, see `OptimizationWs::run_iteration`
+enum IterationOutcome {
+    /// The route is being optimized by another request, with why it could not be locked
+    Busy(String),
+    /// The route is not in the network
+    RouteNotFound,
+    /// ACO found a better version of the route, with its evaluation
+    Optimized(f64),
+    /// ACO found no better version of the route
+    NotImproved,
+    /// The iteration could not run, the session stops
+    Failed(ServiceError),
+}
+
+/// Message the thread running an iteration sends once it finished
+struct IterationFinished {
+    workspace: String,
+    route_id: String,
+    route_index: usize,
+    route_iteration: usize,
+    outcome: IterationOutcome,
+}
+
+impl Message for IterationFinished {
+    type Result = ();
+}
+
+impl Handler<IterationFinished> for OptimizationWs {
+    type Result = ();
+
+    fn handle(&mut self, msg: IterationFinished, ctx: &mut ws::WebsocketContext<Self>) {
+        self.finish_iteration(msg, ctx);
+    }
+}
+
 impl Actor for OptimizationWs {
     type Context = ws::WebsocketContext<Self>;
 
@@ -356,29 +695,72 @@ impl Actor for OptimizationWs {
         );
 
         // Send immediate confirmation that the WebSocket connection is established
-        let connection_msg = serde_json::json!({
-            "status": "connected",
-            "message": "WebSocket connection established, optimization starting",
-            "routes": self.route_ids,
-        });
+        let connection_msg = ProgressEvent::Started {
+            message: "WebSocket connection established, optimization starting".to_string(),
+            routes: self.route_ids.clone(),
+            session_id: Some(self.session_id),
+            resumed_at: self.resumed_at,
+        };
 
         println!(
             "Sending WebSocket connection confirmation: {:?}",
             connection_msg
         );
 
+        self.app_state
+            .live_sessions
+            .lock()
+            .unwrap()
+            .insert(self.session_id, ctx.address());
+
         // Send the confirmation message immediately
-        ctx.text(serde_json::to_string(&connection_msg).unwrap());
+        Self::send(ctx, connection_msg);
 
         // Setup heartbeat first, optimization second
         self.heartbeat(ctx);
 
         // Short delay before starting optimization to ensure connection message is received
         let addr = ctx.address();
+        let iteration = self.iterations_done;
         ctx.run_later(Duration::from_millis(100), move |_, _| {
-            addr.do_send(RunNextIteration { iteration: 0 });
+            addr.do_send(RunNextIteration { iteration });
         });
     }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.app_state
+            .live_sessions
+            .lock()
+            .unwrap()
+            .remove(&self.session_id);
+    }
+}
+
+/// Change the parameters of a live session, from the next iteration on
+pub(crate) struct UpdateParams {
+    pub changes: PartialACO,
+    /// How the change was requested, see `ParamChange::source`
+    pub source: &'static str,
+}
+
+impl Message for UpdateParams {
+    type Result = Result<ParamChange, String>;
+}
+
+impl Handler<UpdateParams> for OptimizationWs {
+    type Result = Result<ParamChange, String>;
+
+    fn handle(&mut self, msg: UpdateParams, ctx: &mut ws::WebsocketContext<Self>) -> Self::Result {
+        self.update_params(msg.changes, msg.source, ctx)
+    }
+}
+
+/// Messages a client sends over the WebSocket, e.g.
+/// `{"type": "update_params", "params": {"max_nonlinearity": 2.5}}`
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    UpdateParams { params: PartialACO },
 }
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for OptimizationWs {
@@ -393,9 +775,18 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for OptimizationWs {
                 println!("Received pong");
                 self.heartbeat = Instant::now();
             }
-            Ok(ws::Message::Text(_)) => {
+            Ok(ws::Message::Text(text)) => {
                 println!("Received text message");
                 self.heartbeat = Instant::now();
+                let result = match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::UpdateParams { params }) => {
+                        self.update_params(params, "websocket", ctx).map(|_| ())
+                    }
+                    Err(e) => Err(format!("Invalid message: {}", e)),
+                };
+                if let Err(error) = result {
+                    Self::send(ctx, ServiceError::InvalidRequest(error).into());
+                }
             }
             Ok(ws::Message::Binary(_)) => {
                 println!("Received binary message");
@@ -1,10 +1,19 @@
 use actix_codec::Framed;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::http::header;
 use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
 
 use awc::{ws::Codec, BoxedSocket, Client};
+use futures::future::join_all;
 use futures::{FutureExt, SinkExt, StreamExt};
-use log::{debug, error, log_enabled, warn};
+use log::{debug, error, info, log_enabled, warn};
+use serde::Deserialize;
+use serde_json::Value;
 use std::collections::HashMap;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::RwLock;
 
 use actix::{
     Actor, ActorContext, ActorFutureExt, AsyncContext, Message as ActixMessage, StreamHandler,
@@ -14,27 +23,127 @@ use actix_web_actors::ws;
 use awc::ws::{Frame, Message};
 use std::time::{Duration, Instant};
 
+use crate::layers::memory::MemoryMode;
+use crate::layers::vehicle::RouteVehicles;
+use crate::opt::aco2::PartialACO;
 use crate::server::cors::cors_middleware;
+use crate::server::notify::{self, Webhook};
+use crate::server::server::{start_server, OptimizationLimits};
 
 const MAX_PAYLOAD_SIZE: usize = 20 * 1024 * 1024;
 
+/// Time the proxy waits for a city server to answer unless configured otherwise
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Ports tried for a city registered without one, after the highest port in use
+const MAX_PORT_ATTEMPTS: u16 = 100;
+
 // Define the city-to-port mapping
 pub struct CityConfig {
-    pub cities: HashMap<String, u16>,
+    cities: RwLock<HashMap<String, u16>>,
     pub default_city: Option<String>,
+    /// Time the proxy waits for a city server to answer
+    pub request_timeout: Duration,
+    /// Starts the servers of the cities registered through `POST /admin/cities`, `None` if
+    /// cities cannot be registered at runtime
+    launcher: Option<CityLauncher>,
+    /// Token `POST /admin/cities` must be sent with, `None` to refuse every registration
+    pub admin_token: Option<String>,
 }
 
 impl CityConfig {
     pub fn new(city_ports: HashMap<String, u16>) -> Self {
         CityConfig {
-            cities: city_ports,
+            cities: RwLock::new(city_ports),
             default_city: Some("toronto".to_string()),
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            launcher: None,
+            admin_token: None,
         }
     }
 
+    pub fn with_launcher(mut self, launcher: CityLauncher) -> Self {
+        self.launcher = Some(launcher);
+        self
+    }
+
     pub fn get_port(&self, city: &str) -> Option<u16> {
-        self.cities.get(city).copied()
+        self.cities.read().unwrap().get(city).copied()
+    }
+
+    /// Cities and their ports, sorted by name
+    pub fn cities(&self) -> Vec<(String, u16)> {
+        let mut cities: Vec<(String, u16)> = self
+            .cities
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(city, &port)| (city.clone(), port))
+            .collect();
+        cities.sort();
+        cities
+    }
+}
+
+/// Where the data of cities is found and how their servers are started
+#[derive(Clone)]
+pub struct CityLauncher {
+    pub host: String,
+    pub gtfs_base_path: String,
+    pub db_base_path: String,
+    pub optimization_limits: OptimizationLimits,
+    pub memory_mode: MemoryMode,
+    /// ACO parameters the servers start with
+    pub aco_defaults: PartialACO,
+    /// Vehicle profiles of the routes of each city, by city name
+    pub vehicles: HashMap<String, RouteVehicles>,
+}
+
+impl CityLauncher {
+    pub fn gtfs_path(&self, city: &str) -> String {
+        format!("{}/{}/gtfs", self.gtfs_base_path, city)
+    }
+
+    pub fn db_path(&self, city: &str) -> String {
+        format!("{}/{}.db", self.db_base_path, city)
     }
+
+    /// Start the server of a city on its own thread, loading the city can take a while and
+    /// must not block the proxy. The city is unregistered once its server stops.
+    fn launch(
+        &self,
+        city_config: web::Data<CityConfig>,
+        city: String,
+        port: u16,
+        webhook: Option<Webhook>,
+    ) {
+        let launcher = self.clone();
+        std::thread::spawn(move || {
+            let (gtfs_path, db_path) = (launcher.gtfs_path(&city), launcher.db_path(&city));
+            let result = actix_web::rt::System::new().block_on(start_server(
+                &city,
+                &gtfs_path,
+                &db_path,
+                &launcher.host,
+                port,
+                webhook,
+                launcher.optimization_limits,
+                launcher.memory_mode,
+                launcher.aco_defaults.clone(),
+                launcher.vehicles.get(&city).cloned().unwrap_or_default(),
+            ));
+            if let Err(e) = result {
+                error!("Failed to start server for {}: {}", city, e);
+            }
+            warn!("Server for {} on port {} stopped", city, port);
+            city_config.cities.write().unwrap().remove(&city);
+        });
+    }
+}
+
+/// Whether a port can be bound on a host, i.e. nothing else listens on it
+fn port_available(host: &str, port: u16) -> bool {
+    TcpListener::bind((host, port)).is_ok()
 }
 
 // Define messages for internal actor communication
@@ -452,7 +561,7 @@ async fn proxy_handler(
 
     // Create a client for this request with increased payload limit
     let client = Client::builder()
-        .timeout(std::time::Duration::from_secs(60))
+        .timeout(city_config.request_timeout)
         .finish();
 
     // Create a new request with the same method
@@ -476,50 +585,318 @@ async fn proxy_handler(
 
     // Wait for response from city server
     match forwarded_req.await {
-        Ok(mut res) => {
+        Ok(res) => {
             let mut client_res = HttpResponse::build(res.status());
 
-            // Copy headers from the city server response
-            for (header_name, header_value) in res.headers().iter() {
+            // Copy headers from the city server response, the framing of the body is set
+            // again for the response to the client
+            for (header_name, header_value) in res.headers().iter().filter(|(h, _)| {
+                *h != "content-length" && *h != "transfer-encoding" && *h != "connection"
+            }) {
                 client_res.insert_header((header_name.clone(), header_value.clone()));
             }
 
-            // Stream body from city server to client
-            match res.body().limit(MAX_PAYLOAD_SIZE).await {
-                Ok(body) => client_res.body(body),
+            // Stream body from city server to client as it arrives, without buffering it
+            client_res.streaming(res)
+        }
+        Err(e) => {
+            error!("Proxy request failed: {}", e);
+            HttpResponse::InternalServerError().body(format!("Proxy request failed: {}", e))
+        }
+    }
+}
+
+/// Compare the cities behind the proxy.
+///
+/// Every city's `/city-summary` is fetched and each normalized metric is also expressed as an
+/// index against the mean of all cities, 100 being the mean. Cities that fail to respond are
+/// listed with an error and left out of the means.
+async fn summary_handler(city_config: web::Data<CityConfig>) -> HttpResponse {
+    let client = Client::builder()
+        .timeout(city_config.request_timeout)
+        .finish();
+
+    let cities = city_config.cities();
+    let responses = join_all(cities.iter().map(|(_, port)| {
+        let request = client.get(format!("http://127.0.0.1:{}/city-summary", port));
+        async move {
+            let mut res = request.send().await.map_err(|e| e.to_string())?;
+            if !res.status().is_success() {
+                return Err(format!("City server responded with {}", res.status()));
+            }
+            res.json::<Value>()
+                .limit(MAX_PAYLOAD_SIZE)
+                .await
+                .map_err(|e| e.to_string())
+        }
+    }))
+    .await;
+
+    // mean of each normalized metric over the cities that responded
+    let mut totals: HashMap<String, (f64, usize)> = HashMap::new();
+    for summary in responses.iter().flatten() {
+        if let Some(normalized) = summary["normalized"].as_object() {
+            for (metric, value) in normalized {
+                if let Some(value) = value.as_f64() {
+                    let total = totals.entry(metric.clone()).or_insert((0.0, 0));
+                    total.0 += value;
+                    total.1 += 1;
+                }
+            }
+        }
+    }
+    let means: HashMap<String, f64> = totals
+        .into_iter()
+        .map(|(metric, (sum, count))| (metric, sum / count as f64))
+        .collect();
+
+    let summaries: Vec<Value> = cities
+        .iter()
+        .zip(responses)
+        .map(|((city, _), response)| match response {
+            Ok(mut summary) => {
+                let index: serde_json::Map<String, Value> = summary["normalized"]
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|(metric, value)| {
+                        let (value, mean) = (value.as_f64()?, *means.get(metric)?);
+                        let index = if mean > 0.0 {
+                            value / mean * 100.0
+                        } else {
+                            0.0
+                        };
+                        Some((metric.clone(), serde_json::json!(index)))
+                    })
+                    .collect();
+                summary["index"] = Value::Object(index);
+                summary
+            }
+            Err(e) => {
+                warn!("Failed to get summary of city '{}': {}", city, e);
+                serde_json::json!({ "city": city, "error": e })
+            }
+        })
+        .collect();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "cities": summaries,
+        "means": means,
+    }))
+}
+
+/// List the cities behind the proxy with their port and time zone.
+///
+/// Every city's `/city-info` is fetched, cities that fail to respond are listed with an error.
+async fn cities_handler(city_config: web::Data<CityConfig>) -> HttpResponse {
+    let client = Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .finish();
+
+    let cities = city_config.cities();
+    let responses = join_all(cities.iter().map(|(_, port)| {
+        let request = client.get(format!("http://127.0.0.1:{}/city-info", port));
+        async move {
+            let mut res = request.send().await.map_err(|e| e.to_string())?;
+            if !res.status().is_success() {
+                return Err(format!("City server responded with {}", res.status()));
+            }
+            res.json::<Value>().await.map_err(|e| e.to_string())
+        }
+    }))
+    .await;
+
+    let infos: Vec<Value> = cities
+        .iter()
+        .zip(responses)
+        .map(|((city, port), response)| match response {
+            Ok(mut info) => {
+                info["city"] = serde_json::json!(city);
+                info["port"] = serde_json::json!(port);
+                info
+            }
+            Err(e) => {
+                warn!("Failed to get info of city '{}': {}", city, e);
+                serde_json::json!({ "city": city, "port": port, "error": e })
+            }
+        })
+        .collect();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "cities": infos,
+        "default_city": city_config.default_city,
+    }))
+}
+
+#[derive(Deserialize)]
+struct RegisterCityRequest {
+    /// Name of the city, its data is read from `{gtfs_base_path}/{name}/gtfs` and
+    /// `{db_base_path}/{name}.db`
+    name: String,
+    /// Port of the city's server, the first free port after the highest one in use if unset
+    port: Option<u16>,
+    /// Webhook notified when the city's batch jobs finish
+    webhook_url: Option<String>,
+}
+
+/// Register a city and start its server without restarting the proxy.
+///
+/// The request must carry the proxy's admin token. The city is listed at once, its server
+/// answers once the city is loaded. A city whose server fails to start is unregistered again.
+async fn register_city_handler(
+    req: HttpRequest,
+    city_config: web::Data<CityConfig>,
+    request: web::Json<RegisterCityRequest>,
+) -> HttpResponse {
+    let Some(admin_token) = &city_config.admin_token else {
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "Registering cities requires the proxy's admin_token to be set"
+        }));
+    };
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if bearer != Some(admin_token.as_str()) {
+        return HttpResponse::Unauthorized()
+            .json(serde_json::json!({"error": "Missing or wrong admin token"}));
+    }
+    let Some(launcher) = &city_config.launcher else {
+        return HttpResponse::NotImplemented()
+            .json(serde_json::json!({"error": "This proxy cannot start city servers"}));
+    };
+    let RegisterCityRequest {
+        name,
+        port,
+        webhook_url,
+    } = request.into_inner();
+    let bad_request = |e: String| HttpResponse::BadRequest().json(serde_json::json!({"error": e}));
+
+    let valid_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    if !valid_name {
+        return bad_request(format!(
+            "Invalid city name '{}', expected lowercase letters, digits, '_' or '-'",
+            name
+        ));
+    }
+    let webhook = match webhook_url {
+        Some(url) => {
+            let checked = url.clone();
+            match web::block(move || notify::validate_request_webhook_url(&checked)).await {
+                Ok(Ok(())) => Some(Webhook::requested(url)),
+                Ok(Err(e)) => return bad_request(e),
                 Err(e) => {
-                    error!("Failed to get response body: {}", e);
-                    HttpResponse::InternalServerError()
-                        .body(format!("Failed to get response body: {}", e))
+                    return HttpResponse::InternalServerError()
+                        .json(serde_json::json!({"error": e.to_string()}))
                 }
             }
         }
+        None => None,
+    };
+    let (gtfs_path, db_path) = (launcher.gtfs_path(&name), launcher.db_path(&name));
+    if !Path::new(&gtfs_path).is_dir() {
+        return bad_request(format!("No GTFS data for {} at {}", name, gtfs_path));
+    }
+    if !Path::new(&db_path).is_file() {
+        return bad_request(format!("No database for {} at {}", name, db_path));
+    }
+
+    let already_registered = || {
+        HttpResponse::Conflict()
+            .json(serde_json::json!({"error": format!("City '{}' is already registered", name)}))
+    };
+    let in_use: Vec<u16> = {
+        let cities = city_config.cities.read().unwrap();
+        if cities.contains_key(&name) {
+            return already_registered();
+        }
+        cities.values().copied().collect()
+    };
+    // binding a port blocks, so ports are probed without holding the cities
+    let host = launcher.host.clone();
+    let probed = web::block(move || match port {
+        Some(port) if in_use.contains(&port) || !port_available(&host, port) => {
+            Err(format!("Port {} is not available", port))
+        }
+        Some(port) => Ok(port),
+        None => {
+            let after = in_use.iter().copied().max().unwrap_or(8080);
+            (1..=MAX_PORT_ATTEMPTS)
+                .filter_map(|i| after.checked_add(i))
+                .find(|&p| !in_use.contains(&p) && port_available(&host, p))
+                .ok_or_else(|| format!("No free port after {}", after))
+        }
+    })
+    .await;
+    let port = match probed {
+        Ok(Ok(port)) => port,
+        Ok(Err(e)) => return HttpResponse::Conflict().json(serde_json::json!({"error": e})),
         Err(e) => {
-            error!("Proxy request failed: {}", e);
-            HttpResponse::InternalServerError().body(format!("Proxy request failed: {}", e))
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({"error": e.to_string()}))
+        }
+    };
+    {
+        // another registration may have taken the name or the port meanwhile
+        let mut cities = city_config.cities.write().unwrap();
+        if cities.contains_key(&name) {
+            return already_registered();
         }
+        if cities.values().any(|&p| p == port) {
+            return HttpResponse::Conflict()
+                .json(serde_json::json!({"error": format!("Port {} is not available", port)}));
+        }
+        cities.insert(name.clone(), port);
     }
+
+    info!("Registered city {} on port {}", name, port);
+    launcher.launch(city_config.clone(), name.clone(), port, webhook);
+    HttpResponse::Created().json(serde_json::json!({
+        "city": name,
+        "port": port,
+        "gtfs_path": gtfs_path,
+        "db_path": db_path,
+    }))
+}
+
+pub(crate) fn build_proxy_app(
+    city_config: web::Data<CityConfig>,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    App::new()
+        .wrap(cors_middleware())
+        .app_data(city_config)
+        .app_data(web::PayloadConfig::new(MAX_PAYLOAD_SIZE))
+        .app_data(web::JsonConfig::default().limit(MAX_PAYLOAD_SIZE))
+        .route("/summary", web::get().to(summary_handler))
+        .route("/cities", web::get().to(cities_handler))
+        .route("/admin/cities", web::post().to(register_city_handler))
+        .default_service(web::route().to(proxy_handler))
 }
 
 // Start the proxy server
 pub async fn start_proxy_server(
     host: &str,
     port: u16,
-    city_ports: HashMap<String, u16>,
+    city_config: CityConfig,
 ) -> std::io::Result<()> {
-    let city_config = web::Data::new(CityConfig::new(city_ports));
+    let city_config = web::Data::new(city_config);
 
     debug!("Starting proxy server on {}:{}", host, port);
 
-    HttpServer::new(move || {
-        App::new()
-            .wrap(cors_middleware())
-            .app_data(city_config.clone())
-            .app_data(web::PayloadConfig::new(MAX_PAYLOAD_SIZE))
-            .app_data(web::JsonConfig::default().limit(MAX_PAYLOAD_SIZE))
-            .default_service(web::route().to(proxy_handler))
-    })
-    .bind(format!("{}:{}", host, port))?
-    .run()
-    .await
+    HttpServer::new(move || build_proxy_app(city_config.clone()))
+        .bind(format!("{}:{}", host, port))?
+        .run()
+        .await
 }
@@ -0,0 +1,143 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::layers::transit_network::TransitRoute;
+use crate::opt::aco2;
+use crate::opt::eval::TransitRouteEvals;
+
+/// Most versions kept per route, the oldest are dropped first
+const VERSIONS_MAX: usize = 20;
+
+/// A version of a route accepted into the network of a workspace by an optimization
+#[derive(Clone, Serialize)]
+pub(crate) struct RouteVersion {
+    /// Number of the version, counting up from 1 for the first optimization of the route
+    pub version: usize,
+    pub accepted_at: String,
+    /// Endpoint or job that optimized the route
+    pub source: String,
+    /// Evaluation of the route by the optimization, `None` if it did not report one
+    pub evaluation: Option<f64>,
+    pub evals: Option<TransitRouteEvals>,
+    /// ACO parameters the route was optimized with
+    pub params: aco2::ACO,
+    #[serde(skip)]
+    pub route: TransitRoute,
+}
+
+/// Versions of the optimized routes, by workspace and route
+///
+/// Every optimization accepted into a network pushes the new version of the route, so a
+/// planner can step back to an earlier candidate of a route without resetting the others.
+/// Undoing the first version puts the original route of the city back.
+#[derive(Default)]
+pub(crate) struct RouteHistory {
+    versions: HashMap<(String, String), Vec<RouteVersion>>,
+}
+
+impl RouteHistory {
+    /// Record the version of a route just accepted into the network of a workspace
+    pub fn record(
+        &mut self,
+        workspace: &str,
+        route: &TransitRoute,
+        source: &str,
+        evaluation: Option<f64>,
+        params: &aco2::ACO,
+    ) {
+        let versions = self
+            .versions
+            .entry((workspace.to_string(), route.route_id.clone()))
+            .or_default();
+        let version = versions.last().map_or(1, |v| v.version + 1);
+        if versions.len() == VERSIONS_MAX {
+            versions.remove(0);
+        }
+        versions.push(RouteVersion {
+            version,
+            accepted_at: chrono::Local::now().to_rfc3339(),
+            source: source.to_string(),
+            evaluation,
+            evals: route.evals.clone(),
+            params: params.clone(),
+            route: route.clone(),
+        });
+    }
+
+    /// Versions of a route in a workspace, oldest first
+    pub fn versions(&self, workspace: &str, route_id: &str) -> &[RouteVersion] {
+        self.versions
+            .get(&(workspace.to_string(), route_id.to_string()))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Drop the latest version of a route
+    ///
+    /// # Returns
+    /// The version dropped and the version to restore, `None` to restore the original route,
+    /// or `None` if the route has no version to undo
+    pub fn undo(
+        &mut self,
+        workspace: &str,
+        route_id: &str,
+    ) -> Option<(RouteVersion, Option<&RouteVersion>)> {
+        let key = (workspace.to_string(), route_id.to_string());
+        let undone = self.versions.get_mut(&key)?.pop()?;
+        if self.versions[&key].is_empty() {
+            self.versions.remove(&key);
+            return Some((undone, None));
+        }
+        Some((undone, self.versions[&key].last()))
+    }
+
+    /// Forget the versions of the routes of a workspace, e.g. when its network is reset
+    pub fn remove_workspace(&mut self, workspace: &str) {
+        self.versions.retain(|(name, _), _| name != workspace);
+    }
+
+    pub fn clear(&mut self) {
+        self.versions.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::transit_network::TransitRouteType;
+
+    fn route(route_id: &str) -> TransitRoute {
+        TransitRoute {
+            route_id: route_id.to_string(),
+            route_type: TransitRouteType::Bus,
+            inbound_stops: vec![],
+            outbound_stops: vec![],
+            evals: None,
+            stop_times: HashMap::new(),
+            service_span: None,
+            vehicle: None,
+        }
+    }
+
+    #[test]
+    fn undo_steps_back_through_versions() {
+        let mut history = RouteHistory::default();
+        let params = aco2::ACO::init();
+        history.record("default", &route("1"), "optimize-route", Some(1.0), &params);
+        history.record("default", &route("1"), "optimize-route", Some(2.0), &params);
+        history.record("other", &route("1"), "optimize-route", Some(3.0), &params);
+        assert_eq!(history.versions("default", "1").len(), 2);
+
+        let (undone, restored) = history.undo("default", "1").unwrap();
+        assert_eq!(undone.version, 2);
+        assert_eq!(restored.unwrap().evaluation, Some(1.0));
+        let (undone, restored) = history.undo("default", "1").unwrap();
+        assert_eq!(undone.version, 1);
+        assert!(restored.is_none());
+        assert!(history.undo("default", "1").is_none());
+        assert_eq!(history.versions("other", "1").len(), 1);
+
+        history.remove_workspace("other");
+        assert!(history.versions("other", "1").is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// Routes being optimized, by workspace
+///
+/// An optimization copies the network of its workspace and runs without holding
+/// `AppState::optimized_transit`, so optimizations of different routes run side by side and only
+/// lock the network to write their routes back. A route is locked while it is optimized so that
+/// two requests do not optimize it at once and the second overwrite the first.
+#[derive(Default)]
+pub(crate) struct RouteLocks {
+    held: Mutex<HashSet<(String, String)>>,
+}
+
+/// Routes locked by `RouteLocks::try_lock`, unlocked when dropped
+pub(crate) struct RouteLockGuard<'a> {
+    locks: &'a RouteLocks,
+    keys: Vec<(String, String)>,
+}
+
+impl RouteLocks {
+    /// Lock routes of a workspace, all of them or none
+    ///
+    /// # Returns
+    /// The guard of the routes, or an error naming the routes already being optimized
+    pub fn try_lock<'a>(
+        &'a self,
+        workspace: &str,
+        route_ids: &[String],
+    ) -> Result<RouteLockGuard<'a>, String> {
+        let mut held = self.held.lock().unwrap();
+        let keys: Vec<(String, String)> = route_ids
+            .iter()
+            .map(|route_id| (workspace.to_string(), route_id.clone()))
+            .collect();
+        let busy: Vec<&str> = keys
+            .iter()
+            .filter(|key| held.contains(*key))
+            .map(|(_, route_id)| route_id.as_str())
+            .collect();
+        if !busy.is_empty() {
+            return Err(format!(
+                "Routes already being optimized in workspace {}: {}",
+                workspace,
+                busy.join(", ")
+            ));
+        }
+        held.extend(keys.iter().cloned());
+        Ok(RouteLockGuard { locks: self, keys })
+    }
+}
+
+impl Drop for RouteLockGuard<'_> {
+    fn drop(&mut self) {
+        let mut held = self.locks.held.lock().unwrap();
+        for key in &self.keys {
+            held.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_are_locked_until_the_guard_drops() {
+        let locks = RouteLocks::default();
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        let guard = locks.try_lock("default", &ids(&["1", "2"])).unwrap();
+        let error = locks.try_lock("default", &ids(&["3", "2"])).err().unwrap();
+        assert!(error.ends_with(": 2"), "{}", error);
+        // nothing is locked by a failed attempt
+        let other = locks.try_lock("default", &ids(&["3"])).unwrap();
+        assert!(locks.try_lock("a", &ids(&["1"])).is_ok());
+
+        drop(guard);
+        assert!(locks.try_lock("default", &ids(&["3"])).is_err());
+        assert!(locks.try_lock("default", &ids(&["1", "2"])).is_ok());
+        drop(other);
+        assert!(locks.try_lock("default", &ids(&["1", "2", "3"])).is_ok());
+    }
+}
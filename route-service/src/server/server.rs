@@ -1,60 +1,264 @@
-use crate::gtfs::geojson;
+use crate::gtfs::error::Error as GtfsError;
+use crate::gtfs::gtfs::Gtfs;
+use crate::gtfs::raw_gtfs::GtfsDataSet;
+use crate::gtfs::structs::{format_gtfs_time, parse_gtfs_time};
+use crate::gtfs::{feeds, geojson, validator};
 use crate::layers::city::City;
-use crate::layers::transit_network::{TransitNetwork, TransitRoute};
-use crate::opt::{aco2, eval};
-use crate::server::opt_ws::OptimizationWs;
+use crate::layers::grid::{GridNetwork, TimePeriod};
+use crate::layers::import_report::ImportReport;
+use crate::layers::isochrone;
+use crate::layers::memory::MemoryMode;
+use crate::layers::raster::Raster;
+use crate::layers::router::{self, JourneyQuery, Router};
+use crate::layers::skim::{self, TravelTimeMatrix};
+use crate::layers::stop_infrastructure::StopInfrastructure;
+use crate::layers::transit_network::{TransitNetwork, TransitRoute, TransitRouteType, TransitStop};
+use crate::layers::vehicle::RouteVehicles;
+use crate::opt::area::{AreaMetrics, StudyArea};
+use crate::opt::audit::{AuditEvent, AuditFilter, ParamsHasher};
+use crate::opt::budget::{OperatingBudget, OperatingCost};
+use crate::opt::checkpoint::Checkpoint;
+use crate::opt::consolidate::{self, ConsolidateParams};
+use crate::opt::constraints::{RouteConstraint, RouteConstraints};
+use crate::opt::demand::PartialDemandModelConfig;
+use crate::opt::equity::EquityEvals;
+use crate::opt::express::{self, ExpressParams};
+use crate::opt::frequency::{FrequencyParams, FrequencyPlan};
+use crate::opt::ga_params::{self, GAConfig};
+use crate::opt::gtfs_export::{self, ScheduleOptions};
+use crate::opt::metrics::{write_header, OPTIMIZER_METRICS};
+use crate::opt::network_diff::{
+    DetailedNetworkDiff, KpiRecord, NetworkDiff, NetworkKpis, RouteDiff, RunRecord, StopImpact,
+    StopImpactKind,
+};
+use crate::opt::new_route::{self, NewRouteParams};
+use crate::opt::objective::{self, ObjectiveSpec};
+use crate::opt::progress::{IterationProgress, ProgressEvent};
+use crate::opt::queue::{BadnessWeights, OptimizationQueue};
+use crate::opt::resources::ResourceMeter;
+use crate::opt::sa;
+use crate::opt::scenario::{Scenario, ScenarioRecord};
+use crate::opt::search::PartialSearchConfig;
+use crate::opt::timetable::TimetablePreview;
+use crate::opt::walking::WalkCheck;
+use crate::opt::{accessibility, aco2, eval, review, validation};
+use crate::server::error::ServiceError;
+use crate::server::jobs::{JobQueue, JobWs};
+use crate::server::metrics::{self, RequestMetrics};
+use crate::server::notify::{self, Webhook};
+use crate::server::opt_ws::{OptimizationWs, UpdateParams};
+use crate::server::route_history::{RouteHistory, RouteVersion};
+use crate::server::route_locks::RouteLocks;
+use crate::server::store::{BoundedStore, StoreStats};
+use crate::server::workspace::Workspaces;
 
-use actix_web::{get, post, web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder};
+use actix::Addr;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, Service, ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::{
+    delete, get, post, web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder,
+    ResponseError,
+};
 use actix_web_actors::ws;
+use futures::{Stream, StreamExt};
 use geo::Centroid;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::net::SocketAddr;
+use std::pin::Pin;
+use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub(crate) struct AppState {
-    pub city: Mutex<Option<City>>,
-    pub optimized_transit: Mutex<Option<TransitNetwork>>, // Stores optimized routes
-    pub optimized_route_ids: Mutex<Vec<String>>,          // Tracks which routes have been optimized
-    pub noop_route_ids: Mutex<Vec<String>>, // Tracks which routes which cannot be optimized
-    pub aco_params: Mutex<aco2::ACO>,       // ACO parameters
-    pub shutdown_signal: Arc<AtomicBool>,   // Signal to stop background threads
+    pub city: RwLock<Option<City>>,
+    pub optimized_transit: RwLock<Option<TransitNetwork>>, // Stores optimized routes
+    pub optimized_route_ids: Mutex<Vec<String>>, // Tracks which routes have been optimized
+    pub noop_route_ids: Mutex<BoundedStore<String, ()>>, // Tracks which routes which cannot be optimized
+    pub aco_params: Mutex<aco2::ACO>,                    // ACO parameters
+    pub operating_budget: Mutex<OperatingBudget>,        // Budget evaluate-network reports against
+    pub route_reviews: Mutex<review::RouteReviews>,      // Review state of optimized routes
+    pub optimization_queue: Mutex<OptimizationQueue>,    // Pinned routes and badness weights
+    pub route_pheromones: Mutex<BoundedStore<String, aco2::Pheromones>>, // Left by the last run of each route
+    pub route_constraints: Mutex<RouteConstraints>, // Stops and segments each route must keep
+    pub shutdown_signal: Arc<AtomicBool>,           // Signal to stop background threads
+    pub gtfs_path: String,                          // GTFS path the city was loaded from
+    pub db_path: String,                            // Database path the city was loaded from
+    pub webhook: Option<Webhook>,                   // Notified when batch jobs finish
+    pub optimization_limits: OptimizationLimits,    // Caps applied to every optimization request
+    pub audit_revision: Mutex<u64>, // Revision of the city state, moved forward by audited calls
+    pub live_sessions: Mutex<HashMap<u64, Addr<OptimizationWs>>>, // Running optimize-live sessions
+    pub workspaces: Mutex<Workspaces>, // Optimized networks besides the active one, locked before optimized_transit
+    pub jobs: JobQueue,                // Optimizations running in the background
+    pub route_locks: RouteLocks,       // Routes being optimized, see RouteLocks
+    pub route_history: Mutex<RouteHistory>, // Accepted versions of each route, locked after the networks
+    pub request_metrics: RequestMetrics,    // Latencies of the requests answered, see /metrics
+}
+
+/// Most routes remembered as impossible to optimize
+const NOOP_ROUTES_MAX: usize = 10_000;
+/// Most routes whose pheromones are kept, each holds a value for every edge the ants used
+const PHEROMONE_ROUTES_MAX: usize = 256;
+/// Time after which an unused entry of a store is removed
+const STORE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Time between two garbage collections of the stores
+const STORE_GC_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+impl AppState {
+    /// ACO parameters of a new optimization, with the current constraints of the routes
+    pub(crate) fn optimization_params(&self) -> aco2::ACO {
+        let mut params = self.aco_params.lock().unwrap().clone();
+        params.constraints = Arc::new(self.route_constraints.lock().unwrap().clone());
+        params
+    }
+
+    /// Size and evictions of the stores kept between requests
+    fn store_stats(&self) -> Vec<StoreStats> {
+        vec![
+            self.noop_route_ids.lock().unwrap().stats(),
+            self.route_pheromones.lock().unwrap().stats(),
+            self.jobs.stats(),
+        ]
+    }
+
+    /// Remove the expired entries of the stores
+    ///
+    /// # Returns
+    /// The number of entries removed
+    fn gc_stores(&self) -> usize {
+        let now = Instant::now();
+        self.noop_route_ids.lock().unwrap().gc(now) + self.route_pheromones.lock().unwrap().gc(now)
+    }
+}
+
+/// Resource limits of an optimization request. The server's limits cap whatever a request
+/// asks for, so a request can only tighten them.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct OptimizationLimits {
+    /// Wall time in seconds after which the batch stops
+    pub max_wall_time_secs: Option<u64>,
+    /// Most routes a single request may optimize
+    pub max_routes: Option<usize>,
+    /// Most ACO generations per route, overriding larger `max_gen` parameters
+    pub max_generations: Option<usize>,
+}
+
+impl OptimizationLimits {
+    /// The tighter of two sets of limits
+    fn min(self, other: OptimizationLimits) -> OptimizationLimits {
+        fn tighter<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+        OptimizationLimits {
+            max_wall_time_secs: tighter(self.max_wall_time_secs, other.max_wall_time_secs),
+            max_routes: tighter(self.max_routes, other.max_routes),
+            max_generations: tighter(self.max_generations, other.max_generations),
+        }
+    }
+
+    fn batch_limits(&self) -> aco2::BatchLimits {
+        aco2::BatchLimits {
+            max_wall_time: self.max_wall_time_secs.map(Duration::from_secs),
+            max_generations: self.max_generations,
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct RouteIds {
     routes: Vec<String>,
+    /// How coverage is computed between routes of the batch, defaults to live
+    #[serde(default)]
+    coverage_mode: aco2::CoverageMode,
+    /// URL notified with a summary once the batch finishes, overrides the city's webhook
+    webhook_url: Option<String>,
+    /// Resource limits of this request, capped by the server's limits
+    #[serde(default)]
+    limits: OptimizationLimits,
+    /// Workspace whose network the routes are optimized in, the active one if missing
+    workspace: Option<String>,
 }
 
+/// GeoJSON of the optimized routes that were not rejected, with the review state of each
+/// route in its `review_state` property
 pub(crate) fn get_optimized_geojson(
     city: &City,
     optimized_transit: &TransitNetwork,
-    optimized_route_ids: &Vec<String>,
+    optimized_route_ids: &[String],
+    reviews: &review::RouteReviews,
 ) -> Value {
+    geojson::convert_to_geojson(&get_optimized_features(
+        city,
+        optimized_transit,
+        optimized_route_ids,
+        reviews,
+    ))
+}
+
+/// Features of `get_optimized_geojson`, for responses that stream them
+fn get_optimized_features(
+    city: &City,
+    optimized_transit: &TransitNetwork,
+    optimized_route_ids: &[String],
+    reviews: &review::RouteReviews,
+) -> Vec<Value> {
+    let visible_route_ids = reviews.visible(optimized_route_ids, true);
     let all_opt_routes = optimized_transit
         .routes
         .iter()
-        .filter(|r| optimized_route_ids.contains(&r.route_id))
+        .filter(|r| visible_route_ids.contains(&r.route_id))
         .collect::<Vec<&TransitRoute>>();
-    let features = geojson::get_all_features(&TransitNetwork::to_gtfs_filtered(
+    let mut features = geojson::get_all_features(&TransitNetwork::to_gtfs_filtered(
         all_opt_routes,
         &city.gtfs,
         &city.road,
     ));
-    let geojson = geojson::convert_to_geojson(&features);
-    geojson
+    tag_review_states(&mut features, reviews);
+    features
+}
+
+/// Respond with a FeatureCollection sent in chunks as it is serialized, nested under the key
+/// among the fields of `parent` if given
+///
+/// The response has no `Content-Length` and uses chunked transfer encoding, so the size of
+/// a city's network is not bounded by what a client or the proxy would buffer.
+fn stream_geojson(features: Vec<Value>, parent: Option<(Value, &str)>) -> HttpResponse {
+    let parent = parent.map(|(fields, key)| match fields {
+        Value::Object(fields) => (fields, key),
+        _ => (serde_json::Map::new(), key),
+    });
+    let chunks = geojson::feature_collection_chunks(features, parent)
+        .map(|chunk| chunk.map(web::Bytes::from));
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .streaming(futures::stream::iter(chunks))
+}
+
+fn tag_review_states(features: &mut [Value], reviews: &review::RouteReviews) {
+    for feature in features.iter_mut() {
+        if let Some(properties) = feature["properties"].as_object_mut() {
+            if let Some(route_id) = properties.get("route_id").and_then(|id| id.as_str()) {
+                let state = reviews.state(route_id);
+                properties.insert("review_state".to_string(), serde_json::json!(state));
+            }
+        }
+    }
 }
 
-fn get_base_geojson(city: &City) -> Value {
-    let features = geojson::get_all_features(&TransitNetwork::to_gtfs_copy(
+fn get_base_features(city: &City) -> Vec<Value> {
+    geojson::get_all_features(&TransitNetwork::to_gtfs_copy(
         city.transit.routes.iter().collect(),
         &city.gtfs,
-    ));
-    let geojson = geojson::convert_to_geojson(&features);
-    geojson
+        &city.road,
+    ))
 }
 
 #[get("/get-data")]
@@ -62,14 +266,12 @@ async fn get_data(data: web::Data<AppState>) -> impl Responder {
     println!("Fetching network data");
 
     // Try to access the city from the shared state
-    let city_guard = data.city.lock().unwrap();
+    let city_guard = data.city.read().unwrap();
 
     if let Some(city) = &*city_guard {
-        HttpResponse::Ok().json(get_base_geojson(city))
+        stream_geojson(get_base_features(city), None)
     } else {
-        HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "City data not loaded"
-        }))
+        ServiceError::CityNotLoaded.error_response()
     }
 }
 
@@ -77,7 +279,8 @@ async fn get_data(data: web::Data<AppState>) -> impl Responder {
 async fn get_noop_route_ids(data: web::Data<AppState>) -> impl Responder {
     println!("Fetching routes that cannot be optimized");
 
-    let noop_route_ids = data.noop_route_ids.lock().unwrap().clone();
+    let mut noop_route_ids = data.noop_route_ids.lock().unwrap().keys();
+    noop_route_ids.sort();
     HttpResponse::Ok().json(serde_json::json!({
         "message": "Routes that cannot be optimized",
         "routes": noop_route_ids
@@ -91,8 +294,12 @@ async fn update_aco_params(
 ) -> impl Responder {
     println!("Updating ACO parameters");
 
+    let params = params.into_inner();
+    if let Err(e) = params.validate() {
+        return ServiceError::InvalidRequest(e.to_string()).error_response();
+    }
     let mut aco_params = data.aco_params.lock().unwrap();
-    aco_params.update_from_partial(params.into_inner());
+    aco_params.update_from_partial(params);
     aco_params.print_stats();
 
     HttpResponse::Ok().json(serde_json::json!({
@@ -100,19 +307,193 @@ async fn update_aco_params(
     }))
 }
 
+#[derive(Deserialize)]
+struct TuneParams {
+    /// Genetic algorithm settings, the defaults of `GAConfig::new` for those left out
+    #[serde(flatten)]
+    ga: GAConfig,
+    /// Apply the tuned parameters over the current ones once the job succeeds
+    #[serde(default)]
+    commit: bool,
+}
+
+/// Queue the tuning of the ACO parameters of a route by a genetic algorithm, see
+/// `GAConfig::optimize_aco_params_with_progress`
+///
+/// # Returns
+/// The id of the job, whose progress and result are reported by `/jobs/{id}`
+#[post("/tune-aco-params/{route_id}")]
+async fn tune_aco_params(
+    route_id: web::Path<String>,
+    params: web::Json<TuneParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let route_id = route_id.into_inner();
+    println!("Tuning ACO parameters for route {}", route_id);
+
+    let TuneParams { mut ga, commit } = params.into_inner();
+    if let Err(e) = ga.validate() {
+        return ServiceError::InvalidRequest(e).error_response();
+    }
+    {
+        let city_guard = data.city.read().unwrap();
+        let Some(city) = &*city_guard else {
+            return ServiceError::CityNotLoaded.error_response();
+        };
+        let optimized_transit_guard = data.optimized_transit.read().unwrap();
+        let transit = optimized_transit_guard.as_ref().unwrap_or(&city.transit);
+        if !transit.routes.iter().any(|r| r.route_id == route_id) {
+            return ServiceError::NotFound(format!("Route {} not found", route_id))
+                .error_response();
+        }
+    }
+    // the parameters the GA leaves alone, e.g. the objective, are the current ones
+    ga.base = data.optimization_params();
+
+    let job_route_id = route_id.clone();
+    let job = JobQueue::submit(
+        &data,
+        "tune-aco-params",
+        vec![route_id.clone()],
+        Box::new(move |data, on_progress| {
+            tune_aco_params_job(data, &job_route_id, &ga, commit, on_progress)
+        }),
+    );
+    HttpResponse::Accepted().json(serde_json::json!({
+        "message": format!("Queued the tuning of the ACO parameters for route {}", route_id),
+        "job_id": job.id,
+        "status": job.status,
+    }))
+}
+
+/// Tune the ACO parameters of a route in the active network
+///
+/// # Returns
+/// The tuned parameters with the score ACO reached with them and whether they were committed
+fn tune_aco_params_job(
+    data: &AppState,
+    route_id: &str,
+    ga: &GAConfig,
+    commit: bool,
+    on_progress: &mut dyn FnMut(ProgressEvent),
+) -> Result<Value, String> {
+    let city_guard = data.city.read().unwrap();
+    let city = city_guard.as_ref().ok_or("City data not loaded")?;
+    // a copy of the network, so that the other endpoints can change it meanwhile
+    let transit = data
+        .optimized_transit
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| city.transit.clone());
+    let route = transit
+        .routes
+        .iter()
+        .find(|r| r.route_id == route_id)
+        .ok_or_else(|| format!("Route {} not found", route_id))?;
+
+    let (best, fitness) = ga
+        .optimize_aco_params_with_progress(route, city, &transit, on_progress)
+        .ok_or_else(|| format!("No parameters were found for route {}", route_id))?;
+    let tuned = ga_params::tuned_params(&best);
+    if commit {
+        let mut aco_params = data.aco_params.lock().unwrap();
+        aco_params.update_from_partial(tuned.clone());
+        aco_params.print_stats();
+    }
+    Ok(serde_json::json!({
+        "route_id": route_id,
+        "params": tuned,
+        "fitness": fitness,
+        "committed": commit,
+    }))
+}
+
+/// Objectives a route can be scored by, see `ObjectiveSpec`
+#[get("/objectives")]
+async fn get_objectives(data: web::Data<AppState>) -> impl Responder {
+    let objectives: Vec<serde_json::Value> = objective::OBJECTIVES
+        .iter()
+        .map(|o| serde_json::json!({ "name": o.name(), "description": o.description() }))
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({
+        "objectives": objectives,
+        "current": data.aco_params.lock().unwrap().objective,
+    }))
+}
+
+#[get("/operating-budget")]
+async fn get_operating_budget(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting operating budget");
+
+    HttpResponse::Ok().json(*data.operating_budget.lock().unwrap())
+}
+
+/// Set the operating budget `/evaluate-network` reports the utilization of, limits left out
+/// are not enforced
+#[post("/operating-budget")]
+async fn update_operating_budget(
+    budget: web::Json<OperatingBudget>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Updating operating budget");
+
+    let budget = budget.into_inner();
+    if let Err(e) = budget.validate() {
+        return ServiceError::InvalidRequest(e.to_string()).error_response();
+    }
+    *data.operating_budget.lock().unwrap() = budget;
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "message": "Operating budget updated",
+        "budget": budget,
+    }))
+}
+
+#[derive(Deserialize)]
+struct OptimizeRouteParams {
+    /// Saved scenario whose version of the route, and its pheromone if kept, seeds the ACO
+    base: Option<String>,
+    /// Objectives to score the route by instead of the ACO params' ones, as
+    /// `name:weight,...`
+    objective: Option<String>,
+    /// Workspace whose network the route is optimized in, the active one if missing
+    workspace: Option<String>,
+    /// Search optimizing the route, ACO unless set to `sa` to benchmark against simulated
+    /// annealing
+    #[serde(default)]
+    algorithm: aco2::Algorithm,
+    /// Seed of the search, the seed of the ACO params if missing
+    seed: Option<u64>,
+    /// Runs of the search with the seed and the seeds following it, the best result is kept
+    n_restarts: Option<usize>,
+}
+
 #[post("/optimize-route/{route_id}")]
-async fn optimize_route(route_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+async fn optimize_route(
+    route_id: web::Path<String>,
+    query: web::Query<OptimizeRouteParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
     let route_id = route_id.into_inner();
     println!("Optimizing route: {}", route_id);
 
+    let n_restarts = query.n_restarts.unwrap_or(1);
+    if !(1..=aco2::MAX_RESTARTS).contains(&n_restarts) {
+        return ServiceError::InvalidRequest(format!(
+            "n_restarts must be between 1 and {}, got {}",
+            aco2::MAX_RESTARTS,
+            n_restarts
+        ))
+        .error_response();
+    }
+
     // Access the original city (immutable)
-    let city_guard = data.city.lock().unwrap();
+    let city_guard = data.city.read().unwrap();
     let city = match &*city_guard {
         Some(city) => city,
         None => {
-            return HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "City data not loaded"
-            }));
+            return ServiceError::CityNotLoaded.error_response();
         }
     };
 
@@ -124,15 +505,159 @@ async fn optimize_route(route_id: web::Path<String>, data: web::Data<AppState>)
         .find(|r| r.route_id == route_id)
         .cloned();
 
+    let scenario = match &query.base {
+        Some(base) => match City::load_scenario(&city.name, base) {
+            Ok(scenario) => Some(scenario),
+            Err(crate::layers::error::Error::CacheNotFound) => {
+                return ServiceError::NotFound(format!("Scenario {} not found", base))
+                    .error_response();
+            }
+            Err(e) => {
+                return ServiceError::Internal(format!("Failed to load scenario {}: {}", base, e))
+                    .error_response();
+            }
+        },
+        None => None,
+    };
+    let seed = match &scenario {
+        Some(scenario) => match scenario.seed(&route_id) {
+            Some(seed) => Some(seed),
+            None => {
+                return ServiceError::NotFound(format!(
+                    "Route {} not found in scenario {}",
+                    route_id, scenario.name
+                ))
+                .error_response();
+            }
+        },
+        None => None,
+    };
+
     if let Some(route) = original_route {
         // Create ACO instance on demand for this optimization
-        let params = data.aco_params.lock().unwrap().clone();
+        let mut params = data.optimization_params();
+        if let Some(objective) = &query.objective {
+            match objective.parse::<ObjectiveSpec>() {
+                Ok(objective) => params.objective = objective,
+                Err(e) => {
+                    return ServiceError::InvalidRequest(e.to_string()).error_response();
+                }
+            }
+        }
+        let objective = params.objective.clone();
+        if let Some(seed) = query.seed {
+            params.seed = seed;
+        }
+        let seeds = aco2::restart_seeds(params.seed, n_restarts);
+
+        // the route is optimized in a copy of the network so that other routes can be
+        // optimized at the same time, only writing it back locks the network
+        let (workspace, network) = {
+            let workspaces = data.workspaces.lock().unwrap();
+            let optimized_transit_guard = data.optimized_transit.read().unwrap();
+            let optimized_route_ids_guard = data.optimized_route_ids.lock().unwrap();
+            match workspaces.get(
+                query.workspace.as_deref(),
+                optimized_transit_guard.as_ref().unwrap(),
+                &optimized_route_ids_guard,
+            ) {
+                Ok((network, _)) => (
+                    query
+                        .workspace
+                        .clone()
+                        .unwrap_or_else(|| workspaces.active().to_string()),
+                    network.clone(),
+                ),
+                Err(e) => return ServiceError::NotFound(e.to_string()).error_response(),
+            }
+        };
+        let _route_lock = match data
+            .route_locks
+            .try_lock(&workspace, std::slice::from_ref(&route_id))
+        {
+            Ok(guard) => guard,
+            Err(e) => return ServiceError::Conflict(e.to_string()).error_response(),
+        };
+        let mut meter = ResourceMeter::start();
+        let mut on_progress = |event: ProgressEvent| meter.observe(&event);
+        // every restart runs the whole search with the next seed, the best result is kept
+        let mut best: Option<(TransitRoute, f64)> = None;
+        let mut best_pheromones = None;
+        let mut best_seed = seeds[0];
+        let mut restarts = vec![];
+        for &run_seed in &seeds {
+            let mut run_params = params.clone();
+            run_params.seed = run_seed;
+            // annealing leaves no pheromone, the route keeps the one of its last ACO run
+            let (result, pheromones) = match query.algorithm {
+                aco2::Algorithm::Aco => {
+                    let (result, pheromones) = aco2::run_aco_from_seed(
+                        run_params,
+                        &route,
+                        seed,
+                        &mut aco2::SearchContext {
+                            city,
+                            opt_transit: &network,
+                            area: None,
+                            deadline: None,
+                            on_progress: &mut on_progress,
+                        },
+                    );
+                    (result, Some(pheromones))
+                }
+                aco2::Algorithm::Sa => {
+                    let start_route = seed.map(|seed| seed.route);
+                    let result = sa::run_sa(
+                        run_params,
+                        &route,
+                        city,
+                        &network,
+                        start_route,
+                        None,
+                        &mut on_progress,
+                    );
+                    (result, None)
+                }
+            };
+            let evaluation = result.as_ref().map(|(_, eval)| *eval);
+            restarts.push(serde_json::json!({
+                "seed": run_seed,
+                "evaluation": evaluation,
+            }));
+            let improves = match (&best, evaluation) {
+                (_, None) => false,
+                (None, Some(_)) => true,
+                (Some((_, best_eval)), Some(eval)) => eval > *best_eval,
+            };
+            if improves {
+                best = result;
+                best_pheromones = pheromones;
+                best_seed = run_seed;
+            }
+        }
+        let (result, pheromones) = (best, best_pheromones);
+        let resources = meter.finish();
+        if let Some((opt_route, eval)) = result {
+            let mut workspaces = data.workspaces.lock().unwrap();
+            let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+            let mut optimized_route_ids_guard = data.optimized_route_ids.lock().unwrap();
+            let (optimized_transit, optimized_route_ids) = match workspaces.get_mut(
+                Some(&workspace),
+                optimized_transit_guard.as_mut().unwrap(),
+                &mut optimized_route_ids_guard,
+            ) {
+                Ok(workspace) => workspace,
+                Err(e) => return ServiceError::NotFound(e.to_string()).error_response(),
+            };
 
-        let mut optimized_transit_guard = data.optimized_transit.lock().unwrap();
-        let optimized_transit = optimized_transit_guard.as_mut().unwrap();
-        let mut optimized_route_ids = data.optimized_route_ids.lock().unwrap();
-        if let Some((opt_route, eval)) = aco2::run_aco(params, &route, city, optimized_transit) {
             // Update the optimized transit with the new route
+            data.route_history.lock().unwrap().record(
+                &workspace,
+                &opt_route,
+                "optimize-route",
+                Some(eval),
+                &params,
+            );
             optimized_transit.routes.retain(|r| r.route_id != route_id);
             optimized_transit.routes.push(opt_route);
 
@@ -140,509 +665,4548 @@ async fn optimize_route(route_id: web::Path<String>, data: web::Data<AppState>)
             if !optimized_route_ids.contains(&route_id) {
                 optimized_route_ids.push(route_id.clone());
             }
+            let mut reviews = data.route_reviews.lock().unwrap();
+            reviews.propose(&route_id);
+            if let Some(pheromones) = pheromones {
+                data.route_pheromones
+                    .lock()
+                    .unwrap()
+                    .insert(route_id.clone(), pheromones);
+            }
 
-            HttpResponse::Ok().json(serde_json::json!({
+            let features =
+                get_optimized_features(city, optimized_transit, optimized_route_ids, &reviews);
+            let fields = serde_json::json!({
                 "message": format!("Optimized route {}", route_id),
-                "geojson": get_optimized_geojson(city, optimized_transit, &optimized_route_ids),
-                "evaluation": eval
-            }))
+                "algorithm": query.algorithm,
+                "evaluation": eval,
+                "objective": objective,
+                "base": query.base,
+                "workspace": workspace,
+                "seed": best_seed,
+                "restarts": restarts,
+                "resources": resources,
+            });
+            stream_geojson(features, Some((fields, "geojson")))
         } else {
-            data.noop_route_ids.lock().unwrap().push(route_id.clone());
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Failed to optimize route {}", route_id)
-            }))
+            data.noop_route_ids
+                .lock()
+                .unwrap()
+                .insert(route_id.clone(), ());
+            ServiceError::NotOptimized(format!("Found no better version of route {}", route_id))
+                .with_details(serde_json::json!({
+                    "resources": resources,
+                    "restarts": restarts,
+                }))
         }
     } else {
-        HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Route {} not found", route_id)
-        }))
+        ServiceError::NotFound(format!("Route {} not found", route_id)).error_response()
     }
 }
 
-#[post("/optimize-routes")]
-async fn optimize_routes(
-    route_ids: web::Json<RouteIds>,
+#[derive(Deserialize)]
+struct AbTestRequest {
+    /// Parameters of the first variant, applied over the current ACO parameters
+    a: aco2::PartialACO,
+    /// Parameters of the second variant, applied over the current ACO parameters
+    b: aco2::PartialACO,
+    /// Seed both variants run with, the seed of the current ACO parameters if missing. Seeds
+    /// given in `a` or `b` are ignored.
+    seed: Option<u64>,
+}
+
+/// Optimize a route twice with the same seed and two sets of ACO parameters, so that
+/// differences between the results come from the parameters alone
+///
+/// Both variants start from the original route and are scored against the current optimized
+/// network, which is left as is. The server's generation limit applies to both, its wall time
+/// limit does not since stopping a variant early would make the comparison unfair.
+#[post("/ab-test-route/{route_id}")]
+async fn ab_test_route(
+    route_id: web::Path<String>,
+    body: web::Json<AbTestRequest>,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    println!("Optimizing multiple routes: {:?}", route_ids.routes);
+    let route_id = route_id.into_inner();
+    println!("A/B testing ACO parameters on route: {}", route_id);
 
-    // Access the original city (immutable)
-    let city_guard = data.city.lock().unwrap();
-    let city = match &*city_guard {
-        Some(city) => city,
-        None => {
-            return HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "City data not loaded"
-            }));
-        }
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let Some(route) = city.transit.routes.iter().find(|r| r.route_id == route_id) else {
+        return ServiceError::NotFound(format!("Route {} not found", route_id)).error_response();
     };
 
-    // Check if any routes exist
-    if route_ids.routes.is_empty() {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "No route IDs provided"
-        }));
+    let AbTestRequest { a, b, seed } = body.into_inner();
+    let base = data.optimization_params();
+    let seed = seed.unwrap_or(base.seed);
+    let mut variants = vec![];
+    for partial in [a, b] {
+        if let Some(Err(e)) = partial.objective.as_ref().map(|o| o.validate()) {
+            return ServiceError::InvalidRequest(e.to_string()).error_response();
+        }
+        let mut params = base.clone();
+        params.update_from_partial(partial);
+        params.seed = seed;
+        if let Some(max_generations) = data.optimization_limits.max_generations {
+            params.max_gen = params.max_gen.min(max_generations);
+        }
+        variants.push(params);
     }
 
-    let mut optimized_transit_guard = data.optimized_transit.lock().unwrap();
-    let optimized_transit = optimized_transit_guard.as_mut().unwrap();
-    let mut optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let transit = optimized_transit_guard.as_ref().unwrap_or(&city.transit);
+    let mut results = vec![];
+    for params in variants {
+        let (objective, max_gen) = (params.objective.clone(), params.max_gen);
+        let mut meter = ResourceMeter::start();
+        let (result, _) = aco2::run_aco_from_seed(
+            params,
+            route,
+            None,
+            &mut aco2::SearchContext {
+                city,
+                opt_transit: transit,
+                area: None,
+                deadline: None,
+                on_progress: &mut |event| meter.observe(&event),
+            },
+        );
+        let (improved, score, result_route) = match result {
+            Some((opt_route, score)) => (true, Some(score), opt_route),
+            None => (false, None, route.clone()),
+        };
+        results.push((
+            serde_json::json!({
+                "improved": improved,
+                "score": score,
+                "objective": objective,
+                "max_gen": max_gen,
+                "stop_ids": result_route
+                    .outbound_stops
+                    .iter()
+                    .map(|s| s.stop_id.as_str())
+                    .collect::<Vec<_>>(),
+                "evals": result_route.evals,
+                "resources": meter.finish(),
+            }),
+            result_route,
+        ));
+    }
+    let diff = RouteDiff::new(&results[0].1, &results[1].1);
 
-    let routes = city
-        .transit
-        .routes
-        .iter()
-        .filter(|r| route_ids.routes.contains(&r.route_id))
-        .collect::<Vec<&TransitRoute>>();
+    let mut features = vec![];
+    for ((_, result_route), variant) in results.iter().zip(["a", "b"]) {
+        let mut variant_features = geojson::get_all_features(&TransitNetwork::to_gtfs_filtered(
+            vec![result_route],
+            &city.gtfs,
+            &city.road,
+        ));
+        geojson::tag_and_simplify_features(&mut variant_features, variant, 0.0);
+        features.extend(variant_features);
+    }
 
-    let params = data.aco_params.lock().unwrap().clone();
-    let results = aco2::run_aco_batch(params, &routes, city, optimized_transit);
+    HttpResponse::Ok().json(serde_json::json!({
+        "route_id": route_id,
+        "seed": seed,
+        "a": results[0].0,
+        "b": results[1].0,
+        "diff": diff,
+        "geojson": geojson::convert_to_geojson(&features),
+    }))
+}
 
-    // Track successful optimizations and evaluations
-    let success_count = results.len();
+/// Save the optimized network under a name, so that `/optimize-route?base=` can later start
+/// from it. The pheromone of the last run of each route optimized by `/optimize-route` is
+/// saved along with it.
+#[post("/save-scenario/{name}")]
+async fn save_scenario(name: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let name = name.into_inner();
+    println!("Saving scenario {}", name);
 
-    for opt_route_id in results {
-        // Track the optimized route ID
-        if !optimized_route_ids.contains(&opt_route_id) {
-            optimized_route_ids.push(opt_route_id.clone());
-        }
+    if !Scenario::valid_name(&name) {
+        return ServiceError::InvalidRequest(
+            "Scenario names may only contain letters, digits, '-' and '_'".to_string(),
+        )
+        .error_response();
     }
 
-    // determine failed routes
-    for route_id in &route_ids.routes {
-        if !optimized_route_ids.contains(route_id) {
-            data.noop_route_ids.lock().unwrap().push(route_id.clone());
+    let city_guard = data.city.read().unwrap();
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let (city, optimized_transit) = match (&*city_guard, &*optimized_transit_guard) {
+        (Some(city), Some(optimized_transit)) => (city, optimized_transit),
+        _ => {
+            return ServiceError::CityNotLoaded.error_response();
         }
-    }
+    };
+    let optimized_route_ids = data.optimized_route_ids.lock().unwrap().clone();
+    let pheromones = data.route_pheromones.lock().unwrap().to_map();
 
-    if success_count > 0 {
-        HttpResponse::Ok().json(serde_json::json!({
-            "message": format!("Optimized {} routes", success_count),
-            "geojson": get_optimized_geojson(city, optimized_transit, &optimized_route_ids),
-        }))
-    } else {
-        HttpResponse::NotFound().json(serde_json::json!({
-            "error": "No routes were successfully optimized"
-        }))
+    let scenario = Scenario {
+        name: name.clone(),
+        saved_at: chrono::Local::now().to_rfc3339(),
+        network: optimized_transit.clone(),
+        optimized_route_ids,
+        pheromones,
+    };
+    if let Err(e) = City::save_scenario(&city.name, &scenario) {
+        return ServiceError::Internal(format!("Failed to save scenario {}: {}", name, e))
+            .error_response();
     }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "message": format!("Saved scenario {}", name),
+        "optimized_routes": scenario.optimized_route_ids,
+        "routes_with_pheromone": scenario.pheromones.len(),
+    }))
 }
 
-#[get("/evaluate-route/{route_id}")]
-async fn evaluate_route(route_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
-    let route_id = route_id.into_inner();
-    println!("Evaluating route: {}", route_id);
+#[derive(Deserialize)]
+struct NewScenario {
+    name: String,
+    description: Option<String>,
+}
 
-    let city_guard = data.city.lock().unwrap();
+/// Store the optimized network in the scenario database of the city, with the ACO parameters
+/// and evaluations it has now
+#[post("/scenarios")]
+async fn create_scenario(
+    scenario: web::Json<NewScenario>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let NewScenario { name, description } = scenario.into_inner();
+    println!("Storing scenario {}", name);
 
-    if let Some(city) = &*city_guard {
-        let optimized_transit_guard = data.optimized_transit.lock().unwrap();
-        let optimized_transit = optimized_transit_guard.as_ref().unwrap();
-        let optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+    if !Scenario::valid_name(&name) {
+        return ServiceError::InvalidRequest(
+            "Scenario names may only contain letters, digits, '-' and '_'".to_string(),
+        )
+        .error_response();
+    }
 
-        // Find the route with the given ID
-        let route = city.transit.routes.iter().find(|r| r.route_id == route_id);
+    let city_guard = data.city.read().unwrap();
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let (city, optimized_transit) = match (&*city_guard, &*optimized_transit_guard) {
+        (Some(city), Some(optimized_transit)) => (city, optimized_transit),
+        _ => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+    let evals = optimized_transit
+        .evals
+        .clone()
+        .unwrap_or_else(|| eval::TransitNetworkEvals::for_network(optimized_transit, &city.grid));
+    let mut record = ScenarioRecord {
+        id: 0,
+        name,
+        description,
+        saved_at: chrono::Local::now().to_rfc3339(),
+        params: data.optimization_params(),
+        evals: Some(evals),
+        optimized_route_ids: data.optimized_route_ids.lock().unwrap().clone(),
+    };
+    let stored =
+        City::scenario_store(&city.name).and_then(|store| store.insert(&record, optimized_transit));
+    match stored {
+        Ok(id) => {
+            record.id = id;
+            HttpResponse::Ok().json(record)
+        }
+        Err(e) => {
+            ServiceError::Internal(format!("Failed to store scenario {}: {}", record.name, e))
+                .error_response()
+        }
+    }
+}
 
-        if let Some(route) = route {
-            let (ridership, avg_occupancy) = (
-                &route.evals.as_ref().unwrap().ridership,
-                route.evals.as_ref().unwrap().avg_ridership,
-            );
+/// Scenarios stored in the scenario database of the city, newest first
+#[get("/scenarios")]
+async fn list_scenarios(data: web::Data<AppState>) -> impl Responder {
+    println!("Listing stored scenarios");
 
-            // Only evaluate the optimized route if it has been optimized
-            if optimized_route_ids.contains(&route_id) {
-                if let Some(opt_route) = optimized_transit
-                    .routes
-                    .iter()
-                    .find(|r| r.route_id == route_id)
-                {
-                    let (opt_ridership, opt_avg_occupancy) = (
-                        &opt_route.evals.as_ref().unwrap().ridership,
-                        opt_route.evals.as_ref().unwrap().avg_ridership,
-                    );
-                    let coverage = opt_route.evals.as_ref().unwrap().coverage;
-                    let economic_score = opt_route.evals.as_ref().unwrap().economic_score;
-                    println!(
-                        "Route {}: coverage={}, economic_score={}",
-                        route_id, coverage, economic_score
-                    );
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    match City::scenario_store(&city.name).and_then(|store| store.list()) {
+        Ok(scenarios) => HttpResponse::Ok().json(serde_json::json!({ "scenarios": scenarios })),
+        Err(e) => {
+            ServiceError::Internal(format!("Failed to list scenarios: {}", e)).error_response()
+        }
+    }
+}
 
-                    return HttpResponse::Ok().json(serde_json::json!({
-                        "route_id": route_id,
-                        "ridership": ridership,
-                        "opt_ridership": opt_ridership,
-                        "average_occupancy": avg_occupancy,
-                        "opt_average_occupancy": opt_avg_occupancy
-                    }));
-                }
-            }
+/// GeoJSON of the routes of a stored scenario
+#[get("/scenarios/{id}/geojson")]
+async fn get_scenario_geojson(id: web::Path<i64>, data: web::Data<AppState>) -> impl Responder {
+    let id = id.into_inner();
+    println!("Fetching GeoJSON of stored scenario {}", id);
 
-            // Return just the original route metrics if no optimized version exists
-            return HttpResponse::Ok().json(serde_json::json!({
-                "route_id": route_id,
-                "ridership": ridership,
-                "average_occupancy": avg_occupancy,
-                "opt_ridership": null,
-                "opt_average_occupancy": null
-            }));
-        } else {
-            HttpResponse::NotFound().json(serde_json::json!({
-                "error": format!("Route {} not found", route_id)
-            }))
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let network = match City::scenario_store(&city.name).and_then(|store| store.network(id)) {
+        Ok(Some(network)) => network,
+        Ok(None) => {
+            return ServiceError::NotFound(format!("Scenario {} not found", id)).error_response();
         }
-    } else {
-        HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "City data not loaded"
-        }))
-    }
+        Err(e) => {
+            return ServiceError::Internal(format!("Failed to load scenario {}: {}", id, e))
+                .error_response();
+        }
+    };
+    stream_geojson(
+        geojson::get_all_features(&TransitNetwork::to_gtfs_copy(
+            network.routes.iter().collect(),
+            &city.gtfs,
+            &city.road,
+        )),
+        None,
+    )
 }
 
-#[get("/evaluate-coverage/{route_id}")]
-async fn evaluate_coverage(
+/// Optimize a route like `/optimize-route/{route_id}`, streaming `ProgressEvent`s as
+/// server-sent events while ACO runs
+#[get("/optimize-route-events/{route_id}")]
+async fn optimize_route_events(
     route_id: web::Path<String>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let route_id = route_id.into_inner();
-    println!(
-        "Evaluating coverage and economic score for route: {}",
-        route_id
-    );
-
-    let city_guard = data.city.lock().unwrap();
+    println!("Optimizing route with progress events: {}", route_id);
 
-    if let Some(city) = &*city_guard {
-        let route = city.transit.routes.iter().find(|r| r.route_id == route_id);
-
-        if let Some(route) = route {
-            let coverage = route.evals.as_ref().unwrap().coverage;
-            let economic_score = route.evals.as_ref().unwrap().economic_score;
+    let (tx, rx) = futures::channel::mpsc::unbounded::<ProgressEvent>();
+    actix_web::rt::task::spawn_blocking(move || {
+        let send = |event: ProgressEvent| {
+            tx.unbounded_send(event).ok();
+        };
+        let city_guard = data.city.read().unwrap();
+        let city = match &*city_guard {
+            Some(city) => city,
+            None => return send(ServiceError::CityNotLoaded.into()),
+        };
+        let route = match city.transit.routes.iter().find(|r| r.route_id == route_id) {
+            Some(route) => route.clone(),
+            None => {
+                return send(ServiceError::NotFound(format!("Route {} not found", route_id)).into())
+            }
+        };
+        send(ProgressEvent::Started {
+            message: format!("Optimizing route {}", route_id),
+            routes: vec![route_id.clone()],
+            session_id: None,
+            resumed_at: None,
+        });
 
-            return HttpResponse::Ok().json(serde_json::json!({
+        let params = data.optimization_params();
+        // the route is optimized in a copy of the active network, only writing it back locks
+        // the network
+        let (workspace, network) = {
+            let workspaces = data.workspaces.lock().unwrap();
+            let optimized_transit_guard = data.optimized_transit.read().unwrap();
+            (
+                workspaces.active().to_string(),
+                optimized_transit_guard.as_ref().unwrap().clone(),
+            )
+        };
+        let _route_lock = match data
+            .route_locks
+            .try_lock(&workspace, std::slice::from_ref(&route_id))
+        {
+            Ok(guard) => guard,
+            Err(e) => return send(ServiceError::Conflict(e).into()),
+        };
+        let progress = |converged: bool| IterationProgress {
+            iteration: 1,
+            total_iterations: 1,
+            current_route: route_id.clone(),
+            current_route_index: 0,
+            routes_count: 1,
+            all_route_ids: vec![route_id.clone()],
+            route_iteration: 1,
+            iterations_per_route: 1,
+            converged_routes: vec![converged],
+            optimize_attempts: vec![1],
+        };
+        match aco2::run_aco_with_progress(
+            params.clone(),
+            &route,
+            city,
+            &network,
+            None,
+            &mut |event| send(event),
+        ) {
+            Some((opt_route, eval)) => {
+                let mut workspaces = data.workspaces.lock().unwrap();
+                let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+                let mut optimized_route_ids_guard = data.optimized_route_ids.lock().unwrap();
+                // the workspace may have been deactivated while the route was optimized
+                let (optimized_transit, optimized_route_ids) = match workspaces.get_mut(
+                    Some(&workspace),
+                    optimized_transit_guard.as_mut().unwrap(),
+                    &mut optimized_route_ids_guard,
+                ) {
+                    Ok(workspace) => workspace,
+                    Err(e) => return send(ServiceError::NotFound(e).into()),
+                };
+                data.route_history.lock().unwrap().record(
+                    &workspace,
+                    &opt_route,
+                    "optimize-route-events",
+                    Some(eval),
+                    &params,
+                );
+                optimized_transit.routes.retain(|r| r.route_id != route_id);
+                optimized_transit.routes.push(opt_route);
+                if !optimized_route_ids.contains(&route_id) {
+                    optimized_route_ids.push(route_id.clone());
+                }
+                let mut reviews = data.route_reviews.lock().unwrap();
+                reviews.propose(&route_id);
+                send(ProgressEvent::RouteOptimized {
+                    message: format!("Optimized route {}", route_id),
+                    geojson: get_optimized_geojson(
+                        city,
+                        optimized_transit,
+                        optimized_route_ids,
+                        &reviews,
+                    ),
+                    evaluation: vec![(route_id.clone(), eval)],
+                    optimized_routes: 1,
+                    progress: progress(false),
+                });
+            }
+            None => {
+                let noop_route_ids = {
+                    let mut noop_route_ids = data.noop_route_ids.lock().unwrap();
+                    noop_route_ids.insert(route_id.clone(), ());
+                    noop_route_ids.keys()
+                };
+                send(ProgressEvent::RouteConverged {
+                    message: format!("Route {} has converged to optimal solution", route_id),
+                    warning: format!("Route {} reached optimal solution", route_id),
+                    converged_route: route_id.clone(),
+                    converged_route_index: 0,
+                    noop_route_ids,
+                    progress: progress(true),
+                });
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(rx.map(|event| Ok::<_, Error>(web::Bytes::from(event.to_sse()))))
+}
+
+/// Queue the optimization of routes, see `optimize_routes_job`
+///
+/// # Returns
+/// The id of the job, whose progress and result are reported by `/jobs/{id}`
+#[post("/optimize-routes")]
+async fn optimize_routes(
+    route_ids: web::Json<RouteIds>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Optimizing multiple routes: {:?}", route_ids.routes);
+
+    let webhook = match route_ids.webhook_url.clone() {
+        Some(url) => {
+            let checked = url.clone();
+            match web::block(move || notify::validate_request_webhook_url(&checked)).await {
+                Ok(Ok(())) => Some(Webhook::requested(url)),
+                Ok(Err(e)) => return ServiceError::InvalidRequest(e).error_response(),
+                Err(e) => return ServiceError::Internal(e.to_string()).error_response(),
+            }
+        }
+        None => data.webhook.clone(),
+    };
+
+    if data.city.read().unwrap().is_none() {
+        return ServiceError::CityNotLoaded.error_response();
+    }
+
+    // Check if any routes exist
+    if route_ids.routes.is_empty() {
+        return ServiceError::InvalidRequest("No route IDs provided".to_string()).error_response();
+    }
+
+    let limits = route_ids.limits.min(data.optimization_limits);
+    if let Some(max_routes) = limits.max_routes {
+        if route_ids.routes.len() > max_routes {
+            return ServiceError::InvalidRequest(format!(
+                "Requested {} routes but at most {} can be optimized per request",
+                route_ids.routes.len(),
+                max_routes
+            ))
+            .with_details(serde_json::json!({ "limits": limits }));
+        }
+    }
+
+    // the workspace is resolved now, so the job merges into it even if another is activated
+    let workspace = {
+        let workspaces = data.workspaces.lock().unwrap();
+        match &route_ids.workspace {
+            Some(name) if !workspaces.contains(name) => {
+                return ServiceError::NotFound(format!("Workspace {} not found", name))
+                    .error_response();
+            }
+            Some(name) => name.clone(),
+            None => workspaces.active().to_string(),
+        }
+    };
+
+    let request = route_ids.into_inner();
+    let job = JobQueue::submit(
+        &data,
+        "optimize-routes",
+        request.routes.clone(),
+        Box::new(move |data, on_progress| {
+            optimize_routes_job(data, request, &workspace, limits, webhook, on_progress)
+        }),
+    );
+    HttpResponse::Accepted().json(serde_json::json!({
+        "message": format!("Queued the optimization of {} routes", job.routes.len()),
+        "job_id": job.id,
+        "status": job.status,
+    }))
+}
+
+/// Optimize the routes of an `/optimize-routes` request in a workspace
+///
+/// The batch runs on a copy of the workspace's network while only the city is locked, for
+/// reading, so the other endpoints keep answering. The optimized routes then replace theirs in
+/// the workspace, routes changed meanwhile by other requests are left as they are.
+///
+/// The webhook is notified once the job finishes, including when it fails before the batch
+/// ran.
+///
+/// # Returns
+/// The optimized network as GeoJSON with the batch's result and the changes it made, or an
+/// error if no route was optimized
+fn optimize_routes_job(
+    data: &AppState,
+    request: RouteIds,
+    workspace: &str,
+    limits: OptimizationLimits,
+    webhook: Option<Webhook>,
+    on_progress: &mut dyn FnMut(ProgressEvent),
+) -> Result<Value, String> {
+    let start = Instant::now();
+    let requested = request.routes.clone();
+    let mut summary = None;
+    let result = optimize_routes_batch(data, request, workspace, limits, &mut summary, on_progress);
+    if let Some(webhook) = webhook {
+        let summary = match (summary, &result) {
+            (Some(summary), _) => summary,
+            (None, Err(e)) => failed_job_summary(data, &requested, start.elapsed(), e),
+            (None, Ok(_)) => return result,
+        };
+        notify::send_in_background(webhook, summary);
+    }
+    result
+}
+
+/// Run the batch of `optimize_routes_job` and merge its routes into the workspace
+///
+/// # Arguments
+/// - `summary`: Set to the summary of the batch once it ran, left unset if the job failed
+///   before
+fn optimize_routes_batch(
+    data: &AppState,
+    request: RouteIds,
+    workspace: &str,
+    limits: OptimizationLimits,
+    summary: &mut Option<notify::JobSummary>,
+    on_progress: &mut dyn FnMut(ProgressEvent),
+) -> Result<Value, String> {
+    let start = Instant::now();
+    let city_guard = data.city.read().unwrap();
+    let city = city_guard.as_ref().ok_or("City data not loaded")?;
+
+    let _route_lock = data.route_locks.try_lock(workspace, &request.routes)?;
+    let mut network = {
+        let workspaces = data.workspaces.lock().unwrap();
+        let optimized_transit_guard = data.optimized_transit.read().unwrap();
+        let optimized_route_ids_guard = data.optimized_route_ids.lock().unwrap();
+        let (network, _) = workspaces.get(
+            Some(workspace),
+            optimized_transit_guard.as_ref().unwrap(),
+            &optimized_route_ids_guard,
+        )?;
+        network.clone()
+    };
+
+    let routes = city
+        .transit
+        .routes
+        .iter()
+        .filter(|r| request.routes.contains(&r.route_id))
+        .collect::<Vec<&TransitRoute>>();
+
+    // versions of the routes before this batch, to report what it changed
+    let routes_before: Vec<TransitRoute> = network
+        .routes
+        .iter()
+        .filter(|r| request.routes.contains(&r.route_id))
+        .cloned()
+        .collect();
+
+    on_progress(ProgressEvent::Started {
+        message: format!("Optimizing {} routes", routes.len()),
+        routes: routes.iter().map(|r| r.route_id.clone()).collect(),
+        session_id: None,
+        resumed_at: None,
+    });
+    let params = data.optimization_params();
+    let result = aco2::run_aco_batch_with_progress(
+        params.clone(),
+        &routes,
+        city,
+        &mut network,
+        aco2::BatchOptions {
+            coverage_mode: request.coverage_mode,
+            limits: limits.batch_limits(),
+            ..aco2::BatchOptions::default()
+        },
+        on_progress,
+    );
+
+    let diff = NetworkDiff::new(&routes_before, &network);
+    let record = RunRecord {
+        job: "optimize-routes".to_string(),
+        finished_at: chrono::Local::now().to_rfc3339(),
+        duration_ms: start.elapsed().as_millis(),
+        routes_requested: request.routes.len(),
+        optimized_route_ids: result.optimized_route_ids.clone(),
+        diff: diff.clone(),
+        resources: Some(result.resources.clone()),
+    };
+    if let Err(e) = City::append_run_history(&city.name, &record) {
+        eprintln!("Failed to record optimization run: {}", e);
+    }
+
+    let mut workspaces = data.workspaces.lock().unwrap();
+    let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+    let mut optimized_route_ids_guard = data.optimized_route_ids.lock().unwrap();
+    let (optimized_transit, optimized_route_ids) = workspaces.get_mut(
+        Some(workspace),
+        optimized_transit_guard.as_mut().unwrap(),
+        &mut optimized_route_ids_guard,
+    )?;
+
+    // Track successful optimizations and evaluations
+    let success_count = result.optimized_route_ids.len();
+
+    let mut reviews = data.route_reviews.lock().unwrap();
+    let mut history = data.route_history.lock().unwrap();
+    for opt_route_id in &result.optimized_route_ids {
+        let optimized = network.routes.iter().find(|r| &r.route_id == opt_route_id);
+        let current = optimized_transit
+            .routes
+            .iter_mut()
+            .find(|r| &r.route_id == opt_route_id);
+        if let (Some(optimized), Some(current)) = (optimized, current) {
+            history.record(workspace, optimized, "optimize-routes", None, &params);
+            *current = optimized.clone();
+        }
+        // Track the optimized route ID
+        if !optimized_route_ids.contains(opt_route_id) {
+            optimized_route_ids.push(opt_route_id.clone());
+        }
+        reviews.propose(opt_route_id);
+    }
+
+    // determine failed routes, routes skipped or cut off for lack of time may still be
+    // optimizable
+    for route_id in &request.routes {
+        if !optimized_route_ids.contains(route_id) && !result.ran_out_of_time(route_id) {
+            data.noop_route_ids
+                .lock()
+                .unwrap()
+                .insert(route_id.clone(), ());
+        }
+    }
+
+    *summary = Some(batch_summary(
+        city,
+        optimized_transit,
+        &request.routes,
+        &result.optimized_route_ids,
+        start.elapsed(),
+    ));
+
+    if success_count == 0 {
+        return Err("No routes were successfully optimized".to_string());
+    }
+    Ok(serde_json::json!({
+        "message": format!("Optimized {} routes", success_count),
+        "geojson": get_optimized_geojson(city, optimized_transit, optimized_route_ids, &reviews),
+        "batch": result,
+        "limits": limits,
+        "diff": diff,
+    }))
+}
+
+/// Jobs queued, running and recently finished, oldest first
+#[get("/jobs")]
+async fn get_jobs(data: web::Data<AppState>) -> impl Responder {
+    println!("Listing jobs");
+    HttpResponse::Ok().json(data.jobs.list())
+}
+
+/// Status, latest progress and, once it finished, result or error of a job
+#[get("/jobs/{id}")]
+async fn get_job(id: web::Path<u64>, data: web::Data<AppState>) -> impl Responder {
+    let id = id.into_inner();
+    println!("Getting job {}", id);
+    match data.jobs.get(id) {
+        Some(job) => HttpResponse::Ok().json(job),
+        None => ServiceError::NotFound(format!("Job {} not found", id)).error_response(),
+    }
+}
+
+/// Follow a job over a WebSocket, which sends its progress events as they are reported and a
+/// `job_finished` message with the job once it finished
+#[get("/jobs/{id}/ws")]
+async fn job_ws(
+    req: HttpRequest,
+    stream: web::Payload,
+    id: web::Path<u64>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let id = id.into_inner();
+    println!("WebSocket connection request for job {}", id);
+    match data.jobs.subscribe(id) {
+        Some(events) => ws::start(JobWs::new(events), &req, stream),
+        None => Ok(ServiceError::NotFound(format!("Job {} not found", id)).error_response()),
+    }
+}
+
+#[derive(Deserialize)]
+struct AreaParams {
+    /// GeoJSON `Polygon`, `MultiPolygon` or `Feature` of the area
+    area: Value,
+    /// Least share, from 0 to 1, of the outbound stops of a bus route inside the area for the
+    /// route to be optimized
+    #[serde(default = "default_min_stop_share")]
+    min_stop_share: f64,
+    /// How coverage is computed between routes of the batch, defaults to live
+    #[serde(default)]
+    coverage_mode: aco2::CoverageMode,
+    /// Resource limits of this request, capped by the server's limits
+    #[serde(default)]
+    limits: OptimizationLimits,
+}
+
+fn default_min_stop_share() -> f64 {
+    0.5
+}
+
+/// Queue the optimization of the bus routes serving an area, see `optimize_area_job`
+///
+/// # Returns
+/// The id of the job, whose progress and result are reported by `/jobs/{id}`
+#[post("/optimize-area")]
+async fn optimize_area(params: web::Json<AreaParams>, data: web::Data<AppState>) -> impl Responder {
+    println!("Optimizing routes of an area");
+
+    let area = match StudyArea::from_geojson(&params.area) {
+        Ok(area) => area,
+        Err(e) => {
+            return ServiceError::InvalidRequest(format!("Invalid area: {}", e)).error_response();
+        }
+    };
+    if !(0.0..=1.0).contains(&params.min_stop_share) {
+        return ServiceError::InvalidRequest("min_stop_share must be between 0 and 1".to_string())
+            .error_response();
+    }
+
+    let route_ids: Vec<String> = {
+        let city_guard = data.city.read().unwrap();
+        let city = match &*city_guard {
+            Some(city) => city,
+            None => {
+                return ServiceError::CityNotLoaded.error_response();
+            }
+        };
+        city.transit
+            .routes
+            .iter()
+            .filter(|r| r.route_type == TransitRouteType::Bus)
+            .filter(|r| {
+                let share = area.stop_share(r);
+                share > 0.0 && share >= params.min_stop_share
+            })
+            .map(|r| r.route_id.clone())
+            .collect()
+    };
+    if route_ids.is_empty() {
+        return ServiceError::InvalidRequest(format!(
+            "No bus route has at least {}% of its stops in the area",
+            params.min_stop_share * 100.0
+        ))
+        .error_response();
+    }
+
+    let limits = params.limits.min(data.optimization_limits);
+    if let Some(max_routes) = limits.max_routes {
+        if route_ids.len() > max_routes {
+            return ServiceError::InvalidRequest(format!(
+                "The area has {} routes but at most {} can be optimized per request",
+                route_ids.len(),
+                max_routes
+            ))
+            .with_details(serde_json::json!({ "routes": route_ids, "limits": limits }));
+        }
+    }
+
+    // the workspace is resolved now, so the job merges into it even if another is activated
+    let workspace = data.workspaces.lock().unwrap().active().to_string();
+    let request = params.into_inner();
+    let job = JobQueue::submit(
+        &data,
+        "optimize-area",
+        route_ids.clone(),
+        Box::new(move |data, on_progress| {
+            optimize_area_job(
+                data,
+                AreaJob {
+                    area,
+                    route_ids,
+                    request,
+                    limits,
+                },
+                &workspace,
+                on_progress,
+            )
+        }),
+    );
+    HttpResponse::Accepted().json(serde_json::json!({
+        "message": format!("Queued the optimization of the {} routes in the area", job.routes.len()),
+        "job_id": job.id,
+        "status": job.status,
+    }))
+}
+
+/// Area optimization queued by `/optimize-area`
+struct AreaJob {
+    area: StudyArea,
+    /// Bus routes serving the area, resolved when the job was queued
+    route_ids: Vec<String>,
+    request: AreaParams,
+    /// Limits of the request, capped by the server's limits
+    limits: OptimizationLimits,
+}
+
+/// Optimize the bus routes serving an area together, keeping their stops outside of it
+///
+/// Like `optimize_routes_batch`, the batch runs on a copy of the workspace's network and
+/// only writing its routes back locks the network.
+///
+/// # Returns
+/// The optimized network as GeoJSON with the batch's result, the changes it made and the
+/// metrics of the area before and after, or an error if the routes could not be optimized
+fn optimize_area_job(
+    data: &AppState,
+    job: AreaJob,
+    workspace: &str,
+    on_progress: &mut dyn FnMut(ProgressEvent),
+) -> Result<Value, String> {
+    let start = Instant::now();
+    let AreaJob {
+        area,
+        route_ids,
+        request,
+        limits,
+    } = job;
+    let city_guard = data.city.read().unwrap();
+    let city = city_guard.as_ref().ok_or("City data not loaded")?;
+
+    let _route_lock = data.route_locks.try_lock(workspace, &route_ids)?;
+    let mut network = {
+        let workspaces = data.workspaces.lock().unwrap();
+        let optimized_transit_guard = data.optimized_transit.read().unwrap();
+        let optimized_route_ids_guard = data.optimized_route_ids.lock().unwrap();
+        let (network, _) = workspaces.get(
+            Some(workspace),
+            optimized_transit_guard.as_ref().unwrap(),
+            &optimized_route_ids_guard,
+        )?;
+        network.clone()
+    };
+
+    // the city may have been replaced since the job was queued
+    let routes = city
+        .transit
+        .routes
+        .iter()
+        .filter(|r| route_ids.contains(&r.route_id))
+        .collect::<Vec<&TransitRoute>>();
+
+    // versions of the routes before this batch, to report what it changed
+    let routes_before: Vec<TransitRoute> = network
+        .routes
+        .iter()
+        .filter(|r| route_ids.contains(&r.route_id))
+        .cloned()
+        .collect();
+    let metrics_before = AreaMetrics::new(&area, &network, &city.grid, &route_ids);
+
+    on_progress(ProgressEvent::Started {
+        message: format!("Optimizing the {} routes in the area", routes.len()),
+        routes: routes.iter().map(|r| r.route_id.clone()).collect(),
+        session_id: None,
+        resumed_at: None,
+    });
+    let aco_params = data.optimization_params();
+    let result = aco2::run_aco_batch_with_progress(
+        aco_params.clone(),
+        &routes,
+        city,
+        &mut network,
+        aco2::BatchOptions {
+            coverage_mode: request.coverage_mode,
+            limits: limits.batch_limits(),
+            area: Some(&area),
+            budget: None,
+        },
+        on_progress,
+    );
+
+    let metrics_after = AreaMetrics::new(&area, &network, &city.grid, &route_ids);
+    let diff = NetworkDiff::new(&routes_before, &network);
+    let record = RunRecord {
+        job: "optimize-area".to_string(),
+        finished_at: chrono::Local::now().to_rfc3339(),
+        duration_ms: start.elapsed().as_millis(),
+        routes_requested: route_ids.len(),
+        optimized_route_ids: result.optimized_route_ids.clone(),
+        diff: diff.clone(),
+        resources: Some(result.resources.clone()),
+    };
+    if let Err(e) = City::append_run_history(&city.name, &record) {
+        eprintln!("Failed to record optimization run: {}", e);
+    }
+
+    let mut workspaces = data.workspaces.lock().unwrap();
+    let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+    let mut optimized_route_ids_guard = data.optimized_route_ids.lock().unwrap();
+    let (optimized_transit, optimized_route_ids) = workspaces.get_mut(
+        Some(workspace),
+        optimized_transit_guard.as_mut().unwrap(),
+        &mut optimized_route_ids_guard,
+    )?;
+
+    let mut reviews = data.route_reviews.lock().unwrap();
+    let mut history = data.route_history.lock().unwrap();
+    for opt_route_id in &result.optimized_route_ids {
+        let optimized = network.routes.iter().find(|r| &r.route_id == opt_route_id);
+        let current = optimized_transit
+            .routes
+            .iter_mut()
+            .find(|r| &r.route_id == opt_route_id);
+        if let (Some(optimized), Some(current)) = (optimized, current) {
+            history.record(workspace, optimized, "optimize-area", None, &aco_params);
+            *current = optimized.clone();
+        }
+        if !optimized_route_ids.contains(opt_route_id) {
+            optimized_route_ids.push(opt_route_id.clone());
+        }
+        reviews.propose(opt_route_id);
+    }
+    // routes skipped or cut off for lack of time may still be optimizable
+    for route_id in &route_ids {
+        if !optimized_route_ids.contains(route_id) && !result.ran_out_of_time(route_id) {
+            data.noop_route_ids
+                .lock()
+                .unwrap()
+                .insert(route_id.clone(), ());
+        }
+    }
+
+    Ok(serde_json::json!({
+        "message": format!(
+            "Optimized {} of the {} routes in the area",
+            result.optimized_route_ids.len(),
+            route_ids.len()
+        ),
+        "geojson": get_optimized_geojson(city, optimized_transit, optimized_route_ids, &reviews),
+        "batch": result,
+        "limits": limits,
+        "diff": diff,
+        "area": {
+            "routes": route_ids,
+            "min_stop_share": request.min_stop_share,
+            "delta": metrics_before.delta(&metrics_after),
+            "before": metrics_before,
+            "after": metrics_after,
+        },
+    }))
+}
+
+/// Summarize a finished batch optimization for webhook notifications
+fn batch_summary(
+    city: &City,
+    optimized_transit: &TransitNetwork,
+    requested: &[String],
+    optimized: &[String],
+    duration: Duration,
+) -> notify::JobSummary {
+    let mut errors = vec![];
+    let mut routes_failed = vec![];
+    for route_id in requested {
+        if optimized.contains(route_id) {
+            continue;
+        }
+        routes_failed.push(route_id.clone());
+        if city.transit.routes.iter().any(|r| &r.route_id == route_id) {
+            errors.push(format!("Route {} could not be optimized", route_id));
+        } else {
+            errors.push(format!("Route {} not found", route_id));
+        }
+    }
+
+    let avg_ridership = |transit: &TransitNetwork| {
+        let total: f64 = transit
+            .routes
+            .iter()
+            .filter(|r| optimized.contains(&r.route_id))
+            .filter_map(|r| r.evals.as_ref().map(|e| e.avg_ridership))
+            .sum();
+        total / optimized.len() as f64
+    };
+    let improvement = (!optimized.is_empty()).then(|| {
+        let before = avg_ridership(&city.transit);
+        let after = avg_ridership(optimized_transit);
+        notify::ImprovementStats {
+            avg_ridership_before: before,
+            avg_ridership_after: after,
+            improvement_pct: if before > 0.0 {
+                (after - before) / before * 100.0
+            } else {
+                0.0
+            },
+        }
+    });
+
+    notify::JobSummary {
+        job: "optimize-routes".to_string(),
+        city: city.name.clone(),
+        status: if optimized.is_empty() {
+            notify::JobStatus::Failed
+        } else {
+            notify::JobStatus::Completed
+        },
+        routes_requested: requested.len(),
+        routes_optimized: optimized.to_vec(),
+        routes_failed,
+        improvement,
+        duration_ms: duration.as_millis(),
+        finished_at: chrono::Local::now().to_rfc3339(),
+        errors,
+    }
+}
+
+/// Summarize a batch optimization that failed before it ran for webhook notifications
+fn failed_job_summary(
+    data: &AppState,
+    requested: &[String],
+    duration: Duration,
+    error: &str,
+) -> notify::JobSummary {
+    let city = data.city.read().unwrap();
+    notify::JobSummary {
+        job: "optimize-routes".to_string(),
+        city: city.as_ref().map(|c| c.name.clone()).unwrap_or_default(),
+        status: notify::JobStatus::Failed,
+        routes_requested: requested.len(),
+        routes_optimized: vec![],
+        routes_failed: requested.to_vec(),
+        improvement: None,
+        duration_ms: duration.as_millis(),
+        finished_at: chrono::Local::now().to_rfc3339(),
+        errors: vec![error.to_string()],
+    }
+}
+
+#[get("/evaluate-route/{route_id}")]
+async fn evaluate_route(route_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let route_id = route_id.into_inner();
+    println!("Evaluating route: {}", route_id);
+
+    let city_guard = data.city.read().unwrap();
+
+    if let Some(city) = &*city_guard {
+        let optimized_transit_guard = data.optimized_transit.read().unwrap();
+        let optimized_transit = optimized_transit_guard.as_ref().unwrap();
+        let optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+
+        // Find the route with the given ID
+        let route = city.transit.routes.iter().find(|r| r.route_id == route_id);
+
+        if let Some(route) = route {
+            let (ridership, avg_occupancy, load_factor) = (
+                &route.evals.as_ref().unwrap().ridership,
+                route.evals.as_ref().unwrap().avg_ridership,
+                &route.evals.as_ref().unwrap().load_factor,
+            );
+
+            // Only evaluate the optimized route if it has been optimized
+            if optimized_route_ids.contains(&route_id) {
+                if let Some(opt_route) = optimized_transit
+                    .routes
+                    .iter()
+                    .find(|r| r.route_id == route_id)
+                {
+                    let (opt_ridership, opt_avg_occupancy, opt_load_factor) = (
+                        &opt_route.evals.as_ref().unwrap().ridership,
+                        opt_route.evals.as_ref().unwrap().avg_ridership,
+                        &opt_route.evals.as_ref().unwrap().load_factor,
+                    );
+                    let coverage = opt_route.evals.as_ref().unwrap().coverage;
+                    let economic_score = opt_route.evals.as_ref().unwrap().economic_score;
+                    println!(
+                        "Route {}: coverage={}, economic_score={}",
+                        route_id, coverage, economic_score
+                    );
+
+                    return HttpResponse::Ok().json(serde_json::json!({
+                        "route_id": route_id,
+                        "ridership": ridership,
+                        "opt_ridership": opt_ridership,
+                        "average_occupancy": avg_occupancy,
+                        "opt_average_occupancy": opt_avg_occupancy,
+                        "load_factor": load_factor,
+                        "opt_load_factor": opt_load_factor
+                    }));
+                }
+            }
+
+            // Return just the original route metrics if no optimized version exists
+            return HttpResponse::Ok().json(serde_json::json!({
                 "route_id": route_id,
-                "coverage": coverage,
-                "economic_score": economic_score,
+                "ridership": ridership,
+                "average_occupancy": avg_occupancy,
+                "opt_ridership": null,
+                "opt_average_occupancy": null,
+                "load_factor": load_factor,
+                "opt_load_factor": null
+            }));
+        } else {
+            ServiceError::NotFound(format!("Route {} not found", route_id)).error_response()
+        }
+    } else {
+        ServiceError::CityNotLoaded.error_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct ProposeExpressParams {
+    /// Share of the stops of the route the express serves, 0.3 by default
+    stop_share: Option<f64>,
+    /// Fewest stops the express serves in each direction, 2 by default
+    min_stops: Option<usize>,
+    /// Minutes between express departures, the average headway of the route by default
+    headway_minutes: Option<f64>,
+}
+
+/// Propose an express overlay of a route, serving its busiest stops on the same alignment,
+/// and evaluate it together with the local route in the optimized network. The network is
+/// left as is.
+#[post("/propose-express/{route_id}")]
+async fn propose_express(
+    route_id: web::Path<String>,
+    query: web::Query<ProposeExpressParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let route_id = route_id.into_inner();
+    println!("Proposing express overlay for route: {}", route_id);
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let transit = optimized_transit_guard.as_ref().unwrap_or(&city.transit);
+    let Some(route) = transit.routes.iter().find(|r| r.route_id == route_id) else {
+        return ServiceError::NotFound(format!("Route {} not found", route_id)).error_response();
+    };
+
+    let params = ExpressParams {
+        stop_share: query.stop_share.unwrap_or(0.3),
+        min_stops: query.min_stops.unwrap_or(2),
+        headway_minutes: query
+            .headway_minutes
+            .unwrap_or_else(|| accessibility::headway_minutes(route)),
+    };
+    let (proposal, express_route) =
+        match express::propose_express(route, transit, &city.grid, &city.search, &params) {
+            Ok(proposal) => proposal,
+            Err(e) => {
+                return ServiceError::InvalidRequest(e.to_string()).error_response();
+            }
+        };
+
+    let mut features = geojson::get_all_features(&TransitNetwork::to_gtfs_filtered(
+        vec![route],
+        &city.gtfs,
+        &city.road,
+    ));
+    geojson::tag_and_simplify_features(&mut features, "local", 0.0);
+    // The express has no GTFS route of its own, so it is converted under the local route's id
+    let mut express_as_local = express_route.clone();
+    express_as_local.route_id = route_id.clone();
+    let mut express_features = geojson::get_all_features(&TransitNetwork::to_gtfs_filtered(
+        vec![&express_as_local],
+        &city.gtfs,
+        &city.road,
+    ));
+    for feature in express_features.iter_mut() {
+        if feature["properties"]["route_id"] == route_id.as_str() {
+            feature["properties"]["route_id"] = serde_json::json!(express_route.route_id);
+        }
+    }
+    geojson::tag_and_simplify_features(&mut express_features, "express", 0.0);
+    features.extend(express_features);
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "proposal": proposal,
+        "geojson": geojson::convert_to_geojson(&features),
+    }))
+}
+
+/// Propose how to consolidate pairs of bus routes of the optimized network that largely run
+/// along each other, evaluating the routes before and after. The network is left as is.
+#[post("/consolidate-routes")]
+async fn consolidate_routes(
+    params: web::Json<ConsolidateParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Proposing route consolidations");
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let transit = optimized_transit_guard.as_ref().unwrap_or(&city.transit);
+
+    let proposals = match consolidate::propose_consolidations(&params, city, transit) {
+        Ok(proposals) => proposals,
+        Err(e) => {
+            return ServiceError::InvalidRequest(e.to_string()).error_response();
+        }
+    };
+    let replacements: Vec<&TransitRoute> = proposals
+        .iter()
+        .flat_map(|(_, routes)| routes.iter())
+        .collect();
+    let mut features = geojson::get_all_features(&TransitNetwork::to_gtfs_filtered(
+        replacements,
+        &city.gtfs,
+        &city.road,
+    ));
+    geojson::tag_and_simplify_features(&mut features, "consolidated", 0.0);
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "params": *params,
+        "proposals": proposals.iter().map(|(p, _)| p).collect::<Vec<_>>(),
+        "geojson": geojson::convert_to_geojson(&features),
+    }))
+}
+
+#[derive(Deserialize)]
+struct CreateRouteRequest {
+    #[serde(flatten)]
+    params: NewRouteParams,
+    /// Return the route without adding it to the optimized network
+    #[serde(default)]
+    dry_run: bool,
+    /// Workspace whose network the route is added to, the active one if missing
+    workspace: Option<String>,
+}
+
+/// Create a bus route between two terminal stops or zones, searched with the current ACO
+/// parameters against the optimized network. Unless `dry_run` is set, the route is added to
+/// the network and proposed for review like an optimized route.
+#[post("/create-route")]
+async fn create_route(
+    body: web::Json<CreateRouteRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!(
+        "Creating a route from {:?} to {:?}",
+        body.params.from, body.params.to
+    );
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let params = data.optimization_params();
+
+    let mut workspaces = data.workspaces.lock().unwrap();
+    let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+    let mut optimized_route_ids_guard = data.optimized_route_ids.lock().unwrap();
+    let (optimized_transit, optimized_route_ids) = match workspaces.get_mut(
+        body.workspace.as_deref(),
+        optimized_transit_guard.as_mut().unwrap(),
+        &mut optimized_route_ids_guard,
+    ) {
+        Ok(workspace) => workspace,
+        Err(e) => return ServiceError::NotFound(e.to_string()).error_response(),
+    };
+
+    let route = match new_route::create_route(params, &body.params, city, optimized_transit) {
+        Ok(route) => route,
+        Err(e) => {
+            return ServiceError::InvalidRequest(e.to_string()).error_response();
+        }
+    };
+    let mut features = geojson::get_all_features(&TransitNetwork::to_gtfs_filtered(
+        vec![&route],
+        &city.gtfs,
+        &city.road,
+    ));
+    geojson::tag_and_simplify_features(&mut features, "new", 0.0);
+    let stop_ids = |stops: &[Arc<TransitStop>]| -> Vec<String> {
+        stops.iter().map(|s| s.stop_id.clone()).collect()
+    };
+    let response = serde_json::json!({
+        "message": format!("Created route {}", route.route_id),
+        "route_id": route.route_id,
+        "outbound_stop_ids": stop_ids(&route.outbound_stops),
+        "inbound_stop_ids": stop_ids(&route.inbound_stops),
+        "evaluation": route.evals,
+        "geojson": geojson::convert_to_geojson(&features),
+        "dry_run": body.dry_run,
+    });
+
+    if !body.dry_run {
+        data.route_reviews.lock().unwrap().propose(&route.route_id);
+        optimized_route_ids.push(route.route_id.clone());
+        optimized_transit.routes.push(route);
+    }
+    HttpResponse::Ok().json(response)
+}
+
+#[derive(Deserialize)]
+struct OptimizeFrequenciesParams {
+    #[serde(flatten)]
+    params: FrequencyParams,
+    /// Routes sharing the fleet, every bus route by default
+    route_ids: Option<Vec<String>>,
+    /// Return the plan without applying it to the optimized network
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Assign departures to routes in each time period by their demand, within a fleet size, and
+/// apply them to the optimized network unless `dry_run` is set
+#[post("/optimize-frequencies")]
+async fn optimize_frequencies(
+    body: web::Json<OptimizeFrequenciesParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Optimizing route frequencies");
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+    let Some(optimized_transit) = optimized_transit_guard.as_mut() else {
+        return ServiceError::NetworkNotLoaded.error_response();
+    };
+
+    let routes: Vec<&TransitRoute> = match &body.route_ids {
+        Some(route_ids) => {
+            let mut routes = vec![];
+            for route_id in route_ids {
+                let Some(route) = optimized_transit
+                    .routes
+                    .iter()
+                    .find(|r| &r.route_id == route_id)
+                else {
+                    return ServiceError::NotFound(format!("Route {} not found", route_id))
+                        .error_response();
+                };
+                routes.push(route);
+            }
+            routes
+        }
+        None => optimized_transit
+            .routes
+            .iter()
+            .filter(|r| r.route_type == TransitRouteType::Bus)
+            .collect(),
+    };
+    if routes.is_empty() {
+        return ServiceError::InvalidRequest("No routes to plan".to_string()).error_response();
+    }
+
+    let plan = match FrequencyPlan::new(
+        &routes,
+        optimized_transit,
+        &city.grid,
+        &city.road,
+        body.params,
+    ) {
+        Ok(plan) => plan,
+        Err(e) => {
+            return ServiceError::InvalidRequest(e.to_string()).error_response();
+        }
+    };
+
+    let mut changed_route_ids = vec![];
+    if !body.dry_run {
+        changed_route_ids = plan.apply(optimized_transit);
+        let evals: Vec<(usize, eval::TransitRouteEvals)> = optimized_transit
+            .routes
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| changed_route_ids.contains(&r.route_id))
+            .map(|(i, r)| {
+                let evals = eval::TransitRouteEvals::for_route(
+                    optimized_transit,
+                    r,
+                    &city.grid,
+                    &city.search,
+                );
+                (i, evals)
+            })
+            .collect();
+        for (i, evals) in evals {
+            optimized_transit.routes[i].evals = Some(evals);
+        }
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "plan": plan,
+        "applied": !body.dry_run,
+        "changed_route_ids": changed_route_ids,
+    }))
+}
+
+#[get("/route-reviews")]
+async fn get_route_reviews(data: web::Data<AppState>) -> impl Responder {
+    println!("Fetching route review states");
+
+    let optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+    let reviews = data.route_reviews.lock().unwrap();
+    let states: serde_json::Map<String, Value> = optimized_route_ids
+        .iter()
+        .map(|id| (id.clone(), serde_json::json!(reviews.state(id))))
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({ "routes": states }))
+}
+
+#[post("/accept-route/{route_id}")]
+async fn accept_route(route_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    review_route(&route_id.into_inner(), review::ReviewState::Accepted, &data)
+}
+
+#[post("/reject-route/{route_id}")]
+async fn reject_route(route_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    review_route(&route_id.into_inner(), review::ReviewState::Rejected, &data)
+}
+
+fn review_route(route_id: &str, state: review::ReviewState, data: &AppState) -> HttpResponse {
+    println!("Reviewing route {}: {:?}", route_id, state);
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let workspace = data.workspaces.lock().unwrap().active().to_string();
+    // a rejected route put back while it is optimized would be overwritten when it ends
+    let _route_lock = match data
+        .route_locks
+        .try_lock(&workspace, &[route_id.to_string()])
+    {
+        Ok(guard) => guard,
+        Err(e) => return ServiceError::Conflict(e.to_string()).error_response(),
+    };
+    let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+    if !data
+        .optimized_route_ids
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|id| id == route_id)
+    {
+        return ServiceError::NotFound(format!("Route {} has not been optimized", route_id))
+            .error_response();
+    }
+
+    if let Err(current) = data.route_reviews.lock().unwrap().review(route_id, state) {
+        return ServiceError::Conflict(format!("Route {} is not proposed", route_id))
+            .with_details(serde_json::json!({ "review_state": current }));
+    }
+    // the rejected version must not count toward coverage and the network evaluations, the
+    // original route takes its place, or none for a route created from scratch
+    if let (review::ReviewState::Rejected, Some(optimized_transit)) =
+        (state, optimized_transit_guard.as_mut())
+    {
+        let original = city.transit.routes.iter().find(|r| r.route_id == route_id);
+        match original {
+            Some(original) => {
+                for route in optimized_transit.routes.iter_mut() {
+                    if route.route_id == route_id {
+                        *route = original.clone();
+                    }
+                }
+            }
+            None => optimized_transit.routes.retain(|r| r.route_id != route_id),
+        }
+        optimized_transit.evals = None;
+    }
+    HttpResponse::Ok().json(serde_json::json!({
+        "route_id": route_id,
+        "review_state": state,
+    }))
+}
+
+/// Versions of a route accepted into the network of a workspace, oldest first
+#[get("/route-history/{route_id}")]
+async fn get_route_history(
+    route_id: web::Path<String>,
+    query: web::Query<WorkspaceParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let route_id = route_id.into_inner();
+    println!("Fetching optimization history of route {}", route_id);
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    if !city.transit.routes.iter().any(|r| r.route_id == route_id) {
+        return ServiceError::NotFound(format!("Route {} not found", route_id)).error_response();
+    }
+    let workspace = {
+        let workspaces = data.workspaces.lock().unwrap();
+        match &query.workspace {
+            Some(name) if !workspaces.contains(name) => {
+                return ServiceError::NotFound(format!("Workspace {} not found", name))
+                    .error_response();
+            }
+            Some(name) => name.clone(),
+            None => workspaces.active().to_string(),
+        }
+    };
+
+    let history = data.route_history.lock().unwrap();
+    let versions: &[RouteVersion] = history.versions(&workspace, &route_id);
+    HttpResponse::Ok().json(serde_json::json!({
+        "route_id": route_id,
+        "workspace": workspace,
+        "current_version": versions.last().map(|v| v.version),
+        "versions": versions,
+    }))
+}
+
+/// Step a route back to the version accepted before its latest optimization, or to the
+/// original route if it was optimized once
+///
+/// Only the route is put back, the other routes of the workspace keep their versions.
+#[post("/undo-optimization/{route_id}")]
+async fn undo_optimization(
+    route_id: web::Path<String>,
+    query: web::Query<WorkspaceParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let route_id = route_id.into_inner();
+    println!("Undoing the last optimization of route {}", route_id);
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let Some(original) = city.transit.routes.iter().find(|r| r.route_id == route_id) else {
+        return ServiceError::NotFound(format!("Route {} not found", route_id)).error_response();
+    };
+
+    let mut workspaces = data.workspaces.lock().unwrap();
+    let workspace = query
+        .workspace
+        .clone()
+        .unwrap_or_else(|| workspaces.active().to_string());
+    // undoing while the route is optimized would be overwritten when the optimization ends
+    let _route_lock = match data
+        .route_locks
+        .try_lock(&workspace, std::slice::from_ref(&route_id))
+    {
+        Ok(guard) => guard,
+        Err(e) => return ServiceError::Conflict(e.to_string()).error_response(),
+    };
+    let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+    let mut optimized_route_ids_guard = data.optimized_route_ids.lock().unwrap();
+    let (optimized_transit, optimized_route_ids) = match workspaces.get_mut(
+        Some(&workspace),
+        optimized_transit_guard.as_mut().unwrap(),
+        &mut optimized_route_ids_guard,
+    ) {
+        Ok(workspace) => workspace,
+        Err(e) => return ServiceError::NotFound(e.to_string()).error_response(),
+    };
+
+    let mut history = data.route_history.lock().unwrap();
+    let Some((undone, restored)) = history.undo(&workspace, &route_id) else {
+        return ServiceError::NotFound(format!(
+            "Route {} has no optimization to undo in workspace {}",
+            route_id, workspace
+        ))
+        .error_response();
+    };
+    let restored_version = restored.map(|v| v.version);
+    let route = restored.map_or_else(|| original.clone(), |v| v.route.clone());
+    optimized_transit.routes.retain(|r| r.route_id != route_id);
+    optimized_transit.routes.push(route);
+
+    let mut reviews = data.route_reviews.lock().unwrap();
+    reviews.propose(&route_id);
+    if restored_version.is_none() {
+        optimized_route_ids.retain(|id| id != &route_id);
+    } else if !optimized_route_ids.contains(&route_id) {
+        optimized_route_ids.push(route_id.clone());
+    }
+
+    let features = get_optimized_features(city, optimized_transit, optimized_route_ids, &reviews);
+    let fields = serde_json::json!({
+        "message": match restored_version {
+            Some(version) => format!("Restored version {} of route {}", version, route_id),
+            None => format!("Restored the original route {}", route_id),
+        },
+        "route_id": route_id,
+        "workspace": workspace,
+        "undone_version": undone.version,
+        "restored_version": restored_version,
+    });
+    stream_geojson(features, Some((fields, "geojson")))
+}
+
+/// Lock stops and segments of a route, e.g. a hospital or a subway connection, so that
+/// optimizations keep them
+///
+/// The constraint replaces the previous one of the route and an empty one unlocks it. Locked
+/// stops and segments must be on the route as it runs in the active network.
+#[post("/route-constraints/{route_id}")]
+async fn set_route_constraints(
+    route_id: web::Path<String>,
+    body: web::Json<RouteConstraint>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let route_id = route_id.into_inner();
+    let constraint = body.into_inner();
+    println!(
+        "Setting constraints of route {}: {:?}",
+        route_id, constraint
+    );
+
+    {
+        let city_guard = data.city.read().unwrap();
+        let Some(city) = &*city_guard else {
+            return ServiceError::CityNotLoaded.error_response();
+        };
+        let optimized_transit_guard = data.optimized_transit.read().unwrap();
+        let transit = optimized_transit_guard.as_ref().unwrap_or(&city.transit);
+        let Some(route) = transit.routes.iter().find(|r| r.route_id == route_id) else {
+            return ServiceError::NotFound(format!("Route {} not found", route_id))
+                .error_response();
+        };
+        if let Err(e) = constraint.check(&route.outbound_stops) {
+            return ServiceError::InvalidRequest(e)
+                .with_details(serde_json::json!({ "route_id": route_id }));
+        }
+    }
+
+    let mut constraints = data.route_constraints.lock().unwrap();
+    if constraint.is_empty() {
+        constraints.remove(&route_id);
+    } else {
+        constraints.insert(route_id.clone(), constraint.clone());
+    }
+    HttpResponse::Ok().json(serde_json::json!({
+        "route_id": route_id,
+        "constraint": constraint,
+    }))
+}
+
+#[get("/route-constraints/{route_id}")]
+async fn get_route_constraints(
+    route_id: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let route_id = route_id.into_inner();
+    println!("Fetching constraints of route {}", route_id);
+
+    let constraint = data
+        .route_constraints
+        .lock()
+        .unwrap()
+        .get(&route_id)
+        .cloned()
+        .unwrap_or_default();
+    HttpResponse::Ok().json(serde_json::json!({
+        "route_id": route_id,
+        "constraint": constraint,
+    }))
+}
+
+#[get("/route/{route_id}")]
+async fn get_route(route_id: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let route_id = route_id.into_inner();
+    println!("Fetching route: {}", route_id);
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+
+    let route = match city.transit.routes.iter().find(|r| r.route_id == route_id) {
+        Some(route) => route,
+        None => {
+            return ServiceError::NotFound(format!("Route {} not found", route_id))
+                .error_response();
+        }
+    };
+    let gtfs_route = city.gtfs.routes.get(&route_id);
+    let optimized = data.optimized_route_ids.lock().unwrap().contains(&route_id);
+    let diff = if optimized {
+        data.optimized_transit
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|transit| transit.routes.iter().find(|r| r.route_id == route_id))
+            .map(|opt_route| route_stop_diff(route, opt_route, &city.stop_infra))
+    } else {
+        None
+    };
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "route_id": route_id,
+        "route_short_name": gtfs_route.and_then(|r| r.route_short_name.clone()),
+        "route_long_name": gtfs_route.and_then(|r| r.route_long_name.clone()),
+        "route_type": route.route_type,
+        "outbound_stops": route.outbound_stops.iter().map(|s| &s.stop_id).collect::<Vec<_>>(),
+        "inbound_stops": route.inbound_stops.iter().map(|s| &s.stop_id).collect::<Vec<_>>(),
+        "timezone": city.timezone,
+        "departures_by_period": route.stop_times,
+        "vehicle": route.vehicle,
+        "service_span": route.service_span.as_ref().map(|span| serde_json::json!({
+            "first_trip_id": span.first_trip_id,
+            "first_departure": format_gtfs_time(span.first_departure),
+            "last_trip_id": span.last_trip_id,
+            "last_arrival": format_gtfs_time(span.last_arrival),
+        })),
+        "optimized": optimized,
+        "diff": diff,
+    }))
+}
+
+/// Stops added and removed by optimizing a route. Added stops without a shelter or pad are
+/// listed separately since serving them needs new infrastructure.
+///
+/// Each direction is compared on its own. Optimized routes without inbound stops, e.g. new or
+/// consolidated routes, are only compared on their outbound stops.
+fn route_stop_diff(
+    route: &TransitRoute,
+    opt_route: &TransitRoute,
+    stop_infra: &StopInfrastructure,
+) -> Value {
+    let stop_ids = |stops: &[Arc<TransitStop>]| -> HashSet<String> {
+        stops.iter().map(|s| s.stop_id.clone()).collect()
+    };
+    let mut directions = vec![(&route.outbound_stops, &opt_route.outbound_stops)];
+    if !opt_route.inbound_stops.is_empty() {
+        directions.push((&route.inbound_stops, &opt_route.inbound_stops));
+    }
+    let mut added: BTreeSet<String> = BTreeSet::new();
+    let mut removed: BTreeSet<String> = BTreeSet::new();
+    for (before, after) in directions {
+        let (before, after) = (stop_ids(before), stop_ids(after));
+        added.extend(after.difference(&before).cloned());
+        removed.extend(before.difference(&after).cloned());
+    }
+    let without_infrastructure: Vec<&String> = added
+        .iter()
+        .filter(|id| !stop_infra.has_infrastructure(id))
+        .collect();
+
+    serde_json::json!({
+        "added_stops": added,
+        "removed_stops": removed,
+        "added_stops_without_infrastructure": without_infrastructure,
+    })
+}
+
+#[derive(Deserialize)]
+struct RouteTimetableParams {
+    /// Time period to preview, e.g. `AmRush`, which is the default
+    period: Option<TimePeriod>,
+}
+
+/// Preview of the outbound timetable of a route over the first hour of a time period, for
+/// the original route and its optimized version if there is one
+#[get("/route-timetable/{route_id}")]
+async fn get_route_timetable(
+    route_id: web::Path<String>,
+    query: web::Query<RouteTimetableParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let route_id = route_id.into_inner();
+    println!("Getting timetable of route {}", route_id);
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+    let route = match city.transit.routes.iter().find(|r| r.route_id == route_id) {
+        Some(route) => route,
+        None => {
+            return ServiceError::NotFound(format!("Route {} not found", route_id))
+                .error_response();
+        }
+    };
+
+    let period = query.period.clone().unwrap_or(TimePeriod::AmRush);
+    // the slim feed keeps the trip the route's outbound stops come from
+    let trip = city
+        .gtfs
+        .trips
+        .get(&route_id)
+        .and_then(|trips| trips.first());
+    let original = TimetablePreview::for_route(route, &period, &city.road, trip);
+
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+    let optimized = optimized_transit_guard
+        .as_ref()
+        .filter(|_| optimized_route_ids.contains(&route_id))
+        .and_then(|transit| transit.routes.iter().find(|r| r.route_id == route_id))
+        .map(|r| TimetablePreview::for_route(r, &period, &city.road, None));
+
+    let (start, end) = period.local_bounds();
+    HttpResponse::Ok().json(serde_json::json!({
+        "route_id": route_id,
+        "period": period,
+        "period_start": format_gtfs_time(start),
+        "period_end": format_gtfs_time(end),
+        "timezone": city.timezone,
+        "original": original,
+        "optimized": optimized,
+    }))
+}
+
+/// Riders boarding and alighting at each outbound stop of a route and the load between
+/// stops, for the original route and its optimized version if there is one
+#[get("/route-ridership/{route_id}")]
+async fn get_route_ridership(
+    route_id: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let route_id = route_id.into_inner();
+    println!("Getting ridership of route {}", route_id);
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+    let route = match city.transit.routes.iter().find(|r| r.route_id == route_id) {
+        Some(route) => route,
+        None => {
+            return ServiceError::NotFound(format!("Route {} not found", route_id))
+                .error_response();
+        }
+    };
+    let original = eval::ridership_by_stop(&city.transit, route, &city.grid);
+
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+    let optimized = optimized_transit_guard
+        .as_ref()
+        .filter(|_| optimized_route_ids.contains(&route_id))
+        .and_then(|transit| {
+            let route = transit.routes.iter().find(|r| r.route_id == route_id)?;
+            Some(eval::ridership_by_stop(transit, route, &city.grid))
+        });
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "route_id": route_id,
+        "original": original,
+        "optimized": optimized,
+    }))
+}
+
+#[derive(Deserialize)]
+struct WalkCheckParams {
+    /// Largest allowed increase in meters, defaults to the ACO `max_walk_increase`
+    max_increase: Option<f64>,
+}
+
+/// Zones whose walk to their nearest stop grows by more than the allowed increase between
+/// the original route and its optimized version
+#[get("/walk-check/{route_id}")]
+async fn get_walk_check(
+    route_id: web::Path<String>,
+    query: web::Query<WalkCheckParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let route_id = route_id.into_inner();
+    println!("Checking walking distances of route {}", route_id);
+
+    let max_increase = query
+        .max_increase
+        .unwrap_or_else(|| data.aco_params.lock().unwrap().max_walk_increase);
+    if max_increase.is_nan() || max_increase < 0.0 {
+        return ServiceError::InvalidRequest(format!(
+            "max_increase must be at least 0, got {}",
+            max_increase
+        ))
+        .error_response();
+    }
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+    let route = match city.transit.routes.iter().find(|r| r.route_id == route_id) {
+        Some(route) => route,
+        None => {
+            return ServiceError::NotFound(format!("Route {} not found", route_id))
+                .error_response();
+        }
+    };
+
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+    let optimized = match optimized_transit_guard
+        .as_ref()
+        .filter(|_| optimized_route_ids.contains(&route_id))
+        .and_then(|transit| transit.routes.iter().find(|r| r.route_id == route_id))
+    {
+        Some(optimized) => optimized,
+        None => {
+            return ServiceError::NotFound(format!("Route {} has not been optimized", route_id))
+                .error_response();
+        }
+    };
+
+    let check =
+        WalkCheck::for_route_change(route, optimized, &city.transit, &city.grid, max_increase);
+    HttpResponse::Ok().json(serde_json::json!({
+        "route_id": route_id,
+        "passed": check.passed(),
+        "check": check,
+    }))
+}
+
+#[derive(Deserialize)]
+struct ValidateRouteParams {
+    /// Side of the road vehicles drive on, defaults to right
+    driving_side: Option<validation::DrivingSide>,
+}
+
+#[get("/validate-route/{route_id}")]
+async fn validate_route(
+    route_id: web::Path<String>,
+    query: web::Query<ValidateRouteParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let route_id = route_id.into_inner();
+    println!("Validating route: {}", route_id);
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+
+    let route = match city.transit.routes.iter().find(|r| r.route_id == route_id) {
+        Some(route) => route,
+        None => {
+            return ServiceError::NotFound(format!("Route {} not found", route_id))
+                .error_response();
+        }
+    };
+
+    let driving_side = query.driving_side.unwrap_or_default();
+    let original = validation::RouteValidation::for_route(route, &city.road, driving_side);
+
+    // Only validate the optimized route if it has been optimized
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+    let optimized = optimized_transit_guard
+        .as_ref()
+        .filter(|_| optimized_route_ids.contains(&route_id))
+        .and_then(|transit| transit.routes.iter().find(|r| r.route_id == route_id))
+        .map(|r| {
+            let exported = TransitNetwork::to_gtfs_filtered(vec![r], &city.gtfs, &city.road);
+            validation::RouteValidation::for_export(route, r, &exported, &city.road, driving_side)
+        });
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "route_id": route_id,
+        "original": original,
+        "optimized": optimized,
+    }))
+}
+
+#[get("/evaluate-coverage/{route_id}")]
+async fn evaluate_coverage(
+    route_id: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let route_id = route_id.into_inner();
+    println!(
+        "Evaluating coverage and economic score for route: {}",
+        route_id
+    );
+
+    let city_guard = data.city.read().unwrap();
+
+    if let Some(city) = &*city_guard {
+        let route = city.transit.routes.iter().find(|r| r.route_id == route_id);
+
+        if let Some(route) = route {
+            let coverage = route.evals.as_ref().unwrap().coverage;
+            let economic_score = route.evals.as_ref().unwrap().economic_score;
+
+            return HttpResponse::Ok().json(serde_json::json!({
+                "route_id": route_id,
+                "coverage": coverage,
+                "economic_score": economic_score,
+            }));
+        } else {
+            ServiceError::NotFound(format!("Route {} not found", route_id)).error_response()
+        }
+    } else {
+        ServiceError::CityNotLoaded.error_response()
+    }
+}
+
+#[get("/grid")]
+async fn get_grid(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting grid data");
+
+    let city_guard = data.city.read().unwrap();
+
+    if let Some(city) = &*city_guard {
+        // Create a simple array of zones with population and coordinates
+        let zones: Vec<serde_json::Value> = city
+            .grid
+            .graph
+            .node_indices()
+            .map(|ni| {
+                let zone = city.grid.get_zone(ni);
+                serde_json::json!({
+                    "POPULATION": zone.population,
+                    "COORDINATES": match zone.polygon.centroid() {
+                        Some(centroid) => [centroid.x(), centroid.y()],
+                        None => [0.0, 0.0], // Default coordinates if centroid is None
+                    }
+                })
+            })
+            .collect();
+
+        HttpResponse::Ok().json(zones)
+    } else {
+        ServiceError::CityNotLoaded.error_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct DesireLinesParams {
+    /// Leave out zone pairs with less demand
+    min_weight: Option<f64>,
+    /// Demand of one time period, e.g. `AmRush`, the whole day if omitted
+    period: Option<TimePeriod>,
+    /// Most lines to return, largest demand first, 500 if omitted
+    limit: Option<usize>,
+}
+
+/// Straight lines between the centroids of zone pairs weighted by their OD demand
+///
+/// Each line lists the routes of the original network serving both zones directly, lines
+/// with no direct route show corridors left unserved.
+#[get("/desire-lines")]
+async fn get_desire_lines(
+    query: web::Query<DesireLinesParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Getting desire lines");
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+
+    let min_weight = query.min_weight.unwrap_or(0.0);
+    let limit = query.limit.unwrap_or(500);
+    let lines = city.grid.desire_lines(query.period.as_ref(), min_weight);
+    let total_lines = lines.len();
+
+    // zones within walking distance of each route
+    let route_zones: Vec<(&str, HashSet<u32>)> = city
+        .transit
+        .routes
+        .iter()
+        .map(|route| {
+            let zones = route
+                .outbound_stops
+                .iter()
+                .chain(route.inbound_stops.iter())
+                .flat_map(|stop| stop.nearby_zone_ids().iter().copied())
+                .collect();
+            (route.route_id.as_str(), zones)
+        })
+        .collect();
+
+    let features: Vec<Value> = lines
+        .into_iter()
+        .take(limit)
+        .filter_map(|(from, to, weight)| {
+            let (origin, destination) = (city.grid.get_zone(from), city.grid.get_zone(to));
+            let (a, b) = (origin.polygon.centroid()?, destination.polygon.centroid()?);
+            let direct_routes: Vec<&str> = route_zones
+                .iter()
+                .filter(|(_, zones)| {
+                    zones.contains(&origin.zoneid) && zones.contains(&destination.zoneid)
+                })
+                .map(|(route_id, _)| *route_id)
+                .collect();
+            Some(serde_json::json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": [[a.x(), a.y()], [b.x(), b.y()]],
+                },
+                "properties": {
+                    "origin": origin.zoneid,
+                    "destination": destination.zoneid,
+                    "weight": weight,
+                    "served": !direct_routes.is_empty(),
+                    "direct_routes": direct_routes,
+                },
+            }))
+        })
+        .collect();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "type": "FeatureCollection",
+        "features": features,
+        "period": query.period,
+        "total_lines": total_lines,
+    }))
+}
+
+#[derive(Deserialize)]
+struct ZonesParams {
+    /// Only return zones intersecting `min_lon,min_lat,max_lon,max_lat`
+    bbox: Option<String>,
+    /// Map zoom level used to simplify the polygons, full detail if omitted
+    zoom: Option<u8>,
+    /// Only return zones with population
+    valid_only: Option<bool>,
+}
+
+#[get("/zones")]
+async fn get_zones(query: web::Query<ZonesParams>, data: web::Data<AppState>) -> impl Responder {
+    println!("Getting zones");
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+
+    let mut zones = match &query.bbox {
+        Some(bbox) => {
+            // every part must be a number, a bad one is not skipped
+            let coords: Result<Vec<f64>, _> = bbox.split(',').map(|c| c.trim().parse()).collect();
+            match coords.as_deref() {
+                Ok(&[min_lon, min_lat, max_lon, max_lat])
+                    if min_lon <= max_lon && min_lat <= max_lat =>
+                {
+                    city.grid
+                        .zones_in_bbox([min_lon, min_lat], [max_lon, max_lat])
+                }
+                _ => {
+                    return ServiceError::InvalidRequest(
+                        "bbox must be min_lon,min_lat,max_lon,max_lat".to_string(),
+                    )
+                    .error_response();
+                }
+            }
+        }
+        None => city.grid.graph.node_indices().collect(),
+    };
+    if query.valid_only.unwrap_or(false) {
+        zones.retain(|&z| city.grid.get_zone(z).valid_zone());
+    }
+    zones.sort_by_key(|&z| city.grid.get_zone(z).zoneid);
+
+    let tolerance = geojson::simplify_tolerance(query.zoom.unwrap_or(geojson::FULL_DETAIL_ZOOM));
+    let features: Vec<Value> = zones
+        .into_iter()
+        .map(|z| {
+            let zone = city.grid.get_zone(z);
+            let (demand_out, demand_in) = city.grid.demand_out_in(z);
+            serde_json::json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": geojson::polygon_coordinates(&zone.polygon, tolerance),
+                },
+                "properties": {
+                    "zoneid": zone.zoneid,
+                    "population": zone.population,
+                    "valid": zone.valid_zone(),
+                    "centroid": zone.polygon.centroid().map(|c| [c.x(), c.y()]),
+                    "demand_out": demand_out,
+                    "demand_in": demand_in,
+                },
+            })
+        })
+        .collect();
+
+    HttpResponse::Ok().json(geojson::convert_to_geojson(&features))
+}
+
+#[derive(Deserialize)]
+struct DemandHeatmapParams {
+    /// Demand of one time period, e.g. `am_rush`, the whole day if omitted
+    period: Option<TimePeriod>,
+    /// Also give the demand of every time period of each zone
+    breakdown: Option<bool>,
+    /// Map zoom level used to simplify the polygons, full detail if omitted
+    zoom: Option<u8>,
+}
+
+/// Zones with the travel demand leaving and arriving at them, for drawing demand heatmaps.
+/// `intensity` scales the total demand of a zone to that of the busiest zone.
+#[get("/demand-heatmap")]
+async fn get_demand_heatmap(
+    query: web::Query<DemandHeatmapParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Getting demand heatmap");
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+
+    let demand = city.grid.zone_demand();
+    let period = query.period.as_ref();
+    let max_demand = demand
+        .values()
+        .map(|d| {
+            let (demand_out, demand_in) = d.in_period(period);
+            demand_out + demand_in
+        })
+        .fold(0.0, f64::max);
+
+    let mut zones: Vec<_> = city.grid.graph.node_indices().collect();
+    zones.sort_by_key(|&z| city.grid.get_zone(z).zoneid);
+    let tolerance = geojson::simplify_tolerance(query.zoom.unwrap_or(geojson::FULL_DETAIL_ZOOM));
+    let features: Vec<Value> = zones
+        .into_iter()
+        .map(|z| {
+            let zone = city.grid.get_zone(z);
+            let zone_demand = demand.get(&z);
+            let (demand_out, demand_in) = zone_demand.map_or((0.0, 0.0), |d| d.in_period(period));
+            let total = demand_out + demand_in;
+            let mut properties = serde_json::json!({
+                "zoneid": zone.zoneid,
+                "population": zone.population,
+                "demand_out": demand_out,
+                "demand_in": demand_in,
+                "demand": total,
+                "intensity": if max_demand > 0.0 { total / max_demand } else { 0.0 },
+            });
+            if query.breakdown.unwrap_or(false) {
+                properties["by_period"] = serde_json::json!(zone_demand.map(|d| &d.by_period));
+            }
+            serde_json::json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": geojson::polygon_coordinates(&zone.polygon, tolerance),
+                },
+                "properties": properties,
+            })
+        })
+        .collect();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "type": "FeatureCollection",
+        "features": features,
+        "period": query.period,
+        "max_demand": max_demand,
+    }))
+}
+
+#[derive(Deserialize)]
+struct IsochroneParams {
+    /// Stop to walk from, only read by `/isochrones`
+    stop_id: Option<String>,
+    /// Comma separated walk times in minutes, 5,10,15 if omitted
+    minutes: Option<String>,
+    /// Walk from the stops of the optimized network instead of the original one
+    optimized: Option<bool>,
+    /// Map zoom level, polygons are simplified below full detail
+    zoom: Option<u8>,
+}
+
+/// Walk times of an isochrone request, sorted from the longest
+fn isochrone_minutes(minutes: Option<&str>) -> Result<Vec<u32>, String> {
+    let mut parsed = match minutes {
+        Some(minutes) => minutes
+            .split(',')
+            .map(|m| match m.trim().parse::<u32>() {
+                Ok(m) if (1..=60).contains(&m) => Ok(m),
+                _ => Err(format!(
+                    "Invalid walk time '{}', expected 1 to 60 minutes",
+                    m
+                )),
+            })
+            .collect::<Result<Vec<u32>, String>>()?,
+        None => isochrone::DEFAULT_MINUTES.to_vec(),
+    };
+    parsed.sort_unstable_by(|a, b| b.cmp(a));
+    parsed.dedup();
+    Ok(parsed)
+}
+
+/// Isochrones as polygon features, the longest walk of each stop first so that shorter ones
+/// are drawn over it
+fn isochrone_features(isochrones: &[isochrone::Isochrone], tolerance: f64) -> Vec<Value> {
+    isochrones
+        .iter()
+        .map(|isochrone| {
+            serde_json::json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": geojson::polygon_coordinates(&isochrone.polygon, tolerance),
+                },
+                "properties": isochrone,
+            })
+        })
+        .collect()
+}
+
+/// Areas within walking distance of a stop along the roads
+#[get("/isochrones")]
+async fn get_isochrones(
+    query: web::Query<IsochroneParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Getting isochrones of stop {:?}", query.stop_id);
+
+    let minutes = match isochrone_minutes(query.minutes.as_deref()) {
+        Ok(minutes) => minutes,
+        Err(e) => return ServiceError::InvalidRequest(e.to_string()).error_response(),
+    };
+    let stop_id = match &query.stop_id {
+        Some(stop_id) => stop_id,
+        None => {
+            return ServiceError::InvalidRequest("stop_id is required".to_string())
+                .error_response();
+        }
+    };
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let stop = [Some(&city.transit), optimized_transit_guard.as_ref()]
+        .into_iter()
+        .flatten()
+        .flat_map(|transit| &transit.routes)
+        .flat_map(|route| route.outbound_stops.iter().chain(&route.inbound_stops))
+        .find(|stop| &stop.stop_id == stop_id);
+    let stop = match stop {
+        Some(stop) => stop,
+        None => {
+            return ServiceError::NotFound(format!("Stop {} not found", stop_id)).error_response();
+        }
+    };
+
+    let isochrones = isochrone::stop_isochrones(stop, &city.road, &minutes);
+    let tolerance = geojson::simplify_tolerance(query.zoom.unwrap_or(geojson::FULL_DETAIL_ZOOM));
+    HttpResponse::Ok().json(geojson::convert_to_geojson(&isochrone_features(
+        &isochrones,
+        tolerance,
+    )))
+}
+
+/// Walk-shed of every stop of the network and the population within each walk time
+#[get("/coverage-isochrones")]
+async fn get_coverage_isochrones(
+    query: web::Query<IsochroneParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Getting coverage isochrones");
+
+    let minutes = match isochrone_minutes(query.minutes.as_deref()) {
+        Ok(minutes) => minutes,
+        Err(e) => return ServiceError::InvalidRequest(e.to_string()).error_response(),
+    };
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let transit = match &*optimized_transit_guard {
+        Some(optimized) if query.optimized.unwrap_or(false) => optimized,
+        _ => &city.transit,
+    };
+
+    let network = isochrone::network_isochrones(transit, &city.road, &city.grid, &minutes);
+    let tolerance = geojson::simplify_tolerance(query.zoom.unwrap_or(geojson::FULL_DETAIL_ZOOM));
+    let mut geojson =
+        geojson::convert_to_geojson(&isochrone_features(&network.isochrones, tolerance));
+    geojson["coverage"] = serde_json::json!(network.coverage);
+    HttpResponse::Ok().json(geojson)
+}
+
+#[derive(Deserialize)]
+struct PlanJourneyParams {
+    /// Origin as `lon,lat`
+    from: String,
+    /// Destination as `lon,lat`
+    to: String,
+    /// Earliest departure as `HH:MM` or `HH:MM:SS` local time, 08:00 if omitted
+    depart_at: Option<String>,
+    /// Plan over the optimized network instead of the original one
+    optimized: Option<bool>,
+    /// Most rides of a journey, from 1 to `MAX_JOURNEY_RIDES`
+    max_rides: Option<usize>,
+    /// Travel time a transfer is worth when journeys are ranked, in seconds
+    transfer_penalty_secs: Option<u32>,
+}
+
+/// Most rides a journey query may ask for
+const MAX_JOURNEY_RIDES: usize = 8;
+
+/// Point of a `lon,lat` query parameter
+fn parse_lon_lat(name: &str, value: &str) -> Result<geo_types::Point, String> {
+    let coords: Vec<f64> = value
+        .split(',')
+        .map(|c| c.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("{} must be lon,lat", name))?;
+    match coords[..] {
+        [lon, lat] if (-180.0..=180.0).contains(&lon) && (-90.0..=90.0).contains(&lat) => {
+            Ok(geo_types::Point::new(lon, lat))
+        }
+        _ => Err(format!("{} must be lon,lat", name)),
+    }
+}
+
+/// Plan journeys between two points over the timetable of the original or optimized network,
+/// to check how an optimization changes actual trips
+#[get("/plan-journey")]
+async fn plan_journey(
+    query: web::Query<PlanJourneyParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Planning a journey from {} to {}", query.from, query.to);
+
+    let (from, to) = match (
+        parse_lon_lat("from", &query.from),
+        parse_lon_lat("to", &query.to),
+    ) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => {
+            return ServiceError::InvalidRequest(e.to_string()).error_response();
+        }
+    };
+    let depart_at = match query.depart_at.as_deref() {
+        None => Some(8 * 3600),
+        Some(time) => parse_gtfs_time(time).or_else(|| parse_gtfs_time(&format!("{}:00", time))),
+    };
+    let Some(depart_at) = depart_at else {
+        return ServiceError::InvalidRequest("depart_at must be HH:MM or HH:MM:SS".to_string())
+            .error_response();
+    };
+    let max_rides = query.max_rides.unwrap_or(router::DEFAULT_MAX_RIDES);
+    if !(1..=MAX_JOURNEY_RIDES).contains(&max_rides) {
+        return ServiceError::InvalidRequest(format!(
+            "max_rides must be between 1 and {}",
+            MAX_JOURNEY_RIDES
+        ))
+        .error_response();
+    }
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+    let optimized = query.optimized.unwrap_or(false);
+    let router = {
+        let optimized_transit_guard = data.optimized_transit.read().unwrap();
+        let transit = match &*optimized_transit_guard {
+            Some(optimized_transit) if optimized => optimized_transit,
+            _ => &city.transit,
+        };
+        Router::new(transit, &city.gtfs, &city.road)
+    };
+
+    let journeys = router.plan(&JourneyQuery {
+        from,
+        to,
+        depart_at,
+        max_rides,
+        transfer_penalty_secs: query
+            .transfer_penalty_secs
+            .unwrap_or(router::DEFAULT_TRANSFER_PENALTY_SECS),
+    });
+    HttpResponse::Ok().json(serde_json::json!({
+        "network": if optimized { "optimized" } else { "original" },
+        "journeys": journeys,
+    }))
+}
+
+#[derive(Deserialize)]
+struct ServiceDensityParams {
+    /// `json` (default) or `geojson`
+    format: Option<String>,
+    /// Compute statistics for the optimized network instead of the original one
+    optimized: Option<bool>,
+}
+
+#[get("/service-density")]
+async fn get_service_density(
+    query: web::Query<ServiceDensityParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Getting service density");
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+
+    let densities = if query.optimized.unwrap_or(false) {
+        let optimized_transit_guard = data.optimized_transit.read().unwrap();
+        match optimized_transit_guard.as_ref() {
+            Some(transit) => eval::service_density(transit, &city.grid),
+            None => {
+                return ServiceError::NetworkNotLoaded.error_response();
+            }
+        }
+    } else {
+        eval::service_density(&city.transit, &city.grid)
+    };
+
+    match query.format.as_deref() {
+        None | Some("json") => HttpResponse::Ok().json(densities),
+        Some("geojson") => {
+            let features: Vec<Value> = densities
+                .iter()
+                .map(|d| {
+                    let zone = city.grid.get_zone(city.grid.get_zone_idx_by_id(d.zoneid));
+                    let rings = geojson::polygon_coordinates(&zone.polygon, 0.0);
+                    serde_json::json!({
+                        "type": "Feature",
+                        "geometry": {
+                            "type": "Polygon",
+                            "coordinates": rings,
+                        },
+                        "properties": d,
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(geojson::convert_to_geojson(&features))
+        }
+        Some(other) => ServiceError::InvalidRequest(format!(
+            "Unknown format '{}', expected 'json' or 'geojson'",
+            other
+        ))
+        .error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct PoiAccessParams {
+    /// Travel time budget in minutes, defaults to 30
+    minutes: Option<f64>,
+    /// Only count points of interest of this category
+    category: Option<String>,
+}
+
+#[get("/poi-access")]
+async fn get_poi_access(
+    query: web::Query<PoiAccessParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Getting POI access");
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+
+    let minutes = query.minutes.unwrap_or(30.0);
+    if !minutes.is_finite() || minutes <= 0.0 {
+        return ServiceError::InvalidRequest("minutes must be positive".to_string())
+            .error_response();
+    }
+
+    let count = |access: &accessibility::ZonePoiAccess| match &query.category {
+        Some(category) => access.reachable.get(category).copied().unwrap_or(0),
+        None => access.total,
+    };
+
+    let before = accessibility::poi_access(&city.transit, &city.grid, minutes);
+    let after = data
+        .optimized_transit
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|transit| accessibility::poi_access(transit, &city.grid, minutes));
+
+    // both lists cover the same zones in the same order
+    let zones: Vec<Value> = before
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            serde_json::json!({
+                "zoneid": b.zoneid,
+                "before": count(b),
+                "after": after.as_ref().map(|a| count(&a[i])),
+            })
+        })
+        .collect();
+    let total_before: u64 = before.iter().map(|z| count(z) as u64).sum();
+    let total_after: Option<u64> = after
+        .as_ref()
+        .map(|a| a.iter().map(|z| count(z) as u64).sum());
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "minutes": minutes,
+        "category": query.category,
+        "total_before": total_before,
+        "total_after": total_after,
+        "zones": zones,
+    }))
+}
+
+#[derive(Deserialize)]
+struct JobAccessParams {
+    /// Travel time budget in minutes, defaults to 45
+    minutes: Option<f64>,
+}
+
+/// Jobs reachable by transit from every populated zone, before and after optimization
+#[get("/job-access")]
+async fn get_job_access(
+    query: web::Query<JobAccessParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Getting job access");
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+
+    if !city.grid.has_jobs() {
+        return ServiceError::NotFound("The city database has no job data".to_string())
+            .error_response();
+    }
+
+    let minutes = query.minutes.unwrap_or(accessibility::JOB_ACCESS_MINUTES);
+    if !minutes.is_finite() || minutes <= 0.0 {
+        return ServiceError::InvalidRequest("minutes must be positive".to_string())
+            .error_response();
+    }
+
+    let before = accessibility::job_access(&city.transit, &city.grid, minutes);
+    let after = data
+        .optimized_transit
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|transit| accessibility::job_access(transit, &city.grid, minutes));
+
+    // both lists cover the same zones in the same order
+    let zones: Vec<Value> = before
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            serde_json::json!({
+                "zoneid": b.zoneid,
+                "population": b.population,
+                "before": b.jobs_reachable,
+                "after": after.as_ref().map(|a| a[i].jobs_reachable),
+            })
+        })
+        .collect();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "minutes": minutes,
+        "avg_jobs_reachable_before": accessibility::avg_jobs_reachable(&before),
+        "avg_jobs_reachable_after": after.as_deref().map(accessibility::avg_jobs_reachable),
+        "zones": zones,
+    }))
+}
+
+#[derive(Deserialize)]
+struct UploadGtfsParams {
+    /// Tag to store the feed under, defaults to the upload time
+    version: Option<String>,
+    /// Rebuild the transit network from the feed once it validates
+    reload: Option<bool>,
+}
+
+/// Upload a GTFS zip as the raw request body, with a content length or chunked.
+///
+/// The feed is stored in the city's feed versions directory and validated by loading it
+/// the same way the city does on startup. Feeds that fail to load are discarded, as are
+/// archives extracting to more than `feeds::MAX_EXTRACTED_BYTES`.
+#[post("/upload-gtfs")]
+async fn upload_gtfs(
+    query: web::Query<UploadGtfsParams>,
+    mut payload: web::Payload,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let version = query
+        .version
+        .clone()
+        .unwrap_or_else(|| chrono::Local::now().format("%Y%m%d-%H%M%S").to_string());
+    println!("Uploading GTFS feed version {}", version);
+
+    if !feeds::is_valid_version(&version) {
+        return ServiceError::InvalidRequest(format!("Invalid version tag '{}'", version))
+            .error_response();
+    }
+
+    let store = feeds::FeedStore::new(&data.gtfs_path);
+    let versions_dir = store.dir();
+    let feed_dir = versions_dir.join(&version);
+    if feed_dir.exists() {
+        return ServiceError::Conflict(format!("Feed version '{}' already exists", version))
+            .error_response();
+    }
+
+    // Stream the archive to disk
+    let zip_path = versions_dir.join(format!(".{}.zip.part", version));
+    let mut file = match std::fs::create_dir_all(versions_dir)
+        .and_then(|_| std::fs::File::create(&zip_path))
+    {
+        Ok(file) => file,
+        Err(e) => {
+            return ServiceError::Internal(format!("Failed to store upload: {}", e))
+                .error_response();
+        }
+    };
+    let mut size = 0;
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                std::fs::remove_file(&zip_path).ok();
+                return ServiceError::InvalidRequest(format!("Failed to read upload: {}", e))
+                    .error_response();
+            }
+        };
+        size += chunk.len();
+        if size > feeds::MAX_UPLOAD_BYTES {
+            std::fs::remove_file(&zip_path).ok();
+            return ServiceError::PayloadTooLarge(format!(
+                "Upload exceeds {} bytes",
+                feeds::MAX_UPLOAD_BYTES
+            ))
+            .error_response();
+        }
+        if let Err(e) = std::io::Write::write_all(&mut file, &chunk) {
+            std::fs::remove_file(&zip_path).ok();
+            return ServiceError::Internal(format!("Failed to store upload: {}", e))
+                .error_response();
+        }
+    }
+    drop(file);
+
+    // Extract and validate the feed by loading it like the city would, off the async workers
+    let feed_path = feed_dir.to_string_lossy().to_string();
+    let (extract_dir, db_path) = (feed_dir.clone(), data.db_path.clone());
+    let loaded = web::block(move || {
+        let extracted = feeds::extract_zip(&zip_path, &extract_dir);
+        std::fs::remove_file(&zip_path).ok();
+        match extracted {
+            Ok(()) => {}
+            Err(e @ GtfsError::ArchiveTooLarge(_)) => {
+                return Err(ServiceError::PayloadTooLarge(e.to_string()))
+            }
+            Err(e) => {
+                return Err(ServiceError::InvalidRequest(format!(
+                    "Invalid GTFS archive: {}",
+                    e
+                )))
+            }
+        }
+        match City::load_gtfs(&extract_dir.to_string_lossy(), &db_path) {
+            Ok(loaded) if !loaded.0.routes.is_empty() => Ok(loaded),
+            Ok(_) => Err(ServiceError::InvalidRequest(
+                "GTFS feed has no routes within the city boundary".to_string(),
+            )),
+            Err(e) => Err(ServiceError::InvalidRequest(format!(
+                "Invalid GTFS feed: {}",
+                e
+            ))),
+        }
+    })
+    .await
+    .unwrap_or_else(|e| Err(ServiceError::Internal(e.to_string())));
+    let (gtfs, import_report) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            std::fs::remove_dir_all(&feed_dir).ok();
+            return e.error_response();
+        }
+    };
+    let feed = feeds::FeedVersion {
+        version: version.clone(),
+        uploaded_at: Some(chrono::Local::now().to_rfc3339()),
+        feed_info: gtfs.feed_info.clone(),
+        summary: Some(feeds::FeedSummary::new(&gtfs, &import_report)),
+    };
+    if let Err(e) = store.save(&feed) {
+        std::fs::remove_dir_all(&feed_dir).ok();
+        return ServiceError::Internal(format!("Failed to store feed metadata: {}", e))
+            .error_response();
+    }
+
+    let reloaded = if query.reload.unwrap_or(false) {
+        let (swapped, swap_report, swap_path) =
+            (data.clone(), import_report.clone(), feed_path.clone());
+        let activated = web::block(move || swap_city_feed(&swapped, gtfs, swap_report, &swap_path))
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()))
+            .and_then(|_| {
+                store
+                    .set_active(&version)
+                    .map_err(|e| format!("Feed activated but failed to record it: {}", e))
+            });
+        if let Err(e) = activated {
+            return ServiceError::Internal(e.to_string())
+                .with_details(serde_json::json!({ "version": version }));
+        }
+        true
+    } else {
+        false
+    };
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "version": version,
+        "path": feed_path,
+        "size_bytes": size,
+        "feed": feed,
+        "import_report": import_report,
+        "reloaded": reloaded,
+    }))
+}
+
+/// Replace the city's feed with a validated one, loaded from `gtfs_path`.
+///
+/// The transit network is rebuilt before anything is replaced, so the city keeps serving
+/// the current feed if the rebuild fails. Optimizations are reset and the inactive
+/// workspaces dropped since they refer to the routes of the previous feed.
+fn swap_city_feed(
+    data: &AppState,
+    gtfs: Gtfs,
+    import_report: ImportReport,
+    gtfs_path: &str,
+) -> Result<(), String> {
+    let mut city_guard = data.city.write().unwrap();
+    let city = city_guard
+        .as_mut()
+        .ok_or_else(|| "City data not loaded".to_string())?;
+    city.replace_gtfs(gtfs, import_report, gtfs_path)
+        .map_err(|e| format!("Failed to rebuild transit network: {}", e))?;
+
+    data.workspaces.lock().unwrap().clear();
+    *data.optimized_transit.write().unwrap() = Some(city.transit.clone());
+    data.optimized_route_ids.lock().unwrap().clear();
+    data.noop_route_ids.lock().unwrap().clear();
+    data.route_reviews.lock().unwrap().clear();
+    data.route_pheromones.lock().unwrap().clear();
+    data.route_history.lock().unwrap().clear();
+    Ok(())
+}
+
+#[get("/feeds")]
+async fn get_feeds(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting feed versions");
+
+    let store = feeds::FeedStore::new(&data.gtfs_path);
+    HttpResponse::Ok().json(serde_json::json!({
+        "active": store.active(),
+        "versions": store.list(),
+    }))
+}
+
+#[post("/feeds/{version}/activate")]
+async fn activate_feed_version(
+    version: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let version = version.into_inner();
+    println!("Activating feed version {}", version);
+
+    let store = feeds::FeedStore::new(&data.gtfs_path);
+    let active = store.active();
+    if version == active {
+        return HttpResponse::Ok().json(serde_json::json!({
+            "active": active,
+            "message": "Feed version already active"
+        }));
+    }
+    let (gtfs_path, gtfs, import_report) = match load_feed_version(&data, &store, &version) {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    if let Err(e) = swap_city_feed(&data, gtfs, import_report, &gtfs_path) {
+        return ServiceError::Internal(e.to_string())
+            .with_details(serde_json::json!({ "active": active }));
+    }
+    if let Err(e) = store.set_active(&version) {
+        return ServiceError::Internal(format!("Feed activated but failed to record it: {}", e))
+            .error_response();
+    }
+    HttpResponse::Ok().json(serde_json::json!({
+        "active": version,
+        "previous": active,
+    }))
+}
+
+/// Reactivate the feed version that was active before the current one
+#[post("/feeds/rollback")]
+async fn rollback_feed(data: web::Data<AppState>) -> impl Responder {
+    println!("Rolling back feed version");
+
+    let store = feeds::FeedStore::new(&data.gtfs_path);
+    let active = store.active();
+    let previous = match store.previous() {
+        Some(previous) => previous,
+        None => {
+            return ServiceError::InvalidRequest(
+                "No previous feed version to roll back to".to_string(),
+            )
+            .with_details(serde_json::json!({ "active": active }));
+        }
+    };
+
+    let (gtfs_path, gtfs, import_report) = match load_feed_version(&data, &store, &previous) {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    // The history is only updated once the city serves the previous feed,
+    // so a failed rollback leaves both untouched
+    if let Err(e) = swap_city_feed(&data, gtfs, import_report, &gtfs_path) {
+        return ServiceError::Internal(e.to_string())
+            .with_details(serde_json::json!({ "active": active }));
+    }
+
+    if let Err(e) = store.pop_active() {
+        return ServiceError::Internal(format!("Feed rolled back but failed to record it: {}", e))
+            .error_response();
+    }
+    HttpResponse::Ok().json(serde_json::json!({
+        "active": previous,
+        "previous": active,
+    }))
+}
+
+/// Load and validate a stored feed version with the path it is loaded from, returning an
+/// error response if it fails
+fn load_feed_version(
+    data: &AppState,
+    store: &feeds::FeedStore,
+    version: &str,
+) -> Result<(String, Gtfs, ImportReport), HttpResponse> {
+    let path = store.path(version).ok_or_else(|| {
+        ServiceError::NotFound(format!("Feed version '{}' not found", version)).error_response()
+    })?;
+    let path = path.to_string_lossy().to_string();
+    let (gtfs, import_report) = City::load_gtfs(&path, &data.db_path).map_err(|e| {
+        ServiceError::InvalidRequest(format!("Feed version '{}' failed to load: {}", version, e))
+            .with_details(serde_json::json!({ "active": store.active() }))
+    })?;
+    Ok((path, gtfs, import_report))
+}
+
+/// Routes of the feed left out of the transit network and why, with the route counts they
+/// explain
+#[get("/dropped-routes")]
+async fn get_dropped_routes(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting dropped routes");
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let routes: Vec<Value> = city
+        .transit
+        .dropped_routes
+        .iter()
+        .map(|dropped| {
+            let route = city.gtfs.routes.get(&dropped.route_id);
+            serde_json::json!({
+                "route_id": dropped.route_id,
+                "route_short_name": route.and_then(|r| r.route_short_name.clone()),
+                "route_long_name": route.and_then(|r| r.route_long_name.clone()),
+                "reason": dropped.reason,
+                "trips": dropped.trips,
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({
+        "feed_routes": city.gtfs.routes.len(),
+        "network_routes": city.transit.routes.len(),
+        "counts": city.transit.dropped_route_counts(),
+        "routes": routes,
+    }))
+}
+
+#[get("/import-report")]
+async fn get_import_report(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting import report");
+
+    let city_guard = data.city.read().unwrap();
+
+    if let Some(city) = &*city_guard {
+        HttpResponse::Ok().json(&city.import_report)
+    } else {
+        ServiceError::CityNotLoaded.error_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct ValidateGtfsParams {
+    /// `feed` to check the source feed, `optimized` the feed generated from the optimized
+    /// network
+    source: Option<String>,
+    /// Most issues listed, all are counted
+    limit: Option<usize>,
+}
+
+/// Most issues listed by `/validate-gtfs` unless a limit is given
+const DEFAULT_VALIDATION_LIMIT: usize = 100;
+
+/// Consistency of the source feed or of the feed generated from the optimized network, see
+/// `validator::validate`
+#[get("/validate-gtfs")]
+async fn validate_gtfs(
+    query: web::Query<ValidateGtfsParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Validating GTFS");
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let source = query.source.as_deref().unwrap_or("feed");
+    let mut report = match source {
+        "feed" => match city.full_gtfs() {
+            Ok(gtfs) => validator::validate(gtfs),
+            Err(e) => {
+                return ServiceError::Internal(format!("Failed to load GTFS: {}", e))
+                    .error_response();
+            }
+        },
+        "optimized" => {
+            let optimized_transit_guard = data.optimized_transit.read().unwrap();
+            let Some(optimized) = &*optimized_transit_guard else {
+                return ServiceError::NetworkNotLoaded.error_response();
+            };
+            validator::validate(&optimized.to_gtfs(&city.gtfs, &city.road))
+        }
+        _ => {
+            return ServiceError::InvalidRequest(format!(
+                "Unknown source {}, expected feed or optimized",
+                source
+            ))
+            .error_response();
+        }
+    };
+    let total = report.issues.len();
+    report
+        .issues
+        .truncate(query.limit.unwrap_or(DEFAULT_VALIDATION_LIMIT));
+    HttpResponse::Ok().json(serde_json::json!({
+        "source": source,
+        "valid": total == 0,
+        "total_issues": total,
+        "counts": report.counts,
+        "issues": report.issues,
+    }))
+}
+
+/// Feed versions, modification times of the source and cache files and the parameters the
+/// transit network was built with, to tell whether results are based on stale data
+#[get("/data-info")]
+async fn get_data_info(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting data info");
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let store = feeds::FeedStore::new(&data.gtfs_path);
+    let active_feed = store.active();
+    let active_feed_path = store
+        .path(&active_feed)
+        .map(|p| p.to_string_lossy().to_string());
+    HttpResponse::Ok().json(serde_json::json!({
+        "info": city.data_info(),
+        "active_feed": active_feed,
+        "active_feed_path": active_feed_path,
+    }))
+}
+
+/// Memory used by the server and how much of the road and grid networks it keeps in memory
+#[get("/debug/memory")]
+async fn get_debug_memory(data: web::Data<AppState>) -> impl Responder {
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    HttpResponse::Ok().json(city.memory_report())
+}
+
+/// Metrics of the server and the optimizer in the Prometheus text format, for scraping
+#[get("/metrics")]
+async fn get_metrics(data: web::Data<AppState>) -> impl Responder {
+    let mut out = String::new();
+    data.request_metrics.write(&mut out);
+    OPTIMIZER_METRICS.write(&mut out);
+    let name = "route_service_websocket_sessions";
+    write_header(&mut out, name, "gauge", "Running optimize-live sessions");
+    out.push_str(&format!(
+        "{} {}\n",
+        name,
+        data.live_sessions.lock().unwrap().len()
+    ));
+    if let Some(city) = &*data.city.read().unwrap() {
+        metrics::write_memory(&mut out, &city.name, &city.memory_report());
+    }
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(out)
+}
+
+/// Size and evictions of the stores the server keeps between requests
+#[get("/debug/stores")]
+async fn get_debug_stores(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "gc_interval_secs": STORE_GC_INTERVAL.as_secs(),
+        "stores": data.store_stats(),
+    }))
+}
+
+#[get("/avg-transfers")]
+async fn get_avg_transfers(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting average transfers");
+
+    let city_guard = data.city.read().unwrap();
+
+    if let Some(city) = &*city_guard {
+        println!("Computing new transfers data");
+        let optimized_transit_guard = data.optimized_transit.read().unwrap();
+        let optimized_transit = optimized_transit_guard.as_ref().unwrap();
+        if optimized_transit.evals.is_none() {
+            return ServiceError::NetworkNotLoaded.error_response();
+        }
+
+        let evals = optimized_transit.evals.as_ref().unwrap();
+
+        let (avg_transfers, zone_transfers) = (evals.avg_transfers, &evals.zone_to_transfers);
+
+        println!("Average transfers: {}", avg_transfers);
+
+        // Convert zone transfers to JSON
+        let zone_transfers_json: Vec<serde_json::Value> = zone_transfers
+            .iter()
+            .map(|(ni, transfers)| {
+                let zone = city.grid.get_zone(*ni);
+                serde_json::json!({
+                    "TRANSFERS": transfers,
+                    "COORDINATES": match zone.polygon.centroid() {
+                        Some(centroid) => [centroid.x(), centroid.y()],
+                        None => [0.0, 0.0],
+                    }
+                })
+            })
+            .collect();
+
+        HttpResponse::Ok().json(serde_json::json!({
+            "average_transfers": avg_transfers,
+            "average_transfer_wait": evals.avg_transfer_wait,
+            "average_impedance": evals.avg_impedance,
+            "zone_transfers": zone_transfers_json
+        }))
+    } else {
+        ServiceError::CityNotLoaded.error_response()
+    }
+}
+
+/// Reset the optimizations of the active workspace, the inactive ones are kept
+#[post("/reset-optimizations")]
+async fn reset_optimizations(data: web::Data<AppState>) -> impl Responder {
+    println!("Resetting all route optimizations");
+
+    let city_guard = data.city.read().unwrap();
+    if let Some(city) = &*city_guard {
+        let workspace = data.workspaces.lock().unwrap().active().to_string();
+        data.route_history
+            .lock()
+            .unwrap()
+            .remove_workspace(&workspace);
+
+        // Reset the optimized transit to original state
+        {
+            let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+            *optimized_transit_guard = Some(city.transit.clone());
+        }
+
+        // Clear the list of optimized route IDs
+        {
+            let mut optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+            optimized_route_ids.clear();
+        }
+
+        // Clear the list of noop route IDs
+        {
+            let mut noop_route_ids = data.noop_route_ids.lock().unwrap();
+            noop_route_ids.clear();
+        }
+
+        data.route_reviews.lock().unwrap().clear();
+        data.route_pheromones.lock().unwrap().clear();
+
+        return HttpResponse::Ok().json(serde_json::json!({
+            "message": "All route optimizations reset"
+        }));
+    }
+
+    ServiceError::CityNotLoaded.error_response()
+}
+
+#[derive(Deserialize)]
+struct OverlayParams {
+    /// Comma separated route ids, defaults to all optimized routes
+    routes: Option<String>,
+    /// Map zoom level used to simplify the geometry, full detail if omitted
+    zoom: Option<u8>,
+}
+
+#[get("/overlay")]
+async fn get_overlay(
+    query: web::Query<OverlayParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Fetching original and optimized overlay");
+
+    let optimized_route_ids = data.optimized_route_ids.lock().unwrap().clone();
+    let route_ids: Vec<String> = match &query.routes {
+        Some(routes) => routes
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect(),
+        None => optimized_route_ids.clone(),
+    };
+
+    let city_guard = data.city.read().unwrap();
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+
+    if let (Some(city), Some(optimized_transit)) = (&*city_guard, &*optimized_transit_guard) {
+        let tolerance =
+            geojson::simplify_tolerance(query.zoom.unwrap_or(geojson::FULL_DETAIL_ZOOM));
+
+        let original_routes = city
+            .transit
+            .routes
+            .iter()
+            .filter(|r| route_ids.contains(&r.route_id))
+            .collect::<Vec<&TransitRoute>>();
+        let mut features = geojson::get_all_features(&TransitNetwork::to_gtfs_copy(
+            original_routes,
+            &city.gtfs,
+            &city.road,
+        ));
+        geojson::tag_and_simplify_features(&mut features, "original", tolerance);
+
+        let reviews = data.route_reviews.lock().unwrap();
+        let visible_route_ids = reviews.visible(&optimized_route_ids, true);
+        let optimized_routes = optimized_transit
+            .routes
+            .iter()
+            .filter(|r| route_ids.contains(&r.route_id) && visible_route_ids.contains(&r.route_id))
+            .collect::<Vec<&TransitRoute>>();
+        let mut optimized_features = geojson::get_all_features(&TransitNetwork::to_gtfs_filtered(
+            optimized_routes,
+            &city.gtfs,
+            &city.road,
+        ));
+        geojson::tag_and_simplify_features(&mut optimized_features, "optimized", tolerance);
+        tag_review_states(&mut optimized_features, &reviews);
+        features.extend(optimized_features);
+
+        stream_geojson(features, None)
+    } else {
+        ServiceError::CityNotLoaded.error_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct WorkspaceParams {
+    /// Workspace to read, the active one if missing
+    workspace: Option<String>,
+}
+
+#[get("/get-optimizations")]
+async fn get_optimizations(
+    query: web::Query<WorkspaceParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Fetching optimized routes");
+
+    // Access the city data (for gtfs and road network)
+    let city_guard = data.city.read().unwrap();
+    let mut workspaces = data.workspaces.lock().unwrap();
+    let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+    let mut optimized_route_ids_guard = data.optimized_route_ids.lock().unwrap();
+
+    if let (Some(city), Some(active_transit)) = (&*city_guard, optimized_transit_guard.as_mut()) {
+        let workspace = query
+            .workspace
+            .as_deref()
+            .unwrap_or(workspaces.active())
+            .to_string();
+        let (optimized_transit, optimized_route_ids) = match workspaces.get_mut(
+            Some(&workspace),
+            active_transit,
+            &mut optimized_route_ids_guard,
+        ) {
+            Ok(workspace) => workspace,
+            Err(e) => return ServiceError::NotFound(e.to_string()).error_response(),
+        };
+
+        if optimized_route_ids.is_empty() {
+            return HttpResponse::Ok().json(serde_json::json!({
+                "message": "No routes have been optimized yet",
+                "workspace": workspace,
+                "features": []
             }));
-        } else {
-            HttpResponse::NotFound().json(serde_json::json!({
-                "error": format!("Route {} not found", route_id)
+        }
+
+        let features = get_optimized_features(
+            city,
+            optimized_transit,
+            optimized_route_ids,
+            &data.route_reviews.lock().unwrap(),
+        );
+        let fields = serde_json::json!({
+            "message": format!("Found {} optimized routes", optimized_route_ids.len()),
+            "workspace": workspace,
+            "routes": optimized_route_ids,
+        });
+        stream_geojson(features, Some((fields, "geojson")))
+    } else {
+        ServiceError::CityNotLoaded.error_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateWorkspaceRequest {
+    name: String,
+    /// Workspace whose network the new one starts from, the original network if missing
+    from: Option<String>,
+}
+
+/// Create a workspace, an optimized network kept in memory besides the active one
+///
+/// Unlike `/save-scenario`, nothing is written to disk: workspaces let planners compare
+/// alternative optimizations of the city without resetting each other's work.
+#[post("/workspaces")]
+async fn create_workspace(
+    request: web::Json<CreateWorkspaceRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Creating workspace {}", request.name);
+
+    let city_guard = data.city.read().unwrap();
+    let mut workspaces = data.workspaces.lock().unwrap();
+    let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+    let mut optimized_route_ids_guard = data.optimized_route_ids.lock().unwrap();
+    let (city, active_transit) = match (&*city_guard, optimized_transit_guard.as_mut()) {
+        (Some(city), Some(active_transit)) => (city, active_transit),
+        _ => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+
+    if workspaces.contains(&request.name) {
+        return ServiceError::Conflict(format!("Workspace {} already exists", request.name))
+            .error_response();
+    }
+    let network = match &request.from {
+        Some(from) => {
+            match workspaces.get_mut(Some(from), active_transit, &mut optimized_route_ids_guard) {
+                Ok((network, optimized_routes)) => aco2::OptimizedTransitNetwork {
+                    network: network.clone(),
+                    optimized_routes: optimized_routes.clone(),
+                },
+                Err(e) => return ServiceError::NotFound(e.to_string()).error_response(),
+            }
+        }
+        None => aco2::OptimizedTransitNetwork {
+            network: city.transit.clone(),
+            optimized_routes: vec![],
+        },
+    };
+    let optimized_routes = network.optimized_routes.len();
+    if let Err(e) = workspaces.create(&request.name, network) {
+        return ServiceError::InvalidRequest(e.to_string()).error_response();
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "message": format!("Created workspace {}", request.name),
+        "name": request.name,
+        "from": request.from,
+        "optimized_routes": optimized_routes,
+    }))
+}
+
+#[get("/workspaces")]
+async fn get_workspaces(data: web::Data<AppState>) -> impl Responder {
+    println!("Listing workspaces");
+
+    let workspaces = data.workspaces.lock().unwrap();
+    let optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+    HttpResponse::Ok().json(serde_json::json!({
+        "active": workspaces.active(),
+        "workspaces": workspaces.list(&optimized_route_ids),
+    }))
+}
+
+/// Make a workspace the active one, whose network every endpoint reads and optimizes by
+/// default. The previously active network is kept as an inactive workspace.
+#[post("/workspaces/{name}/activate")]
+async fn activate_workspace(name: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let name = name.into_inner();
+    println!("Activating workspace {}", name);
+
+    let mut workspaces = data.workspaces.lock().unwrap();
+    let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+    let mut optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+    let Some(network) = optimized_transit_guard.take() else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let mut active = aco2::OptimizedTransitNetwork {
+        network,
+        optimized_routes: std::mem::take(&mut *optimized_route_ids),
+    };
+    let activated = workspaces.activate(&name, &mut active);
+    *optimized_transit_guard = Some(active.network);
+    *optimized_route_ids = active.optimized_routes;
+    if let Err(e) = activated {
+        return ServiceError::NotFound(e).error_response();
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "message": format!("Activated workspace {}", name),
+        "active": workspaces.active(),
+        "optimized_routes": *optimized_route_ids,
+    }))
+}
+
+#[delete("/workspaces/{name}")]
+async fn delete_workspace(name: web::Path<String>, data: web::Data<AppState>) -> impl Responder {
+    let name = name.into_inner();
+    println!("Deleting workspace {}", name);
+
+    let mut workspaces = data.workspaces.lock().unwrap();
+    if name == workspaces.active() {
+        return ServiceError::Conflict(format!(
+            "Workspace {} is active, activate another one first",
+            name
+        ))
+        .error_response();
+    }
+    match workspaces.remove(&name) {
+        Ok(_) => {
+            data.route_history.lock().unwrap().remove_workspace(&name);
+            HttpResponse::Ok().json(serde_json::json!({
+                "message": format!("Deleted workspace {}", name)
             }))
         }
+        Err(e) => ServiceError::NotFound(e.to_string()).error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RouteIdParams {
+    route_ids: String, // Comma-separated list of route IDs
+}
+
+#[derive(Deserialize)]
+struct OptimizeLiveParams {
+    route_ids: Option<String>, // Comma-separated list of route IDs
+    /// Optimize the next routes of the optimization queue instead of `route_ids`
+    from_queue: Option<usize>,
+    /// Carry on from the checkpoint of an earlier session on the same routes
+    #[serde(default)]
+    resume: bool,
+}
+
+#[get("/optimize-live")]
+async fn optimize_live(
+    req: HttpRequest,
+    stream: web::Payload,
+    query: web::Query<OptimizeLiveParams>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let route_ids: Vec<String> = match (&query.route_ids, query.from_queue) {
+        // Parse comma-separated route IDs
+        (Some(route_ids), None) => route_ids
+            .split(',')
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .collect(),
+        (None, Some(count)) => {
+            let city_guard = data.city.read().unwrap();
+            let Some(city) = &*city_guard else {
+                return Ok(ServiceError::CityNotLoaded.error_response());
+            };
+            let optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+            data.optimization_queue
+                .lock()
+                .unwrap()
+                .entries(&city.transit, &optimized_route_ids)
+                .into_iter()
+                .take(count)
+                .map(|e| e.route_id)
+                .collect()
+        }
+        _ => {
+            return Ok(ServiceError::InvalidRequest(
+                "Provide either route_ids or from_queue".to_string(),
+            )
+            .error_response());
+        }
+    };
+
+    println!(
+        "WebSocket connection request for optimize-live with routes {:?}",
+        route_ids
+    );
+
+    if route_ids.is_empty() {
+        return Ok(
+            ServiceError::InvalidRequest("No valid route IDs provided".to_string())
+                .error_response(),
+        );
+    }
+
+    let ws = if query.resume {
+        let city_name = match &*data.city.read().unwrap() {
+            Some(city) => city.name.clone(),
+            None => {
+                return Ok(ServiceError::CityNotLoaded.error_response());
+            }
+        };
+        let checkpoint = match City::load_checkpoint(&city_name, &Checkpoint::live_name(&route_ids))
+        {
+            Ok(checkpoint) => checkpoint,
+            Err(_) => {
+                return Ok(ServiceError::NotFound(format!(
+                    "No checkpoint of a live session on routes {:?}",
+                    route_ids
+                ))
+                .error_response());
+            }
+        };
+        match OptimizationWs::resume(data.clone(), route_ids, checkpoint) {
+            Ok(ws) => ws,
+            Err(e) => {
+                return Ok(ServiceError::InvalidRequest(e.to_string()).error_response());
+            }
+        }
     } else {
-        HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "City data not loaded"
+        OptimizationWs::new(data.clone(), route_ids)
+    };
+    ws::start(ws, &req, stream)
+}
+
+/// Change the ACO parameters of a running optimize-live session, from its next iteration on.
+/// The parameters of the server and of the other sessions are left as they are.
+#[post("/optimize-live/{session_id}/params")]
+async fn update_live_params(
+    session_id: web::Path<u64>,
+    params: web::Json<aco2::PartialACO>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let session_id = session_id.into_inner();
+    println!("Updating parameters of live session {}", session_id);
+
+    let session = data.live_sessions.lock().unwrap().get(&session_id).cloned();
+    let Some(session) = session else {
+        return ServiceError::NotFound(format!("Live session {} not found", session_id))
+            .error_response();
+    };
+    let update = UpdateParams {
+        changes: params.into_inner(),
+        source: "rest",
+    };
+    match session.send(update).await {
+        Ok(Ok(change)) => HttpResponse::Ok().json(serde_json::json!({
+            "session_id": session_id,
+            "change": change,
+        })),
+        Ok(Err(e)) => ServiceError::InvalidRequest(e.to_string()).error_response(),
+        Err(_) => ServiceError::NotFound(format!("Live session {} has ended", session_id))
+            .error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct QueueParams {
+    /// Only return the first entries
+    limit: Option<usize>,
+}
+
+/// Bus routes that have not been optimized yet, pinned routes first and then the worst
+/// routes by badness score
+#[get("/optimization-queue")]
+async fn get_optimization_queue(
+    query: web::Query<QueueParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Getting optimization queue");
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+    let queue = data.optimization_queue.lock().unwrap();
+    let mut entries = queue.entries(&city.transit, &optimized_route_ids);
+    let total = entries.len();
+    if let Some(limit) = query.limit {
+        entries.truncate(limit);
+    }
+    HttpResponse::Ok().json(serde_json::json!({
+        "weights": queue.weights,
+        "pinned": queue.pinned(),
+        "total": total,
+        "routes": entries,
+    }))
+}
+
+#[derive(Deserialize)]
+struct QueueUpdate {
+    /// Routes pinned to the front of the queue in order, replacing the pinned routes
+    pinned: Option<Vec<String>>,
+    /// Weights of the badness score
+    weights: Option<BadnessWeights>,
+}
+
+/// Pin and reorder routes at the front of the optimization queue, or change how the other
+/// routes are scored
+#[post("/optimization-queue")]
+async fn update_optimization_queue(
+    update: web::Json<QueueUpdate>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Updating optimization queue");
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let update = update.into_inner();
+    if let Some(weights) = &update.weights {
+        if let Err(e) = weights.validate() {
+            return ServiceError::InvalidRequest(e.to_string()).error_response();
+        }
+    }
+    if let Some(pinned) = &update.pinned {
+        let unknown: Vec<&String> = pinned
+            .iter()
+            .filter(|id| !city.transit.routes.iter().any(|r| &r.route_id == *id))
+            .collect();
+        if !unknown.is_empty() {
+            return ServiceError::NotFound(format!("Routes not found: {:?}", unknown))
+                .error_response();
+        }
+    }
+
+    let mut queue = data.optimization_queue.lock().unwrap();
+    if let Some(weights) = update.weights {
+        queue.weights = weights;
+    }
+    if let Some(pinned) = update.pinned {
+        queue.set_pinned(pinned);
+    }
+    HttpResponse::Ok().json(serde_json::json!({
+        "message": "Optimization queue updated",
+        "weights": queue.weights,
+        "pinned": queue.pinned(),
+    }))
+}
+
+#[get("/rank-route-improvements")]
+async fn rank_route_improvements(data: web::Data<AppState>) -> impl Responder {
+    println!("Ranking routes by improvement");
+
+    // Get the necessary data
+    let city_guard = data.city.read().unwrap();
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+
+    if let (Some(city), Some(optimized_transit)) = (&*city_guard, &*optimized_transit_guard) {
+        if optimized_route_ids.is_empty() {
+            return HttpResponse::Ok().json(serde_json::json!({
+                "message": "No routes have been optimized yet",
+                "ranked_routes": []
+            }));
+        }
+
+        // Call the rank_routes_by_improvement function
+        let ranked_routes = eval::rank_routes_by_improvement(
+            &city.gtfs,
+            &city.transit,
+            optimized_transit,
+            &optimized_route_ids,
+        );
+
+        HttpResponse::Ok().json(serde_json::json!({
+            "message": format!("Ranked {} optimized routes", ranked_routes.len()),
+            "ranked_routes": ranked_routes
         }))
+    } else {
+        ServiceError::CityNotLoaded.error_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct EvaluateNetworkParams {
+    /// Also apply optimized routes that have not been reviewed yet
+    include_proposed: Option<bool>,
+}
+
+/// Average transfers, transfer wait and impedance of a network, cached in its evals
+fn transfer_metrics(transit: &TransitNetwork, grid: &GridNetwork) -> (f64, f64, f64) {
+    match &transit.evals {
+        Some(evals) => (
+            evals.avg_transfers,
+            evals.avg_transfer_wait,
+            evals.avg_impedance,
+        ),
+        None => {
+            let impedance = eval::transfer_impedance(transit, grid);
+            (
+                impedance.avg_transfers,
+                impedance.avg_transfer_wait,
+                impedance.avg_impedance,
+            )
+        }
     }
 }
 
-#[get("/grid")]
-async fn get_grid(data: web::Data<AppState>) -> impl Responder {
-    println!("Getting grid data");
+#[get("/evaluate-network")]
+async fn evaluate_network(
+    query: web::Query<EvaluateNetworkParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Evaluating network metrics");
 
-    let city_guard = data.city.lock().unwrap();
+    let city_guard = data.city.read().unwrap();
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
 
-    if let Some(city) = &*city_guard {
-        // Create a simple array of zones with population and coordinates
-        let zones: Vec<serde_json::Value> = city
-            .grid
-            .graph
-            .node_indices()
-            .map(|ni| {
-                let zone = city.grid.get_zone(ni);
-                serde_json::json!({
-                    "POPULATION": zone.population,
-                    "COORDINATES": match zone.polygon.centroid() {
-                        Some(centroid) => [centroid.x(), centroid.y()],
-                        None => [0.0, 0.0], // Default coordinates if centroid is None
-                    }
-                })
-            })
-            .collect();
+    if let (Some(city), Some(optimized_transit)) = (&*city_guard, &*optimized_transit_guard) {
+        // Only accepted optimizations count towards the optimized network by default
+        let optimized_route_ids = data.optimized_route_ids.lock().unwrap().clone();
+        let applied_route_ids = data.route_reviews.lock().unwrap().visible(
+            &optimized_route_ids,
+            query.include_proposed.unwrap_or(false),
+        );
+        let optimized_transit = &review::merged_network(
+            &city.transit,
+            optimized_transit,
+            &applied_route_ids,
+            &city.grid,
+        );
 
-        HttpResponse::Ok().json(zones)
-    } else {
-        HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "City data not loaded"
+        // Calculate metrics for original network
+        let original_coverage_score =
+            eval::evaluate_network_coverage(&city.transit, &city.grid, &city.search);
+        let original_economic_score =
+            eval::evaluate_network_economic_score(&city.transit, &city.grid);
+        let original_avg_ridership = eval::avg_ridership(&city.transit, &city.grid);
+
+        // Get cached transfer metrics or calculate if not available
+        let (original_avg_transfers, original_transfer_wait, original_impedance) =
+            transfer_metrics(&city.transit, &city.grid);
+
+        let original_transit_score = eval::transit_score(
+            original_avg_transfers,
+            original_avg_ridership,
+            original_coverage_score,
+        );
+
+        let optimized_avg_ridership = eval::avg_ridership(optimized_transit, &city.grid);
+
+        let ridership_improvement =
+            (optimized_avg_ridership / original_avg_ridership).max(1.0) - 1.0;
+
+        // Calculate metrics for optimized network
+        let optimized_coverage_score =
+            eval::evaluate_network_coverage(optimized_transit, &city.grid, &city.search);
+        let optimized_economic_score =
+            eval::evaluate_network_economic_score(optimized_transit, &city.grid);
+
+        // Get cached transfer metrics or calculate if not available
+        let (optimized_avg_transfers, optimized_transfer_wait, optimized_impedance) =
+            transfer_metrics(optimized_transit, &city.grid);
+
+        let optimized_transit_score = eval::transit_score(
+            optimized_avg_transfers,
+            optimized_avg_ridership,
+            optimized_coverage_score,
+        );
+
+        let optimized_transit_score = optimized_transit_score * (1.0 + ridership_improvement / 2.0);
+        let optimized_coverage_score =
+            optimized_coverage_score * (1.0 + ridership_improvement / 4.0);
+        let optimized_economic_score = optimized_economic_score * (1.0 + ridership_improvement);
+
+        println!("Original:");
+        println!("  Coverage: {}", original_coverage_score);
+        println!("  Economic Score: {}", original_economic_score);
+        println!("  Avg Transfers: {}", original_avg_transfers);
+        println!("  Avg Impedance: {} min", original_impedance);
+        println!("  Avg Ridership: {}", original_avg_ridership);
+        println!("  Transit Score: {}", original_transit_score);
+        println!("Optimized:");
+        println!("  Coverage: {}", optimized_coverage_score);
+        println!("  Economic Score: {}", optimized_economic_score);
+        println!("  Avg Transfers: {}", optimized_avg_transfers);
+        println!("  Avg Impedance: {} min", optimized_impedance);
+        println!("  Avg Ridership: {}", optimized_avg_ridership);
+        println!("  Transit Score: {}", optimized_transit_score);
+
+        let original = NetworkKpis {
+            coverage: original_coverage_score.min(99.0),
+            economic_score: original_economic_score.min(99.0),
+            avg_transfers: original_avg_transfers,
+            avg_transfer_wait: original_transfer_wait,
+            avg_impedance: original_impedance,
+            avg_ridership: original_avg_ridership,
+            transit_score: original_transit_score.min(99.0),
+        };
+        let optimized = NetworkKpis {
+            coverage: optimized_coverage_score.min(99.0),
+            economic_score: optimized_economic_score.min(99.0),
+            avg_transfers: optimized_avg_transfers,
+            avg_transfer_wait: optimized_transfer_wait,
+            avg_impedance: optimized_impedance,
+            avg_ridership: optimized_avg_ridership,
+            transit_score: optimized_transit_score.min(99.0),
+        };
+        let record = KpiRecord {
+            recorded_at: chrono::Local::now().to_rfc3339(),
+            include_proposed: query.include_proposed.unwrap_or(false),
+            optimized_routes: applied_route_ids.len(),
+            original,
+            optimized,
+        };
+        if let Err(e) = City::append_kpi_history(&city.name, &record) {
+            log::error!("Failed to record network KPIs: {}", e);
+        }
+
+        let budget = *data.operating_budget.lock().unwrap();
+        let original_cost = OperatingCost::for_network(&city.transit, &city.road);
+        let optimized_cost = OperatingCost::for_network(optimized_transit, &city.road);
+
+        HttpResponse::Ok().json(serde_json::json!({
+            "timezone": city.timezone,
+            "original": record.original,
+            "optimized": record.optimized,
+            "budget": {
+                "original": budget.utilization(&original_cost),
+                "optimized": budget.utilization(&optimized_cost),
+            },
         }))
+    } else {
+        ServiceError::CityNotLoaded.error_response()
     }
 }
 
-#[get("/avg-transfers")]
-async fn get_avg_transfers(data: web::Data<AppState>) -> impl Responder {
-    println!("Getting average transfers");
-
-    let city_guard = data.city.lock().unwrap();
+#[derive(Deserialize)]
+struct EvaluateEquityParams {
+    /// Numeric column of the zone table counting the people of a demographic group
+    demographic: Option<String>,
+    /// Also apply optimized routes that have not been reviewed yet
+    include_proposed: Option<bool>,
+}
 
-    if let Some(city) = &*city_guard {
-        println!("Computing new transfers data");
-        let optimized_transit_guard = data.optimized_transit.lock().unwrap();
-        let optimized_transit = optimized_transit_guard.as_ref().unwrap();
-        if optimized_transit.evals.is_none() {
-            return HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Optimized transit data not loaded"
-            }));
+/// Equity of a network, using the transfers cached in its evals
+fn equity_metrics(
+    transit: &TransitNetwork,
+    grid: &GridNetwork,
+    demographic: Option<(&str, &HashMap<u32, f64>)>,
+) -> EquityEvals {
+    match (&transit.evals, demographic) {
+        (Some(evals), None) => evals.equity.clone(),
+        (Some(evals), demographic) => {
+            EquityEvals::for_network(transit, grid, &evals.zone_to_transfers, demographic)
         }
+        (None, demographic) => {
+            let impedance = eval::transfer_impedance(transit, grid);
+            EquityEvals::for_network(transit, grid, &impedance.zone_to_transfers, demographic)
+        }
+    }
+}
 
-        let evals = optimized_transit.evals.as_ref().unwrap();
+/// Coverage and service of the residents of the original and optimized networks by zone
+/// population, and of a demographic group if the zone table has a column counting it
+#[get("/evaluate-equity")]
+async fn evaluate_equity(
+    query: web::Query<EvaluateEquityParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Evaluating network equity");
 
-        let (avg_transfers, zone_transfers) = (evals.avg_transfers, &evals.zone_to_transfers);
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let demographic = match &query.demographic {
+        Some(column) => match city.zone_column(column) {
+            Ok(Some(values)) => Some((column.as_str(), values)),
+            Ok(None) => {
+                return ServiceError::InvalidRequest(format!(
+                    "The zone table has no column {}",
+                    column
+                ))
+                .error_response();
+            }
+            Err(e) => {
+                return ServiceError::Internal(format!("Failed to read the zone table: {}", e))
+                    .error_response();
+            }
+        },
+        None => None,
+    };
+    let demographic = demographic
+        .as_ref()
+        .map(|(column, values)| (*column, values));
 
-        println!("Average transfers: {}", avg_transfers);
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let Some(optimized_transit) = optimized_transit_guard.as_ref() else {
+        return ServiceError::NetworkNotLoaded.error_response();
+    };
+    let optimized_route_ids = data.optimized_route_ids.lock().unwrap().clone();
+    let applied_route_ids = data.route_reviews.lock().unwrap().visible(
+        &optimized_route_ids,
+        query.include_proposed.unwrap_or(false),
+    );
+    let optimized_transit = &review::merged_network(
+        &city.transit,
+        optimized_transit,
+        &applied_route_ids,
+        &city.grid,
+    );
 
-        // Convert zone transfers to JSON
-        let zone_transfers_json: Vec<serde_json::Value> = zone_transfers
+    HttpResponse::Ok().json(serde_json::json!({
+        "original": equity_metrics(&city.transit, &city.grid, demographic),
+        "optimized": equity_metrics(optimized_transit, &city.grid, demographic),
+    }))
+}
+
+/// Network metrics of the city next to the same metrics normalized by its size and density,
+/// aggregated across cities by the proxy's `/summary`
+/// Stops that gained, lost or kept service in the optimized network compared to the original
+/// one, as points with the routes serving them before and after
+#[get("/stop-impacts")]
+async fn get_stop_impacts(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting stop impacts");
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let Some(optimized_transit) = optimized_transit_guard.as_ref() else {
+        return ServiceError::NetworkNotLoaded.error_response();
+    };
+
+    let impacts = StopImpact::for_network(&city.transit.routes, optimized_transit);
+    let count = |kind| impacts.iter().filter(|s| s.impact == kind).count();
+    let summary = serde_json::json!({
+        "gained": count(StopImpactKind::Gained),
+        "lost": count(StopImpactKind::Lost),
+        "unchanged": count(StopImpactKind::Unchanged),
+        "routes_changed": impacts
             .iter()
-            .map(|(ni, transfers)| {
-                let zone = city.grid.get_zone(*ni);
-                serde_json::json!({
-                    "TRANSFERS": transfers,
-                    "COORDINATES": match zone.polygon.centroid() {
-                        Some(centroid) => [centroid.x(), centroid.y()],
-                        None => [0.0, 0.0],
-                    }
-                })
+            .filter(|s| s.routes_before != s.routes_after)
+            .count(),
+    });
+    let features: Vec<serde_json::Value> = impacts
+        .iter()
+        .map(|stop| {
+            serde_json::json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": [stop.lon, stop.lat],
+                },
+                "properties": {
+                    "stop_id": stop.stop_id,
+                    "stop_name": city.gtfs.stops.get(&stop.stop_id).map(|s| &s.stop_name),
+                    "impact": stop.impact,
+                    "routes_before": stop.routes_before,
+                    "routes_after": stop.routes_after,
+                    "route_count_before": stop.routes_before.len(),
+                    "route_count_after": stop.routes_after.len(),
+                },
             })
-            .collect();
+        })
+        .collect();
 
-        HttpResponse::Ok().json(serde_json::json!({
-            "average_transfers": avg_transfers,
-            "zone_transfers": zone_transfers_json
-        }))
-    } else {
-        HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "City data not loaded"
-        }))
-    }
+    let mut geojson = geojson::convert_to_geojson(&features);
+    geojson["summary"] = summary;
+    HttpResponse::Ok().json(geojson)
 }
 
-#[post("/reset-optimizations")]
-async fn reset_optimizations(data: web::Data<AppState>) -> impl Responder {
-    println!("Resetting all route optimizations");
+/// Routes and zones that changed in the optimized network compared to the original one, with
+/// the stops added, removed and moved on each route and the population gaining or losing service
+#[get("/network-diff")]
+async fn get_network_diff(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting network diff");
 
-    let city_guard = data.city.lock().unwrap();
-    if let Some(city) = &*city_guard {
-        // Reset the optimized transit to original state
-        {
-            let mut optimized_transit_guard = data.optimized_transit.lock().unwrap();
-            *optimized_transit_guard = Some(city.transit.clone());
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let Some(optimized_transit) = optimized_transit_guard.as_ref() else {
+        return ServiceError::NetworkNotLoaded.error_response();
+    };
+
+    HttpResponse::Ok().json(DetailedNetworkDiff::new(
+        &city.transit.routes,
+        &optimized_transit.routes,
+        &city.grid,
+    ))
+}
+
+#[get("/city-summary")]
+async fn get_city_summary(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting city summary");
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
         }
+    };
 
-        // Clear the list of optimized route IDs
-        {
-            let mut optimized_route_ids = data.optimized_route_ids.lock().unwrap();
-            optimized_route_ids.clear();
+    let coverage = eval::evaluate_network_coverage(&city.transit, &city.grid, &city.search);
+    let avg_ridership = eval::avg_ridership(&city.transit, &city.grid);
+    let avg_transfers = match &city.transit.evals {
+        Some(evals) => evals.avg_transfers,
+        None => eval::average_transfers(&city.transit, &city.grid).0,
+    };
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "city": city.name,
+        "timezone": city.timezone,
+        "current_period": TimePeriod::at(chrono::Utc::now(), city.tz()),
+        "profile": city.profile,
+        "metrics": {
+            "coverage": coverage,
+            "avg_ridership": avg_ridership,
+            "avg_transfers": avg_transfers,
+            "transit_score": eval::transit_score(avg_transfers, avg_ridership, coverage),
+        },
+        "normalized": city.profile.normalize(coverage, avg_ridership),
+    }))
+}
+
+/// Name and local time of the city, listed for every city by the proxy's `/cities`
+#[get("/city-info")]
+async fn get_city_info(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting city info");
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
         }
+    };
 
-        // Clear the list of noop route IDs
-        {
-            let mut noop_route_ids = data.noop_route_ids.lock().unwrap();
-            noop_route_ids.clear();
+    let now = chrono::Utc::now();
+    HttpResponse::Ok().json(serde_json::json!({
+        "city": city.name,
+        "timezone": city.timezone,
+        "local_time": now.with_timezone(&city.tz()).to_rfc3339(),
+        "current_period": TimePeriod::at(now, city.tz()),
+        "periods": TimePeriod::ALL.iter().map(|period| {
+            let (start, end) = period.local_bounds();
+            serde_json::json!({
+                "period": period,
+                "start": format_gtfs_time(start),
+                "end": format_gtfs_time(end),
+            })
+        }).collect::<Vec<_>>(),
+    }))
+}
+
+#[derive(Deserialize)]
+struct ExportRasterParams {
+    /// Zone metric to rasterize: demand, coverage or transfers
+    metric: String,
+    /// Pixel size in meters, 100 if omitted
+    resolution: Option<f64>,
+    /// Evaluate the optimized network instead of the original one, when there is one
+    optimized: Option<bool>,
+}
+
+/// Zone metric rasterized over the city as a Float32 GeoTIFF in EPSG:4326
+#[get("/export-raster")]
+async fn export_raster(
+    query: web::Query<ExportRasterParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Exporting {} raster", query.metric);
+
+    let metric: eval::ZoneMetric = match query.metric.parse() {
+        Ok(metric) => metric,
+        Err(e) => {
+            return ServiceError::InvalidRequest(e.to_string()).error_response();
+        }
+    };
+
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
         }
+    };
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let transit = match &*optimized_transit_guard {
+        Some(optimized) if query.optimized.unwrap_or(false) => optimized,
+        _ => &city.transit,
+    };
 
-        return HttpResponse::Ok().json(serde_json::json!({
-            "message": "All route optimizations reset"
-        }));
+    let values = eval::zone_metric(metric, transit, &city.grid);
+    let resolution = query.resolution.unwrap_or(100.0);
+    match Raster::rasterize(&city.grid, &values, resolution) {
+        Ok(raster) => HttpResponse::Ok()
+            .content_type("image/tiff")
+            .insert_header((
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"{}_{}.tif\"",
+                    city.name,
+                    metric.name()
+                ),
+            ))
+            .body(raster.to_geotiff()),
+        Err(e) => ServiceError::InvalidRequest(e.to_string().to_string()).error_response(),
     }
-
-    HttpResponse::InternalServerError().json(serde_json::json!({
-        "error": "City data not loaded"
-    }))
 }
 
-#[get("/get-optimizations")]
-async fn get_optimizations(data: web::Data<AppState>) -> impl Responder {
-    println!("Fetching optimized routes");
+#[derive(Deserialize)]
+struct ExportGtfsParams {
+    /// Format of the export, only `zip` is supported
+    format: Option<String>,
+    /// Export this saved scenario instead of the optimized network
+    scenario: Option<String>,
+    /// Average speed of the trips whose times are estimated, in km/h
+    avg_speed_kmh: Option<f64>,
+    /// Minutes between departures of routes without departures
+    default_headway_min: Option<f64>,
+    /// Write every departure as a trip instead of repeating trips by frequencies
+    explicit_trips: Option<bool>,
+}
 
-    // Get the list of optimized route IDs
-    let optimized_route_ids = data.optimized_route_ids.lock().unwrap().clone();
+/// Optimized network, or a saved scenario, as a zipped GTFS feed
+#[get("/export-gtfs")]
+async fn export_gtfs(
+    query: web::Query<ExportGtfsParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Exporting GTFS feed");
 
-    if optimized_route_ids.is_empty() {
-        return HttpResponse::Ok().json(serde_json::json!({
-            "message": "No routes have been optimized yet",
-            "features": []
-        }));
+    let format = query.format.as_deref().unwrap_or("zip");
+    if format != "zip" {
+        return ServiceError::InvalidRequest(format!(
+            "Unsupported export format {}, expected zip",
+            format
+        ))
+        .error_response();
+    }
+    let defaults = ScheduleOptions::default();
+    let options = ScheduleOptions {
+        avg_speed_kmh: query.avg_speed_kmh.unwrap_or(defaults.avg_speed_kmh),
+        default_headway_min: query
+            .default_headway_min
+            .unwrap_or(defaults.default_headway_min),
+        explicit_trips: query.explicit_trips.unwrap_or(defaults.explicit_trips),
+    };
+    if let Err(e) = options.validate() {
+        return ServiceError::InvalidRequest(e).error_response();
     }
 
-    // Access the city data (for gtfs and road network)
-    let city_guard = data.city.lock().unwrap();
-    let optimized_transit_guard = data.optimized_transit.lock().unwrap();
+    let city_guard = data.city.read().unwrap();
+    let city = match &*city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+    let scenario = match &query.scenario {
+        Some(name) => match City::load_scenario(&city.name, name) {
+            Ok(scenario) => Some(scenario),
+            Err(crate::layers::error::Error::CacheNotFound) => {
+                return ServiceError::NotFound(format!("Scenario {} not found", name))
+                    .error_response();
+            }
+            Err(e) => {
+                return ServiceError::Internal(format!("Failed to load scenario {}: {}", name, e))
+                    .error_response();
+            }
+        },
+        None => None,
+    };
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let (network, name) = match (&scenario, &*optimized_transit_guard) {
+        (Some(scenario), _) => (&scenario.network, scenario.name.as_str()),
+        (None, Some(optimized)) => (optimized, "optimized"),
+        (None, None) => {
+            return ServiceError::NotFound("No routes have been optimized yet".to_string())
+                .error_response();
+        }
+    };
 
-    if let (Some(city), Some(optimized_transit)) = (&*city_guard, &*optimized_transit_guard) {
-        HttpResponse::Ok().json(serde_json::json!({
-            "message": format!("Found {} optimized routes", optimized_route_ids.len()),
-            "routes": optimized_route_ids,
-            "geojson": get_optimized_geojson(city, optimized_transit, &optimized_route_ids)
-        }))
-    } else {
-        HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "City data not loaded"
-        }))
+    let feed = gtfs_export::export_gtfs(network, &city.gtfs, &city.road, &options);
+    let zipped = feed
+        .try_into()
+        .and_then(|dataset: GtfsDataSet| dataset.write_to_zip(std::io::Cursor::new(Vec::new())));
+    match zipped {
+        Ok(cursor) => HttpResponse::Ok()
+            .content_type("application/zip")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"{}_{}_gtfs.zip\"", city.name, name),
+            ))
+            .body(cursor.into_inner()),
+        Err(e) => ServiceError::Internal(format!("Failed to export GTFS: {}", e)).error_response(),
     }
 }
 
 #[derive(Deserialize)]
-struct RouteIdParams {
-    route_ids: String, // Comma-separated list of route IDs
+struct TravelTimeMatrixParams {
+    /// Compare the original network with this saved scenario instead of the optimized network
+    scenario: Option<String>,
+    /// `json` (default) or `csv`
+    format: Option<String>,
+    /// Longest trip kept in minutes, defaults to `skim::DEFAULT_MAX_MINUTES`
+    max_minutes: Option<f64>,
 }
 
-#[get("/optimize-live")]
-async fn optimize_live(
-    req: HttpRequest,
-    stream: web::Payload,
-    query: web::Query<RouteIdParams>,
+/// Zone to zone travel times by transit of the original network and of the optimized network,
+/// or a saved scenario, split into in-vehicle, wait, transfer and walk time
+#[get("/travel-time-matrix")]
+async fn get_travel_time_matrix(
+    query: web::Query<TravelTimeMatrixParams>,
     data: web::Data<AppState>,
-) -> Result<HttpResponse, Error> {
-    // Parse comma-separated route IDs
-    let route_ids: Vec<String> = query
-        .route_ids
-        .split(',')
-        .map(|id| id.trim().to_string())
-        .filter(|id| !id.is_empty())
-        .collect();
+) -> impl Responder {
+    println!("Getting travel time matrix");
+
+    let format = query.format.as_deref().unwrap_or("json");
+    if format != "json" && format != "csv" {
+        return ServiceError::InvalidRequest(format!(
+            "Unknown format '{}', expected 'json' or 'csv'",
+            format
+        ))
+        .error_response();
+    }
+    let max_minutes = query.max_minutes.unwrap_or(skim::DEFAULT_MAX_MINUTES);
+    if !max_minutes.is_finite() || max_minutes <= 0.0 {
+        return ServiceError::InvalidRequest(format!(
+            "max_minutes must be positive, got {}",
+            max_minutes
+        ))
+        .error_response();
+    }
+
+    let city_guard = data.city.read().unwrap();
+    let Some(city) = &*city_guard else {
+        return ServiceError::CityNotLoaded.error_response();
+    };
+    let scenario = match &query.scenario {
+        Some(name) => match City::load_scenario(&city.name, name) {
+            Ok(scenario) => Some(scenario),
+            Err(crate::layers::error::Error::CacheNotFound) => {
+                return ServiceError::NotFound(format!("Scenario {} not found", name))
+                    .error_response();
+            }
+            Err(e) => {
+                return ServiceError::Internal(format!("Failed to load scenario {}: {}", name, e))
+                    .error_response();
+            }
+        },
+        None => None,
+    };
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
+    let (network, name, key) = match (&scenario, &*optimized_transit_guard) {
+        (Some(scenario), _) => (
+            &scenario.network,
+            scenario.name.as_str(),
+            format!("scenario-{}", scenario.name),
+        ),
+        (None, Some(optimized)) => (optimized, "optimized", "optimized".to_string()),
+        (None, None) => return ServiceError::NetworkNotLoaded.error_response(),
+    };
 
-    println!(
-        "WebSocket connection request for optimize-live with routes {:?}",
-        route_ids
+    let original = TravelTimeMatrix::cached(
+        &city.name,
+        "original",
+        &city.transit,
+        &city.grid,
+        max_minutes,
     );
-
-    if route_ids.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "No valid route IDs provided"
-        })));
+    let compared = TravelTimeMatrix::cached(&city.name, &key, network, &city.grid, max_minutes);
+    if format == "json" {
+        return HttpResponse::Ok().json(serde_json::json!({
+            "network": name,
+            "original": original,
+            "optimized": compared,
+        }));
+    }
+    match TravelTimeMatrix::to_csv(&[("original", &original), (name, &compared)]) {
+        Ok(csv) => HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header((
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"{}_{}_travel_times.csv\"",
+                    city.name, name
+                ),
+            ))
+            .body(csv),
+        Err(e) => {
+            ServiceError::Internal(format!("Failed to write travel times: {}", e)).error_response()
+        }
     }
-
-    let ws = OptimizationWs::new(data.clone(), route_ids);
-    ws::start(ws, &req, stream)
 }
 
-#[get("/rank-route-improvements")]
-async fn rank_route_improvements(data: web::Data<AppState>) -> impl Responder {
-    println!("Ranking routes by improvement");
+/// Largest GTFS-realtime snapshot accepted by `/ingest-realtime`, in bytes
+const REALTIME_PAYLOAD_LIMIT: usize = 32 * 1024 * 1024;
 
-    // Get the necessary data
-    let city_guard = data.city.lock().unwrap();
-    let optimized_transit_guard = data.optimized_transit.lock().unwrap();
-    let optimized_route_ids = data.optimized_route_ids.lock().unwrap();
+/// Add a GTFS-realtime snapshot (protobuf) of trip updates and vehicle positions to the
+/// observations of the city
+#[post("/ingest-realtime")]
+async fn ingest_realtime(body: web::Bytes, data: web::Data<AppState>) -> impl Responder {
+    println!("Ingesting GTFS-realtime snapshot of {} bytes", body.len());
 
-    if let (Some(city), Some(optimized_transit)) = (&*city_guard, &*optimized_transit_guard) {
-        if optimized_route_ids.is_empty() {
-            return HttpResponse::Ok().json(serde_json::json!({
-                "message": "No routes have been optimized yet",
-                "ranked_routes": []
-            }));
+    let mut city_guard = data.city.write().unwrap();
+    let city = match &mut *city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+    match city.ingest_realtime(&body) {
+        Ok((entities, unmatched)) => HttpResponse::Ok().json(serde_json::json!({
+            "entities": entities,
+            "unmatched_entities": unmatched,
+        })),
+        Err(e) => {
+            ServiceError::InvalidRequest(format!("Failed to read GTFS-realtime snapshot: {}", e))
+                .error_response()
         }
+    }
+}
 
-        // Call the rank_routes_by_improvement function
-        let ranked_routes = eval::rank_routes_by_improvement(
-            &city.gtfs,
-            &city.transit,
-            optimized_transit,
-            &optimized_route_ids,
-        );
+/// Headways and delays observed on each route by time period in the ingested snapshots
+#[get("/realtime-observations")]
+async fn get_realtime_observations(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting realtime observations");
 
-        HttpResponse::Ok().json(serde_json::json!({
-            "message": format!("Ranked {} optimized routes", ranked_routes.len()),
-            "ranked_routes": ranked_routes
-        }))
-    } else {
-        HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "City data not loaded"
-        }))
+    let city_guard = data.city.read().unwrap();
+    match &*city_guard {
+        Some(city) => HttpResponse::Ok().json(city.realtime.summary(city.tz())),
+        None => ServiceError::CityNotLoaded.error_response(),
     }
 }
 
-#[get("/evaluate-network")]
-async fn evaluate_network(data: web::Data<AppState>) -> impl Responder {
-    println!("Evaluating network metrics");
+#[get("/search-config")]
+async fn get_search_config(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting search parameters");
 
-    let city_guard = data.city.lock().unwrap();
-    let optimized_transit_guard = data.optimized_transit.lock().unwrap();
+    let city_guard = data.city.read().unwrap();
+    match &*city_guard {
+        Some(city) => HttpResponse::Ok().json(&city.search),
+        None => ServiceError::CityNotLoaded.error_response(),
+    }
+}
 
-    if let (Some(city), Some(optimized_transit)) = (&*city_guard, &*optimized_transit_guard) {
-        // Calculate metrics for original network
-        let original_coverage_score = eval::evaluate_network_coverage(&city.transit, &city.grid);
-        let original_economic_score =
-            eval::evaluate_network_economic_score(&city.transit, &city.grid);
-        let original_avg_ridership = eval::avg_ridership(&city.transit, &city.grid);
+/// Update the search parameters of the city and save them for the next start. Route coverage
+/// is re-evaluated when the coverage radius changes.
+#[post("/search-config")]
+async fn update_search_config(
+    params: web::Json<PartialSearchConfig>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Updating search parameters");
 
-        // Get cached average transfers or calculate if not available
-        let original_avg_transfers = match &city.transit.evals {
-            Some(evals) => evals.avg_transfers,
-            None => {
-                let (avg, _) = eval::average_transfers(&city.transit, &city.grid);
-                avg
-            }
-        };
+    let mut city_guard = data.city.write().unwrap();
+    let city = match &mut *city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
 
-        let original_transit_score = eval::transit_score(
-            original_avg_transfers,
-            original_avg_ridership,
-            original_coverage_score,
-        );
+    let previous_radius = city.search.coverage_radius;
+    if let Err(e) = city.search.update_from_partial(params.into_inner()) {
+        return ServiceError::InvalidRequest(e.to_string()).error_response();
+    }
+    if let Err(e) = City::save_search_config(&city.name, &city.search) {
+        return ServiceError::Internal(format!("Failed to save search parameters: {}", e))
+            .error_response();
+    }
 
-        let optimized_avg_ridership = eval::avg_ridership(&optimized_transit, &city.grid);
+    if city.search.coverage_radius != previous_radius {
+        let radius = city.search.coverage_radius;
+        let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+        let networks = std::iter::once(&mut city.transit).chain(optimized_transit_guard.as_mut());
+        for route in networks.flat_map(|n| n.routes.iter_mut()) {
+            if let Some(evals) = route.evals.as_mut() {
+                evals.coverage = eval::evaluate_coverage(&route.outbound_stops, &city.grid, radius);
+            }
+        }
+    }
 
-        let ridership_improvement =
-            (optimized_avg_ridership / original_avg_ridership).max(1.0) - 1.0;
+    HttpResponse::Ok().json(serde_json::json!({
+        "message": "Search parameters updated",
+        "search": city.search,
+    }))
+}
 
-        // Calculate metrics for optimized network
-        let optimized_coverage_score =
-            eval::evaluate_network_coverage(&optimized_transit, &city.grid);
-        let optimized_economic_score =
-            eval::evaluate_network_economic_score(&optimized_transit, &city.grid);
+#[get("/demand-model")]
+async fn get_demand_model(data: web::Data<AppState>) -> impl Responder {
+    println!("Getting demand model");
 
-        // Get cached average transfers or calculate if not available
-        let optimized_avg_transfers = match &optimized_transit.evals {
-            Some(evals) => evals.avg_transfers,
-            None => {
-                let (avg, _) = eval::average_transfers(&optimized_transit, &city.grid);
-                avg
-            }
-        };
+    let city_guard = data.city.read().unwrap();
+    match &*city_guard {
+        Some(city) => HttpResponse::Ok().json(&city.transit.demand_model),
+        None => ServiceError::CityNotLoaded.error_response(),
+    }
+}
 
-        let optimized_transit_score = eval::transit_score(
-            optimized_avg_transfers,
-            optimized_avg_ridership,
-            optimized_coverage_score,
-        );
+/// Update the model splitting demand between competing routes and save it for the next
+/// start. The routes of the city and of every workspace are evaluated again with it.
+#[post("/demand-model")]
+async fn update_demand_model(
+    params: web::Json<PartialDemandModelConfig>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Updating demand model");
 
-        let optimized_transit_score = optimized_transit_score * (1.0 + ridership_improvement / 2.0);
-        let optimized_coverage_score =
-            optimized_coverage_score * (1.0 + ridership_improvement / 4.0);
-        let optimized_economic_score = optimized_economic_score * (1.0 + ridership_improvement);
+    let mut city_guard = data.city.write().unwrap();
+    let city = match &mut *city_guard {
+        Some(city) => city,
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
 
-        println!("Original:");
-        println!("  Coverage: {}", original_coverage_score);
-        println!("  Economic Score: {}", original_economic_score);
-        println!("  Avg Transfers: {}", original_avg_transfers);
-        println!("  Avg Ridership: {}", original_avg_ridership);
-        println!("  Transit Score: {}", original_transit_score);
-        println!("Optimized:");
-        println!("  Coverage: {}", optimized_coverage_score);
-        println!("  Economic Score: {}", optimized_economic_score);
-        println!("  Avg Transfers: {}", optimized_avg_transfers);
-        println!("  Avg Ridership: {}", optimized_avg_ridership);
-        println!("  Transit Score: {}", optimized_transit_score);
+    let mut model = city.transit.demand_model.clone();
+    if let Err(e) = model.update_from_partial(params.into_inner()) {
+        return ServiceError::InvalidRequest(e.to_string()).error_response();
+    }
+    if let Err(e) = City::save_demand_model(&city.name, &model) {
+        return ServiceError::Internal(format!("Failed to save demand model: {}", e))
+            .error_response();
+    }
 
-        HttpResponse::Ok().json(serde_json::json!({
-            "original": {
-                "coverage": original_coverage_score.min(99.0),
-                "economic_score": original_economic_score.min(99.0),
-                "avg_transfers": original_avg_transfers,
-                "avg_ridership": original_avg_ridership,
-                "transit_score": original_transit_score.min(99.0),
-            },
-            "optimized": {
-                "coverage": optimized_coverage_score.min(99.0),
-                "economic_score": optimized_economic_score.min(99.0),
-                "avg_transfers": optimized_avg_transfers,
-                "avg_ridership": optimized_avg_ridership,
-                "transit_score": optimized_transit_score.min(99.0),
-            },
-        }))
-    } else {
-        HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "City data not loaded"
-        }))
+    let mut workspaces = data.workspaces.lock().unwrap();
+    let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
+    let networks = std::iter::once(&mut city.transit)
+        .chain(optimized_transit_guard.as_mut())
+        .chain(workspaces.inactive_networks_mut());
+    for network in networks {
+        network.set_demand_model(model.clone(), &city.grid, &city.search);
     }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "message": "Demand model updated",
+        "demand_model": model,
+    }))
 }
 
 #[get("/route-improvements")]
@@ -664,14 +5228,13 @@ async fn get_route_improvements(
     );
 
     if route_ids.is_empty() {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "No valid route IDs provided"
-        }));
+        return ServiceError::InvalidRequest("No valid route IDs provided".to_string())
+            .error_response();
     }
 
     // Get the necessary data
-    let city_guard = data.city.lock().unwrap();
-    let optimized_transit_guard = data.optimized_transit.lock().unwrap();
+    let city_guard = data.city.read().unwrap();
+    let optimized_transit_guard = data.optimized_transit.read().unwrap();
     let optimized_route_ids = data.optimized_route_ids.lock().unwrap();
 
     if let (Some(city), Some(optimized_transit)) = (&*city_guard, &*optimized_transit_guard) {
@@ -702,9 +5265,172 @@ async fn get_route_improvements(
             "routes": ranked_routes
         }))
     } else {
-        HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "City data not loaded"
-        }))
+        ServiceError::CityNotLoaded.error_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct RunHistoryParams {
+    /// Only return the most recent runs
+    limit: Option<usize>,
+}
+
+/// Optimization runs of the city with the changes each made to the network, oldest first
+#[get("/run-history")]
+async fn get_run_history(
+    query: web::Query<RunHistoryParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Getting optimization run history");
+
+    let city_name = match &*data.city.read().unwrap() {
+        Some(city) => city.name.clone(),
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+
+    match City::load_run_history(&city_name) {
+        Ok(mut runs) => {
+            if let Some(limit) = query.limit {
+                runs.drain(..runs.len().saturating_sub(limit));
+            }
+            HttpResponse::Ok().json(serde_json::json!({
+                "city": city_name,
+                "runs": runs,
+            }))
+        }
+        Err(e) => {
+            ServiceError::Internal(format!("Failed to load run history: {}", e)).error_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct KpiHistoryParams {
+    /// Only evaluations at or after this RFC 3339 time
+    since: Option<String>,
+    /// Only return the most recent evaluations
+    limit: Option<usize>,
+}
+
+/// Network KPIs recorded each time the network was evaluated, oldest first
+#[get("/kpi-history")]
+async fn get_kpi_history(
+    query: web::Query<KpiHistoryParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Getting network KPI history");
+
+    let city_name = match &*data.city.read().unwrap() {
+        Some(city) => city.name.clone(),
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+    let since = match query
+        .since
+        .as_ref()
+        .map(|t| chrono::DateTime::parse_from_rfc3339(t).map_err(|e| (t, e)))
+        .transpose()
+    {
+        Ok(since) => since,
+        Err((t, e)) => {
+            return ServiceError::InvalidRequest(format!("Invalid time {:?}: {}", t, e))
+                .error_response();
+        }
+    };
+
+    match City::load_kpi_history(&city_name) {
+        Ok(records) => {
+            let mut records: Vec<KpiRecord> = records
+                .into_iter()
+                .filter(|r| {
+                    since.is_none_or(|since| {
+                        chrono::DateTime::parse_from_rfc3339(&r.recorded_at)
+                            .is_ok_and(|t| t >= since)
+                    })
+                })
+                .collect();
+            if let Some(limit) = query.limit {
+                records.drain(..records.len().saturating_sub(limit));
+            }
+            HttpResponse::Ok().json(serde_json::json!({
+                "city": city_name,
+                "records": records,
+            }))
+        }
+        Err(e) => {
+            ServiceError::Internal(format!("Failed to load KPI history: {}", e)).error_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AuditLogParams {
+    user: Option<String>,
+    /// Endpoint pattern, e.g. `/optimize-route/{route_id}`, or path of the calls
+    endpoint: Option<String>,
+    /// Only calls at or after this RFC 3339 time
+    since: Option<String>,
+    /// Only calls before this RFC 3339 time
+    until: Option<String>,
+    /// Only return the most recent calls
+    limit: Option<usize>,
+}
+
+/// Calls that changed the state of the city, oldest first
+#[get("/audit-log")]
+async fn get_audit_log(
+    query: web::Query<AuditLogParams>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    println!("Getting audit log");
+
+    let city_name = match &*data.city.read().unwrap() {
+        Some(city) => city.name.clone(),
+        None => {
+            return ServiceError::CityNotLoaded.error_response();
+        }
+    };
+
+    let parse_time = |time: &Option<String>| {
+        time.as_ref()
+            .map(|t| {
+                chrono::DateTime::parse_from_rfc3339(t)
+                    .map(|t| t.with_timezone(&chrono::Utc))
+                    .map_err(|e| format!("Invalid time {:?}: {}", t, e))
+            })
+            .transpose()
+    };
+    let filter = match (parse_time(&query.since), parse_time(&query.until)) {
+        (Ok(since), Ok(until)) => AuditFilter {
+            user: query.user.clone(),
+            endpoint: query.endpoint.clone(),
+            since,
+            until,
+        },
+        (Err(e), _) | (_, Err(e)) => {
+            return ServiceError::InvalidRequest(e.to_string()).error_response();
+        }
+    };
+
+    match City::load_audit_log(&city_name) {
+        Ok(events) => {
+            let mut events: Vec<AuditEvent> =
+                events.into_iter().filter(|e| filter.matches(e)).collect();
+            if let Some(limit) = query.limit {
+                events.drain(..events.len().saturating_sub(limit));
+            }
+            HttpResponse::Ok().json(serde_json::json!({
+                "city": city_name,
+                "revision": *data.audit_revision.lock().unwrap(),
+                "events": events,
+            }))
+        }
+        Err(e) => {
+            ServiceError::Internal(format!("Failed to load audit log: {}", e)).error_response()
+        }
     }
 }
 
@@ -712,35 +5438,56 @@ async fn get_route_improvements(
 async fn optimize_network(data: web::Data<AppState>) -> impl Responder {
     println!("Optimizing entire network");
 
-    let city_guard = data.city.lock().unwrap();
+    let city_guard = data.city.read().unwrap();
     match &*city_guard {
         Some(city) => {
-            let mut optimized_transit_guard = data.optimized_transit.lock().unwrap();
+            let workspace = data.workspaces.lock().unwrap().active().to_string();
+            let mut optimized_transit_guard = data.optimized_transit.write().unwrap();
             let optimized_transit = optimized_transit_guard.as_mut().unwrap();
             let mut optimized_route_ids = data.optimized_route_ids.lock().unwrap();
-            if let Ok(opt_transit) = City::load_opt_transit_from_cache(&city.name) {
-                println!("Loaded network from cache");
-                *optimized_transit = opt_transit.network;
-                *optimized_route_ids = opt_transit.optimized_routes;
-            } else {
-                println!("Failed to load network from cache");
-                return HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": "Failed to load network from cache"
-                }));
+            match City::load_opt_transit_from_cache(&city.name) {
+                Ok(opt_transit) => {
+                    println!("Loaded network from cache");
+                    *optimized_transit = opt_transit.network;
+                    *optimized_route_ids = opt_transit.optimized_routes;
+                    data.route_reviews.lock().unwrap().clear();
+                    data.route_pheromones.lock().unwrap().clear();
+                    data.route_history
+                        .lock()
+                        .unwrap()
+                        .remove_workspace(&workspace);
+                }
+                Err(crate::layers::error::Error::CacheNotFound) => {
+                    println!("No optimized network in the cache");
+                    return ServiceError::NotFound("No optimized network in the cache".to_string())
+                        .error_response();
+                }
+                Err(e) => {
+                    println!("Failed to load network from cache");
+                    return ServiceError::Internal(format!(
+                        "Failed to load network from cache: {}",
+                        e
+                    ))
+                    .error_response();
+                }
             }
 
-            return HttpResponse::Ok().json(serde_json::json!({
+            let diff = NetworkDiff::new(&city.transit.routes, optimized_transit);
+            let features = get_optimized_features(
+                city,
+                optimized_transit,
+                &optimized_route_ids,
+                &data.route_reviews.lock().unwrap(),
+            );
+            let fields = serde_json::json!({
                 "message": format!("Found {} optimized routes", optimized_route_ids.len()),
                 "routes": optimized_route_ids.clone(),
-                "geojson": get_optimized_geojson(city, optimized_transit, &optimized_route_ids)
-            }));
+                "diff": diff,
+            });
+            stream_geojson(features, Some((fields, "geojson")))
         }
-        None => {
-            return HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "City data not loaded"
-            }));
-        }
-    };
+        None => ServiceError::CityNotLoaded.error_response(),
+    }
 }
 
 /// Background worker function that periodically updates the TransitNetworkEvals
@@ -763,9 +5510,9 @@ fn background_evaluation_worker(app_state: web::Data<AppState>, update_interval:
 
         // Get locks on required data
         {
-            let city_guard = app_state.city.lock().unwrap();
+            let city_guard = app_state.city.read().unwrap();
             if let Some(city) = &*city_guard {
-                let mut optimized_transit_guard = app_state.optimized_transit.lock().unwrap();
+                let mut optimized_transit_guard = app_state.optimized_transit.write().unwrap();
                 if let Some(optimized_transit) = optimized_transit_guard.as_mut() {
                     // Update the network evaluations
                     let network_evals =
@@ -788,40 +5535,195 @@ fn background_evaluation_worker(app_state: web::Data<AppState>, update_interval:
     println!("Background evaluation thread shutting down");
 }
 
+/// Background worker function that periodically removes expired entries of the stores
+fn store_gc_worker(app_state: web::Data<AppState>, interval: Duration) {
+    while !app_state.shutdown_signal.load(Ordering::Relaxed) {
+        for _ in 0..(interval.as_secs() * 10) {
+            if app_state.shutdown_signal.load(Ordering::Relaxed) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        let removed = app_state.gc_stores();
+        if removed > 0 {
+            log::info!("Removed {} expired store entries", removed);
+        }
+    }
+}
+
+/// Header with the identity of the caller, set by the authenticating proxy in front of the
+/// service
+const AUDIT_USER_HEADER: &str = "X-Remote-User";
+
+/// GET endpoints that optimize routes and store the result
+const AUDITED_GET_ENDPOINTS: [&str; 2] = ["/optimize-route-events/{route_id}", "/optimize-live"];
+
+/// A call being recorded in the audit log, see `AuditCall::start`
+struct AuditCall {
+    user: String,
+    method: String,
+    path: String,
+    params: Rc<RefCell<ParamsHasher>>,
+}
+
+impl AuditCall {
+    /// Start recording a call if it changes the state of the city. The body is hashed as the
+    /// handler reads it, so only the part of the body that was read is part of the hash.
+    fn start(req: &mut ServiceRequest) -> Option<AuditCall> {
+        let mutating = match *req.method() {
+            Method::GET => req
+                .match_pattern()
+                .is_some_and(|p| AUDITED_GET_ENDPOINTS.contains(&p.as_str())),
+            Method::HEAD | Method::OPTIONS => false,
+            _ => true,
+        };
+        if !mutating {
+            return None;
+        }
+
+        let mut hasher = ParamsHasher::new();
+        hasher.update(req.path().as_bytes());
+        hasher.update(b"?");
+        hasher.update(req.query_string().as_bytes());
+        let params = Rc::new(RefCell::new(hasher));
+        let body_params = params.clone();
+        let body: Pin<Box<dyn Stream<Item = _>>> =
+            Box::pin(req.parts_mut().1.take().map(move |chunk| {
+                if let Ok(bytes) = &chunk {
+                    body_params.borrow_mut().update(bytes);
+                }
+                chunk
+            }));
+        req.set_payload(Payload::from(body));
+
+        Some(AuditCall {
+            user: req
+                .headers()
+                .get(AUDIT_USER_HEADER)
+                .and_then(|user| user.to_str().ok())
+                .filter(|user| !user.is_empty())
+                .unwrap_or("anonymous")
+                .to_string(),
+            method: req.method().to_string(),
+            path: req.path().to_string(),
+            params,
+        })
+    }
+
+    /// Append the call to the audit log of the city, moving its revision forward if the call
+    /// succeeded
+    fn finish<B>(self, res: &ServiceResponse<B>, data: &AppState, city_name: &str) {
+        let mut revision = data.audit_revision.lock().unwrap();
+        let revision_before = *revision;
+        if res.status().is_success() {
+            *revision += 1;
+        }
+        let event = AuditEvent {
+            at: chrono::Utc::now().to_rfc3339(),
+            user: self.user,
+            method: self.method,
+            endpoint: res
+                .request()
+                .match_pattern()
+                .unwrap_or_else(|| self.path.clone()),
+            path: self.path,
+            params_hash: self.params.borrow().finish(),
+            status: res.status().as_u16(),
+            revision_before,
+            revision_after: *revision,
+        };
+        if let Err(e) = City::append_audit_event(city_name, &event) {
+            log::error!("Failed to record {} {}: {}", event.method, event.path, e);
+        }
+    }
+}
+
+/// Load a city and serve it
+///
+/// # Arguments
+/// - `aco_defaults`: ACO parameters the server starts with, over the defaults of `ACO::init`
 pub async fn start_server(
     city_name: &str,
     gtfs_path: &str,
     db_path: &str,
     host: &str,
     port: u16,
+    webhook: Option<Webhook>,
+    optimization_limits: OptimizationLimits,
+    memory_mode: MemoryMode,
+    aco_defaults: aco2::PartialACO,
+    vehicles: RouteVehicles,
 ) -> std::io::Result<()> {
     let addr: SocketAddr = format!("{}:{}", host, port)
         .parse()
         .expect("Invalid address format");
 
-    println!("Loading city data from {} and {}", gtfs_path, db_path);
+    // Load the active feed version, falling back to the original feed if it fails
+    let store = feeds::FeedStore::new(gtfs_path);
+    let active_version = store.active();
+    let active_path = store
+        .path(&active_version)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|| gtfs_path.to_string());
+
+    println!("Loading city data from {} and {}", active_path, db_path);
     // Try loading the city data upfront
-    let city_result = City::load_with_cached_transit(
-        city_name, gtfs_path, db_path, true,  // set cache
+    let mut city_result = City::load_with_cached_transit(
+        city_name,
+        &active_path,
+        db_path,
+        true,  // set cache
         false, // don't invalidate cache
+        memory_mode,
     );
+    if city_result.is_err() && active_path != gtfs_path {
+        log::error!(
+            "Failed to load feed version {}, falling back to the original feed: {:?}",
+            active_version,
+            city_result.as_ref().err()
+        );
+        city_result =
+            City::load_with_cached_transit(city_name, gtfs_path, db_path, true, true, memory_mode);
+    }
 
     if city_result.is_err() {
         log::error!("Failed to load city data: {:?}", city_result.err());
         return Ok(());
     }
+    if let Ok(city) = &mut city_result {
+        let assigned = city
+            .transit
+            .assign_vehicles(&vehicles, &city.grid, &city.search);
+        log::info!("Vehicle profiles of {} routes changed", assigned);
+        match City::load_demand_model(&city.name) {
+            Ok(model) => {
+                if city
+                    .transit
+                    .set_demand_model(model, &city.grid, &city.search)
+                {
+                    log::info!("Routes evaluated with the saved demand model");
+                }
+            }
+            Err(e) => log::error!("Failed to load demand model: {:?}", e),
+        }
+    }
 
-    // Initialize application state with the city and a copy of transit for optimizations
-    let shutdown_signal = Arc::new(AtomicBool::new(false));
+    let app_state = build_app_state(
+        city_name,
+        city_result.ok(),
+        gtfs_path,
+        db_path,
+        webhook,
+        optimization_limits,
+    );
+    app_state
+        .aco_params
+        .lock()
+        .unwrap()
+        .update_from_partial(aco_defaults);
 
-    let app_state = web::Data::new(AppState {
-        optimized_transit: Mutex::new(city_result.as_ref().ok().map(|c| c.transit.clone())),
-        optimized_route_ids: Mutex::new(Vec::new()),
-        noop_route_ids: Mutex::new(Vec::new()),
-        city: Mutex::new(city_result.ok()),
-        aco_params: Mutex::new(aco2::ACO::init()),
-        shutdown_signal: shutdown_signal.clone(),
-    });
+    let app_state_clone = app_state.clone();
+    thread::spawn(move || store_gc_worker(app_state_clone, STORE_GC_INTERVAL));
 
     // Start the background evaluation thread
     // let app_state_clone = app_state.clone();
@@ -831,28 +5733,10 @@ pub async fn start_server(
     // });
 
     println!("Starting server on {}:{}", host, port);
-    let server = HttpServer::new(move || {
-        App::new()
-            .app_data(app_state.clone()) // Pass the state to all routes
-            .service(get_data)
-            .service(optimize_route)
-            .service(optimize_routes)
-            .service(evaluate_route)
-            .service(evaluate_coverage)
-            .service(get_grid)
-            .service(reset_optimizations)
-            .service(optimize_live)
-            .service(get_optimizations)
-            .service(get_avg_transfers)
-            .service(get_noop_route_ids)
-            .service(update_aco_params)
-            .service(rank_route_improvements)
-            .service(evaluate_network)
-            .service(get_route_improvements)
-            .service(optimize_network)
-    })
-    .bind(addr)?
-    .run();
+    let city_name = city_name.to_string();
+    let server = HttpServer::new(move || build_app(app_state.clone(), &city_name))
+        .bind(addr)?
+        .run();
 
     // Set up graceful shutdown handling
     // let srv = server.handle();
@@ -868,3 +5752,188 @@ pub async fn start_server(
     server.await?;
     Ok(())
 }
+
+/// State of the server of a city, with a copy of its transit network to optimize
+///
+/// # Arguments
+/// - `city`: The loaded city, `None` if it failed to load
+pub(crate) fn build_app_state(
+    city_name: &str,
+    city: Option<City>,
+    gtfs_path: &str,
+    db_path: &str,
+    webhook: Option<Webhook>,
+    optimization_limits: OptimizationLimits,
+) -> web::Data<AppState> {
+    // Continue the revision of the city from its audit log
+    let audit_revision = City::load_audit_log(city_name)
+        .map(|events| events.last().map_or(0, |e| e.revision_after))
+        .unwrap_or_else(|e| {
+            log::error!("Failed to load audit log: {}", e);
+            0
+        });
+
+    web::Data::new(AppState {
+        optimized_transit: RwLock::new(city.as_ref().map(|c| c.transit.clone())),
+        optimized_route_ids: Mutex::new(Vec::new()),
+        noop_route_ids: Mutex::new(BoundedStore::new(
+            "noop_route_ids",
+            NOOP_ROUTES_MAX,
+            Some(STORE_TTL),
+        )),
+        city: RwLock::new(city),
+        aco_params: Mutex::new(aco2::ACO::init()),
+        operating_budget: Mutex::new(OperatingBudget::default()),
+        route_reviews: Mutex::new(review::RouteReviews::default()),
+        optimization_queue: Mutex::new(OptimizationQueue::default()),
+        route_pheromones: Mutex::new(BoundedStore::new(
+            "route_pheromones",
+            PHEROMONE_ROUTES_MAX,
+            Some(STORE_TTL),
+        )),
+        route_constraints: Mutex::new(RouteConstraints::new()),
+        shutdown_signal: Arc::new(AtomicBool::new(false)),
+        gtfs_path: gtfs_path.to_string(),
+        db_path: db_path.to_string(),
+        webhook,
+        optimization_limits,
+        audit_revision: Mutex::new(audit_revision),
+        live_sessions: Mutex::new(HashMap::new()),
+        workspaces: Mutex::new(Workspaces::default()),
+        jobs: JobQueue::default(),
+        route_locks: RouteLocks::default(),
+        route_history: Mutex::new(RouteHistory::default()),
+        request_metrics: RequestMetrics::default(),
+    })
+}
+
+/// The app serving a city, built apart from `start_server` so that it can be served without
+/// binding a socket, e.g. by `actix_web::test::init_service`
+pub(crate) fn build_app(
+    app_state: web::Data<AppState>,
+    city_name: &str,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = Error,
+        InitError = (),
+    >,
+> {
+    let (audit_state, audit_city) = (app_state.clone(), city_name.to_string());
+    App::new()
+        .app_data(app_state) // Pass the state to all routes
+        .app_data(web::PayloadConfig::new(REALTIME_PAYLOAD_LIMIT))
+        .wrap_fn(move |mut req, srv| {
+            // Record calls that change the city in its audit log once they are answered
+            let call = AuditCall::start(&mut req);
+            let start = Instant::now();
+            let res = srv.call(req);
+            let (data, city_name) = (audit_state.clone(), audit_city.clone());
+            async move {
+                let res = res.await?;
+                if let Some(call) = call {
+                    call.finish(&res, &data, &city_name);
+                }
+                data.request_metrics.observe(
+                    res.request().method().as_str(),
+                    res.request().match_pattern().as_deref(),
+                    res.status().as_u16(),
+                    start.elapsed(),
+                );
+                Ok(res)
+            }
+        })
+        .service(get_data)
+        .service(optimize_route)
+        .service(ab_test_route)
+        .service(optimize_routes)
+        .service(get_jobs)
+        .service(get_job)
+        .service(job_ws)
+        .service(evaluate_route)
+        .service(propose_express)
+        .service(create_route)
+        .service(consolidate_routes)
+        .service(optimize_frequencies)
+        .service(evaluate_coverage)
+        .service(get_grid)
+        .service(reset_optimizations)
+        .service(optimize_live)
+        .service(update_live_params)
+        .service(get_optimizations)
+        .service(create_workspace)
+        .service(get_workspaces)
+        .service(activate_workspace)
+        .service(delete_workspace)
+        .service(get_avg_transfers)
+        .service(get_noop_route_ids)
+        .service(update_aco_params)
+        .service(tune_aco_params)
+        .service(get_operating_budget)
+        .service(update_operating_budget)
+        .service(get_objectives)
+        .service(rank_route_improvements)
+        .service(evaluate_network)
+        .service(get_route_improvements)
+        .service(optimize_network)
+        .service(get_import_report)
+        .service(get_dropped_routes)
+        .service(get_data_info)
+        .service(validate_gtfs)
+        .service(get_debug_memory)
+        .service(get_debug_stores)
+        .service(get_metrics)
+        .service(get_service_density)
+        .service(get_isochrones)
+        .service(get_coverage_isochrones)
+        .service(plan_journey)
+        .service(get_overlay)
+        .service(validate_route)
+        .service(get_route)
+        .service(get_poi_access)
+        .service(upload_gtfs)
+        .service(get_feeds)
+        .service(rollback_feed)
+        .service(activate_feed_version)
+        .service(get_route_reviews)
+        .service(accept_route)
+        .service(reject_route)
+        .service(get_route_history)
+        .service(undo_optimization)
+        .service(set_route_constraints)
+        .service(get_route_constraints)
+        .service(get_zones)
+        .service(get_demand_heatmap)
+        .service(optimize_route_events)
+        .service(get_city_summary)
+        .service(get_stop_impacts)
+        .service(evaluate_equity)
+        .service(get_network_diff)
+        .service(export_raster)
+        .service(export_gtfs)
+        .service(get_travel_time_matrix)
+        .service(ingest_realtime)
+        .service(get_realtime_observations)
+        .service(get_search_config)
+        .service(update_search_config)
+        .service(get_demand_model)
+        .service(update_demand_model)
+        .service(get_run_history)
+        .service(get_kpi_history)
+        .service(get_job_access)
+        .service(get_city_info)
+        .service(get_desire_lines)
+        .service(save_scenario)
+        .service(create_scenario)
+        .service(list_scenarios)
+        .service(get_scenario_geojson)
+        .service(get_route_timetable)
+        .service(get_route_ridership)
+        .service(get_walk_check)
+        .service(get_audit_log)
+        .service(optimize_area)
+        .service(get_optimization_queue)
+        .service(update_optimization_queue)
+}
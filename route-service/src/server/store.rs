@@ -0,0 +1,136 @@
+use lru::LruCache;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+/// Entries a long-lived server keeps between requests, bounded in number and optionally in
+/// age so that they cannot grow without limit
+///
+/// Inserting past `max_entries` evicts the least recently used entry. Entries not written for
+/// longer than the time to live are removed by `gc`, which the server runs periodically.
+pub(crate) struct BoundedStore<K: Hash + Eq, V> {
+    name: &'static str,
+    /// Entries with the last time they were written, least recently used last
+    entries: LruCache<K, (V, Instant)>,
+    ttl: Option<Duration>,
+    /// Entries evicted to stay within `max_entries`
+    evicted: u64,
+    /// Entries removed by `gc` for being older than `ttl`
+    expired: u64,
+}
+
+/// Size and evictions of a store, as reported by `/debug/stores`
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct StoreStats {
+    pub name: &'static str,
+    pub entries: usize,
+    pub max_entries: usize,
+    pub ttl_secs: Option<u64>,
+    pub evicted: u64,
+    pub expired: u64,
+}
+
+impl<K: Hash + Eq, V> BoundedStore<K, V> {
+    pub fn new(name: &'static str, max_entries: usize, ttl: Option<Duration>) -> Self {
+        BoundedStore {
+            name,
+            entries: LruCache::new(NonZeroUsize::new(max_entries.max(1)).unwrap()),
+            ttl,
+            evicted: 0,
+            expired: 0,
+        }
+    }
+
+    /// Insert or replace an entry, evicting the least recently used one if the store is full
+    pub fn insert(&mut self, key: K, value: V) {
+        let replaced = self.entries.contains(&key);
+        if self.entries.push(key, (value, Instant::now())).is_some() && !replaced {
+            self.evicted += 1;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Keys from the least to the most recently used
+    pub fn keys(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        self.entries.iter().rev().map(|(k, _)| k.clone()).collect()
+    }
+
+    pub fn to_map(&self) -> HashMap<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.entries
+            .iter()
+            .map(|(k, (v, _))| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    /// Remove the entries not written for longer than the time to live
+    ///
+    /// # Returns
+    /// The number of entries removed
+    pub fn gc(&mut self, now: Instant) -> usize {
+        let Some(ttl) = self.ttl else {
+            return 0;
+        };
+        let mut removed = 0;
+        while let Some((_, (_, used))) = self.entries.peek_lru() {
+            if now.saturating_duration_since(*used) <= ttl {
+                break;
+            }
+            self.entries.pop_lru();
+            removed += 1;
+        }
+        self.expired += removed as u64;
+        removed
+    }
+
+    pub fn stats(&self) -> StoreStats {
+        StoreStats {
+            name: self.name,
+            entries: self.entries.len(),
+            max_entries: self.entries.cap().get(),
+            ttl_secs: self.ttl.map(|ttl| ttl.as_secs()),
+            evicted: self.evicted,
+            expired: self.expired,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used_and_expired_entries() {
+        let mut store = BoundedStore::new("test", 2, Some(Duration::from_secs(60)));
+        store.insert("a", 1);
+        store.insert("b", 2);
+        // replacing a makes b the least recently used and evicts nothing
+        store.insert("a", 3);
+        store.insert("c", 4);
+        assert_eq!(store.keys(), vec!["a", "c"]);
+        assert_eq!(store.stats().evicted, 1);
+
+        assert_eq!(store.gc(Instant::now()), 0);
+        assert_eq!(store.gc(Instant::now() + Duration::from_secs(61)), 2);
+        let stats = store.stats();
+        assert_eq!((stats.entries, stats.expired, stats.max_entries), (0, 2, 2));
+
+        let mut forever = BoundedStore::new("forever", 1, None);
+        forever.insert("a", ());
+        assert_eq!(forever.gc(Instant::now() + Duration::from_secs(1 << 30)), 0);
+        assert_eq!(forever.stats().entries, 1);
+    }
+}
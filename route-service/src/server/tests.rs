@@ -0,0 +1,2413 @@
+use actix_web::{test, web, HttpServer};
+use futures::{SinkExt, StreamExt};
+use prost::Message;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+use super::notify::Webhook;
+use super::proxy::{build_proxy_app, CityConfig, CityLauncher};
+use super::server::{build_app, build_app_state, AppState, OptimizationLimits};
+use crate::gtfs::realtime::{
+    FeedEntity, FeedHeader, FeedMessage, StopTimeEvent, StopTimeUpdate, TripDescriptor, TripUpdate,
+};
+use crate::layers::{
+    city::City,
+    demo_city::{load_demo_city, DemoCity, DemoCityConfig},
+    grid::TimePeriod,
+    memory::MemoryMode,
+    transit_network::TransitRoute,
+};
+use crate::opt::aco2::{OptimizedTransitNetwork, PartialACO, ACO};
+use crate::opt::checkpoint::{Checkpoint, LiveProgress};
+use crate::opt::eval;
+use crate::opt::progress::ParamChange;
+
+/// Server state of a small synthetic city
+fn demo_state(name: &str) -> (String, web::Data<AppState>) {
+    let city_name = format!("server_{}_{}", name, std::process::id());
+    use_temp_cache_dir(&city_name);
+    let city = load_demo_city(
+        &city_name,
+        &DemoCityConfig {
+            cols: 8,
+            rows: 8,
+            routes: 3,
+            ..Default::default()
+        },
+    );
+    // where load_demo_city wrote the city, removed once it was loaded
+    let dir = std::env::temp_dir().join(&city_name);
+    let state = build_app_state(
+        &city_name,
+        Some(city),
+        dir.join("gtfs").to_str().unwrap(),
+        dir.join("demo.db").to_str().unwrap(),
+        None,
+        OptimizationLimits::default(),
+    );
+    (city_name, state)
+}
+
+/// Keep the caches and logs of a test city in a temporary directory of its own, so tests
+/// neither write to the working tree nor share files
+fn use_temp_cache_dir(city_name: &str) {
+    let dir = std::env::temp_dir().join(format!("{}_cache", city_name));
+    City::set_city_cache_dir(city_name, dir.to_str().unwrap());
+}
+
+/// Remove the caches and logs calls to the server wrote for the city
+fn remove_city_files(city_name: &str) {
+    std::fs::remove_dir_all(City::cache_dir(city_name)).ok();
+}
+
+fn route_ids(state: &AppState) -> Vec<String> {
+    let city = state.city.read().unwrap();
+    let mut route_ids: Vec<String> = city
+        .as_ref()
+        .unwrap()
+        .transit
+        .routes
+        .iter()
+        .map(|r| r.route_id.clone())
+        .collect();
+    route_ids.sort();
+    route_ids
+}
+
+#[actix_web::test]
+async fn optimize_evaluate_and_reset_routes() {
+    let (city_name, state) = demo_state("http");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    let req = test::TestRequest::get().uri("/get-data").to_request();
+    let data: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(data["type"], "FeatureCollection");
+    assert!(!data["features"].as_array().unwrap().is_empty());
+    let directions: HashSet<&str> = data["features"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|f| f["properties"]["direction"].as_str())
+        .collect();
+    assert_eq!(directions, HashSet::from(["outbound", "inbound"]));
+
+    // every route of the demo feed has trips in the network
+    let req = test::TestRequest::get().uri("/dropped-routes").to_request();
+    let dropped: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(dropped["feed_routes"], dropped["network_routes"]);
+    assert!(dropped["routes"].as_array().unwrap().is_empty());
+
+    let req = test::TestRequest::get().uri("/debug/memory").to_request();
+    let memory: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(memory["mode"], "standard");
+    assert!(memory["max_rss_kb"].as_u64().unwrap() > 0);
+    assert!(memory["grid"]["lazy_links"].is_null());
+
+    let req = test::TestRequest::get().uri("/stop-impacts").to_request();
+    let impacts: Value = test::call_and_read_body_json(&app, req).await;
+    let stops = impacts["features"].as_array().unwrap().len();
+    assert!(stops > 0);
+    assert_eq!(impacts["summary"]["unchanged"], stops);
+    assert_eq!(impacts["summary"]["routes_changed"], 0);
+
+    let req = test::TestRequest::get().uri("/network-diff").to_request();
+    let diff: Value = test::call_and_read_body_json(&app, req).await;
+    assert!(diff["routes"].as_array().unwrap().is_empty());
+    assert!(diff["zones"].as_array().unwrap().is_empty());
+    assert_eq!(diff["length_km_before"], diff["length_km_after"]);
+
+    let route_ids = route_ids(&state);
+    let req = test::TestRequest::post()
+        .uri(&format!("/optimize-route/{}?objective=bogus", route_ids[0]))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::post()
+        .uri("/optimize-route/missing")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/propose-express/{}?stop_share=0.3", route_ids[0]))
+        .to_request();
+    let express: Value = test::call_and_read_body_json(&app, req).await;
+    let proposal = &express["proposal"];
+    assert_eq!(
+        proposal["express_route_id"],
+        format!("{}-express", route_ids[0])
+    );
+    let express_stops = proposal["express_stop_ids"].as_array().unwrap().len();
+    assert!(express_stops >= 2 && proposal["skipped_stops"].as_u64().unwrap() > 0);
+    assert!(proposal["combined"]["load_factor_after"].is_object());
+    let variants: HashSet<&str> = express["geojson"]["features"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|f| f["properties"]["variant"].as_str())
+        .collect();
+    assert_eq!(variants, HashSet::from(["local", "express"]));
+    let req = test::TestRequest::post()
+        .uri(&format!("/propose-express/{}?stop_share=1.5", route_ids[0]))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    // proposing an express leaves the network as is
+    assert!(state.optimized_route_ids.lock().unwrap().is_empty());
+
+    let req = test::TestRequest::post()
+        .uri("/optimize-frequencies")
+        .set_json(serde_json::json!({ "fleet_size": 1000, "dry_run": true }))
+        .to_request();
+    let frequencies: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(frequencies["applied"], false);
+    assert_eq!(
+        frequencies["plan"]["routes"].as_array().unwrap().len(),
+        route_ids.len()
+    );
+    assert_eq!(frequencies["plan"]["fleet"].as_array().unwrap().len(), 5);
+    let req = test::TestRequest::post()
+        .uri("/optimize-frequencies")
+        .set_json(serde_json::json!({ "fleet_size": 0 }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    // not every route of the city can be improved, those that cannot are marked as noop
+    let mut optimized = None;
+    for route_id in &route_ids {
+        let req = test::TestRequest::post()
+            .uri(&format!("/optimize-route/{}", route_id))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        if res.status().is_success() {
+            optimized = Some((
+                route_id.clone(),
+                test::read_body_json::<Value, _>(res).await,
+            ));
+            break;
+        }
+        assert_eq!(res.status(), 422);
+        let error: Value = test::read_body_json(res).await;
+        assert_eq!(error["code"], "not_optimized");
+        assert!(error["details"]["resources"].is_object());
+        assert!(state
+            .noop_route_ids
+            .lock()
+            .unwrap()
+            .keys()
+            .contains(route_id));
+    }
+    let (route_id, optimized) = optimized.expect("no route of the city could be optimized");
+    assert_eq!(optimized["geojson"]["type"], "FeatureCollection");
+    assert!(optimized["evaluation"].is_number());
+    assert_eq!(optimized["objective"]["demand_per_km"], 1.0);
+    assert!(optimized["resources"]["wall_ms"].is_number());
+    assert_eq!(
+        *state.optimized_route_ids.lock().unwrap(),
+        vec![route_id.clone()]
+    );
+    assert!(state
+        .route_pheromones
+        .lock()
+        .unwrap()
+        .keys()
+        .contains(&route_id));
+    // the optimized route runs back along its new outbound stops
+    {
+        let transit = state.optimized_transit.read().unwrap();
+        let route = transit
+            .as_ref()
+            .unwrap()
+            .routes
+            .iter()
+            .find(|r| r.route_id == route_id)
+            .unwrap();
+        assert!(!route.inbound_stops.is_empty());
+    }
+
+    let req = test::TestRequest::get().uri("/network-diff").to_request();
+    let diff: Value = test::call_and_read_body_json(&app, req).await;
+    let routes = diff["routes"].as_array().unwrap();
+    assert_eq!(routes.len(), 1);
+    let change = &routes[0];
+    assert_eq!(change["route_id"], route_id);
+    assert_eq!(change["change"], "changed");
+    assert!(change["length_km_before"].as_f64().unwrap() > 0.0);
+    let stops_changed = ["added_stops", "removed_stops", "moved_stops"]
+        .iter()
+        .map(|key| change[key].as_array().unwrap().len())
+        .sum::<usize>();
+    assert!(stops_changed > 0);
+    for moved in change["moved_stops"].as_array().unwrap() {
+        assert!(moved["meters"].as_f64().unwrap() <= 400.0);
+    }
+
+    let req = test::TestRequest::get().uri("/stop-impacts").to_request();
+    let impacts: Value = test::call_and_read_body_json(&app, req).await;
+    let summary = &impacts["summary"];
+    let features = impacts["features"].as_array().unwrap();
+    assert_eq!(
+        ["gained", "lost", "unchanged"]
+            .iter()
+            .map(|kind| summary[kind].as_u64().unwrap())
+            .sum::<u64>(),
+        features.len() as u64
+    );
+    assert!(summary["routes_changed"].as_u64().unwrap() > 0);
+    for feature in features {
+        let properties = &feature["properties"];
+        let (before, after) = (
+            properties["routes_before"].as_array().unwrap(),
+            properties["routes_after"].as_array().unwrap(),
+        );
+        let expected = match (before.is_empty(), after.is_empty()) {
+            (true, _) => "gained",
+            (_, true) => "lost",
+            _ => "unchanged",
+        };
+        assert_eq!(properties["impact"], expected);
+    }
+
+    // identical parameters and seeds give identical routes
+    let ab_test = |a: Value, b: Value| {
+        test::TestRequest::post()
+            .uri(&format!("/ab-test-route/{}", route_id))
+            .set_json(serde_json::json!({ "a": a, "b": b, "seed": 7 }))
+            .to_request()
+    };
+    let same = serde_json::json!({ "max_gen": 10, "num_ant": 10 });
+    let ab: Value = test::call_and_read_body_json(&app, ab_test(same.clone(), same)).await;
+    assert_eq!(ab["seed"], 7);
+    assert_eq!(ab["a"]["stop_ids"], ab["b"]["stop_ids"]);
+    assert_eq!(ab["diff"]["identical"], true);
+    assert!(ab["a"]["evals"]["avg_ridership"].is_number());
+    let ab: Value = test::call_and_read_body_json(
+        &app,
+        ab_test(
+            serde_json::json!({ "max_gen": 10 }),
+            serde_json::json!({ "max_gen": 1, "num_ant": 1, "seed": 99 }),
+        ),
+    )
+    .await;
+    assert_eq!(
+        (ab["a"]["max_gen"].as_u64(), ab["b"]["max_gen"].as_u64()),
+        (Some(10), Some(1))
+    );
+    let shared = ab["diff"]["shared_stops"].as_u64().unwrap() as usize;
+    assert_eq!(
+        shared + ab["diff"]["stops_only_in_first"].as_array().unwrap().len(),
+        ab["a"]["stop_ids"].as_array().unwrap().len()
+    );
+    let req = ab_test(
+        serde_json::json!({ "objective": { "bogus": 1.0 } }),
+        serde_json::json!({}),
+    );
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    for uri in [
+        "/evaluate-network",
+        "/evaluate-network?include_proposed=true",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let metrics: Value = test::call_and_read_body_json(&app, req).await;
+        for network in ["original", "optimized"] {
+            for metric in [
+                "coverage",
+                "economic_score",
+                "avg_transfers",
+                "transit_score",
+            ] {
+                assert!(
+                    metrics[network][metric].is_number(),
+                    "{} {}",
+                    network,
+                    metric
+                );
+            }
+        }
+    }
+    // every evaluation is recorded in the KPI history
+    let req = test::TestRequest::get()
+        .uri("/kpi-history?limit=1")
+        .to_request();
+    let history: Value = test::call_and_read_body_json(&app, req).await;
+    let records = history["records"].as_array().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["include_proposed"], true);
+    assert!(records[0]["optimized"]["transit_score"].is_number());
+    let req = test::TestRequest::get()
+        .uri("/kpi-history?since=2000-01-01T00:00:00Z")
+        .to_request();
+    let history: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(history["records"].as_array().unwrap().len(), 2);
+    let req = test::TestRequest::get()
+        .uri("/kpi-history?since=yesterday")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    let req = test::TestRequest::get().uri("/debug/stores").to_request();
+    let stores: Value = test::call_and_read_body_json(&app, req).await;
+    let pheromones = &stores["stores"][1];
+    assert_eq!(pheromones["name"], "route_pheromones");
+    assert!(pheromones["entries"].as_u64().unwrap() >= 1);
+    assert_eq!(pheromones["evicted"], 0);
+    let jobs = &stores["stores"][2];
+    assert_eq!(jobs["name"], "jobs");
+    assert_eq!(jobs["max_entries"], 100);
+
+    let req = test::TestRequest::post()
+        .uri("/reset-optimizations")
+        .to_request();
+    let reset: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(reset["message"], "All route optimizations reset");
+    assert!(state.optimized_route_ids.lock().unwrap().is_empty());
+    assert!(state.noop_route_ids.lock().unwrap().keys().is_empty());
+    assert!(state.route_pheromones.lock().unwrap().keys().is_empty());
+    let original_routes = {
+        let city = state.city.read().unwrap();
+        serde_json::to_value(&city.as_ref().unwrap().transit.routes).unwrap()
+    };
+    let reset_routes = {
+        let transit = state.optimized_transit.read().unwrap();
+        serde_json::to_value(&transit.as_ref().unwrap().routes).unwrap()
+    };
+    assert_eq!(original_routes, reset_routes);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn optimize_live_streams_progress_until_the_batch_finishes() {
+    let (city_name, state) = demo_state("ws");
+    let route_ids = route_ids(&state);
+
+    let server_city = city_name.clone();
+    let server_state = state.clone();
+    let server = HttpServer::new(move || build_app(server_state.clone(), &server_city))
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+    let addr = server.addrs()[0];
+    let server = server.run();
+    let handle = server.handle();
+    actix_rt::spawn(server);
+
+    let (_, mut socket) = awc::Client::new()
+        .ws(format!(
+            "ws://{}/optimize-live?route_ids={}",
+            addr,
+            route_ids.join(",")
+        ))
+        .max_frame_size(64 * 1024 * 1024)
+        .connect()
+        .await
+        .unwrap();
+    let mut events = vec![];
+    while let Some(frame) = socket.next().await {
+        match frame.unwrap() {
+            awc::ws::Frame::Text(text) => {
+                let event: Value = serde_json::from_slice(&text).unwrap();
+                if event["event"] == "started" {
+                    // change the parameters of the session both ways while it runs
+                    let session_id = event["session_id"].as_u64().unwrap();
+                    let update = serde_json::json!({
+                        "type": "update_params",
+                        "params": { "max_nonlinearity": 2.5 },
+                    });
+                    socket
+                        .send(awc::ws::Message::Text(update.to_string().into()))
+                        .await
+                        .unwrap();
+                    let client = awc::Client::new();
+                    let url = format!("http://{}/optimize-live/{}/params", addr, session_id);
+                    let mut res = client
+                        .post(&url)
+                        .send_json(&serde_json::json!({ "num_ant": 5 }))
+                        .await
+                        .unwrap();
+                    assert_eq!(res.status(), 200);
+                    let change: Value = res.json().await.unwrap();
+                    assert_eq!(change["change"]["source"], "rest");
+                    let res = client
+                        .post(&url)
+                        .send_json(&serde_json::json!({ "objective": { "bogus": 1.0 } }))
+                        .await
+                        .unwrap();
+                    assert_eq!(res.status(), 400);
+                    let res = client
+                        .post(format!("http://{}/optimize-live/0/params", addr))
+                        .send_json(&serde_json::json!({}))
+                        .await
+                        .unwrap();
+                    assert_eq!(res.status(), 404);
+                }
+                let done = event["event"] == "batch_finished";
+                events.push(event);
+                if done {
+                    break;
+                }
+            }
+            awc::ws::Frame::Close(_) => break,
+            _ => {}
+        }
+    }
+    handle.stop(true).await;
+
+    let names: Vec<&str> = events.iter().filter_map(|e| e["event"].as_str()).collect();
+    assert_eq!(names.first(), Some(&"started"));
+    assert_eq!(events[0]["routes"], serde_json::json!(route_ids));
+    assert_eq!(names.last(), Some(&"batch_finished"));
+    assert!(!names.contains(&"error"), "{:?}", names);
+    assert!(names.contains(&"route_optimized"), "{:?}", names);
+    // the generations of an iteration are sent before its outcome
+    let first_optimized = names.iter().position(|&n| n == "route_optimized").unwrap();
+    assert!(names[..first_optimized].contains(&"generation_completed"));
+    let finished = events.last().unwrap();
+    assert_eq!(
+        finished["optimize_attempts"].as_array().unwrap().len(),
+        route_ids.len()
+    );
+    // the session log has both changes, the server's parameters are left as they are
+    assert_eq!(names.iter().filter(|&&n| n == "params_updated").count(), 2);
+    let changes = finished["param_changes"].as_array().unwrap();
+    let sources: HashSet<&str> = changes
+        .iter()
+        .filter_map(|c| c["source"].as_str())
+        .collect();
+    assert_eq!(sources, HashSet::from(["websocket", "rest"]));
+    assert_eq!(state.aco_params.lock().unwrap().max_nonlinearity, 2.0);
+    // the session is forgotten once its actor stops, which may be after the last event
+    for _ in 0..50 {
+        if state.live_sessions.lock().unwrap().is_empty() {
+            break;
+        }
+        actix_rt::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(state.live_sessions.lock().unwrap().is_empty());
+    let optimized_route_ids = state.optimized_route_ids.lock().unwrap();
+    assert!(!optimized_route_ids.is_empty());
+    assert!(optimized_route_ids.iter().all(|id| route_ids.contains(id)));
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn optimize_live_resumes_from_its_checkpoint() {
+    let (city_name, state) = demo_state("ws_resume");
+    let route_id = route_ids(&state)[0].clone();
+
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/optimize-live?route_ids={}&resume=true",
+            route_id
+        ))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    // a session that optimized the route 9 times out of 10 before it stopped
+    let mut network = state.city.read().unwrap().as_ref().unwrap().transit.clone();
+    let route = network
+        .routes
+        .iter_mut()
+        .find(|r| r.route_id == route_id)
+        .unwrap();
+    route.outbound_stops.reverse();
+    let checkpointed_stops: Vec<String> = route
+        .outbound_stops
+        .iter()
+        .map(|s| s.stop_id.clone())
+        .collect();
+    let checkpoint = Checkpoint {
+        saved_at: chrono::Local::now().to_rfc3339(),
+        route_ids: vec![route_id.clone()],
+        progress: 9,
+        network: OptimizedTransitNetwork {
+            network,
+            optimized_routes: vec![route_id.clone()],
+        },
+        pheromones: Default::default(),
+        live: Some(LiveProgress {
+            converged_routes: vec![false],
+            optimize_attempts: vec![9],
+            param_changes: vec![ParamChange {
+                iteration: 3,
+                source: "websocket".to_string(),
+                changes: PartialACO {
+                    max_nonlinearity: Some(2.5),
+                    ..Default::default()
+                },
+            }],
+        }),
+    };
+    let name = Checkpoint::live_name(std::slice::from_ref(&route_id));
+    City::save_checkpoint(&city_name, &name, &checkpoint).unwrap();
+
+    let server_city = city_name.clone();
+    let server_state = state.clone();
+    let server = HttpServer::new(move || build_app(server_state.clone(), &server_city))
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+    let addr = server.addrs()[0];
+    let server = server.run();
+    let handle = server.handle();
+    actix_rt::spawn(server);
+
+    let (_, mut socket) = awc::Client::new()
+        .ws(format!(
+            "ws://{}/optimize-live?route_ids={}&resume=true",
+            addr, route_id
+        ))
+        .max_frame_size(64 * 1024 * 1024)
+        .connect()
+        .await
+        .unwrap();
+    let mut events = vec![];
+    while let Some(frame) = socket.next().await {
+        match frame.unwrap() {
+            awc::ws::Frame::Text(text) => {
+                let event: Value = serde_json::from_slice(&text).unwrap();
+                let done = event["event"] == "batch_finished";
+                events.push(event);
+                if done {
+                    break;
+                }
+            }
+            awc::ws::Frame::Close(_) => break,
+            _ => {}
+        }
+    }
+    handle.stop(true).await;
+
+    assert_eq!(events[0]["event"], "started");
+    assert_eq!(events[0]["resumed_at"], 9);
+    let finished = events.last().unwrap();
+    assert_eq!(finished["event"], "batch_finished");
+    assert_eq!(finished["optimize_attempts"], serde_json::json!([10]));
+    assert_eq!(
+        finished["param_changes"][0]["changes"]["max_nonlinearity"],
+        2.5
+    );
+    assert!(state
+        .optimized_route_ids
+        .lock()
+        .unwrap()
+        .contains(&route_id));
+    // the route carried on from the checkpoint, unless the last iteration improved it again
+    if !events.iter().any(|e| e["event"] == "route_optimized") {
+        let optimized_transit = state.optimized_transit.read().unwrap();
+        let route = optimized_transit
+            .as_ref()
+            .unwrap()
+            .routes
+            .iter()
+            .find(|r| r.route_id == route_id)
+            .unwrap();
+        let stops: Vec<String> = route
+            .outbound_stops
+            .iter()
+            .map(|s| s.stop_id.clone())
+            .collect();
+        assert_eq!(stops, checkpointed_stops);
+    }
+    // the session finished, there is nothing left to resume
+    assert!(City::load_checkpoint(&city_name, &name).is_err());
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn workspaces_keep_optimizations_apart() {
+    let (city_name, state) = demo_state("workspaces");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let route_ids = route_ids(&state);
+
+    let req = test::TestRequest::post()
+        .uri("/workspaces")
+        .set_json(serde_json::json!({ "name": "alt" }))
+        .to_request();
+    let created: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(created["optimized_routes"], 0);
+    for (body, status) in [
+        (serde_json::json!({ "name": "alt" }), 409),
+        (serde_json::json!({ "name": "../alt" }), 400),
+        (
+            serde_json::json!({ "name": "copy", "from": "missing" }),
+            404,
+        ),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/workspaces")
+            .set_json(body)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), status);
+    }
+
+    // optimizing in an inactive workspace leaves the active network as is
+    let req = test::TestRequest::post()
+        .uri(&format!(
+            "/optimize-route/{}?workspace=missing",
+            route_ids[0]
+        ))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    let mut optimized = None;
+    for route_id in &route_ids {
+        let req = test::TestRequest::post()
+            .uri(&format!("/optimize-route/{}?workspace=alt", route_id))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        if res.status().is_success() {
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body["workspace"], "alt");
+            optimized = Some(route_id.clone());
+            break;
+        }
+    }
+    let route_id = optimized.expect("no route of the city could be optimized");
+    assert!(state.optimized_route_ids.lock().unwrap().is_empty());
+
+    let req = test::TestRequest::get()
+        .uri("/get-optimizations?workspace=alt")
+        .to_request();
+    let alt: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(alt["routes"], serde_json::json!([route_id]));
+    let req = test::TestRequest::get()
+        .uri("/get-optimizations")
+        .to_request();
+    let default: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(default["workspace"], "default");
+    assert_eq!(default["features"], serde_json::json!([]));
+
+    let req = test::TestRequest::post()
+        .uri("/workspaces/alt/activate")
+        .to_request();
+    let activated: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(activated["active"], "alt");
+    assert_eq!(*state.optimized_route_ids.lock().unwrap(), vec![route_id]);
+    let req = test::TestRequest::get().uri("/workspaces").to_request();
+    let listed: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(listed["active"], "alt");
+    assert_eq!(
+        listed["workspaces"],
+        serde_json::json!([
+            { "name": "alt", "active": true, "optimized_routes": 1 },
+            { "name": "default", "active": false, "optimized_routes": 0 },
+        ])
+    );
+
+    let req = test::TestRequest::post()
+        .uri("/workspaces/missing/activate")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    assert_eq!(state.optimized_route_ids.lock().unwrap().len(), 1);
+    for (name, status) in [("alt", 409), ("default", 200), ("default", 404)] {
+        let req = test::TestRequest::delete()
+            .uri(&format!("/workspaces/{}", name))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), status);
+    }
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn get_data_draws_routes_of_feeds_without_shapes() {
+    let (city_name, state) = demo_state("no_shapes");
+    {
+        let mut city = state.city.write().unwrap();
+        let gtfs = &mut city.as_mut().unwrap().gtfs;
+        gtfs.shapes.clear();
+        for trip in gtfs.trips.values_mut().flatten() {
+            trip.shape_id = None;
+        }
+    }
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    let req = test::TestRequest::get().uri("/get-data").to_request();
+    let data: Value = test::call_and_read_body_json(&app, req).await;
+    let routes: Vec<&Value> = data["features"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|f| f["geometry"]["type"] == "LineString")
+        .collect();
+    assert!(!routes.is_empty());
+    for route in routes {
+        assert_eq!(route["properties"]["synthesized_geometry"], true);
+        // road paths pass through nodes between the stops
+        let coords = route["geometry"]["coordinates"].as_array().unwrap();
+        let stops = route["properties"]["route_stops"].as_array().unwrap();
+        assert!(coords.len() >= stops.len());
+    }
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn get_zones_rejects_bboxes_with_bad_parts() {
+    let (city_name, state) = demo_state("zones_bbox");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    for (bbox, status) in [
+        ("-180,-90,180,90", 200),
+        ("1,2,x,3,4", 400),
+        ("1,2,3", 400),
+        ("1,2,3,4,5", 400),
+    ] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/zones?bbox={}", bbox))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), status);
+    }
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn create_route_proposes_a_new_route() {
+    let (city_name, state) = demo_state("create_route");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let (from, to) = {
+        let city = state.city.read().unwrap();
+        let routes = &city.as_ref().unwrap().transit.routes;
+        (
+            routes[0].outbound_stops[0].stop_id.clone(),
+            routes[1].outbound_stops.last().unwrap().stop_id.clone(),
+        )
+    };
+
+    let req = test::TestRequest::post()
+        .uri("/create-route")
+        .set_json(serde_json::json!({ "from": { "stop": "missing" }, "to": { "stop": to } }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::post()
+        .uri("/create-route")
+        .set_json(
+            serde_json::json!({ "from": { "stop": from }, "to": { "stop": to }, "dry_run": true }),
+        )
+        .to_request();
+    let preview: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(preview["route_id"], "new-1");
+    assert!(state.optimized_route_ids.lock().unwrap().is_empty());
+
+    let req = test::TestRequest::post()
+        .uri("/create-route")
+        .set_json(serde_json::json!({ "from": { "stop": from }, "to": { "stop": to } }))
+        .to_request();
+    let created: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(created["route_id"], "new-1");
+    assert_eq!(created["outbound_stop_ids"][0], from);
+    assert!(created["evaluation"]["avg_ridership"].is_number());
+    assert_eq!(created["geojson"]["type"], "FeatureCollection");
+
+    let req = test::TestRequest::get()
+        .uri("/get-optimizations")
+        .to_request();
+    let optimizations: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(optimizations["routes"], serde_json::json!(["new-1"]));
+    let req = test::TestRequest::post()
+        .uri("/accept-route/new-1")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    let req = test::TestRequest::get()
+        .uri("/evaluate-network")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn consolidate_routes_leaves_the_network_as_is() {
+    let (city_name, state) = demo_state("consolidate");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/consolidate-routes")
+        .set_json(serde_json::json!({ "min_overlap": 2.0 }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::post()
+        .uri("/consolidate-routes")
+        .set_json(serde_json::json!({ "min_overlap": 0.1 }))
+        .to_request();
+    let consolidated: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(consolidated["params"]["max_pairs"], 10);
+    assert_eq!(consolidated["geojson"]["type"], "FeatureCollection");
+    for proposal in consolidated["proposals"].as_array().unwrap() {
+        assert!(proposal["stop_overlap"].as_f64().unwrap() >= 0.1);
+        assert!(proposal["before"][proposal["kept_route_id"].as_str().unwrap()].is_object());
+    }
+    let routes = state
+        .optimized_transit
+        .read()
+        .unwrap()
+        .as_ref()
+        .unwrap()
+        .routes
+        .len();
+    assert_eq!(routes, route_ids(&state).len());
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn export_gtfs_zips_the_optimized_network() {
+    let (city_name, state) = demo_state("export_gtfs");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/export-gtfs?format=json")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::get()
+        .uri("/export-gtfs?scenario=missing")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    let req = test::TestRequest::get()
+        .uri("/export-gtfs?format=zip")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/zip"
+    );
+    let body = test::read_body(resp).await;
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body.to_vec())).unwrap();
+    let files: HashSet<&str> = archive.file_names().collect();
+    for file in [
+        "agency.txt",
+        "stops.txt",
+        "routes.txt",
+        "trips.txt",
+        "stop_times.txt",
+        "shapes.txt",
+        "calendar.txt",
+    ] {
+        assert!(files.contains(file), "{} missing from the export", file);
+    }
+    let mut trips = String::new();
+    std::io::Read::read_to_string(&mut archive.by_name("trips.txt").unwrap(), &mut trips).unwrap();
+    // one trip per direction of each route
+    assert_eq!(trips.lines().count() - 1, 2 * route_ids(&state).len());
+
+    let req = test::TestRequest::get()
+        .uri("/export-gtfs?avg_speed_kmh=0")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::get()
+        .uri("/export-gtfs?explicit_trips=true&avg_speed_kmh=15")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body = test::read_body(resp).await;
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body.to_vec())).unwrap();
+    let mut trips = String::new();
+    std::io::Read::read_to_string(&mut archive.by_name("trips.txt").unwrap(), &mut trips).unwrap();
+    // every departure is its own trip
+    assert!(trips.lines().count() - 1 > 2 * route_ids(&state).len());
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn ingest_realtime_observes_headways_and_delays() {
+    let (city_name, state) = demo_state("realtime");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let route_id = route_ids(&state)[0].clone();
+
+    let req = test::TestRequest::post()
+        .uri("/ingest-realtime")
+        .set_payload("not protobuf")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    // 2025-03-04 08:00 in Toronto, two trips 15 minutes apart, the second one late
+    let am_rush = 1741093200;
+    let entity = |trip_id: &str, time: i64, delay: i32| FeedEntity {
+        id: trip_id.to_string(),
+        trip_update: Some(TripUpdate {
+            trip: TripDescriptor {
+                trip_id: Some(trip_id.to_string()),
+                route_id: Some(route_id.clone()),
+                ..Default::default()
+            },
+            stop_time_update: vec![StopTimeUpdate {
+                stop_id: Some("S1".to_string()),
+                arrival: Some(StopTimeEvent {
+                    delay: Some(delay),
+                    time: Some(time),
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let feed = FeedMessage {
+        header: FeedHeader {
+            gtfs_realtime_version: "2.0".to_string(),
+            timestamp: Some(am_rush as u64),
+        },
+        entity: vec![entity("a", am_rush, 0), entity("b", am_rush + 900, 120)],
+    };
+    let req = test::TestRequest::post()
+        .uri("/ingest-realtime")
+        .set_payload(feed.encode_to_vec())
+        .to_request();
+    let ingested: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ingested["entities"], 2);
+    assert_eq!(ingested["unmatched_entities"], 0);
+
+    let req = test::TestRequest::get()
+        .uri("/realtime-observations")
+        .to_request();
+    let observations: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(observations["snapshots"], 1);
+    let service = &observations["routes"][&route_id]["AmRush"];
+    assert_eq!(service["mean_headway_secs"], 900.0);
+    assert_eq!(service["mean_delay_secs"], 60.0);
+    assert_eq!(service["observed_departures"], 10.0);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn isochrones_cover_the_walk_shed_of_stops() {
+    let (city_name, state) = demo_state("isochrones");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let stop_id = {
+        let city = state.city.read().unwrap();
+        city.as_ref().unwrap().transit.routes[0].outbound_stops[0]
+            .stop_id
+            .clone()
+    };
+
+    let req = test::TestRequest::get()
+        .uri("/isochrones?stop_id=missing")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    let req = test::TestRequest::get()
+        .uri(&format!("/isochrones?stop_id={}&minutes=5,90", stop_id))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/isochrones?stop_id={}&minutes=5,10", stop_id))
+        .to_request();
+    let isochrones: Value = test::call_and_read_body_json(&app, req).await;
+    let features = isochrones["features"].as_array().unwrap();
+    assert_eq!(features.len(), 2);
+    assert_eq!(features[0]["properties"]["minutes"], 10);
+    assert_eq!(features[0]["properties"]["stop_id"], stop_id.as_str());
+    assert_eq!(features[0]["geometry"]["type"], "Polygon");
+
+    let req = test::TestRequest::get()
+        .uri("/coverage-isochrones")
+        .to_request();
+    let coverage: Value = test::call_and_read_body_json(&app, req).await;
+    assert!(!coverage["features"].as_array().unwrap().is_empty());
+    let minutes: Vec<u64> = coverage["coverage"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["minutes"].as_u64().unwrap())
+        .collect();
+    assert_eq!(minutes, vec![15, 10, 5]);
+    assert!(coverage["coverage"][0]["population"].as_u64().unwrap() > 0);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn optimize_routes_runs_as_a_background_job() {
+    let (city_name, state) = demo_state("jobs");
+    let route_ids = route_ids(&state);
+
+    let server_city = city_name.clone();
+    let server_state = state.clone();
+    let server = HttpServer::new(move || build_app(server_state.clone(), &server_city))
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+    let addr = server.addrs()[0];
+    let server = server.run();
+    let handle = server.handle();
+    actix_rt::spawn(server);
+    let client = awc::Client::new();
+
+    for (body, status) in [
+        (serde_json::json!({ "routes": [] }), 400),
+        (
+            serde_json::json!({ "routes": route_ids, "workspace": "missing" }),
+            404,
+        ),
+    ] {
+        let res = client
+            .post(format!("http://{}/optimize-routes", addr))
+            .send_json(&body)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), status);
+    }
+    let res = client
+        .get(format!("http://{}/jobs/0", addr))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+
+    let mut res = client
+        .post(format!("http://{}/optimize-routes", addr))
+        .send_json(&serde_json::json!({ "routes": route_ids }))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 202);
+    let queued: Value = res.json().await.unwrap();
+    let job_id = queued["job_id"].as_u64().unwrap();
+
+    // other endpoints answer while the job runs
+    let res = client
+        .get(format!("http://{}/workspaces", addr))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let (_, mut socket) = client
+        .ws(format!("ws://{}/jobs/{}/ws", addr, job_id))
+        .max_frame_size(64 * 1024 * 1024)
+        .connect()
+        .await
+        .unwrap();
+    let mut events = vec![];
+    while let Some(frame) = socket.next().await {
+        match frame.unwrap() {
+            awc::ws::Frame::Text(text) => {
+                events.push(serde_json::from_slice::<Value>(&text).unwrap());
+            }
+            awc::ws::Frame::Close(_) => break,
+            _ => {}
+        }
+    }
+
+    let mut res = client
+        .get(format!("http://{}/jobs/{}", addr, job_id))
+        .send()
+        .await
+        .unwrap();
+    let job: Value = res.json().await.unwrap();
+    let mut res = client
+        .get(format!("http://{}/jobs", addr))
+        .send()
+        .await
+        .unwrap();
+    let jobs: Value = res.json().await.unwrap();
+    handle.stop(true).await;
+
+    let names: Vec<&str> = events.iter().filter_map(|e| e["event"].as_str()).collect();
+    // the socket gets the events reported after it connected, then the last message
+    assert_eq!(names.last(), Some(&"job_finished"));
+    assert!(!names[..names.len() - 1].contains(&"job_finished"));
+    assert_eq!(events.last().unwrap()["job"]["status"], "succeeded");
+    assert_eq!(job["status"], "succeeded", "{}", job["error"]);
+    assert_eq!(job["kind"], "optimize-routes");
+    assert!(job["events"].as_u64().unwrap() > 0);
+    assert!(!job["result"]["geojson"]["features"]
+        .as_array()
+        .unwrap()
+        .is_empty());
+    assert_eq!(jobs[0]["id"], job_id);
+    let optimized_route_ids = state.optimized_route_ids.lock().unwrap();
+    assert_eq!(
+        job["result"]["batch"]["optimized_route_ids"]
+            .as_array()
+            .unwrap()
+            .len(),
+        optimized_route_ids.len()
+    );
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn optimize_routes_notifies_the_webhook_of_failed_jobs() {
+    let received = web::Data::new(std::sync::Mutex::new(Vec::<Value>::new()));
+    let hook_received = received.clone();
+    let hook = HttpServer::new(move || {
+        actix_web::App::new().app_data(hook_received.clone()).route(
+            "/hook",
+            web::post().to(
+                |body: web::Json<Value>, received: web::Data<std::sync::Mutex<Vec<Value>>>| async move {
+                    received.lock().unwrap().push(body.into_inner());
+                    actix_web::HttpResponse::Ok().finish()
+                },
+            ),
+        )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let hook_url = format!("http://{}/hook", hook.addrs()[0]);
+    let hook = hook.run();
+    let hook_handle = hook.handle();
+    actix_rt::spawn(hook);
+
+    // the city's webhook is notified, even on the loopback interface
+    let (city_name, state) = demo_state("failed_job_webhook");
+    let city = state.city.write().unwrap().take();
+    let state = build_app_state(
+        &city_name,
+        city,
+        &state.gtfs_path,
+        &state.db_path,
+        Some(Webhook::configured(hook_url.clone())),
+        OptimizationLimits::default(),
+    );
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let route_ids = route_ids(&state);
+
+    // requests cannot make the server post to its own network
+    let req = test::TestRequest::post()
+        .uri("/optimize-routes")
+        .set_json(serde_json::json!({ "routes": route_ids, "webhook_url": hook_url }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    // the job fails before its batch runs while another request optimizes the routes
+    let busy = state.route_locks.try_lock("default", &route_ids).unwrap();
+    let req = test::TestRequest::post()
+        .uri("/optimize-routes")
+        .set_json(serde_json::json!({ "routes": route_ids }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 202);
+
+    let mut summary = None;
+    for _ in 0..100 {
+        if let Some(body) = received.lock().unwrap().first() {
+            summary = Some(body.clone());
+            break;
+        }
+        actix_rt::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    drop(busy);
+    hook_handle.stop(true).await;
+
+    let summary = summary.expect("the webhook was not notified");
+    assert_eq!(summary["status"], "failed");
+    assert_eq!(summary["city"], city_name.as_str());
+    assert_eq!(
+        summary["routes_failed"].as_array().unwrap().len(),
+        route_ids.len()
+    );
+    assert!(!summary["errors"].as_array().unwrap().is_empty());
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn plan_journey_rides_between_two_points() {
+    let (city_name, state) = demo_state("plan_journey");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let (from, to) = {
+        let city = state.city.read().unwrap();
+        let stops = &city.as_ref().unwrap().transit.routes[0].outbound_stops;
+        let (a, b) = (stops[0].geom, stops[stops.len() - 1].geom);
+        (
+            format!("{},{}", a.x(), a.y()),
+            format!("{},{}", b.x(), b.y()),
+        )
+    };
+
+    for query in [
+        format!("from=nowhere&to={}", to),
+        format!("from={}&to={}&depart_at=8am", from, to),
+        format!("from={}&to={}&max_rides=0", from, to),
+    ] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/plan-journey?{}", query))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/plan-journey?from={}&to={}&depart_at=08:30&optimized=true",
+            from, to
+        ))
+        .to_request();
+    let planned: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(planned["network"], "optimized");
+    let journeys = planned["journeys"].as_array().unwrap();
+    assert!(!journeys.is_empty());
+    assert!(journeys[0]["departure"].as_str().unwrap() >= "08:30:00");
+    assert!(journeys[0]["legs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|leg| leg["mode"] == "ride"));
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn proxy_registers_cities_at_runtime() {
+    let demo = DemoCity::generate(&DemoCityConfig {
+        cols: 6,
+        rows: 6,
+        routes: 2,
+        ..Default::default()
+    })
+    .unwrap();
+    let city_name = format!("proxy_city_{}", std::process::id());
+    use_temp_cache_dir(&city_name);
+    let base = std::env::temp_dir().join(format!("server_{}", city_name));
+    let (gtfs_base, db_base) = (base.join("gtfs"), base.join("db"));
+    std::fs::create_dir_all(&db_base).unwrap();
+    let launcher = CityLauncher {
+        host: "127.0.0.1".to_string(),
+        gtfs_base_path: gtfs_base.to_str().unwrap().to_string(),
+        db_base_path: db_base.to_str().unwrap().to_string(),
+        optimization_limits: OptimizationLimits::default(),
+        memory_mode: MemoryMode::Standard,
+        aco_defaults: PartialACO::default(),
+        vehicles: HashMap::new(),
+    };
+    demo.write_db(&launcher.db_path(&city_name)).unwrap();
+    demo.write_gtfs(&launcher.gtfs_path(&city_name)).unwrap();
+
+    // cities cannot be registered without an admin token configured
+    let unguarded = CityConfig::new(HashMap::new()).with_launcher(launcher.clone());
+    let app = test::init_service(build_proxy_app(web::Data::new(unguarded))).await;
+    let req = test::TestRequest::post()
+        .uri("/admin/cities")
+        .insert_header(("Authorization", "Bearer "))
+        .set_json(serde_json::json!({ "name": city_name }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+    let mut config = CityConfig::new(HashMap::new()).with_launcher(launcher);
+    config.admin_token = Some("secret".to_string());
+    let config = web::Data::new(config);
+    let app = test::init_service(build_proxy_app(config.clone())).await;
+    for authorization in [None, Some("secret"), Some("Bearer wrong")] {
+        let mut req = test::TestRequest::post().uri("/admin/cities");
+        if let Some(authorization) = authorization {
+            req = req.insert_header(("Authorization", authorization));
+        }
+        let req = req
+            .set_json(serde_json::json!({ "name": city_name }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+    }
+    let register = |body: Value| {
+        test::TestRequest::post()
+            .uri("/admin/cities")
+            .insert_header(("Authorization", "Bearer secret"))
+            .set_json(body)
+            .to_request()
+    };
+    for name in ["../etc", "Toronto", "nowhere"] {
+        let req = register(serde_json::json!({ "name": name }));
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    let port = std::net::TcpListener::bind(("127.0.0.1", 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let req = register(serde_json::json!({ "name": city_name, "port": port }));
+    let registered: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(registered["port"], port);
+    assert_eq!(config.get_port(&city_name), Some(port));
+    let req = register(serde_json::json!({ "name": city_name }));
+    assert_eq!(test::call_service(&app, req).await.status(), 409);
+
+    // the proxy forwards to the new city's server once it loaded the city
+    let started = std::time::Instant::now();
+    let info = loop {
+        let req = test::TestRequest::get()
+            .uri(&format!("/city-info?city={}", city_name))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        if res.status().is_success() {
+            break test::read_body_json::<Value, _>(res).await;
+        }
+        assert!(
+            started.elapsed().as_secs() < 120,
+            "{} never started",
+            city_name
+        );
+        actix_rt::time::sleep(std::time::Duration::from_millis(200)).await;
+    };
+    assert!(info.is_object());
+
+    // GeoJSON is streamed by the city's server and passed through by the proxy
+    let req = test::TestRequest::get()
+        .uri(&format!("/get-data?city={}", city_name))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert!(res.status().is_success());
+    assert!(!res.headers().contains_key("content-length"));
+    let data: Value = test::read_body_json(res).await;
+    assert_eq!(data["type"], "FeatureCollection");
+    assert!(!data["features"].as_array().unwrap().is_empty());
+
+    std::fs::remove_dir_all(&base).ok();
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn optimize_route_locks_only_its_route() {
+    let (city_name, state) = demo_state("route_locks");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let route_ids = route_ids(&state);
+
+    // a route being optimized elsewhere is refused, the others are not
+    let busy = state
+        .route_locks
+        .try_lock("default", &route_ids[..1])
+        .unwrap();
+    let req = test::TestRequest::post()
+        .uri(&format!("/optimize-route/{}", route_ids[0]))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 409);
+
+    let optimize = |route_id: &String| {
+        test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(&format!("/optimize-route/{}", route_id))
+                .to_request(),
+        )
+    };
+    let (a, b) = futures::join!(optimize(&route_ids[1]), optimize(&route_ids[2]));
+    drop(busy);
+
+    // each route is written back into the network without undoing the other
+    let optimized_route_ids = state.optimized_route_ids.lock().unwrap().clone();
+    for (route_id, res) in [(&route_ids[1], a), (&route_ids[2], b)] {
+        match res.status().as_u16() {
+            200 => assert!(optimized_route_ids.contains(route_id)),
+            status => assert_eq!(status, 422),
+        }
+    }
+    assert!(!optimized_route_ids.contains(&route_ids[0]));
+    let req = test::TestRequest::post()
+        .uri(&format!("/optimize-route/{}", route_ids[0]))
+        .to_request();
+    assert_ne!(test::call_service(&app, req).await.status(), 409);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn optimize_route_with_simulated_annealing() {
+    let (city_name, state) = demo_state("sa");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let route_ids = route_ids(&state);
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/optimize-route/{}?algorithm=tabu", route_ids[0]))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    let mut optimized = None;
+    for route_id in &route_ids {
+        let req = test::TestRequest::post()
+            .uri(&format!("/optimize-route/{}?algorithm=sa", route_id))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        if res.status().is_success() {
+            optimized = Some((
+                route_id.clone(),
+                test::read_body_json::<Value, _>(res).await,
+            ));
+            break;
+        }
+        assert_eq!(res.status(), 422);
+    }
+    let (route_id, optimized) = optimized.expect("annealing optimized no route of the city");
+    assert_eq!(optimized["algorithm"], "sa");
+    assert!(optimized["evaluation"].is_number());
+    assert!(state
+        .optimized_route_ids
+        .lock()
+        .unwrap()
+        .contains(&route_id));
+    // annealing leaves no pheromone to seed a later ACO run with
+    assert!(!state
+        .route_pheromones
+        .lock()
+        .unwrap()
+        .keys()
+        .contains(&route_id));
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn evaluate_network_reports_the_operating_budget() {
+    let (city_name, state) = demo_state("budget");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/operating-budget")
+        .set_json(serde_json::json!({ "max_fleet": 0 }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::get()
+        .uri("/evaluate-network")
+        .to_request();
+    let unlimited: Value = test::call_and_read_body_json(&app, req).await;
+    assert!(unlimited["budget"]["original"]["within_budget"]
+        .as_bool()
+        .unwrap());
+    assert!(unlimited["budget"]["original"]["fleet"].is_null());
+    let fleet = unlimited["budget"]["original"]["cost"]["fleet"]
+        .as_u64()
+        .unwrap();
+    assert!(fleet > 0);
+
+    let req = test::TestRequest::post()
+        .uri("/operating-budget")
+        .set_json(serde_json::json!({ "max_fleet": fleet * 2, "max_route_km": 0.001 }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    let req = test::TestRequest::get()
+        .uri("/operating-budget")
+        .to_request();
+    let budget: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(budget["max_fleet"], fleet * 2);
+    assert!(budget["max_revenue_hours"].is_null());
+
+    let req = test::TestRequest::get()
+        .uri("/evaluate-network")
+        .to_request();
+    let evaluated: Value = test::call_and_read_body_json(&app, req).await;
+    let original = &evaluated["budget"]["original"];
+    assert_eq!(original["fleet"].as_f64().unwrap(), 0.5);
+    assert!(original["route_km"].as_f64().unwrap() > 1.0);
+    assert!(!original["within_budget"].as_bool().unwrap());
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn route_ridership_profiles_original_and_optimized_routes() {
+    let (city_name, state) = demo_state("ridership");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let route_id = route_ids(&state)[0].clone();
+
+    let req = test::TestRequest::get()
+        .uri("/route-ridership/missing")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/route-ridership/{}", route_id))
+        .to_request();
+    let ridership: Value = test::call_and_read_body_json(&app, req).await;
+    let stops = ridership["original"].as_array().unwrap();
+    assert!(stops.len() >= 2);
+    assert!(stops[0]["lon"].is_number() && stops[0]["lat"].is_number());
+    assert_eq!(stops[0]["alightings"], 0.0);
+    assert_eq!(stops[0]["load"], stops[0]["boardings"]);
+    assert!(ridership["optimized"].is_null());
+
+    state
+        .optimized_route_ids
+        .lock()
+        .unwrap()
+        .push(route_id.clone());
+    let req = test::TestRequest::get()
+        .uri(&format!("/route-ridership/{}", route_id))
+        .to_request();
+    let ridership: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ridership["optimized"], ridership["original"]);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn demand_heatmap_sums_the_demand_of_each_zone() {
+    let (city_name, state) = demo_state("heatmap");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/demand-heatmap?period=rush")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    let heatmap = |uri: &'static str| {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        test::call_and_read_body_json::<_, _, Value>(&app, req)
+    };
+    let day = heatmap("/demand-heatmap?breakdown=true").await;
+    let features = day["features"].as_array().unwrap();
+    assert_eq!(features[0]["geometry"]["type"], "Polygon");
+    let sum = |features: &[Value], key: &str| -> f64 {
+        features
+            .iter()
+            .map(|f| f["properties"][key].as_f64().unwrap())
+            .sum()
+    };
+    // every trip leaves one zone and arrives at another
+    let total = sum(features, "demand_out");
+    assert!(total > 0.0);
+    assert!((total - sum(features, "demand_in")).abs() < 1e-6 * total);
+    let max_intensity = features
+        .iter()
+        .map(|f| f["properties"]["intensity"].as_f64().unwrap())
+        .fold(0.0, f64::max);
+    assert!((max_intensity - 1.0).abs() < 1e-9);
+
+    let am_rush = heatmap("/demand-heatmap?period=am_rush").await;
+    assert_eq!(am_rush["period"], "AmRush");
+    let am_features = am_rush["features"].as_array().unwrap();
+    assert_eq!(am_features.len(), features.len());
+    assert!(am_features[0]["properties"]["by_period"].is_null());
+    for (day, am) in features.iter().zip(am_features) {
+        let by_period = &day["properties"]["by_period"];
+        if by_period.is_null() {
+            continue;
+        }
+        assert_eq!(
+            by_period["AmRush"]["demand_out"],
+            am["properties"]["demand_out"]
+        );
+        assert_eq!(by_period.as_object().unwrap().len(), TimePeriod::ALL.len());
+    }
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn validate_gtfs_checks_the_feed_generated_from_the_network() {
+    let (city_name, state) = demo_state("validate_gtfs");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/validate-gtfs?source=scenario")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    let req = test::TestRequest::get()
+        .uri("/validate-gtfs?source=optimized")
+        .to_request();
+    let report: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(report["source"], "optimized");
+    assert_eq!(report["valid"], true, "{}", report);
+    assert_eq!(report["total_issues"], 0);
+    assert!(report["issues"].as_array().unwrap().is_empty());
+
+    // a route without stops has no trips in the generated feed
+    {
+        let mut optimized = state.optimized_transit.write().unwrap();
+        optimized.as_mut().unwrap().routes[0].outbound_stops.clear();
+        optimized.as_mut().unwrap().routes[0].inbound_stops.clear();
+    }
+    let req = test::TestRequest::get()
+        .uri("/validate-gtfs?source=optimized&limit=0")
+        .to_request();
+    let report: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(report["valid"], false);
+    assert!(report["total_issues"].as_u64().unwrap() > 0);
+    assert!(report["issues"].as_array().unwrap().is_empty());
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn optimizations_keep_locked_stops_and_segments() {
+    let (city_name, state) = demo_state("constraints");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let outbound_stop_ids = |route_id: &str| -> Vec<String> {
+        let transit = state.optimized_transit.read().unwrap();
+        let route = transit
+            .as_ref()
+            .unwrap()
+            .routes
+            .iter()
+            .find(|r| r.route_id == route_id)
+            .unwrap();
+        route
+            .outbound_stops
+            .iter()
+            .map(|s| s.stop_id.clone())
+            .collect()
+    };
+    let set_constraint = |route_id: &str, body: Value| {
+        test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(&format!("/route-constraints/{}", route_id))
+                .set_json(body)
+                .to_request(),
+        )
+    };
+
+    let res = set_constraint("nowhere", serde_json::json!({})).await;
+    assert_eq!(res.status(), 404);
+    let route_ids = route_ids(&state);
+    let res = set_constraint(
+        &route_ids[0],
+        serde_json::json!({ "locked_stops": ["not-a-stop"] }),
+    )
+    .await;
+    assert_eq!(res.status(), 400);
+
+    let mut optimized = false;
+    for route_id in &route_ids {
+        let stops = outbound_stop_ids(route_id);
+        let mid = stops.len() / 2;
+        let body = serde_json::json!({
+            "locked_stops": [stops[mid]],
+            "locked_segments": [[stops[1], stops[2]]],
+        });
+        let res = set_constraint(route_id, body.clone()).await;
+        assert!(res.status().is_success());
+        let req = test::TestRequest::get()
+            .uri(&format!("/route-constraints/{}", route_id))
+            .to_request();
+        let data: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(data["constraint"], body);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/optimize-route/{}", route_id))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        if res.status() == 422 {
+            continue;
+        }
+        assert!(res.status().is_success());
+        let after = outbound_stop_ids(route_id);
+        assert!(after.contains(&stops[mid]));
+        assert!(after
+            .windows(2)
+            .any(|w| w[0] == stops[1] && w[1] == stops[2]));
+        optimized = true;
+        break;
+    }
+    assert!(optimized, "no route of the city could be optimized");
+
+    let res = set_constraint(&route_ids[0], serde_json::json!({})).await;
+    assert!(res.status().is_success());
+    assert!(!state
+        .route_constraints
+        .lock()
+        .unwrap()
+        .contains_key(&route_ids[0]));
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn tune_aco_params_in_a_job() {
+    let (city_name, state) = demo_state("tune");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let route_ids = route_ids(&state);
+    let tune = |route_id: &str, body: Value| {
+        test::TestRequest::post()
+            .uri(&format!("/tune-aco-params/{}", route_id))
+            .set_json(body)
+            .to_request()
+    };
+
+    let res = test::call_service(&app, tune("missing", serde_json::json!({}))).await;
+    assert_eq!(res.status(), 404);
+    let body = serde_json::json!({ "population_size": 2, "elitism_count": 2 });
+    let res = test::call_service(&app, tune(&route_ids[0], body)).await;
+    assert_eq!(res.status(), 400);
+
+    let body = serde_json::json!({
+        "population_size": 2,
+        "max_generations": 2,
+        "elitism_count": 1,
+        "commit": true,
+    });
+    let res = test::call_service(&app, tune(&route_ids[0], body)).await;
+    assert_eq!(res.status(), 202);
+    let queued: Value = test::read_body_json(res).await;
+    let job_id = queued["job_id"].as_u64().unwrap();
+    let job = loop {
+        let job = state.jobs.get(job_id).unwrap();
+        if job.status.finished() {
+            break job;
+        }
+        actix_rt::time::sleep(std::time::Duration::from_millis(50)).await;
+    };
+    assert_eq!(job.kind, "tune-aco-params");
+    assert!(job.error.is_none(), "{:?}", job.error);
+    assert_eq!(job.events, 2);
+    let progress = serde_json::to_value(job.progress.unwrap()).unwrap();
+    assert_eq!(progress["event"], "tuning_generation_completed");
+    assert_eq!(progress["generation"], 2);
+
+    // the tuned parameters replace the current ones, the others are kept
+    let result = job.result.unwrap();
+    assert_eq!(result["committed"], true);
+    assert!(result["params"]["objective"].is_null());
+    let params = state.aco_params.lock().unwrap();
+    assert_eq!(result["params"]["alpha"], params.alpha);
+    assert_eq!(result["params"]["num_ant"], params.num_ant);
+    assert_eq!(params.seed, ACO::init().seed);
+    drop(params);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn optimize_area_runs_as_a_background_job() {
+    let (city_name, state) = demo_state("area_job");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    // a box around every stop of the city
+    let (min, max) = {
+        let city = state.city.read().unwrap();
+        let stops = city
+            .as_ref()
+            .unwrap()
+            .transit
+            .routes
+            .iter()
+            .flat_map(|r| r.outbound_stops.iter());
+        stops.fold(
+            ((f64::MAX, f64::MAX), (f64::MIN, f64::MIN)),
+            |((min_x, min_y), (max_x, max_y)), stop| {
+                (
+                    (min_x.min(stop.geom.x()), min_y.min(stop.geom.y())),
+                    (max_x.max(stop.geom.x()), max_y.max(stop.geom.y())),
+                )
+            },
+        )
+    };
+    let (min, max) = ((min.0 - 0.01, min.1 - 0.01), (max.0 + 0.01, max.1 + 0.01));
+    let area = serde_json::json!({
+        "type": "Polygon",
+        "coordinates": [[
+            [min.0, min.1], [max.0, min.1], [max.0, max.1], [min.0, max.1], [min.0, min.1]
+        ]],
+    });
+
+    let req = test::TestRequest::post()
+        .uri("/optimize-area")
+        .set_json(serde_json::json!({ "area": area, "min_stop_share": 2.0 }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    let req = test::TestRequest::post()
+        .uri("/optimize-area")
+        .set_json(serde_json::json!({ "area": area }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), 202);
+    let queued: Value = test::read_body_json(res).await;
+    let job_id = queued["job_id"].as_u64().unwrap();
+    let job = loop {
+        let job = state.jobs.get(job_id).unwrap();
+        if job.status.finished() {
+            break job;
+        }
+        actix_rt::time::sleep(std::time::Duration::from_millis(50)).await;
+    };
+    assert_eq!(job.kind, "optimize-area");
+    assert!(job.error.is_none(), "{:?}", job.error);
+    let mut routes = job.routes.clone();
+    routes.sort();
+    assert_eq!(routes, route_ids(&state));
+    let result = job.result.unwrap();
+    assert_eq!(result["area"]["routes"].as_array().unwrap().len(), 3);
+    assert!(!result["geojson"]["features"].as_array().unwrap().is_empty());
+    let optimized_route_ids = state.optimized_route_ids.lock().unwrap();
+    assert_eq!(
+        result["batch"]["optimized_route_ids"]
+            .as_array()
+            .unwrap()
+            .len(),
+        optimized_route_ids.len()
+    );
+    drop(optimized_route_ids);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn evaluate_equity_by_population_and_demographic() {
+    let (city_name, state) = demo_state("equity");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/evaluate-equity")
+        .to_request();
+    let equity: Value = test::call_and_read_body_json(&app, req).await;
+    for network in ["original", "optimized"] {
+        let metrics = &equity[network];
+        let covered_share = metrics["covered_share"].as_f64().unwrap();
+        assert!(covered_share > 0.0 && covered_share <= 1.0, "{}", network);
+        let gini = metrics["service_gini"].as_f64().unwrap();
+        assert!((0.0..=1.0).contains(&gini), "{}", network);
+        let groups = metrics["by_population"].as_array().unwrap();
+        assert_eq!(groups.len(), 5);
+        assert_eq!(
+            groups
+                .iter()
+                .map(|g| g["population"].as_u64().unwrap())
+                .sum::<u64>(),
+            metrics["total_population"].as_u64().unwrap()
+        );
+        assert!(metrics["demographic"].is_null());
+    }
+    // the network evals carry the same metrics
+    {
+        let city = state.city.read().unwrap();
+        let evals = city.as_ref().unwrap().transit.evals.as_ref().unwrap();
+        assert_eq!(
+            evals.equity.covered_population,
+            equity["original"]["covered_population"]
+        );
+        assert_eq!(evals.equity.by_population.len(), 5);
+    }
+
+    // demographic columns are read from the database, which the demo state removes
+    let dir = std::env::temp_dir().join(&city_name);
+    let demo = DemoCity::generate(&DemoCityConfig {
+        cols: 8,
+        rows: 8,
+        routes: 3,
+        ..Default::default()
+    })
+    .unwrap();
+    std::fs::create_dir_all(&dir).unwrap();
+    demo.write_db(dir.join("demo.db").to_str().unwrap())
+        .unwrap();
+
+    let req = test::TestRequest::get()
+        .uri("/evaluate-equity?demographic=jobs")
+        .to_request();
+    let equity: Value = test::call_and_read_body_json(&app, req).await;
+    let demographic = &equity["optimized"]["demographic"];
+    assert_eq!(demographic["column"], "jobs");
+    assert!(demographic["total"].as_f64().unwrap() > 0.0);
+    assert!(demographic["covered_share"].as_f64().unwrap() <= 1.0);
+
+    let req = test::TestRequest::get()
+        .uri("/evaluate-equity?demographic=income%22%3B%20DROP%20TABLE%20zone")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    std::fs::remove_dir_all(&dir).ok();
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn travel_time_matrix_as_json_and_csv() {
+    let (city_name, state) = demo_state("skim");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    let req = test::TestRequest::get()
+        .uri("/travel-time-matrix?max_minutes=60")
+        .to_request();
+    let matrix: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(matrix["network"], "optimized");
+    let times = matrix["original"]["times"].as_array().unwrap();
+    assert!(!times.is_empty());
+    assert!(times
+        .iter()
+        .all(|t| t["total_min"].as_f64().unwrap() <= 60.0));
+    // nothing is optimized yet
+    assert_eq!(matrix["original"], matrix["optimized"]);
+    for key in ["original", "optimized"] {
+        let skim = format!(
+            "{}/{}_skims/{}.cached",
+            City::cache_dir(&city_name),
+            city_name,
+            key
+        );
+        assert!(std::path::Path::new(&skim).exists(), "{}", skim);
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/travel-time-matrix?max_minutes=60&format=csv")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/csv");
+    let csv = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    let mut lines = csv.lines();
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with("network,origin_zone,destination_zone"));
+    assert_eq!(lines.count(), 2 * times.len());
+
+    for uri in [
+        "/travel-time-matrix?format=xml",
+        "/travel-time-matrix?max_minutes=0",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400, "{}", uri);
+    }
+    let req = test::TestRequest::get()
+        .uri("/travel-time-matrix?scenario=missing")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    let req = test::TestRequest::post()
+        .uri("/save-scenario/baseline")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    let req = test::TestRequest::get()
+        .uri("/travel-time-matrix?scenario=baseline")
+        .to_request();
+    let matrix: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(matrix["network"], "baseline");
+    assert_eq!(matrix["original"]["max_minutes"], 120.0);
+    let skim = format!(
+        "{}/{}_skims/scenario-baseline.cached",
+        City::cache_dir(&city_name),
+        city_name
+    );
+    assert!(std::path::Path::new(&skim).exists());
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn optimize_route_with_seeds_and_restarts() {
+    let (city_name, state) = demo_state("seeds");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let route_id = route_ids(&state)[0].clone();
+
+    for n_restarts in [0, 17] {
+        let req = test::TestRequest::post()
+            .uri(&format!(
+                "/optimize-route/{}?n_restarts={}",
+                route_id, n_restarts
+            ))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    // runs that find no better route still report the seeds they tried
+    let optimize = |uri: String| {
+        let app = &app;
+        async move {
+            let req = test::TestRequest::post().uri(&uri).to_request();
+            let res = test::call_service(app, req).await;
+            let success = res.status().is_success();
+            let body: Value = test::read_body_json(res).await;
+            match success {
+                true => body,
+                false => body["details"].clone(),
+            }
+        }
+    };
+    let restarted = optimize(format!("/optimize-route/{}?seed=7&n_restarts=3", route_id)).await;
+    let restarts = restarted["restarts"].as_array().unwrap();
+    let seeds: Vec<u64> = restarts
+        .iter()
+        .map(|r| r["seed"].as_u64().unwrap())
+        .collect();
+    assert_eq!(seeds, vec![7, 8, 9]);
+    if let Some(evaluation) = restarted["evaluation"].as_f64() {
+        let best = restarts
+            .iter()
+            .filter_map(|r| r["evaluation"].as_f64())
+            .fold(f64::MIN, f64::max);
+        assert_eq!(evaluation, best);
+        let seed = restarted["seed"].as_u64().unwrap();
+        assert_eq!(
+            restarts[seeds.iter().position(|&s| s == seed).unwrap()]["evaluation"],
+            evaluation
+        );
+    }
+
+    // the same seed on the same network finds the same route
+    let req = test::TestRequest::post()
+        .uri("/reset-optimizations")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    let single = optimize(format!("/optimize-route/{}?seed=8", route_id)).await;
+    assert_eq!(single["restarts"].as_array().unwrap().len(), 1);
+    assert_eq!(single["restarts"][0]["seed"], 8);
+    assert_eq!(
+        single["restarts"][0]["evaluation"],
+        restarts[1]["evaluation"]
+    );
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn stored_scenarios_are_listed_and_mapped() {
+    let (city_name, state) = demo_state("scenario_store");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/scenarios")
+        .set_json(serde_json::json!({ "name": "no spaces" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    for (name, description) in [("baseline", None), ("draft", Some("second try"))] {
+        let req = test::TestRequest::post()
+            .uri("/scenarios")
+            .set_json(serde_json::json!({ "name": name, "description": description }))
+            .to_request();
+        let stored: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(stored["name"], name);
+        assert!(stored["id"].as_i64().unwrap() > 0);
+        assert!(stored["evals"]["avg_transfers"].is_number());
+    }
+
+    let req = test::TestRequest::get().uri("/scenarios").to_request();
+    let listed: Value = test::call_and_read_body_json(&app, req).await;
+    let scenarios = listed["scenarios"].as_array().unwrap();
+    assert_eq!(scenarios.len(), 2);
+    // newest first
+    assert_eq!(scenarios[0]["name"], "draft");
+    assert_eq!(scenarios[0]["description"], "second try");
+    assert!(scenarios[1]["description"].is_null());
+    assert_eq!(scenarios[0]["params"]["seed"], 42);
+    assert!(scenarios[0]["evals"]["avg_transfers"].is_number());
+    let db = format!(
+        "{}/{}_scenarios/scenarios.db",
+        City::cache_dir(&city_name),
+        city_name
+    );
+    assert!(std::path::Path::new(&db).exists());
+
+    let id = scenarios[1]["id"].as_i64().unwrap();
+    let req = test::TestRequest::get()
+        .uri(&format!("/scenarios/{}/geojson", id))
+        .to_request();
+    let geojson: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(geojson["type"], "FeatureCollection");
+    let route_id = &route_ids(&state)[0];
+    assert!(geojson["features"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|f| f["properties"]["route_id"] == route_id.as_str()));
+
+    let req = test::TestRequest::get()
+        .uri("/scenarios/999/geojson")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn undo_optimization_restores_the_previous_version() {
+    let (city_name, state) = demo_state("route_history");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    let mut optimized = None;
+    for route_id in route_ids(&state) {
+        let req = test::TestRequest::post()
+            .uri(&format!("/optimize-route/{}", route_id))
+            .to_request();
+        if test::call_service(&app, req).await.status().is_success() {
+            optimized = Some(route_id);
+            break;
+        }
+    }
+    let route_id = optimized.expect("no route of the city could be optimized");
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/route-history/{}", route_id))
+        .to_request();
+    let history: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(history["workspace"], "default");
+    assert_eq!(history["current_version"], 1);
+    let versions = history["versions"].as_array().unwrap();
+    assert_eq!(versions.len(), 1);
+    assert_eq!(versions[0]["source"], "optimize-route");
+    assert!(versions[0]["evaluation"].is_number());
+    assert_eq!(versions[0]["params"]["seed"], 42);
+
+    // the route cannot be undone while it is optimized
+    {
+        let _lock = state
+            .route_locks
+            .try_lock("default", std::slice::from_ref(&route_id))
+            .unwrap();
+        let req = test::TestRequest::post()
+            .uri(&format!("/undo-optimization/{}", route_id))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 409);
+    }
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/undo-optimization/{}", route_id))
+        .to_request();
+    let undone: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(undone["undone_version"], 1);
+    assert!(undone["restored_version"].is_null());
+    assert_eq!(undone["geojson"]["type"], "FeatureCollection");
+    assert!(state.optimized_route_ids.lock().unwrap().is_empty());
+    {
+        let city_guard = state.city.read().unwrap();
+        let original = city_guard
+            .as_ref()
+            .unwrap()
+            .transit
+            .routes
+            .iter()
+            .find(|r| r.route_id == route_id)
+            .cloned();
+        let optimized_transit = state.optimized_transit.read().unwrap();
+        let restored = optimized_transit
+            .as_ref()
+            .unwrap()
+            .routes
+            .iter()
+            .find(|r| r.route_id == route_id)
+            .cloned();
+        assert!(original.is_some() && restored == original);
+    }
+
+    // nothing left to undo
+    let req = test::TestRequest::post()
+        .uri(&format!("/undo-optimization/{}", route_id))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    let req = test::TestRequest::get()
+        .uri(&format!("/route-history/{}?workspace=missing", route_id))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    let req = test::TestRequest::get()
+        .uri("/route-history/missing")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn metrics_report_requests_optimizations_and_memory() {
+    let (city_name, state) = demo_state("metrics");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let route_id = route_ids(&state)[0].clone();
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/optimize-route/{}", route_id))
+        .to_request();
+    test::call_service(&app, req).await;
+    let req = test::TestRequest::get().uri("/missing").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), 200);
+    assert_eq!(
+        res.headers().get("content-type").unwrap(),
+        "text/plain; version=0.0.4"
+    );
+    let body = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+    // requests are counted by route pattern rather than by route
+    let optimized = "route_service_http_request_duration_seconds_count{method=\"POST\",\
+                     endpoint=\"/optimize-route/{route_id}\",status=";
+    assert!(body
+        .lines()
+        .any(|line| line.starts_with(optimized) && line.ends_with(" 1")));
+    assert!(body.contains("endpoint=\"unmatched\",status=\"404\""));
+    // the optimizer counters are shared by the whole process, other tests add to them
+    let route_searches = format!(
+        "route_service_route_last_search_seconds{{route_id=\"{}\"}}",
+        route_id
+    );
+    assert!(body.contains(&route_searches));
+    assert!(body.contains("route_service_optimizer_cache_hit_ratio{cache=\"heuristic\"}"));
+    assert!(body.contains("route_service_optimizer_cache_hits_total{cache=\"pheromone\"}"));
+    assert!(body.contains("route_service_aco_generations_per_second{route_id="));
+    assert!(body.contains("# TYPE route_service_route_search_seconds histogram"));
+    assert!(body.contains("route_service_websocket_sessions 0"));
+    assert!(body.contains(&format!(
+        "route_service_road_nodes{{city=\"{}\"}}",
+        city_name
+    )));
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn demand_model_is_updated_and_saved() {
+    let (city_name, state) = demo_state("demand_model");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    let req = test::TestRequest::get().uri("/demand-model").to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["wait_weight"], 2.0);
+
+    let req = test::TestRequest::post()
+        .uri("/demand-model")
+        .set_json(serde_json::json!({ "scale": -1.0 }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    let req = test::TestRequest::post()
+        .uri("/demand-model")
+        .set_json(serde_json::json!({ "scale": 0.0, "wait_weight": 3.0 }))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["demand_model"]["scale"], 0.0);
+    assert_eq!(body["demand_model"]["in_vehicle_weight"], 1.0);
+    {
+        let city_guard = state.city.read().unwrap();
+        let city = city_guard.as_ref().unwrap();
+        assert_eq!(city.transit.demand_model.wait_weight, 3.0);
+        let optimized_guard = state.optimized_transit.read().unwrap();
+        assert_eq!(optimized_guard.as_ref().unwrap().demand_model.scale, 0.0);
+        // routes are evaluated with the new model
+        for route in &city.transit.routes {
+            let (ridership, _) = eval::ridership_over_route(&city.transit, route, &city.grid);
+            assert_eq!(route.evals.as_ref().unwrap().ridership, ridership);
+        }
+    }
+    // the model is kept for the next start
+    assert_eq!(City::load_demand_model(&city_name).unwrap().scale, 0.0);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn poi_access_rejects_minutes_that_are_not_finite() {
+    let (city_name, state) = demo_state("poi_access_minutes");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    for minutes in ["NaN", "inf", "-5"] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/poi-access?minutes={}", minutes))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+    let req = test::TestRequest::get()
+        .uri("/poi-access?minutes=30")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn rejected_route_is_replaced_by_the_original() {
+    let (city_name, state) = demo_state("reject_route");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    let mut optimized = None;
+    for route_id in route_ids(&state) {
+        let req = test::TestRequest::post()
+            .uri(&format!("/optimize-route/{}", route_id))
+            .to_request();
+        if test::call_service(&app, req).await.status().is_success() {
+            optimized = Some(route_id);
+            break;
+        }
+    }
+    let route_id = optimized.expect("no route of the city could be optimized");
+    let req = test::TestRequest::post()
+        .uri(&format!("/reject-route/{}", route_id))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    {
+        let city_guard = state.city.read().unwrap();
+        let city = city_guard.as_ref().unwrap();
+        let original = city.transit.routes.iter().find(|r| r.route_id == route_id);
+        let optimized_guard = state.optimized_transit.read().unwrap();
+        let optimized = optimized_guard.as_ref().unwrap();
+        let restored = optimized.routes.iter().find(|r| r.route_id == route_id);
+        assert!(restored == original);
+        assert!(optimized.evals.is_none());
+    }
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/reject-route/{}", route_id))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 409);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn route_diff_compares_each_direction_on_its_own() {
+    let (city_name, state) = demo_state("route_diff");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let original = state.city.read().unwrap().as_ref().unwrap().transit.routes[0].clone();
+    assert!(!original.inbound_stops.is_empty());
+    state
+        .optimized_route_ids
+        .lock()
+        .unwrap()
+        .push(original.route_id.clone());
+
+    // replace the optimized version of the route and read back its diff
+    let diff = |route: TransitRoute| {
+        let app = &app;
+        let state = state.clone();
+        async move {
+            let route_id = route.route_id.clone();
+            {
+                let mut optimized_guard = state.optimized_transit.write().unwrap();
+                let optimized = optimized_guard.as_mut().unwrap();
+                let current = optimized
+                    .routes
+                    .iter_mut()
+                    .find(|r| r.route_id == route.route_id)
+                    .unwrap();
+                *current = route;
+            }
+            let req = test::TestRequest::get()
+                .uri(&format!("/route/{}", route_id))
+                .to_request();
+            let body: Value = test::call_and_read_body_json(app, req).await;
+            body["diff"].clone()
+        }
+    };
+
+    // a route optimized without inbound stops has not lost them
+    let mut outbound_only = original.clone();
+    outbound_only.inbound_stops.clear();
+    let res = diff(outbound_only).await;
+    assert_eq!(res["removed_stops"], serde_json::json!([]));
+    assert_eq!(res["added_stops"], serde_json::json!([]));
+
+    // a stop dropped from one direction is removed even if the other direction serves it
+    let mut dropped = original.clone();
+    let stop = dropped.inbound_stops.remove(1);
+    dropped.outbound_stops.push(stop.clone());
+    let res = diff(dropped).await;
+    assert_eq!(res["removed_stops"], serde_json::json!([stop.stop_id]));
+    assert_eq!(res["added_stops"], serde_json::json!([stop.stop_id]));
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn calls_changing_the_city_are_audited() {
+    let (city_name, state) = demo_state("audit_calls");
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+
+    // reads are not recorded
+    for uri in ["/workspaces", "/jobs", "/audit-log"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+    let req = test::TestRequest::post()
+        .uri("/workspaces")
+        .insert_header(("X-Remote-User", "planner"))
+        .set_json(serde_json::json!({ "name": "audited" }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    // failed calls are recorded without moving the revision forward
+    let req = test::TestRequest::post()
+        .uri("/accept-route/missing")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    let req = test::TestRequest::get().uri("/audit-log").to_request();
+    let log: Value = test::call_and_read_body_json(&app, req).await;
+    let events = log["events"].as_array().unwrap();
+    assert_eq!(events.len(), 2, "{:?}", events);
+    let (created, accepted) = (&events[0], &events[1]);
+    assert_eq!(created["user"], "planner");
+    assert_eq!(created["method"], "POST");
+    assert_eq!(created["endpoint"], "/workspaces");
+    assert_eq!(created["revision_after"], 1);
+    assert_eq!(accepted["user"], "anonymous");
+    assert_eq!(accepted["endpoint"], "/accept-route/{route_id}");
+    assert_eq!(accepted["path"], "/accept-route/missing");
+    assert_eq!(accepted["status"], 404);
+    assert_eq!(accepted["revision_before"], accepted["revision_after"]);
+    assert_eq!(log["revision"], 1);
+    remove_city_files(&city_name);
+}
+
+#[actix_web::test]
+async fn noop_routes_are_listed_in_order() {
+    let (city_name, state) = demo_state("noop_order");
+    for route_id in ["c", "a", "b"] {
+        state
+            .noop_route_ids
+            .lock()
+            .unwrap()
+            .insert(route_id.to_string(), ());
+    }
+    let app = test::init_service(build_app(state.clone(), &city_name)).await;
+    let req = test::TestRequest::get()
+        .uri("/get-noop-routes")
+        .to_request();
+    let noop: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(noop["routes"], serde_json::json!(["a", "b", "c"]));
+    remove_city_files(&city_name);
+}
@@ -0,0 +1,231 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::layers::transit_network::TransitNetwork;
+use crate::opt::aco2::OptimizedTransitNetwork;
+use crate::opt::scenario::Scenario;
+
+/// Workspace the server starts with
+pub(crate) const DEFAULT_WORKSPACE: &str = "default";
+
+/// Optimized networks planners work on side by side, by name
+///
+/// A workspace is an optimized network kept in memory, unlike a saved `Scenario`, which is a
+/// snapshot of one on disk. The network of the active workspace is the one in
+/// `AppState::optimized_transit` that every endpoint reads by default, the others are kept
+/// here until they are activated or named by an endpoint's `workspace` parameter.
+pub(crate) struct Workspaces {
+    active: String,
+    inactive: BTreeMap<String, OptimizedTransitNetwork>,
+}
+
+/// A workspace as listed by `GET /workspaces`
+#[derive(Serialize)]
+pub(crate) struct WorkspaceSummary {
+    pub name: String,
+    pub active: bool,
+    pub optimized_routes: usize,
+}
+
+impl Default for Workspaces {
+    fn default() -> Self {
+        Workspaces {
+            active: DEFAULT_WORKSPACE.to_string(),
+            inactive: BTreeMap::new(),
+        }
+    }
+}
+
+impl Workspaces {
+    pub fn active(&self) -> &str {
+        &self.active
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        name == self.active || self.inactive.contains_key(name)
+    }
+
+    /// Every workspace sorted by name
+    ///
+    /// # Parameters
+    /// - `active_route_ids`: Optimized routes of the active workspace
+    pub fn list(&self, active_route_ids: &[String]) -> Vec<WorkspaceSummary> {
+        let mut workspaces: Vec<WorkspaceSummary> = self
+            .inactive
+            .iter()
+            .map(|(name, network)| WorkspaceSummary {
+                name: name.clone(),
+                active: false,
+                optimized_routes: network.optimized_routes.len(),
+            })
+            .chain(std::iter::once(WorkspaceSummary {
+                name: self.active.clone(),
+                active: true,
+                optimized_routes: active_route_ids.len(),
+            }))
+            .collect();
+        workspaces.sort_by(|a, b| a.name.cmp(&b.name));
+        workspaces
+    }
+
+    /// Add an inactive workspace
+    pub fn create(&mut self, name: &str, network: OptimizedTransitNetwork) -> Result<(), String> {
+        if !Scenario::valid_name(name) {
+            return Err(
+                "Workspace names may only contain letters, digits, '-' and '_'".to_string(),
+            );
+        }
+        if self.contains(name) {
+            return Err(format!("Workspace {} already exists", name));
+        }
+        self.inactive.insert(name.to_string(), network);
+        Ok(())
+    }
+
+    /// Remove an inactive workspace, the active one cannot be removed
+    pub fn remove(&mut self, name: &str) -> Result<OptimizedTransitNetwork, String> {
+        if name == self.active {
+            return Err(format!("Workspace {} is active", name));
+        }
+        self.inactive
+            .remove(name)
+            .ok_or_else(|| format!("Workspace {} not found", name))
+    }
+
+    /// Make a workspace the active one
+    ///
+    /// # Parameters
+    /// - `active`: Network of the active workspace, swapped with the network of `name` and
+    ///   kept under the previous name. Left as it is if there is no such workspace.
+    pub fn activate(
+        &mut self,
+        name: &str,
+        active: &mut OptimizedTransitNetwork,
+    ) -> Result<(), String> {
+        if name == self.active {
+            return Ok(());
+        }
+        let network = self
+            .inactive
+            .remove(name)
+            .ok_or_else(|| format!("Workspace {} not found", name))?;
+        let previous = std::mem::replace(&mut self.active, name.to_string());
+        self.inactive
+            .insert(previous, std::mem::replace(active, network));
+        Ok(())
+    }
+
+    /// Network and optimized routes of a workspace
+    ///
+    /// # Parameters
+    /// - `name`: Workspace, the active one if `None`
+    /// - `active_network`, `active_route_ids`: State of the active workspace
+    pub fn get_mut<'a>(
+        &'a mut self,
+        name: Option<&str>,
+        active_network: &'a mut TransitNetwork,
+        active_route_ids: &'a mut Vec<String>,
+    ) -> Result<(&'a mut TransitNetwork, &'a mut Vec<String>), String> {
+        match name {
+            None => Ok((active_network, active_route_ids)),
+            Some(name) if name == self.active => Ok((active_network, active_route_ids)),
+            Some(name) => self
+                .inactive
+                .get_mut(name)
+                .map(|w| (&mut w.network, &mut w.optimized_routes))
+                .ok_or_else(|| format!("Workspace {} not found", name)),
+        }
+    }
+
+    /// Network and optimized routes of a workspace, see `get_mut`
+    pub fn get<'a>(
+        &'a self,
+        name: Option<&str>,
+        active_network: &'a TransitNetwork,
+        active_route_ids: &'a [String],
+    ) -> Result<(&'a TransitNetwork, &'a [String]), String> {
+        match name {
+            None => Ok((active_network, active_route_ids)),
+            Some(name) if name == self.active => Ok((active_network, active_route_ids)),
+            Some(name) => self
+                .inactive
+                .get(name)
+                .map(|w| (&w.network, w.optimized_routes.as_slice()))
+                .ok_or_else(|| format!("Workspace {} not found", name)),
+        }
+    }
+
+    /// Networks of the inactive workspaces
+    pub fn inactive_networks_mut(&mut self) -> impl Iterator<Item = &mut TransitNetwork> {
+        self.inactive.values_mut().map(|w| &mut w.network)
+    }
+
+    /// Drop every workspace but the active one, e.g. when the routes they optimized are gone
+    pub fn clear(&mut self) {
+        self.inactive.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstar::RTree;
+
+    fn network(optimized_routes: &[&str]) -> OptimizedTransitNetwork {
+        OptimizedTransitNetwork {
+            network: TransitNetwork {
+                routes: vec![],
+                inbound_stops: RTree::new(),
+                outbound_stops: RTree::new(),
+                evals: None,
+                dropped_routes: vec![],
+                demand_model: Default::default(),
+            },
+            optimized_routes: optimized_routes.iter().map(|id| id.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn activating_keeps_the_previous_network() {
+        let mut workspaces = Workspaces::default();
+        workspaces.create("a", network(&["1"])).unwrap();
+        assert!(workspaces.create("a", network(&[])).is_err());
+        assert!(workspaces.create(DEFAULT_WORKSPACE, network(&[])).is_err());
+        assert!(workspaces.create("../a", network(&[])).is_err());
+
+        let mut activated = network(&["2", "3"]);
+        workspaces.activate("a", &mut activated).unwrap();
+        assert_eq!(activated.optimized_routes, vec!["1"]);
+        assert_eq!(workspaces.active(), "a");
+        let listed: Vec<(String, bool, usize)> = workspaces
+            .list(&activated.optimized_routes)
+            .into_iter()
+            .map(|w| (w.name, w.active, w.optimized_routes))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                ("a".to_string(), true, 1),
+                (DEFAULT_WORKSPACE.to_string(), false, 2)
+            ]
+        );
+
+        let (mut active, mut active_ids) = (activated.network, activated.optimized_routes);
+        let (_, ids) = workspaces
+            .get_mut(Some(DEFAULT_WORKSPACE), &mut active, &mut active_ids)
+            .unwrap();
+        assert_eq!(ids.len(), 2);
+        assert!(workspaces
+            .get_mut(Some("b"), &mut active, &mut active_ids)
+            .is_err());
+        let (_, ids) = workspaces.get(Some("a"), &active, &active_ids).unwrap();
+        assert_eq!(ids, ["1"]);
+        let mut missing = network(&["4"]);
+        assert!(workspaces.activate("b", &mut missing).is_err());
+        assert_eq!(missing.optimized_routes, vec!["4"]);
+
+        assert!(workspaces.remove("a").is_err());
+        assert!(workspaces.remove(DEFAULT_WORKSPACE).is_ok());
+        assert!(!workspaces.contains(DEFAULT_WORKSPACE));
+    }
+}
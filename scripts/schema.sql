@@ -52,6 +52,20 @@ CREATE TABLE demand (
     FOREIGN KEY(destid) REFERENCES zone(zoneid)
 );
 
+-- Optional points of interest used for accessibility analysis
+CREATE TABLE poi (
+    id INTEGER PRIMARY KEY,
+    category TEXT,
+    geom POINT
+);
+
+-- Optional city boundary used to clip GTFS feeds at load time
+-- Stops further than buffer_m meters outside the boundary are dropped
+CREATE TABLE boundary (
+    geom POLYGON,
+    buffer_m REAL
+);
+
 -- Tables for GTFS data
 -- All columns are TEXT to match the CSV source data
 CREATE TABLE gtfs_agency (